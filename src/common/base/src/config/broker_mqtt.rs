@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::sync::OnceLock;
 
 use serde::{Deserialize, Serialize};
@@ -20,13 +21,14 @@ use super::common::{
     default_prometheus, override_default_by_env, Auth, Log, Prometheus, Storage, Telemetry,
 };
 use super::default_mqtt::{
-    default_auth, default_grpc_port, default_log, default_mqtt_cluster_dynamic_feature,
-    default_mqtt_cluster_dynamic_flapping_detect, default_mqtt_cluster_dynamic_network,
-    default_mqtt_cluster_dynamic_protocol, default_mqtt_cluster_dynamic_security,
-    default_mqtt_cluster_dynamic_slow_sub, default_network, default_network_quic_port,
-    default_network_tcp_port, default_network_tcps_port, default_network_websocket_port,
-    default_network_websockets_port, default_offline_message, default_placement_center,
-    default_storage, default_system, default_tcp_thread, default_telemetry,
+    default_auth, default_grpc_port, default_http_port, default_log,
+    default_mqtt_cluster_dynamic_feature, default_mqtt_cluster_dynamic_flapping_detect,
+    default_mqtt_cluster_dynamic_network, default_mqtt_cluster_dynamic_protocol,
+    default_mqtt_cluster_dynamic_security, default_mqtt_cluster_dynamic_slow_sub, default_network,
+    default_network_max_total_connections, default_network_quic_port, default_network_tcp_port,
+    default_network_tcps_port, default_network_websocket_port, default_network_websockets_port,
+    default_offline_message, default_placement_center, default_storage, default_system,
+    default_system_topic, default_tcp_thread, default_telemetry,
 };
 use crate::tools::{read_file, try_create_fold};
 
@@ -36,6 +38,8 @@ pub struct BrokerMqttConfig {
     pub broker_id: u64,
     #[serde(default = "default_grpc_port")]
     pub grpc_port: u32,
+    #[serde(default = "default_http_port")]
+    pub http_port: u32,
     #[serde(default = "default_placement_center")]
     pub placement_center: Vec<String>,
     #[serde(default = "default_network")]
@@ -56,6 +60,16 @@ pub struct BrokerMqttConfig {
     pub telemetry: Telemetry,
     #[serde(default = "default_prometheus")]
     pub prometheus: Prometheus,
+    #[serde(default = "default_system_topic")]
+    pub system_topic: SystemTopic,
+    #[serde(default)]
+    pub inject_user_properties: Vec<InjectUserProperty>,
+    #[serde(default)]
+    pub retain_latest: Vec<RetainLatestRule>,
+    #[serde(default)]
+    pub tenant_encryption_keys: Vec<TenantEncryptionKey>,
+    #[serde(default)]
+    pub subscription_transforms: Vec<SubscriptionTransform>,
 
     #[serde(default = "default_mqtt_cluster_dynamic_slow_sub")]
     pub cluster_dynamic_config_slow_sub: MqttClusterDynamicSlowSub,
@@ -83,6 +97,66 @@ pub struct MqttClusterDynamicConfigProtocol {
     pub receive_max: u16,
     pub max_message_expiry_interval: u64,
     pub client_pkid_persistent: bool,
+    #[serde(default)]
+    pub client_id_max_len: u32,
+    #[serde(default)]
+    pub client_id_allowed_regex: String,
+    // Consumer lag in messages above which the PUBACK reports x-broker-lag, 0 means disabled
+    #[serde(default)]
+    pub flow_control_threshold: u64,
+    // Maximum number of shared subscription groups, 0 means unlimited
+    #[serde(default)]
+    pub max_shared_subscription_groups: u32,
+    // Maximum number of clients subscribed in a shared subscription group, 0 means unlimited
+    #[serde(default)]
+    pub max_shared_subscription_group_members: u32,
+    // Maximum number of messages in flight to a subscriber connection, 0 means one at a time
+    #[serde(default)]
+    pub delivery_concurrency: u32,
+    // Maximum number of retained messages delivered for a subscription, 0 means unlimited
+    #[serde(default)]
+    pub retain_max_per_subscribe: u32,
+    // Whether the newest retained messages are delivered first when the number is capped
+    #[serde(default)]
+    pub retain_newest_first: bool,
+    // Whether the shared subscription groups of a topic share a single read of its messages
+    #[serde(default)]
+    pub fan_out_read_once: bool,
+    // Whether a retained message with the same payload as the current one is not written again
+    #[serde(default)]
+    pub retain_skip_unchanged: bool,
+    // Maximum encoded size in bytes of the properties of a CONNECT, 0 means unlimited
+    #[serde(default)]
+    pub max_connect_properties_size: u32,
+    // Maximum number of user properties of a CONNECT, 0 means unlimited
+    #[serde(default)]
+    pub max_connect_user_properties: u32,
+    // Seconds a new subscription is delivered at a capped rate, 0 means disabled
+    #[serde(default)]
+    pub slow_start_duration_secs: u64,
+    // Messages delivered to a new subscription in its first second, doubled every second
+    #[serde(default)]
+    pub slow_start_initial_rate: u32,
+    // Records read from the topic but not yet delivered that a push thread holds in memory,
+    // 0 is unbounded
+    #[serde(default)]
+    pub max_reader_inflight_records: u32,
+    // Whether a batch read from the storage with out-of-order offsets fails the push, instead
+    // of being sorted by offset
+    #[serde(default)]
+    pub strict_record_offset_order: bool,
+    // Upper bound of the delivery rate, in messages per second, a client may request with the
+    // x-max-delivery-rate user property of a SUBSCRIBE, 0 is unbounded
+    #[serde(default)]
+    pub max_client_delivery_rate: u32,
+    // Seconds an inbound QoS 2 message waits for its PUBREL before its packet identifier is
+    // released, 0 means it waits as long as the session
+    #[serde(default)]
+    pub qos2_inflight_expire_secs: u64,
+    // Seconds without a publish after which the message shard of a topic without subscribers is
+    // deleted, its retained message is kept, 0 keeps the shards
+    #[serde(default)]
+    pub idle_topic_shard_expire_secs: u64,
 }
 
 impl MqttClusterDynamicConfigProtocol {
@@ -96,6 +170,18 @@ impl MqttClusterDynamicConfigProtocol {
 pub struct MqttClusterDynamicConfigSecurity {
     pub is_self_protection_status: bool,
     pub secret_free_login: bool,
+    // Whether a CONNECT without a username is accepted
+    #[serde(default)]
+    pub allow_anonymous: bool,
+    // Topic filters whose messages are authorized again against the ACL when they are delivered
+    #[serde(default)]
+    pub delivery_acl_check_topics: Vec<String>,
+    // Whether a subscriber denied at delivery time is also disconnected
+    #[serde(default)]
+    pub delivery_acl_deny_disconnect: bool,
+    // Whether a user stored with a plaintext password is upgraded to a hash on its next login
+    #[serde(default)]
+    pub upgrade_plaintext_password: bool,
 }
 
 impl MqttClusterDynamicConfigSecurity {
@@ -113,6 +199,12 @@ pub struct MqttClusterDynamicConfigNetwork {
     pub websockets_max_connection_num: u64,
     pub response_max_try_mut_times: u64,
     pub response_try_mut_sleep_time_ms: u64,
+    // IPs or CIDRs a connection is accepted from, empty accepts every peer
+    #[serde(default)]
+    pub accept_ip_allow_list: Vec<String>,
+    // IPs or CIDRs a connection is refused from, before the allow list is checked
+    #[serde(default)]
+    pub accept_ip_deny_list: Vec<String>,
 }
 
 // MQTT cluster Feature related dynamic configuration
@@ -180,6 +272,29 @@ pub struct Network {
     pub tls_cert: String,
     #[serde(default)]
     pub tls_key: String,
+    // PEM bundle of the CAs the client certificates of the TLS listener are verified against,
+    // the clients are not asked for a certificate when empty
+    #[serde(default)]
+    pub tls_ca: String,
+    // The TLS listener refuses the clients without a certificate signed by tls_ca, the common
+    // name of the certificate is then the username of the client
+    #[serde(default)]
+    pub tls_require_client_cert: bool,
+    // Upper bound of the TCP and TLS connections of the broker
+    #[serde(default = "default_network_max_total_connections")]
+    pub max_total_connections: usize,
+    // MQTT protocol levels accepted by each listener, e.g. [4] for MQTT 3.1.1 only, all of them
+    // when empty
+    #[serde(default)]
+    pub tcp_protocol_versions: Vec<u8>,
+    #[serde(default)]
+    pub tcps_protocol_versions: Vec<u8>,
+    #[serde(default)]
+    pub websocket_protocol_versions: Vec<u8>,
+    #[serde(default)]
+    pub websockets_protocol_versions: Vec<u8>,
+    #[serde(default)]
+    pub quic_protocol_versions: Vec<u8>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -200,6 +315,10 @@ pub struct TcpThread {
     pub lock_max_try_mut_times: u64,
     #[serde(default)]
     pub lock_try_mut_sleep_time_ms: u64,
+    #[serde(default)]
+    pub response_batch_max_num: usize,
+    #[serde(default)]
+    pub response_batch_window_ms: u64,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -222,6 +341,62 @@ pub struct OfflineMessage {
     pub max_messages_num: u32,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SystemTopic {
+    #[serde(default)]
+    pub default_interval_sec: u64,
+    // (metric name, publish interval)
+    #[serde(default)]
+    pub metric_interval_sec: HashMap<String, u64>,
+}
+
+// User properties the broker adds to the messages delivered on the topics matching topic_filter.
+// The values support the placeholders ${broker_id} and ${ingest_ts}.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct InjectUserProperty {
+    #[serde(default)]
+    pub topic_filter: String,
+    #[serde(default)]
+    pub user_properties: HashMap<String, String>,
+}
+
+// Reshapes the payload delivered to the subscription of client_id on sub_path, the other subscribers
+// of the topic receive the original payload. The inject_fields are added to a JSON object payload,
+// then the payload is written in place of ${payload} in the envelope, which also supports ${topic}.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SubscriptionTransform {
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub sub_path: String,
+    #[serde(default)]
+    pub inject_fields: HashMap<String, String>,
+    #[serde(default)]
+    pub envelope: String,
+}
+
+pub const MAX_RETAIN_LATEST_NUM: u32 = 100;
+
+// Topics matching topic_filter retain their latest retain_num retained messages instead of only the
+// last one, and deliver them oldest first to new subscribers. retain_num is capped at MAX_RETAIN_LATEST_NUM.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RetainLatestRule {
+    #[serde(default)]
+    pub topic_filter: String,
+    #[serde(default)]
+    pub retain_num: u32,
+}
+
+// The messages of the topics of a tenant, the first level of the topic name, are stored encrypted with
+// AES-256-GCM. key is the 32 bytes key encoded in hex.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TenantEncryptionKey {
+    #[serde(default)]
+    pub tenant: String,
+    #[serde(default)]
+    pub key: String,
+}
+
 static BROKER_MQTT_CONF: OnceLock<BrokerMqttConfig> = OnceLock::new();
 
 pub fn init_broker_mqtt_conf_by_path(config_path: &str) -> &'static BrokerMqttConfig {
@@ -292,6 +467,7 @@ mod tests {
         assert_eq!(config.cluster_name, "mqtt-broker".to_string());
         assert_eq!(config.placement_center.len(), 1);
         assert_eq!(config.grpc_port, 9981);
+        assert_eq!(config.http_port, 9982);
 
         assert_eq!(config.network.tcp_port, 1883);
         assert_eq!(config.network.tcps_port, 8883);
@@ -300,6 +476,7 @@ mod tests {
         assert_eq!(config.network.quic_port, 9083);
         assert!(!config.network.tls_cert.is_empty());
         assert!(!config.network.tls_key.is_empty());
+        assert_eq!(config.network.max_total_connections, 100000);
 
         assert_eq!(config.tcp_thread.accept_thread_num, 1);
         assert_eq!(config.tcp_thread.handler_thread_num, 10);
@@ -309,11 +486,23 @@ mod tests {
         assert_eq!(config.tcp_thread.response_queue_size, 2000);
         assert_eq!(config.tcp_thread.lock_max_try_mut_times, 30);
         assert_eq!(config.tcp_thread.lock_try_mut_sleep_time_ms, 50);
+        assert_eq!(config.tcp_thread.response_batch_max_num, 16);
+        assert_eq!(config.tcp_thread.response_batch_window_ms, 1);
 
         assert_eq!(config.system.runtime_worker_threads, 128);
         assert_eq!(config.system.default_user, "admin".to_string());
         assert_eq!(config.system.default_password, "pwd123".to_string());
 
+        assert_eq!(config.system_topic.default_interval_sec, 60);
+        assert_eq!(
+            config.system_topic.metric_interval_sec.get("version"),
+            Some(&3600)
+        );
+        assert_eq!(
+            config.system_topic.metric_interval_sec.get("sysdescr"),
+            Some(&3600)
+        );
+
         assert_eq!(config.storage.storage_type, "memory".to_string());
         assert_eq!(config.storage.journal_addr, "".to_string());
         assert_eq!(config.storage.mysql_addr, "".to_string());
@@ -446,6 +635,7 @@ mod tests {
         assert_eq!(config.cluster_name, "mqtt-broker".to_string());
         assert_eq!(config.placement_center.len(), 1);
         assert_eq!(config.grpc_port, 9981);
+        assert_eq!(config.http_port, 9982);
 
         assert_eq!(config.network.tcp_port, 1883);
         assert_eq!(config.network.tcps_port, 8883);
@@ -463,11 +653,23 @@ mod tests {
         assert_eq!(config.tcp_thread.response_queue_size, 2000);
         assert_eq!(config.tcp_thread.lock_max_try_mut_times, 30);
         assert_eq!(config.tcp_thread.lock_try_mut_sleep_time_ms, 50);
+        assert_eq!(config.tcp_thread.response_batch_max_num, 16);
+        assert_eq!(config.tcp_thread.response_batch_window_ms, 1);
 
         assert_eq!(config.system.runtime_worker_threads, 128);
         assert_eq!(config.system.default_user, "admin".to_string());
         assert_eq!(config.system.default_password, "pwd123".to_string());
 
+        assert_eq!(config.system_topic.default_interval_sec, 60);
+        assert_eq!(
+            config.system_topic.metric_interval_sec.get("version"),
+            Some(&3600)
+        );
+        assert_eq!(
+            config.system_topic.metric_interval_sec.get("sysdescr"),
+            Some(&3600)
+        );
+
         assert_eq!(config.storage.storage_type, "memory".to_string());
         assert_eq!(config.storage.journal_addr, "".to_string());
         assert_eq!(config.storage.mysql_addr, "".to_string());
@@ -27,6 +27,8 @@ pub struct Storage {
     #[serde(default)]
     pub rocksdb_data_path: String,
     pub rocksdb_max_open_files: Option<i32>,
+    #[serde(default)]
+    pub mmap_data_path: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -52,6 +54,9 @@ pub struct Auth {
     pub journal_addr: String,
     #[serde(default)]
     pub mysql_addr: String,
+    // Built-in extended authentication methods accepted in the CONNECT, empty means all of them
+    #[serde(default)]
+    pub authentication_methods: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
@@ -12,11 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use super::broker_mqtt::{
     ConfigAvailableFlag, MqttClusterDynamicConfigFeature, MqttClusterDynamicConfigNetwork,
     MqttClusterDynamicConfigProtocol, MqttClusterDynamicConfigSecurity,
     MqttClusterDynamicFlappingDetect, MqttClusterDynamicSlowSub, Network, OfflineMessage, System,
-    TcpThread,
+    SystemTopic, TcpThread,
 };
 use super::common::{Auth, Log, Storage, Telemetry};
 
@@ -24,6 +26,10 @@ pub fn default_grpc_port() -> u32 {
     9981
 }
 
+pub fn default_http_port() -> u32 {
+    9982
+}
+
 pub fn default_placement_center() -> Vec<String> {
     vec!["127.0.0.1:1228".to_string()]
 }
@@ -37,8 +43,19 @@ pub fn default_network() -> Network {
         quic_port: default_network_quic_port(),
         tls_cert: "".to_string(),
         tls_key: "".to_string(),
+        tls_ca: "".to_string(),
+        tls_require_client_cert: false,
+        max_total_connections: default_network_max_total_connections(),
+        tcp_protocol_versions: Vec::new(),
+        tcps_protocol_versions: Vec::new(),
+        websocket_protocol_versions: Vec::new(),
+        websockets_protocol_versions: Vec::new(),
+        quic_protocol_versions: Vec::new(),
     }
 }
+pub fn default_network_max_total_connections() -> usize {
+    100000
+}
 pub fn default_network_tcp_port() -> u32 {
     1883
 }
@@ -65,6 +82,8 @@ pub fn default_tcp_thread() -> TcpThread {
         response_queue_size: 2000,
         lock_max_try_mut_times: 30,
         lock_try_mut_sleep_time_ms: 50,
+        response_batch_max_num: 16,
+        response_batch_window_ms: 1,
     }
 }
 
@@ -76,6 +95,16 @@ pub fn default_system() -> System {
     }
 }
 
+pub fn default_system_topic() -> SystemTopic {
+    SystemTopic {
+        default_interval_sec: 60,
+        metric_interval_sec: HashMap::from([
+            ("version".to_string(), 3600),
+            ("sysdescr".to_string(), 3600),
+        ]),
+    }
+}
+
 pub fn default_storage() -> Storage {
     Storage {
         storage_type: "memory".to_string(),
@@ -83,6 +112,7 @@ pub fn default_storage() -> Storage {
         mysql_addr: "".to_string(),
         rocksdb_data_path: "".to_string(),
         rocksdb_max_open_files: None,
+        mmap_data_path: "".to_string(),
     }
 }
 
@@ -106,6 +136,7 @@ pub fn default_auth() -> Auth {
         storage_type: "memory".to_string(),
         journal_addr: "".to_string(),
         mysql_addr: "".to_string(),
+        authentication_methods: Vec::new(),
     }
 }
 
@@ -131,13 +162,17 @@ pub fn default_mqtt_cluster_dynamic_security() -> MqttClusterDynamicConfigSecuri
     MqttClusterDynamicConfigSecurity {
         secret_free_login: false,
         is_self_protection_status: false,
+        allow_anonymous: false,
+        delivery_acl_check_topics: Vec::new(),
+        delivery_acl_deny_disconnect: false,
+        upgrade_plaintext_password: false,
     }
 }
 
 pub fn default_mqtt_cluster_dynamic_protocol() -> MqttClusterDynamicConfigProtocol {
     MqttClusterDynamicConfigProtocol {
         session_expiry_interval: 1800,
-        topic_alias_max: 65535,
+        topic_alias_max: 32,
         max_qos: 2,
         max_packet_size: 1024 * 1024 * 10,
         max_server_keep_alive: 3600,
@@ -145,6 +180,25 @@ pub fn default_mqtt_cluster_dynamic_protocol() -> MqttClusterDynamicConfigProtoc
         receive_max: 65535,
         client_pkid_persistent: false,
         max_message_expiry_interval: 3600,
+        client_id_max_len: 65535,
+        client_id_allowed_regex: "".to_string(),
+        flow_control_threshold: 0,
+        max_shared_subscription_groups: 0,
+        max_shared_subscription_group_members: 0,
+        delivery_concurrency: 0,
+        retain_max_per_subscribe: 0,
+        retain_newest_first: false,
+        fan_out_read_once: false,
+        retain_skip_unchanged: false,
+        max_connect_properties_size: 0,
+        max_connect_user_properties: 0,
+        slow_start_duration_secs: 0,
+        slow_start_initial_rate: 0,
+        max_reader_inflight_records: 0,
+        strict_record_offset_order: false,
+        max_client_delivery_rate: 0,
+        qos2_inflight_expire_secs: 0,
+        idle_topic_shard_expire_secs: 0,
     }
 }
 
@@ -174,5 +228,7 @@ pub fn default_mqtt_cluster_dynamic_network() -> MqttClusterDynamicConfigNetwork
         websockets_max_connection_num: 1000,
         response_max_try_mut_times: 128,
         response_try_mut_sleep_time_ms: 100,
+        accept_ip_allow_list: Vec::new(),
+        accept_ip_deny_list: Vec::new(),
     }
 }
@@ -92,6 +92,12 @@ pub enum CommonError {
     #[error("CRC check for the message data failed")]
     CrcCheckByMessage,
 
+    #[error("shard {0} under namespace {1} already exists")]
+    ShardAlreadyExists(String, String),
+
+    #[error("Circuit breaker is open, the call is rejected without being executed")]
+    CircuitBreakerOpen,
+
     #[error("{0}")]
     OpenDALError(#[from] opendal::Error),
 }
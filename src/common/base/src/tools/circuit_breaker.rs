@@ -0,0 +1,233 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::sync::Mutex;
+
+use super::now_second;
+use crate::error::common::CommonError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    // Calls are executed, consecutive failures are counted
+    Closed,
+    // Calls are rejected until reset_timeout_secs has elapsed
+    Open,
+    // Calls are executed to probe whether the dependency has recovered
+    HalfOpen,
+}
+
+struct CircuitInner {
+    state: CircuitState,
+    failure_count: u32,
+    success_count: u32,
+    opened_at: u64,
+}
+
+/// Isolates a failing dependency, e.g. the storage, so that calls fail immediately
+/// while it is unavailable instead of each of them waiting for its own timeout.
+///
+/// F is the clock returning the current time in seconds.
+pub struct CircuitBreaker<F = fn() -> u64> {
+    failure_threshold: u32,
+    success_threshold: u32,
+    reset_timeout_secs: u64,
+    clock: F,
+    inner: Mutex<CircuitInner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, success_threshold: u32, reset_timeout_secs: u64) -> Self {
+        Self::with_clock(
+            failure_threshold,
+            success_threshold,
+            reset_timeout_secs,
+            now_second,
+        )
+    }
+}
+
+impl<F> CircuitBreaker<F>
+where
+    F: Fn() -> u64,
+{
+    pub fn with_clock(
+        failure_threshold: u32,
+        success_threshold: u32,
+        reset_timeout_secs: u64,
+        clock: F,
+    ) -> Self {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            success_threshold: success_threshold.max(1),
+            reset_timeout_secs,
+            clock,
+            inner: Mutex::new(CircuitInner {
+                state: CircuitState::Closed,
+                failure_count: 0,
+                success_count: 0,
+                opened_at: 0,
+            }),
+        }
+    }
+
+    // An open breaker becomes half open once reset_timeout_secs has elapsed.
+    pub fn state(&self) -> CircuitState {
+        let mut inner = self.inner.lock().unwrap();
+        self.try_half_open(&mut inner);
+        inner.state
+    }
+
+    pub fn allow_request(&self) -> bool {
+        self.state() != CircuitState::Open
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            CircuitState::Closed => {
+                inner.failure_count = 0;
+            }
+            CircuitState::HalfOpen => {
+                inner.success_count += 1;
+                if inner.success_count >= self.success_threshold {
+                    inner.state = CircuitState::Closed;
+                    inner.failure_count = 0;
+                    inner.success_count = 0;
+                }
+            }
+            CircuitState::Open => {}
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            CircuitState::Closed => {
+                inner.failure_count += 1;
+                if inner.failure_count >= self.failure_threshold {
+                    self.open(&mut inner);
+                }
+            }
+            // A failed probe opens the breaker again
+            CircuitState::HalfOpen => self.open(&mut inner),
+            CircuitState::Open => {}
+        }
+    }
+
+    // Runs the call if the breaker allows it and records its result,
+    // otherwise returns CircuitBreakerOpen without running it.
+    pub async fn call<T, Fut>(&self, fut: Fut) -> Result<T, CommonError>
+    where
+        Fut: Future<Output = Result<T, CommonError>>,
+    {
+        if !self.allow_request() {
+            return Err(CommonError::CircuitBreakerOpen);
+        }
+
+        let result = fut.await;
+        match result {
+            Ok(_) => self.record_success(),
+            Err(_) => self.record_failure(),
+        }
+        result
+    }
+
+    fn open(&self, inner: &mut CircuitInner) {
+        inner.state = CircuitState::Open;
+        inner.opened_at = (self.clock)();
+        inner.failure_count = 0;
+        inner.success_count = 0;
+    }
+
+    fn try_half_open(&self, inner: &mut CircuitInner) {
+        if inner.state == CircuitState::Open
+            && (self.clock)() >= inner.opened_at + self.reset_timeout_secs
+        {
+            inner.state = CircuitState::HalfOpen;
+            inner.success_count = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::time::sleep;
+
+    use super::{CircuitBreaker, CircuitState};
+    use crate::error::common::CommonError;
+
+    async fn storage_call(calls: &AtomicU32, ok: bool) -> Result<(), CommonError> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        if ok {
+            Ok(())
+        } else {
+            Err(CommonError::CommonError("storage is down".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker_test() {
+        let breaker = CircuitBreaker::new(5, 2, 1);
+        let calls = AtomicU32::new(0);
+
+        for _ in 0..4 {
+            assert!(breaker.call(storage_call(&calls, false)).await.is_err());
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        assert!(breaker.call(storage_call(&calls, false)).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+        // the storage is not called while the breaker is open
+        let res = breaker.call(storage_call(&calls, true)).await;
+        assert!(matches!(res, Err(CommonError::CircuitBreakerOpen)));
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+        sleep(Duration::from_millis(1100)).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        assert!(breaker.call(storage_call(&calls, true)).await.is_ok());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.call(storage_call(&calls, true)).await.is_ok());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(calls.load(Ordering::SeqCst), 7);
+    }
+
+    #[test]
+    fn half_open_failure_test() {
+        let now = Arc::new(AtomicU64::new(100));
+        let clock = now.clone();
+        let breaker = CircuitBreaker::with_clock(1, 1, 10, move || clock.load(Ordering::SeqCst));
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        now.store(110, Ordering::SeqCst);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        // a failed probe opens the breaker for another reset_timeout_secs
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        now.store(119, Ordering::SeqCst);
+        assert!(!breaker.allow_request());
+        now.store(120, Ordering::SeqCst);
+        assert!(breaker.allow_request());
+    }
+}
@@ -12,9 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod circuit_breaker;
+
 use std::fs;
 use std::path::{self, Path};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use local_ip_address::local_ip;
 use log::warn;
@@ -78,12 +81,38 @@ pub fn now_nanos() -> u128 {
 /// # Return value
 /// Returns the number of seconds since the Unix era (January 1, 1970 00:00:00 UTC).
 pub fn now_second() -> u64 {
+    now_second_wall()
+}
+
+/// Get current seconds from the system clock
+///
+/// The system clock can go backward, e.g. on an NTP adjustment, use it for the timestamps
+/// that are stored or compared across processes.
+///
+/// # Return value
+/// Returns the number of seconds since the Unix era (January 1, 1970 00:00:00 UTC).
+pub fn now_second_wall() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs()
 }
 
+static MONOTONIC_ANCHOR: OnceLock<(Instant, u64)> = OnceLock::new();
+
+/// Get current seconds from a clock that never goes backward
+///
+/// The seconds elapsed since the first call of the process, added to the wall clock time of
+/// that first call, so the values are close to `now_second_wall()` but only comparable
+/// within the same process.
+///
+/// # Return value
+/// Returns a number of seconds that never decreases.
+pub fn now_second_monotonic() -> u64 {
+    let (instant, wall) = MONOTONIC_ANCHOR.get_or_init(|| (Instant::now(), now_second_wall()));
+    wall + instant.elapsed().as_secs()
+}
+
 /// Generate a unique Uuid
 ///
 /// This function generates a version 4 UUID (Universally Unique Identifier) and converts it to string form,
@@ -180,7 +209,7 @@ where
 #[cfg(test)]
 mod tests {
     use crate::enum_type::time_unit_enum::TimeUnit;
-    use crate::tools::{convert_seconds, get_local_ip, unique_id};
+    use crate::tools::{convert_seconds, get_local_ip, now_second_monotonic, unique_id};
 
     #[test]
     fn get_local_ip_test() {
@@ -196,4 +225,16 @@ mod tests {
     fn test_convert_seconds() {
         assert_eq!(convert_seconds(1, TimeUnit::Minutes), 60);
     }
+
+    #[test]
+    fn now_second_monotonic_test() {
+        let mut last = now_second_monotonic();
+        assert!(last > 0);
+        for _ in 0..1000 {
+            let now = now_second_monotonic();
+            assert!(now >= last);
+            assert!(now > 0);
+            last = now;
+        }
+    }
 }
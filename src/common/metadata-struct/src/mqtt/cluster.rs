@@ -47,6 +47,66 @@ pub struct MqttClusterDynamicConfigProtocol {
     pub receive_max: u16,
     pub max_message_expiry_interval: u64,
     pub client_pkid_persistent: bool,
+    #[serde(default)]
+    pub client_id_max_len: u32,
+    #[serde(default)]
+    pub client_id_allowed_regex: String,
+    // Consumer lag in messages above which the PUBACK reports x-broker-lag, 0 means disabled
+    #[serde(default)]
+    pub flow_control_threshold: u64,
+    // Maximum number of shared subscription groups, 0 means unlimited
+    #[serde(default)]
+    pub max_shared_subscription_groups: u32,
+    // Maximum number of clients subscribed in a shared subscription group, 0 means unlimited
+    #[serde(default)]
+    pub max_shared_subscription_group_members: u32,
+    // Maximum number of messages in flight to a subscriber connection, 0 means one at a time
+    #[serde(default)]
+    pub delivery_concurrency: u32,
+    // Maximum number of retained messages delivered for a subscription, 0 means unlimited
+    #[serde(default)]
+    pub retain_max_per_subscribe: u32,
+    // Whether the newest retained messages are delivered first when the number is capped
+    #[serde(default)]
+    pub retain_newest_first: bool,
+    // Whether the shared subscription groups of a topic share a single read of its messages
+    #[serde(default)]
+    pub fan_out_read_once: bool,
+    // Whether a retained message with the same payload as the current one is not written again
+    #[serde(default)]
+    pub retain_skip_unchanged: bool,
+    // Maximum encoded size in bytes of the properties of a CONNECT, 0 means unlimited
+    #[serde(default)]
+    pub max_connect_properties_size: u32,
+    // Maximum number of user properties of a CONNECT, 0 means unlimited
+    #[serde(default)]
+    pub max_connect_user_properties: u32,
+    // Seconds a new subscription is delivered at a capped rate, 0 means disabled
+    #[serde(default)]
+    pub slow_start_duration_secs: u64,
+    // Messages delivered to a new subscription in its first second, doubled every second
+    #[serde(default)]
+    pub slow_start_initial_rate: u32,
+    // Records read from the topic but not yet delivered that a push thread holds in memory,
+    // 0 is unbounded
+    #[serde(default)]
+    pub max_reader_inflight_records: u32,
+    // Whether a batch read from the storage with out-of-order offsets fails the push, instead
+    // of being sorted by offset
+    #[serde(default)]
+    pub strict_record_offset_order: bool,
+    // Upper bound of the delivery rate, in messages per second, a client may request with the
+    // x-max-delivery-rate user property of a SUBSCRIBE, 0 is unbounded
+    #[serde(default)]
+    pub max_client_delivery_rate: u32,
+    // Seconds an inbound QoS 2 message waits for its PUBREL before its packet identifier is
+    // released, 0 means it waits as long as the session
+    #[serde(default)]
+    pub qos2_inflight_expire_secs: u64,
+    // Seconds without a publish after which the message shard of a topic without subscribers is
+    // deleted, its retained message is kept, 0 keeps the shards
+    #[serde(default)]
+    pub idle_topic_shard_expire_secs: u64,
 }
 
 impl MqttClusterDynamicConfigProtocol {
@@ -60,6 +120,18 @@ impl MqttClusterDynamicConfigProtocol {
 pub struct MqttClusterDynamicConfigSecurity {
     pub is_self_protection_status: bool,
     pub secret_free_login: bool,
+    // Whether a CONNECT without a username is accepted
+    #[serde(default)]
+    pub allow_anonymous: bool,
+    // Topic filters whose messages are authorized again against the ACL when they are delivered
+    #[serde(default)]
+    pub delivery_acl_check_topics: Vec<String>,
+    // Whether a subscriber denied at delivery time is also disconnected
+    #[serde(default)]
+    pub delivery_acl_deny_disconnect: bool,
+    // Whether a user stored with a plaintext password is upgraded to a hash on its next login
+    #[serde(default)]
+    pub upgrade_plaintext_password: bool,
 }
 
 impl MqttClusterDynamicConfigSecurity {
@@ -77,6 +149,12 @@ pub struct MqttClusterDynamicConfigNetwork {
     pub websockets_max_connection_num: u64,
     pub response_max_try_mut_times: u64,
     pub response_try_mut_sleep_time_ms: u64,
+    // IPs or CIDRs a connection is accepted from, empty accepts every peer
+    #[serde(default)]
+    pub accept_ip_allow_list: Vec<String>,
+    // IPs or CIDRs a connection is refused from, before the allow list is checked
+    #[serde(default)]
+    pub accept_ip_deny_list: Vec<String>,
 }
 
 // MQTT cluster Feature related dynamic configuration
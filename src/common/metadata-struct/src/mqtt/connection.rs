@@ -51,6 +51,8 @@ pub struct MQTTConnection {
     pub sender_qos_message: Arc<AtomicIsize>,
     // Time when the connection was created
     pub create_time: u64,
+    // Authentication Method of the CONNECT, a re-authentication must use the same method
+    pub authentication_method: Option<String>,
 }
 
 pub struct ConnectionConfig {
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use bytes::Bytes;
 use common_base::error::common::CommonError;
 use common_base::tools::now_second;
@@ -21,6 +23,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::adapter::record::Record;
 
+// The tags of a message are stored in the user properties named with this prefix
+pub const MESSAGE_TAG_USER_PROPERTY_PREFIX: &str = "x-tag-";
+
 #[derive(Clone, Serialize, Deserialize, Default, Debug)]
 pub struct MqttMessage {
     pub client_id: String,
@@ -110,7 +115,11 @@ impl MqttMessage {
         let msg =
             MqttMessage::build_message(client_id, publish, publish_properties, expiry_interval);
         match serde_json::to_vec(&msg) {
-            Ok(data) => Some(Record::build_byte(data)),
+            Ok(data) => {
+                let mut record = Record::build_byte(data);
+                record.tags = msg.record_tags();
+                Some(record)
+            }
 
             Err(e) => {
                 error!("Message encoding failed, error message :{}", e.to_string());
@@ -119,6 +128,42 @@ impl MqttMessage {
         }
     }
 
+    // (tag_key, tag_value) of the message
+    pub fn tags(&self) -> HashMap<String, String> {
+        self.user_properties
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(MESSAGE_TAG_USER_PROPERTY_PREFIX)
+                    .map(|tag_key| (tag_key.to_owned(), value.clone()))
+            })
+            .collect()
+    }
+
+    pub fn set_tag(&mut self, tag_key: &str, tag_value: &str) {
+        let key = format!("{}{}", MESSAGE_TAG_USER_PROPERTY_PREFIX, tag_key);
+        self.user_properties.retain(|(k, _)| *k != key);
+        self.user_properties.push((key, tag_value.to_owned()));
+    }
+
+    pub fn has_tag(&self, tag_key: &str, tag_value: &str) -> bool {
+        let key = format!("{}{}", MESSAGE_TAG_USER_PROPERTY_PREFIX, tag_key);
+        self.user_properties
+            .iter()
+            .any(|(k, v)| *k == key && v == tag_value)
+    }
+
+    // The tag of the record indexed by the storage for a tag of the message
+    pub fn record_tag(tag_key: &str, tag_value: &str) -> String {
+        format!("{}={}", tag_key, tag_value)
+    }
+
+    pub fn record_tags(&self) -> Vec<String> {
+        self.tags()
+            .iter()
+            .map(|(tag_key, tag_value)| MqttMessage::record_tag(tag_key, tag_value))
+            .collect()
+    }
+
     pub fn decode_record(record: Record) -> Result<MqttMessage, CommonError> {
         let data: MqttMessage = match serde_json::from_slice(record.data.as_slice()) {
             Ok(da) => da,
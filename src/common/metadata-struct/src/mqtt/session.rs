@@ -27,6 +27,10 @@ pub struct MqttSession {
     pub broker_id: Option<u64>,
     pub reconnect_time: Option<u64>,
     pub distinct_time: Option<u64>,
+    // The QoS 2 pkids received from the client whose PUBREL was not received yet, saved when
+    // the connection closes so that a redelivery after the reconnect is not stored twice
+    #[serde(default)]
+    pub qos2_pkids: Vec<u16>,
 }
 
 impl MqttSession {
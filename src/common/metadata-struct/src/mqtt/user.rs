@@ -12,17 +12,124 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{self, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use common_base::error::common::CommonError;
 use serde::{Deserialize, Serialize};
 
+/// The password of a user as it is stored, an argon2 PHC string or, for the users
+/// saved before the passwords were hashed, the plaintext password.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PasswordHash {
+    Argon2(String),
+    Plaintext(String),
+}
+
+impl PasswordHash {
+    pub fn hash(plaintext: &str) -> Result<PasswordHash, CommonError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(plaintext.as_bytes(), &salt)
+            .map_err(|e| CommonError::CommonError(format!("Failed to hash the password, {}", e)))?
+            .to_string();
+        Ok(PasswordHash::Argon2(hash))
+    }
+
+    pub fn parse(stored: &str) -> PasswordHash {
+        if password_hash::PasswordHash::new(stored)
+            .is_ok_and(|hash| hash.algorithm.as_str().starts_with("argon2"))
+        {
+            return PasswordHash::Argon2(stored.to_string());
+        }
+        PasswordHash::Plaintext(stored.to_string())
+    }
+
+    pub fn verify(&self, plaintext: &str) -> bool {
+        match self {
+            PasswordHash::Argon2(hash) => match password_hash::PasswordHash::new(hash) {
+                Ok(hash) => Argon2::default()
+                    .verify_password(plaintext.as_bytes(), &hash)
+                    .is_ok(),
+                Err(_) => false,
+            },
+            PasswordHash::Plaintext(password) => password == plaintext,
+        }
+    }
+
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self, PasswordHash::Plaintext(_))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            PasswordHash::Argon2(hash) => hash,
+            PasswordHash::Plaintext(password) => password,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct MqttUser {
     pub username: String,
+    // The stored password, see PasswordHash
     pub password: String,
     pub is_superuser: bool,
 }
 
 impl MqttUser {
+    pub fn new(username: String, plaintext: &str, is_superuser: bool) -> Result<Self, CommonError> {
+        Ok(MqttUser {
+            username,
+            password: PasswordHash::hash(plaintext)?.as_str().to_string(),
+            is_superuser,
+        })
+    }
+
+    pub fn password_hash(&self) -> PasswordHash {
+        PasswordHash::parse(&self.password)
+    }
+
+    pub fn verify(&self, plaintext: &str) -> bool {
+        self.password_hash().verify(plaintext)
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         serde_json::to_vec(&self).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{MqttUser, PasswordHash};
+
+    #[test]
+    fn user_password_verify_test() {
+        let user = MqttUser::new("lobo".to_string(), "pwd123", false).unwrap();
+        assert_ne!(user.password, "pwd123");
+        assert!(!user.password_hash().is_plaintext());
+        assert!(user.verify("pwd123"));
+        assert!(!user.verify("pwd1111"));
+        assert!(!user.verify(""));
+
+        // the same password is salted differently for every user
+        let other = MqttUser::new("lobo".to_string(), "pwd123", false).unwrap();
+        assert_ne!(user.password, other.password);
+    }
+
+    #[test]
+    fn plaintext_password_verify_test() {
+        let user = MqttUser {
+            username: "lobo".to_string(),
+            password: "pwd123".to_string(),
+            is_superuser: false,
+        };
+        assert!(user.password_hash().is_plaintext());
+        assert!(user.verify("pwd123"));
+        assert!(!user.verify("pwd1111"));
+
+        // a plaintext password that looks like a PHC string of another algorithm
+        assert!(PasswordHash::parse("$unknown$abc").is_plaintext());
+        assert!(PasswordHash::parse("$unknown$abc").verify("$unknown$abc"));
+    }
+}
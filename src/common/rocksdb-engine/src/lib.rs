@@ -141,6 +141,34 @@ impl RocksDBEngine {
         Ok(result)
     }
 
+    // Search data by prefix backwards from the last key, at most limit entries
+    pub fn read_prefix_rev(
+        &self,
+        cf: Arc<BoundColumnFamily>,
+        search_key: &str,
+        last_key: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u8>)>, CommonError> {
+        let mut iter = self.db.raw_iterator_cf(&cf);
+        iter.seek_for_prev(last_key);
+
+        let mut result = Vec::new();
+        while iter.valid() && result.len() < limit {
+            if let Some(key) = iter.key() {
+                if let Some(val) = iter.value() {
+                    let key = String::from_utf8(key.to_vec())?;
+                    if !key.starts_with(search_key) {
+                        break;
+                    }
+                    result.push((key, val.to_vec()));
+                }
+            }
+
+            iter.prev();
+        }
+        Ok(result)
+    }
+
     // Read all data in a ColumnFamily
     pub fn read_all_by_cf(
         &self,
@@ -399,4 +427,43 @@ mod tests {
 
         remove_dir_all(config.rocksdb.data_path).await.unwrap();
     }
+
+    #[tokio::test]
+    async fn read_prefix_rev() {
+        let config = placement_center_test_conf();
+
+        let rs = RocksDBEngine::new(
+            &config.rocksdb.data_path,
+            config.rocksdb.max_open_files.unwrap(),
+            vec!["cluster".to_string()],
+        );
+
+        let cf = rs.cf_handle(&cf_name()).unwrap();
+
+        rs.write_str(cf.clone(), "/rev/tmp_test/s1", "1".to_string())
+            .unwrap();
+        rs.write_str(cf.clone(), "/rev/tmp_test/s2", "2".to_string())
+            .unwrap();
+        rs.write_str(cf.clone(), "/rev/tmp_test/s3", "3".to_string())
+            .unwrap();
+        rs.write_str(cf.clone(), "/rev/tmp_test/t1", "4".to_string())
+            .unwrap();
+
+        let result = rs
+            .read_prefix_rev(cf.clone(), "/rev/tmp_test/s", "/rev/tmp_test/s9", 10)
+            .unwrap();
+        let keys: Vec<String> = result.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(
+            keys,
+            vec!["/rev/tmp_test/s3", "/rev/tmp_test/s2", "/rev/tmp_test/s1"]
+        );
+
+        let result = rs
+            .read_prefix_rev(cf.clone(), "/rev/tmp_test/s", "/rev/tmp_test/s2", 1)
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].0, "/rev/tmp_test/s2");
+
+        remove_dir_all(config.rocksdb.data_path).await.unwrap();
+    }
 }
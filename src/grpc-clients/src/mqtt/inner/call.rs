@@ -14,8 +14,10 @@
 
 use common_base::error::common::CommonError;
 use protocol::broker_mqtt::broker_mqtt_inner::{
-    DeleteSessionReply, DeleteSessionRequest, SendLastWillMessageReply, SendLastWillMessageRequest,
-    UpdateMqttCacheReply, UpdateMqttCacheRequest,
+    DeleteSessionReply, DeleteSessionRequest, ExportSessionReply, ExportSessionRequest,
+    ForwardMessageReply, ForwardMessageRequest, ImportSessionReply, ImportSessionRequest,
+    SendLastWillMessageReply, SendLastWillMessageRequest, UpdateMqttCacheReply,
+    UpdateMqttCacheRequest,
 };
 
 use crate::pool::ClientPool;
@@ -52,3 +54,24 @@ generate_mqtt_inner_service_call!(
     SendLastWillMessageReply,
     SendLastWillMessage
 );
+
+generate_mqtt_inner_service_call!(
+    broker_mqtt_forward_message,
+    ForwardMessageRequest,
+    ForwardMessageReply,
+    ForwardMessage
+);
+
+generate_mqtt_inner_service_call!(
+    broker_mqtt_export_session,
+    ExportSessionRequest,
+    ExportSessionReply,
+    ExportSession
+);
+
+generate_mqtt_inner_service_call!(
+    broker_mqtt_import_session,
+    ImportSessionRequest,
+    ImportSessionReply,
+    ImportSession
+);
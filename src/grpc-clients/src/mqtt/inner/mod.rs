@@ -16,8 +16,10 @@ use common_base::error::common::CommonError;
 use mobc::Manager;
 use protocol::broker_mqtt::broker_mqtt_inner::mqtt_broker_inner_service_client::MqttBrokerInnerServiceClient;
 use protocol::broker_mqtt::broker_mqtt_inner::{
-    DeleteSessionReply, DeleteSessionRequest, SendLastWillMessageReply, SendLastWillMessageRequest,
-    UpdateMqttCacheReply, UpdateMqttCacheRequest,
+    DeleteSessionReply, DeleteSessionRequest, ExportSessionReply, ExportSessionRequest,
+    ForwardMessageReply, ForwardMessageRequest, ImportSessionReply, ImportSessionRequest,
+    SendLastWillMessageReply, SendLastWillMessageRequest, UpdateMqttCacheReply,
+    UpdateMqttCacheRequest,
 };
 use tonic::transport::Channel;
 
@@ -83,3 +85,27 @@ impl_retriable_request!(
     mqtt_broker_mqtt_services_client,
     send_last_will_message
 );
+
+impl_retriable_request!(
+    ForwardMessageRequest,
+    MqttBrokerInnerServiceClient<Channel>,
+    ForwardMessageReply,
+    mqtt_broker_mqtt_services_client,
+    forward_message
+);
+
+impl_retriable_request!(
+    ExportSessionRequest,
+    MqttBrokerInnerServiceClient<Channel>,
+    ExportSessionReply,
+    mqtt_broker_mqtt_services_client,
+    export_session
+);
+
+impl_retriable_request!(
+    ImportSessionRequest,
+    MqttBrokerInnerServiceClient<Channel>,
+    ImportSessionReply,
+    mqtt_broker_mqtt_services_client,
+    import_session
+);
@@ -14,12 +14,13 @@
 
 use common_base::error::common::CommonError;
 use protocol::placement_center::placement_center_inner::{
-    BindSchemaReply, BindSchemaRequest, ClusterStatusReply, ClusterStatusRequest,
-    CreateSchemaReply, CreateSchemaRequest, DeleteIdempotentDataReply, DeleteIdempotentDataRequest,
-    DeleteResourceConfigReply, DeleteResourceConfigRequest, DeleteSchemaReply, DeleteSchemaRequest,
-    ExistsIdempotentDataReply, ExistsIdempotentDataRequest, GetOffsetDataReply,
-    GetOffsetDataRequest, GetResourceConfigReply, GetResourceConfigRequest, HeartbeatReply,
-    HeartbeatRequest, ListBindSchemaReply, ListBindSchemaRequest, ListSchemaReply,
+    BindSchemaReply, BindSchemaRequest, BrokerHeartbeatReply, BrokerHeartbeatRequest,
+    ClusterStatusReply, ClusterStatusRequest, CreateSchemaReply, CreateSchemaRequest,
+    DeleteIdempotentDataReply, DeleteIdempotentDataRequest, DeleteResourceConfigReply,
+    DeleteResourceConfigRequest, DeleteSchemaReply, DeleteSchemaRequest, DescribeClusterReply,
+    DescribeClusterRequest, ExistsIdempotentDataReply, ExistsIdempotentDataRequest,
+    GetOffsetDataReply, GetOffsetDataRequest, GetResourceConfigReply, GetResourceConfigRequest,
+    HeartbeatReply, HeartbeatRequest, ListBindSchemaReply, ListBindSchemaRequest, ListSchemaReply,
     ListSchemaRequest, NodeListReply, NodeListRequest, RegisterNodeReply, RegisterNodeRequest,
     SaveOffsetDataReply, SaveOffsetDataRequest, SetIdempotentDataReply, SetIdempotentDataRequest,
     SetResourceConfigReply, SetResourceConfigRequest, UnBindSchemaReply, UnBindSchemaRequest,
@@ -46,6 +47,12 @@ generate_placement_service_call!(
     ClusterStatusReply,
     ClusterStatus
 );
+generate_placement_service_call!(
+    describe_cluster,
+    DescribeClusterRequest,
+    DescribeClusterReply,
+    DescribeCluster
+);
 generate_placement_service_call!(node_list, NodeListRequest, NodeListReply, ListNode);
 generate_placement_service_call!(
     register_node,
@@ -60,6 +67,12 @@ generate_placement_service_call!(
     UnRegisterNode
 );
 generate_placement_service_call!(heartbeat, HeartbeatRequest, HeartbeatReply, Heartbeat);
+generate_placement_service_call!(
+    broker_heartbeat,
+    BrokerHeartbeatRequest,
+    BrokerHeartbeatReply,
+    BrokerHeartbeat
+);
 
 generate_placement_service_call!(
     set_resource_config,
@@ -16,12 +16,13 @@ use common_base::error::common::CommonError;
 use mobc::Manager;
 use protocol::placement_center::placement_center_inner::placement_center_service_client::PlacementCenterServiceClient;
 use protocol::placement_center::placement_center_inner::{
-    BindSchemaReply, BindSchemaRequest, ClusterStatusReply, ClusterStatusRequest,
-    CreateSchemaReply, CreateSchemaRequest, DeleteIdempotentDataReply, DeleteIdempotentDataRequest,
-    DeleteResourceConfigReply, DeleteResourceConfigRequest, DeleteSchemaReply, DeleteSchemaRequest,
-    ExistsIdempotentDataReply, ExistsIdempotentDataRequest, GetOffsetDataReply,
-    GetOffsetDataRequest, GetResourceConfigReply, GetResourceConfigRequest, HeartbeatReply,
-    HeartbeatRequest, ListBindSchemaReply, ListBindSchemaRequest, ListSchemaReply,
+    BindSchemaReply, BindSchemaRequest, BrokerHeartbeatReply, BrokerHeartbeatRequest,
+    ClusterStatusReply, ClusterStatusRequest, CreateSchemaReply, CreateSchemaRequest,
+    DeleteIdempotentDataReply, DeleteIdempotentDataRequest, DeleteResourceConfigReply,
+    DeleteResourceConfigRequest, DeleteSchemaReply, DeleteSchemaRequest, DescribeClusterReply,
+    DescribeClusterRequest, ExistsIdempotentDataReply, ExistsIdempotentDataRequest,
+    GetOffsetDataReply, GetOffsetDataRequest, GetResourceConfigReply, GetResourceConfigRequest,
+    HeartbeatReply, HeartbeatRequest, ListBindSchemaReply, ListBindSchemaRequest, ListSchemaReply,
     ListSchemaRequest, NodeListReply, NodeListRequest, RegisterNodeReply, RegisterNodeRequest,
     SaveOffsetDataReply, SaveOffsetDataRequest, SetIdempotentDataReply, SetIdempotentDataRequest,
     SetResourceConfigReply, SetResourceConfigRequest, UnBindSchemaReply, UnBindSchemaRequest,
@@ -77,6 +78,15 @@ impl_retriable_request!(
     true
 );
 
+impl_retriable_request!(
+    DescribeClusterRequest,
+    PlacementCenterServiceClient<Channel>,
+    DescribeClusterReply,
+    placement_center_inner_services_client,
+    describe_cluster,
+    true
+);
+
 impl_retriable_request!(
     NodeListRequest,
     PlacementCenterServiceClient<Channel>,
@@ -113,6 +123,15 @@ impl_retriable_request!(
     true
 );
 
+impl_retriable_request!(
+    BrokerHeartbeatRequest,
+    PlacementCenterServiceClient<Channel>,
+    BrokerHeartbeatReply,
+    placement_center_inner_services_client,
+    broker_heartbeat,
+    true
+);
+
 impl_retriable_request!(
     SetResourceConfigRequest,
     PlacementCenterServiceClient<Channel>,
@@ -18,7 +18,7 @@ use protocol::placement_center::placement_center_journal::{
     DeleteSegmentReply, DeleteSegmentRequest, DeleteShardReply, DeleteShardRequest,
     ListSegmentMetaReply, ListSegmentMetaRequest, ListSegmentReply, ListSegmentRequest,
     ListShardReply, ListShardRequest, UpdateSegmentMetaReply, UpdateSegmentMetaRequest,
-    UpdateSegmentStatusReply, UpdateSegmentStatusRequest,
+    UpdateSegmentStatusReply, UpdateSegmentStatusRequest, UpdateShardReply, UpdateShardRequest,
 };
 
 use crate::pool::ClientPool;
@@ -48,6 +48,12 @@ generate_journal_service_call!(
     DeleteShardReply,
     DeleteShard
 );
+generate_journal_service_call!(
+    update_shard,
+    UpdateShardRequest,
+    UpdateShardReply,
+    UpdateShard
+);
 generate_journal_service_call!(
     list_segment,
     ListSegmentRequest,
@@ -20,7 +20,7 @@ use protocol::placement_center::placement_center_journal::{
     DeleteSegmentReply, DeleteSegmentRequest, DeleteShardReply, DeleteShardRequest,
     ListSegmentMetaReply, ListSegmentMetaRequest, ListSegmentReply, ListSegmentRequest,
     ListShardReply, ListShardRequest, UpdateSegmentMetaReply, UpdateSegmentMetaRequest,
-    UpdateSegmentStatusReply, UpdateSegmentStatusRequest,
+    UpdateSegmentStatusReply, UpdateSegmentStatusRequest, UpdateShardReply, UpdateShardRequest,
 };
 use tonic::transport::Channel;
 
@@ -91,6 +91,15 @@ impl_retriable_request!(
     true
 );
 
+impl_retriable_request!(
+    UpdateShardRequest,
+    EngineServiceClient<Channel>,
+    UpdateShardReply,
+    placement_center_journal_services_client,
+    update_shard,
+    true
+);
+
 impl_retriable_request!(
     ListSegmentRequest,
     EngineServiceClient<Channel>,
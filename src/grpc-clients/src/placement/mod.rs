@@ -75,6 +75,7 @@ pub enum PlacementCenterInterface {
     Snapshot,
     AddLearner,
     ChangeMembership,
+    ReadIndex,
 }
 
 #[allow(clippy::module_inception)]
@@ -19,16 +19,18 @@ use protocol::placement_center::placement_center_mqtt::{
     CreateSessionReply, CreateSessionRequest, CreateTopicReply, CreateTopicRequest,
     CreateTopicRewriteRuleReply, CreateTopicRewriteRuleRequest, CreateUserReply, CreateUserRequest,
     DeleteAclReply, DeleteAclRequest, DeleteBlacklistReply, DeleteBlacklistRequest,
-    DeleteConnectorReply, DeleteConnectorRequest, DeleteSessionReply, DeleteSessionRequest,
-    DeleteSubscribeReply, DeleteSubscribeRequest, DeleteTopicReply, DeleteTopicRequest,
-    DeleteTopicRewriteRuleReply, DeleteTopicRewriteRuleRequest, DeleteUserReply, DeleteUserRequest,
-    GetShareSubLeaderReply, GetShareSubLeaderRequest, ListAclReply, ListAclRequest,
-    ListBlacklistReply, ListBlacklistRequest, ListConnectorReply, ListConnectorRequest,
-    ListSessionReply, ListSessionRequest, ListSubscribeReply, ListSubscribeRequest, ListTopicReply,
-    ListTopicRequest, ListTopicRewriteRuleReply, ListTopicRewriteRuleRequest, ListUserReply,
-    ListUserRequest, SaveLastWillMessageReply, SaveLastWillMessageRequest, SetSubscribeReply,
-    SetSubscribeRequest, SetTopicRetainMessageReply, SetTopicRetainMessageRequest,
-    UpdateConnectorReply, UpdateConnectorRequest, UpdateSessionReply, UpdateSessionRequest,
+    DeleteConnectorReply, DeleteConnectorRequest, DeleteLastWillMessageReply,
+    DeleteLastWillMessageRequest, DeleteSessionReply, DeleteSessionRequest, DeleteSubscribeReply,
+    DeleteSubscribeRequest, DeleteTopicReply, DeleteTopicRequest, DeleteTopicRewriteRuleReply,
+    DeleteTopicRewriteRuleRequest, DeleteUserReply, DeleteUserRequest, GetLastWillMessageReply,
+    GetLastWillMessageRequest, GetShareSubLeaderReply, GetShareSubLeaderRequest, ListAclReply,
+    ListAclRequest, ListBlacklistReply, ListBlacklistRequest, ListConnectorReply,
+    ListConnectorRequest, ListSessionReply, ListSessionRequest, ListSubscribeReply,
+    ListSubscribeRequest, ListTopicReply, ListTopicRequest, ListTopicRewriteRuleReply,
+    ListTopicRewriteRuleRequest, ListUserReply, ListUserRequest, SaveLastWillMessageReply,
+    SaveLastWillMessageRequest, SetSubscribeReply, SetSubscribeRequest, SetTopicRetainMessageReply,
+    SetTopicRetainMessageRequest, UpdateConnectorReply, UpdateConnectorRequest, UpdateSessionReply,
+    UpdateSessionRequest,
 };
 
 use crate::pool::ClientPool;
@@ -123,6 +125,18 @@ generate_mqtt_service_call!(
     SaveLastWillMessageReply,
     SaveLastWillMessage
 );
+generate_mqtt_service_call!(
+    placement_get_last_will_message,
+    GetLastWillMessageRequest,
+    GetLastWillMessageReply,
+    GetLastWillMessage
+);
+generate_mqtt_service_call!(
+    placement_delete_last_will_message,
+    DeleteLastWillMessageRequest,
+    DeleteLastWillMessageReply,
+    DeleteLastWillMessage
+);
 generate_mqtt_service_call!(create_acl, CreateAclRequest, CreateAclReply, CreateAcl);
 generate_mqtt_service_call!(delete_acl, DeleteAclRequest, DeleteAclReply, DeleteAcl);
 generate_mqtt_service_call!(list_acl, ListAclRequest, ListAclReply, ListAcl);
@@ -21,16 +21,18 @@ use protocol::placement_center::placement_center_mqtt::{
     CreateSessionReply, CreateSessionRequest, CreateTopicReply, CreateTopicRequest,
     CreateTopicRewriteRuleReply, CreateTopicRewriteRuleRequest, CreateUserReply, CreateUserRequest,
     DeleteAclReply, DeleteAclRequest, DeleteBlacklistReply, DeleteBlacklistRequest,
-    DeleteConnectorReply, DeleteConnectorRequest, DeleteSessionReply, DeleteSessionRequest,
-    DeleteSubscribeReply, DeleteSubscribeRequest, DeleteTopicReply, DeleteTopicRequest,
-    DeleteTopicRewriteRuleReply, DeleteTopicRewriteRuleRequest, DeleteUserReply, DeleteUserRequest,
-    GetShareSubLeaderReply, GetShareSubLeaderRequest, ListAclReply, ListAclRequest,
-    ListBlacklistReply, ListBlacklistRequest, ListConnectorReply, ListConnectorRequest,
-    ListSessionReply, ListSessionRequest, ListSubscribeReply, ListSubscribeRequest, ListTopicReply,
-    ListTopicRequest, ListTopicRewriteRuleReply, ListTopicRewriteRuleRequest, ListUserReply,
-    ListUserRequest, SaveLastWillMessageReply, SaveLastWillMessageRequest, SetSubscribeReply,
-    SetSubscribeRequest, SetTopicRetainMessageReply, SetTopicRetainMessageRequest,
-    UpdateConnectorReply, UpdateConnectorRequest, UpdateSessionReply, UpdateSessionRequest,
+    DeleteConnectorReply, DeleteConnectorRequest, DeleteLastWillMessageReply,
+    DeleteLastWillMessageRequest, DeleteSessionReply, DeleteSessionRequest, DeleteSubscribeReply,
+    DeleteSubscribeRequest, DeleteTopicReply, DeleteTopicRequest, DeleteTopicRewriteRuleReply,
+    DeleteTopicRewriteRuleRequest, DeleteUserReply, DeleteUserRequest, GetLastWillMessageReply,
+    GetLastWillMessageRequest, GetShareSubLeaderReply, GetShareSubLeaderRequest, ListAclReply,
+    ListAclRequest, ListBlacklistReply, ListBlacklistRequest, ListConnectorReply,
+    ListConnectorRequest, ListSessionReply, ListSessionRequest, ListSubscribeReply,
+    ListSubscribeRequest, ListTopicReply, ListTopicRequest, ListTopicRewriteRuleReply,
+    ListTopicRewriteRuleRequest, ListUserReply, ListUserRequest, SaveLastWillMessageReply,
+    SaveLastWillMessageRequest, SetSubscribeReply, SetSubscribeRequest, SetTopicRetainMessageReply,
+    SetTopicRetainMessageRequest, UpdateConnectorReply, UpdateConnectorRequest, UpdateSessionReply,
+    UpdateSessionRequest,
 };
 use tonic::transport::Channel;
 
@@ -191,6 +193,24 @@ impl_retriable_request!(
     true
 );
 
+impl_retriable_request!(
+    GetLastWillMessageRequest,
+    MqttServiceClient<Channel>,
+    GetLastWillMessageReply,
+    placement_center_mqtt_services_client,
+    get_last_will_message,
+    true
+);
+
+impl_retriable_request!(
+    DeleteLastWillMessageRequest,
+    MqttServiceClient<Channel>,
+    DeleteLastWillMessageReply,
+    placement_center_mqtt_services_client,
+    delete_last_will_message,
+    true
+);
+
 impl_retriable_request!(
     CreateAclRequest,
     MqttServiceClient<Channel>,
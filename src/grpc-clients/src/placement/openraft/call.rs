@@ -15,7 +15,8 @@
 use common_base::error::common::CommonError;
 use protocol::placement_center::placement_center_openraft::{
     AddLearnerReply, AddLearnerRequest, AppendReply, AppendRequest, ChangeMembershipReply,
-    ChangeMembershipRequest, SnapshotReply, SnapshotRequest, VoteReply, VoteRequest,
+    ChangeMembershipRequest, ReadIndexReply, ReadIndexRequest, SnapshotReply, SnapshotRequest,
+    VoteReply, VoteRequest,
 };
 
 use crate::pool::ClientPool;
@@ -57,3 +58,9 @@ generate_openraft_service_call!(
     ChangeMembershipReply,
     ChangeMembership
 );
+generate_openraft_service_call!(
+    placement_openraft_read_index,
+    ReadIndexRequest,
+    ReadIndexReply,
+    ReadIndex
+);
@@ -17,7 +17,8 @@ use mobc::Manager;
 use protocol::placement_center::placement_center_openraft::open_raft_service_client::OpenRaftServiceClient;
 use protocol::placement_center::placement_center_openraft::{
     AddLearnerReply, AddLearnerRequest, AppendReply, AppendRequest, ChangeMembershipReply,
-    ChangeMembershipRequest, SnapshotReply, SnapshotRequest, VoteReply, VoteRequest,
+    ChangeMembershipRequest, ReadIndexReply, ReadIndexRequest, SnapshotReply, SnapshotRequest,
+    VoteReply, VoteRequest,
 };
 use tonic::transport::Channel;
 
@@ -101,3 +102,12 @@ impl_retriable_request!(
     change_membership,
     true
 );
+
+impl_retriable_request!(
+    ReadIndexRequest,
+    OpenRaftServiceClient<Channel>,
+    ReadIndexReply,
+    placement_center_openraft_services_client,
+    read_index,
+    true
+);
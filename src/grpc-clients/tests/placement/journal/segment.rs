@@ -17,13 +17,14 @@ mod tests {
 
     use common_base::tools::unique_id;
     use grpc_clients::placement::inner::call::register_node;
-    use grpc_clients::placement::journal::call::{create_next_segment, create_shard};
+    use grpc_clients::placement::journal::call::{create_next_segment, create_shard, list_segment};
     use grpc_clients::pool::ClientPool;
     use metadata_struct::journal::node_extend::JournalNodeExtend;
+    use metadata_struct::journal::segment::JournalSegment;
     use metadata_struct::journal::shard::JournalShardConfig;
     use protocol::placement_center::placement_center_inner::{ClusterType, RegisterNodeRequest};
     use protocol::placement_center::placement_center_journal::{
-        CreateNextSegmentRequest, CreateShardRequest,
+        CreateNextSegmentRequest, CreateShardRequest, ListSegmentRequest,
     };
 
     use crate::common::get_placement_addr;
@@ -71,9 +72,72 @@ mod tests {
             cluster_name: cluster.clone(),
             namespace: namespace.clone(),
             shard_name: shard_name.clone(),
+            idempotency_key: None,
         };
         create_next_segment(&client_pool, &addrs, request)
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn create_next_segment_idempotency_test() {
+        let client_pool = ClientPool::new(1);
+        let addrs = vec![get_placement_addr()];
+
+        let cluster = unique_id();
+        let namespace = "n1".to_string();
+        let shard_name = "s1".to_string();
+
+        let extend = JournalNodeExtend {
+            tcp_addr: "".to_string(),
+            tcps_addr: "".to_string(),
+            data_fold: vec!["/data".to_string()],
+        };
+        let request = RegisterNodeRequest {
+            cluster_type: ClusterType::JournalServer.into(),
+            cluster_name: cluster.clone(),
+            node_id: 1,
+            node_ip: "127.0.0.1".to_string(),
+            node_inner_addr: "127.0.0.1:3228".to_string(),
+            extend_info: serde_json::to_string(&extend).unwrap(),
+        };
+        register_node(&client_pool, &addrs, request).await.unwrap();
+
+        let config = JournalShardConfig {
+            replica_num: 1,
+            max_segment_size: 10 * 1024 * 1024,
+        };
+        let request = CreateShardRequest {
+            cluster_name: cluster.clone(),
+            namespace: namespace.clone(),
+            shard_name: shard_name.clone(),
+            shard_config: serde_json::to_vec(&config).unwrap(),
+        };
+        create_shard(&client_pool, &addrs, request).await.unwrap();
+
+        // the retry with the same key must not create another segment
+        let idempotency_key = unique_id();
+        for _ in 0..2 {
+            let request = CreateNextSegmentRequest {
+                cluster_name: cluster.clone(),
+                namespace: namespace.clone(),
+                shard_name: shard_name.clone(),
+                idempotency_key: Some(idempotency_key.clone()),
+            };
+            create_next_segment(&client_pool, &addrs, request)
+                .await
+                .unwrap();
+        }
+
+        let request = ListSegmentRequest {
+            cluster_name: cluster.clone(),
+            namespace: namespace.clone(),
+            shard_name: shard_name.clone(),
+            segment_no: -1,
+        };
+        let reply = list_segment(&client_pool, &addrs, request).await.unwrap();
+        let data: Vec<JournalSegment> = serde_json::from_slice(&reply.segments).unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data.last().unwrap().segment_seq, 1);
+    }
 }
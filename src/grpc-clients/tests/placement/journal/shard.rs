@@ -154,6 +154,7 @@ mod tests {
             cluster_name: cluster_name.clone(),
             shard_name: shard_name.clone(),
             namespace: namespace.clone(),
+            idempotency_key: None,
         };
         create_next_segment(&client_pool, &addrs, request)
             .await
@@ -231,6 +232,7 @@ mod tests {
             cluster_name: cluster_name.clone(),
             shard_name: shard_name.clone(),
             namespace: namespace.clone(),
+            idempotency_key: None,
         };
         create_next_segment(&client_pool, &addrs, request)
             .await
@@ -442,6 +444,7 @@ mod tests {
             cluster_name: cluster_name.clone(),
             shard_name: shard_name.clone(),
             namespace: namespace.clone(),
+            idempotency_key: None,
         };
         create_next_segment(&client_pool, &addrs, request)
             .await
@@ -551,6 +554,7 @@ mod tests {
             cluster_name: cluster_name.clone(),
             shard_name: shard_name.clone(),
             namespace: namespace.clone(),
+            idempotency_key: None,
         };
         create_next_segment(&client_pool, &addrs, request)
             .await
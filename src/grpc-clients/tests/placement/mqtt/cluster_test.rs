@@ -21,6 +21,7 @@ mod tests {
         get_resource_config, node_list, register_node, set_resource_config, unregister_node,
     };
     use grpc_clients::pool::ClientPool;
+    use metadata_struct::placement::node::BrokerNode;
     use protocol::placement_center::placement_center_inner::{
         ClusterStatusRequest, ClusterType, DeleteIdempotentDataRequest,
         DeleteResourceConfigRequest, ExistsIdempotentDataRequest, GetResourceConfigRequest,
@@ -139,17 +140,61 @@ mod tests {
 
         let request = NodeListRequest {
             cluster_name: cluster_name.clone(),
+            consistent: false,
         };
         assert!(node_list(&client_pool, &addrs, request).await.is_ok());
 
         let request_cluster_name_empty = NodeListRequest {
             cluster_name: "".to_string(),
+            consistent: false,
         };
         assert!(node_list(&client_pool, &addrs, request_cluster_name_empty)
             .await
             .is_err());
     }
 
+    #[tokio::test]
+    async fn node_list_consistent_read_test() {
+        let client_pool: Arc<ClientPool> = Arc::new(ClientPool::new(1));
+        let addrs = vec![get_placement_addr()];
+
+        let cluster_type = ClusterType::PlacementCenter as i32;
+        let cluster_name = "test-consistent-read-cluster".to_string();
+        let node_id = 1236u64;
+
+        let request = RegisterNodeRequest {
+            cluster_type,
+            cluster_name: cluster_name.clone(),
+            node_ip: "127.0.0.1".to_string(),
+            node_id,
+            node_inner_addr: "127.0.0.1".to_string(),
+            extend_info: "".to_string(),
+        };
+        assert!(register_node(&client_pool, &addrs, request).await.is_ok());
+
+        // Read on every placement node right after the write, including the followers
+        for addr in addrs.iter() {
+            let request = NodeListRequest {
+                cluster_name: cluster_name.clone(),
+                consistent: true,
+            };
+            let reply = node_list(&client_pool, &[addr], request).await.unwrap();
+            let nodes: Vec<BrokerNode> = reply
+                .nodes
+                .iter()
+                .map(|raw| serde_json::from_slice::<BrokerNode>(raw).unwrap())
+                .collect();
+            assert!(nodes.iter().any(|node| node.node_id == node_id));
+        }
+
+        let request = UnRegisterNodeRequest {
+            cluster_type,
+            cluster_name: cluster_name.clone(),
+            node_id,
+        };
+        assert!(unregister_node(&client_pool, &addrs, request).await.is_ok());
+    }
+
     #[tokio::test]
     async fn delete_idempotent_data_test() {
         let client_pool: Arc<ClientPool> = Arc::new(ClientPool::new(1));
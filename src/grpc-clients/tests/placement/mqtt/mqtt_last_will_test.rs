@@ -17,10 +17,15 @@ mod tests {
     use std::sync::Arc;
 
     use common_base::tools::unique_id;
-    use grpc_clients::placement::mqtt::call::placement_save_last_will_message;
+    use grpc_clients::placement::mqtt::call::{
+        placement_delete_last_will_message, placement_get_last_will_message,
+        placement_save_last_will_message,
+    };
     use grpc_clients::pool::ClientPool;
     use metadata_struct::mqtt::lastwill::LastWillData;
-    use protocol::placement_center::placement_center_mqtt::SaveLastWillMessageRequest;
+    use protocol::placement_center::placement_center_mqtt::{
+        DeleteLastWillMessageRequest, GetLastWillMessageRequest, SaveLastWillMessageRequest,
+    };
 
     use crate::common::get_placement_addr;
 
@@ -49,5 +54,40 @@ mod tests {
                 panic!("{:?}", e);
             }
         }
+
+        let request = GetLastWillMessageRequest {
+            cluster_name: cluster_name.clone(),
+            client_id: client_id.clone(),
+        };
+        match placement_get_last_will_message(&client_pool, &addrs, request.clone()).await {
+            Ok(reply) => {
+                let data =
+                    serde_json::from_slice::<LastWillData>(&reply.last_will_message).unwrap();
+                assert_eq!(data.client_id, client_id);
+            }
+            Err(e) => {
+                panic!("{:?}", e);
+            }
+        }
+
+        let delete_request = DeleteLastWillMessageRequest {
+            cluster_name: cluster_name.clone(),
+            client_id: client_id.clone(),
+        };
+        match placement_delete_last_will_message(&client_pool, &addrs, delete_request).await {
+            Ok(_) => {}
+            Err(e) => {
+                panic!("{:?}", e);
+            }
+        }
+
+        match placement_get_last_will_message(&client_pool, &addrs, request).await {
+            Ok(reply) => {
+                assert!(reply.last_will_message.is_empty());
+            }
+            Err(e) => {
+                panic!("{:?}", e);
+            }
+        }
     }
 }
@@ -22,8 +22,8 @@ use metadata_struct::adapter::read_config::ReadConfig;
 use metadata_struct::journal::segment::segment_name;
 use metadata_struct::journal::shard::shard_name_iden;
 use protocol::journal_server::journal_engine::{
-    FetchOffsetReqBody, FetchOffsetShard, ReadReqBody, ReadReqFilter, ReadReqMessage,
-    ReadReqOptions, ReadType,
+    ClientSegmentMetadata, FetchOffsetReqBody, FetchOffsetShard, ReadReqBody, ReadReqFilter,
+    ReadReqMessage, ReadReqOptions, ReadType,
 };
 use tokio::select;
 use tokio::sync::broadcast::{self, Receiver, Sender};
@@ -160,7 +160,8 @@ pub async fn async_read_data_by_offset(
     shards: &Vec<ReadShardByOffset>,
     read_config: &ReadConfig,
 ) -> Result<Vec<ReadMessageData>, JournalClientError> {
-    let leader_shards = group_by_reader_leader(connection_manager, metadata_cache, shards).await;
+    let leader_shards =
+        group_by_offset_segment_leader(connection_manager, metadata_cache, shards).await;
     let mut results = Vec::new();
     for (leader_id, shards) in leader_shards {
         let mut messages = Vec::new();
@@ -332,6 +333,68 @@ async fn group_by_reader_leader(
     result
 }
 
+// The segment holding the offset: the sealed segment whose offsets contain it, or the segment
+// still being written once the offset is past all the sealed ones. A read reaching the end of a
+// sealed segment returns its last messages, the next read from the following offset then goes
+// to the next segment.
+fn read_segment_by_offset(
+    segments: &[ClientSegmentMetadata],
+    offset: u64,
+) -> Option<&ClientSegmentMetadata> {
+    let mut segments: Vec<&ClientSegmentMetadata> = segments.iter().collect();
+    segments.sort_by_key(|meta| meta.segment_no);
+    segments
+        .iter()
+        .find(|meta| meta.end_offset < 0 || offset as i64 <= meta.end_offset)
+        .or(segments.last())
+        .copied()
+}
+
+// Groups the offset reads by the leader of the segment holding the offset, so that a shard
+// spanning several segments is read from its oldest segment not consumed yet. The segments
+// sealed since the metadata was cached are seen after the next refresh of the cache.
+async fn group_by_offset_segment_leader(
+    connection_manager: &Arc<ConnectionManager>,
+    metadata_cache: &Arc<MetadataCache>,
+    shards: &Vec<ReadShardByOffset>,
+) -> DashMap<u64, Vec<(u32, ReadShardByOffset)>> {
+    let result: DashMap<u64, Vec<(u32, ReadShardByOffset)>> = DashMap::with_capacity(2);
+    for shard in shards {
+        let segments = get_metadata_by_shard(
+            metadata_cache,
+            connection_manager,
+            &shard.namespace,
+            &shard.shard_name,
+        )
+        .await;
+        let (segment, leader) = match read_segment_by_offset(&segments, shard.offset) {
+            Some(meta) => (meta.segment_no, meta.leader),
+            None => {
+                let segment = get_active_segment(
+                    metadata_cache,
+                    connection_manager,
+                    &shard.namespace,
+                    &shard.shard_name,
+                )
+                .await;
+                let leader = get_segment_leader(
+                    metadata_cache,
+                    connection_manager,
+                    &shard.namespace,
+                    &shard.shard_name,
+                )
+                .await;
+                (segment, leader)
+            }
+        };
+        result
+            .entry(leader)
+            .or_default()
+            .push((segment, shard.to_owned()));
+    }
+    result
+}
+
 pub async fn fetch_offset_by_timestamp(
     connection_manager: &Arc<ConnectionManager>,
     metadata_cache: &Arc<MetadataCache>,
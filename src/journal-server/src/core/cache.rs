@@ -367,6 +367,7 @@ pub async fn load_metadata_cache(cache_manager: &Arc<CacheManager>, client_pool:
     // load node
     let request = NodeListRequest {
         cluster_name: conf.cluster_name.clone(),
+        consistent: false,
     };
     match node_list(client_pool, &conf.placement_center, request).await {
         Ok(list) => {
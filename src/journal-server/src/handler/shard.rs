@@ -15,6 +15,7 @@
 use std::sync::Arc;
 
 use common_base::config::journal_server::journal_server_conf;
+use common_base::tools::unique_id;
 use grpc_clients::placement::journal::call::update_segment_status;
 use grpc_clients::pool::ClientPool;
 use metadata_struct::journal::segment::{JournalSegment, SegmentStatus};
@@ -202,6 +203,7 @@ impl ShardHandler {
             cluster_name: conf.cluster_name.to_string(),
             namespace: namespace.to_string(),
             shard_name: shard_name.to_string(),
+            idempotency_key: Some(unique_id()),
         };
         grpc_clients::placement::journal::call::create_next_segment(
             &self.client_pool,
@@ -16,7 +16,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use common_base::config::journal_server::journal_server_conf;
-use common_base::tools::now_second;
+use common_base::tools::{now_second, unique_id};
 use dashmap::DashMap;
 use grpc_clients::placement::journal::call::create_next_segment;
 use grpc_clients::pool::ClientPool;
@@ -99,6 +99,7 @@ impl SegmentScrollManager {
                         cluster_name: conf.cluster_name.clone(),
                         namespace: segment_iden.namespace.clone(),
                         shard_name: segment_iden.shard_name.clone(),
+                        idempotency_key: Some(unique_id()),
                     };
 
                     match create_next_segment(&self.client_pool, &conf.placement_center, request)
@@ -0,0 +1,135 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Feeds arbitrary bytes to the packet handler of a logged in connection, any panic is a crash.
+// cd src/mqtt-broker && cargo +nightly fuzz run apply_raw fuzz/regressions/apply_raw
+
+#![no_main]
+
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+
+use bytes::BytesMut;
+use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+use common_base::tools::unique_id;
+use delay_message::DelayMessageManager;
+use grpc_clients::pool::ClientPool;
+use libfuzzer_sys::fuzz_target;
+use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
+use mqtt_broker::handler::cache::CacheManager;
+use mqtt_broker::handler::cluster_config::build_default_cluster_config;
+use mqtt_broker::handler::command::Command;
+use mqtt_broker::security::AuthDriver;
+use mqtt_broker::server::connection::{NetworkConnection, NetworkConnectionType};
+use mqtt_broker::server::connection_manager::ConnectionManager;
+use mqtt_broker::subscribe::subscribe_manager::SubscribeManager;
+use protocol::mqtt::codec::MqttCodec;
+use schema_register::schema::SchemaRegisterManager;
+use storage_adapter::memory::MemoryStorageAdapter;
+use tokio::runtime::Runtime;
+
+struct Broker {
+    runtime: Runtime,
+    cache_manager: Arc<CacheManager>,
+    connection_manager: Arc<ConnectionManager>,
+    command: Command<MemoryStorageAdapter>,
+}
+
+fn broker() -> &'static Broker {
+    static BROKER: OnceLock<Broker> = OnceLock::new();
+    BROKER.get_or_init(|| {
+        init_broker_mqtt_conf_by_path(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../config/mqtt-server.toml"
+        ));
+
+        let runtime = Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let command = Command::new(
+            cache_manager.clone(),
+            storage_adapter.clone(),
+            Arc::new(DelayMessageManager::new(unique_id(), 1, storage_adapter)),
+            Arc::new(SubscribeManager::new()),
+            client_pool.clone(),
+            connection_manager.clone(),
+            Arc::new(SchemaRegisterManager::new()),
+            Arc::new(AuthDriver::new(cache_manager.clone(), client_pool)),
+        );
+        Broker {
+            runtime,
+            cache_manager,
+            connection_manager,
+            command,
+        }
+    })
+}
+
+// The first byte picks the protocol version of the connection, the rest is the packet stream
+fuzz_target!(|data: &[u8]| {
+    let Some((version, data)) = data.split_first() else {
+        return;
+    };
+    let protocol_version = [3, 4, 5][*version as usize % 3];
+    let broker = broker();
+
+    let addr: SocketAddr = "127.0.0.1:1883".parse().unwrap();
+    let connection_id = broker
+        .connection_manager
+        .add_connection(NetworkConnection::new(
+            NetworkConnectionType::Tcp,
+            addr,
+            None,
+        ));
+    broker
+        .connection_manager
+        .set_connect_protocol(connection_id, protocol_version);
+    let config = ConnectionConfig {
+        connect_id: connection_id,
+        client_id: unique_id(),
+        receive_maximum: 100,
+        max_packet_size: 1024 * 1024,
+        topic_alias_max: 100,
+        request_problem_info: 0,
+        keep_alive: 60,
+        source_ip_addr: addr.ip().to_string(),
+    };
+    broker
+        .cache_manager
+        .add_connection(connection_id, MQTTConnection::new(config));
+    broker
+        .cache_manager
+        .login_success(connection_id, "fuzz".to_string());
+
+    let mut command = broker.command.clone();
+    let mut codec = MqttCodec::new(Some(protocol_version));
+    let mut stream = BytesMut::from(data);
+    broker.runtime.block_on(async {
+        let _ = command
+            .apply_raw(
+                broker.connection_manager.clone(),
+                connection_id,
+                addr,
+                &mut codec,
+                &mut stream,
+            )
+            .await;
+        broker.cache_manager.remove_connection(connection_id);
+        broker.connection_manager.close_connect(connection_id).await;
+    });
+});
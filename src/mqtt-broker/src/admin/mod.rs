@@ -64,11 +64,12 @@ pub async fn create_user_by_req(
     request: Request<CreateUserRequest>,
 ) -> Result<Response<CreateUserReply>, Status> {
     let req = request.into_inner();
-    let mqtt_user = MqttUser {
-        username: req.username,
-        password: req.password,
-        is_superuser: req.is_superuser,
-    };
+    let mqtt_user = tokio::task::spawn_blocking(move || {
+        MqttUser::new(req.username, &req.password, req.is_superuser)
+    })
+    .await
+    .map_err(|e| Status::cancelled(e.to_string()))?
+    .map_err(|e| Status::cancelled(e.to_string()))?;
 
     let auth_driver = AuthDriver::new(cache_manager.clone(), client_pool.clone());
     match auth_driver.save_user(mqtt_user).await {
@@ -0,0 +1,183 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use grpc_clients::pool::ClientPool;
+use log::info;
+use metadata_struct::mqtt::connection::MQTTConnection;
+use metadata_struct::mqtt::message::MqttMessage;
+use protocol::mqtt::common::PublishProperties;
+use serde::{Deserialize, Serialize};
+use storage_adapter::storage::StorageAdapter;
+
+use super::cache::CacheManager;
+use super::error::MqttBrokerError;
+use crate::observability::system_topic::write_topic_data;
+use crate::security::acl::auth::is_super_user;
+use crate::security::AuthDriver;
+use crate::subscribe::subscribe_manager::SubscribeManager;
+
+pub const ADMIN_TOPIC_PREFIX: &str = "$robustmq/admin/";
+
+pub const ADMIN_ACTION_FLUSH_CACHE: &str = "flush_cache";
+pub const ADMIN_ACTION_RELOAD_ACL: &str = "reload_acl";
+pub const ADMIN_ACTION_GET_STATS: &str = "get_stats";
+// The payload is the server reference the clients are redirected to, the least loaded
+// broker of the cluster when it is empty
+pub const ADMIN_ACTION_DRAIN_START: &str = "drain_start";
+pub const ADMIN_ACTION_DRAIN_STOP: &str = "drain_stop";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AdminStats {
+    pub connection_num: usize,
+    pub session_num: usize,
+    pub topic_num: usize,
+    pub subscribe_num: usize,
+}
+
+pub fn is_admin_topic(topic_name: &str) -> bool {
+    topic_name.starts_with(ADMIN_TOPIC_PREFIX)
+}
+
+/// Processes the publishes to $robustmq/admin/<action>, which lets admin tools
+/// trigger actions on the broker over MQTT. Only super users may publish to them.
+pub struct SystemTopicHandler<S> {
+    cache_manager: Arc<CacheManager>,
+    subscribe_manager: Arc<SubscribeManager>,
+    auth_driver: Arc<AuthDriver>,
+    message_storage_adapter: Arc<S>,
+    client_pool: Arc<ClientPool>,
+}
+
+impl<S> SystemTopicHandler<S>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    pub fn new(
+        cache_manager: Arc<CacheManager>,
+        subscribe_manager: Arc<SubscribeManager>,
+        auth_driver: Arc<AuthDriver>,
+        message_storage_adapter: Arc<S>,
+        client_pool: Arc<ClientPool>,
+    ) -> Self {
+        SystemTopicHandler {
+            cache_manager,
+            subscribe_manager,
+            auth_driver,
+            message_storage_adapter,
+            client_pool,
+        }
+    }
+
+    pub fn is_allow(&self, connection: &MQTTConnection) -> bool {
+        is_super_user(&self.cache_manager, &connection.login_user)
+    }
+
+    pub async fn handle(
+        &self,
+        connection: &MQTTConnection,
+        topic_name: &str,
+        payload: &Bytes,
+        publish_properties: &Option<PublishProperties>,
+    ) -> Result<(), MqttBrokerError> {
+        let action = topic_name.trim_start_matches(ADMIN_TOPIC_PREFIX);
+        info!(
+            "Admin action [{}] is triggered by client_id [{}], user [{}]",
+            action, connection.client_id, connection.login_user
+        );
+
+        match action {
+            ADMIN_ACTION_FLUSH_CACHE => {
+                self.cache_manager.topic_info.clear();
+                Ok(())
+            }
+            ADMIN_ACTION_RELOAD_ACL => self.auth_driver.update_acl_cache().await,
+            ADMIN_ACTION_GET_STATS => self.publish_stats(publish_properties).await,
+            ADMIN_ACTION_DRAIN_START => self.start_drain(payload),
+            ADMIN_ACTION_DRAIN_STOP => {
+                self.cache_manager.connection_drain.stop();
+                Ok(())
+            }
+            _ => Err(MqttBrokerError::AdminActionNotSupported(action.to_string())),
+        }
+    }
+
+    pub fn stats(&self) -> AdminStats {
+        AdminStats {
+            connection_num: self.cache_manager.count_active_connections(),
+            session_num: self.cache_manager.session_info.len(),
+            topic_num: self.cache_manager.topic_info.len(),
+            subscribe_num: self.subscribe_manager.subscribe_list.len(),
+        }
+    }
+
+    fn start_drain(&self, payload: &Bytes) -> Result<(), MqttBrokerError> {
+        let server_reference = String::from_utf8(payload.to_vec())
+            .map(|reference| reference.trim().to_owned())
+            .unwrap_or_default();
+        // Without a server reference the clients go to the least loaded broker of the cluster
+        let server_reference = if server_reference.is_empty() {
+            self.cache_manager
+                .connection_drain
+                .least_loaded_server()
+                .ok_or(MqttBrokerError::AdminDrainServerReferenceIsEmpty)?
+        } else {
+            server_reference
+        };
+        self.cache_manager.connection_drain.start(server_reference);
+        Ok(())
+    }
+
+    async fn publish_stats(
+        &self,
+        publish_properties: &Option<PublishProperties>,
+    ) -> Result<(), MqttBrokerError> {
+        let Some(response_topic) = publish_properties
+            .as_ref()
+            .and_then(|properties| properties.response_topic.clone())
+        else {
+            return Err(MqttBrokerError::AdminResponseTopicIsEmpty);
+        };
+
+        let content = serde_json::to_string(&self.stats())?;
+        if let Some(record) =
+            MqttMessage::build_system_topic_message(response_topic.clone(), content)
+        {
+            write_topic_data(
+                &self.message_storage_adapter,
+                &self.cache_manager,
+                &self.client_pool,
+                response_topic,
+                record,
+            )
+            .await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_admin_topic;
+
+    #[test]
+    fn is_admin_topic_test() {
+        assert!(is_admin_topic("$robustmq/admin/flush_cache"));
+        assert!(!is_admin_topic("$robustmq/admin"));
+        assert!(!is_admin_topic("/robustmq/admin/flush_cache"));
+        assert!(!is_admin_topic("$SYS/brokers"));
+    }
+}
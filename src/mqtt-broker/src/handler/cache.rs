@@ -13,26 +13,39 @@
 // limitations under the License.
 
 use common_base::tools::now_second;
-use dashmap::DashMap;
+use dashmap::{DashMap, SharedValue};
 use grpc_clients::pool::ClientPool;
-use log::warn;
+use log::{info, warn};
 use metadata_struct::acl::mqtt_acl::MqttAcl;
 use metadata_struct::acl::mqtt_blacklist::MqttAclBlackList;
 use metadata_struct::mqtt::cluster::MqttClusterDynamicConfig;
 use metadata_struct::mqtt::connection::MQTTConnection;
+use metadata_struct::mqtt::message::MqttMessage;
 use metadata_struct::mqtt::session::MqttSession;
 use metadata_struct::mqtt::topic::MqttTopic;
 use metadata_struct::mqtt::topic_rewrite_rule::MqttTopicRewriteRule;
 use metadata_struct::mqtt::user::MqttUser;
+use metadata_struct::placement::node::BrokerNode;
 use protocol::mqtt::common::{MqttProtocol, PublishProperties};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::hash::BuildHasher;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast::Sender;
 use tokio::time::sleep;
 
+use crate::handler::drain::ConnectionDrain;
+use crate::handler::keep_alive::keep_live_time;
+use crate::handler::lastwill::DelayedLastWill;
+use crate::handler::pkid::PkidAllocator;
+use crate::observability::metrics::session::{
+    record_client_connection_dec, record_client_connection_inc,
+};
 use crate::security::acl::metadata::AclMetadata;
+use crate::security::encryption::topic_tenant;
+use crate::subscribe::cluster_route::MessageForward;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum MetadataCacheAction {
@@ -84,6 +97,7 @@ pub enum QosAckPackageType {
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ClientPkidData {
     pub client_id: String,
+    pub pkid: u16,
     pub create_time: u64,
 }
 
@@ -97,14 +111,17 @@ pub struct CacheManager {
     // (cluster_name, Cluster)
     pub cluster_info: DashMap<String, MqttClusterDynamicConfig>,
 
+    // (cluster_name, client_id_allowed_regex of the cluster config compiled when it is set)
+    pub client_id_allowed_regex: DashMap<String, Regex>,
+
     // (username, User)
     pub user_info: DashMap<String, MqttUser>,
 
     // (client_id, Session)
     pub session_info: DashMap<String, MqttSession>,
 
-    // (client_id, vec<pkid>)
-    pub publish_pkid_info: DashMap<String, Vec<u16>>,
+    // (client_id, PkidAllocator)
+    pub publish_pkid_info: DashMap<String, PkidAllocator>,
 
     // (connect_id, Connection)
     pub connection_info: DashMap<u64, MQTTConnection>,
@@ -115,10 +132,11 @@ pub struct CacheManager {
     // (topic_id, topic_name)
     pub topic_id_name: DashMap<String, String>,
 
-    // (client_id, HeartbeatShard)
-    pub heartbeat_data: DashMap<String, ConnectionLiveTime>,
+    // (connect_id, ConnectionLiveTime)
+    pub heartbeat_data: DashMap<u64, ConnectionLiveTime>,
 
-    //(client_id_pkid, AckPacketInfo)
+    // (client_id_pkid, AckPacketInfo), the delivery waiting for the ack holds a receiver of
+    // the channel until it ends
     pub qos_ack_packet: DashMap<String, QosAckPacketInfo>,
 
     // (client_id_pkid, QosPkidData)
@@ -129,6 +147,30 @@ pub struct CacheManager {
 
     // All topic rewrite rule
     pub topic_rewrite_rule: DashMap<String, MqttTopicRewriteRule>,
+
+    // (topic_name, latest retained messages, oldest first) of the topics retaining more than one message
+    pub retain_message_history: DashMap<String, VecDeque<MqttMessage>>,
+
+    // (tenant, AES-256-GCM key) of the tenants whose messages are stored encrypted
+    pub tenant_encryption_key: DashMap<String, Vec<u8>>,
+
+    // drain mode of the node
+    pub connection_drain: ConnectionDrain,
+
+    // (broker_id, BrokerNode) of the brokers of the cluster
+    pub broker_node_info: DashMap<u64, BrokerNode>,
+
+    // messages forwarded between the brokers of the cluster
+    pub message_forward: MessageForward,
+
+    // (topic_name, second of the last publish) of the topics published since the broker started
+    pub topic_last_publish: DashMap<String, u64>,
+
+    // (topic_name, second of the reclaim) of the idle topics whose message shard was deleted
+    pub reclaimed_topic_shard: DashMap<String, u64>,
+
+    // wills of the lost connections waiting for their delay interval
+    pub delayed_last_will: DelayedLastWill,
 }
 
 impl CacheManager {
@@ -137,6 +179,7 @@ impl CacheManager {
             client_pool,
             cluster_name,
             cluster_info: DashMap::with_capacity(1),
+            client_id_allowed_regex: DashMap::with_capacity(1),
             user_info: DashMap::with_capacity(8),
             session_info: DashMap::with_capacity(8),
             topic_info: DashMap::with_capacity(8),
@@ -148,6 +191,14 @@ impl CacheManager {
             client_pkid_data: DashMap::with_capacity(8),
             acl_metadata: AclMetadata::new(),
             topic_rewrite_rule: DashMap::with_capacity(8),
+            retain_message_history: DashMap::with_capacity(8),
+            tenant_encryption_key: DashMap::with_capacity(2),
+            connection_drain: ConnectionDrain::new(),
+            broker_node_info: DashMap::with_capacity(2),
+            message_forward: MessageForward::new(),
+            topic_last_publish: DashMap::with_capacity(8),
+            reclaimed_topic_shard: DashMap::with_capacity(8),
+            delayed_last_will: DelayedLastWill::new(),
         }
     }
 
@@ -175,7 +226,6 @@ impl CacheManager {
     pub fn remove_session(&self, client_id: &str) {
         self.session_info.remove(client_id);
         self.publish_pkid_info.remove(client_id);
-        self.heartbeat_data.remove(client_id);
 
         for (key, _) in self.qos_ack_packet.clone() {
             if key.starts_with(client_id) {
@@ -183,11 +233,7 @@ impl CacheManager {
             }
         }
 
-        for (key, _) in self.client_pkid_data.clone() {
-            if key.starts_with(client_id) {
-                self.qos_ack_packet.remove(&key);
-            }
-        }
+        self.clear_client_pkids(client_id);
     }
 
     // user
@@ -208,12 +254,17 @@ impl CacheManager {
     pub fn add_connection(&self, connect_id: u64, conn: MQTTConnection) {
         if let Some(mut session) = self.session_info.get_mut(&conn.client_id) {
             session.connection_id = Some(connect_id);
-            self.connection_info.insert(connect_id, conn);
+            if self.connection_info.insert(connect_id, conn).is_none() {
+                record_client_connection_inc();
+            }
         }
     }
 
     pub fn remove_connection(&self, connect_id: u64) {
-        self.connection_info.remove(&connect_id);
+        if self.connection_info.remove(&connect_id).is_some() {
+            record_client_connection_dec();
+        }
+        self.heartbeat_data.remove(&connect_id);
     }
 
     pub fn get_connect_id(&self, client_id: &str) -> Option<u64> {
@@ -232,6 +283,11 @@ impl CacheManager {
         None
     }
 
+    // The connections of the node, reported to the placement center as the load of the node
+    pub fn count_active_connections(&self) -> usize {
+        self.connection_info.len()
+    }
+
     // topic
     pub fn add_topic(&self, topic_name: &str, topic: &MqttTopic) {
         self.topic_info.insert(topic_name.to_owned(), topic.clone());
@@ -242,6 +298,21 @@ impl CacheManager {
     pub fn delete_topic(&self, topic_name: &String, topic: &MqttTopic) {
         self.topic_info.remove(topic_name);
         self.topic_id_name.remove(&topic.topic_id);
+        self.retain_message_history.remove(topic_name);
+        self.topic_last_publish.remove(topic_name);
+        self.reclaimed_topic_shard.remove(topic_name);
+    }
+
+    pub fn report_topic_publish(&self, topic_name: &str, now: u64) {
+        self.topic_last_publish.insert(topic_name.to_owned(), now);
+    }
+
+    // The last publish of the topic, or its creation when it was not published since the start
+    pub fn topic_last_active_time(&self, topic: &MqttTopic) -> u64 {
+        if let Some(time) = self.topic_last_publish.get(&topic.topic_name) {
+            return *time;
+        }
+        topic.create_time
     }
 
     pub fn topic_exists(&self, topic: &str) -> bool {
@@ -255,6 +326,20 @@ impl CacheManager {
         None
     }
 
+    // broker node
+    pub fn set_broker_nodes(&self, nodes: Vec<BrokerNode>) {
+        let node_ids: HashSet<u64> = nodes.iter().map(|node| node.node_id).collect();
+        self.broker_node_info
+            .retain(|node_id, _| node_ids.contains(node_id));
+        for node in nodes {
+            self.broker_node_info.insert(node.node_id, node);
+        }
+    }
+
+    pub fn get_broker_node(&self, node_id: u64) -> Option<BrokerNode> {
+        self.broker_node_info.get(&node_id).map(|node| node.clone())
+    }
+
     pub fn get_topic_by_name(&self, topic_name: &str) -> Option<MqttTopic> {
         if let Some(topic) = self.topic_info.get(topic_name) {
             return Some(topic.clone());
@@ -268,6 +353,49 @@ impl CacheManager {
         }
     }
 
+    pub fn add_retain_message_history(
+        &self,
+        topic_name: &str,
+        retain_message: MqttMessage,
+        retain_num: usize,
+    ) {
+        let mut history = self
+            .retain_message_history
+            .entry(topic_name.to_owned())
+            .or_default();
+        history.push_back(retain_message);
+        while history.len() > retain_num {
+            history.pop_front();
+        }
+    }
+
+    pub fn get_retain_message_history(&self, topic_name: &str) -> Vec<MqttMessage> {
+        if let Some(history) = self.retain_message_history.get(topic_name) {
+            return history.iter().cloned().collect();
+        }
+        Vec::new()
+    }
+
+    pub fn clear_retain_message_history(&self, topic_name: &str) {
+        self.retain_message_history.remove(topic_name);
+    }
+
+    // tenant encryption key
+    pub fn add_tenant_encryption_key(&self, tenant: &str, key: Vec<u8>) {
+        self.tenant_encryption_key.insert(tenant.to_owned(), key);
+    }
+
+    pub fn remove_tenant_encryption_key(&self, tenant: &str) {
+        self.tenant_encryption_key.remove(tenant);
+    }
+
+    pub fn get_topic_encryption_key(&self, topic_name: &str) -> Option<Vec<u8>> {
+        let tenant = topic_tenant(topic_name)?;
+        self.tenant_encryption_key
+            .get(tenant)
+            .map(|key| key.value().clone())
+    }
+
     // topic rewrite rule
     pub fn add_topic_rewrite_rule(&self, topic_rewrite_rule: MqttTopicRewriteRule) {
         let key = self.topic_rewrite_rule_key(
@@ -324,45 +452,49 @@ impl CacheManager {
         if let Some(properties) = publish_properties {
             if let Some(alias) = properties.topic_alias {
                 if let Some(conn) = self.connection_info.get_mut(&connect_id) {
-                    conn.topic_alias.insert(alias, topic_name.to_owned());
+                    let topic_alias_max = self.get_cluster_info().protocol.topic_alias_max;
+                    if conn.topic_alias.contains_key(&alias)
+                        || conn.topic_alias.len() < topic_alias_max as usize
+                    {
+                        conn.topic_alias.insert(alias, topic_name.to_owned());
+                    }
                 }
             }
         }
     }
 
     // pkid
+    // When all the pkids of the client are in flight, the delivery waits until an
+    // acknowledged one is released, instead of reusing a pkid still in use.
     pub async fn get_pkid(&self, client_id: &str) -> u16 {
-        let pkid = self.get_available_pkid(client_id).await;
-        if let Some(mut pkid_list) = self.publish_pkid_info.get_mut(client_id) {
-            pkid_list.push(pkid);
-        } else {
-            self.publish_pkid_info
-                .insert(client_id.to_owned(), vec![pkid]);
-        }
-        pkid
-    }
-
-    async fn get_available_pkid(&self, client_id: &str) -> u16 {
+        let mut paused = false;
         loop {
-            if let Some(pkid_list) = self.publish_pkid_info.get(client_id) {
-                for i in 1..65535 {
-                    if pkid_list.contains(&i) {
-                        continue;
-                    }
-                    return i;
+            let pkid = self
+                .publish_pkid_info
+                .entry(client_id.to_owned())
+                .or_default()
+                .allocate();
+            if let Some(pkid) = pkid {
+                if paused {
+                    info!("Pkid of client {} is released, delivery resumes", client_id);
                 }
-            } else {
-                self.publish_pkid_info.insert(client_id.to_owned(), vec![1]);
-                return 1;
+                return pkid;
+            }
+
+            if !paused {
+                warn!(
+                    "No pkid available for client {}, delivery is paused until one is released",
+                    client_id
+                );
+                paused = true;
             }
             sleep(Duration::from_millis(10)).await;
-            warn!("{}", "No pkid available for client, wait 10ms.");
         }
     }
 
     pub fn remove_pkid_info(&self, client_id: &str, pkid: u16) {
-        if let Some(mut pkid_list) = self.publish_pkid_info.get_mut(client_id) {
-            pkid_list.retain(|x| *x == pkid);
+        if let Some(mut allocator) = self.publish_pkid_info.get_mut(client_id) {
+            allocator.release(pkid);
         }
     }
 
@@ -373,6 +505,7 @@ impl CacheManager {
             key,
             ClientPkidData {
                 client_id: client_id.to_owned(),
+                pkid,
                 create_time: now_second(),
             },
         );
@@ -391,13 +524,101 @@ impl CacheManager {
         None
     }
 
+    // The QoS 2 pkids of the client whose PUBREL was not received yet
+    pub fn list_client_pkids(&self, client_id: &str) -> Vec<u16> {
+        let mut pkids: Vec<u16> = self
+            .client_pkid_data
+            .iter()
+            .filter(|raw| raw.client_id == client_id)
+            .map(|raw| raw.pkid)
+            .collect();
+        pkids.sort_unstable();
+        pkids
+    }
+
+    // Removes the QoS 2 pkids whose PUBREL was not received within expire_secs, and returns them
+    pub fn remove_expired_client_pkids(&self, now: u64, expire_secs: u64) -> Vec<ClientPkidData> {
+        let mut expired = Vec::new();
+        self.client_pkid_data.retain(|_, data| {
+            if data.create_time.saturating_add(expire_secs) <= now {
+                expired.push(data.clone());
+                return false;
+            }
+            true
+        });
+        expired
+    }
+
+    pub fn clear_client_pkids(&self, client_id: &str) {
+        self.client_pkid_data
+            .retain(|_, data| data.client_id != client_id);
+    }
+
     // heartbeat
-    pub fn report_heartbeat(&self, client_id: String, live_time: ConnectionLiveTime) {
-        self.heartbeat_data.insert(client_id, live_time);
+    pub fn report_heartbeat(&self, connect_id: u64, live_time: ConnectionLiveTime) {
+        self.heartbeat_data.insert(connect_id, live_time);
+    }
+
+    // Groups the updates by the shard of their connection and takes the lock of each shard once.
+    // The shard grows once for the connections it does not hold yet, the last update of a
+    // connection in the batch wins.
+    pub fn report_heartbeats_batch(&self, updates: Vec<(u64, ConnectionLiveTime)>) {
+        let hasher = self.heartbeat_data.hasher();
+        let shards = self.heartbeat_data.shards();
+
+        let mut shard_updates: Vec<Vec<(u64, u64, ConnectionLiveTime)>> =
+            vec![Vec::new(); shards.len()];
+        for (connect_id, live_time) in updates {
+            let hash = hasher.hash_one(connect_id);
+            let index = self.heartbeat_data.determine_shard(hash as usize);
+            shard_updates[index].push((hash, connect_id, live_time));
+        }
+
+        for (shard, mut updates) in shards.iter().zip(shard_updates) {
+            if updates.is_empty() {
+                continue;
+            }
+            let mut shard = shard.write();
+            updates.retain(|(hash, connect_id, live_time)| {
+                match shard.get_mut(*hash, |(key, _)| key == connect_id) {
+                    Some((_, value)) => {
+                        *value.get_mut() = live_time.clone();
+                        false
+                    }
+                    None => true,
+                }
+            });
+
+            shard.reserve(updates.len(), |(key, _)| hasher.hash_one(key));
+            for (hash, connect_id, live_time) in updates {
+                // a connection can be missing once and still be in the batch several times
+                match shard.get_mut(hash, |(key, _)| *key == connect_id) {
+                    Some((_, value)) => *value.get_mut() = live_time,
+                    None => {
+                        shard.insert(
+                            hash,
+                            (connect_id, SharedValue::new(live_time)),
+                            |(key, _)| hasher.hash_one(key),
+                        );
+                    }
+                }
+            }
+        }
     }
 
-    pub fn remove_heartbeat(&self, client_id: &str) {
-        self.heartbeat_data.remove(client_id);
+    // The connections whose last heartbeat is older than twice their keep alive
+    pub fn get_expired_connections(&self, now: u64) -> Vec<u64> {
+        self.heartbeat_data
+            .iter()
+            .filter(|entry| {
+                now.saturating_sub(entry.heartbeat) >= keep_live_time(entry.keep_live) as u64
+            })
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
+    pub fn remove_heartbeat(&self, connect_id: u64) {
+        self.heartbeat_data.remove(&connect_id);
     }
 
     // acl
@@ -446,6 +667,15 @@ impl CacheManager {
         None
     }
 
+    // The receivers of the ack channel, none once the delivery of the packet no longer waits
+    pub fn ack_packet_receiver_count(&self, client_id: &str, pkid: u16) -> usize {
+        let key = self.key(client_id, pkid);
+        if let Some(data) = self.qos_ack_packet.get(&key) {
+            return data.sx.receiver_count();
+        }
+        0
+    }
+
     // key
     fn key(&self, client_id: &str, pkid: u16) -> String {
         format!("{}_{}", client_id, pkid)
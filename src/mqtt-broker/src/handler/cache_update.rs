@@ -13,6 +13,8 @@
 // limitations under the License.
 
 use crate::bridge::manager::ConnectorManager;
+use crate::security::encryption::decode_encryption_key;
+use crate::server::connection_manager::ConnectionManager;
 use crate::storage::connector::ConnectorStorage;
 use crate::storage::topic::TopicStorage;
 use crate::{security::AuthDriver, subscribe::subscribe_manager::SubscribeManager};
@@ -34,6 +36,7 @@ use schema_register::schema::SchemaRegisterManager;
 use std::sync::Arc;
 
 use super::cluster_config::build_cluster_config;
+use super::topic::process_topic_deleted;
 use super::{cache::CacheManager, sub_exclusive::remove_exclusive_subscribe_by_path};
 
 pub async fn load_metadata_cache(
@@ -55,6 +58,19 @@ pub async fn load_metadata_cache(
     };
     cache_manager.set_cluster_info(cluster);
 
+    // load the tenant encryption keys
+    for tenant_key in broker_mqtt_conf().tenant_encryption_keys.iter() {
+        match decode_encryption_key(tenant_key) {
+            Ok(key) => cache_manager.add_tenant_encryption_key(&tenant_key.tenant, key),
+            Err(e) => {
+                panic!(
+                    "Failed to load the encryption key of tenant {} with error message:{}",
+                    tenant_key.tenant, e
+                );
+            }
+        }
+    }
+
     // load all topic
     let topic_storage = TopicStorage::new(client_pool.clone());
     let topic_list = match topic_storage.all().await {
@@ -157,6 +173,7 @@ pub async fn load_metadata_cache(
 
 pub async fn update_cache_metadata(
     cache_manager: &Arc<CacheManager>,
+    connection_manager: &Arc<ConnectionManager>,
     connector_manager: &Arc<ConnectorManager>,
     subscribe_manager: &Arc<SubscribeManager>,
     schema_manager: &Arc<SchemaRegisterManager>,
@@ -248,7 +265,13 @@ pub async fn update_cache_metadata(
             MqttBrokerUpdateCacheActionType::Delete => {
                 match serde_json::from_str::<MqttTopic>(&request.data) {
                     Ok(topic) => {
-                        cache_manager.delete_topic(&topic.topic_name, &topic);
+                        process_topic_deleted(
+                            cache_manager,
+                            connection_manager,
+                            subscribe_manager,
+                            &topic,
+                        )
+                        .await;
                     }
                     Err(e) => {
                         error!("{}", e);
@@ -19,6 +19,7 @@ use crate::handler::error::MqttBrokerError;
 use crate::storage::cluster::ClusterStorage;
 use common_base::config::broker_mqtt::{broker_mqtt_conf, ConfigAvailableFlag};
 use grpc_clients::pool::ClientPool;
+use log::error;
 use metadata_struct::mqtt::cluster::{
     AvailableFlag, MqttClusterDynamicConfig, MqttClusterDynamicConfigFeature,
     MqttClusterDynamicConfigNetwork, MqttClusterDynamicConfigProtocol,
@@ -29,6 +30,7 @@ use metadata_struct::mqtt::cluster::{
     DEFAULT_DYNAMIC_CONFIG_SLOW_SUB,
 };
 use protocol::mqtt::common::{qos, QoS};
+use regex::Regex;
 
 /// This section primarily implements cache management for cluster-related configuration operations.
 /// Through this implementation, we can retrieve configuration information within the cluster
@@ -74,9 +76,29 @@ impl CacheManager {
     }
 
     pub fn set_cluster_info(&self, cluster: MqttClusterDynamicConfig) {
+        // An invalid pattern refuses every client id that would be checked against it
+        match build_client_id_regex(&cluster) {
+            Ok(Some(re)) => {
+                self.client_id_allowed_regex
+                    .insert(self.cluster_name.clone(), re);
+            }
+            Ok(None) => {
+                self.client_id_allowed_regex.remove(&self.cluster_name);
+            }
+            Err(e) => {
+                error!("{}, the client ids are refused until it is fixed", e);
+                self.client_id_allowed_regex.remove(&self.cluster_name);
+            }
+        }
         self.cluster_info.insert(self.cluster_name.clone(), cluster);
     }
 
+    pub fn get_client_id_allowed_regex(&self) -> Option<Regex> {
+        self.client_id_allowed_regex
+            .get(&self.cluster_name)
+            .map(|re| re.clone())
+    }
+
     pub fn get_cluster_info(&self) -> MqttClusterDynamicConfig {
         self.cluster_info.get(&self.cluster_name).unwrap().clone()
     }
@@ -99,7 +121,7 @@ pub fn build_default_cluster_config() -> MqttClusterDynamicConfig {
     MqttClusterDynamicConfig {
         protocol: MqttClusterDynamicConfigProtocol {
             session_expiry_interval: 1800,
-            topic_alias_max: 65535,
+            topic_alias_max: 32,
             max_qos: QoS::ExactlyOnce,
             max_packet_size: 1024 * 1024 * 10,
             max_server_keep_alive: 3600,
@@ -107,6 +129,25 @@ pub fn build_default_cluster_config() -> MqttClusterDynamicConfig {
             receive_max: 65535,
             client_pkid_persistent: false,
             max_message_expiry_interval: 3600,
+            client_id_max_len: 65535,
+            client_id_allowed_regex: "".to_string(),
+            flow_control_threshold: 0,
+            max_shared_subscription_groups: 0,
+            max_shared_subscription_group_members: 0,
+            delivery_concurrency: 0,
+            retain_max_per_subscribe: 0,
+            retain_newest_first: false,
+            fan_out_read_once: false,
+            retain_skip_unchanged: false,
+            max_connect_properties_size: 0,
+            max_connect_user_properties: 0,
+            slow_start_duration_secs: 0,
+            slow_start_initial_rate: 0,
+            max_reader_inflight_records: 0,
+            strict_record_offset_order: false,
+            max_client_delivery_rate: 0,
+            qos2_inflight_expire_secs: 0,
+            idle_topic_shard_expire_secs: 0,
         },
         feature: MqttClusterDynamicConfigFeature {
             retain_available: AvailableFlag::Enable,
@@ -118,6 +159,10 @@ pub fn build_default_cluster_config() -> MqttClusterDynamicConfig {
         security: MqttClusterDynamicConfigSecurity {
             secret_free_login: false,
             is_self_protection_status: false,
+            allow_anonymous: false,
+            delivery_acl_check_topics: Vec::new(),
+            delivery_acl_deny_disconnect: false,
+            upgrade_plaintext_password: false,
         },
         network: MqttClusterDynamicConfigNetwork {
             tcp_max_connection_num: 1000,
@@ -126,6 +171,8 @@ pub fn build_default_cluster_config() -> MqttClusterDynamicConfig {
             websockets_max_connection_num: 1000,
             response_max_try_mut_times: 128,
             response_try_mut_sleep_time_ms: 100,
+            accept_ip_allow_list: Vec::new(),
+            accept_ip_deny_list: Vec::new(),
         },
         slow: MqttClusterDynamicSlowSub {
             enable: false,
@@ -146,7 +193,7 @@ pub fn build_default_cluster_config() -> MqttClusterDynamicConfig {
 pub async fn build_cluster_config(
     client_pool: &Arc<ClientPool>,
 ) -> Result<MqttClusterDynamicConfig, MqttBrokerError> {
-    Ok(MqttClusterDynamicConfig {
+    let cluster = MqttClusterDynamicConfig {
         protocol: build_protocol(client_pool).await?,
         feature: build_feature(client_pool).await?,
         security: build_security(client_pool).await?,
@@ -154,7 +201,22 @@ pub async fn build_cluster_config(
         slow: build_slow_sub(client_pool).await?,
         flapping_detect: build_flapping_detect(client_pool).await?,
         offline_message: build_offline_message(client_pool).await?,
-    })
+    };
+    // The broker does not start with a client id pattern it cannot apply
+    build_client_id_regex(&cluster)?;
+    Ok(cluster)
+}
+
+pub fn build_client_id_regex(
+    cluster: &MqttClusterDynamicConfig,
+) -> Result<Option<Regex>, MqttBrokerError> {
+    let pattern = &cluster.protocol.client_id_allowed_regex;
+    if pattern.is_empty() {
+        return Ok(None);
+    }
+    Regex::new(pattern)
+        .map(Some)
+        .map_err(|e| MqttBrokerError::InvalidClientIdRegex(pattern.clone(), e.to_string()))
 }
 
 async fn build_protocol(
@@ -185,6 +247,50 @@ async fn build_protocol(
         max_message_expiry_interval: conf
             .cluster_dynamic_config_protocol
             .max_message_expiry_interval,
+        client_id_max_len: conf.cluster_dynamic_config_protocol.client_id_max_len,
+        client_id_allowed_regex: conf
+            .cluster_dynamic_config_protocol
+            .client_id_allowed_regex
+            .clone(),
+        flow_control_threshold: conf.cluster_dynamic_config_protocol.flow_control_threshold,
+        max_shared_subscription_groups: conf
+            .cluster_dynamic_config_protocol
+            .max_shared_subscription_groups,
+        max_shared_subscription_group_members: conf
+            .cluster_dynamic_config_protocol
+            .max_shared_subscription_group_members,
+        delivery_concurrency: conf.cluster_dynamic_config_protocol.delivery_concurrency,
+        retain_max_per_subscribe: conf
+            .cluster_dynamic_config_protocol
+            .retain_max_per_subscribe,
+        retain_newest_first: conf.cluster_dynamic_config_protocol.retain_newest_first,
+        fan_out_read_once: conf.cluster_dynamic_config_protocol.fan_out_read_once,
+        retain_skip_unchanged: conf.cluster_dynamic_config_protocol.retain_skip_unchanged,
+        max_connect_properties_size: conf
+            .cluster_dynamic_config_protocol
+            .max_connect_properties_size,
+        max_connect_user_properties: conf
+            .cluster_dynamic_config_protocol
+            .max_connect_user_properties,
+        slow_start_duration_secs: conf
+            .cluster_dynamic_config_protocol
+            .slow_start_duration_secs,
+        slow_start_initial_rate: conf.cluster_dynamic_config_protocol.slow_start_initial_rate,
+        max_reader_inflight_records: conf
+            .cluster_dynamic_config_protocol
+            .max_reader_inflight_records,
+        strict_record_offset_order: conf
+            .cluster_dynamic_config_protocol
+            .strict_record_offset_order,
+        max_client_delivery_rate: conf
+            .cluster_dynamic_config_protocol
+            .max_client_delivery_rate,
+        qos2_inflight_expire_secs: conf
+            .cluster_dynamic_config_protocol
+            .qos2_inflight_expire_secs,
+        idle_topic_shard_expire_secs: conf
+            .cluster_dynamic_config_protocol
+            .idle_topic_shard_expire_secs,
     })
 }
 
@@ -250,6 +356,17 @@ async fn build_security(
         is_self_protection_status: conf
             .cluster_dynamic_config_security
             .is_self_protection_status,
+        allow_anonymous: conf.cluster_dynamic_config_security.allow_anonymous,
+        delivery_acl_check_topics: conf
+            .cluster_dynamic_config_security
+            .delivery_acl_check_topics
+            .clone(),
+        delivery_acl_deny_disconnect: conf
+            .cluster_dynamic_config_security
+            .delivery_acl_deny_disconnect,
+        upgrade_plaintext_password: conf
+            .cluster_dynamic_config_security
+            .upgrade_plaintext_password,
     })
 }
 
@@ -280,6 +397,14 @@ async fn build_network(
         response_try_mut_sleep_time_ms: conf
             .cluster_dynamic_config_network
             .response_try_mut_sleep_time_ms,
+        accept_ip_allow_list: conf
+            .cluster_dynamic_config_network
+            .accept_ip_allow_list
+            .clone(),
+        accept_ip_deny_list: conf
+            .cluster_dynamic_config_network
+            .accept_ip_deny_list
+            .clone(),
     })
 }
 
@@ -15,9 +15,9 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use super::flow_control::is_qos_message;
 use super::mqtt::MqttService;
 use crate::handler::cache::CacheManager;
+use crate::handler::error::MqttBrokerError;
 use crate::handler::response::{
     response_packet_mqtt_connect_fail, response_packet_mqtt_distinct_by_reason,
 };
@@ -25,14 +25,17 @@ use crate::security::AuthDriver;
 use crate::server::connection::NetworkConnection;
 use crate::server::connection_manager::ConnectionManager;
 use crate::subscribe::subscribe_manager::SubscribeManager;
+use bytes::BytesMut;
 use common_base::telemetry::trace::CustomContext;
 use delay_message::DelayMessageManager;
 use grpc_clients::pool::ClientPool;
-use log::info;
+use log::{debug, info};
 use opentelemetry::global;
 use opentelemetry::trace::{Span, SpanKind, Tracer};
+use protocol::mqtt::codec::MqttCodec;
 use protocol::mqtt::common::{
-    is_mqtt3, is_mqtt4, is_mqtt5, ConnectReturnCode, DisconnectReasonCode, MqttPacket, MqttProtocol,
+    is_mqtt3, is_mqtt4, is_mqtt5, ConnectReturnCode, DisconnectReasonCode, Error, MqttPacket,
+    MqttProtocol, QoS,
 };
 use schema_register::schema::SchemaRegisterManager;
 use storage_adapter::storage::StorageAdapter;
@@ -44,6 +47,8 @@ pub struct Command<S> {
     mqtt4_service: MqttService<S>,
     mqtt5_service: MqttService<S>,
     metadata_cache: Arc<CacheManager>,
+    // Protocol levels accepted on the listener, all of them when empty
+    protocol_versions: Vec<u8>,
 }
 
 impl<S> Command<S>
@@ -99,9 +104,60 @@ where
             mqtt4_service,
             mqtt5_service,
             metadata_cache: cache_manager,
+            protocol_versions: Vec::new(),
         }
     }
 
+    pub fn with_protocol_versions(mut self, protocol_versions: Vec<u8>) -> Self {
+        self.protocol_versions = protocol_versions;
+        self
+    }
+
+    /// Decodes the raw bytes received on a connection and applies the complete packets in order,
+    /// as the network servers do, returning the responses. An incomplete trailing packet is left
+    /// in the stream, and a CONNECT that fails to decode is answered with a Malformed Packet
+    /// CONNACK. This is the entrypoint of the fuzz targets under `fuzz/`.
+    pub async fn apply_raw(
+        &mut self,
+        connect_manager: Arc<ConnectionManager>,
+        connection_id: u64,
+        addr: SocketAddr,
+        codec: &mut MqttCodec,
+        stream: &mut BytesMut,
+    ) -> Result<Vec<MqttPacket>, MqttBrokerError> {
+        let mut responses = Vec::new();
+        while !stream.is_empty() {
+            let is_connect_pkg = stream[0] >> 4 == 1;
+            let packet = match codec.decode_data(stream) {
+                Ok(Some(packet)) => packet,
+                Ok(None) | Err(Error::InsufficientBytes(_)) => break,
+                // e.g. a will QoS of 3, the CONNACK tells the client why it is disconnected
+                Err(e) if is_connect_pkg => {
+                    responses.push(response_packet_mqtt_connect_fail(
+                        &MqttProtocol::Mqtt5,
+                        ConnectReturnCode::MalformedPacket,
+                        &None,
+                        Some(e.to_string()),
+                    ));
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            // Read for every packet, as the CONNECT sets the protocol of the connection
+            let Some(connection) = connect_manager.get_connect(connection_id) else {
+                return Err(MqttBrokerError::NotFoundConnectionInCache(connection_id));
+            };
+            if let Some(resp) = self
+                .apply(connect_manager.clone(), connection, addr, packet)
+                .await
+            {
+                responses.push(resp);
+            }
+        }
+        Ok(responses)
+    }
+
     pub async fn apply(
         &mut self,
         connect_manager: Arc<ConnectionManager>,
@@ -130,51 +186,60 @@ where
                 last_will_properties,
                 login,
             ) => {
+                // e.g. a MQTT 5 CONNECT on a MQTT 3.1.1 only listener, the CONNACK is the one of
+                // MQTT 3.1.1 that every client can decode: 0x01, unacceptable protocol version
+                if !self.protocol_versions.is_empty()
+                    && !self.protocol_versions.contains(&protocol_version)
+                {
+                    // The responses are encoded with the protocol of the connection
+                    connect_manager.set_connect_protocol(tcp_connection.connection_id, 4);
+                    return Some(response_packet_mqtt_connect_fail(
+                        &MqttProtocol::Mqtt4,
+                        ConnectReturnCode::UnsupportedProtocolVersion,
+                        &None,
+                        None,
+                    ));
+                }
+
                 connect_manager
                     .set_connect_protocol(tcp_connection.connection_id, protocol_version);
 
-                let resp_pkg = if is_mqtt3(protocol_version) {
-                    Some(
-                        self.mqtt3_service
-                            .connect(
-                                tcp_connection.connection_id,
-                                connect,
-                                properties,
-                                last_will,
-                                last_will_properties,
-                                &login,
-                                addr,
-                            )
-                            .await,
-                    )
+                let ack_pkg = if is_mqtt3(protocol_version) {
+                    self.mqtt3_service
+                        .connect(
+                            tcp_connection.connection_id,
+                            connect,
+                            properties,
+                            last_will,
+                            last_will_properties,
+                            &login,
+                            addr,
+                        )
+                        .await
                 } else if is_mqtt4(protocol_version) {
-                    Some(
-                        self.mqtt4_service
-                            .connect(
-                                tcp_connection.connection_id,
-                                connect,
-                                properties,
-                                last_will,
-                                last_will_properties,
-                                &login,
-                                addr,
-                            )
-                            .await,
-                    )
+                    self.mqtt4_service
+                        .connect(
+                            tcp_connection.connection_id,
+                            connect,
+                            properties,
+                            last_will,
+                            last_will_properties,
+                            &login,
+                            addr,
+                        )
+                        .await
                 } else if is_mqtt5(protocol_version) {
-                    Some(
-                        self.mqtt5_service
-                            .connect(
-                                tcp_connection.connection_id,
-                                connect,
-                                properties,
-                                last_will,
-                                last_will_properties,
-                                &login,
-                                addr,
-                            )
-                            .await,
-                    )
+                    self.mqtt5_service
+                        .connect(
+                            tcp_connection.connection_id,
+                            connect,
+                            properties,
+                            last_will,
+                            last_will_properties,
+                            &login,
+                            addr,
+                        )
+                        .await
                 } else {
                     return Some(response_packet_mqtt_connect_fail(
                         &MqttProtocol::Mqtt4,
@@ -184,7 +249,6 @@ where
                     ));
                 };
 
-                let ack_pkg = resp_pkg.unwrap();
                 if let MqttPacket::ConnAck(conn_ack, _) = ack_pkg.clone() {
                     if conn_ack.code == ConnectReturnCode::Success {
                         let username = if let Some(user) = login {
@@ -220,13 +284,15 @@ where
                 {
                     se.clone()
                 } else {
-                    return Some(response_packet_mqtt_distinct_by_reason(
-                        &tcp_connection.get_protocol(),
-                        Some(DisconnectReasonCode::MaximumConnectTime),
-                    ));
+                    debug!(
+                        "publish of connection {} is dropped, the connection was closed",
+                        tcp_connection.connection_id
+                    );
+                    return None;
                 };
 
-                if is_qos_message(publish.qos) {
+                // A QoS 2 message is counted by the service until its PUBREL
+                if publish.qos == QoS::AtLeastOnce {
                     connection.recv_qos_message_incr();
                 }
 
@@ -258,12 +324,8 @@ where
                     None
                 };
 
-                if let Some(pack) = resp.clone() {
-                    if let MqttPacket::PubRec(_, _) = pack {
-                        // todo
-                    } else if is_qos_message(publish.qos) {
-                        connection.recv_qos_message_decr();
-                    }
+                if resp.is_some() && publish.qos == QoS::AtLeastOnce {
+                    connection.recv_qos_message_decr();
                 }
                 // span add_event
                 span.add_event(
@@ -502,6 +564,37 @@ where
                 }
             }
 
+            MqttPacket::Auth(auth, auth_properties) => {
+                if tcp_connection.is_mqtt5() {
+                    return Some(
+                        self.mqtt5_service
+                            .auth(tcp_connection.connection_id, auth, auth_properties)
+                            .await,
+                    );
+                }
+
+                // AUTH was added by MQTT 5
+                return Some(response_packet_mqtt_distinct_by_reason(
+                    &tcp_connection.get_protocol(),
+                    Some(DisconnectReasonCode::ProtocolError),
+                ));
+            }
+
+            // Sent by the server only, a client sending them is a Protocol Error
+            MqttPacket::ConnAck(_, _)
+            | MqttPacket::SubAck(_, _)
+            | MqttPacket::UnsubAck(_, _)
+            | MqttPacket::PingResp(_) => {
+                debug!(
+                    "connect [{}] sent a packet only the server may send, {:?}",
+                    tcp_connection.connection_id, packet
+                );
+                return Some(response_packet_mqtt_distinct_by_reason(
+                    &tcp_connection.get_protocol(),
+                    Some(DisconnectReasonCode::ProtocolError),
+                ));
+            }
+
             _ => {
                 return Some(response_packet_mqtt_connect_fail(
                     &MqttProtocol::Mqtt5,
@@ -523,3 +616,267 @@ where
         self.metadata_cache.is_login(connection_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::{Bytes, BytesMut};
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use delay_message::DelayMessageManager;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
+    use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
+    use protocol::mqtt::common::{
+        Connect, ConnectReturnCode, DisconnectReasonCode, LastWill, MqttPacket, MqttProtocol, QoS,
+        SubscribeReasonCode,
+    };
+    use schema_register::schema::SchemaRegisterManager;
+    use storage_adapter::memory::MemoryStorageAdapter;
+
+    use super::Command;
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::security::AuthDriver;
+    use crate::server::connection::{NetworkConnection, NetworkConnectionType};
+    use crate::server::connection_manager::ConnectionManager;
+    use crate::subscribe::subscribe_manager::SubscribeManager;
+
+    #[tokio::test]
+    async fn apply_raw_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let mut command = Command::new(
+            cache_manager.clone(),
+            storage_adapter.clone(),
+            Arc::new(DelayMessageManager::new(unique_id(), 1, storage_adapter)),
+            Arc::new(SubscribeManager::new()),
+            client_pool.clone(),
+            connection_manager.clone(),
+            Arc::new(SchemaRegisterManager::new()),
+            Arc::new(AuthDriver::new(cache_manager.clone(), client_pool)),
+        );
+
+        let addr = "127.0.0.1:1883".parse().unwrap();
+        let connection_id = connection_manager.add_connection(NetworkConnection::new(
+            NetworkConnectionType::Tcp,
+            addr,
+            None,
+        ));
+        connection_manager.set_connect_protocol(connection_id, 5);
+        let config = ConnectionConfig {
+            connect_id: connection_id,
+            client_id: unique_id(),
+            receive_maximum: 100,
+            max_packet_size: 100,
+            topic_alias_max: 100,
+            request_problem_info: 0,
+            keep_alive: 60,
+            source_ip_addr: "127.0.0.1".to_string(),
+        };
+        cache_manager.add_connection(connection_id, MQTTConnection::new(config));
+        cache_manager.login_success(connection_id, "user".to_string());
+
+        // a SUBSCRIBE of "$share", which had no group to decode, then the first byte of a PINGREQ
+        let mut stream = BytesMut::from(
+            &[
+                0x82, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x06, b'$', b's', b'h', b'a', b'r', b'e', 0x01,
+                0xc0,
+            ][..],
+        );
+        let mut codec = MqttCodec::new(Some(5));
+        let responses = command
+            .apply_raw(
+                connection_manager.clone(),
+                connection_id,
+                addr,
+                &mut codec,
+                &mut stream,
+            )
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 1);
+        let MqttPacket::SubAck(suback, _) = &responses[0] else {
+            panic!("expected a SUBACK packet, got {:?}", responses[0]);
+        };
+        assert_eq!(
+            suback.return_codes,
+            vec![SubscribeReasonCode::TopicFilterInvalid]
+        );
+        assert_eq!(&stream[..], &[0xc0]);
+
+        // a SUBACK is sent by the server only, the connection is closed
+        let mut stream = BytesMut::from(&[0x90, 0x04, 0x00, 0x01, 0x00, 0x00][..]);
+        let responses = command
+            .apply_raw(
+                connection_manager.clone(),
+                connection_id,
+                addr,
+                &mut codec,
+                &mut stream,
+            )
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 1);
+        let MqttPacket::Disconnect(disconnect, _) = &responses[0] else {
+            panic!("expected a DISCONNECT packet, got {:?}", responses[0]);
+        };
+        assert_eq!(
+            disconnect.reason_code,
+            Some(DisconnectReasonCode::ProtocolError)
+        );
+
+        // an unknown packet type is an error, not a panic
+        let mut stream = BytesMut::from(&[0xf0, 0x00][..]);
+        assert!(command
+            .apply_raw(
+                connection_manager,
+                connection_id,
+                addr,
+                &mut codec,
+                &mut stream,
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn apply_raw_invalid_will_qos_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let mut command = Command::new(
+            cache_manager.clone(),
+            storage_adapter.clone(),
+            Arc::new(DelayMessageManager::new(unique_id(), 1, storage_adapter)),
+            Arc::new(SubscribeManager::new()),
+            client_pool.clone(),
+            connection_manager.clone(),
+            Arc::new(SchemaRegisterManager::new()),
+            Arc::new(AuthDriver::new(cache_manager.clone(), client_pool)),
+        );
+
+        let addr = "127.0.0.1:1883".parse().unwrap();
+        let connection_id = connection_manager.add_connection(NetworkConnection::new(
+            NetworkConnectionType::Tcp,
+            addr,
+            None,
+        ));
+
+        let connect = Connect {
+            keep_alive: 30,
+            client_id: unique_id(),
+            clean_session: true,
+        };
+        let last_will = LastWill {
+            topic: Bytes::from("will_topic"),
+            message: Bytes::from("will_message"),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+        };
+        let mut codec = MqttCodec::new(Some(5));
+        let mut stream = BytesMut::new();
+        codec
+            .encode_data(
+                MqttPacketWrapper {
+                    protocol_version: 5,
+                    packet: MqttPacket::Connect(5, connect, None, Some(last_will), None, None),
+                },
+                &mut stream,
+            )
+            .unwrap();
+        // the connect flags follow the fixed header, the protocol name and the protocol level,
+        // set the will qos to 3
+        stream[2 + 2 + 4 + 1] |= 0b0001_1000;
+
+        let mut codec = MqttCodec::new(None);
+        let responses = command
+            .apply_raw(
+                connection_manager,
+                connection_id,
+                addr,
+                &mut codec,
+                &mut stream,
+            )
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 1);
+        let MqttPacket::ConnAck(connack, _) = &responses[0] else {
+            panic!("expected a CONNACK packet, got {:?}", responses[0]);
+        };
+        assert_eq!(connack.code, ConnectReturnCode::MalformedPacket);
+        assert!(!cache_manager.is_login(connection_id));
+    }
+
+    #[tokio::test]
+    async fn protocol_versions_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let mut command = Command::new(
+            cache_manager.clone(),
+            storage_adapter.clone(),
+            Arc::new(DelayMessageManager::new(unique_id(), 1, storage_adapter)),
+            Arc::new(SubscribeManager::new()),
+            client_pool.clone(),
+            connection_manager.clone(),
+            Arc::new(SchemaRegisterManager::new()),
+            Arc::new(AuthDriver::new(cache_manager.clone(), client_pool)),
+        )
+        .with_protocol_versions(vec![4]);
+
+        let addr = "127.0.0.1:1883".parse().unwrap();
+        let connection = NetworkConnection::new(NetworkConnectionType::Tcp, addr, None);
+        let connection_id = connection_manager.add_connection(connection.clone());
+
+        // an MQTT 5 CONNECT on a listener of MQTT 3.1.1 only
+        let connect = Connect {
+            keep_alive: 60,
+            client_id: unique_id(),
+            clean_session: true,
+        };
+        let response = command
+            .apply(
+                connection_manager.clone(),
+                connection,
+                addr,
+                MqttPacket::Connect(5, connect, None, None, None, None),
+            )
+            .await;
+        let Some(MqttPacket::ConnAck(connack, properties)) = response else {
+            panic!("expected a CONNACK packet, got {:?}", response);
+        };
+        assert_eq!(connack.code, ConnectReturnCode::RefusedProtocolVersion);
+        assert!(properties.is_none());
+        assert_eq!(
+            connection_manager.get_connect_protocol(connection_id),
+            Some(MqttProtocol::Mqtt4)
+        );
+    }
+}
@@ -17,6 +17,7 @@ use std::sync::Arc;
 
 use common_base::tools::{now_second, unique_id};
 use grpc_clients::pool::ClientPool;
+use log::warn;
 use metadata_struct::mqtt::cluster::MqttClusterDynamicConfig;
 use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
 use protocol::mqtt::common::{Connect, ConnectProperties};
@@ -24,6 +25,7 @@ use protocol::mqtt::common::{Connect, ConnectProperties};
 use super::cache::CacheManager;
 use super::error::MqttBrokerError;
 use super::keep_alive::client_keep_live_time;
+use super::pkid::pkid_save_by_session;
 use crate::server::connection_manager::ConnectionManager;
 use crate::storage::session::SessionStorage;
 
@@ -86,7 +88,11 @@ pub fn build_connection(
         keep_alive,
         source_ip_addr: addr.to_string(),
     };
-    MQTTConnection::new(config)
+    let mut connection = MQTTConnection::new(config);
+    connection.authentication_method = connect_properties
+        .as_ref()
+        .and_then(|properties| properties.authentication_method.clone());
+    connection
 }
 
 pub fn get_client_id(client_id: &str) -> (String, bool) {
@@ -115,6 +121,14 @@ pub async fn disconnect_connection(
     client_pool: &Arc<ClientPool>,
     connection_manager: &Arc<ConnectionManager>,
 ) -> Result<(), MqttBrokerError> {
+    // Keep the QoS 2 pkids in flight, so that a redelivery after the reconnect is not stored twice
+    if let Err(e) = pkid_save_by_session(cache_manager, client_pool, client_id).await {
+        warn!(
+            "Failed to save the QoS 2 pkids of client [{}] with the session, {}",
+            client_id, e
+        );
+    }
+
     // Remove the connection cache
     cache_manager.remove_connection(connect_id);
     // Remove the client id bound connection information
@@ -0,0 +1,300 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use grpc_clients::pool::ClientPool;
+use log::{error, info, warn};
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio::time::sleep;
+
+use super::cache::CacheManager;
+use super::connection::disconnect_connection;
+use super::response::response_packet_mqtt_distinct_redirect;
+use crate::server::connection_manager::ConnectionManager;
+use crate::server::packet::ResponsePackage;
+use crate::subscribe::sub_common::write_packet_to_client;
+
+// Time between two batches of redirected connections
+const DRAIN_INTERVAL: Duration = Duration::from_secs(10);
+// Share of the remaining connections redirected by each batch
+const DRAIN_BATCH_RATIO: f64 = 0.1;
+
+/// Drain mode of the node for rolling upgrades. While it is on, new connections are
+/// refused with a redirect to the server reference and the existing ones are moved
+/// there by [`ConnectionDrainer`], a batch at a time.
+#[derive(Clone, Default)]
+pub struct ConnectionDrain {
+    server_reference: Arc<RwLock<Option<String>>>,
+    // The broker of the cluster with the fewest connections, as last reported by the placement center
+    least_loaded_server: Arc<RwLock<Option<String>>>,
+}
+
+impl ConnectionDrain {
+    pub fn new() -> Self {
+        ConnectionDrain::default()
+    }
+
+    pub fn start(&self, server_reference: String) {
+        *self.server_reference.write().unwrap() = Some(server_reference);
+    }
+
+    pub fn stop(&self) {
+        *self.server_reference.write().unwrap() = None;
+    }
+
+    // The server the clients are redirected to, None when the node is not drained
+    pub fn server_reference(&self) -> Option<String> {
+        self.server_reference.read().unwrap().clone()
+    }
+
+    pub fn set_least_loaded_server(&self, server: Option<String>) {
+        *self.least_loaded_server.write().unwrap() = server;
+    }
+
+    pub fn least_loaded_server(&self) -> Option<String> {
+        self.least_loaded_server.read().unwrap().clone()
+    }
+}
+
+pub struct ConnectionDrainer {
+    cache_manager: Arc<CacheManager>,
+    connection_manager: Arc<ConnectionManager>,
+    client_pool: Arc<ClientPool>,
+    stop_send: broadcast::Sender<bool>,
+    interval: Duration,
+    batch_ratio: f64,
+}
+
+impl ConnectionDrainer {
+    pub fn new(
+        cache_manager: Arc<CacheManager>,
+        connection_manager: Arc<ConnectionManager>,
+        client_pool: Arc<ClientPool>,
+        stop_send: broadcast::Sender<bool>,
+    ) -> Self {
+        ConnectionDrainer {
+            cache_manager,
+            connection_manager,
+            client_pool,
+            stop_send,
+            interval: DRAIN_INTERVAL,
+            batch_ratio: DRAIN_BATCH_RATIO,
+        }
+    }
+
+    pub fn with_batch(mut self, interval: Duration, batch_ratio: f64) -> Self {
+        self.interval = interval;
+        self.batch_ratio = batch_ratio;
+        self
+    }
+
+    pub async fn start(&self) {
+        let mut stop_rx = self.stop_send.subscribe();
+        loop {
+            select! {
+                val = stop_rx.recv() => {
+                    if let Ok(true) = val {
+                        info!("{}", "Connection drain thread stopped successfully.");
+                        break;
+                    }
+                }
+                _ = sleep(self.interval) => {
+                    self.drain_batch().await;
+                }
+            }
+        }
+    }
+
+    // Redirects the next batch of connections, oldest first, and returns their number.
+    pub async fn drain_batch(&self) -> usize {
+        let Some(server_reference) = self.cache_manager.connection_drain.server_reference() else {
+            return 0;
+        };
+
+        let mut connect_ids: Vec<u64> = self
+            .cache_manager
+            .connection_info
+            .iter()
+            .map(|raw| *raw.key())
+            .collect();
+        if connect_ids.is_empty() {
+            return 0;
+        }
+        connect_ids.sort_unstable();
+        let batch_num = ((connect_ids.len() as f64 * self.batch_ratio).ceil() as usize)
+            .clamp(1, connect_ids.len());
+
+        for connect_id in connect_ids.into_iter().take(batch_num) {
+            let Some(connection) = self.cache_manager.get_connection(connect_id) else {
+                continue;
+            };
+            if let Some(protocol) = self.connection_manager.get_connect_protocol(connect_id) {
+                let resp = ResponsePackage {
+                    connection_id: connect_id,
+                    packet: response_packet_mqtt_distinct_redirect(
+                        &protocol,
+                        server_reference.clone(),
+                    ),
+                    close_after_send: true,
+                };
+                if let Err(e) = write_packet_to_client(resp, &self.connection_manager).await {
+                    warn!(
+                        "Failed to send the redirect to client_id [{}], connect_id [{}], {}",
+                        connection.client_id, connect_id, e
+                    );
+                }
+            }
+
+            if let Err(e) = disconnect_connection(
+                &connection.client_id,
+                connect_id,
+                &self.cache_manager,
+                &self.client_pool,
+                &self.connection_manager,
+            )
+            .await
+            {
+                error!("{}", e);
+            }
+            info!(
+                "client redirected, client_id={}, connect_id={}, server_reference={}",
+                connection.client_id, connect_id, server_reference
+            );
+        }
+        batch_num
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use futures::StreamExt;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
+    use metadata_struct::mqtt::session::MqttSession;
+    use protocol::mqtt::codec::MqttCodec;
+    use protocol::mqtt::common::{DisconnectReasonCode, MqttPacket};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::broadcast;
+    use tokio::time::timeout;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    use super::ConnectionDrainer;
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::server::connection::{NetworkConnection, NetworkConnectionType};
+    use crate::server::connection_manager::ConnectionManager;
+
+    #[tokio::test]
+    async fn drain_batch_redirect_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut read_streams = Vec::new();
+        for _ in 0..4 {
+            let client_stream = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (server_stream, peer_addr) = listener.accept().await.unwrap();
+            let (_, w_stream) = tokio::io::split(server_stream);
+            let connect_id = connection_manager.add_connection(NetworkConnection::new(
+                NetworkConnectionType::Tcp,
+                peer_addr,
+                None,
+            ));
+            connection_manager.set_connect_protocol(connect_id, 5);
+            connection_manager
+                .add_tcp_write(connect_id, FramedWrite::new(w_stream, MqttCodec::new(None)));
+
+            let client_id = unique_id();
+            cache_manager.add_session(
+                client_id.clone(),
+                MqttSession::new(client_id.clone(), 60, false, None),
+            );
+            cache_manager.add_connection(
+                connect_id,
+                MQTTConnection::new(ConnectionConfig {
+                    connect_id,
+                    client_id,
+                    receive_maximum: 100,
+                    max_packet_size: 100,
+                    topic_alias_max: 100,
+                    request_problem_info: 0,
+                    keep_alive: 60,
+                    source_ip_addr: peer_addr.ip().to_string(),
+                }),
+            );
+            read_streams.push(FramedRead::new(client_stream, MqttCodec::new(Some(5))));
+        }
+
+        let (stop_send, _) = broadcast::channel(1);
+        let drainer = ConnectionDrainer::new(
+            cache_manager.clone(),
+            connection_manager,
+            client_pool,
+            stop_send,
+        )
+        .with_batch(Duration::from_secs(1), 0.5);
+
+        // nothing is moved while the node is not drained
+        assert_eq!(drainer.drain_batch().await, 0);
+        assert_eq!(cache_manager.connection_info.len(), 4);
+
+        cache_manager
+            .connection_drain
+            .start("127.0.0.1:2883".to_string());
+
+        // every batch redirects half of the remaining connections, the oldest first
+        for (remaining, redirected) in [(2, 0..2), (1, 2..3), (0, 3..4)] {
+            drainer.drain_batch().await;
+            assert_eq!(cache_manager.connection_info.len(), remaining);
+
+            for read_stream in read_streams[redirected].iter_mut() {
+                let packet = timeout(Duration::from_secs(10), read_stream.next())
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .unwrap();
+                let MqttPacket::Disconnect(disconnect, properties) = packet else {
+                    panic!("expected a DISCONNECT, got {:?}", packet);
+                };
+                assert_eq!(
+                    disconnect.reason_code,
+                    Some(DisconnectReasonCode::UseAnotherServer)
+                );
+                assert_eq!(
+                    properties.unwrap().server_reference,
+                    Some("127.0.0.1:2883".to_string())
+                );
+            }
+        }
+        assert_eq!(drainer.drain_batch().await, 0);
+    }
+}
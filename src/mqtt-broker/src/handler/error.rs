@@ -45,6 +45,9 @@ pub enum MqttBrokerError {
     #[error("{0}")]
     FromMysqlError(#[from] mysql::Error),
 
+    #[error("{0}")]
+    MqttProtocolError(#[from] protocol::mqtt::common::Error),
+
     #[error("Topic alias is too long. alias is {0}")]
     TopicAliasTooLong(u16),
 
@@ -66,6 +69,18 @@ pub enum MqttBrokerError {
     #[error("Cluster is in self-protection state, please request later")]
     ClusterIsInSelfProtection,
 
+    #[error("The broker has reached the maximum number of connections, please request later")]
+    TotalConnectionsExceeded,
+
+    #[error("The properties of the CONNECT are too large, {0}")]
+    ConnectPropertiesTooLarge(String),
+
+    #[error("Messages of topic {0} forwarded by broker {1} were already forwarded {2} times, they are dropped to break the loop")]
+    ForwardMessageLoop(String, u64, u32),
+
+    #[error("Messages forwarded with id {0} are being saved, the forward is retried later")]
+    ForwardMessageInProgress(String),
+
     #[error(
         "Subscribe to push, send QOS2 message to client {0}, wait for PubRec message timeout."
     )]
@@ -74,6 +89,26 @@ pub enum MqttBrokerError {
     #[error("Bad subscription Path [{0}] does not exist")]
     SubscriptionPathNotExists(String),
 
+    #[error("Admin action [{0}] is not supported")]
+    AdminActionNotSupported(String),
+
+    #[error("The response topic of the admin action is empty")]
+    AdminResponseTopicIsEmpty,
+
+    #[error("The server reference to drain the connections to is empty")]
+    AdminDrainServerReferenceIsEmpty,
+
+    #[error("The number of shared subscription groups exceeds the maximum of {0}")]
+    SharedSubscriptionGroupsExceeded(u32),
+
+    #[error(
+        "The number of members of the shared subscription group [{0}] exceeds the maximum of {1}"
+    )]
+    SharedSubscriptionGroupMembersExceeded(String, u32),
+
+    #[error("Authentication method [{0}] is not supported")]
+    BadAuthenticationMethod(String),
+
     #[error("User does not exist")]
     UserDoesNotExist,
 
@@ -83,6 +118,18 @@ pub enum MqttBrokerError {
     #[error("Session does not exist")]
     SessionDoesNotExist,
 
+    #[error("Client [{0}] is still connected, its session cannot be migrated")]
+    SessionIsConnected(String),
+
+    #[error("Session of client [{0}] already exists on the broker, it is not imported")]
+    SessionAlreadyExists(String),
+
+    #[error("Client [{0}] is not connected")]
+    ClientNotConnected(String),
+
+    #[error("Session expiry interval cannot be changed from 0 to {0} when disconnecting")]
+    SessionExpiryIntervalChangedFromZero(u32),
+
     #[error("Topic [{0}] does not exist")]
     TopicDoesNotExist(String),
 
@@ -109,6 +156,21 @@ pub enum MqttBrokerError {
 
     #[error("kafka error: {0}")]
     KafkaError(#[from] KafkaError),
+
+    #[error("Invalid encryption key of tenant {0}, expected 32 bytes encoded in hex")]
+    InvalidEncryptionKey(String),
+
+    #[error("Message encryption failed: {0}")]
+    MessageEncryptFailed(String),
+
+    #[error("Message decryption failed: {0}")]
+    MessageDecryptFailed(String),
+
+    #[error("Invalid client id allowed regex {0}: {1}")]
+    InvalidClientIdRegex(String, String),
+
+    #[error("Records of topic {0} were read out of order, offset {1} was followed by offset {2}")]
+    RecordOffsetOutOfOrder(String, u64, u64),
 }
 
 impl From<MqttBrokerError> for Status {
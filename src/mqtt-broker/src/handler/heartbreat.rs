@@ -18,11 +18,12 @@ use std::time::Duration;
 use common_base::config::broker_mqtt::broker_mqtt_conf;
 use common_base::tools::now_second;
 use grpc_clients::pool::ClientPool;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use tokio::select;
 use tokio::sync::broadcast;
 use tokio::time::sleep;
 
+use super::cache::CacheManager;
 use super::error::MqttBrokerError;
 use crate::storage::cluster::ClusterStorage;
 
@@ -33,7 +34,34 @@ pub async fn register_node(client_pool: &Arc<ClientPool>) -> Result<(), MqttBrok
     Ok(())
 }
 
-pub async fn report_heartbeat(client_pool: &Arc<ClientPool>, stop_send: broadcast::Sender<bool>) {
+// Discovers the placement center peers from the configured seed addresses, so that the
+// peers missing from `placement_center` are known when the cluster is extended
+pub async fn discover_placement_peers(
+    client_pool: &Arc<ClientPool>,
+) -> Result<Vec<String>, MqttBrokerError> {
+    let cluster_storage = ClusterStorage::new(client_pool.clone());
+    let config = broker_mqtt_conf();
+    let peers = cluster_storage.describe_cluster().await?;
+
+    let mut addrs = Vec::new();
+    for peer in peers {
+        if !config.placement_center.contains(&peer.grpc_addr) {
+            warn!(
+                "Placement center node {} ({}) is not in the placement_center config of the broker",
+                peer.node_id, peer.grpc_addr
+            );
+        }
+        addrs.push(peer.grpc_addr);
+    }
+    info!("Discovered placement center peers: {:?}", addrs);
+    Ok(addrs)
+}
+
+pub async fn report_heartbeat(
+    client_pool: &Arc<ClientPool>,
+    cache_manager: &Arc<CacheManager>,
+    stop_send: broadcast::Sender<bool>,
+) {
     loop {
         let mut stop_recv = stop_send.subscribe();
         select! {
@@ -45,17 +73,21 @@ pub async fn report_heartbeat(client_pool: &Arc<ClientPool>, stop_send: broadcas
                     }
                 }
             }
-            _ = report(client_pool) => {
+            _ = report(client_pool, cache_manager) => {
                 sleep(Duration::from_secs(3)).await;
             }
         }
     }
 }
 
-async fn report(client_pool: &Arc<ClientPool>) {
+async fn report(client_pool: &Arc<ClientPool>, cache_manager: &Arc<CacheManager>) {
     let cluster_storage = ClusterStorage::new(client_pool.clone());
-    match cluster_storage.heartbeat().await {
-        Ok(()) => {
+    let active_connections = cache_manager.count_active_connections();
+    match cluster_storage.broker_heartbeat(active_connections).await {
+        Ok(least_loaded_server) => {
+            cache_manager
+                .connection_drain
+                .set_least_loaded_server(least_loaded_server);
             let config = broker_mqtt_conf();
             debug!(
                 "Heartbeat reporting successfully,node:{},{}",
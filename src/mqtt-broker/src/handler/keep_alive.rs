@@ -17,40 +17,48 @@ use std::time::Duration;
 
 use axum::extract::ws::Message;
 use bytes::BytesMut;
-use common_base::tools::now_second;
+use common_base::tools::{now_mills, now_second, now_second_monotonic};
 use grpc_clients::pool::ClientPool;
 use log::{error, info, warn};
 use metadata_struct::mqtt::cluster::MqttClusterDynamicConfig;
 use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
 use protocol::mqtt::common::{DisconnectReasonCode, MqttProtocol};
 use serde::{Deserialize, Serialize};
+use storage_adapter::storage::StorageAdapter;
 use tokio::select;
 use tokio::sync::broadcast::{self};
 use tokio::time::sleep;
 
 use super::cache::{CacheManager, ConnectionLiveTime};
 use super::connection::disconnect_connection;
+use super::lastwill::trigger_last_will;
 use super::response::response_packet_mqtt_distinct_by_reason;
 use crate::server::connection_manager::ConnectionManager;
 
-pub struct ClientKeepAlive {
+pub struct ClientKeepAlive<S> {
     cache_manager: Arc<CacheManager>,
     stop_send: broadcast::Sender<bool>,
     client_pool: Arc<ClientPool>,
     connection_manager: Arc<ConnectionManager>,
+    message_storage_adapter: Arc<S>,
 }
 
-impl ClientKeepAlive {
+impl<S> ClientKeepAlive<S>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
     pub fn new(
         client_pool: Arc<ClientPool>,
         connection_manager: Arc<ConnectionManager>,
         cache_manager: Arc<CacheManager>,
+        message_storage_adapter: Arc<S>,
         stop_send: broadcast::Sender<bool>,
     ) -> Self {
         ClientKeepAlive {
             client_pool,
             connection_manager,
             cache_manager,
+            message_storage_adapter,
             stop_send,
         }
     }
@@ -112,6 +120,7 @@ impl ClientKeepAlive {
                                             "Heartbeat timeout, active disconnection {} successful",
                                             connect_id
                                         );
+                                        self.spawn_last_will(connection.client_id.clone());
                                     }
                                     Err(e) => {
                                         error!("{}", e);
@@ -158,6 +167,7 @@ impl ClientKeepAlive {
                                             "Heartbeat timeout, active disconnection {} successful",
                                             connect_id
                                         );
+                                        self.spawn_last_will(connection.client_id.clone());
                                     }
                                     Err(e) => {
                                         error!("{}", e);
@@ -173,36 +183,117 @@ impl ClientKeepAlive {
             }
         }
 
-        for (client_id, _) in self.cache_manager.heartbeat_data.clone() {
-            if !self.cache_manager.session_info.contains_key(&client_id) {
-                self.cache_manager.heartbeat_data.remove(&client_id);
+        for (connect_id, _) in self.cache_manager.heartbeat_data.clone() {
+            if !self.cache_manager.connection_info.contains_key(&connect_id) {
+                self.cache_manager.remove_heartbeat(connect_id);
             }
         }
+
+        expire_qos2_inflight(&self.cache_manager, now_second());
+    }
+
+    // The connection was lost without a DISCONNECT, so the will of the client is published
+    fn spawn_last_will(&self, client_id: String) {
+        let cache_manager = self.cache_manager.clone();
+        let client_pool = self.client_pool.clone();
+        let message_storage_adapter = self.message_storage_adapter.clone();
+        tokio::spawn(async move {
+            if let Err(e) = trigger_last_will(
+                &client_id,
+                &cache_manager,
+                &client_pool,
+                message_storage_adapter,
+            )
+            .await
+            {
+                error!(
+                    "Failed to publish the last will message of client {}, {}",
+                    client_id, e
+                );
+            }
+        });
     }
 
     async fn get_expire_connection(&self) -> Vec<u64> {
-        let mut expire_connection = Vec::new();
-        for (connect_id, connection) in self.cache_manager.connection_info.clone() {
-            if let Some(time) = self.cache_manager.heartbeat_data.get(&connection.client_id) {
-                let max_timeout = keep_live_time(time.keep_live) as u64;
-                if (now_second() - time.heartbeat) >= max_timeout {
-                    info!("{}","Connection was closed by the server because the heartbeat timeout was not reported.");
-                    expire_connection.push(connect_id);
-                }
-            } else {
-                let live_time = ConnectionLiveTime {
-                    protocol: MqttProtocol::Mqtt5,
-                    keep_live: connection.keep_alive,
-                    heartbeat: now_second(),
-                };
-                self.cache_manager
-                    .report_heartbeat(connection.client_id, live_time);
+        // the heartbeats are only compared within the process, so a clock adjustment
+        // does not expire or keep alive the connections
+        let now = now_second_monotonic();
+
+        // connections that have not reported a heartbeat yet start from now
+        let new_heartbeats: Vec<(u64, ConnectionLiveTime)> = self
+            .cache_manager
+            .connection_info
+            .iter()
+            .filter(|connection| {
+                !self
+                    .cache_manager
+                    .heartbeat_data
+                    .contains_key(connection.key())
+            })
+            .map(|connection| {
+                (
+                    *connection.key(),
+                    ConnectionLiveTime {
+                        protocol: MqttProtocol::Mqtt5,
+                        keep_live: connection.keep_alive,
+                        heartbeat: now,
+                    },
+                )
+            })
+            .collect();
+        self.cache_manager.report_heartbeats_batch(new_heartbeats);
+
+        let expire_connection = self.cache_manager.get_expired_connections(now);
+        for connect_id in expire_connection.iter() {
+            if let Some(connection) = self.cache_manager.get_connection(*connect_id) {
+                let (stats, duration_ms) = self
+                    .connection_manager
+                    .get_connect(*connect_id)
+                    .map(|network| {
+                        (
+                            network.stats.snapshot(),
+                            now_mills().saturating_sub(network.create_time),
+                        )
+                    })
+                    .unwrap_or_default();
+                info!(
+                    "client disconnected, client_id={}, connect_id={}, reason=keepalive_timeout, messages_sent={}, messages_received={}, bytes_sent={}, bytes_received={}, duration_ms={}",
+                    connection.client_id,
+                    connect_id,
+                    stats.messages_sent,
+                    stats.messages_received,
+                    stats.bytes_sent,
+                    stats.bytes_received,
+                    duration_ms
+                );
             }
         }
         expire_connection
     }
 }
 
+// An inbound QoS 2 message whose PUBREL does not come in time releases its packet identifier
+// and its slot of the receive maximum of the connection. The pkids persisted with
+// client_pkid_persistent are kept by the placement center and are not expired.
+pub fn expire_qos2_inflight(cache_manager: &Arc<CacheManager>, now: u64) {
+    let protocol = cache_manager.get_cluster_info().protocol;
+    if protocol.qos2_inflight_expire_secs == 0 || protocol.client_pkid_persistent {
+        return;
+    }
+
+    for data in cache_manager.remove_expired_client_pkids(now, protocol.qos2_inflight_expire_secs) {
+        warn!(
+            "PUBREL of client_id [{}] pkid [{}] was not received within {}s, the packet identifier is released",
+            data.client_id, data.pkid, protocol.qos2_inflight_expire_secs
+        );
+        if let Some(connect_id) = cache_manager.get_connect_id(&data.client_id) {
+            if let Some(connection) = cache_manager.connection_info.get(&connect_id) {
+                connection.recv_qos_message_decr();
+            }
+        }
+    }
+}
+
 pub fn keep_live_time(keep_alive: u16) -> u16 {
     let new_keep_alive: u32 = (keep_alive as u32) * 2;
     if new_keep_alive > 65535 {
@@ -231,18 +322,21 @@ pub struct KeepAliveRunInfo {
 #[cfg(test)]
 mod test {
     use std::sync::Arc;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     use common_base::config::broker_mqtt::BrokerMqttConfig;
-    use common_base::tools::{now_second, unique_id};
+    use common_base::tools::{now_second, now_second_monotonic, unique_id};
     use grpc_clients::pool::ClientPool;
     use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
     use metadata_struct::mqtt::session::MqttSession;
+    use protocol::mqtt::common::MqttProtocol;
+    use storage_adapter::memory::MemoryStorageAdapter;
     use tokio::sync::broadcast;
     use tokio::time::sleep;
 
-    use super::keep_live_time;
-    use crate::handler::cache::CacheManager;
+    use super::{expire_qos2_inflight, keep_live_time};
+    use crate::handler::cache::{CacheManager, ConnectionLiveTime};
+    use crate::handler::cluster_config::build_default_cluster_config;
     use crate::handler::keep_alive::ClientKeepAlive;
     use crate::server::connection_manager::ConnectionManager;
 
@@ -275,6 +369,7 @@ mod test {
             client_pool,
             connection_manager,
             cache_manager.clone(),
+            Arc::new(MemoryStorageAdapter::new()),
             stop_send,
         );
 
@@ -299,7 +394,7 @@ mod test {
         let connection = MQTTConnection::new(config);
         cache_manager.add_connection(connect_id, connection);
 
-        let start = now_second();
+        let start = now_second_monotonic();
         loop {
             let res = alive.get_expire_connection().await;
             if res.contains(&connect_id) {
@@ -307,6 +402,158 @@ mod test {
             }
             sleep(Duration::from_millis(100)).await;
         }
-        assert_eq!((now_second() - start), keep_live_time(keep_alive) as u64);
+        assert_eq!(
+            (now_second_monotonic() - start),
+            keep_live_time(keep_alive) as u64
+        );
+    }
+
+    #[test]
+    pub fn expire_qos2_inflight_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.qos2_inflight_expire_secs = 0;
+        cache_manager.set_cluster_info(cluster.clone());
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        let mut session = MqttSession::new(client_id.clone(), 60, false, None);
+        session.connection_id = Some(connect_id);
+        cache_manager.add_session(client_id.clone(), session);
+        let config = ConnectionConfig {
+            connect_id,
+            client_id: client_id.clone(),
+            receive_maximum: 100,
+            max_packet_size: 100,
+            topic_alias_max: 100,
+            request_problem_info: 100,
+            keep_alive: 60,
+            source_ip_addr: "127.0.0.1".to_string(),
+        };
+        let connection = MQTTConnection::new(config);
+        cache_manager.add_connection(connect_id, connection);
+
+        // two QoS 2 messages waiting for their PUBREL
+        for pkid in [1, 2] {
+            cache_manager.add_client_pkid(&client_id, pkid);
+            cache_manager
+                .connection_info
+                .get(&connect_id)
+                .unwrap()
+                .recv_qos_message_incr();
+        }
+        cache_manager
+            .client_pkid_data
+            .iter_mut()
+            .filter(|data| data.pkid == 1)
+            .for_each(|mut data| data.create_time = now_second() - 100);
+
+        // disabled, the pkids wait as long as the session
+        expire_qos2_inflight(&cache_manager, now_second());
+        assert_eq!(cache_manager.list_client_pkids(&client_id), vec![1, 2]);
+
+        cluster.protocol.qos2_inflight_expire_secs = 60;
+        cache_manager.set_cluster_info(cluster);
+        expire_qos2_inflight(&cache_manager, now_second());
+        assert_eq!(cache_manager.list_client_pkids(&client_id), vec![2]);
+        let connection = cache_manager.get_connection(connect_id).unwrap();
+        assert_eq!(connection.get_recv_qos_message(), 1);
+    }
+
+    fn build_live_time(keep_live: u16, heartbeat: u64) -> ConnectionLiveTime {
+        ConnectionLiveTime {
+            protocol: MqttProtocol::Mqtt5,
+            keep_live,
+            heartbeat,
+        }
+    }
+
+    #[test]
+    pub fn report_heartbeats_batch_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = CacheManager::new(client_pool, unique_id());
+
+        let now = now_second();
+        cache_manager.report_heartbeat(1, build_live_time(10, now - 100));
+        cache_manager.report_heartbeats_batch(vec![
+            (1, build_live_time(10, now - 30)),
+            (2, build_live_time(10, now - 19)),
+            (3, build_live_time(10, now - 20)),
+            (3, build_live_time(10, now)),
+            (4, build_live_time(0, now)),
+        ]);
+        assert_eq!(cache_manager.heartbeat_data.len(), 4);
+        assert_eq!(cache_manager.heartbeat_data.get(&3).unwrap().heartbeat, now);
+
+        let mut expired = cache_manager.get_expired_connections(now);
+        expired.sort();
+        assert_eq!(expired, vec![1, 4]);
+
+        cache_manager.remove_heartbeat(1);
+        cache_manager.remove_heartbeat(4);
+        assert!(cache_manager.get_expired_connections(now).is_empty());
+    }
+
+    #[test]
+    pub fn report_heartbeats_batch_many_shards_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = CacheManager::new(client_pool, unique_id());
+
+        let now = now_second();
+        cache_manager.report_heartbeat(7, build_live_time(10, now - 100));
+        cache_manager.report_heartbeats_batch(
+            (0..1000)
+                .map(|connect_id| (connect_id, build_live_time(10, now - connect_id)))
+                .collect(),
+        );
+        assert_eq!(cache_manager.heartbeat_data.len(), 1000);
+        for connect_id in 0..1000 {
+            let live_time = cache_manager.heartbeat_data.get(&connect_id).unwrap();
+            assert_eq!(live_time.heartbeat, now - connect_id);
+        }
+
+        cache_manager.remove_heartbeat(7);
+        assert!(cache_manager.heartbeat_data.get(&7).is_none());
+        assert_eq!(cache_manager.heartbeat_data.len(), 999);
+    }
+
+    // The best time of 5 rounds to report the first heartbeat of 10,000 connections one by one
+    // and as a batch, the batch grows each shard once instead of every time it is full.
+    // cargo test -p mqtt-broker report_heartbeats_throughput -- --ignored
+    #[test]
+    #[ignore]
+    pub fn report_heartbeats_throughput() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let now = now_second();
+        let updates: Vec<(u64, ConnectionLiveTime)> = (0..10000)
+            .map(|connect_id| (connect_id, build_live_time(60, now)))
+            .collect();
+
+        let mut sequential = Duration::MAX;
+        let mut batched = Duration::MAX;
+        for _ in 0..5 {
+            let cache_manager = CacheManager::new(client_pool.clone(), unique_id());
+            let round = updates.clone();
+            let start = Instant::now();
+            for (connect_id, live_time) in round {
+                cache_manager.report_heartbeat(connect_id, live_time);
+            }
+            sequential = sequential.min(start.elapsed());
+
+            let cache_manager = CacheManager::new(client_pool.clone(), unique_id());
+            let round = updates.clone();
+            let start = Instant::now();
+            cache_manager.report_heartbeats_batch(round);
+            batched = batched.min(start.elapsed());
+            assert_eq!(cache_manager.heartbeat_data.len(), updates.len());
+        }
+
+        assert!(
+            batched * 5 < sequential * 4,
+            "sequential={:?}, batched={:?}",
+            sequential,
+            batched
+        );
     }
 }
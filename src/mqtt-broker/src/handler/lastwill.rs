@@ -13,22 +13,135 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
+use common_base::tools::unique_id;
+use dashmap::DashMap;
 use grpc_clients::pool::ClientPool;
 use metadata_struct::mqtt::lastwill::LastWillData;
 use metadata_struct::mqtt::message::MqttMessage;
-use protocol::mqtt::common::{LastWill, LastWillProperties, Publish, PublishProperties};
+use metadata_struct::mqtt::session::MqttSession;
+use protocol::mqtt::common::{
+    DisconnectReasonCode, LastWill, LastWillProperties, Publish, PublishProperties,
+};
 use storage_adapter::storage::StorageAdapter;
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio::time::sleep;
 
 use super::cache::CacheManager;
 use super::error::MqttBrokerError;
 use super::message::build_message_expire;
 use super::retain::save_retain_message;
 use super::topic::try_init_topic;
+use crate::security::encryption::{decrypt_message_payload, encrypt_message_payload};
 use crate::storage::message::MessageStorage;
 use crate::storage::session::SessionStorage;
 
+/// The wills waiting for their delay interval, keyed by client id. A client that connects again
+/// to its session cancels its will, a client that starts a new session ends the old one and the
+/// will is published at once.
+#[derive(Clone, Default)]
+pub struct DelayedLastWill {
+    // (client_id, (schedule id, sender of true to publish the will now or false to cancel it))
+    wills: DashMap<String, (String, broadcast::Sender<bool>)>,
+}
+
+impl DelayedLastWill {
+    pub fn new() -> Self {
+        DelayedLastWill::default()
+    }
+
+    // The will of a client lost again replaces the one still waiting, which is canceled
+    fn schedule(&self, client_id: &str) -> (String, broadcast::Receiver<bool>) {
+        let schedule_id = unique_id();
+        let (sx, rx) = broadcast::channel(1);
+        if let Some((_, (_, previous))) = self.wills.remove(client_id) {
+            let _ = previous.send(false);
+        }
+        self.wills
+            .insert(client_id.to_owned(), (schedule_id.clone(), sx));
+        (schedule_id, rx)
+    }
+
+    fn unschedule(&self, client_id: &str, schedule_id: &str) {
+        self.wills
+            .remove_if(client_id, |_, (id, _)| id == schedule_id);
+    }
+
+    pub fn is_scheduled(&self, client_id: &str) -> bool {
+        self.wills.contains_key(client_id)
+    }
+
+    // The client connected again to its session, returns whether a will was waiting
+    pub fn cancel(&self, client_id: &str) -> bool {
+        self.notify(client_id, false)
+    }
+
+    // The session of the client ended before the delay, returns whether a will was waiting
+    pub fn publish_now(&self, client_id: &str) -> bool {
+        self.notify(client_id, true)
+    }
+
+    fn notify(&self, client_id: &str, publish: bool) -> bool {
+        if let Some((_, (_, sx))) = self.wills.remove(client_id) {
+            return sx.send(publish).is_ok();
+        }
+        false
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LastWillDelay {
+    // The delay interval passed, the will is published
+    Elapsed,
+    // The session ended before the delay interval passed, the will is published
+    SessionEnded,
+    // The client connected again to its session, the will is not published
+    Canceled,
+}
+
+// Waits for the delay interval of the will of the client, 0 does not wait
+pub async fn wait_last_will_delay(
+    cache_manager: &Arc<CacheManager>,
+    client_id: &str,
+    delay: u64,
+) -> LastWillDelay {
+    if delay == 0 {
+        return LastWillDelay::Elapsed;
+    }
+
+    let (schedule_id, mut rx) = cache_manager.delayed_last_will.schedule(client_id);
+    select! {
+        val = rx.recv() => {
+            if let Ok(true) = val {
+                return LastWillDelay::SessionEnded;
+            }
+            LastWillDelay::Canceled
+        }
+        _ = sleep(Duration::from_secs(delay)) => {
+            cache_manager
+                .delayed_last_will
+                .unschedule(client_id, &schedule_id);
+            LastWillDelay::Elapsed
+        }
+    }
+}
+
+// The will is published when its delay interval passes or when the session ends, whichever
+// happens first [MQTT-3.1.3-9]
+pub fn last_will_publish_delay(
+    last_will_properties: &Option<LastWillProperties>,
+    session: &Option<MqttSession>,
+) -> u64 {
+    let delay = last_will_delay_interval(last_will_properties).unwrap_or(0);
+    if let Some(session) = session {
+        return delay.min(session.session_expiry);
+    }
+    delay
+}
+
 pub async fn send_last_will_message<S>(
     client_id: &str,
     cache_manager: &Arc<CacheManager>,
@@ -48,7 +161,9 @@ where
         return Ok(());
     }
 
-    let publish = publish_res.unwrap();
+    // The will is stored encrypted like the messages of its topic
+    let mut publish = publish_res.unwrap();
+    publish.payload = decrypt_message_payload(cache_manager, &topic_name, &publish.payload)?;
 
     let topic = try_init_topic(
         &topic_name,
@@ -61,7 +176,8 @@ where
     save_retain_message(
         cache_manager,
         client_pool,
-        topic_name,
+        &message_storage_adapter,
+        topic_name.clone(),
         client_id,
         &publish,
         &publish_properties,
@@ -70,6 +186,7 @@ where
 
     // Persisting stores message data
     let message_storage = MessageStorage::new(message_storage_adapter.clone());
+    publish.payload = encrypt_message_payload(cache_manager, &topic_name, &publish.payload)?;
 
     let message_expire = build_message_expire(cache_manager, &publish_properties);
     if let Some(record) =
@@ -82,6 +199,61 @@ where
     Ok(())
 }
 
+// Publishes the stored will of a client whose connection ended without a DISCONNECT that
+// discards it. A will with a delay interval is published once the delay has passed, unless the
+// client connected again to its session in the meantime [MQTT-3.1.3-9].
+pub async fn trigger_last_will<S>(
+    client_id: &str,
+    cache_manager: &Arc<CacheManager>,
+    client_pool: &Arc<ClientPool>,
+    message_storage_adapter: Arc<S>,
+) -> Result<(), MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let session_storage = SessionStorage::new(client_pool.clone());
+    let Some(data) = session_storage
+        .get_last_will_message(client_id.to_owned())
+        .await?
+    else {
+        return Ok(());
+    };
+
+    let delay = last_will_publish_delay(
+        &data.last_will_properties,
+        &cache_manager.get_session_info(client_id),
+    );
+    let session_ended = match wait_last_will_delay(cache_manager, client_id, delay).await {
+        LastWillDelay::Canceled => return Ok(()),
+        LastWillDelay::Elapsed => {
+            // the client connected again before its will was scheduled
+            if delay > 0 && cache_manager.get_connect_id(client_id).is_some() {
+                return Ok(());
+            }
+            false
+        }
+        LastWillDelay::SessionEnded => true,
+    };
+
+    send_last_will_message(
+        client_id,
+        cache_manager,
+        client_pool,
+        &data.last_will,
+        &data.last_will_properties,
+        message_storage_adapter,
+    )
+    .await?;
+
+    // The new session of the client saves its own will in place of this one
+    if session_ended {
+        return Ok(());
+    }
+
+    // A will is published once, the placement center must not find it again on session expiry
+    delete_last_will_message(client_id.to_owned(), client_pool).await
+}
+
 fn build_publish_message_by_lastwill(
     last_will: &Option<LastWill>,
     last_will_properties: &Option<LastWillProperties>,
@@ -124,16 +296,23 @@ pub async fn save_last_will_message(
     client_id: String,
     last_will: &Option<LastWill>,
     last_will_properties: &Option<LastWillProperties>,
+    cache_manager: &Arc<CacheManager>,
     client_pool: &Arc<ClientPool>,
 ) -> Result<(), MqttBrokerError> {
-    if last_will.is_none() {
+    let Some(will) = last_will else {
         return Ok(());
+    };
+
+    // The will message is encrypted with the key of the tenant of its topic
+    let mut will = will.clone();
+    if let Ok(topic_name) = std::str::from_utf8(&will.topic) {
+        will.message = encrypt_message_payload(cache_manager, topic_name, &will.message)?;
     }
 
     let session_storage = SessionStorage::new(client_pool.clone());
     let lastwill = LastWillData {
         client_id: client_id.clone(),
-        last_will: last_will.clone(),
+        last_will: Some(will),
         last_will_properties: last_will_properties.clone(),
     };
 
@@ -144,6 +323,23 @@ pub async fn save_last_will_message(
     Ok(())
 }
 
+pub async fn delete_last_will_message(
+    client_id: String,
+    client_pool: &Arc<ClientPool>,
+) -> Result<(), MqttBrokerError> {
+    let session_storage = SessionStorage::new(client_pool.clone());
+    session_storage.delete_last_will_message(client_id).await?;
+    Ok(())
+}
+
+// A DISCONNECT discards the will, unless a v5 client asks for it to be published
+pub fn is_discard_last_will(reason_code: &Option<DisconnectReasonCode>) -> bool {
+    !matches!(
+        reason_code,
+        Some(DisconnectReasonCode::DisconnectWithWillMessage)
+    )
+}
+
 pub fn last_will_delay_interval(last_will_properties: &Option<LastWillProperties>) -> Option<u64> {
     let delay_interval = if let Some(properties) = last_will_properties.clone() {
         properties.delay_interval?
@@ -156,10 +352,122 @@ pub fn last_will_delay_interval(last_will_properties: &Option<LastWillProperties
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
     use bytes::Bytes;
-    use protocol::mqtt::common::{LastWill, LastWillProperties};
+    use common_base::tools::unique_id;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::mqtt::session::MqttSession;
+    use protocol::mqtt::common::{DisconnectReasonCode, LastWill, LastWillProperties};
+    use tokio::time::{sleep, Instant};
+
+    use super::{
+        build_publish_message_by_lastwill, is_discard_last_will, last_will_delay_interval,
+        last_will_publish_delay, wait_last_will_delay, LastWillDelay,
+    };
+    use crate::handler::cache::CacheManager;
+
+    fn build_cache_manager() -> Arc<CacheManager> {
+        let client_pool = Arc::new(ClientPool::new(1));
+        Arc::new(CacheManager::new(client_pool, unique_id()))
+    }
+
+    #[test]
+    fn last_will_publish_delay_test() {
+        let properties = Some(LastWillProperties {
+            delay_interval: Some(30),
+            ..Default::default()
+        });
+        assert_eq!(last_will_publish_delay(&None, &None), 0);
+        assert_eq!(last_will_publish_delay(&properties, &None), 30);
+
+        // the session ends first, the will is published when it expires
+        let session = MqttSession::new(unique_id(), 10, true, Some(30));
+        assert_eq!(last_will_publish_delay(&properties, &Some(session)), 10);
+        let session = MqttSession::new(unique_id(), 0, true, Some(30));
+        assert_eq!(last_will_publish_delay(&properties, &Some(session)), 0);
+        let session = MqttSession::new(unique_id(), 60, true, Some(30));
+        assert_eq!(last_will_publish_delay(&properties, &Some(session)), 30);
+    }
+
+    #[tokio::test]
+    async fn zero_delay_fires_immediately_test() {
+        let cache_manager = build_cache_manager();
+        let client_id = unique_id();
+        assert_eq!(
+            wait_last_will_delay(&cache_manager, &client_id, 0).await,
+            LastWillDelay::Elapsed
+        );
+        assert!(!cache_manager.delayed_last_will.is_scheduled(&client_id));
+    }
+
+    #[tokio::test]
+    async fn delay_elapses_fires_will_test() {
+        let cache_manager = build_cache_manager();
+        let client_id = unique_id();
+        let start = Instant::now();
+        assert_eq!(
+            wait_last_will_delay(&cache_manager, &client_id, 1).await,
+            LastWillDelay::Elapsed
+        );
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(!cache_manager.delayed_last_will.is_scheduled(&client_id));
+    }
 
-    use super::{build_publish_message_by_lastwill, last_will_delay_interval};
+    #[tokio::test]
+    async fn reconnect_cancels_will_test() {
+        let cache_manager = build_cache_manager();
+        let client_id = unique_id();
+
+        let wait = {
+            let cache_manager = cache_manager.clone();
+            let client_id = client_id.clone();
+            tokio::spawn(async move { wait_last_will_delay(&cache_manager, &client_id, 60).await })
+        };
+        while !cache_manager.delayed_last_will.is_scheduled(&client_id) {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // the client connects again to its session before the delay
+        assert!(cache_manager.delayed_last_will.cancel(&client_id));
+        assert_eq!(wait.await.unwrap(), LastWillDelay::Canceled);
+        assert!(!cache_manager.delayed_last_will.is_scheduled(&client_id));
+        assert!(!cache_manager.delayed_last_will.cancel(&client_id));
+    }
+
+    #[tokio::test]
+    async fn new_session_publishes_will_test() {
+        let cache_manager = build_cache_manager();
+        let client_id = unique_id();
+
+        let wait = {
+            let cache_manager = cache_manager.clone();
+            let client_id = client_id.clone();
+            tokio::spawn(async move { wait_last_will_delay(&cache_manager, &client_id, 60).await })
+        };
+        while !cache_manager.delayed_last_will.is_scheduled(&client_id) {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // the client starts a new session, which ends the one of the will
+        assert!(cache_manager.delayed_last_will.publish_now(&client_id));
+        assert_eq!(wait.await.unwrap(), LastWillDelay::SessionEnded);
+    }
+
+    #[test]
+    fn is_discard_last_will_test() {
+        assert!(is_discard_last_will(&None));
+        assert!(is_discard_last_will(&Some(
+            DisconnectReasonCode::NormalDisconnection
+        )));
+        assert!(is_discard_last_will(&Some(
+            DisconnectReasonCode::UnspecifiedError
+        )));
+        assert!(!is_discard_last_will(&Some(
+            DisconnectReasonCode::DisconnectWithWillMessage
+        )));
+    }
 
     #[tokio::test]
     pub async fn last_will_delay_interval_test() {
@@ -24,6 +24,15 @@ pub fn is_message_expire(message: &MqttMessage) -> bool {
     message.expiry_interval < now_second()
 }
 
+// The seconds until the message expires, sent as the message expiry interval of the PUBLISH
+// [MQTT-3.3.2-6]
+pub fn message_remaining_expiry(message: &MqttMessage) -> u32 {
+    message
+        .expiry_interval
+        .saturating_sub(now_second())
+        .min(u32::MAX as u64) as u32
+}
+
 pub fn build_message_expire(
     cache_manager: &Arc<CacheManager>,
     publish_properties: &Option<PublishProperties>,
@@ -53,7 +62,9 @@ mod tests {
     use protocol::mqtt::common::PublishProperties;
 
     use crate::handler::cache::CacheManager;
-    use crate::handler::message::{build_message_expire, is_message_expire};
+    use crate::handler::message::{
+        build_message_expire, is_message_expire, message_remaining_expiry,
+    };
 
     #[test]
     fn build_message_expire_test() {
@@ -97,4 +108,26 @@ mod tests {
 
         assert!(!is_message_expire(&message));
     }
+
+    #[test]
+    fn message_remaining_expiry_test() {
+        let message = MqttMessage {
+            expiry_interval: now_second() - 10,
+            ..Default::default()
+        };
+        assert_eq!(message_remaining_expiry(&message), 0);
+
+        let message = MqttMessage {
+            expiry_interval: now_second() + 10,
+            ..Default::default()
+        };
+        let remaining = message_remaining_expiry(&message);
+        assert!(remaining == 9 || remaining == 10);
+
+        let message = MqttMessage {
+            expiry_interval: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(message_remaining_expiry(&message), u32::MAX);
+    }
 }
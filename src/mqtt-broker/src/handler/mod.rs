@@ -13,6 +13,7 @@
 // limitations under the License.
 
 pub mod acl;
+pub mod admin_topic;
 pub mod cache;
 pub mod cache_update;
 pub mod cluster_config;
@@ -20,6 +21,7 @@ pub mod command;
 pub mod connection;
 pub mod constant;
 pub mod delay_message;
+pub mod drain;
 pub mod error;
 pub mod flapping_detect;
 pub mod flow_control;
@@ -33,6 +35,7 @@ pub mod pkid;
 pub mod response;
 pub mod retain;
 pub mod session;
+pub mod session_migrate;
 pub mod sub_auto;
 pub mod sub_exclusive;
 pub mod sub_parse_topic;
@@ -15,21 +15,24 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use common_base::tools::now_second;
+use common_base::config::broker_mqtt::broker_mqtt_conf;
+use common_base::tools::now_second_monotonic;
 use delay_message::DelayMessageManager;
 use grpc_clients::pool::ClientPool;
-use log::{error, warn};
+use log::{debug, error, info, warn};
+use metadata_struct::mqtt::connection::MQTTConnection;
 use protocol::mqtt::common::{
-    Connect, ConnectProperties, ConnectReturnCode, Disconnect, DisconnectProperties,
-    DisconnectReasonCode, LastWill, LastWillProperties, Login, MqttPacket, MqttProtocol, PingReq,
-    PubAck, PubAckProperties, PubAckReason, PubComp, PubCompProperties, PubCompReason, PubRec,
-    PubRecProperties, PubRecReason, PubRel, PubRelProperties, PubRelReason, Publish,
-    PublishProperties, QoS, Subscribe, SubscribeProperties, SubscribeReasonCode, UnsubAckReason,
-    Unsubscribe, UnsubscribeProperties,
+    Auth, AuthProperties, AuthReason, Connect, ConnectProperties, ConnectReturnCode, Disconnect,
+    DisconnectProperties, DisconnectReasonCode, LastWill, LastWillProperties, Login, MqttPacket,
+    MqttProtocol, PingReq, PubAck, PubAckProperties, PubAckReason, PubComp, PubCompProperties,
+    PubCompReason, PubRec, PubRecProperties, PubRecReason, PubRel, PubRelProperties, PubRelReason,
+    Publish, PublishProperties, QoS, Subscribe, SubscribeProperties, SubscribeReasonCode,
+    UnsubAckReason, Unsubscribe, UnsubscribeProperties,
 };
 use schema_register::schema::SchemaRegisterManager;
 use storage_adapter::storage::StorageAdapter;
 
+use super::admin_topic::{is_admin_topic, SystemTopicHandler};
 use super::connection::disconnect_connection;
 use super::offline_message::save_message;
 use super::retain::{is_new_sub, try_send_retain_message};
@@ -40,11 +43,15 @@ use crate::handler::cache::{
     CacheManager, ConnectionLiveTime, QosAckPackageData, QosAckPackageType,
 };
 use crate::handler::connection::{build_connection, get_client_id};
+use crate::handler::error::MqttBrokerError;
 use crate::handler::flapping_detect::check_flapping_detect;
-use crate::handler::lastwill::save_last_will_message;
-use crate::handler::pkid::{pkid_delete, pkid_exists, pkid_save};
+use crate::handler::lastwill::{
+    delete_last_will_message, is_discard_last_will, save_last_will_message, trigger_last_will,
+};
+use crate::handler::pkid::{pkid_delete, pkid_exists, pkid_restore_by_session, pkid_save};
 use crate::handler::response::{
-    response_packet_mqtt_connect_fail, response_packet_mqtt_connect_success,
+    response_packet_mqtt_auth, response_packet_mqtt_connect_fail,
+    response_packet_mqtt_connect_redirect, response_packet_mqtt_connect_success,
     response_packet_mqtt_distinct_by_reason, response_packet_mqtt_ping_resp,
     response_packet_mqtt_puback_fail, response_packet_mqtt_puback_success,
     response_packet_mqtt_pubcomp_fail, response_packet_mqtt_pubcomp_success,
@@ -53,19 +60,23 @@ use crate::handler::response::{
     response_packet_mqtt_unsuback,
 };
 use crate::handler::retain::save_retain_message;
-use crate::handler::session::{build_session, save_session};
-use crate::handler::topic::{get_topic_name, try_init_topic};
+use crate::handler::session::{build_session, save_session, update_session_expiry_by_disconnect};
+use crate::handler::topic::{get_topic_name, try_init_topic_with_created};
 use crate::handler::topic_rewrite::{process_sub_topic_rewrite, process_unsub_topic_rewrite};
 use crate::handler::validator::{
-    connect_validator, publish_validator, subscribe_validator, un_subscribe_validator,
+    client_id_validator, connect_validator, publish_validator, subscribe_validator,
+    un_subscribe_validator,
 };
+use crate::observability::metrics::publish::{record_message_dropped, MessageDropReason};
 use crate::observability::system_topic::event::{
     st_report_connected_event, st_report_disconnected_event, st_report_subscribed_event,
     st_report_unsubscribed_event,
 };
 use crate::security::AuthDriver;
 use crate::server::connection_manager::ConnectionManager;
-use crate::subscribe::sub_common::{min_qos, path_contain_sub};
+use crate::subscribe::cluster_route::{forward_message, remote_subscriber_brokers};
+use crate::subscribe::flow_control_signal::BROKER_LAG_USER_PROPERTY;
+use crate::subscribe::sub_common::{min_qos, path_contain_sub, send_packet_ack};
 use crate::subscribe::subscribe_manager::SubscribeManager;
 
 #[derive(Clone)]
@@ -121,12 +132,19 @@ where
         login: &Option<Login>,
         addr: SocketAddr,
     ) -> MqttPacket {
+        // The node is drained, e.g. for a rolling upgrade
+        if let Some(server_reference) = self.cache_manager.connection_drain.server_reference() {
+            return response_packet_mqtt_connect_redirect(&self.protocol, server_reference);
+        }
+
         let cluster = self.cache_manager.get_cluster_info();
+        let client_id_regex = self.cache_manager.get_client_id_allowed_regex();
 
         // connect params validator
         if let Some(res) = connect_validator(
             &self.protocol,
             &cluster,
+            client_id_regex.as_ref(),
             &connect,
             &connect_properties,
             &last_will,
@@ -136,8 +154,17 @@ where
             return res;
         }
 
-        // blacklist check
         let (client_id, new_client_id) = get_client_id(&connect.client_id);
+        if new_client_id && !client_id_validator(&cluster, client_id_regex.as_ref(), &client_id) {
+            return response_packet_mqtt_connect_fail(
+                &self.protocol,
+                ConnectReturnCode::ClientIdentifierNotValid,
+                &connect_properties,
+                None,
+            );
+        }
+
+        // blacklist check
         let connection = build_connection(
             connect_id,
             client_id.clone(),
@@ -157,11 +184,20 @@ where
         }
 
         // login check
-        match self
-            .auth_driver
-            .check_login_auth(login, &connect_properties, &addr)
-            .await
-        {
+        let client_cert_common_name = self
+            .connection_manager
+            .get_connect(connect_id)
+            .and_then(|connection| connection.client_cert_common_name);
+        let login_res = if let Some(common_name) = client_cert_common_name {
+            self.auth_driver
+                .check_client_cert_login(common_name, login)
+                .await
+        } else {
+            self.auth_driver
+                .check_login_auth(login, &connect_properties, &addr)
+                .await
+        };
+        match login_res {
             Ok(flag) => {
                 if !flag {
                     return response_packet_mqtt_connect_fail(
@@ -173,9 +209,14 @@ where
                 }
             }
             Err(e) => {
+                let code = if matches!(e, MqttBrokerError::BadAuthenticationMethod(_)) {
+                    ConnectReturnCode::BadAuthenticationMethod
+                } else {
+                    ConnectReturnCode::UnspecifiedError
+                };
                 return response_packet_mqtt_connect_fail(
                     &self.protocol,
-                    ConnectReturnCode::UnspecifiedError,
+                    code,
                     &connect_properties,
                     Some(e.to_string()),
                 );
@@ -226,11 +267,25 @@ where
                 Some(e.to_string()),
             );
         }
+        // A will waiting for its delay is canceled by a connection to the same session, and
+        // published right away when the client starts a new session instead
+        if new_session {
+            self.cache_manager.delayed_last_will.publish_now(&client_id);
+        } else {
+            self.cache_manager.delayed_last_will.cancel(&client_id);
+        }
+
+        pkid_restore_by_session(&self.cache_manager, &session, new_session);
+        // The restored QoS 2 messages still wait for their PUBREL
+        for _ in self.cache_manager.list_client_pkids(&client_id) {
+            connection.recv_qos_message_incr();
+        }
 
         if let Err(e) = save_last_will_message(
             client_id.clone(),
             &last_will,
             &last_will_properties,
+            &self.cache_manager,
             &self.client_pool,
         )
         .await
@@ -255,10 +310,9 @@ where
         let live_time = ConnectionLiveTime {
             protocol: self.protocol.clone(),
             keep_live: connection.keep_alive as u16,
-            heartbeat: now_second(),
+            heartbeat: now_second_monotonic(),
         };
-        self.cache_manager
-            .report_heartbeat(client_id.clone(), live_time);
+        self.cache_manager.report_heartbeat(connect_id, live_time);
 
         self.cache_manager
             .add_session(client_id.clone(), session.clone());
@@ -291,16 +345,19 @@ where
     pub async fn publish(
         &self,
         connect_id: u64,
-        publish: Publish,
+        mut publish: Publish,
         publish_properties: Option<PublishProperties>,
     ) -> Option<MqttPacket> {
         let connection = if let Some(se) = self.cache_manager.connection_info.get(&connect_id) {
             se.clone()
         } else {
-            return Some(response_packet_mqtt_distinct_by_reason(
-                &self.protocol,
-                Some(DisconnectReasonCode::MaximumConnectTime),
-            ));
+            // The connection was closed while the publish was queued, e.g. by a session
+            // takeover, so the client is gone and there is nobody to answer.
+            debug!(
+                "publish of connection {} is dropped, the connection was closed",
+                connect_id
+            );
+            return None;
         };
 
         if let Some(pkg) = publish_validator(
@@ -313,13 +370,23 @@ where
         )
         .await
         {
-            if publish.qos == QoS::AtMostOnce {
+            if publish.qos == QoS::AtMostOnce && !matches!(pkg, MqttPacket::Disconnect(_, _)) {
                 return None;
             } else {
                 return Some(pkg);
             }
         }
 
+        // Explains in the PUBACK what the broker did with the message, for debugging
+        let mut reason_strings = Vec::new();
+
+        // A QoS 2 message to a cluster that only supports QoS 1 is stored and acknowledged as QoS 1
+        let max_qos = self.cache_manager.get_cluster_info().protocol.max_qos;
+        if publish.qos == QoS::ExactlyOnce && max_qos == QoS::AtLeastOnce {
+            publish.qos = QoS::AtLeastOnce;
+            reason_strings.push("qos downgraded from 2 to 1".to_string());
+        }
+
         let is_puback = publish.qos != QoS::ExactlyOnce;
 
         let topic_name = match get_topic_name(
@@ -350,11 +417,18 @@ where
             }
         };
 
+        if is_admin_topic(&topic_name) {
+            return self
+                .admin_publish(&connection, &publish, &publish_properties, &topic_name)
+                .await;
+        }
+
         if !self
             .auth_driver
             .allow_publish(&connection, &topic_name, publish.retain, publish.qos)
             .await
         {
+            record_message_dropped(MessageDropReason::AuthDenied, publish.qos, 1);
             if is_puback {
                 return Some(response_packet_mqtt_puback_fail(
                     &self.protocol,
@@ -374,7 +448,7 @@ where
             }
         }
 
-        let topic = match try_init_topic(
+        let topic = match try_init_topic_with_created(
             &topic_name,
             &self.cache_manager,
             &self.message_storage_adapter,
@@ -382,7 +456,12 @@ where
         )
         .await
         {
-            Ok(tp) => tp,
+            Ok((tp, created)) => {
+                if created {
+                    reason_strings.push("topic auto-created".to_string());
+                }
+                tp
+            }
             Err(e) => {
                 if is_puback {
                     return Some(response_packet_mqtt_puback_fail(
@@ -406,6 +485,7 @@ where
 
         if self.schema_manager.is_check_schema(&topic_name) {
             if let Err(e) = self.schema_manager.validate(&topic_name, &publish.payload) {
+                record_message_dropped(MessageDropReason::Quarantined, publish.qos, 1);
                 if is_puback {
                     return Some(response_packet_mqtt_puback_fail(
                         &self.protocol,
@@ -428,10 +508,34 @@ where
 
         let client_id = connection.client_id.clone();
 
+        // The pkid of a QoS 2 message is saved before the message, so that a redelivery of
+        // the PUBLISH is answered as already stored and the message is stored only once
+        if publish.qos == QoS::ExactlyOnce {
+            if let Err(e) = pkid_save(
+                &self.cache_manager,
+                &self.client_pool,
+                &client_id,
+                publish.pkid,
+            )
+            .await
+            {
+                return Some(response_packet_mqtt_pubrec_fail(
+                    &self.protocol,
+                    &connection,
+                    publish.pkid,
+                    PubRecReason::UnspecifiedError,
+                    Some(e.to_string()),
+                ));
+            }
+            // Counted until the PUBREL releases the pkid
+            connection.recv_qos_message_incr();
+        }
+
         // Persisting retain message data
         match save_retain_message(
             &self.cache_manager,
             &self.client_pool,
+            &self.message_storage_adapter,
             topic_name.clone(),
             &client_id,
             &publish,
@@ -441,6 +545,7 @@ where
         {
             Ok(()) => {}
             Err(e) => {
+                self.release_qos2_pkid(&connection, &publish).await;
                 if is_puback {
                     return Some(response_packet_mqtt_puback_fail(
                         &self.protocol,
@@ -462,7 +567,7 @@ where
         }
 
         // Persisting stores message data
-        let offset = match save_message(
+        let offsets = match save_message(
             &self.message_storage_adapter,
             &self.delay_message_manager,
             &self.cache_manager,
@@ -474,10 +579,9 @@ where
         )
         .await
         {
-            Ok(da) => {
-                format!("{:?}", da)
-            }
+            Ok(da) => da,
             Err(e) => {
+                self.release_qos2_pkid(&connection, &publish).await;
                 if is_puback {
                     return Some(response_packet_mqtt_puback_fail(
                         &self.protocol,
@@ -498,7 +602,74 @@ where
             }
         };
 
-        let user_properties: Vec<(String, String)> = vec![("offset".to_string(), offset)];
+        if let Some(offset) = offsets.as_ref().and_then(|offsets| offsets.last()) {
+            reason_strings.push(format!("offset: {}", offset));
+        }
+
+        // The subscribers of the other brokers only read the storage of their own broker
+        if let Some(offsets) = offsets.clone() {
+            let broker_ids = remote_subscriber_brokers(
+                &self.subscribe_manager,
+                &topic.topic_name,
+                broker_mqtt_conf().broker_id,
+            );
+            if !broker_ids.is_empty() {
+                // The publishers wait while too many forwards are in flight
+                match self.cache_manager.message_forward.acquire().await {
+                    Ok(permit) => {
+                        let cache_manager = self.cache_manager.clone();
+                        let client_pool = self.client_pool.clone();
+                        let message_storage_adapter = self.message_storage_adapter.clone();
+                        let topic = topic.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = forward_message(
+                                &cache_manager,
+                                &client_pool,
+                                &message_storage_adapter,
+                                &topic,
+                                &offsets,
+                                broker_ids,
+                            )
+                            .await
+                            {
+                                error!(
+                                    "Failed to forward the messages of topic [{}], {}",
+                                    topic.topic_name, e
+                                );
+                            }
+                            drop(permit);
+                        });
+                    }
+                    Err(e) => error!(
+                        "Messages of topic [{}] are not forwarded, {}",
+                        topic.topic_name, e
+                    ),
+                }
+            }
+        }
+
+        // The offsets of the stored message, none when the message was not stored
+        let mut user_properties: Vec<(String, String)> = Vec::new();
+        if let Some(offsets) = offsets.as_ref().filter(|offsets| !offsets.is_empty()) {
+            let offsets: Vec<String> = offsets.iter().map(|offset| offset.to_string()).collect();
+            user_properties.push(("offset".to_string(), offsets.join(",")));
+        }
+
+        // Tell the producer to slow down when the slowest subscriber falls too far behind
+        if let Some(offset) = offsets.as_ref().and_then(|offsets| offsets.last()) {
+            let threshold = self
+                .cache_manager
+                .get_cluster_info()
+                .protocol
+                .flow_control_threshold;
+            if let Some(lag) = self.subscribe_manager.flow_control_signal.exceeded_lag(
+                &topic.topic_id,
+                *offset,
+                threshold,
+            ) {
+                user_properties.push((BROKER_LAG_USER_PROPERTY.to_string(), lag.to_string()));
+            }
+        }
 
         self.cache_manager
             .add_topic_alias(connect_id, &topic_name, &publish_properties);
@@ -513,41 +684,14 @@ where
                 };
                 Some(response_packet_mqtt_puback_success(
                     &self.protocol,
+                    &connection,
                     reason_code,
                     publish.pkid,
                     user_properties,
+                    (!reason_strings.is_empty()).then(|| reason_strings.join("; ")),
                 ))
             }
             QoS::ExactlyOnce => {
-                match pkid_save(
-                    &self.cache_manager,
-                    &self.client_pool,
-                    &client_id,
-                    publish.pkid,
-                )
-                .await
-                {
-                    Ok(()) => {}
-                    Err(e) => {
-                        if is_puback {
-                            return Some(response_packet_mqtt_puback_fail(
-                                &self.protocol,
-                                &connection,
-                                publish.pkid,
-                                PubAckReason::UnspecifiedError,
-                                Some(e.to_string()),
-                            ));
-                        } else {
-                            return Some(response_packet_mqtt_pubrec_fail(
-                                &self.protocol,
-                                &connection,
-                                publish.pkid,
-                                PubRecReason::UnspecifiedError,
-                                Some(e.to_string()),
-                            ));
-                        }
-                    }
-                }
                 let reason_code = if path_contain_sub(&topic_name) {
                     PubRecReason::Success
                 } else {
@@ -564,6 +708,99 @@ where
         }
     }
 
+    // The message of a QoS 2 PUBLISH was not stored, the client may send it again
+    async fn release_qos2_pkid(&self, connection: &MQTTConnection, publish: &Publish) {
+        if publish.qos != QoS::ExactlyOnce {
+            return;
+        }
+        match pkid_delete(
+            &self.cache_manager,
+            &self.client_pool,
+            &connection.client_id,
+            publish.pkid,
+        )
+        .await
+        {
+            Ok(()) => {
+                connection.recv_qos_message_decr();
+            }
+            Err(e) => {
+                error!(
+                    "Failed to release pkid {} of client {}, {}",
+                    publish.pkid, connection.client_id, e
+                );
+            }
+        }
+    }
+
+    async fn admin_publish(
+        &self,
+        connection: &MQTTConnection,
+        publish: &Publish,
+        publish_properties: &Option<PublishProperties>,
+        topic_name: &str,
+    ) -> Option<MqttPacket> {
+        let handler = SystemTopicHandler::new(
+            self.cache_manager.clone(),
+            self.subscribe_manager.clone(),
+            self.auth_driver.clone(),
+            self.message_storage_adapter.clone(),
+            self.client_pool.clone(),
+        );
+
+        if !handler.is_allow(connection) {
+            return Some(response_packet_mqtt_distinct_by_reason(
+                &self.protocol,
+                Some(DisconnectReasonCode::NotAuthorized),
+            ));
+        }
+
+        let is_puback = publish.qos != QoS::ExactlyOnce;
+        if let Err(e) = handler
+            .handle(connection, topic_name, &publish.payload, publish_properties)
+            .await
+        {
+            if publish.qos == QoS::AtMostOnce {
+                return None;
+            }
+            if is_puback {
+                return Some(response_packet_mqtt_puback_fail(
+                    &self.protocol,
+                    connection,
+                    publish.pkid,
+                    PubAckReason::UnspecifiedError,
+                    Some(e.to_string()),
+                ));
+            } else {
+                return Some(response_packet_mqtt_pubrec_fail(
+                    &self.protocol,
+                    connection,
+                    publish.pkid,
+                    PubRecReason::UnspecifiedError,
+                    Some(e.to_string()),
+                ));
+            }
+        }
+
+        match publish.qos {
+            QoS::AtMostOnce => None,
+            QoS::AtLeastOnce => Some(response_packet_mqtt_puback_success(
+                &self.protocol,
+                connection,
+                PubAckReason::Success,
+                publish.pkid,
+                Vec::new(),
+                None,
+            )),
+            QoS::ExactlyOnce => Some(response_packet_mqtt_pubrec_success(
+                &self.protocol,
+                PubRecReason::Success,
+                publish.pkid,
+                Vec::new(),
+            )),
+        }
+    }
+
     pub async fn publish_ack(
         &self,
         connect_id: u64,
@@ -573,20 +810,14 @@ where
         if let Some(conn) = self.cache_manager.connection_info.get(&connect_id) {
             let client_id = conn.client_id.clone();
             let pkid = pub_ack.pkid;
-            if let Some(data) = self.cache_manager.get_ack_packet(client_id.clone(), pkid) {
-                match data.sx.send(QosAckPackageData {
+            send_packet_ack(
+                &self.cache_manager,
+                &client_id,
+                QosAckPackageData {
                     ack_type: QosAckPackageType::PubAck,
-                    pkid: pub_ack.pkid,
-                }) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!(
-                            "publish ack send ack manager message error, error message:{}",
-                            e
-                        );
-                    }
-                }
-            }
+                    pkid,
+                },
+            );
         }
 
         None
@@ -601,19 +832,15 @@ where
         if let Some(conn) = self.cache_manager.connection_info.get(&connect_id) {
             let client_id = conn.client_id.clone();
             let pkid = pub_rec.pkid;
-            if let Some(data) = self.cache_manager.get_ack_packet(client_id.clone(), pkid) {
-                match data.sx.send(QosAckPackageData {
+            if send_packet_ack(
+                &self.cache_manager,
+                &client_id,
+                QosAckPackageData {
                     ack_type: QosAckPackageType::PubRec,
-                    pkid: pub_rec.pkid,
-                }) {
-                    Ok(_) => return None,
-                    Err(e) => {
-                        error!(
-                            "publish rec send ack manager message error, error message:{}",
-                            e
-                        );
-                    }
-                }
+                    pkid,
+                },
+            ) {
+                return None;
             }
         }
 
@@ -633,20 +860,14 @@ where
         if let Some(conn) = self.cache_manager.connection_info.get(&connect_id) {
             let client_id = conn.client_id.clone();
             let pkid = pub_comp.pkid;
-            if let Some(data) = self.cache_manager.get_ack_packet(client_id.clone(), pkid) {
-                match data.sx.send(QosAckPackageData {
+            send_packet_ack(
+                &self.cache_manager,
+                &client_id,
+                QosAckPackageData {
                     ack_type: QosAckPackageType::PubComp,
-                    pkid: pub_comp.pkid,
-                }) {
-                    Ok(_) => return None,
-                    Err(e) => {
-                        error!(
-                            "publish comp send ack manager message error, error message:{}",
-                            e
-                        );
-                    }
-                }
-            }
+                    pkid,
+                },
+            );
         }
         None
     }
@@ -720,8 +941,6 @@ where
             }
         }
 
-        connection.recv_qos_message_decr();
-
         response_packet_mqtt_pubcomp_success(&self.protocol, pub_rel.pkid)
     }
 
@@ -742,17 +961,34 @@ where
 
         if let Some(packet) = subscribe_validator(
             &self.protocol,
-            &self.auth_driver,
             &self.cache_manager,
             &self.subscribe_manager,
             &connection,
             &subscribe,
+            &subscribe_properties,
         )
         .await
         {
             return packet;
         }
 
+        // The filters the ACL denies are answered with NotAuthorized, the others are subscribed
+        let allowed = self
+            .auth_driver
+            .allow_subscribe(&connection, &subscribe)
+            .await;
+        if !allowed.iter().any(|allow| *allow) {
+            return response_packet_mqtt_suback(
+                &self.protocol,
+                &connection,
+                subscribe.packet_identifier,
+                vec![SubscribeReasonCode::NotAuthorized; subscribe.filters.len()],
+                None,
+            );
+        }
+        let mut allowed_iter = allowed.iter();
+        subscribe.filters.retain(|_| *allowed_iter.next().unwrap());
+
         let new_subs = is_new_sub(&connection.client_id, &subscribe, &self.subscribe_manager).await;
         process_sub_topic_rewrite(&mut subscribe, &self.cache_manager.topic_rewrite_rule);
 
@@ -801,7 +1037,15 @@ where
 
         let mut return_codes: Vec<SubscribeReasonCode> = Vec::new();
         let cluster_qos = self.cache_manager.get_cluster_info().protocol.max_qos;
-        for filter in subscribe.filters.clone() {
+        let mut filters = subscribe.filters.iter();
+        for allow in allowed {
+            if !allow {
+                return_codes.push(SubscribeReasonCode::NotAuthorized);
+                continue;
+            }
+            let Some(filter) = filters.next() else {
+                break;
+            };
             match min_qos(cluster_qos, filter.qos) {
                 QoS::AtMostOnce => {
                     return_codes.push(SubscribeReasonCode::QoS0);
@@ -833,16 +1077,97 @@ where
             );
         };
 
+        // The session may have been lost, e.g. after the broker recovered from a crash,
+        // so the client is asked to reconnect instead of being kept alive.
+        if self
+            .cache_manager
+            .get_session_info(&connection.client_id)
+            .is_none()
+        {
+            return response_packet_mqtt_distinct_by_reason(
+                &self.protocol,
+                Some(DisconnectReasonCode::UseAnotherServer),
+            );
+        }
+
         let live_time = ConnectionLiveTime {
             protocol: self.protocol.clone(),
             keep_live: connection.keep_alive as u16,
-            heartbeat: now_second(),
+            heartbeat: now_second_monotonic(),
         };
-        self.cache_manager
-            .report_heartbeat(connection.client_id, live_time);
+        self.cache_manager.report_heartbeat(connect_id, live_time);
         response_packet_mqtt_ping_resp()
     }
 
+    pub async fn auth(
+        &self,
+        connect_id: u64,
+        auth: Auth,
+        auth_properties: Option<AuthProperties>,
+    ) -> MqttPacket {
+        let connection = if let Some(se) = self.cache_manager.connection_info.get(&connect_id) {
+            se.clone()
+        } else {
+            return response_packet_mqtt_distinct_by_reason(
+                &self.protocol,
+                Some(DisconnectReasonCode::MaximumConnectTime),
+            );
+        };
+
+        // The built-in methods complete in a single step, so the broker never asks the client
+        // to continue and only a re-authentication can be started by the client.
+        if auth.reason != Some(AuthReason::ReAuthenticate) {
+            return response_packet_mqtt_distinct_by_reason(
+                &self.protocol,
+                Some(DisconnectReasonCode::ProtocolError),
+            );
+        }
+
+        // The re-authentication must use the Authentication Method of the CONNECT
+        let properties = auth_properties.unwrap_or_default();
+        let Some(method) = connection.authentication_method.clone() else {
+            return response_packet_mqtt_distinct_by_reason(
+                &self.protocol,
+                Some(DisconnectReasonCode::ProtocolError),
+            );
+        };
+        if properties.authentication_method.as_ref() != Some(&method) {
+            return response_packet_mqtt_distinct_by_reason(
+                &self.protocol,
+                Some(DisconnectReasonCode::ProtocolError),
+            );
+        }
+
+        match self
+            .auth_driver
+            .re_authenticate(&method, &properties.authentication_data)
+            .await
+        {
+            Ok(true) => {
+                info!(
+                    "client re-authenticated, client_id={}, connect_id={}",
+                    connection.client_id, connect_id
+                );
+                response_packet_mqtt_auth(AuthReason::Success, method)
+            }
+            Ok(false) => response_packet_mqtt_distinct_by_reason(
+                &self.protocol,
+                Some(DisconnectReasonCode::NotAuthorized),
+            ),
+            Err(e) => {
+                warn!(
+                    "re-authentication of connect_id={} failed, {}",
+                    connect_id,
+                    e.to_string()
+                );
+                response_packet_mqtt_distinct_by_reason(
+                    &self.protocol,
+                    Some(DisconnectReasonCode::NotAuthorized),
+                )
+            }
+        }
+    }
+
     pub async fn un_subscribe(
         &self,
         connect_id: u64,
@@ -876,7 +1201,6 @@ where
             &un_subscribe,
             &self.client_pool,
             &self.subscribe_manager,
-            &self.cache_manager,
         )
         .await
         {
@@ -911,14 +1235,19 @@ where
         &self,
         connect_id: u64,
         disconnect: Disconnect,
-        _: Option<DisconnectProperties>,
+        disconnect_properties: Option<DisconnectProperties>,
     ) -> Option<MqttPacket> {
         let connection = if let Some(se) = self.cache_manager.connection_info.get(&connect_id) {
             se.clone()
         } else {
             return None;
         };
+        info!(
+            "client disconnected, client_id={}, connect_id={}, reason_code={:?}",
+            connection.client_id, connect_id, disconnect.reason_code
+        );
 
+        let mut publish_last_will = false;
         if let Some(session) = self.cache_manager.get_session_info(&connection.client_id) {
             st_report_disconnected_event(
                 &self.message_storage_adapter,
@@ -931,6 +1260,36 @@ where
                 disconnect.reason_code,
             )
             .await;
+
+            if session.is_contain_last_will {
+                if is_discard_last_will(&disconnect.reason_code) {
+                    if let Err(e) =
+                        delete_last_will_message(connection.client_id.clone(), &self.client_pool)
+                            .await
+                    {
+                        warn!("delete last will message failed, {}", e.to_string());
+                    }
+                } else {
+                    publish_last_will = true;
+                }
+            }
+        }
+
+        if let Err(e) = update_session_expiry_by_disconnect(
+            &self.cache_manager,
+            &self.client_pool,
+            &connection.client_id,
+            &disconnect_properties,
+        )
+        .await
+        {
+            warn!("update session expiry failed, {}", e.to_string());
+            if let MqttBrokerError::SessionExpiryIntervalChangedFromZero(_) = e {
+                return Some(response_packet_mqtt_distinct_by_reason(
+                    &self.protocol,
+                    Some(DisconnectReasonCode::ProtocolError),
+                ));
+            }
         }
 
         match disconnect_connection(
@@ -942,7 +1301,11 @@ where
         )
         .await
         {
-            Ok(()) => {}
+            Ok(()) => {
+                if publish_last_will {
+                    self.spawn_last_will(connection.client_id.clone());
+                }
+            }
             Err(e) => {
                 warn!("disconnect connection failed, {}", e.to_string());
             }
@@ -950,4 +1313,1015 @@ where
 
         None
     }
+
+    // A v5 client disconnected with DisconnectWithWillMessage, its will is published as after
+    // a lost connection
+    fn spawn_last_will(&self, client_id: String) {
+        let cache_manager = self.cache_manager.clone();
+        let client_pool = self.client_pool.clone();
+        let message_storage_adapter = self.message_storage_adapter.clone();
+        tokio::spawn(async move {
+            if let Err(e) = trigger_last_will(
+                &client_id,
+                &cache_manager,
+                &client_pool,
+                message_storage_adapter,
+            )
+            .await
+            {
+                error!(
+                    "Failed to publish the last will message of client {}, {}",
+                    client_id, e
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, Once};
+
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use delay_message::DelayMessageManager;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::acl::mqtt_acl::{
+        MqttAcl, MqttAclAction, MqttAclPermission, MqttAclResourceType,
+    };
+    use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
+    use metadata_struct::mqtt::session::MqttSession;
+    use metadata_struct::mqtt::subscribe_data::MqttSubscribe;
+    use metadata_struct::mqtt::topic::MqttTopic;
+    use metadata_struct::mqtt::user::MqttUser;
+    use protocol::mqtt::common::{
+        Auth, AuthProperties, AuthReason, Connect, ConnectProperties, ConnectReturnCode,
+        Disconnect, DisconnectReasonCode, Filter, MqttPacket, MqttProtocol, PingReq, PubAckReason,
+        PubCompReason, PubRecReason, PubRel, PubRelReason, Publish, QoS, RetainForwardRule,
+        Subscribe, SubscribeProperties, SubscribeReasonCode,
+    };
+    use schema_register::schema::SchemaRegisterManager;
+    use storage_adapter::memory::MemoryStorageAdapter;
+
+    use super::MqttService;
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::handler::constant::WILDCARD_RESOURCE;
+    use crate::handler::pkid::pkid_restore_by_session;
+    use crate::observability::metrics::snapshot::metrics_snapshot;
+    use crate::security::AuthDriver;
+    use crate::server::connection_manager::ConnectionManager;
+    use crate::server::packet::ResponsePackage;
+    use crate::storage::message::MessageStorage;
+    use crate::subscribe::flow_control_signal::BROKER_LAG_USER_PROPERTY;
+    use crate::subscribe::subscribe_manager::SubscribeManager;
+
+    fn build_service() -> (
+        MqttService<MemoryStorageAdapter>,
+        Arc<CacheManager>,
+        Arc<SubscribeManager>,
+    ) {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        let subscribe_manager = Arc::new(SubscribeManager::new());
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let service = MqttService::new(
+            MqttProtocol::Mqtt5,
+            cache_manager.clone(),
+            Arc::new(ConnectionManager::new(cache_manager.clone())),
+            storage_adapter.clone(),
+            Arc::new(DelayMessageManager::new(unique_id(), 1, storage_adapter)),
+            subscribe_manager.clone(),
+            Arc::new(SchemaRegisterManager::new()),
+            client_pool.clone(),
+            Arc::new(AuthDriver::new(cache_manager.clone(), client_pool)),
+        );
+        (service, cache_manager, subscribe_manager)
+    }
+
+    fn add_connection(cache_manager: &Arc<CacheManager>, connect_id: u64, client_id: &str) {
+        let config = ConnectionConfig {
+            connect_id,
+            client_id: client_id.to_owned(),
+            receive_maximum: 100,
+            max_packet_size: 100,
+            topic_alias_max: 100,
+            request_problem_info: 0,
+            keep_alive: 60,
+            source_ip_addr: "127.0.0.1".to_string(),
+        };
+        cache_manager.add_connection(connect_id, MQTTConnection::new(config));
+    }
+
+    fn build_subscribe(path: &str) -> Subscribe {
+        Subscribe {
+            packet_identifier: 1,
+            filters: vec![Filter {
+                path: path.to_owned(),
+                qos: QoS::AtLeastOnce,
+                nolocal: false,
+                preserve_retain: false,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            }],
+        }
+    }
+
+    fn build_subscribe_properties(identifier: usize) -> Option<SubscribeProperties> {
+        Some(SubscribeProperties {
+            subscription_identifier: Some(identifier),
+            user_properties: Vec::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn connect_in_drain_mode_test() {
+        let (mut service, cache_manager, _) = build_service();
+        cache_manager
+            .connection_drain
+            .start("127.0.0.1:2883".to_string());
+
+        let connect = Connect {
+            keep_alive: 60,
+            client_id: unique_id(),
+            clean_session: true,
+        };
+        let resp = service
+            .connect(
+                1,
+                connect,
+                None,
+                None,
+                None,
+                &None,
+                "127.0.0.1:1883".parse().unwrap(),
+            )
+            .await;
+        let MqttPacket::ConnAck(connack, properties) = resp else {
+            panic!("expected a CONNACK packet, got {:?}", resp);
+        };
+        assert_eq!(connack.code, ConnectReturnCode::UseAnotherServer);
+        assert_eq!(
+            properties.unwrap().server_reference,
+            Some("127.0.0.1:2883".to_string())
+        );
+        assert!(cache_manager.connection_info.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ping_unknown_session_test() {
+        let (service, cache_manager, _) = build_service();
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+        cache_manager.add_session(
+            client_id.clone(),
+            MqttSession::new(client_id.clone(), 60, false, None),
+        );
+
+        let resp = service.ping(connect_id, PingReq).await;
+        assert!(matches!(resp, MqttPacket::PingResp(_)));
+        assert!(!ResponsePackage::new(connect_id, resp).close_after_send);
+
+        // the session is lost, e.g. the broker recovered from a crash
+        cache_manager.remove_session(&client_id);
+        let resp = service.ping(connect_id, PingReq).await;
+        if let MqttPacket::Disconnect(disconnect, _) = &resp {
+            assert_eq!(
+                disconnect.reason_code,
+                Some(DisconnectReasonCode::UseAnotherServer)
+            );
+        } else {
+            panic!("expected a DISCONNECT packet, got {:?}", resp);
+        }
+        assert!(ResponsePackage::new(connect_id, resp).close_after_send);
+    }
+
+    #[tokio::test]
+    async fn subscription_identifier_out_of_range_test() {
+        let (service, cache_manager, _) = build_service();
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+
+        let resp = service
+            .subscribe(
+                connect_id,
+                build_subscribe("/test/sub_id/range"),
+                build_subscribe_properties(268_435_456),
+            )
+            .await;
+        if let MqttPacket::Disconnect(disconnect, _) = &resp {
+            assert_eq!(
+                disconnect.reason_code,
+                Some(DisconnectReasonCode::ProtocolError)
+            );
+        } else {
+            panic!("expected a DISCONNECT packet, got {:?}", resp);
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_not_authorized_per_filter_test() {
+        let (service, cache_manager, _) = build_service();
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+        cache_manager.add_acl(MqttAcl {
+            resource_type: MqttAclResourceType::ClientId,
+            resource_name: client_id.clone(),
+            topic: "sensors/#".to_string(),
+            ip: WILDCARD_RESOURCE.to_string(),
+            action: MqttAclAction::Subscribe,
+            permission: MqttAclPermission::Deny,
+        });
+
+        let mut subscribe = build_subscribe("sensors/+/temp");
+        let mut filter = subscribe.filters[0].clone();
+        filter.path = "alerts/room1".to_string();
+        subscribe.filters.push(filter);
+        let connection = cache_manager.get_connection(connect_id).unwrap();
+        assert_eq!(
+            service
+                .auth_driver
+                .allow_subscribe(&connection, &subscribe)
+                .await,
+            vec![false, true]
+        );
+
+        // every filter denied, nothing is subscribed
+        subscribe.filters[1].path = "sensors/room1/humidity".to_string();
+        let resp = service.subscribe(connect_id, subscribe, None).await;
+        if let MqttPacket::SubAck(sub_ack, _) = &resp {
+            assert_eq!(
+                sub_ack.return_codes,
+                vec![
+                    SubscribeReasonCode::NotAuthorized,
+                    SubscribeReasonCode::NotAuthorized
+                ]
+            );
+        } else {
+            panic!("expected a SUBACK packet, got {:?}", resp);
+        }
+    }
+
+    fn add_share_subscribe(subscribe_manager: &Arc<SubscribeManager>, client_id: &str, path: &str) {
+        let subscribe = build_subscribe(path);
+        subscribe_manager.add_subscribe(MqttSubscribe {
+            client_id: client_id.to_string(),
+            path: path.to_string(),
+            cluster_name: unique_id(),
+            broker_id: 1,
+            protocol: MqttProtocol::Mqtt5,
+            filter: subscribe.filters[0].clone(),
+            pkid: subscribe.packet_identifier,
+            subscribe_properties: None,
+        });
+    }
+
+    #[tokio::test]
+    async fn share_subscription_group_quota_test() {
+        let (service, cache_manager, subscribe_manager) = build_service();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.max_shared_subscription_groups = 1;
+        cache_manager.set_cluster_info(cluster);
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+
+        let other_client_id = unique_id();
+        add_share_subscribe(&subscribe_manager, &other_client_id, "$share/g1/test/quota");
+        assert_eq!(subscribe_manager.share_group_num(), 1);
+
+        let resp = service
+            .subscribe(connect_id, build_subscribe("$share/g2/test/quota"), None)
+            .await;
+        if let MqttPacket::SubAck(sub_ack, _) = &resp {
+            assert_eq!(
+                sub_ack.return_codes,
+                vec![SubscribeReasonCode::QuotaExceeded]
+            );
+        } else {
+            panic!("expected a SUBACK packet, got {:?}", resp);
+        }
+
+        subscribe_manager.remove_subscribe(&other_client_id, "$share/g1/test/quota");
+        assert_eq!(subscribe_manager.share_group_num(), 0);
+    }
+
+    #[tokio::test]
+    async fn share_subscription_group_member_quota_test() {
+        let (service, cache_manager, subscribe_manager) = build_service();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.max_shared_subscription_group_members = 2;
+        cache_manager.set_cluster_info(cluster);
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+
+        // the subscriptions of a client in the group count as one member
+        let member_1 = unique_id();
+        let member_2 = unique_id();
+        add_share_subscribe(&subscribe_manager, &member_1, "$share/g1/test/quota/1");
+        add_share_subscribe(&subscribe_manager, &member_1, "$share/g1/test/quota/2");
+        add_share_subscribe(&subscribe_manager, &member_2, "$share/g1/test/quota/1");
+        assert_eq!(subscribe_manager.share_group_member_num("g1"), 2);
+
+        let resp = service
+            .subscribe(connect_id, build_subscribe("$share/g1/test/quota/1"), None)
+            .await;
+        if let MqttPacket::SubAck(sub_ack, _) = &resp {
+            assert_eq!(
+                sub_ack.return_codes,
+                vec![SubscribeReasonCode::QuotaExceeded]
+            );
+        } else {
+            panic!("expected a SUBACK packet, got {:?}", resp);
+        }
+
+        subscribe_manager.remove_subscribe(&member_1, "$share/g1/test/quota/1");
+        assert_eq!(subscribe_manager.share_group_member_num("g1"), 2);
+        subscribe_manager.remove_subscribe(&member_1, "$share/g1/test/quota/2");
+        assert_eq!(subscribe_manager.share_group_member_num("g1"), 1);
+        assert!(!subscribe_manager.is_share_group_member("g1", &member_1));
+    }
+
+    #[tokio::test]
+    async fn connect_bad_authentication_method_test() {
+        let (mut service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let connect = Connect {
+            keep_alive: 60,
+            client_id: unique_id(),
+            clean_session: true,
+        };
+        let connect_properties = Some(ConnectProperties {
+            authentication_method: Some("UNKNOWN".to_string()),
+            ..Default::default()
+        });
+        let resp = service
+            .connect(
+                1,
+                connect,
+                connect_properties,
+                None,
+                None,
+                &None,
+                "127.0.0.1:1883".parse().unwrap(),
+            )
+            .await;
+        if let MqttPacket::ConnAck(conn_ack, _) = &resp {
+            assert_eq!(conn_ack.code, ConnectReturnCode::BadAuthenticationMethod);
+        } else {
+            panic!("expected a CONNACK packet, got {:?}", resp);
+        }
+    }
+
+    #[tokio::test]
+    async fn re_authenticate_test() {
+        let (service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        cache_manager.add_user(MqttUser {
+            username: "lobo".to_string(),
+            password: "pwd123".to_string(),
+            is_superuser: false,
+        });
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+        cache_manager
+            .connection_info
+            .get_mut(&connect_id)
+            .unwrap()
+            .authentication_method = Some("PLAIN".to_string());
+
+        let build_auth = |method: &str, data: &'static [u8]| {
+            (
+                Auth {
+                    reason: Some(AuthReason::ReAuthenticate),
+                },
+                Some(AuthProperties {
+                    authentication_method: Some(method.to_string()),
+                    authentication_data: Some(Bytes::from_static(data)),
+                    ..Default::default()
+                }),
+            )
+        };
+
+        let (auth, properties) = build_auth("PLAIN", b"\0lobo\0pwd123");
+        let resp = service.auth(connect_id, auth, properties).await;
+        if let MqttPacket::Auth(auth, _) = &resp {
+            assert_eq!(auth.reason, Some(AuthReason::Success));
+        } else {
+            panic!("expected an AUTH packet, got {:?}", resp);
+        }
+        // the session stays open
+        assert!(cache_manager.connection_info.contains_key(&connect_id));
+
+        // another method than the one of the CONNECT
+        let (auth, properties) = build_auth("SCRAM-SHA-256", b"");
+        let resp = service.auth(connect_id, auth, properties).await;
+        if let MqttPacket::Disconnect(disconnect, _) = &resp {
+            assert_eq!(
+                disconnect.reason_code,
+                Some(DisconnectReasonCode::ProtocolError)
+            );
+        } else {
+            panic!("expected a DISCONNECT packet, got {:?}", resp);
+        }
+
+        let (auth, properties) = build_auth("PLAIN", b"\0lobo\0pwd");
+        let resp = service.auth(connect_id, auth, properties).await;
+        if let MqttPacket::Disconnect(disconnect, _) = &resp {
+            assert_eq!(
+                disconnect.reason_code,
+                Some(DisconnectReasonCode::NotAuthorized)
+            );
+        } else {
+            panic!("expected a DISCONNECT packet, got {:?}", resp);
+        }
+    }
+
+    #[tokio::test]
+    async fn qos2_redelivery_after_reconnect_test() {
+        let (service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let client_id = unique_id();
+        cache_manager.add_session(
+            client_id.clone(),
+            MqttSession::new(client_id.clone(), 60, false, None),
+        );
+        add_connection(&cache_manager, 1, &client_id);
+
+        let topic_name = "/test/qos2/redelivery";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+
+        let mut publish = Publish {
+            dup: false,
+            qos: QoS::ExactlyOnce,
+            pkid: 7,
+            retain: false,
+            topic: Bytes::from(topic_name),
+            payload: Bytes::from("exactly once"),
+        };
+        let resp = service.publish(1, publish.clone(), None).await;
+        assert!(matches!(resp, Some(MqttPacket::PubRec(_, _))));
+
+        // the PUBREC is lost and the connection closes, the pkids in flight are saved with the
+        // session, which the broker then loses, e.g. the client reconnects to another broker
+        let mut session = cache_manager.get_session_info(&client_id).unwrap();
+        session.qos2_pkids = cache_manager.list_client_pkids(&client_id);
+        assert_eq!(session.qos2_pkids, vec![7]);
+        cache_manager.remove_connection(1);
+        cache_manager.remove_session(&client_id);
+        assert!(cache_manager.list_client_pkids(&client_id).is_empty());
+
+        pkid_restore_by_session(&cache_manager, &session, false);
+        cache_manager.add_session(client_id.clone(), session.clone());
+        add_connection(&cache_manager, 2, &client_id);
+
+        publish.dup = true;
+        let resp = service.publish(2, publish, None).await;
+        if let Some(MqttPacket::PubRec(pub_rec, _)) = &resp {
+            assert_eq!(pub_rec.reason, Some(PubRecReason::Success));
+        } else {
+            panic!("expected a PUBREC packet, got {:?}", resp);
+        }
+
+        let message_storage = MessageStorage::new(service.message_storage_adapter.clone());
+        let records = message_storage
+            .read_topic_message(&topic.topic_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+
+        // a new session starts without the pkids of the previous one
+        pkid_restore_by_session(&cache_manager, &session, true);
+        assert!(cache_manager.list_client_pkids(&client_id).is_empty());
+    }
+
+    #[tokio::test]
+    async fn qos2_exactly_once_test() {
+        let (service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let client_id = unique_id();
+        add_connection(&cache_manager, 1, &client_id);
+
+        let topic_name = "/test/qos2/exactly_once";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+
+        let mut publish = Publish {
+            dup: false,
+            qos: QoS::ExactlyOnce,
+            pkid: 9,
+            retain: false,
+            topic: Bytes::from(topic_name),
+            payload: Bytes::from("exactly once"),
+        };
+        let resp = service.publish(1, publish.clone(), None).await;
+        assert!(matches!(resp, Some(MqttPacket::PubRec(_, _))));
+        // PUBREC sent, the PUBREL is awaited
+        assert_eq!(cache_manager.list_client_pkids(&client_id), vec![9]);
+
+        // the PUBREC is lost and the client sends the PUBLISH again
+        publish.dup = true;
+        let resp = service.publish(1, publish.clone(), None).await;
+        let Some(MqttPacket::PubRec(pub_rec, _)) = resp else {
+            panic!("expected a PUBREC packet, got {:?}", resp);
+        };
+        assert_eq!(pub_rec.reason, Some(PubRecReason::Success));
+
+        let pub_rel = PubRel {
+            pkid: 9,
+            reason: Some(PubRelReason::Success),
+        };
+        let resp = service.publish_rel(1, pub_rel.clone(), None).await;
+        let MqttPacket::PubComp(pub_comp, _) = resp else {
+            panic!("expected a PUBCOMP packet, got {:?}", resp);
+        };
+        assert_eq!(pub_comp.pkid, 9);
+        assert_eq!(pub_comp.reason, Some(PubCompReason::Success));
+        assert!(cache_manager.list_client_pkids(&client_id).is_empty());
+
+        // a PUBREL sent again once the flow is complete
+        let resp = service.publish_rel(1, pub_rel, None).await;
+        let MqttPacket::PubComp(pub_comp, _) = resp else {
+            panic!("expected a PUBCOMP packet, got {:?}", resp);
+        };
+        assert_eq!(
+            pub_comp.reason,
+            Some(PubCompReason::PacketIdentifierNotFound)
+        );
+
+        let message_storage = MessageStorage::new(service.message_storage_adapter.clone());
+        let records = message_storage
+            .read_topic_message(&topic.topic_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+
+        // the pkid is free again and carries a new message
+        publish.dup = false;
+        publish.payload = Bytes::from("exactly once again");
+        let resp = service.publish(1, publish, None).await;
+        assert!(matches!(resp, Some(MqttPacket::PubRec(_, _))));
+        let records = message_storage
+            .read_topic_message(&topic.topic_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn qos2_out_of_order_pub_rel_test() {
+        let (service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let client_id = unique_id();
+        add_connection(&cache_manager, 1, &client_id);
+
+        let topic_name = "/test/qos2/out_of_order";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+
+        for pkid in [1, 2, 3] {
+            let publish = Publish {
+                dup: false,
+                qos: QoS::ExactlyOnce,
+                pkid,
+                retain: false,
+                topic: Bytes::from(topic_name),
+                payload: Bytes::from(format!("message-{}", pkid)),
+            };
+            let resp = service.publish(1, publish, None).await;
+            assert!(matches!(resp, Some(MqttPacket::PubRec(_, _))));
+        }
+        assert_eq!(cache_manager.list_client_pkids(&client_id), vec![1, 2, 3]);
+        let connection = cache_manager.get_connection(1).unwrap();
+        assert_eq!(connection.get_recv_qos_message(), 3);
+
+        // the PUBRELs come in another order than the PUBLISHes, each only releases its own pkid
+        for (pkid, in_flight) in [(3, vec![1, 2]), (1, vec![2]), (2, vec![])] {
+            let pub_rel = PubRel {
+                pkid,
+                reason: Some(PubRelReason::Success),
+            };
+            let resp = service.publish_rel(1, pub_rel, None).await;
+            let MqttPacket::PubComp(pub_comp, _) = resp else {
+                panic!("expected a PUBCOMP packet, got {:?}", resp);
+            };
+            assert_eq!(pub_comp.pkid, pkid);
+            assert_eq!(pub_comp.reason, Some(PubCompReason::Success));
+            assert_eq!(cache_manager.list_client_pkids(&client_id), in_flight);
+        }
+        assert_eq!(connection.get_recv_qos_message(), 0);
+
+        // a PUBREL of a pkid that was never published
+        let pub_rel = PubRel {
+            pkid: 4,
+            reason: Some(PubRelReason::Success),
+        };
+        let resp = service.publish_rel(1, pub_rel, None).await;
+        let MqttPacket::PubComp(pub_comp, _) = resp else {
+            panic!("expected a PUBCOMP packet, got {:?}", resp);
+        };
+        assert_eq!(
+            pub_comp.reason,
+            Some(PubCompReason::PacketIdentifierNotFound)
+        );
+        assert_eq!(connection.get_recv_qos_message(), 0);
+
+        let message_storage = MessageStorage::new(service.message_storage_adapter.clone());
+        let records = message_storage
+            .read_topic_message(&topic.topic_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 3);
+    }
+
+    fn build_admin_publish(action: &str) -> Publish {
+        Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid: 1,
+            retain: false,
+            topic: Bytes::from(format!("$robustmq/admin/{}", action)),
+            payload: Bytes::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_flush_cache_test() {
+        let (service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+        let user = MqttUser {
+            username: unique_id(),
+            password: unique_id(),
+            is_superuser: true,
+        };
+        cache_manager.add_user(user.clone());
+        cache_manager
+            .connection_info
+            .get_mut(&connect_id)
+            .unwrap()
+            .login_success(user.username);
+
+        let topic_name = "/test/admin/flush_cache";
+        cache_manager.add_topic(
+            topic_name,
+            &MqttTopic::new(unique_id(), unique_id(), topic_name.to_string()),
+        );
+        assert!(!cache_manager.topic_info.is_empty());
+
+        let resp = service
+            .publish(connect_id, build_admin_publish("flush_cache"), None)
+            .await;
+        if let Some(MqttPacket::PubAck(pub_ack, _)) = &resp {
+            assert_eq!(pub_ack.reason, Some(PubAckReason::Success));
+        } else {
+            panic!("expected a PUBACK packet, got {:?}", resp);
+        }
+        assert!(cache_manager.topic_info.is_empty());
+    }
+
+    #[tokio::test]
+    async fn admin_publish_not_authorized_test() {
+        let (service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+        let topic_name = "/test/admin/not_authorized";
+        cache_manager.add_topic(
+            topic_name,
+            &MqttTopic::new(unique_id(), unique_id(), topic_name.to_string()),
+        );
+
+        let resp = service
+            .publish(connect_id, build_admin_publish("flush_cache"), None)
+            .await;
+        if let Some(MqttPacket::Disconnect(disconnect, _)) = &resp {
+            assert_eq!(
+                disconnect.reason_code,
+                Some(DisconnectReasonCode::NotAuthorized)
+            );
+        } else {
+            panic!("expected a DISCONNECT packet, got {:?}", resp);
+        }
+        assert_eq!(cache_manager.topic_info.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn publish_broker_lag_test() {
+        let (service, cache_manager, subscribe_manager) = build_service();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.flow_control_threshold = 100;
+        cache_manager.set_cluster_info(cluster);
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+
+        let topic_name = "/test/flow_control/lag";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+
+        // a slow subscriber that never gets past the first message
+        subscribe_manager.add_topic_subscribe(topic_name, "slow_client", topic_name);
+        subscribe_manager
+            .flow_control_signal
+            .report_offset(&topic.topic_id, "slow_client", 0);
+
+        let mut lags = Vec::new();
+        for i in 0..1000 {
+            let publish = Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                pkid: (i % 100 + 1) as u16,
+                retain: false,
+                topic: Bytes::from(topic_name),
+                payload: Bytes::from(format!("message-{}", i)),
+            };
+            let resp = service.publish(connect_id, publish, None).await;
+            let Some(MqttPacket::PubAck(_, Some(properties))) = resp else {
+                panic!("expected a PUBACK packet, got {:?}", resp);
+            };
+            if let Some((_, lag)) = properties
+                .user_properties
+                .iter()
+                .find(|(key, _)| key == BROKER_LAG_USER_PROPERTY)
+            {
+                lags.push(lag.parse::<u64>().unwrap());
+            }
+        }
+
+        assert_eq!(lags.len(), 900);
+        assert_eq!(lags.first(), Some(&101));
+        assert_eq!(lags.last(), Some(&1000));
+        assert!(lags.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[tokio::test]
+    async fn publish_offset_user_property_test() {
+        let (service, cache_manager, subscribe_manager) = build_service();
+        let client_id = unique_id();
+        let connect_id = 1;
+        add_connection(&cache_manager, connect_id, &client_id);
+
+        let offset_property = |resp: Option<MqttPacket>| {
+            let Some(MqttPacket::PubAck(_, Some(properties))) = resp else {
+                panic!("expected a PUBACK packet, got {:?}", resp);
+            };
+            properties
+                .user_properties
+                .into_iter()
+                .find(|(key, _)| key == "offset")
+                .map(|(_, value)| value)
+        };
+        let build_publish = |topic_name: &str, pkid: u16| Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid,
+            retain: false,
+            topic: Bytes::from(topic_name.to_string()),
+            payload: Bytes::from(format!("message-{}", pkid)),
+        };
+
+        // the offset of a stored message
+        let topic_name = "/test/offset/stored";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+        subscribe_manager.add_topic_subscribe(topic_name, "subscriber", topic_name);
+        for (pkid, offset) in [(1, "0"), (2, "1")] {
+            let resp = service
+                .publish(connect_id, build_publish(topic_name, pkid), None)
+                .await;
+            assert_eq!(offset_property(resp), Some(offset.to_string()));
+        }
+
+        // no offset for a message that is not stored
+        let mut cluster = build_default_cluster_config();
+        cluster.offline_message.enable = false;
+        cache_manager.set_cluster_info(cluster);
+        let topic_name = "/test/offset/dropped";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+        let resp = service
+            .publish(connect_id, build_publish(topic_name, 3), None)
+            .await;
+        assert_eq!(offset_property(resp), None);
+    }
+
+    #[tokio::test]
+    async fn publish_dropped_no_subscribers_metrics_test() {
+        let (service, cache_manager, _) = build_service();
+        let mut cluster = build_default_cluster_config();
+        cluster.offline_message.enable = false;
+        cache_manager.set_cluster_info(cluster);
+
+        // the metrics are global, other tests may record them concurrently
+        let before = metrics_snapshot();
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        cache_manager.add_session(
+            client_id.clone(),
+            MqttSession::new(client_id.clone(), 60, false, None),
+        );
+        add_connection(&cache_manager, connect_id, &client_id);
+        assert!(metrics_snapshot().client_connections >= 1);
+
+        let topic_name = "/test/metrics/no_subscribers";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid: 1,
+            retain: false,
+            topic: Bytes::from(topic_name),
+            payload: Bytes::from("dropped"),
+        };
+        let resp = service.publish(connect_id, publish, None).await;
+        assert!(matches!(resp, Some(MqttPacket::PubAck(_, _))));
+
+        let after = metrics_snapshot();
+        assert!(after.messages_dropped > before.messages_dropped);
+    }
+
+    #[tokio::test]
+    async fn publish_puback_reason_string_test() {
+        let (service, cache_manager, _) = build_service();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.max_qos = QoS::AtLeastOnce;
+        cache_manager.set_cluster_info(cluster);
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        let config = ConnectionConfig {
+            connect_id,
+            client_id: client_id.clone(),
+            receive_maximum: 100,
+            max_packet_size: 100,
+            topic_alias_max: 100,
+            request_problem_info: 1,
+            keep_alive: 60,
+            source_ip_addr: "127.0.0.1".to_string(),
+        };
+        cache_manager.add_connection(connect_id, MQTTConnection::new(config));
+
+        let topic_name = "/test/puback/reason_string";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::ExactlyOnce,
+            pkid: 1,
+            retain: false,
+            topic: Bytes::from(topic_name),
+            payload: Bytes::from("downgraded"),
+        };
+        let resp = service.publish(connect_id, publish, None).await;
+        let Some(MqttPacket::PubAck(_, Some(properties))) = resp else {
+            panic!("expected a PUBACK packet, got {:?}", resp);
+        };
+        // the topic was already known, so it was not auto-created
+        assert_eq!(
+            properties.reason_string,
+            Some("qos downgraded from 2 to 1; offset: 0".to_string())
+        );
+
+        // no reason string unless the client requested the problem information
+        add_connection(&cache_manager, connect_id, &client_id);
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid: 2,
+            retain: false,
+            topic: Bytes::from(topic_name),
+            payload: Bytes::from("no problem info"),
+        };
+        let resp = service.publish(connect_id, publish, None).await;
+        let Some(MqttPacket::PubAck(_, Some(properties))) = resp else {
+            panic!("expected a PUBACK packet, got {:?}", resp);
+        };
+        assert_eq!(properties.reason_string, None);
+    }
+
+    // Keeps the log records, so that tests can assert on what the broker logged
+    struct CaptureLogger {
+        records: Mutex<Vec<String>>,
+    }
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                self.records.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE_LOGGER: CaptureLogger = CaptureLogger {
+        records: Mutex::new(Vec::new()),
+    };
+
+    fn init_capture_logger() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CAPTURE_LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Info);
+        });
+    }
+
+    #[tokio::test]
+    async fn disconnect_reason_log_test() {
+        init_capture_logger();
+        let (service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        cache_manager.add_session(
+            client_id.clone(),
+            MqttSession::new(client_id.clone(), 60, false, None),
+        );
+        add_connection(&cache_manager, connect_id, &client_id);
+
+        let disconnect = Disconnect {
+            reason_code: Some(DisconnectReasonCode::AdministrativeAction),
+        };
+        let resp = service.disconnect(connect_id, disconnect, None).await;
+        assert!(resp.is_none());
+
+        let records = CAPTURE_LOGGER.records.lock().unwrap();
+        let record = records
+            .iter()
+            .find(|record| {
+                record.starts_with("client disconnected")
+                    && record.contains(&format!("client_id={}", client_id))
+            })
+            .expect("expected a disconnect log record");
+        assert!(record.contains("connect_id=1"));
+        assert!(record.contains("reason_code=Some(AdministrativeAction)"));
+    }
+
+    #[tokio::test]
+    async fn publish_after_connection_removed_test() {
+        let (service, cache_manager, _) = build_service();
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let client_id = unique_id();
+        let connect_id = 1;
+        cache_manager.add_session(
+            client_id.clone(),
+            MqttSession::new(client_id.clone(), 60, false, None),
+        );
+        add_connection(&cache_manager, connect_id, &client_id);
+
+        let topic_name = "/test/publish/removed";
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.to_string());
+        cache_manager.add_topic(topic_name, &topic);
+
+        // the connection is taken over just before the publish is processed
+        cache_manager.remove_connection(connect_id);
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid: 1,
+            retain: false,
+            topic: Bytes::from(topic_name),
+            payload: Bytes::from("late"),
+        };
+        let resp = service.publish(connect_id, publish, None).await;
+        assert!(resp.is_none());
+    }
 }
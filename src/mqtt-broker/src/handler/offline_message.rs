@@ -19,11 +19,15 @@ use super::{
     delay_message::{decode_delay_topic, is_delay_message},
     error::MqttBrokerError,
     message::build_message_expire,
+    topic::restore_reclaimed_topic_shard,
 };
 use crate::{
-    observability::metrics::packets::record_messages_dropped_no_subscribers_metrics,
-    storage::message::MessageStorage, subscribe::subscribe_manager::SubscribeManager,
+    observability::metrics::publish::{record_message_dropped, MessageDropReason},
+    security::encryption::encrypt_message_payload,
+    storage::message::MessageStorage,
+    subscribe::subscribe_manager::SubscribeManager,
 };
+use common_base::tools::now_second;
 use delay_message::DelayMessageManager;
 use metadata_struct::mqtt::{message::MqttMessage, topic::MqttTopic};
 use protocol::mqtt::common::{Publish, PublishProperties};
@@ -43,37 +47,54 @@ pub async fn save_message<S>(
     subscribe_manager: &Arc<SubscribeManager>,
     client_id: &str,
     topic: &MqttTopic,
-) -> Result<Option<String>, MqttBrokerError>
+) -> Result<Option<Vec<u64>>, MqttBrokerError>
 where
     S: StorageAdapter + Sync + Send + 'static + Clone,
 {
+    cache_manager.report_topic_publish(&topic.topic_name, now_second());
+
     // If Topic is not subscribed and offline messaging is not enabled. The message is not saved.
     if !is_exist_subscribe(subscribe_manager, &topic.topic_name)
         && !cache_manager.get_cluster_info().offline_message.enable
     {
-        record_messages_dropped_no_subscribers_metrics(publish.qos);
+        record_message_dropped(MessageDropReason::NoSubscriber, publish.qos, 1);
         return Ok(None);
     }
 
+    let delay_topic = if is_delay_message(&topic.topic_name) {
+        let delay_topic = decode_delay_topic(&topic.topic_name)?;
+        if delay_topic.is_none() {
+            return Err(MqttBrokerError::DelayPublishDecodeTopicNameFail(
+                topic.topic_name.clone(),
+            ));
+        }
+        delay_topic
+    } else {
+        None
+    };
+
+    // The delayed messages are encrypted with the key of the topic they are delivered to
+    let target_topic_name = delay_topic
+        .as_ref()
+        .map(|delay_topic| delay_topic.topic.as_str())
+        .unwrap_or(&topic.topic_name);
+    let mut publish = publish.clone();
+    publish.payload = encrypt_message_payload(cache_manager, target_topic_name, &publish.payload)?;
+
     let message_storage = MessageStorage::new(message_storage_adapter.clone());
     let message_expire = build_message_expire(cache_manager, publish_properties);
     let offset = if let Some(record) =
-        MqttMessage::build_record(client_id, publish, publish_properties, message_expire)
+        MqttMessage::build_record(client_id, &publish, publish_properties, message_expire)
     {
-        if is_delay_message(&topic.topic_name) {
-            let delay_topic = decode_delay_topic(&topic.topic_name)?;
-            if delay_topic.is_none() {
-                return Err(MqttBrokerError::DelayPublishDecodeTopicNameFail(
-                    topic.topic_name.clone(),
-                ));
-            }
+        if delay_topic.is_some() {
             delay_message_manager.send_delay_message(record).await?;
             return Ok(None);
         } else {
+            restore_reclaimed_topic_shard(cache_manager, message_storage_adapter, topic).await?;
             let offsets = message_storage
                 .append_topic_message(&topic.topic_id, vec![record])
                 .await?;
-            Some(format!("{:?}", offsets))
+            Some(offsets)
         }
     } else {
         None
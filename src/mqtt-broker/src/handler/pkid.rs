@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use common_base::config::broker_mqtt::broker_mqtt_conf;
@@ -19,12 +20,49 @@ use grpc_clients::placement::inner::call::{
     delete_idempotent_data, exists_idempotent_data, set_idempotent_data,
 };
 use grpc_clients::pool::ClientPool;
+use metadata_struct::mqtt::session::MqttSession;
 use protocol::placement_center::placement_center_inner::{
     DeleteIdempotentDataRequest, ExistsIdempotentDataRequest, SetIdempotentDataRequest,
 };
 
 use super::cache::CacheManager;
 use super::error::MqttBrokerError;
+use crate::storage::session::SessionStorage;
+
+/// The Packet Identifiers in flight to a client. Identifiers are handed out in turn
+/// from 1 to 65535, skipping the ones still in use, so a freed identifier is not
+/// reused right away. None is returned when all 65535 are in use.
+#[derive(Clone, Default)]
+pub struct PkidAllocator {
+    in_use: HashSet<u16>,
+    last: u16,
+}
+
+impl PkidAllocator {
+    pub fn allocate(&mut self) -> Option<u16> {
+        if self.in_use.len() >= u16::MAX as usize {
+            return None;
+        }
+        loop {
+            self.last = if self.last == u16::MAX {
+                1
+            } else {
+                self.last + 1
+            };
+            if self.in_use.insert(self.last) {
+                return Some(self.last);
+            }
+        }
+    }
+
+    pub fn release(&mut self, pkid: u16) {
+        self.in_use.remove(&pkid);
+    }
+
+    pub fn in_use_num(&self) -> usize {
+        self.in_use.len()
+    }
+}
 
 pub async fn pkid_save(
     cache_manager: &Arc<CacheManager>,
@@ -114,16 +152,115 @@ pub async fn pkid_delete(
     Ok(())
 }
 
+/// Saves the QoS 2 pkids in flight with the session when the connection closes. The pkids
+/// persisted with client_pkid_persistent are already kept by the placement center.
+pub async fn pkid_save_by_session(
+    cache_manager: &Arc<CacheManager>,
+    client_pool: &Arc<ClientPool>,
+    client_id: &str,
+) -> Result<(), MqttBrokerError> {
+    if cache_manager
+        .get_cluster_info()
+        .protocol
+        .client_pkid_persistent
+    {
+        return Ok(());
+    }
+    let Some(mut session) = cache_manager.get_session_info(client_id) else {
+        return Ok(());
+    };
+    let pkids = cache_manager.list_client_pkids(client_id);
+    if pkids == session.qos2_pkids {
+        return Ok(());
+    }
+
+    session.qos2_pkids = pkids;
+    let session_storage = SessionStorage::new(client_pool.clone());
+    if let Err(e) = session_storage
+        .set_session(client_id.to_owned(), &session)
+        .await
+    {
+        return Err(MqttBrokerError::CommonError(e.to_string()));
+    }
+    cache_manager.add_session(client_id.to_owned(), session);
+    Ok(())
+}
+
+/// Restores the QoS 2 pkids saved with a resumed session, a new session starts without any.
+pub fn pkid_restore_by_session(
+    cache_manager: &Arc<CacheManager>,
+    session: &MqttSession,
+    new_session: bool,
+) {
+    cache_manager.clear_client_pkids(&session.client_id);
+    if new_session {
+        return;
+    }
+    for pkid in session.qos2_pkids.iter() {
+        cache_manager.add_client_pkid(&session.client_id, *pkid);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::Arc;
+    use std::time::Duration;
 
     use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
     use grpc_clients::pool::ClientPool;
+    use tokio::time::{sleep, timeout};
 
-    use super::{pkid_delete, pkid_exists, pkid_save};
+    use super::{pkid_delete, pkid_exists, pkid_save, PkidAllocator};
     use crate::handler::cache::CacheManager;
 
+    #[test]
+    pub fn pkid_allocator_test() {
+        let mut allocator = PkidAllocator::default();
+        assert_eq!(allocator.allocate(), Some(1));
+        assert_eq!(allocator.allocate(), Some(2));
+        allocator.release(1);
+        assert_eq!(allocator.allocate(), Some(3));
+
+        for _ in 3..u16::MAX {
+            assert!(allocator.allocate().is_some());
+        }
+        // 2..=65535 are in use, only the freed 1 is left after the wrap around
+        assert_eq!(allocator.in_use_num(), 65534);
+        assert_eq!(allocator.allocate(), Some(1));
+        assert_eq!(allocator.allocate(), None);
+
+        allocator.release(500);
+        assert_eq!(allocator.allocate(), Some(500));
+    }
+
+    #[tokio::test]
+    pub async fn pkid_exhausted_pause_resume_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        let client_id = unique_id();
+
+        for i in 1..=u16::MAX {
+            assert_eq!(cache_manager.get_pkid(&client_id).await, i);
+        }
+
+        // all the pkids are in flight, the next delivery waits
+        let wait_cache_manager = cache_manager.clone();
+        let wait_client_id = client_id.clone();
+        let handle =
+            tokio::spawn(async move { wait_cache_manager.get_pkid(&wait_client_id).await });
+        sleep(Duration::from_millis(100)).await;
+        assert!(!handle.is_finished());
+
+        // the ack of pkid 7 frees it and the delivery resumes
+        cache_manager.remove_pkid_info(&client_id, 7);
+        let pkid = timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pkid, 7);
+    }
+
     #[tokio::test]
     #[ignore]
     pub async fn pkid_test() {
@@ -16,11 +16,11 @@ use log::{error, warn};
 use metadata_struct::mqtt::cluster::MqttClusterDynamicConfig;
 use metadata_struct::mqtt::connection::MQTTConnection;
 use protocol::mqtt::common::{
-    ConnAck, ConnAckProperties, ConnectProperties, ConnectReturnCode, Disconnect,
-    DisconnectProperties, DisconnectReasonCode, MqttPacket, MqttProtocol, PingResp, PubAck,
-    PubAckProperties, PubAckReason, PubComp, PubCompProperties, PubCompReason, PubRec,
-    PubRecProperties, PubRecReason, PubRel, PubRelProperties, PubRelReason, SubAck,
-    SubAckProperties, SubscribeReasonCode, UnsubAck, UnsubAckProperties, UnsubAckReason,
+    Auth, AuthProperties, AuthReason, ConnAck, ConnAckProperties, ConnectProperties,
+    ConnectReturnCode, Disconnect, DisconnectProperties, DisconnectReasonCode, MqttPacket,
+    MqttProtocol, PingResp, PubAck, PubAckProperties, PubAckReason, PubComp, PubCompProperties,
+    PubCompReason, PubRec, PubRecProperties, PubRecReason, PubRel, PubRelProperties, PubRelReason,
+    SubAck, SubAckProperties, SubscribeReasonCode, UnsubAck, UnsubAckProperties, UnsubAckReason,
 };
 
 use super::connection::response_information;
@@ -98,7 +98,9 @@ pub fn response_packet_mqtt_connect_fail(
     if !protocol.is_mqtt5() {
         let new_code = if code == ConnectReturnCode::ClientIdentifierNotValid {
             ConnectReturnCode::BadClientId
-        } else if code == ConnectReturnCode::ProtocolError {
+        } else if code == ConnectReturnCode::ProtocolError
+            || code == ConnectReturnCode::UnsupportedProtocolVersion
+        {
             ConnectReturnCode::RefusedProtocolVersion
         } else if code == ConnectReturnCode::Success && code == ConnectReturnCode::NotAuthorized {
             code
@@ -126,6 +128,33 @@ pub fn response_packet_mqtt_connect_fail(
     )
 }
 
+// Tells a connecting client to use another server, e.g. while the node is drained
+pub fn response_packet_mqtt_connect_redirect(
+    protocol: &MqttProtocol,
+    server_reference: String,
+) -> MqttPacket {
+    if !protocol.is_mqtt5() {
+        return MqttPacket::ConnAck(
+            ConnAck {
+                session_present: false,
+                code: ConnectReturnCode::ServiceUnavailable,
+            },
+            None,
+        );
+    }
+    let properties = ConnAckProperties {
+        server_reference: Some(server_reference),
+        ..Default::default()
+    };
+    MqttPacket::ConnAck(
+        ConnAck {
+            session_present: false,
+            code: ConnectReturnCode::UseAnotherServer,
+        },
+        Some(properties),
+    )
+}
+
 pub fn response_packet_mqtt_distinct(
     protocol: &MqttProtocol,
     code: Option<DisconnectReasonCode>,
@@ -157,11 +186,32 @@ pub fn response_packet_mqtt_distinct_by_reason(
     )
 }
 
+pub fn response_packet_mqtt_distinct_redirect(
+    protocol: &MqttProtocol,
+    server_reference: String,
+) -> MqttPacket {
+    if !protocol.is_mqtt5() {
+        return MqttPacket::Disconnect(Disconnect { reason_code: None }, None);
+    }
+    let properties = DisconnectProperties {
+        server_reference: Some(server_reference),
+        ..Default::default()
+    };
+    MqttPacket::Disconnect(
+        Disconnect {
+            reason_code: Some(DisconnectReasonCode::UseAnotherServer),
+        },
+        Some(properties),
+    )
+}
+
 pub fn response_packet_mqtt_puback_success(
     protocol: &MqttProtocol,
+    connection: &MQTTConnection,
     reason: PubAckReason,
     pkid: u16,
     user_properties: Vec<(String, String)>,
+    reason_string: Option<String>,
 ) -> MqttPacket {
     if !protocol.is_mqtt5() {
         let pub_ack = PubAck { pkid, reason: None };
@@ -172,11 +222,14 @@ pub fn response_packet_mqtt_puback_success(
         pkid,
         reason: Some(reason),
     };
-    let properties = Some(PubAckProperties {
+    let mut properties = PubAckProperties {
         reason_string: None,
         user_properties,
-    });
-    MqttPacket::PubAck(pub_ack, properties)
+    };
+    if connection.is_response_problem_info() {
+        properties.reason_string = reason_string;
+    }
+    MqttPacket::PubAck(pub_ack, Some(properties))
 }
 
 pub fn response_packet_mqtt_puback_fail(
@@ -318,6 +371,18 @@ pub fn response_packet_mqtt_ping_resp() -> MqttPacket {
     MqttPacket::PingResp(PingResp {})
 }
 
+pub fn response_packet_mqtt_auth(reason: AuthReason, authentication_method: String) -> MqttPacket {
+    MqttPacket::Auth(
+        Auth {
+            reason: Some(reason),
+        },
+        Some(AuthProperties {
+            authentication_method: Some(authentication_method),
+            ..Default::default()
+        }),
+    )
+}
+
 pub fn response_packet_mqtt_unsuback(
     connection: &MQTTConnection,
     pkid: u16,
@@ -13,36 +13,49 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
+use common_base::config::broker_mqtt::{broker_mqtt_conf, RetainLatestRule, MAX_RETAIN_LATEST_NUM};
 use common_base::tools::now_second;
 use dashmap::DashMap;
 use grpc_clients::pool::ClientPool;
-use log::info;
+use log::{debug, error, info, warn};
 use metadata_struct::mqtt::message::MqttMessage;
 use protocol::mqtt::common::{
     MqttProtocol, Publish, PublishProperties, QoS, RetainForwardRule, Subscribe,
     SubscribeProperties,
 };
+use storage_adapter::storage::StorageAdapter;
+use tokio::select;
 use tokio::sync::broadcast::{self};
+use tokio::time::sleep;
 
 use super::cache::{CacheManager, QosAckPacketInfo};
 use super::constant::{SUB_RETAIN_MESSAGE_PUSH_FLAG, SUB_RETAIN_MESSAGE_PUSH_FLAG_VALUE};
 use super::error::MqttBrokerError;
-use super::message::build_message_expire;
+use super::message::{build_message_expire, is_message_expire, message_remaining_expiry};
 use crate::observability::metrics::packets::{
-    record_retain_recv_metrics, record_retain_sent_metrics,
+    record_retain_recv_metrics, record_retain_sent_metrics, record_retain_skipped_metrics,
 };
+use crate::observability::metrics::publish::{record_message_dropped, MessageDropReason};
+use crate::security::encryption::{decrypt_message_payload, encrypt_message_payload};
 use crate::server::connection_manager::ConnectionManager;
+use crate::storage::retain_index::RetainMessageIndex;
 use crate::storage::topic::TopicStorage;
 use crate::subscribe::exclusive_push::{
-    exclusive_publish_message_qos1, exclusive_publish_message_qos2,
+    exclusive_publish_message_qos1, exclusive_publish_message_qos2, QOS2_ACK_TIMEOUT,
+};
+use crate::subscribe::sub_common::{
+    get_sub_topic_id_list, is_share_sub, min_qos, path_regex_match, publish_message_qos0,
 };
-use crate::subscribe::sub_common::{get_sub_topic_id_list, min_qos, publish_message_qos0};
 use crate::subscribe::subscribe_manager::SubscribeManager;
 use crate::subscribe::subscriber::SubPublishParam;
 use crate::subscribe::subscriber::Subscriber;
 
+// How often the expired retained messages are deleted from the storage
+const RETAIN_MESSAGE_EXPIRE_INTERVAL: Duration = Duration::from_secs(30);
+
 pub async fn is_new_sub(
     client_id: &str,
     subscribe: &Subscribe,
@@ -58,40 +71,245 @@ pub async fn is_new_sub(
     results
 }
 
-pub async fn save_retain_message(
+#[allow(clippy::too_many_arguments)]
+pub async fn save_retain_message<S>(
     cache_manager: &Arc<CacheManager>,
     client_pool: &Arc<ClientPool>,
+    message_storage_adapter: &Arc<S>,
     topic_name: String,
     client_id: &str,
     publish: &Publish,
     publish_properties: &Option<PublishProperties>,
-) -> Result<(), MqttBrokerError> {
+) -> Result<(), MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
     if !publish.retain {
         return Ok(());
     }
 
     let topic_storage = TopicStorage::new(client_pool.clone());
+    let retain_index = RetainMessageIndex::new(message_storage_adapter.clone());
 
     if publish.payload.is_empty() {
-        topic_storage
-            .delete_retain_message(topic_name.clone())
-            .await?;
-        cache_manager.update_topic_retain_message(&topic_name, Some(Vec::new()));
+        clear_retain_message(cache_manager, &topic_storage, &retain_index, &topic_name).await?;
     } else {
         record_retain_recv_metrics(publish.qos);
-        let message_expire = build_message_expire(cache_manager, publish_properties);
-        let retain_message =
-            MqttMessage::build_message(client_id, publish, publish_properties, message_expire);
+        let cluster = cache_manager.get_cluster_info();
+        if cluster.protocol.retain_skip_unchanged
+            && is_retain_payload_unchanged(cache_manager, &topic_name, &publish.payload)
+        {
+            record_retain_skipped_metrics(publish.qos);
+            return Ok(());
+        }
+
+        let (retain_message, message_expire) = build_retain_message(
+            cache_manager,
+            &topic_name,
+            client_id,
+            publish,
+            publish_properties,
+        )?;
         topic_storage
             .set_retain_message(topic_name.clone(), &retain_message, message_expire)
             .await?;
 
         cache_manager.update_topic_retain_message(&topic_name, Some(retain_message.encode()));
+        let retain_num = retain_latest_num(&broker_mqtt_conf().retain_latest, &topic_name);
+        if retain_num > 1 {
+            cache_manager.add_retain_message_history(
+                &topic_name,
+                retain_message.clone(),
+                retain_num,
+            );
+        }
+
+        if let Err(e) = retain_index
+            .set(&topic_name, &retain_message, message_expire)
+            .await
+        {
+            error!(
+                "Failed to update topic {} in the retained message index, error message: {}",
+                topic_name, e
+            );
+        }
     }
 
     Ok(())
 }
 
+// The retained message is stored encrypted like the messages of the topic.
+// Returns the message and its expiry time.
+fn build_retain_message(
+    cache_manager: &Arc<CacheManager>,
+    topic_name: &str,
+    client_id: &str,
+    publish: &Publish,
+    publish_properties: &Option<PublishProperties>,
+) -> Result<(MqttMessage, u64), MqttBrokerError> {
+    let mut publish = publish.clone();
+    publish.payload = encrypt_message_payload(cache_manager, topic_name, &publish.payload)?;
+
+    let message_expire = build_message_expire(cache_manager, publish_properties);
+    let retain_message =
+        MqttMessage::build_message(client_id, &publish, publish_properties, message_expire);
+    Ok((retain_message, message_expire))
+}
+
+async fn clear_retain_message<S>(
+    cache_manager: &Arc<CacheManager>,
+    topic_storage: &TopicStorage,
+    retain_index: &RetainMessageIndex<S>,
+    topic_name: &str,
+) -> Result<(), MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    topic_storage
+        .delete_retain_message(topic_name.to_owned())
+        .await?;
+    cache_manager.update_topic_retain_message(topic_name, Some(Vec::new()));
+    cache_manager.clear_retain_message_history(topic_name);
+
+    // The index only speeds up the startup, the retained message is already deleted
+    if let Err(e) = retain_index.clear(topic_name).await {
+        error!(
+            "Failed to clear topic {} in the retained message index, error message: {}",
+            topic_name, e
+        );
+    }
+    Ok(())
+}
+
+// The cached topics whose retained message expired
+pub fn expired_retain_topics(cache_manager: &Arc<CacheManager>) -> Vec<String> {
+    cache_manager
+        .topic_info
+        .iter()
+        .filter(|topic| match &topic.retain_message {
+            Some(retain_message) if !retain_message.is_empty() => {
+                serde_json::from_slice::<MqttMessage>(retain_message)
+                    .map(|message| is_message_expire(&message))
+                    .unwrap_or(false)
+            }
+            _ => false,
+        })
+        .map(|topic| topic.topic_name.clone())
+        .collect()
+}
+
+// Deletes the expired retained messages from the storage, returns the number of topics purged.
+pub async fn purge_expired_retain_messages<S>(
+    cache_manager: &Arc<CacheManager>,
+    client_pool: &Arc<ClientPool>,
+    message_storage_adapter: &Arc<S>,
+) -> usize
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let topic_storage = TopicStorage::new(client_pool.clone());
+    let retain_index = RetainMessageIndex::new(message_storage_adapter.clone());
+    let mut purged = 0;
+    for topic_name in expired_retain_topics(cache_manager) {
+        if let Err(e) =
+            clear_retain_message(cache_manager, &topic_storage, &retain_index, &topic_name).await
+        {
+            warn!(
+                "Failed to delete the expired retained message of topic {}, {}",
+                topic_name, e
+            );
+            continue;
+        }
+        purged += 1;
+    }
+    purged
+}
+
+pub async fn start_retain_message_expire_thread<S>(
+    cache_manager: Arc<CacheManager>,
+    client_pool: Arc<ClientPool>,
+    message_storage_adapter: Arc<S>,
+    stop_send: broadcast::Sender<bool>,
+) where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    loop {
+        let mut stop_recv = stop_send.subscribe();
+        select! {
+            val = stop_recv.recv() =>{
+                if let Ok(flag) = val {
+                    if flag {
+                        debug!("{}","Retained message expire thread exited successfully");
+                        break;
+                    }
+                }
+            }
+            _ = purge_expired_retain_messages(
+                &cache_manager,
+                &client_pool,
+                &message_storage_adapter,
+            ) => {
+                sleep(RETAIN_MESSAGE_EXPIRE_INTERVAL).await;
+            }
+        }
+    }
+}
+
+// Whether the topic already retains a live message with the payload
+fn is_retain_payload_unchanged(
+    cache_manager: &Arc<CacheManager>,
+    topic_name: &str,
+    payload: &Bytes,
+) -> bool {
+    let Some(retain_message) = cache_manager
+        .get_topic_by_name(topic_name)
+        .and_then(|topic| topic.retain_message)
+    else {
+        return false;
+    };
+    if retain_message.is_empty() {
+        return false;
+    }
+    let Ok(message) = serde_json::from_slice::<MqttMessage>(&retain_message) else {
+        return false;
+    };
+    if is_message_expire(&message) {
+        return false;
+    }
+    // The stored payload is encrypted with a random nonce, so it is compared decrypted
+    match decrypt_message_payload(cache_manager, topic_name, &message.payload) {
+        Ok(stored) => stored == *payload,
+        Err(_) => false,
+    }
+}
+
+// Number of retained messages kept for the topic, the first matching retain_latest rule applies.
+pub fn retain_latest_num(rules: &[RetainLatestRule], topic_name: &str) -> usize {
+    for rule in rules {
+        if path_regex_match(topic_name, &rule.topic_filter) {
+            return rule.retain_num.clamp(1, MAX_RETAIN_LATEST_NUM) as usize;
+        }
+    }
+    1
+}
+
+// Restores the retained messages of the cached topics from the retained message index.
+pub async fn load_retain_message_index<S>(
+    cache_manager: &Arc<CacheManager>,
+    message_storage_adapter: &Arc<S>,
+) -> Result<usize, MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let retain_index = RetainMessageIndex::new(message_storage_adapter.clone());
+    let retain_messages = retain_index.load().await?;
+    let num = retain_messages.len();
+    for (topic_name, retain_message) in retain_messages {
+        cache_manager.update_topic_retain_message(&topic_name, Some(retain_message));
+    }
+    Ok(num)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn try_send_retain_message(
     protocol: MqttProtocol,
@@ -123,6 +341,104 @@ pub async fn try_send_retain_message(
     });
 }
 
+// Read from the user properties of the SUBSCRIBE, the number of retained messages to skip
+pub const RETAIN_PAGE_OFFSET: &str = "x-retain-page-offset";
+// Set on the last retained message delivered when more are left beyond the cap
+pub const RETAIN_NEXT_PAGE_OFFSET: &str = "x-retain-next-offset";
+
+fn retain_page_offset(subscribe_properties: &Option<SubscribeProperties>) -> usize {
+    subscribe_properties
+        .as_ref()
+        .and_then(|properties| {
+            properties
+                .user_properties
+                .iter()
+                .find(|(key, _)| key == RETAIN_PAGE_OFFSET)
+        })
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0)
+}
+
+// Orders the retained messages matching a subscription by their creation time and returns the
+// page starting at page_offset, at most max_num of them when max_num is not 0, along with the
+// offset of the next page if any message is left.
+pub fn select_retain_messages(
+    mut messages: Vec<(String, MqttMessage)>,
+    max_num: usize,
+    newest_first: bool,
+    page_offset: usize,
+) -> (Vec<(String, MqttMessage)>, Option<usize>) {
+    messages.sort_by_key(|(_, msg)| msg.create_time);
+    if newest_first {
+        messages.reverse();
+    }
+
+    let total = messages.len();
+    let page: Vec<_> = messages.into_iter().skip(page_offset).collect();
+    if max_num == 0 || page.len() <= max_num {
+        return (page, None);
+    }
+
+    let next_page_offset = page_offset + max_num;
+    let page = page.into_iter().take(max_num).collect();
+    (page, (next_page_offset < total).then_some(next_page_offset))
+}
+
+// Sends the retained messages matching each subscription of the client again, so that
+// an admin can repair a client whose retained state is stale. Shared subscriptions
+// do not receive retained messages. Returns the number of subscriptions resynced.
+pub async fn resync_retained(
+    client_id: &str,
+    client_pool: &Arc<ClientPool>,
+    cache_manager: &Arc<CacheManager>,
+    connection_manager: &Arc<ConnectionManager>,
+    subscribe_manager: &Arc<SubscribeManager>,
+) -> Result<usize, MqttBrokerError> {
+    let Some(session) = cache_manager.get_session_info(client_id) else {
+        return Err(MqttBrokerError::SessionDoesNotExist);
+    };
+    if session.connection_id.is_none() {
+        return Err(MqttBrokerError::ClientNotConnected(client_id.to_owned()));
+    }
+
+    let subscribes: Vec<_> = subscribe_manager
+        .subscribe_list
+        .iter()
+        .filter(|raw| raw.client_id == client_id && !is_share_sub(&raw.path))
+        .map(|raw| raw.value().clone())
+        .collect();
+
+    let (stop_sx, _) = broadcast::channel(1);
+    for sub in subscribes.iter() {
+        let subscribe = Subscribe {
+            packet_identifier: sub.pkid,
+            filters: vec![sub.filter.clone()],
+        };
+        // Retained messages are resent even to the subscriptions that only receive them when new
+        let is_new_subs = DashMap::with_capacity(1);
+        is_new_subs.insert(sub.path.clone(), true);
+        send_retain_message(
+            &sub.protocol,
+            &sub.client_id,
+            &subscribe,
+            &sub.subscribe_properties,
+            client_pool,
+            cache_manager,
+            connection_manager,
+            &stop_sx,
+            &is_new_subs,
+        )
+        .await?;
+    }
+
+    info!(
+        "Retained messages of {} subscriptions are resynced to client_id [{}]",
+        subscribes.len(),
+        client_id
+    );
+    Ok(subscribes.len())
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn send_retain_message(
     protocol: &MqttProtocol,
@@ -160,6 +476,7 @@ async fn send_retain_message(
         let topic_id_list = get_sub_topic_id_list(cache_manager, &filter.path).await;
         let topic_storage = TopicStorage::new(client_pool.clone());
         let cluster = cache_manager.get_cluster_info();
+        let mut candidates = Vec::new();
         for topic_id in topic_id_list.iter() {
             let topic_name = if let Some(topic_name) = cache_manager.topic_name_by_id(topic_id) {
                 topic_name
@@ -167,16 +484,49 @@ async fn send_retain_message(
                 continue;
             };
 
-            let msg = if let Some(message) = topic_storage.get_retain_message(&topic_name).await? {
-                message
-            } else {
-                continue;
-            };
+            // The history ends with the retained message of the storage, which is only read
+            // when the topic has no history on this broker
+            let mut retain_messages = cache_manager.get_retain_message_history(&topic_name);
+            if retain_messages.is_empty() {
+                if let Some(message) = topic_storage.get_retain_message(&topic_name).await? {
+                    retain_messages.push(message);
+                }
+            }
+            retain_messages.retain(|msg| !is_message_expire(msg));
 
-            if filter.nolocal && *client_id == msg.client_id {
-                continue;
+            for mut msg in retain_messages {
+                if filter.nolocal && *client_id == msg.client_id {
+                    continue;
+                }
+                msg.payload = match decrypt_message_payload(
+                    cache_manager,
+                    &topic_name,
+                    &msg.payload,
+                ) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        error!(
+                            "retained message of topic {} is not sent to the client and is discarded, {}",
+                            topic_name, e
+                        );
+                        record_message_dropped(MessageDropReason::Quarantined, msg.qos, 1);
+                        continue;
+                    }
+                };
+                candidates.push((topic_name.clone(), msg));
             }
+        }
+
+        let page_offset = retain_page_offset(subscribe_properties);
+        let (selected, next_page_offset) = select_retain_messages(
+            candidates,
+            cluster.protocol.retain_max_per_subscribe as usize,
+            cluster.protocol.retain_newest_first,
+            page_offset,
+        );
+        let last_index = selected.len().saturating_sub(1);
 
+        for (index, (topic_name, msg)) in selected.into_iter().enumerate() {
             let retain = if filter.preserve_retain {
                 msg.retain
             } else {
@@ -185,15 +535,22 @@ async fn send_retain_message(
 
             let qos = min_qos(cluster.protocol.max_qos, filter.qos);
 
+            let message_expiry_interval = message_remaining_expiry(&msg);
             let mut user_properties = msg.user_properties;
             user_properties.push((
                 SUB_RETAIN_MESSAGE_PUSH_FLAG.to_string(),
                 SUB_RETAIN_MESSAGE_PUSH_FLAG_VALUE.to_string(),
             ));
+            // The client fetches the next page with a SUBSCRIBE carrying this offset
+            if let Some(next) = next_page_offset {
+                if index == last_index {
+                    user_properties.push((RETAIN_NEXT_PAGE_OFFSET.to_string(), next.to_string()));
+                }
+            }
 
             let properties = PublishProperties {
                 payload_format_indicator: msg.format_indicator,
-                message_expiry_interval: Some(msg.expiry_interval as u32),
+                message_expiry_interval: Some(message_expiry_interval),
                 topic_alias: None,
                 response_topic: msg.response_topic,
                 correlation_data: msg.correlation_data,
@@ -243,7 +600,7 @@ async fn send_retain_message(
                 }
 
                 QoS::AtLeastOnce => {
-                    let (wait_puback_sx, _) = broadcast::channel(1);
+                    let (wait_puback_sx, _wait_puback_rx) = broadcast::channel(1);
                     cache_manager.add_ack_packet(
                         client_id,
                         pkid,
@@ -267,7 +624,7 @@ async fn send_retain_message(
                 }
 
                 QoS::ExactlyOnce => {
-                    let (wait_ack_sx, _) = broadcast::channel(1);
+                    let (wait_ack_sx, _wait_ack_rx) = broadcast::channel(1);
                     cache_manager.add_ack_packet(
                         client_id,
                         pkid,
@@ -283,6 +640,7 @@ async fn send_retain_message(
                         &sub_pub_param,
                         stop_sx,
                         &wait_ack_sx,
+                        QOS2_ACK_TIMEOUT,
                     )
                     .await?;
 
@@ -296,3 +654,612 @@ async fn send_retain_message(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::{
+        init_broker_mqtt_conf_by_path, RetainLatestRule, MAX_RETAIN_LATEST_NUM,
+    };
+    use common_base::tools::{now_second, unique_id};
+    use dashmap::DashMap;
+    use futures::StreamExt;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use metadata_struct::mqtt::subscribe_data::MqttSubscribe;
+    use metadata_struct::mqtt::topic::MqttTopic;
+    use protocol::mqtt::common::{
+        Filter, MqttPacket, MqttProtocol, Publish, QoS, RetainForwardRule, Subscribe,
+        SubscribeProperties,
+    };
+    use storage_adapter::memory::MemoryStorageAdapter;
+    use tokio::sync::broadcast;
+    use tokio::time::timeout;
+
+    use super::{
+        build_retain_message, expired_retain_topics, resync_retained, retain_latest_num,
+        save_retain_message, select_retain_messages, send_retain_message, RETAIN_NEXT_PAGE_OFFSET,
+        RETAIN_PAGE_OFFSET,
+    };
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::handler::error::MqttBrokerError;
+    use crate::observability::metrics::packets::get_retain_skipped_num;
+    use crate::storage::message::cluster_name;
+    use crate::storage::retain_index::{RetainMessageIndex, RETAIN_INDEX_SHARD_NAME};
+    use crate::subscribe::push_fixture::build_push_fixture;
+
+    #[tokio::test]
+    async fn skip_unchanged_retain_message_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.retain_skip_unchanged = true;
+        cache_manager.set_cluster_info(cluster);
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+
+        let topic_name = "/test/retain/unchanged".to_string();
+        let mut topic = MqttTopic::new(unique_id(), "mqtt-broker".to_string(), topic_name.clone());
+        let retained = MqttMessage {
+            client_id: "publisher".to_string(),
+            retain: true,
+            topic: Bytes::from(topic_name.clone()),
+            payload: Bytes::from("on"),
+            expiry_interval: now_second() + 3600,
+            ..Default::default()
+        };
+        topic.retain_message = Some(retained.encode());
+        cache_manager.add_topic(&topic_name, &topic);
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid: 1,
+            retain: true,
+            topic: Bytes::from(topic_name.clone()),
+            payload: Bytes::from("on"),
+        };
+        let skipped = get_retain_skipped_num(QoS::AtLeastOnce);
+        save_retain_message(
+            &cache_manager,
+            &client_pool,
+            &storage_adapter,
+            topic_name.clone(),
+            "publisher",
+            &publish,
+            &None,
+        )
+        .await
+        .unwrap();
+
+        // neither the retained message nor its index was written
+        assert_eq!(get_retain_skipped_num(QoS::AtLeastOnce), skipped + 1);
+        let index_key = storage_adapter.shard_key(&cluster_name(), RETAIN_INDEX_SHARD_NAME);
+        let index_records = storage_adapter
+            .shard_data
+            .get(&index_key)
+            .map(|records| records.len())
+            .unwrap_or_default();
+        assert_eq!(index_records, 0);
+        assert_eq!(
+            cache_manager
+                .get_topic_by_name(&topic_name)
+                .unwrap()
+                .retain_message,
+            Some(retained.encode())
+        );
+
+        // a new payload is written, which fails as there is no placement center in the test
+        let publish = Publish {
+            payload: Bytes::from("off"),
+            ..publish
+        };
+        assert!(save_retain_message(
+            &cache_manager,
+            &client_pool,
+            &storage_adapter,
+            topic_name,
+            "publisher",
+            &publish,
+            &None,
+        )
+        .await
+        .is_err());
+        assert_eq!(get_retain_skipped_num(QoS::AtLeastOnce), skipped + 1);
+    }
+
+    #[test]
+    fn retain_latest_num_test() {
+        let rules = vec![
+            RetainLatestRule {
+                topic_filter: "/sensor/+".to_string(),
+                retain_num: 5,
+            },
+            RetainLatestRule {
+                topic_filter: "/log/#".to_string(),
+                retain_num: 1000,
+            },
+        ];
+        assert_eq!(retain_latest_num(&rules, "/sensor/1"), 5);
+        assert_eq!(
+            retain_latest_num(&rules, "/log/app/1"),
+            MAX_RETAIN_LATEST_NUM as usize
+        );
+        assert_eq!(retain_latest_num(&rules, "/test/1"), 1);
+        assert_eq!(retain_latest_num(&[], "/sensor/1"), 1);
+    }
+
+    #[test]
+    fn select_retain_messages_test() {
+        let messages: Vec<(String, MqttMessage)> = [3, 1, 4, 0, 2]
+            .iter()
+            .map(|i| {
+                let message = MqttMessage {
+                    payload: Bytes::from(format!("message-{}", i)),
+                    create_time: *i,
+                    ..Default::default()
+                };
+                (format!("/test/{}", i), message)
+            })
+            .collect();
+        let topics = |page: &[(String, MqttMessage)]| -> Vec<String> {
+            page.iter().map(|(topic, _)| topic.clone()).collect()
+        };
+
+        let (page, next) = select_retain_messages(messages.clone(), 0, false, 0);
+        assert_eq!(page.len(), 5);
+        assert_eq!(next, None);
+
+        let (page, next) = select_retain_messages(messages.clone(), 2, false, 0);
+        assert_eq!(topics(&page), vec!["/test/0", "/test/1"]);
+        assert_eq!(next, Some(2));
+
+        let (page, next) = select_retain_messages(messages.clone(), 2, true, 0);
+        assert_eq!(topics(&page), vec!["/test/4", "/test/3"]);
+        assert_eq!(next, Some(2));
+
+        let (page, next) = select_retain_messages(messages.clone(), 2, true, 2);
+        assert_eq!(topics(&page), vec!["/test/2", "/test/1"]);
+        assert_eq!(next, Some(4));
+
+        let (page, next) = select_retain_messages(messages, 2, true, 4);
+        assert_eq!(topics(&page), vec!["/test/0"]);
+        assert_eq!(next, None);
+    }
+
+    #[tokio::test]
+    async fn send_retain_messages_capped_test() {
+        let fixture = build_push_fixture();
+        let client_pool = fixture.client_pool.clone();
+        let cache_manager = fixture.cache_manager.clone();
+        let connection_manager = fixture.connection_manager.clone();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.retain_max_per_subscribe = 2;
+        cluster.protocol.retain_newest_first = true;
+        cache_manager.set_cluster_info(cluster);
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        // 5 topics with a retained message each, created one second apart
+        let now = now_second();
+        for i in 0..5 {
+            let topic_name = format!("/test/retain/cap/{}", i);
+            let topic = MqttTopic::new(unique_id(), "mqtt-broker".to_string(), topic_name.clone());
+            cache_manager.add_topic(&topic_name, &topic);
+            let message = MqttMessage {
+                client_id: "publisher".to_string(),
+                retain: true,
+                topic: Bytes::from(topic_name.clone()),
+                payload: Bytes::from(format!("message-{}", i)),
+                expiry_interval: now + 3600,
+                create_time: now + i,
+                ..Default::default()
+            };
+            cache_manager.add_retain_message_history(&topic_name, message, 1);
+        }
+
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            filters: vec![Filter {
+                path: "/test/retain/cap/#".to_string(),
+                qos: QoS::AtMostOnce,
+                nolocal: false,
+                preserve_retain: true,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            }],
+        };
+        let (stop_sx, _) = broadcast::channel(1);
+
+        let mut page_offset = None;
+        for expected in [[4, 3], [2, 1]] {
+            let subscribe_properties = page_offset.map(|offset: String| SubscribeProperties {
+                subscription_identifier: None,
+                user_properties: vec![(RETAIN_PAGE_OFFSET.to_string(), offset)],
+            });
+            send_retain_message(
+                &MqttProtocol::Mqtt5,
+                &client_id,
+                &subscribe,
+                &subscribe_properties,
+                &client_pool,
+                &cache_manager,
+                &connection_manager,
+                &stop_sx,
+                &DashMap::new(),
+            )
+            .await
+            .unwrap();
+
+            let mut next_page_offset = None;
+            for i in expected {
+                let packet = timeout(Duration::from_secs(10), read_stream.next())
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .unwrap();
+                let MqttPacket::Publish(publish, properties) = packet else {
+                    panic!("expected a PUBLISH");
+                };
+                assert_eq!(publish.payload, Bytes::from(format!("message-{}", i)));
+                next_page_offset = properties
+                    .unwrap()
+                    .user_properties
+                    .into_iter()
+                    .find(|(key, _)| key == RETAIN_NEXT_PAGE_OFFSET)
+                    .map(|(_, value)| value);
+            }
+            // the delivery stops at the cap
+            assert!(timeout(Duration::from_millis(300), read_stream.next())
+                .await
+                .is_err());
+            assert!(next_page_offset.is_some());
+            page_offset = next_page_offset;
+        }
+        assert_eq!(page_offset, Some("4".to_string()));
+    }
+
+    #[tokio::test]
+    async fn send_retain_latest_messages_test() {
+        let fixture = build_push_fixture();
+        let client_pool = fixture.client_pool.clone();
+        let cache_manager = fixture.cache_manager.clone();
+        let connection_manager = fixture.connection_manager.clone();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        // the topic keeps the latest 3 of the 5 retained messages
+        let topic_name = "/test/retain/latest".to_string();
+        let topic = MqttTopic::new(unique_id(), "mqtt-broker".to_string(), topic_name.clone());
+        cache_manager.add_topic(&topic_name, &topic);
+        for i in 0..5 {
+            let message = MqttMessage {
+                client_id: "publisher".to_string(),
+                retain: true,
+                topic: Bytes::from(topic_name.clone()),
+                payload: Bytes::from(format!("message-{}", i)),
+                expiry_interval: now_second() + 3600,
+                ..Default::default()
+            };
+            cache_manager.add_retain_message_history(&topic_name, message, 3);
+        }
+
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            filters: vec![Filter {
+                path: topic_name.clone(),
+                qos: QoS::AtMostOnce,
+                nolocal: false,
+                preserve_retain: true,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            }],
+        };
+        let (stop_sx, _) = broadcast::channel(1);
+        send_retain_message(
+            &MqttProtocol::Mqtt5,
+            &client_id,
+            &subscribe,
+            &None,
+            &client_pool,
+            &cache_manager,
+            &connection_manager,
+            &stop_sx,
+            &DashMap::new(),
+        )
+        .await
+        .unwrap();
+
+        for i in 2..5 {
+            let packet = timeout(Duration::from_secs(10), read_stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let MqttPacket::Publish(publish, _) = packet else {
+                panic!("expected a PUBLISH");
+            };
+            assert!(publish.retain);
+            assert_eq!(publish.payload, Bytes::from(format!("message-{}", i)));
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypted_tenant_retain_message_test() {
+        let fixture = build_push_fixture();
+        let client_pool = fixture.client_pool.clone();
+        let cache_manager = fixture.cache_manager.clone();
+        let connection_manager = fixture.connection_manager.clone();
+        let storage_adapter = fixture.storage_adapter.clone();
+        cache_manager.add_tenant_encryption_key("tenant1", vec![9u8; 32]);
+
+        let topic_name = "/tenant1/retain/encrypted".to_string();
+        let topic = MqttTopic::new(unique_id(), "mqtt-broker".to_string(), topic_name.clone());
+        cache_manager.add_topic(&topic_name, &topic);
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            pkid: 0,
+            retain: true,
+            topic: Bytes::from(topic_name.clone()),
+            payload: Bytes::from("tenant secret"),
+        };
+        let (retain_message, message_expire) =
+            build_retain_message(&cache_manager, &topic_name, "publisher", &publish, &None)
+                .unwrap();
+        RetainMessageIndex::new(storage_adapter.clone())
+            .set(&topic_name, &retain_message, message_expire)
+            .await
+            .unwrap();
+
+        // the raw retained record holds the payload encrypted
+        let index_key = storage_adapter.shard_key(&cluster_name(), RETAIN_INDEX_SHARD_NAME);
+        let raw_record = storage_adapter
+            .shard_data
+            .get(&index_key)
+            .unwrap()
+            .first()
+            .unwrap()
+            .data
+            .clone();
+        let plaintext = b"tenant secret";
+        assert!(!raw_record
+            .windows(plaintext.len())
+            .any(|window| window == plaintext));
+        assert_ne!(retain_message.payload, Bytes::from("tenant secret"));
+
+        // and the subscriber receives it decrypted
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+        cache_manager.add_retain_message_history(&topic_name, retain_message, 1);
+
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            filters: vec![Filter {
+                path: topic_name.clone(),
+                qos: QoS::AtMostOnce,
+                nolocal: false,
+                preserve_retain: true,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            }],
+        };
+        let (stop_sx, _) = broadcast::channel(1);
+        send_retain_message(
+            &MqttProtocol::Mqtt5,
+            &client_id,
+            &subscribe,
+            &None,
+            &client_pool,
+            &cache_manager,
+            &connection_manager,
+            &stop_sx,
+            &DashMap::new(),
+        )
+        .await
+        .unwrap();
+
+        let packet = timeout(Duration::from_secs(10), read_stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let MqttPacket::Publish(publish, _) = packet else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.payload, Bytes::from("tenant secret"));
+    }
+
+    #[tokio::test]
+    async fn send_retain_message_expired_test() {
+        let fixture = build_push_fixture();
+        let client_pool = fixture.client_pool.clone();
+        let cache_manager = fixture.cache_manager.clone();
+        let connection_manager = fixture.connection_manager.clone();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        // the first message expires in a second, the second one in a minute
+        for (i, expiry) in [1, 60].into_iter().enumerate() {
+            let topic_name = format!("/test/retain/expired/{}", i);
+            let topic = MqttTopic::new(unique_id(), "mqtt-broker".to_string(), topic_name.clone());
+            cache_manager.add_topic(&topic_name, &topic);
+            let message = MqttMessage {
+                client_id: "publisher".to_string(),
+                retain: true,
+                topic: Bytes::from(topic_name.clone()),
+                payload: Bytes::from(format!("message-{}", i)),
+                expiry_interval: now_second() + expiry,
+                ..Default::default()
+            };
+            cache_manager.add_retain_message_history(&topic_name, message, 1);
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            filters: vec![Filter {
+                path: "/test/retain/expired/#".to_string(),
+                qos: QoS::AtMostOnce,
+                nolocal: false,
+                preserve_retain: true,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            }],
+        };
+        let (stop_sx, _) = broadcast::channel(1);
+        send_retain_message(
+            &MqttProtocol::Mqtt5,
+            &client_id,
+            &subscribe,
+            &None,
+            &client_pool,
+            &cache_manager,
+            &connection_manager,
+            &stop_sx,
+            &DashMap::new(),
+        )
+        .await
+        .unwrap();
+
+        let packet = timeout(Duration::from_secs(10), read_stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let MqttPacket::Publish(publish, properties) = packet else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.payload, Bytes::from("message-1"));
+        // the remaining seconds, not the time the message expires at
+        let expiry = properties.unwrap().message_expiry_interval.unwrap();
+        assert!(expiry > 50 && expiry <= 58);
+
+        // the expired message is not delivered
+        assert!(timeout(Duration::from_millis(300), read_stream.next())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn expired_retain_topics_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        for (topic_name, expiry_interval) in [
+            ("/test/retain/purge/expired", now_second() - 10),
+            ("/test/retain/purge/live", now_second() + 3600),
+        ] {
+            let mut topic = MqttTopic::new(
+                unique_id(),
+                "mqtt-broker".to_string(),
+                topic_name.to_string(),
+            );
+            let message = MqttMessage {
+                topic: Bytes::from(topic_name.to_string()),
+                payload: Bytes::from("payload"),
+                expiry_interval,
+                ..Default::default()
+            };
+            topic.retain_message = Some(message.encode());
+            cache_manager.add_topic(topic_name, &topic);
+        }
+        // a topic without or with a cleared retained message
+        for topic_name in ["/test/retain/purge/none", "/test/retain/purge/cleared"] {
+            let topic = MqttTopic::new(
+                unique_id(),
+                "mqtt-broker".to_string(),
+                topic_name.to_string(),
+            );
+            cache_manager.add_topic(topic_name, &topic);
+        }
+        cache_manager.update_topic_retain_message("/test/retain/purge/cleared", Some(Vec::new()));
+
+        assert_eq!(
+            expired_retain_topics(&cache_manager),
+            vec!["/test/retain/purge/expired".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn resync_retained_test() {
+        let fixture = build_push_fixture();
+        let client_pool = fixture.client_pool.clone();
+        let cache_manager = fixture.cache_manager.clone();
+        let connection_manager = fixture.connection_manager.clone();
+        let subscribe_manager = fixture.subscribe_manager.clone();
+        let (connection_id, mut read_stream) = fixture.connect().await;
+
+        assert!(matches!(
+            resync_retained(
+                &unique_id(),
+                &client_pool,
+                &cache_manager,
+                &connection_manager,
+                &subscribe_manager,
+            )
+            .await,
+            Err(MqttBrokerError::SessionDoesNotExist)
+        ));
+        let client_id = fixture.add_session(connection_id);
+
+        let topic_name = "/test/retain/resync".to_string();
+        let topic = MqttTopic::new(unique_id(), "mqtt-broker".to_string(), topic_name.clone());
+        cache_manager.add_topic(&topic_name, &topic);
+        let build_message = |payload: &str| MqttMessage {
+            client_id: "publisher".to_string(),
+            retain: true,
+            topic: Bytes::from(topic_name.clone()),
+            payload: Bytes::from(payload.to_owned()),
+            expiry_interval: now_second() + 3600,
+            ..Default::default()
+        };
+        cache_manager.add_retain_message_history(&topic_name, build_message("message-1"), 1);
+
+        // already subscribed, so the retained messages are not sent on its own
+        subscribe_manager.add_subscribe(MqttSubscribe {
+            client_id: client_id.clone(),
+            path: topic_name.clone(),
+            cluster_name: "mqtt-broker".to_string(),
+            broker_id: 1,
+            protocol: MqttProtocol::Mqtt5,
+            filter: Filter {
+                path: topic_name.clone(),
+                qos: QoS::AtMostOnce,
+                nolocal: false,
+                preserve_retain: true,
+                retain_forward_rule: RetainForwardRule::OnNewSubscribe,
+            },
+            pkid: 1,
+            subscribe_properties: None,
+        });
+
+        for payload in ["message-1", "message-2"] {
+            if payload == "message-2" {
+                cache_manager.add_retain_message_history(&topic_name, build_message(payload), 1);
+            }
+            let num = resync_retained(
+                &client_id,
+                &client_pool,
+                &cache_manager,
+                &connection_manager,
+                &subscribe_manager,
+            )
+            .await
+            .unwrap();
+            assert_eq!(num, 1);
+
+            let packet = timeout(Duration::from_secs(10), read_stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let MqttPacket::Publish(publish, _) = packet else {
+                panic!("expected a PUBLISH");
+            };
+            assert!(publish.retain);
+            assert_eq!(publish.payload, Bytes::from(payload));
+        }
+    }
+}
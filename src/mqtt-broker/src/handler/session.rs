@@ -18,7 +18,9 @@ use common_base::config::broker_mqtt::broker_mqtt_conf;
 use common_base::tools::now_second;
 use grpc_clients::pool::ClientPool;
 use metadata_struct::mqtt::session::MqttSession;
-use protocol::mqtt::common::{Connect, ConnectProperties, LastWill, LastWillProperties};
+use protocol::mqtt::common::{
+    Connect, ConnectProperties, DisconnectProperties, LastWill, LastWillProperties,
+};
 
 use super::cache::CacheManager;
 use super::error::MqttBrokerError;
@@ -107,6 +109,63 @@ pub async fn save_session(
     Ok(())
 }
 
+pub async fn update_session_expiry_by_disconnect(
+    cache_manager: &Arc<CacheManager>,
+    client_pool: &Arc<ClientPool>,
+    client_id: &str,
+    disconnect_properties: &Option<DisconnectProperties>,
+) -> Result<(), MqttBrokerError> {
+    let Some(mut session) = cache_manager.get_session_info(client_id) else {
+        return Ok(());
+    };
+
+    let Some(session_expiry) =
+        disconnect_session_expiry_interval(cache_manager, &session, disconnect_properties)?
+    else {
+        return Ok(());
+    };
+
+    session.session_expiry = session_expiry;
+    let session_storage = SessionStorage::new(client_pool.clone());
+    if let Err(e) = session_storage
+        .set_session(client_id.to_owned(), &session)
+        .await
+    {
+        return Err(MqttBrokerError::CommonError(e.to_string()));
+    }
+    cache_manager.add_session(client_id.to_owned(), session);
+    Ok(())
+}
+
+// The Session Expiry Interval in DISCONNECT overrides the one set in CONNECT.
+// It is a Protocol Error to set a non-zero value when the CONNECT used 0.
+fn disconnect_session_expiry_interval(
+    cache_manager: &Arc<CacheManager>,
+    session: &MqttSession,
+    disconnect_properties: &Option<DisconnectProperties>,
+) -> Result<Option<u64>, MqttBrokerError> {
+    let Some(interval) = disconnect_properties
+        .as_ref()
+        .and_then(|properties| properties.session_expiry_interval)
+    else {
+        return Ok(None);
+    };
+
+    if session.session_expiry == 0 && interval > 0 {
+        return Err(MqttBrokerError::SessionExpiryIntervalChangedFromZero(
+            interval,
+        ));
+    }
+
+    let cluster_session_expiry_interval = cache_manager
+        .get_cluster_info()
+        .protocol
+        .session_expiry_interval;
+    Ok(Some(
+        std::cmp::min(cluster_session_expiry_interval, interval) as u64,
+    ))
+}
+
 fn session_expiry_interval(
     cache_manager: &Arc<CacheManager>,
     connect_properties: &Option<ConnectProperties>,
@@ -138,9 +197,9 @@ mod test {
     use common_base::config::broker_mqtt::BrokerMqttConfig;
     use grpc_clients::pool::ClientPool;
     use metadata_struct::mqtt::session::MqttSession;
-    use protocol::mqtt::common::ConnectProperties;
+    use protocol::mqtt::common::{ConnectProperties, DisconnectProperties};
 
-    use super::session_expiry_interval;
+    use super::{disconnect_session_expiry_interval, session_expiry_interval};
     use crate::handler::{cache::CacheManager, cluster_config::build_default_cluster_config};
 
     #[tokio::test]
@@ -200,4 +259,39 @@ mod test {
         let res = session_expiry_interval(&cache_manager, &Some(properties));
         assert_eq!(res, 1800);
     }
+
+    #[test]
+    pub fn disconnect_session_expiry_interval_test() {
+        let client_pool = Arc::new(ClientPool::new(100));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, "test".to_string()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+
+        let session = MqttSession::new("client_id_test".to_string(), 60, false, None);
+        let res = disconnect_session_expiry_interval(&cache_manager, &session, &None).unwrap();
+        assert!(res.is_none());
+
+        let properties = DisconnectProperties {
+            session_expiry_interval: Some(120),
+            ..Default::default()
+        };
+        let res = disconnect_session_expiry_interval(&cache_manager, &session, &Some(properties))
+            .unwrap();
+        assert_eq!(res, Some(120));
+
+        let properties = DisconnectProperties {
+            session_expiry_interval: Some(0),
+            ..Default::default()
+        };
+        let res = disconnect_session_expiry_interval(&cache_manager, &session, &Some(properties))
+            .unwrap();
+        assert_eq!(res, Some(0));
+
+        let session = MqttSession::new("client_id_test".to_string(), 0, false, None);
+        let properties = DisconnectProperties {
+            session_expiry_interval: Some(120),
+            ..Default::default()
+        };
+        let res = disconnect_session_expiry_interval(&cache_manager, &session, &Some(properties));
+        assert!(res.is_err());
+    }
 }
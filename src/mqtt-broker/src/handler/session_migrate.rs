@@ -0,0 +1,438 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use common_base::config::broker_mqtt::broker_mqtt_conf;
+use grpc_clients::pool::ClientPool;
+use log::{info, warn};
+use metadata_struct::adapter::record::Record;
+use metadata_struct::mqtt::message::MqttMessage;
+use metadata_struct::mqtt::session::MqttSession;
+use metadata_struct::mqtt::subscribe_data::MqttSubscribe;
+use serde::{Deserialize, Serialize};
+use storage_adapter::storage::StorageAdapter;
+
+use super::cache::CacheManager;
+use super::error::MqttBrokerError;
+use super::pkid::pkid_restore_by_session;
+use super::subscribe::parse_subscribe;
+use super::topic::try_init_topic;
+use crate::storage::message::MessageStorage;
+use crate::subscribe::exclusive_push::exclusive_group_name;
+use crate::subscribe::subscribe_manager::SubscribeManager;
+
+// Messages not pushed yet taken along per subscription
+const SESSION_MIGRATE_MESSAGE_LIMIT: u64 = 10_000;
+
+/// The state of a session moved to another broker, e.g. after its connection was redirected
+/// by the drain of the broker.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionState {
+    pub session: MqttSession,
+    pub subscribes: Vec<MqttSubscribe>,
+    pub queues: Vec<SubscriptionQueue>,
+}
+
+// The progress of the push of a subscription to one of the topics it matches
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SubscriptionQueue {
+    pub sub_path: String,
+    pub topic_name: String,
+    // The committed offset of the push on the exporting broker
+    pub offset: u64,
+    // The stored messages from the committed offset on, which the client did not acknowledge yet
+    pub messages: Vec<Vec<u8>>,
+}
+
+impl SessionState {
+    pub fn encode(&self) -> Result<Vec<u8>, MqttBrokerError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, MqttBrokerError> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+fn is_client_connected(cache_manager: &Arc<CacheManager>, client_id: &str) -> bool {
+    cache_manager
+        .get_connect_id(client_id)
+        .and_then(|connect_id| cache_manager.get_connection(connect_id))
+        .is_some()
+}
+
+/// Exports the session of the client and gives up its ownership: the session and its
+/// subscriptions are removed from the broker, which stops their pushes, so that the session is
+/// only ever active on the broker importing it. The session of a client still connected to the
+/// broker is not exported.
+pub async fn export_session<S>(
+    cache_manager: &Arc<CacheManager>,
+    subscribe_manager: &Arc<SubscribeManager>,
+    message_storage_adapter: &Arc<S>,
+    client_id: &str,
+) -> Result<SessionState, MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    if is_client_connected(cache_manager, client_id) {
+        return Err(MqttBrokerError::SessionIsConnected(client_id.to_owned()));
+    }
+    let Some(mut session) = cache_manager.get_session_info(client_id) else {
+        return Err(MqttBrokerError::SessionDoesNotExist);
+    };
+    session.qos2_pkids = cache_manager.list_client_pkids(client_id);
+
+    let subscribes: Vec<MqttSubscribe> = subscribe_manager
+        .subscribe_list
+        .iter()
+        .filter(|raw| raw.value().client_id == client_id)
+        .map(|raw| raw.value().clone())
+        .collect();
+
+    let message_storage = MessageStorage::new(message_storage_adapter.clone());
+    let subscribers: Vec<_> = subscribe_manager
+        .exclusive_push
+        .iter()
+        .filter(|raw| raw.value().client_id == client_id)
+        .map(|raw| raw.value().clone())
+        .collect();
+    let mut queues = Vec::with_capacity(subscribers.len());
+    for subscriber in subscribers {
+        let group_id = exclusive_group_name(client_id, &subscriber.sub_path, &subscriber.topic_id);
+        let offset = message_storage.get_group_offset(&group_id).await?;
+        let messages = message_storage
+            .read_topic_message(&subscriber.topic_id, offset, SESSION_MIGRATE_MESSAGE_LIMIT)
+            .await?
+            .into_iter()
+            .map(|record| record.data)
+            .collect();
+        queues.push(SubscriptionQueue {
+            sub_path: subscriber.sub_path,
+            topic_name: subscriber.topic_name,
+            offset,
+            messages,
+        });
+    }
+
+    for subscribe in subscribes.iter() {
+        subscribe_manager.remove_subscribe(client_id, &subscribe.path);
+    }
+    subscribe_manager.remove_client_id(client_id);
+    cache_manager.remove_session(client_id);
+
+    info!(
+        "Session of client [{}] was exported with {} subscriptions",
+        client_id,
+        subscribes.len()
+    );
+    Ok(SessionState {
+        session,
+        subscribes,
+        queues,
+    })
+}
+
+/// Imports a session exported by another broker and takes its ownership. A session the broker
+/// already holds is not replaced, so that two connections never share it.
+///
+/// The messages not pushed yet are appended to the topics of the broker, unless the broker
+/// already pushes the topic to other clients: it then received the messages of the topic too,
+/// and appending them again would deliver them twice to the other clients.
+pub async fn import_session<S>(
+    cache_manager: &Arc<CacheManager>,
+    subscribe_manager: &Arc<SubscribeManager>,
+    message_storage_adapter: &Arc<S>,
+    client_pool: &Arc<ClientPool>,
+    state: SessionState,
+) -> Result<(), MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let client_id = state.session.client_id.clone();
+    if is_client_connected(cache_manager, &client_id)
+        || cache_manager.get_session_info(&client_id).is_some()
+    {
+        return Err(MqttBrokerError::SessionAlreadyExists(client_id));
+    }
+
+    let conf = broker_mqtt_conf();
+    let mut session = state.session;
+    session.update_connnction_id(None);
+    session.update_broker_id(Some(conf.broker_id));
+    pkid_restore_by_session(cache_manager, &session, false);
+    cache_manager.add_session(client_id.clone(), session);
+
+    // The offsets are committed before the pushes start, so that they start from them
+    let message_storage = MessageStorage::new(message_storage_adapter.clone());
+    for queue in state.queues {
+        let topic = try_init_topic(
+            &queue.topic_name,
+            cache_manager,
+            message_storage_adapter,
+            client_pool,
+        )
+        .await?;
+
+        let is_pushed_locally = subscribe_manager
+            .exclusive_push
+            .iter()
+            .any(|raw| raw.value().topic_id == topic.topic_id);
+        let offset = if is_pushed_locally || queue.messages.is_empty() {
+            if !queue.messages.is_empty() {
+                warn!(
+                    "{} messages of topic [{}] queued for client [{}] are not imported, the topic is already pushed by the broker",
+                    queue.messages.len(),
+                    queue.topic_name,
+                    client_id
+                );
+            }
+            message_storage
+                .read_latest_topic_message(&topic.topic_id, 1)
+                .await?
+                .first()
+                .and_then(|record| record.offset)
+                .map(|offset| offset + 1)
+                .unwrap_or(0)
+        } else {
+            let mut records = Vec::with_capacity(queue.messages.len());
+            for data in queue.messages {
+                let mut record = Record::build_byte(data);
+                record.tags = MqttMessage::decode_record(record.clone())?.record_tags();
+                records.push(record);
+            }
+            let offsets = message_storage
+                .append_topic_message(&topic.topic_id, records)
+                .await?;
+            offsets.first().copied().unwrap_or_default()
+        };
+
+        let group_id = exclusive_group_name(&client_id, &queue.sub_path, &topic.topic_id);
+        message_storage
+            .commit_group_offset(&group_id, &topic.topic_id, offset)
+            .await?;
+    }
+
+    for mut subscribe in state.subscribes {
+        subscribe.broker_id = conf.broker_id;
+        subscribe_manager.add_subscribe(subscribe.clone());
+        for (_, topic) in cache_manager.topic_info.clone() {
+            parse_subscribe(
+                client_pool,
+                cache_manager,
+                subscribe_manager,
+                &subscribe.client_id,
+                &topic,
+                &subscribe.protocol,
+                subscribe.pkid,
+                &subscribe.filter,
+                &subscribe.subscribe_properties,
+            )
+            .await;
+        }
+    }
+
+    info!("Session of client [{}] was imported", client_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::adapter::record::Record;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use metadata_struct::mqtt::session::MqttSession;
+    use metadata_struct::mqtt::subscribe_data::MqttSubscribe;
+    use metadata_struct::mqtt::topic::MqttTopic;
+    use protocol::mqtt::common::{Filter, MqttProtocol, QoS, RetainForwardRule};
+    use storage_adapter::memory::MemoryStorageAdapter;
+
+    use super::{export_session, import_session, SessionState};
+    use crate::handler::cache::CacheManager;
+    use crate::handler::subscribe::parse_subscribe;
+    use crate::storage::message::MessageStorage;
+    use crate::subscribe::exclusive_push::exclusive_group_name;
+    use crate::subscribe::subscribe_manager::SubscribeManager;
+
+    fn build_subscribe(client_id: &str, path: &str) -> MqttSubscribe {
+        MqttSubscribe {
+            client_id: client_id.to_owned(),
+            path: path.to_owned(),
+            cluster_name: "mqtt-broker".to_string(),
+            broker_id: 1,
+            protocol: MqttProtocol::Mqtt5,
+            filter: Filter {
+                path: path.to_owned(),
+                qos: QoS::AtLeastOnce,
+                nolocal: false,
+                preserve_retain: false,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            },
+            pkid: 1,
+            subscribe_properties: None,
+        }
+    }
+
+    fn build_message(topic_name: &str, payload: &str) -> Record {
+        let message = MqttMessage {
+            client_id: "publisher".to_string(),
+            topic: Bytes::from(topic_name.to_owned()),
+            payload: Bytes::from(payload.to_owned()),
+            ..Default::default()
+        };
+        Record::build_byte(message.encode())
+    }
+
+    #[tokio::test]
+    async fn export_import_session_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_id = "migrate_client";
+        let topic_name = "/test/migrate/1";
+        let topic = MqttTopic::new(
+            unique_id(),
+            "mqtt-broker".to_string(),
+            topic_name.to_string(),
+        );
+        let client_pool = Arc::new(ClientPool::new(1));
+
+        // node 1, the drained broker the client was connected to
+        let node1_cache = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        node1_cache.add_topic(topic_name, &topic);
+        let node1_storage = Arc::new(MemoryStorageAdapter::new());
+        let node1_subscribe_manager = Arc::new(SubscribeManager::new());
+        node1_cache.add_session(
+            client_id.to_string(),
+            MqttSession::new(client_id.to_string(), 3600, false, None),
+        );
+        let subscribe = build_subscribe(client_id, "/test/migrate/+");
+        node1_subscribe_manager.add_subscribe(subscribe.clone());
+        parse_subscribe(
+            &client_pool,
+            &node1_cache,
+            &node1_subscribe_manager,
+            client_id,
+            &topic,
+            &subscribe.protocol,
+            subscribe.pkid,
+            &subscribe.filter,
+            &subscribe.subscribe_properties,
+        )
+        .await;
+
+        // the first message was pushed, the two others are queued
+        let node1_message_storage = MessageStorage::new(node1_storage.clone());
+        node1_message_storage
+            .append_topic_message(
+                &topic.topic_id,
+                vec![
+                    build_message(topic_name, "m0"),
+                    build_message(topic_name, "m1"),
+                    build_message(topic_name, "m2"),
+                ],
+            )
+            .await
+            .unwrap();
+        let group_id = exclusive_group_name(client_id, &subscribe.path, &topic.topic_id);
+        node1_message_storage
+            .commit_group_offset(&group_id, &topic.topic_id, 1)
+            .await
+            .unwrap();
+
+        let state = export_session(
+            &node1_cache,
+            &node1_subscribe_manager,
+            &node1_storage,
+            client_id,
+        )
+        .await
+        .unwrap();
+        assert_eq!(state.subscribes.len(), 1);
+        assert_eq!(state.queues.len(), 1);
+        assert_eq!(state.queues[0].offset, 1);
+        assert_eq!(state.queues[0].messages.len(), 2);
+        // node 1 gave up the session, it no longer pushes it
+        assert!(node1_cache.get_session_info(client_id).is_none());
+        assert!(node1_subscribe_manager.subscribe_list.is_empty());
+        assert!(node1_subscribe_manager.exclusive_push.is_empty());
+        assert!(export_session(
+            &node1_cache,
+            &node1_subscribe_manager,
+            &node1_storage,
+            client_id
+        )
+        .await
+        .is_err());
+
+        // node 2, the broker the client was redirected to
+        let node2_cache = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        node2_cache.add_topic(topic_name, &topic);
+        let node2_storage = Arc::new(MemoryStorageAdapter::new());
+        let node2_subscribe_manager = Arc::new(SubscribeManager::new());
+        let state = SessionState::decode(&state.encode().unwrap()).unwrap();
+        import_session(
+            &node2_cache,
+            &node2_subscribe_manager,
+            &node2_storage,
+            &client_pool,
+            state.clone(),
+        )
+        .await
+        .unwrap();
+
+        let session = node2_cache.get_session_info(client_id).unwrap();
+        assert_eq!(session.broker_id, Some(1));
+        assert!(session.connection_id.is_none());
+        assert!(node2_subscribe_manager
+            .get_subscribe(client_id, &subscribe.path)
+            .is_some());
+        assert_eq!(node2_subscribe_manager.exclusive_push.len(), 1);
+
+        // the push of node 2 starts from the first queued message
+        let node2_message_storage = MessageStorage::new(node2_storage.clone());
+        let offset = node2_message_storage
+            .get_group_offset(&group_id)
+            .await
+            .unwrap();
+        let records = node2_message_storage
+            .read_topic_message(&topic.topic_id, offset, 10)
+            .await
+            .unwrap();
+        let payloads: Vec<Bytes> = records
+            .into_iter()
+            .map(|record| MqttMessage::decode_record(record).unwrap().payload)
+            .collect();
+        assert_eq!(payloads, vec![Bytes::from("m1"), Bytes::from("m2")]);
+
+        // the session is owned by node 2, a second import is refused
+        assert!(import_session(
+            &node2_cache,
+            &node2_subscribe_manager,
+            &node2_storage,
+            &client_pool,
+            state
+        )
+        .await
+        .is_err());
+    }
+}
@@ -27,9 +27,10 @@ use protocol::{
 use serde::{Deserialize, Serialize};
 
 use crate::subscribe::{
+    delivery_rate::requested_delivery_rate,
+    publisher_allowlist::requested_publisher_allowlist,
     sub_common::{
-        decode_queue_info, decode_share_info, get_share_sub_leader, is_queue_sub, is_share_sub,
-        path_regex_match,
+        get_share_sub_leader, is_queue_sub, is_share_sub, path_regex_match, share_group_key,
     },
     subscribe_manager::{ShareSubShareSub, SubscribeManager},
     subscriber::Subscriber,
@@ -44,6 +45,7 @@ struct ParseShareQueueSubscribeRequest {
     client_id: String,
     protocol: MqttProtocol,
     sub_identifier: Option<usize>,
+    publisher_allowlist: Option<Vec<String>>,
     filter: Filter,
     sub_name: String,
     group_name: String,
@@ -145,6 +147,7 @@ pub async fn parse_subscribe(
                 client_id: client_id.to_owned(),
                 protocol: protocol.clone(),
                 sub_identifier,
+                publisher_allowlist: requested_publisher_allowlist(subscribe_properties),
                 filter: filter.clone(),
                 pkid,
                 sub_name: "".to_string(),
@@ -163,6 +166,7 @@ pub async fn parse_subscribe(
                 protocol: protocol.clone(),
                 pkid,
                 sub_identifier,
+                publisher_allowlist: requested_publisher_allowlist(subscribe_properties),
                 filter: filter.clone(),
                 sub_name: "".to_string(),
                 group_name: "".to_string(),
@@ -176,6 +180,7 @@ pub async fn parse_subscribe(
             client_id,
             protocol,
             &sub_identifier,
+            subscribe_properties,
             filter,
         );
     }
@@ -186,8 +191,8 @@ async fn parse_share_subscribe(
     subscribe_manager: &Arc<SubscribeManager>,
     req: &mut ParseShareQueueSubscribeRequest,
 ) {
-    let (group_name, sub_name) = decode_share_info(&req.filter.path);
-    req.group_name = format!("{}_{}", group_name, sub_name);
+    let (group_name, sub_name) = share_group_key(&req.filter.path);
+    req.group_name = group_name;
     req.sub_name = sub_name;
     parse_share_queue_subscribe_common(client_pool, subscribe_manager, req).await;
 }
@@ -197,9 +202,8 @@ async fn parse_queue_subscribe(
     subscribe_manager: &Arc<SubscribeManager>,
     req: &mut ParseShareQueueSubscribeRequest,
 ) {
-    let sub_name = decode_queue_info(&req.filter.path);
-    // queueSub is a special shareSub
-    let group_name = format!("$queue_{}", sub_name);
+    // queueSub is a special shareSub, $queue/<topic> is parsed as $share/__queue__/<topic>
+    let (group_name, sub_name) = share_group_key(&req.filter.path);
     req.group_name = group_name;
     req.sub_name = sub_name;
     parse_share_queue_subscribe_common(client_pool, subscribe_manager, req).await;
@@ -246,6 +250,8 @@ async fn add_share_push_leader(
         retain_forward_rule: req.filter.retain_forward_rule.clone(),
         subscription_identifier: req.sub_identifier,
         sub_path: req.filter.path.clone(),
+        max_delivery_rate: None,
+        publisher_allowlist: req.publisher_allowlist.clone(),
     };
 
     subscribe_manager.add_topic_subscribe(&req.topic_name, &req.client_id, &req.filter.path);
@@ -280,6 +286,7 @@ fn add_exclusive_push(
     client_id: &str,
     protocol: &MqttProtocol,
     sub_identifier: &Option<usize>,
+    subscribe_properties: &Option<SubscribeProperties>,
     filter: &Filter,
 ) {
     if path_regex_match(&topic.topic_name, &filter.path) {
@@ -295,7 +302,16 @@ fn add_exclusive_push(
             retain_forward_rule: filter.retain_forward_rule.to_owned(),
             subscription_identifier: sub_identifier.to_owned(),
             sub_path: filter.path.to_owned(),
+            max_delivery_rate: requested_delivery_rate(subscribe_properties),
+            publisher_allowlist: requested_publisher_allowlist(subscribe_properties),
         };
+
+        // A re-subscribe of the same filter only changes the QoS, the Subscription
+        // Identifier, the delivery rate and the publisher allowlist of the running push
+        if subscribe_manager.update_exclusive_push(client_id, &filter.path, &topic.topic_id, &sub) {
+            return;
+        }
+
         subscribe_manager.add_topic_subscribe(&topic.topic_name, client_id, &filter.path);
         subscribe_manager.add_exclusive_push(client_id, &filter.path, &topic.topic_id, sub);
     }
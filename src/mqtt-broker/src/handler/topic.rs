@@ -13,24 +13,37 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
 use common_base::config::broker_mqtt::broker_mqtt_conf;
-use common_base::tools::unique_id;
+use common_base::error::common::CommonError;
+use common_base::tools::{now_mills, now_second, unique_id};
 use grpc_clients::pool::ClientPool;
+use log::{debug, info, warn};
 use metadata_struct::mqtt::topic::MqttTopic;
-use protocol::mqtt::common::{Publish, PublishProperties};
+use protocol::mqtt::common::{Publish, PublishProperties, QoS};
 use regex::Regex;
 use storage_adapter::storage::{ShardInfo, StorageAdapter};
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio::time::sleep;
 
 use super::error::MqttBrokerError;
 use crate::handler::cache::CacheManager;
 use crate::handler::topic_rewrite::process_publish_topic_rewrite;
+use crate::server::connection_manager::ConnectionManager;
 use crate::storage::message::cluster_name;
 use crate::storage::topic::TopicStorage;
 use crate::subscribe::sub_common::{
-    decode_queue_info, decode_share_info, is_queue_sub, is_share_sub,
+    decode_queue_info, decode_share_info, is_queue_sub, is_share_sub, publish_message_qos0,
 };
+use crate::subscribe::subscribe_manager::{SubscribeManager, TopicDeleteEvent};
+use crate::subscribe::subscriber::{SubPublishParam, Subscriber};
+
+pub const TOPIC_DELETED_EVENT_TOPIC_PREFIX: &str = "$robustmq/events/topic-deleted/";
+
+const IDLE_TOPIC_SHARD_RECLAIM_INTERVAL: Duration = Duration::from_secs(60);
 
 pub fn payload_format_validator(
     payload: &Bytes,
@@ -123,39 +136,253 @@ pub async fn try_init_topic<S>(
 where
     S: StorageAdapter + Sync + Send + 'static + Clone,
 {
-    let topic = if let Some(tp) = metadata_cache.get_topic_by_name(topic_name) {
-        tp
+    let (topic, _) = try_init_topic_with_created(
+        topic_name,
+        metadata_cache,
+        message_storage_adapter,
+        client_pool,
+    )
+    .await?;
+    Ok(topic)
+}
+
+// Same as try_init_topic, also returns whether the topic was created by this call.
+pub async fn try_init_topic_with_created<S>(
+    topic_name: &str,
+    metadata_cache: &Arc<CacheManager>,
+    message_storage_adapter: &Arc<S>,
+    client_pool: &Arc<ClientPool>,
+) -> Result<(MqttTopic, bool), MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    if let Some(tp) = metadata_cache.get_topic_by_name(topic_name) {
+        return Ok((tp, false));
+    }
+
+    let namespace = cluster_name();
+
+    // create Topic
+    let topic_storage = TopicStorage::new(client_pool.clone());
+    let topic_id = unique_id();
+    let conf = broker_mqtt_conf();
+    let (topic, created) = if let Some(topic) = topic_storage.get_topic(topic_name).await? {
+        (topic, false)
     } else {
-        let namespace = cluster_name();
-
-        // create Topic
-        let topic_storage = TopicStorage::new(client_pool.clone());
-        let topic_id = unique_id();
-        let conf = broker_mqtt_conf();
-        let topic = if let Some(topic) = topic_storage.get_topic(topic_name).await? {
-            topic
-        } else {
-            let topic = MqttTopic::new(topic_id, conf.cluster_name.clone(), topic_name.to_owned());
-            topic_storage.save_topic(topic.clone()).await?;
-            topic
+        let topic = MqttTopic::new(topic_id, conf.cluster_name.clone(), topic_name.to_owned());
+        topic_storage.save_topic(topic.clone()).await?;
+        (topic, true)
+    };
+    metadata_cache.add_topic(topic_name, &topic);
+
+    // Create the resource object of the storage layer
+    let list = message_storage_adapter
+        .list_shard(namespace.clone(), topic_name.to_owned())
+        .await?;
+    if list.is_empty() {
+        let shard = ShardInfo {
+            namespace: namespace.clone(),
+            shard_name: topic_name.to_owned(),
+            replica_num: 1,
         };
-        metadata_cache.add_topic(topic_name, &topic);
+        create_topic_shard(message_storage_adapter, shard).await?;
+    }
+    Ok((topic, created))
+}
 
-        // Create the resource object of the storage layer
-        let list = message_storage_adapter
-            .list_shard(namespace.clone(), topic_name.to_owned())
-            .await?;
-        if list.is_empty() {
-            let shard = ShardInfo {
-                namespace: namespace.clone(),
-                shard_name: topic_name.to_owned(),
-                replica_num: 1,
-            };
-            message_storage_adapter.create_shard(shard).await?;
+// Concurrent first publishers of a topic may all find no shard and race to create it,
+// the shard created by the winner is usable by the others.
+async fn create_topic_shard<S>(
+    message_storage_adapter: &Arc<S>,
+    shard: ShardInfo,
+) -> Result<(), MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static,
+{
+    match message_storage_adapter.create_shard(shard).await {
+        Ok(()) => Ok(()),
+        Err(CommonError::ShardAlreadyExists(shard_name, namespace)) => {
+            debug!(
+                "shard {} under namespace {} was created by another publisher",
+                shard_name, namespace
+            );
+            Ok(())
         }
-        return Ok(topic);
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Deletes the message shard of the topics without subscribers that were not published for
+// idle_topic_shard_expire_secs. The topic and its retained message are kept, so a new subscriber
+// still gets the retained message, and the shard is created again by the next publish.
+pub async fn reclaim_idle_topic_shards<S>(
+    cache_manager: &Arc<CacheManager>,
+    subscribe_manager: &Arc<SubscribeManager>,
+    message_storage_adapter: &Arc<S>,
+    now: u64,
+) -> Vec<String>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let expire_secs = cache_manager
+        .get_cluster_info()
+        .protocol
+        .idle_topic_shard_expire_secs;
+    if expire_secs == 0 {
+        return Vec::new();
+    }
+
+    let idle_topics: Vec<MqttTopic> = cache_manager
+        .topic_info
+        .iter()
+        .map(|topic| topic.value().clone())
+        .filter(|topic| {
+            !cache_manager
+                .reclaimed_topic_shard
+                .contains_key(&topic.topic_name)
+                && cache_manager.topic_last_active_time(topic) + expire_secs <= now
+                && !subscribe_manager.contain_topic_subscribe(&topic.topic_name)
+        })
+        .collect();
+
+    let mut reclaimed = Vec::new();
+    for topic in idle_topics {
+        if let Err(e) = message_storage_adapter
+            .delete_shard(cluster_name(), topic.topic_id.clone())
+            .await
+        {
+            warn!(
+                "Failed to delete the message shard of the idle topic {}, {}",
+                topic.topic_name, e
+            );
+            continue;
+        }
+        cache_manager
+            .reclaimed_topic_shard
+            .insert(topic.topic_name.clone(), now);
+        reclaimed.push(topic.topic_name);
+    }
+
+    if !reclaimed.is_empty() {
+        info!(
+            "The message shards of {} idle topics were deleted, their retained messages are kept",
+            reclaimed.len()
+        );
+    }
+    reclaimed
+}
+
+// Creates again the message shard of a topic whose shard was reclaimed while it was idle.
+pub async fn restore_reclaimed_topic_shard<S>(
+    cache_manager: &Arc<CacheManager>,
+    message_storage_adapter: &Arc<S>,
+    topic: &MqttTopic,
+) -> Result<(), MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    if !cache_manager
+        .reclaimed_topic_shard
+        .contains_key(&topic.topic_name)
+    {
+        return Ok(());
+    }
+
+    let shard = ShardInfo {
+        namespace: cluster_name(),
+        shard_name: topic.topic_id.clone(),
+        replica_num: 1,
     };
-    Ok(topic)
+    create_topic_shard(message_storage_adapter, shard).await?;
+    cache_manager
+        .reclaimed_topic_shard
+        .remove(&topic.topic_name);
+    Ok(())
+}
+
+pub async fn start_reclaim_idle_topic_shard_thread<S>(
+    cache_manager: Arc<CacheManager>,
+    subscribe_manager: Arc<SubscribeManager>,
+    message_storage_adapter: Arc<S>,
+    stop_send: broadcast::Sender<bool>,
+) where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    loop {
+        let mut stop_recv = stop_send.subscribe();
+        select! {
+            val = stop_recv.recv() =>{
+                if let Ok(flag) = val {
+                    if flag {
+                        debug!("{}","Idle topic shard reclaim thread exited successfully");
+                        break;
+                    }
+                }
+            }
+            _ = reclaim_idle_topic_shards(
+                &cache_manager,
+                &subscribe_manager,
+                &message_storage_adapter,
+                now_second(),
+            ) => {
+                sleep(IDLE_TOPIC_SHARD_RECLAIM_INTERVAL).await;
+            }
+        }
+    }
+}
+
+// Removes the deleted topic and its subscriptions, then tells each connected subscriber
+// with a QoS 0 message on $robustmq/events/topic-deleted/<topic_name>.
+pub async fn process_topic_deleted(
+    cache_manager: &Arc<CacheManager>,
+    connection_manager: &Arc<ConnectionManager>,
+    subscribe_manager: &Arc<SubscribeManager>,
+    topic: &MqttTopic,
+) {
+    cache_manager.delete_topic(&topic.topic_name, topic);
+
+    let event = TopicDeleteEvent {
+        topic_id: topic.topic_id.clone(),
+        topic_name: topic.topic_name.clone(),
+    };
+    let client_ids = subscribe_manager.remove_deleted_topic_subscribe(&event);
+    info!(
+        "Topic [{}] is deleted, the subscriptions of {} clients are cleaned up",
+        topic.topic_name,
+        client_ids.len()
+    );
+
+    let payload = match serde_json::to_vec(&event) {
+        Ok(payload) => Bytes::from(payload),
+        Err(e) => {
+            debug!("Failed to encode the topic deleted event, {}", e);
+            return;
+        }
+    };
+    let event_topic = format!("{}{}", TOPIC_DELETED_EVENT_TOPIC_PREFIX, topic.topic_name);
+    let (stop_sx, _) = broadcast::channel(1);
+    for client_id in client_ids {
+        // the offline subscribers are not notified
+        if cache_manager.get_connect_id(&client_id).is_none() {
+            continue;
+        }
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            pkid: 0,
+            retain: false,
+            topic: Bytes::from(event_topic.clone()),
+            payload: payload.clone(),
+        };
+        let subscriber = Subscriber {
+            client_id,
+            ..Default::default()
+        };
+        let sub_pub_param =
+            SubPublishParam::new(subscriber, publish, None, now_mills(), "".to_string(), 0);
+        publish_message_qos0(cache_manager, connection_manager, &sub_pub_param, &stop_sx).await;
+    }
 }
 
 pub fn gen_rewrite_topic(input: &str, pattern: &str, template: &str) -> Option<String> {
@@ -188,9 +415,36 @@ pub fn gen_rewrite_topic(input: &str, pattern: &str, template: &str) -> Option<S
 
 #[cfg(test)]
 mod test {
-    use super::topic_name_validator;
+    use std::sync::Arc;
+
+    use common_base::error::common::CommonError;
+    use common_base::tools::unique_id;
+    use storage_adapter::rocksdb::RocksDBStorageAdapter;
+    use storage_adapter::storage::{ShardInfo, StorageAdapter};
+
+    use super::{create_topic_shard, topic_name_validator};
     use crate::handler::error::MqttBrokerError;
 
+    #[tokio::test]
+    async fn create_topic_shard_already_exists_test() {
+        let db_path = format!("/tmp/robustmq_{}", unique_id());
+        let storage_adapter = Arc::new(RocksDBStorageAdapter::new(db_path.as_str(), 100));
+        let shard = ShardInfo {
+            namespace: unique_id(),
+            shard_name: "/test/create_shard".to_string(),
+            replica_num: 1,
+        };
+
+        // another publisher created the shard first
+        create_topic_shard(&storage_adapter, shard.clone())
+            .await
+            .unwrap();
+        let res = storage_adapter.create_shard(shard.clone()).await;
+        assert!(matches!(res, Err(CommonError::ShardAlreadyExists(_, _))));
+
+        create_topic_shard(&storage_adapter, shard).await.unwrap();
+    }
+
     #[test]
     pub fn topic_name_validator_test() {
         let topic_name = "".to_string();
@@ -218,3 +472,246 @@ mod test {
         topic_name_validator(&topic_name).unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use futures::StreamExt;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::adapter::record::Record;
+    use metadata_struct::mqtt::session::MqttSession;
+    use metadata_struct::mqtt::subscribe_data::MqttSubscribe;
+    use metadata_struct::mqtt::topic::MqttTopic;
+    use protocol::mqtt::codec::MqttCodec;
+    use protocol::mqtt::common::{Filter, MqttPacket, MqttProtocol, QoS, RetainForwardRule};
+    use storage_adapter::memory::MemoryStorageAdapter;
+    use storage_adapter::storage::{ShardInfo, StorageAdapter};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::broadcast;
+    use tokio::time::timeout;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    use super::{
+        process_topic_deleted, reclaim_idle_topic_shards, restore_reclaimed_topic_shard,
+        TOPIC_DELETED_EVENT_TOPIC_PREFIX,
+    };
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::server::connection::{NetworkConnection, NetworkConnectionType};
+    use crate::server::connection_manager::ConnectionManager;
+    use crate::storage::message::cluster_name;
+    use crate::subscribe::subscribe_manager::{SubscribeManager, TopicDeleteEvent};
+    use crate::subscribe::subscriber::Subscriber;
+
+    fn build_subscribe(client_id: &str, path: &str) -> MqttSubscribe {
+        MqttSubscribe {
+            client_id: client_id.to_owned(),
+            path: path.to_owned(),
+            cluster_name: "mqtt-broker".to_string(),
+            broker_id: 1,
+            protocol: MqttProtocol::Mqtt5,
+            filter: Filter {
+                path: path.to_owned(),
+                qos: QoS::AtMostOnce,
+                nolocal: false,
+                preserve_retain: false,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            },
+            pkid: 1,
+            subscribe_properties: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn process_topic_deleted_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+        let subscribe_manager = Arc::new(SubscribeManager::new());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client_stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server_stream, peer_addr) = listener.accept().await.unwrap();
+        let (_, w_stream) = tokio::io::split(server_stream);
+        let connection_id = connection_manager.add_connection(NetworkConnection::new(
+            NetworkConnectionType::Tcp,
+            peer_addr,
+            None,
+        ));
+        connection_manager.set_connect_protocol(connection_id, 5);
+        connection_manager.add_tcp_write(
+            connection_id,
+            FramedWrite::new(w_stream, MqttCodec::new(None)),
+        );
+        let mut read_stream = FramedRead::new(client_stream, MqttCodec::new(Some(5)));
+
+        let client_id = unique_id();
+        let mut session = MqttSession::new(client_id.clone(), 60, false, None);
+        session.connection_id = Some(connection_id);
+        cache_manager.add_session(client_id.clone(), session);
+
+        let topic_name = "/test/topic/delete".to_string();
+        let wildcard_path = "/test/topic/#".to_string();
+        let topic = MqttTopic::new(unique_id(), "mqtt-broker".to_string(), topic_name.clone());
+        cache_manager.add_topic(&topic_name, &topic);
+
+        subscribe_manager.add_subscribe(build_subscribe(&client_id, &topic_name));
+        subscribe_manager.add_subscribe(build_subscribe(&client_id, &wildcard_path));
+        for path in [&topic_name, &wildcard_path] {
+            let subscriber = Subscriber {
+                client_id: client_id.clone(),
+                sub_path: path.clone(),
+                topic_name: topic_name.clone(),
+                topic_id: topic.topic_id.clone(),
+                ..Default::default()
+            };
+            subscribe_manager.add_exclusive_push(&client_id, path, &topic.topic_id, subscriber);
+        }
+        let mut stop_rxs = Vec::new();
+        for (key, _) in subscribe_manager.exclusive_push.clone() {
+            let (stop_sx, stop_rx) = broadcast::channel(1);
+            subscribe_manager.exclusive_push_thread.insert(key, stop_sx);
+            stop_rxs.push(stop_rx);
+        }
+
+        process_topic_deleted(
+            &cache_manager,
+            &connection_manager,
+            &subscribe_manager,
+            &topic,
+        )
+        .await;
+
+        // the subscriber is told the topic is deleted
+        let packet = timeout(Duration::from_secs(10), read_stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let MqttPacket::Publish(publish, _) = packet else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(
+            publish.topic,
+            format!("{}{}", TOPIC_DELETED_EVENT_TOPIC_PREFIX, topic_name)
+        );
+        let event: TopicDeleteEvent = serde_json::from_slice(&publish.payload).unwrap();
+        assert_eq!(event.topic_id, topic.topic_id);
+        assert_eq!(event.topic_name, topic_name);
+
+        // the pushes of the topic are stopped and the subscription to it is removed,
+        // the wildcard subscription is kept for the other topics
+        for mut stop_rx in stop_rxs {
+            assert!(stop_rx.try_recv().unwrap());
+        }
+        assert!(subscribe_manager.exclusive_push.is_empty());
+        assert!(subscribe_manager.exclusive_push_thread.is_empty());
+        assert!(subscribe_manager
+            .get_subscribe(&client_id, &topic_name)
+            .is_none());
+        assert!(subscribe_manager
+            .get_subscribe(&client_id, &wildcard_path)
+            .is_some());
+        assert!(!cache_manager.topic_exists(&topic_name));
+    }
+
+    #[tokio::test]
+    async fn reclaim_idle_topic_shards_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.idle_topic_shard_expire_secs = 60;
+        cache_manager.set_cluster_info(cluster);
+        let subscribe_manager = Arc::new(SubscribeManager::new());
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+
+        let mut topics = Vec::new();
+        for topic_name in [
+            "/test/idle/retained",
+            "/test/idle/subscribed",
+            "/test/idle/active",
+        ] {
+            let mut topic = MqttTopic::new(
+                unique_id(),
+                "mqtt-broker".to_string(),
+                topic_name.to_owned(),
+            );
+            topic.retain_message = Some(b"retained".to_vec());
+            cache_manager.add_topic(topic_name, &topic);
+            let shard = ShardInfo {
+                namespace: cluster_name(),
+                shard_name: topic.topic_id.clone(),
+                replica_num: 1,
+            };
+            storage_adapter.create_shard(shard).await.unwrap();
+            storage_adapter
+                .write(
+                    cluster_name(),
+                    topic.topic_id.clone(),
+                    Record::build_byte(b"message".to_vec()),
+                )
+                .await
+                .unwrap();
+            topics.push(topic);
+        }
+        subscribe_manager.add_subscribe(build_subscribe(&unique_id(), "/test/idle/subscribed"));
+        let now = topics[0].create_time + 60;
+        cache_manager.report_topic_publish("/test/idle/active", now - 1);
+
+        // only the topic without subscribers and traffic is reclaimed
+        let reclaimed =
+            reclaim_idle_topic_shards(&cache_manager, &subscribe_manager, &storage_adapter, now)
+                .await;
+        assert_eq!(reclaimed, vec!["/test/idle/retained".to_string()]);
+        let shard_key = storage_adapter.shard_key(&cluster_name(), &topics[0].topic_id);
+        assert!(!storage_adapter.shard_data.contains_key(&shard_key));
+        for topic in &topics[1..] {
+            let key = storage_adapter.shard_key(&cluster_name(), &topic.topic_id);
+            assert!(storage_adapter.shard_data.contains_key(&key));
+        }
+
+        // the retained message is kept for the next subscriber
+        let topic = cache_manager
+            .get_topic_by_name("/test/idle/retained")
+            .unwrap();
+        assert_eq!(topic.retain_message, Some(b"retained".to_vec()));
+
+        // once idle long enough the active topic is reclaimed, the reclaimed one is not again
+        let reclaimed = reclaim_idle_topic_shards(
+            &cache_manager,
+            &subscribe_manager,
+            &storage_adapter,
+            now + 60,
+        )
+        .await;
+        assert_eq!(reclaimed, vec!["/test/idle/active".to_string()]);
+
+        // the next publish gets a new shard
+        restore_reclaimed_topic_shard(&cache_manager, &storage_adapter, &topic)
+            .await
+            .unwrap();
+        assert!(storage_adapter.shard_data.contains_key(&shard_key));
+        assert!(!cache_manager
+            .reclaimed_topic_shard
+            .contains_key("/test/idle/retained"));
+    }
+}
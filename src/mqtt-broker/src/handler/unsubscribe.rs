@@ -12,13 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{
-    cache::CacheManager, error::MqttBrokerError, sub_exclusive::remove_exclusive_subscribe,
-};
-use crate::subscribe::{
-    sub_common::{decode_share_info, is_share_sub, path_regex_match},
-    subscribe_manager::SubscribeManager,
-};
+use super::{error::MqttBrokerError, sub_exclusive::remove_exclusive_subscribe};
+use crate::subscribe::{sub_common::is_share_sub, subscribe_manager::SubscribeManager};
 use common_base::config::broker_mqtt::broker_mqtt_conf;
 use grpc_clients::{placement::mqtt::call::placement_delete_subscribe, pool::ClientPool};
 use protocol::{
@@ -31,7 +26,6 @@ pub async fn remove_subscribe(
     un_subscribe: &Unsubscribe,
     client_pool: &Arc<ClientPool>,
     subscribe_manager: &Arc<SubscribeManager>,
-    cache_manager: &Arc<CacheManager>,
 ) -> Result<(), MqttBrokerError> {
     let conf = broker_mqtt_conf();
 
@@ -48,81 +42,162 @@ pub async fn remove_subscribe(
 
     remove_exclusive_subscribe(subscribe_manager, un_subscribe.clone());
 
-    unsubscribe_by_path(
-        cache_manager,
-        subscribe_manager,
-        client_id,
-        &un_subscribe.filters,
-    )?;
+    unsubscribe_by_path(subscribe_manager, client_id, &un_subscribe.filters)?;
 
     Ok(())
 }
 
+// The pushes are found by the filter the client subscribed to, wildcards included, so that a
+// filter that matches no topic, or a topic that is gone, is still unsubscribed.
 fn unsubscribe_by_path(
-    cache_manager: &Arc<CacheManager>,
     subscribe_manager: &Arc<SubscribeManager>,
     client_id: &str,
     filter_path: &[String],
 ) -> Result<(), MqttBrokerError> {
-    for (topic_name, _) in cache_manager.topic_info.clone() {
-        for path in filter_path {
-            if !path_regex_match(&topic_name, path) {
-                continue;
-            }
-
-            if is_share_sub(path) {
-                let (group_name, sub_name) = decode_share_info(path);
-                // share leader
-                for (key, data) in subscribe_manager.share_leader_push.clone() {
-                    let mut flag = false;
-                    for (sub_key, share_sub) in data.sub_list {
-                        if share_sub.client_id == *client_id
-                            && (share_sub.group_name.is_some()
-                                && share_sub.group_name.unwrap() == group_name)
-                            && share_sub.sub_path == sub_name
-                        {
-                            let mut_data =
-                                subscribe_manager.share_leader_push.get_mut(&key).unwrap();
-                            mut_data.sub_list.remove(&sub_key);
-                            subscribe_manager.remove_topic_subscribe_by_path(
-                                &share_sub.topic_name,
-                                &share_sub.sub_path,
-                            );
-                            flag = true;
-                        }
-                    }
-
-                    if flag {
-                        if let Some(sx) = subscribe_manager.share_leader_push_thread.get(&key) {
-                            sx.send(true)?;
-                        }
+    for path in filter_path {
+        if is_share_sub(path) {
+            // share leader, the push of the group goes on for its other members and is
+            // stopped by the leader once the group has none
+            for (key, data) in subscribe_manager.share_leader_push.clone() {
+                for (sub_key, share_sub) in data.sub_list {
+                    if share_sub.client_id == *client_id && share_sub.sub_path == *path {
+                        let mut_data = subscribe_manager.share_leader_push.get_mut(&key).unwrap();
+                        mut_data.sub_list.remove(&sub_key);
+                        subscribe_manager.remove_topic_subscribe_by_path(
+                            &share_sub.topic_name,
+                            &share_sub.sub_path,
+                        );
                     }
                 }
+            }
 
-                // share follower
-                for (key, data) in subscribe_manager.share_follower_resub.clone() {
-                    if data.client_id == *client_id && data.filter.path == *path {
-                        subscribe_manager.share_follower_resub.remove(&key);
-                        if let Some(sx) = subscribe_manager.share_follower_resub_thread.get(&key) {
-                            sx.send(true)?;
-                        }
+            // share follower
+            for (key, data) in subscribe_manager.share_follower_resub.clone() {
+                if data.client_id == *client_id && data.filter.path == *path {
+                    subscribe_manager.share_follower_resub.remove(&key);
+                    if let Some(sx) = subscribe_manager.share_follower_resub_thread.get(&key) {
+                        sx.send(true)?;
                     }
                 }
-            } else {
-                for (key, subscriber) in subscribe_manager.exclusive_push.clone() {
-                    if subscriber.client_id == *client_id && subscriber.sub_path == *path {
-                        if let Some(sx) = subscribe_manager.exclusive_push_thread.get(&key) {
-                            sx.send(true)?;
-                            subscribe_manager.exclusive_push.remove(&key);
-                        }
-                        subscribe_manager.remove_topic_subscribe_by_path(
-                            &subscriber.topic_name,
-                            &subscriber.sub_path,
-                        );
+            }
+        } else {
+            for (key, subscriber) in subscribe_manager.exclusive_push.clone() {
+                if subscriber.client_id == *client_id && subscriber.sub_path == *path {
+                    if let Some(sx) = subscribe_manager.exclusive_push_thread.get(&key) {
+                        sx.send(true)?;
+                        subscribe_manager.exclusive_push.remove(&key);
                     }
+                    subscribe_manager.remove_topic_subscribe_by_path(
+                        &subscriber.topic_name,
+                        &subscriber.sub_path,
+                    );
                 }
             }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use protocol::mqtt::common::QoS;
+    use tokio::sync::broadcast;
+
+    use super::unsubscribe_by_path;
+    use crate::subscribe::subscribe_manager::SubscribeManager;
+    use crate::subscribe::subscriber::Subscriber;
+
+    #[tokio::test]
+    async fn unsubscribe_by_filter_test() {
+        let subscribe_manager = Arc::new(SubscribeManager::new());
+        let client_id = "client-1";
+
+        // pushes of wildcard filters, their topics are not known to the cache
+        let exclusive_path = "sensors/+/temp";
+        let exclusive = Subscriber {
+            client_id: client_id.to_string(),
+            sub_path: exclusive_path.to_string(),
+            topic_name: "sensors/a/temp".to_string(),
+            topic_id: "topic-1".to_string(),
+            qos: QoS::AtLeastOnce,
+            ..Default::default()
+        };
+        subscribe_manager.add_exclusive_push(client_id, exclusive_path, "topic-1", exclusive);
+        let (stop_sx, mut stop_rx) = broadcast::channel(1);
+        let exclusive_key = subscribe_manager
+            .exclusive_push
+            .iter()
+            .next()
+            .unwrap()
+            .key()
+            .clone();
+        subscribe_manager
+            .exclusive_push_thread
+            .insert(exclusive_key, stop_sx);
+
+        let share_path = "$share/group1/sensors/#";
+        let share = Subscriber {
+            client_id: client_id.to_string(),
+            sub_path: share_path.to_string(),
+            topic_name: "sensors/b".to_string(),
+            topic_id: "topic-2".to_string(),
+            group_name: Some("group1_sensors/#".to_string()),
+            ..Default::default()
+        };
+        subscribe_manager.add_share_subscribe_leader("sensors/#", share);
+
+        unsubscribe_by_path(
+            &subscribe_manager,
+            client_id,
+            &[exclusive_path.to_string(), share_path.to_string()],
+        )
+        .unwrap();
+
+        assert!(subscribe_manager.exclusive_push.is_empty());
+        assert!(stop_rx.try_recv().unwrap());
+        for data in subscribe_manager.share_leader_push.iter() {
+            assert!(data.sub_list.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_share_member_test() {
+        let subscribe_manager = Arc::new(SubscribeManager::new());
+        let share_path = "$share/group1/sensors/#";
+        for client_id in ["client-1", "client-2"] {
+            let share = Subscriber {
+                client_id: client_id.to_string(),
+                sub_path: share_path.to_string(),
+                topic_name: "sensors/b".to_string(),
+                topic_id: "topic-2".to_string(),
+                group_name: Some("group1_sensors/#".to_string()),
+                ..Default::default()
+            };
+            subscribe_manager.add_share_subscribe_leader("sensors/#", share);
+        }
+        let share_leader_key = subscribe_manager
+            .share_leader_push
+            .iter()
+            .next()
+            .unwrap()
+            .key()
+            .clone();
+        let (stop_sx, mut stop_rx) = broadcast::channel(1);
+        subscribe_manager
+            .share_leader_push_thread
+            .insert(share_leader_key.clone(), stop_sx);
+
+        unsubscribe_by_path(&subscribe_manager, "client-1", &[share_path.to_string()]).unwrap();
+
+        // the other member of the group is still pushed to
+        let data = subscribe_manager
+            .share_leader_push
+            .get(&share_leader_key)
+            .unwrap();
+        assert_eq!(data.sub_list.len(), 1);
+        assert!(data.sub_list.iter().all(|sub| sub.client_id == "client-2"));
+        assert!(stop_rx.try_recv().is_err());
+    }
+}
@@ -73,10 +73,15 @@ impl UpdateUserCache {
 
 pub async fn init_system_user(cache_manager: &Arc<CacheManager>, client_pool: &Arc<ClientPool>) {
     let conf = broker_mqtt_conf();
-    let system_user_info = MqttUser {
-        username: conf.system.default_user.clone(),
-        password: conf.system.default_password.clone(),
-        is_superuser: true,
+    let system_user_info = match MqttUser::new(
+        conf.system.default_user.clone(),
+        &conf.system.default_password,
+        true,
+    ) {
+        Ok(user) => user,
+        Err(e) => {
+            panic!("{}", e.to_string());
+        }
     };
     let user_storage = UserStorage::new(client_pool.clone());
     match user_storage.save_user(system_user_info.clone()).await {
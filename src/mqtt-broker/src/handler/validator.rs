@@ -15,15 +15,19 @@
 use futures_util::SinkExt;
 use grpc_clients::pool::ClientPool;
 use log::error;
-use metadata_struct::mqtt::cluster::MqttClusterDynamicConfig;
+use metadata_struct::mqtt::cluster::{AvailableFlag, MqttClusterDynamicConfig};
 use metadata_struct::mqtt::connection::MQTTConnection;
 use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
 use protocol::mqtt::common::{
     Connect, ConnectProperties, ConnectReturnCode, DisconnectReasonCode, LastWill,
     LastWillProperties, Login, MqttPacket, MqttProtocol, PubAckReason, PubRecReason, Publish,
-    PublishProperties, QoS, Subscribe, SubscribeReasonCode, UnsubAckReason, Unsubscribe,
+    PublishProperties, QoS, Subscribe, SubscribeProperties, SubscribeReasonCode, UnsubAckReason,
+    Unsubscribe,
 };
+use protocol::mqtt::mqttv5::connect::properties::len as connect_properties_len;
+use regex::Regex;
 use std::cmp::min;
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::io::{AsyncWrite, AsyncWriteExt, WriteHalf};
@@ -39,13 +43,14 @@ use super::pkid::pkid_exists;
 use super::response::{
     response_packet_mqtt_connect_fail, response_packet_mqtt_distinct_by_reason,
     response_packet_mqtt_puback_fail, response_packet_mqtt_pubrec_fail,
-    response_packet_mqtt_suback, response_packet_mqtt_unsuback,
+    response_packet_mqtt_pubrec_success, response_packet_mqtt_suback,
+    response_packet_mqtt_unsuback,
 };
 use super::sub_exclusive::check_exclusive_subscribe;
 use super::topic::topic_name_validator;
-use crate::security::AuthDriver;
+use crate::observability::metrics::publish::{record_message_dropped, MessageDropReason};
 use crate::server::connection_manager::ConnectionManager;
-use crate::subscribe::sub_common::sub_path_validator;
+use crate::subscribe::sub_common::{decode_share_info, is_share_sub, sub_path_validator};
 use crate::subscribe::subscribe_manager::SubscribeManager;
 
 pub async fn tcp_establish_connection_check(
@@ -62,6 +67,12 @@ pub async fn tcp_establish_connection_check(
     if let Some(value) = handle_connection_rate_exceeded(addr, write_frame_stream).await {
         return value;
     }
+
+    if let Some(value) =
+        handle_total_connection_exceeded(addr, connection_manager, write_frame_stream).await
+    {
+        return value;
+    }
     true
 }
 
@@ -83,6 +94,12 @@ pub async fn tcp_tls_establish_connection_check(
         return value;
     }
 
+    if let Some(value) =
+        handle_total_connection_exceeded(addr, connection_manager, write_frame_stream).await
+    {
+        return value;
+    }
+
     true
 }
 
@@ -155,9 +172,72 @@ where
     None
 }
 
+// Checked last, the connection is counted in the total once it is accepted
+async fn handle_total_connection_exceeded<T>(
+    addr: &SocketAddr,
+    connection_manager: &Arc<ConnectionManager>,
+    write_frame_stream: &mut FramedWrite<WriteHalf<T>, MqttCodec>,
+) -> Option<bool>
+where
+    T: AsyncWriteExt + AsyncWrite,
+{
+    if !connection_manager.try_acquire_total_connection() {
+        let packet_wrapper = MqttPacketWrapper {
+            protocol_version: MqttProtocol::Mqtt5.into(),
+            packet: response_packet_mqtt_connect_fail(
+                &MqttProtocol::Mqtt5,
+                ConnectReturnCode::ServerUnavailable,
+                &None,
+                Some(MqttBrokerError::TotalConnectionsExceeded.to_string()),
+            ),
+        };
+        match write_frame_stream.send(packet_wrapper).await {
+            Ok(_) => {}
+            Err(e) => error!("{}", e),
+        }
+
+        match write_frame_stream.close().await {
+            Ok(_) => {
+                error!(
+                    "tcp connection failed to establish from IP: {}, the broker reached the maximum number of connections",
+                    addr.to_string()
+                );
+            }
+            Err(e) => error!("{}", e),
+        }
+        return Some(false);
+    }
+    None
+}
+
+fn connect_properties_validator(
+    cluster: &MqttClusterDynamicConfig,
+    properties: &ConnectProperties,
+) -> Result<(), MqttBrokerError> {
+    let max_user_properties = cluster.protocol.max_connect_user_properties as usize;
+    if max_user_properties > 0 && properties.user_properties.len() > max_user_properties {
+        return Err(MqttBrokerError::ConnectPropertiesTooLarge(format!(
+            "{} user properties, the maximum is {}",
+            properties.user_properties.len(),
+            max_user_properties
+        )));
+    }
+
+    let max_size = cluster.protocol.max_connect_properties_size as usize;
+    let size = connect_properties_len(properties);
+    if max_size > 0 && size > max_size {
+        return Err(MqttBrokerError::ConnectPropertiesTooLarge(format!(
+            "{} bytes, the maximum is {}",
+            size, max_size
+        )));
+    }
+    Ok(())
+}
+
 pub fn connect_validator(
     protocol: &MqttProtocol,
     cluster: &MqttClusterDynamicConfig,
+    client_id_regex: Option<&Regex>,
     connect: &Connect,
     connect_properties: &Option<ConnectProperties>,
     last_will: &Option<LastWill>,
@@ -173,7 +253,21 @@ pub fn connect_validator(
         ));
     }
 
-    if !connect.client_id.is_empty() && !client_id_validator(&connect.client_id) {
+    // Checked before anything else is done with the CONNECT, e.g. the authentication
+    if let Some(properties) = connect_properties {
+        if let Err(e) = connect_properties_validator(cluster, properties) {
+            return Some(response_packet_mqtt_connect_fail(
+                protocol,
+                ConnectReturnCode::PacketTooLarge,
+                connect_properties,
+                Some(e.to_string()),
+            ));
+        }
+    }
+
+    if !connect.client_id.is_empty()
+        && !client_id_validator(cluster, client_id_regex, &connect.client_id)
+    {
         return Some(response_packet_mqtt_connect_fail(
             protocol,
             ConnectReturnCode::ClientIdentifierNotValid,
@@ -218,15 +312,43 @@ pub fn connect_validator(
         match topic_name_validator(&topic_name) {
             Ok(()) => {}
             Err(e) => {
-                response_packet_mqtt_connect_fail(
+                return Some(response_packet_mqtt_connect_fail(
                     protocol,
                     ConnectReturnCode::TopicNameInvalid,
                     connect_properties,
                     Some(e.to_string()),
-                );
+                ));
             }
         }
 
+        // The will topic is a topic name, so it must not contain wildcard characters
+        if topic_name.contains('+') || topic_name.contains('#') {
+            return Some(response_packet_mqtt_connect_fail(
+                protocol,
+                ConnectReturnCode::TopicNameInvalid,
+                connect_properties,
+                Some(MqttBrokerError::TopicNameIncorrectlyFormatted(topic_name).to_string()),
+            ));
+        }
+
+        if will.qos > cluster.protocol.max_qos {
+            return Some(response_packet_mqtt_connect_fail(
+                protocol,
+                ConnectReturnCode::QoSNotSupported,
+                connect_properties,
+                None,
+            ));
+        }
+
+        if will.retain && cluster.feature.retain_available == AvailableFlag::Disable {
+            return Some(response_packet_mqtt_connect_fail(
+                protocol,
+                ConnectReturnCode::RetainNotSupported,
+                connect_properties,
+                None,
+            ));
+        }
+
         if will.message.is_empty() {
             return Some(response_packet_mqtt_connect_fail(
                 protocol,
@@ -284,6 +406,15 @@ pub async fn publish_validator(
         .await
         {
             Ok(res) => {
+                // A redelivery whose PUBREC was lost, the message was already stored
+                if res && publish.dup {
+                    return Some(response_packet_mqtt_pubrec_success(
+                        protocol,
+                        PubRecReason::Success,
+                        publish.pkid,
+                        Vec::new(),
+                    ));
+                }
                 if res {
                     return Some(response_packet_mqtt_pubrec_fail(
                         protocol,
@@ -310,6 +441,7 @@ pub async fn publish_validator(
     let max_packet_size =
         min(cluster.protocol.max_packet_size, connection.max_packet_size) as usize;
     if publish.payload.len() > max_packet_size {
+        record_message_dropped(MessageDropReason::Oversized, publish.qos, 1);
         if is_puback {
             return Some(response_packet_mqtt_puback_fail(
                 protocol,
@@ -358,6 +490,7 @@ pub async fn publish_validator(
     if is_qos_message(publish.qos)
         && connection.get_recv_qos_message() >= cluster.protocol.receive_max as isize
     {
+        record_message_dropped(MessageDropReason::Overflow, publish.qos, 1);
         if is_puback {
             return Some(response_packet_mqtt_puback_fail(
                 protocol,
@@ -380,24 +513,11 @@ pub async fn publish_validator(
     if let Some(properties) = publish_properties {
         if let Some(alias) = properties.topic_alias {
             let cluster = cache_manager.get_cluster_info();
-            if alias > cluster.protocol.topic_alias_max {
-                if is_puback {
-                    return Some(response_packet_mqtt_puback_fail(
-                        protocol,
-                        connection,
-                        publish.pkid,
-                        PubAckReason::UnspecifiedError,
-                        Some(MqttBrokerError::TopicAliasTooLong(alias).to_string()),
-                    ));
-                } else {
-                    return Some(response_packet_mqtt_pubrec_fail(
-                        protocol,
-                        connection,
-                        publish.pkid,
-                        PubRecReason::UnspecifiedError,
-                        Some(MqttBrokerError::TopicAliasTooLong(alias).to_string()),
-                    ));
-                }
+            if alias == 0 || alias > cluster.protocol.topic_alias_max {
+                return Some(response_packet_mqtt_distinct_by_reason(
+                    protocol,
+                    Some(DisconnectReasonCode::TopicAliasInvalid),
+                ));
             }
         }
     }
@@ -405,13 +525,16 @@ pub async fn publish_validator(
     None
 }
 
+const SUBSCRIPTION_IDENTIFIER_MAX: usize = 268_435_455;
+
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe_validator(
     protocol: &MqttProtocol,
-    auth_driver: &Arc<AuthDriver>,
     metadata_cache: &Arc<CacheManager>,
     subscribe_manager: &Arc<SubscribeManager>,
     connection: &MQTTConnection,
     subscribe: &Subscribe,
+    subscribe_properties: &Option<SubscribeProperties>,
 ) -> Option<MqttPacket> {
     let mut return_codes: Vec<SubscribeReasonCode> = Vec::new();
     for filter in subscribe.filters.clone() {
@@ -430,6 +553,10 @@ pub async fn subscribe_validator(
         ));
     }
 
+    if let Some(packet) = subscription_identifier_validator(protocol, subscribe_properties) {
+        return Some(packet);
+    }
+
     if is_subscribe_rate_exceeded() {
         return Some(response_packet_mqtt_suback(
             protocol,
@@ -440,6 +567,16 @@ pub async fn subscribe_validator(
         ));
     }
 
+    if let Some(packet) = share_subscription_quota_validator(
+        protocol,
+        metadata_cache,
+        subscribe_manager,
+        connection,
+        subscribe,
+    ) {
+        return Some(packet);
+    }
+
     if !check_exclusive_subscribe(metadata_cache, subscribe_manager, subscribe) {
         return Some(response_packet_mqtt_suback(
             protocol,
@@ -450,19 +587,92 @@ pub async fn subscribe_validator(
         ));
     }
 
-    if !auth_driver.allow_subscribe(connection, subscribe).await {
-        return Some(response_packet_mqtt_suback(
+    None
+}
+
+// The Subscription Identifier must be in 1..268435455. Several subscriptions of a client may
+// share an identifier, every PUBLISH then carries the identifier of the matching subscription.
+fn subscription_identifier_validator(
+    protocol: &MqttProtocol,
+    subscribe_properties: &Option<SubscribeProperties>,
+) -> Option<MqttPacket> {
+    let identifier = subscribe_properties
+        .as_ref()
+        .and_then(|properties| properties.subscription_identifier)?;
+
+    if identifier == 0 || identifier > SUBSCRIPTION_IDENTIFIER_MAX {
+        return Some(response_packet_mqtt_distinct_by_reason(
             protocol,
-            connection,
-            subscribe.packet_identifier,
-            vec![SubscribeReasonCode::NotAuthorized],
-            None,
+            Some(DisconnectReasonCode::ProtocolError),
         ));
     }
 
     None
 }
 
+// The SUBSCRIBE must not create more shared subscription groups than allowed,
+// nor add a client to a shared subscription group that already has the maximum of members.
+fn share_subscription_quota_validator(
+    protocol: &MqttProtocol,
+    metadata_cache: &Arc<CacheManager>,
+    subscribe_manager: &Arc<SubscribeManager>,
+    connection: &MQTTConnection,
+    subscribe: &Subscribe,
+) -> Option<MqttPacket> {
+    let cluster = metadata_cache.get_cluster_info();
+    let max_groups = cluster.protocol.max_shared_subscription_groups;
+    let max_members = cluster.protocol.max_shared_subscription_group_members;
+    if max_groups == 0 && max_members == 0 {
+        return None;
+    }
+
+    let mut new_groups = HashSet::new();
+    for filter in subscribe.filters.iter() {
+        if !is_share_sub(&filter.path) {
+            continue;
+        }
+
+        let (group_name, _) = decode_share_info(&filter.path);
+        let reason = if !subscribe_manager
+            .share_group_members
+            .contains_key(&group_name)
+        {
+            new_groups.insert(group_name);
+            if max_groups > 0
+                && subscribe_manager.share_group_num() + new_groups.len() > max_groups as usize
+            {
+                Some(MqttBrokerError::SharedSubscriptionGroupsExceeded(
+                    max_groups,
+                ))
+            } else {
+                None
+            }
+        } else if max_members > 0
+            && !subscribe_manager.is_share_group_member(&group_name, &connection.client_id)
+            && subscribe_manager.share_group_member_num(&group_name) >= max_members as usize
+        {
+            Some(MqttBrokerError::SharedSubscriptionGroupMembersExceeded(
+                group_name,
+                max_members,
+            ))
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            return Some(response_packet_mqtt_suback(
+                protocol,
+                connection,
+                subscribe.packet_identifier,
+                vec![SubscribeReasonCode::QuotaExceeded; subscribe.filters.len()],
+                Some(reason.to_string()),
+            ));
+        }
+    }
+
+    None
+}
+
 pub async fn un_subscribe_validator(
     client_id: &str,
     subscribe_manager: &Arc<SubscribeManager>,
@@ -520,11 +730,24 @@ pub fn connection_max_packet_size(
     cluster.protocol.max_packet_size
 }
 
-pub fn client_id_validator(client_id: &str) -> bool {
-    if client_id.len() == 5 && client_id.len() > 23 {
+// client_id_regex is client_id_allowed_regex of the cluster compiled when the config was set,
+// see CacheManager::get_client_id_allowed_regex
+pub fn client_id_validator(
+    cluster: &MqttClusterDynamicConfig,
+    client_id_regex: Option<&Regex>,
+    client_id: &str,
+) -> bool {
+    let max_len = cluster.protocol.client_id_max_len;
+    if max_len > 0 && client_id.len() > max_len as usize {
         return false;
     }
-    true
+
+    if cluster.protocol.client_id_allowed_regex.is_empty() {
+        return true;
+    }
+
+    // a pattern that failed to compile refuses every client id
+    client_id_regex.is_some_and(|re| re.is_match(client_id))
 }
 
 pub fn username_validator(username: &str) -> bool {
@@ -543,6 +766,357 @@ pub fn password_validator(password: &str) -> bool {
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::mqtt::cluster::{AvailableFlag, MqttClusterDynamicConfig};
+    use metadata_struct::mqtt::connection::MQTTConnection;
+    use protocol::mqtt::common::{
+        Connect, ConnectProperties, ConnectReturnCode, DisconnectReasonCode, LastWill, MqttPacket,
+        MqttProtocol, Publish, PublishProperties, QoS,
+    };
+
+    use super::{client_id_validator, connect_validator, publish_validator};
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::{build_client_id_regex, build_default_cluster_config};
+    use crate::handler::response::response_packet_mqtt_connect_success;
+
     #[test]
     pub fn topic_name_validator_test() {}
+
+    #[test]
+    pub fn client_id_validator_test() {
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.client_id_max_len = 8;
+        cluster.protocol.client_id_allowed_regex = r"^[a-zA-Z0-9_-]+$".to_string();
+
+        let client_id_regex = build_client_id_regex(&cluster).unwrap();
+
+        assert!(client_id_validator(
+            &cluster,
+            client_id_regex.as_ref(),
+            "client-1"
+        ));
+        assert!(!client_id_validator(
+            &cluster,
+            client_id_regex.as_ref(),
+            "client-123"
+        ));
+        assert!(!client_id_validator(
+            &cluster,
+            client_id_regex.as_ref(),
+            "c/1#"
+        ));
+
+        cluster.protocol.client_id_max_len = 0;
+        cluster.protocol.client_id_allowed_regex = "".to_string();
+        assert!(client_id_validator(
+            &cluster,
+            None,
+            "any/client#id+with-no-limit"
+        ));
+    }
+
+    #[test]
+    pub fn client_id_invalid_regex_test() {
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.client_id_allowed_regex = "[a-z".to_string();
+        assert!(build_client_id_regex(&cluster).is_err());
+
+        // the cache keeps no regex for the invalid pattern, and the client ids are refused
+        let cache_manager = CacheManager::new(Arc::new(ClientPool::new(1)), "test".to_string());
+        cache_manager.set_cluster_info(cluster.clone());
+        let client_id_regex = cache_manager.get_client_id_allowed_regex();
+        assert!(client_id_regex.is_none());
+        assert!(!client_id_validator(
+            &cluster,
+            client_id_regex.as_ref(),
+            "client-1"
+        ));
+
+        cluster.protocol.client_id_allowed_regex = r"^[a-z0-9-]+$".to_string();
+        cache_manager.set_cluster_info(cluster.clone());
+        let client_id_regex = cache_manager.get_client_id_allowed_regex();
+        assert!(client_id_validator(
+            &cluster,
+            client_id_regex.as_ref(),
+            "client-1"
+        ));
+    }
+
+    #[test]
+    pub fn connect_validator_client_id_test() {
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.client_id_max_len = 8;
+        cluster.protocol.client_id_allowed_regex = r"^[a-zA-Z0-9_-]+$".to_string();
+        let client_id_regex = build_client_id_regex(&cluster).unwrap();
+
+        for client_id in ["client-123", "c/1#"] {
+            let connect = Connect {
+                keep_alive: 60,
+                client_id: client_id.to_string(),
+                clean_session: true,
+            };
+            let res = connect_validator(
+                &MqttProtocol::Mqtt5,
+                &cluster,
+                client_id_regex.as_ref(),
+                &connect,
+                &None,
+                &None,
+                &None,
+                &None,
+            );
+            match res {
+                Some(MqttPacket::ConnAck(ack, _)) => {
+                    assert_eq!(ack.code, ConnectReturnCode::ClientIdentifierNotValid);
+                }
+                _ => panic!("client id {} should be rejected", client_id),
+            }
+        }
+    }
+
+    #[test]
+    pub fn connect_validator_properties_size_test() {
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.max_connect_properties_size = 1024;
+        cluster.protocol.max_connect_user_properties = 4;
+        let connect = Connect {
+            keep_alive: 60,
+            client_id: "client-123".to_string(),
+            clean_session: true,
+        };
+        let client_id_regex = build_client_id_regex(&cluster).unwrap();
+        let validate = |properties: ConnectProperties| {
+            connect_validator(
+                &MqttProtocol::Mqtt5,
+                &cluster,
+                client_id_regex.as_ref(),
+                &connect,
+                &Some(properties),
+                &None,
+                &None,
+                &None,
+            )
+        };
+
+        let properties = ConnectProperties {
+            user_properties: vec![("k".to_string(), "v".to_string())],
+            authentication_data: Some(Bytes::from(vec![0u8; 512])),
+            ..Default::default()
+        };
+        assert!(validate(properties).is_none());
+
+        // rejected before the authentication data is looked at
+        let oversized = [
+            ConnectProperties {
+                authentication_data: Some(Bytes::from(vec![0u8; 64 * 1024])),
+                ..Default::default()
+            },
+            ConnectProperties {
+                user_properties: vec![("k".repeat(600), "v".repeat(600))],
+                ..Default::default()
+            },
+            ConnectProperties {
+                user_properties: (0..5).map(|i| (i.to_string(), i.to_string())).collect(),
+                ..Default::default()
+            },
+        ];
+        for properties in oversized {
+            match validate(properties) {
+                Some(MqttPacket::ConnAck(ack, _)) => {
+                    assert_eq!(ack.code, ConnectReturnCode::PacketTooLarge);
+                }
+                res => panic!("the CONNECT should be rejected, got {:?}", res),
+            }
+        }
+
+        // unlimited by default
+        cluster.protocol.max_connect_properties_size = 0;
+        cluster.protocol.max_connect_user_properties = 0;
+        let properties = ConnectProperties {
+            user_properties: (0..100).map(|i| (i.to_string(), i.to_string())).collect(),
+            authentication_data: Some(Bytes::from(vec![0u8; 64 * 1024])),
+            ..Default::default()
+        };
+        assert!(connect_validator(
+            &MqttProtocol::Mqtt5,
+            &cluster,
+            build_client_id_regex(&cluster).unwrap().as_ref(),
+            &connect,
+            &Some(properties),
+            &None,
+            &None,
+            &None,
+        )
+        .is_none());
+    }
+
+    #[tokio::test]
+    pub async fn publish_validator_topic_alias_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), "test".to_string()));
+        let cluster = build_default_cluster_config();
+        cache_manager.set_cluster_info(cluster.clone());
+
+        let topic_alias_max = match response_packet_mqtt_connect_success(
+            &MqttProtocol::Mqtt5,
+            &cluster,
+            "client-1".to_string(),
+            false,
+            60,
+            false,
+            60,
+            &None,
+        ) {
+            MqttPacket::ConnAck(_, Some(properties)) => properties.topic_alias_max.unwrap(),
+            _ => panic!("connack properties should be returned"),
+        };
+        assert_eq!(topic_alias_max, 32);
+
+        let connect_id = 1;
+        let connection = MQTTConnection {
+            connect_id,
+            client_id: "client-1".to_string(),
+            max_packet_size: 1024 * 1024,
+            ..Default::default()
+        };
+        cache_manager
+            .connection_info
+            .insert(connect_id, connection.clone());
+
+        let publish = Publish {
+            qos: QoS::AtLeastOnce,
+            pkid: 1,
+            topic: Bytes::from("/test/topic"),
+            payload: Bytes::from("data"),
+            ..Default::default()
+        };
+
+        for alias in 1..=topic_alias_max {
+            let publish_properties = Some(PublishProperties {
+                topic_alias: Some(alias),
+                ..Default::default()
+            });
+            let res = publish_validator(
+                &MqttProtocol::Mqtt5,
+                &cache_manager,
+                &client_pool,
+                &connection,
+                &publish,
+                &publish_properties,
+            )
+            .await;
+            assert!(res.is_none());
+            cache_manager.add_topic_alias(connect_id, "/test/topic", &publish_properties);
+        }
+        assert_eq!(
+            cache_manager
+                .connection_info
+                .get(&connect_id)
+                .unwrap()
+                .topic_alias
+                .len(),
+            topic_alias_max as usize
+        );
+
+        let publish_properties = Some(PublishProperties {
+            topic_alias: Some(topic_alias_max + 1),
+            ..Default::default()
+        });
+        let res = publish_validator(
+            &MqttProtocol::Mqtt5,
+            &cache_manager,
+            &client_pool,
+            &connection,
+            &publish,
+            &publish_properties,
+        )
+        .await;
+        match res {
+            Some(MqttPacket::Disconnect(disconnect, _)) => {
+                assert_eq!(
+                    disconnect.reason_code,
+                    Some(DisconnectReasonCode::TopicAliasInvalid)
+                );
+            }
+            _ => panic!("topic alias {} should be rejected", topic_alias_max + 1),
+        }
+    }
+
+    fn connect_validator_last_will_code(
+        cluster: &MqttClusterDynamicConfig,
+        last_will: LastWill,
+    ) -> Option<ConnectReturnCode> {
+        let connect = Connect {
+            keep_alive: 60,
+            client_id: "client-1".to_string(),
+            clean_session: true,
+        };
+        match connect_validator(
+            &MqttProtocol::Mqtt5,
+            cluster,
+            build_client_id_regex(cluster).unwrap().as_ref(),
+            &connect,
+            &None,
+            &Some(last_will),
+            &None,
+            &None,
+        ) {
+            Some(MqttPacket::ConnAck(ack, _)) => Some(ack.code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    pub fn connect_validator_last_will_test() {
+        let mut cluster = build_default_cluster_config();
+
+        let last_will = LastWill {
+            topic: Bytes::from("a/b"),
+            message: Bytes::from("will message"),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+        };
+        assert!(connect_validator_last_will_code(&cluster, last_will.clone()).is_none());
+
+        let wildcard_will = LastWill {
+            topic: Bytes::from("a/+/b"),
+            ..last_will.clone()
+        };
+        assert_eq!(
+            connect_validator_last_will_code(&cluster, wildcard_will),
+            Some(ConnectReturnCode::TopicNameInvalid)
+        );
+
+        let wildcard_will = LastWill {
+            topic: Bytes::from("a/#"),
+            ..last_will.clone()
+        };
+        assert_eq!(
+            connect_validator_last_will_code(&cluster, wildcard_will),
+            Some(ConnectReturnCode::TopicNameInvalid)
+        );
+
+        cluster.protocol.max_qos = QoS::AtLeastOnce;
+        let qos2_will = LastWill {
+            qos: QoS::ExactlyOnce,
+            ..last_will.clone()
+        };
+        assert_eq!(
+            connect_validator_last_will_code(&cluster, qos2_will),
+            Some(ConnectReturnCode::QoSNotSupported)
+        );
+
+        cluster.feature.retain_available = AvailableFlag::Disable;
+        let retain_will = LastWill {
+            retain: true,
+            ..last_will
+        };
+        assert_eq!(
+            connect_validator_last_will_code(&cluster, retain_will),
+            Some(ConnectReturnCode::RetainNotSupported)
+        );
+    }
 }
@@ -27,9 +27,12 @@ use grpc_clients::pool::ClientPool;
 use handler::acl::UpdateAclCache;
 use handler::cache::CacheManager;
 use handler::cache_update::load_metadata_cache;
-use handler::heartbreat::{register_node, report_heartbeat};
+use handler::drain::ConnectionDrainer;
+use handler::heartbreat::{discover_placement_peers, register_node, report_heartbeat};
 use handler::keep_alive::ClientKeepAlive;
+use handler::retain::{load_retain_message_index, start_retain_message_expire_thread};
 use handler::sub_parse_topic::start_parse_subscribe_by_new_topic_thread;
+use handler::topic::start_reclaim_idle_topic_shard_thread;
 use handler::user::{init_system_user, UpdateUserCache};
 use lazy_static::lazy_static;
 use log::{error, info};
@@ -38,16 +41,19 @@ use schema_register::schema::SchemaRegisterManager;
 use security::AuthDriver;
 use server::connection_manager::ConnectionManager;
 use server::grpc::server::GrpcServer;
+use server::http::server::{http_server, HttpServerState};
 use server::tcp::server::start_tcp_server;
 use server::websocket::server::{websocket_server, websockets_server, WebSocketServerState};
 use storage::cluster::ClusterStorage;
 use storage_adapter::memory::MemoryStorageAdapter;
+use storage_adapter::mmap::MmapStorageAdapter;
 // use storage_adapter::mysql::MySQLStorageAdapter;
 // use storage_adapter::rocksdb::RocksDBStorageAdapter;
 use crate::handler::flapping_detect::UpdateFlappingDetectCache;
 use crate::server::quic::server::start_quic_server;
 use storage_adapter::storage::StorageAdapter;
 use storage_adapter::StorageType;
+use subscribe::cluster_route::start_broker_node_refresh_thread;
 use subscribe::exclusive_push::ExclusivePush;
 use subscribe::share_follower_resub::ShareFollowerResub;
 use subscribe::share_leader_push::ShareLeaderPush;
@@ -68,7 +74,7 @@ pub mod observability;
 pub mod security;
 pub mod server;
 pub mod storage;
-mod subscribe;
+pub mod subscribe;
 
 pub fn start_mqtt_broker_server(stop_send: broadcast::Sender<bool>) {
     let conf = broker_mqtt_conf();
@@ -107,8 +113,19 @@ pub fn start_mqtt_broker_server(stop_send: broadcast::Sender<bool>) {
         //     let server = MqttBroker::new(client_pool, message_storage_adapter, metadata_cache);
         //     server.start(stop_send);
         // }
+        StorageType::Mmap => {
+            if conf.storage.mmap_data_path.is_empty() {
+                panic!("storage type is [mmap],[storage.mmap_data_path] cannot be empty");
+            }
+            let message_storage_adapter = Arc::new(
+                MmapStorageAdapter::new(conf.storage.mmap_data_path.as_str())
+                    .expect("Failed to open the mmap storage"),
+            );
+            let server = MqttBroker::new(client_pool, message_storage_adapter, metadata_cache);
+            server.start(stop_send);
+        }
         _ => {
-            panic!("Message data storage type configuration error, optional :mysql, memory");
+            panic!("Message data storage type configuration error, optional :mysql, memory, mmap");
         }
     }
 }
@@ -143,7 +160,10 @@ where
 
         let subscribe_manager = Arc::new(SubscribeManager::new());
         let connector_manager = Arc::new(ConnectorManager::new());
-        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+        let connection_manager = Arc::new(
+            ConnectionManager::new(cache_manager.clone())
+                .with_max_total_connections(conf.network.max_total_connections),
+        );
         let auth_driver = Arc::new(AuthDriver::new(cache_manager.clone(), client_pool.clone()));
         let delay_message_manager = Arc::new(DelayMessageManager::new(
             conf.cluster_name.clone(),
@@ -170,6 +190,7 @@ where
 
         self.register_node();
         self.start_cluster_heartbeat_report(stop_send.clone());
+        self.start_broker_node_refresh_thread(stop_send.clone());
 
         self.start_push_server(stop_send.clone());
 
@@ -178,10 +199,14 @@ where
         self.start_mqtt_server(stop_send.clone());
         self.start_quic_server(stop_send.clone());
         self.start_websocket_server(stop_send.clone());
+        self.start_http_server();
         self.start_keep_alive_thread(stop_send.clone());
+        self.start_connection_drain_thread(stop_send.clone());
         self.start_delay_message_thread();
         self.start_update_cache_thread(stop_send.clone());
         self.start_system_topic_thread(stop_send.clone());
+        self.start_reclaim_idle_topic_shard_thread(stop_send.clone());
+        self.start_retain_message_expire_thread(stop_send.clone());
         self.start_prometheus();
         self.start_connector_thread(stop_send.clone());
         self.awaiting_stop(stop_send);
@@ -305,10 +330,31 @@ where
             .spawn(async move { websockets_server(ws_state).await });
     }
 
+    fn start_http_server(&self) {
+        let http_state = HttpServerState::new(
+            self.cache_manager.clone(),
+            self.message_storage_adapter.clone(),
+            self.client_pool.clone(),
+            self.connection_manager.clone(),
+            self.subscribe_manager.clone(),
+        );
+        self.runtime
+            .spawn(async move { http_server(http_state).await });
+    }
+
     fn start_cluster_heartbeat_report(&self, stop_send: broadcast::Sender<bool>) {
+        let client_pool = self.client_pool.clone();
+        let cache_manager = self.cache_manager.clone();
+        self.runtime.spawn(async move {
+            report_heartbeat(&client_pool, &cache_manager, stop_send).await;
+        });
+    }
+
+    fn start_broker_node_refresh_thread(&self, stop_send: broadcast::Sender<bool>) {
+        let cache_manager = self.cache_manager.clone();
         let client_pool = self.client_pool.clone();
         self.runtime.spawn(async move {
-            report_heartbeat(&client_pool, stop_send).await;
+            start_broker_node_refresh_thread(cache_manager, client_pool, stop_send).await;
         });
     }
 
@@ -374,6 +420,7 @@ where
             self.client_pool.clone(),
             self.connection_manager.clone(),
             self.cache_manager.clone(),
+            self.message_storage_adapter.clone(),
             stop_send,
         );
         self.runtime.spawn(async move {
@@ -381,6 +428,18 @@ where
         });
     }
 
+    fn start_connection_drain_thread(&self, stop_send: broadcast::Sender<bool>) {
+        let drainer = ConnectionDrainer::new(
+            self.cache_manager.clone(),
+            self.connection_manager.clone(),
+            self.client_pool.clone(),
+            stop_send,
+        );
+        self.runtime.spawn(async move {
+            drainer.start().await;
+        });
+    }
+
     fn start_delay_message_thread(&self) {
         let delay_message_manager = self.delay_message_manager.clone();
         let message_storage_adapter = self.message_storage_adapter.clone();
@@ -447,6 +506,36 @@ where
         });
     }
 
+    fn start_reclaim_idle_topic_shard_thread(&self, stop_send: broadcast::Sender<bool>) {
+        let cache_manager = self.cache_manager.clone();
+        let subscribe_manager = self.subscribe_manager.clone();
+        let message_storage_adapter = self.message_storage_adapter.clone();
+        self.runtime.spawn(async move {
+            start_reclaim_idle_topic_shard_thread(
+                cache_manager,
+                subscribe_manager,
+                message_storage_adapter,
+                stop_send,
+            )
+            .await;
+        });
+    }
+
+    fn start_retain_message_expire_thread(&self, stop_send: broadcast::Sender<bool>) {
+        let cache_manager = self.cache_manager.clone();
+        let client_pool = self.client_pool.clone();
+        let message_storage_adapter = self.message_storage_adapter.clone();
+        self.runtime.spawn(async move {
+            start_retain_message_expire_thread(
+                cache_manager,
+                client_pool,
+                message_storage_adapter,
+                stop_send,
+            )
+            .await;
+        });
+    }
+
     pub fn awaiting_stop(&self, stop_send: broadcast::Sender<bool>) {
         self.runtime.spawn(async move {
             sleep(Duration::from_millis(5)).await;
@@ -485,6 +574,30 @@ where
             )
             .await;
 
+            match load_retain_message_index(&self.cache_manager, &self.message_storage_adapter)
+                .await
+            {
+                Ok(num) => {
+                    info!(
+                        "{} retained messages were loaded from the retained message index",
+                        num
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to load the retained message index, error message: {}",
+                        e
+                    );
+                }
+            }
+
+            if let Err(e) = discover_placement_peers(&self.client_pool).await {
+                error!(
+                    "Failed to discover the placement center peers, error message: {}",
+                    e
+                );
+            }
+
             let config = broker_mqtt_conf();
             match register_node(&self.client_pool).await {
                 Ok(()) => {
@@ -18,3 +18,4 @@ pub mod packets;
 pub mod publish;
 pub mod server;
 pub mod session;
+pub mod snapshot;
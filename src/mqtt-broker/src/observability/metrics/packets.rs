@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::snapshot::MetricsSnapshot;
 use crate::server::connection::{NetworkConnection, NetworkConnectionType};
+use common_base::metrics::registry::FamilyGauge;
 use prometheus_client::encoding::EncodeLabelSet;
 use protocol::mqtt::{
     codec::{calc_mqtt_packet_size, MqttPacketWrapper},
@@ -244,6 +246,13 @@ common_base::register_gauge_metric!(
     QosLabel
 );
 
+common_base::register_gauge_metric!(
+    RETAIN_PACKETS_SKIPPED,
+    "retain_packets_skipped",
+    "Number of reserved messages not stored again because their payload was unchanged",
+    QosLabel
+);
+
 common_base::register_gauge_metric!(
     MESSAGES_DROPPED_NO_SUBSCRIBERS,
     "messages.dropped.no_subscribers",
@@ -277,6 +286,7 @@ pub fn record_received_metrics(
     } else {
         0
     };
+    connection.stats.record_received(pkg, payload_size as u64);
     common_base::gauge_metric_inc_by!(BYTES_RECEIVED, label, payload_size as i64);
     common_base::gauge_metric_inc!(PACKETS_RECEIVED, label);
     match pkg {
@@ -374,12 +384,77 @@ pub fn record_retain_sent_metrics(qos: QoS) {
     common_base::gauge_metric_inc!(RETAIN_PACKETS_SEND, label);
 }
 
+pub fn record_retain_skipped_metrics(qos: QoS) {
+    let qos_str = (qos as u8).to_string();
+    let label = QosLabel { qos: qos_str };
+    common_base::gauge_metric_inc!(RETAIN_PACKETS_SKIPPED, label);
+}
+
+pub fn get_retain_skipped_num(qos: QoS) -> i64 {
+    let qos_str = (qos as u8).to_string();
+    let label = QosLabel { qos: qos_str };
+    let mut res = 0;
+    common_base::gauge_metric_get!(RETAIN_PACKETS_SKIPPED, label, res);
+    res
+}
+
 pub fn record_messages_dropped_no_subscribers_metrics(qos: QoS) {
     let qos_str = (qos as u8).to_string();
     let label = QosLabel { qos: qos_str };
     common_base::gauge_metric_inc!(MESSAGES_DROPPED_NO_SUBSCRIBERS, label);
 }
 
+fn network_labels() -> Vec<NetworkLabel> {
+    [
+        NetworkConnectionType::Tcp,
+        NetworkConnectionType::Tls,
+        NetworkConnectionType::WebSocket,
+        NetworkConnectionType::WebSockets,
+        NetworkConnectionType::Quic,
+    ]
+    .iter()
+    .map(|network_type| NetworkLabel {
+        network: network_type.to_string(),
+    })
+    .collect()
+}
+
+// The packets that are not a publish are sent with qos -1
+fn network_qos_labels() -> Vec<NetworkQosLabel> {
+    let mut labels = Vec::new();
+    for label in network_labels() {
+        for qos in ["-1", "0", "1", "2"] {
+            labels.push(NetworkQosLabel {
+                network: label.network.clone(),
+                qos: qos.to_string(),
+            });
+        }
+    }
+    labels
+}
+
+fn sum_gauge<L>(family: &FamilyGauge<L>, labels: &[L]) -> i64
+where
+    L: EncodeLabelSet + Eq + Clone + std::hash::Hash + std::fmt::Debug + Sync + Send + 'static,
+{
+    let family = family.read().unwrap();
+    labels
+        .iter()
+        .filter_map(|label| family.get(label).map(|gauge| gauge.get()))
+        .sum()
+}
+
+pub(crate) fn fill_snapshot(snapshot: &mut MetricsSnapshot) {
+    let network_labels = network_labels();
+    let network_qos_labels = network_qos_labels();
+    snapshot.packets_received = sum_gauge(&PACKETS_RECEIVED, &network_labels);
+    snapshot.packets_sent = sum_gauge(&PACKETS_SENT, &network_qos_labels);
+    snapshot.messages_received = sum_gauge(&PACKETS_PUBLISH_RECEIVED, &network_labels);
+    snapshot.messages_sent = sum_gauge(&PACKETS_PUBLISH_SENT, &network_qos_labels);
+    snapshot.bytes_received = sum_gauge(&BYTES_RECEIVED, &network_labels);
+    snapshot.bytes_sent = sum_gauge(&BYTES_SENT, &network_qos_labels);
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -483,6 +558,10 @@ mod test {
             connection_stop_sx: None,
             connection_id: 100,
             protocol: Some(MqttProtocol::Mqtt3),
+            has_connected: Default::default(),
+            stats: Default::default(),
+            client_cert_common_name: None,
+            create_time: 0,
         };
         let ty = NetworkConnectionType::Tcp;
         record_received_metrics(&nc, &mp, &ty);
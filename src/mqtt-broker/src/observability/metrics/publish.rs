@@ -11,3 +11,125 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+
+use std::fmt;
+
+use prometheus_client::encoding::EncodeLabelSet;
+use protocol::mqtt::common::QoS;
+
+use super::packets::record_messages_dropped_no_subscribers_metrics;
+use super::snapshot::MetricsSnapshot;
+
+/// Why a message received or pushed by the broker was not delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageDropReason {
+    // The message expired before it was pushed to the subscriber
+    Expired,
+    // The message is larger than the maximum packet size of the broker or of the client
+    Oversized,
+    // The topic has no subscriber and offline messages are disabled
+    NoSubscriber,
+    // The client has more QoS 1 and QoS 2 messages in flight than the Receive Maximum
+    Overflow,
+    // The client is not allowed to publish to or receive from the topic
+    AuthDenied,
+    // The payload fails the schema of the topic or cannot be decrypted
+    Quarantined,
+}
+
+impl MessageDropReason {
+    const ALL: [MessageDropReason; 6] = [
+        MessageDropReason::Expired,
+        MessageDropReason::Oversized,
+        MessageDropReason::NoSubscriber,
+        MessageDropReason::Overflow,
+        MessageDropReason::AuthDenied,
+        MessageDropReason::Quarantined,
+    ];
+}
+
+impl fmt::Display for MessageDropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            MessageDropReason::Expired => "expired",
+            MessageDropReason::Oversized => "oversized",
+            MessageDropReason::NoSubscriber => "no_subscriber",
+            MessageDropReason::Overflow => "overflow",
+            MessageDropReason::AuthDenied => "auth_denied",
+            MessageDropReason::Quarantined => "quarantined",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+#[derive(Eq, Hash, Clone, EncodeLabelSet, Debug, PartialEq)]
+struct MessageDropLabel {
+    reason: String,
+    qos: String,
+}
+
+common_base::register_counter_metric!(
+    MESSAGES_DROPPED,
+    "messages_dropped",
+    "Number of messages dropped by the broker, by reason",
+    MessageDropLabel
+);
+
+fn build_label(reason: MessageDropReason, qos: &str) -> MessageDropLabel {
+    MessageDropLabel {
+        reason: reason.to_string(),
+        qos: qos.to_string(),
+    }
+}
+
+// Every place that drops a message records it here, so that the drops are counted the same way
+pub fn record_message_dropped(reason: MessageDropReason, qos: QoS, num: u64) {
+    if num == 0 {
+        return;
+    }
+    let label = build_label(reason, &(qos as u8).to_string());
+    MESSAGES_DROPPED
+        .write()
+        .unwrap()
+        .get_or_create(&label)
+        .inc_by(num);
+
+    if reason == MessageDropReason::NoSubscriber {
+        record_messages_dropped_no_subscribers_metrics(qos);
+    }
+}
+
+pub fn get_message_dropped_num(reason: MessageDropReason) -> u64 {
+    let family = MESSAGES_DROPPED.read().unwrap();
+    ["0", "1", "2"]
+        .iter()
+        .filter_map(|qos| family.get(&build_label(reason, qos)).map(|c| c.get()))
+        .sum()
+}
+
+pub(crate) fn fill_snapshot(snapshot: &mut MetricsSnapshot) {
+    snapshot.messages_dropped = MessageDropReason::ALL
+        .iter()
+        .map(|reason| get_message_dropped_num(*reason) as i64)
+        .sum();
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::mqtt::common::QoS;
+
+    use super::{get_message_dropped_num, record_message_dropped, MessageDropReason};
+
+    #[test]
+    fn record_message_dropped_test() {
+        let before = get_message_dropped_num(MessageDropReason::Overflow);
+        record_message_dropped(MessageDropReason::Overflow, QoS::AtLeastOnce, 2);
+        record_message_dropped(MessageDropReason::Overflow, QoS::ExactlyOnce, 1);
+        record_message_dropped(MessageDropReason::Overflow, QoS::ExactlyOnce, 0);
+        assert_eq!(
+            get_message_dropped_num(MessageDropReason::Overflow),
+            before + 3
+        );
+        assert_eq!(MessageDropReason::AuthDenied.to_string(), "auth_denied");
+    }
+}
@@ -13,6 +13,17 @@
 // limitations under the License.
 
 use prometheus_client::encoding::EncodeLabelSet;
+
+use super::snapshot::MetricsSnapshot;
+
+pub const PUSH_THREAD_TYPE_EXCLUSIVE: &str = "exclusive";
+pub const PUSH_THREAD_TYPE_SHARE_LEADER: &str = "share_leader";
+
+#[derive(Eq, Hash, Clone, EncodeLabelSet, Debug, PartialEq)]
+struct PushThreadLabel {
+    r#type: String,
+}
+
 #[derive(Eq, Hash, Clone, EncodeLabelSet, Debug, PartialEq)]
 struct LabelType {
     label: String,
@@ -26,6 +37,94 @@ common_base::register_gauge_metric!(
     LabelType
 );
 
+common_base::register_gauge_metric!(
+    BROKER_PUSH_THREAD_NUM,
+    "push_thread_num",
+    "Number of running subscription push threads",
+    PushThreadLabel
+);
+
+pub fn metrics_push_thread_num(push_type: &str, num: usize) {
+    let label = PushThreadLabel {
+        r#type: push_type.to_string(),
+    };
+    BROKER_PUSH_THREAD_NUM
+        .write()
+        .unwrap()
+        .get_or_create(&label)
+        .set(num as i64);
+}
+
+#[derive(Eq, Hash, Clone, EncodeLabelSet, Debug, PartialEq)]
+struct TopicLabel {
+    topic_id: String,
+}
+
+common_base::register_counter_metric!(
+    BROKER_PUSH_CIRCUIT_OPEN,
+    "push_circuit_open",
+    "Number of times the delivery of a topic was paused by repeated storage read failures",
+    TopicLabel
+);
+
+pub fn metrics_push_circuit_open(topic_id: &str) {
+    let label = TopicLabel {
+        topic_id: topic_id.to_string(),
+    };
+    common_base::counter_metric_inc!(BROKER_PUSH_CIRCUIT_OPEN, label)
+}
+
+pub fn get_push_circuit_open_num(topic_id: &str) -> u64 {
+    let label = TopicLabel {
+        topic_id: topic_id.to_string(),
+    };
+    let mut res = 0;
+    common_base::counter_metric_get!(BROKER_PUSH_CIRCUIT_OPEN, label, res);
+    res
+}
+
+#[derive(Eq, Hash, Clone, EncodeLabelSet, Debug, PartialEq)]
+struct AckTypeLabel {
+    r#type: String,
+}
+
+common_base::register_counter_metric!(
+    BROKER_QOS_ACK_RECEIVER_GONE,
+    "qos_ack_receiver_gone",
+    "Number of acks received for a delivery that no longer waited, the broadcast channel of the ack had no receiver",
+    AckTypeLabel
+);
+
+pub fn metrics_qos_ack_receiver_gone(ack_type: &str) {
+    let label = AckTypeLabel {
+        r#type: ack_type.to_string(),
+    };
+    common_base::counter_metric_inc!(BROKER_QOS_ACK_RECEIVER_GONE, label)
+}
+
+pub fn get_qos_ack_receiver_gone_num(ack_type: &str) -> u64 {
+    let label = AckTypeLabel {
+        r#type: ack_type.to_string(),
+    };
+    let mut res = 0;
+    common_base::counter_metric_get!(BROKER_QOS_ACK_RECEIVER_GONE, label, res);
+    res
+}
+
+pub(crate) fn fill_snapshot(snapshot: &mut MetricsSnapshot) {
+    let family = BROKER_PUSH_THREAD_NUM.read().unwrap();
+    snapshot.push_threads = [PUSH_THREAD_TYPE_EXCLUSIVE, PUSH_THREAD_TYPE_SHARE_LEADER]
+        .iter()
+        .filter_map(|push_type| {
+            family
+                .get(&PushThreadLabel {
+                    r#type: push_type.to_string(),
+                })
+                .map(|gauge| gauge.get())
+        })
+        .sum();
+}
+
 pub fn metrics_request_queue(label: &str, len: usize) {
     let label_type = LabelType {
         label: label.to_string(),
@@ -11,3 +11,34 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+
+use prometheus_client::encoding::EncodeLabelSet;
+
+use super::snapshot::MetricsSnapshot;
+
+#[derive(Eq, Hash, Clone, EncodeLabelSet, Debug, PartialEq)]
+struct ClientLabel {}
+
+common_base::register_gauge_metric!(
+    CLIENT_CONNECTIONS_NUM,
+    "client_connections_num",
+    "Number of clients connected to the broker",
+    ClientLabel
+);
+
+pub fn record_client_connection_inc() {
+    let label = ClientLabel {};
+    common_base::gauge_metric_inc!(CLIENT_CONNECTIONS_NUM, label);
+}
+
+pub fn record_client_connection_dec() {
+    let label = ClientLabel {};
+    common_base::gauge_metric_inc_by!(CLIENT_CONNECTIONS_NUM, label, -1);
+}
+
+pub(crate) fn fill_snapshot(snapshot: &mut MetricsSnapshot) {
+    let label = ClientLabel {};
+    let mut res = 0;
+    common_base::gauge_metric_get!(CLIENT_CONNECTIONS_NUM, label, res);
+    snapshot.client_connections = res;
+}
@@ -0,0 +1,95 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{packets, publish, server, session};
+
+/// The current values of the broker metrics, read from the same families that are exported to
+/// Prometheus, so that tests and embedders can assert on them without parsing the text format.
+/// The counters are summed over all the network, qos and reason labels.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub client_connections: i64,
+    pub packets_received: i64,
+    pub packets_sent: i64,
+    pub messages_received: i64,
+    pub messages_sent: i64,
+    pub bytes_received: i64,
+    pub bytes_sent: i64,
+    pub messages_dropped: i64,
+    pub push_threads: i64,
+}
+
+pub fn metrics_snapshot() -> MetricsSnapshot {
+    let mut snapshot = MetricsSnapshot::default();
+    packets::fill_snapshot(&mut snapshot);
+    publish::fill_snapshot(&mut snapshot);
+    server::fill_snapshot(&mut snapshot);
+    session::fill_snapshot(&mut snapshot);
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use protocol::mqtt::codec::{calc_mqtt_packet_size, MqttPacketWrapper};
+    use protocol::mqtt::common::{MqttPacket, MqttProtocol, Publish, QoS};
+
+    use super::metrics_snapshot;
+    use crate::observability::metrics::packets::{record_received_metrics, record_sent_metrics};
+    use crate::server::connection::{NetworkConnection, NetworkConnectionType};
+
+    #[test]
+    fn metrics_snapshot_publish_test() {
+        let packet = MqttPacket::Publish(
+            Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                pkid: 1,
+                retain: false,
+                topic: Bytes::from("/test/snapshot"),
+                payload: Bytes::from("snapshot"),
+            },
+            None,
+        );
+        let wrapper = MqttPacketWrapper {
+            protocol_version: 5,
+            packet: packet.clone(),
+        };
+        let packet_size = calc_mqtt_packet_size(wrapper.clone()) as i64;
+        let connection = NetworkConnection {
+            connection_type: NetworkConnectionType::Tcp,
+            addr: "127.0.0.1:1883".parse().unwrap(),
+            connection_stop_sx: None,
+            connection_id: 1,
+            protocol: Some(MqttProtocol::Mqtt5),
+            has_connected: Default::default(),
+            stats: Default::default(),
+            client_cert_common_name: None,
+            create_time: 0,
+        };
+
+        // the metrics are global, other tests may record them concurrently
+        let before = metrics_snapshot();
+        record_received_metrics(&connection, &packet, &NetworkConnectionType::Tcp);
+        record_sent_metrics(&wrapper, NetworkConnectionType::Tcp.to_string());
+        let after = metrics_snapshot();
+
+        assert!(after.packets_received > before.packets_received);
+        assert!(after.messages_received > before.messages_received);
+        assert!(after.bytes_received >= before.bytes_received + packet_size);
+        assert!(after.packets_sent > before.packets_sent);
+        assert!(after.messages_sent > before.messages_sent);
+        assert!(after.bytes_sent >= before.bytes_sent + packet_size);
+    }
+}
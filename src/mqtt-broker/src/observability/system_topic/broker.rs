@@ -15,18 +15,30 @@
 use std::env;
 use std::sync::Arc;
 
+use bytes::Bytes;
 use common_base::tools::now_second;
 use grpc_clients::pool::ClientPool;
 use log::error;
 use metadata_struct::adapter::record::Record;
+use metadata_struct::mqtt::cluster::AvailableFlag;
 use metadata_struct::mqtt::message::MqttMessage;
+use protocol::mqtt::common::{Publish, QoS};
+use serde::{Deserialize, Serialize};
 use storage_adapter::storage::StorageAdapter;
 
+use super::scheduler::{
+    SystemTopicScheduler, SYSTEM_METRIC_BROKERS, SYSTEM_METRIC_DATETIME, SYSTEM_METRIC_SYSDESCR,
+    SYSTEM_METRIC_UPTIME, SYSTEM_METRIC_VERSION,
+};
 use super::{
     replace_topic_name, write_topic_data, SYSTEM_TOPIC_BROKERS, SYSTEM_TOPIC_BROKERS_DATETIME,
     SYSTEM_TOPIC_BROKERS_SYSDESCR, SYSTEM_TOPIC_BROKERS_UPTIME, SYSTEM_TOPIC_BROKERS_VERSION,
+    SYSTEM_TOPIC_BROKER_VERSION,
 };
 use crate::handler::cache::CacheManager;
+use crate::handler::error::MqttBrokerError;
+use crate::handler::retain::save_retain_message;
+use crate::handler::topic::try_init_topic;
 use crate::storage::cluster::ClusterStorage;
 use crate::BROKER_START_TIME;
 
@@ -34,13 +46,26 @@ pub(crate) async fn report_broker_info<S>(
     client_pool: &Arc<ClientPool>,
     metadata_cache: &Arc<CacheManager>,
     message_storage_adapter: &Arc<S>,
+    scheduler: &mut SystemTopicScheduler,
 ) where
     S: StorageAdapter + Clone + Send + Sync + 'static,
 {
-    report_cluster_status(client_pool, metadata_cache, message_storage_adapter).await;
-    report_broker_version(client_pool, metadata_cache, message_storage_adapter).await;
-    report_broker_time(client_pool, metadata_cache, message_storage_adapter).await;
-    report_broker_sysdescr(client_pool, metadata_cache, message_storage_adapter).await;
+    let now = now_second();
+    if scheduler.is_due(SYSTEM_METRIC_BROKERS, now) {
+        report_cluster_status(client_pool, metadata_cache, message_storage_adapter).await;
+    }
+    if scheduler.is_due(SYSTEM_METRIC_VERSION, now) {
+        report_broker_version(client_pool, metadata_cache, message_storage_adapter).await;
+    }
+    if scheduler.is_due(SYSTEM_METRIC_UPTIME, now) {
+        report_broker_uptime(client_pool, metadata_cache, message_storage_adapter).await;
+    }
+    if scheduler.is_due(SYSTEM_METRIC_DATETIME, now) {
+        report_broker_datetime(client_pool, metadata_cache, message_storage_adapter).await;
+    }
+    if scheduler.is_due(SYSTEM_METRIC_SYSDESCR, now) {
+        report_broker_sysdescr(client_pool, metadata_cache, message_storage_adapter).await;
+    }
 }
 
 async fn report_cluster_status<S>(
@@ -88,7 +113,72 @@ async fn report_broker_version<S>(
     }
 }
 
-async fn report_broker_time<S>(
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BrokerVersionInfo {
+    pub version: String,
+    pub protocol: String,
+    pub max_qos: u8,
+    pub retain_available: bool,
+    pub shared_subscriptions: bool,
+}
+
+pub fn build_broker_version_info(metadata_cache: &Arc<CacheManager>) -> BrokerVersionInfo {
+    let cluster = metadata_cache.get_cluster_info();
+    BrokerVersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol: "MQTT5".to_string(),
+        max_qos: cluster.protocol.max_qos as u8,
+        retain_available: cluster.feature.retain_available == AvailableFlag::Enable,
+        shared_subscriptions: cluster.feature.shared_subscription_available
+            == AvailableFlag::Enable,
+    }
+}
+
+pub fn build_broker_version_publish(
+    metadata_cache: &Arc<CacheManager>,
+) -> Result<Publish, MqttBrokerError> {
+    let content = serde_json::to_string(&build_broker_version_info(metadata_cache))?;
+    Ok(Publish {
+        dup: false,
+        qos: QoS::AtMostOnce,
+        pkid: 0,
+        retain: true,
+        topic: Bytes::from(SYSTEM_TOPIC_BROKER_VERSION),
+        payload: Bytes::from(content),
+    })
+}
+
+// Saves the capabilities of the broker as the retained message of $SYS/broker/version,
+// so that a client discovers them by subscribing to it.
+pub(crate) async fn publish_broker_version<S>(
+    client_pool: &Arc<ClientPool>,
+    metadata_cache: &Arc<CacheManager>,
+    message_storage_adapter: &Arc<S>,
+) -> Result<(), MqttBrokerError>
+where
+    S: StorageAdapter + Clone + Send + Sync + 'static,
+{
+    try_init_topic(
+        SYSTEM_TOPIC_BROKER_VERSION,
+        metadata_cache,
+        message_storage_adapter,
+        client_pool,
+    )
+    .await?;
+    let publish = build_broker_version_publish(metadata_cache)?;
+    save_retain_message(
+        metadata_cache,
+        client_pool,
+        message_storage_adapter,
+        SYSTEM_TOPIC_BROKER_VERSION.to_string(),
+        "",
+        &publish,
+        &None,
+    )
+    .await
+}
+
+async fn report_broker_uptime<S>(
     client_pool: &Arc<ClientPool>,
     metadata_cache: &Arc<CacheManager>,
     message_storage_adapter: &Arc<S>,
@@ -109,7 +199,15 @@ async fn report_broker_time<S>(
         )
         .await;
     }
+}
 
+async fn report_broker_datetime<S>(
+    client_pool: &Arc<ClientPool>,
+    metadata_cache: &Arc<CacheManager>,
+    message_storage_adapter: &Arc<S>,
+) where
+    S: StorageAdapter + Clone + Send + Sync + 'static,
+{
     let topic_name = replace_topic_name(SYSTEM_TOPIC_BROKERS_DATETIME.to_string());
     if let Some(record) =
         MqttMessage::build_system_topic_message(topic_name.clone(), now_second().to_string())
@@ -173,6 +271,33 @@ async fn build_node_cluster(topic_name: &str, client_pool: &Arc<ClientPool>) ->
 #[cfg(test)]
 mod tests {
     use std::env;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use dashmap::DashMap;
+    use futures::StreamExt;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use metadata_struct::mqtt::session::MqttSession;
+    use metadata_struct::mqtt::topic::MqttTopic;
+    use protocol::mqtt::codec::MqttCodec;
+    use protocol::mqtt::common::{
+        Filter, MqttPacket, MqttProtocol, QoS, RetainForwardRule, Subscribe,
+    };
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::timeout;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    use super::{build_broker_version_info, build_broker_version_publish, BrokerVersionInfo};
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::handler::message::build_message_expire;
+    use crate::handler::retain::try_send_retain_message;
+    use crate::observability::system_topic::SYSTEM_TOPIC_BROKER_VERSION;
+    use crate::server::connection::{NetworkConnection, NetworkConnectionType};
+    use crate::server::connection_manager::ConnectionManager;
 
     #[tokio::test]
     async fn os_info_test() {
@@ -189,4 +314,100 @@ mod tests {
         println!("{}", version);
         assert_ne!(version, "-".to_string());
     }
+
+    #[tokio::test]
+    async fn broker_version_retain_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+
+        let info = build_broker_version_info(&cache_manager);
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.protocol, "MQTT5");
+        assert_eq!(info.max_qos, 2);
+        assert!(info.retain_available);
+        assert!(info.shared_subscriptions);
+
+        // the retained message as saved at startup
+        let topic = MqttTopic::new(
+            unique_id(),
+            "mqtt-broker".to_string(),
+            SYSTEM_TOPIC_BROKER_VERSION.to_string(),
+        );
+        cache_manager.add_topic(SYSTEM_TOPIC_BROKER_VERSION, &topic);
+        let publish = build_broker_version_publish(&cache_manager).unwrap();
+        assert!(publish.retain);
+        let message = MqttMessage::build_message(
+            "",
+            &publish,
+            &None,
+            build_message_expire(&cache_manager, &None),
+        );
+        cache_manager.add_retain_message_history(SYSTEM_TOPIC_BROKER_VERSION, message, 1);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client_stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server_stream, peer_addr) = listener.accept().await.unwrap();
+        let (_, w_stream) = tokio::io::split(server_stream);
+        let connection_id = connection_manager.add_connection(NetworkConnection::new(
+            NetworkConnectionType::Tcp,
+            peer_addr,
+            None,
+        ));
+        connection_manager.set_connect_protocol(connection_id, 5);
+        connection_manager.add_tcp_write(
+            connection_id,
+            FramedWrite::new(w_stream, MqttCodec::new(None)),
+        );
+        let mut read_stream = FramedRead::new(client_stream, MqttCodec::new(Some(5)));
+
+        let client_id = unique_id();
+        let mut session = MqttSession::new(client_id.clone(), 60, false, None);
+        session.connection_id = Some(connection_id);
+        cache_manager.add_session(client_id.clone(), session);
+
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            filters: vec![Filter {
+                path: SYSTEM_TOPIC_BROKER_VERSION.to_string(),
+                qos: QoS::AtMostOnce,
+                nolocal: false,
+                preserve_retain: true,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            }],
+        };
+        try_send_retain_message(
+            MqttProtocol::Mqtt5,
+            client_id,
+            subscribe,
+            None,
+            client_pool,
+            cache_manager,
+            connection_manager,
+            DashMap::new(),
+        )
+        .await;
+
+        let packet = timeout(Duration::from_secs(10), read_stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let MqttPacket::Publish(publish, _) = packet else {
+            panic!("expected a PUBLISH");
+        };
+        assert!(publish.retain);
+        assert_eq!(publish.topic, SYSTEM_TOPIC_BROKER_VERSION);
+        let received = serde_json::from_slice::<BrokerVersionInfo>(&publish.payload).unwrap();
+        assert_eq!(received, info);
+    }
 }
@@ -30,6 +30,7 @@ use super::{
     SYSTEM_TOPIC_BROKERS_SUBSCRIBED, SYSTEM_TOPIC_BROKERS_UNSUBSCRIBED,
 };
 use crate::handler::cache::CacheManager;
+use crate::server::connection::NetworkConnection;
 use crate::server::connection_manager::ConnectionManager;
 
 #[derive(Default, Serialize, Deserialize)]
@@ -59,6 +60,12 @@ pub struct SystemTopicDisConnectedEventMessage {
     pub ip_address: String,
     pub disconnected_at: u128,
     pub client_id: String,
+    // Traffic of the connection, for billing and monitoring
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub duration_ms: u128,
 }
 #[derive(Default, Serialize, Deserialize)]
 pub struct SystemTopicSubscribedEventMessage {
@@ -158,17 +165,8 @@ pub async fn st_report_disconnected_event<S>(
     S: StorageAdapter + Clone + Send + Sync + 'static,
 {
     if let Some(network_connection) = connection_manager.get_connect(connect_id) {
-        let event_data = SystemTopicDisConnectedEventMessage {
-            username: connection.login_user.clone(),
-            ts: now_mills(),
-            sock_port: network_connection.addr.port(),
-            reason: format!("{:?}", reason),
-            proto_ver: network_connection.protocol.clone(),
-            proto_name: "MQTT".to_string(),
-            ip_address: connection.source_ip_addr.clone(),
-            client_id: session.client_id.to_string(),
-            disconnected_at: now_mills(),
-        };
+        let event_data =
+            build_disconnected_event(&session.client_id, connection, &network_connection, reason);
 
         match serde_json::to_string(&event_data) {
             Ok(data) => {
@@ -197,6 +195,32 @@ pub async fn st_report_disconnected_event<S>(
     }
 }
 
+pub fn build_disconnected_event(
+    client_id: &str,
+    connection: &MQTTConnection,
+    network_connection: &NetworkConnection,
+    reason: Option<DisconnectReasonCode>,
+) -> SystemTopicDisConnectedEventMessage {
+    let stats = network_connection.stats.snapshot();
+    let now = now_mills();
+    SystemTopicDisConnectedEventMessage {
+        username: connection.login_user.clone(),
+        ts: now,
+        sock_port: network_connection.addr.port(),
+        reason: format!("{:?}", reason),
+        proto_ver: network_connection.protocol.clone(),
+        proto_name: "MQTT".to_string(),
+        ip_address: connection.source_ip_addr.clone(),
+        client_id: client_id.to_string(),
+        disconnected_at: now,
+        messages_sent: stats.messages_sent,
+        messages_received: stats.messages_received,
+        bytes_sent: stats.bytes_sent,
+        bytes_received: stats.bytes_received,
+        duration_ms: now.saturating_sub(network_connection.create_time),
+    }
+}
+
 // Subscribe to events. When any client subscribes to a topic, messages for that topic are published
 pub async fn st_report_subscribed_event<S>(
     message_storage_adapter: &Arc<S>,
@@ -314,3 +338,76 @@ fn replace_name(mut topic_name: String, client_id: String) -> String {
     }
     topic_name
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
+    use protocol::mqtt::codec::{calc_mqtt_packet_size, MqttPacketWrapper};
+    use protocol::mqtt::common::{DisconnectReasonCode, MqttPacket, MqttProtocol, Publish, QoS};
+
+    use super::build_disconnected_event;
+    use crate::observability::metrics::packets::record_received_metrics;
+    use crate::server::connection::{NetworkConnection, NetworkConnectionType};
+
+    #[test]
+    fn disconnected_event_stats_test() {
+        let mut network_connection = NetworkConnection::new(
+            NetworkConnectionType::Tcp,
+            "127.0.0.1:1883".parse().unwrap(),
+            None,
+        );
+        network_connection.set_protocol(MqttProtocol::Mqtt5);
+        let connection = MQTTConnection::new(ConnectionConfig {
+            connect_id: network_connection.connection_id(),
+            client_id: "c1".to_string(),
+            receive_maximum: 10,
+            max_packet_size: 1024,
+            topic_alias_max: 0,
+            request_problem_info: 0,
+            keep_alive: 60,
+            source_ip_addr: "127.0.0.1".to_string(),
+        });
+
+        let packet = MqttPacket::Publish(
+            Publish {
+                dup: false,
+                qos: QoS::AtMostOnce,
+                pkid: 0,
+                retain: false,
+                topic: Bytes::from("/test/stats"),
+                payload: Bytes::from("stats"),
+            },
+            None,
+        );
+        let packet_size = calc_mqtt_packet_size(MqttPacketWrapper {
+            protocol_version: 5,
+            packet: packet.clone(),
+        }) as u64;
+
+        // a clone of the connection shares the stats, as the ones kept by the connection manager
+        record_received_metrics(
+            &network_connection.clone(),
+            &packet,
+            &NetworkConnectionType::Tcp,
+        );
+        network_connection
+            .stats
+            .record_received(&packet, packet_size);
+        network_connection.stats.record_sent(&packet, packet_size);
+
+        let event = build_disconnected_event(
+            "c1",
+            &connection,
+            &network_connection,
+            Some(DisconnectReasonCode::NormalDisconnection),
+        );
+        assert_eq!(event.client_id, "c1");
+        assert_eq!(event.messages_received, 2);
+        assert_eq!(event.messages_sent, 1);
+        assert_eq!(event.bytes_received, 2 * packet_size);
+        assert_eq!(event.bytes_sent, packet_size);
+        assert!(event.bytes_sent > 0);
+        assert!(event.reason.contains("NormalDisconnection"));
+    }
+}
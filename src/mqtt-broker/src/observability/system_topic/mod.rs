@@ -15,11 +15,13 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use broker::report_broker_info;
+use broker::{publish_broker_version, report_broker_info};
+use common_base::config::broker_mqtt::broker_mqtt_conf;
 use common_base::tools::get_local_ip;
 use grpc_clients::pool::ClientPool;
 use log::{debug, error};
 use metadata_struct::adapter::record::Record;
+use scheduler::SystemTopicScheduler;
 use storage_adapter::storage::StorageAdapter;
 use tokio::select;
 use tokio::sync::broadcast;
@@ -35,6 +37,8 @@ pub const SYSTEM_TOPIC_BROKERS_VERSION: &str = "$SYS/brokers/${node}/version";
 pub const SYSTEM_TOPIC_BROKERS_UPTIME: &str = "$SYS/brokers/${node}/uptime";
 pub const SYSTEM_TOPIC_BROKERS_DATETIME: &str = "$SYS/brokers/${node}/datetime";
 pub const SYSTEM_TOPIC_BROKERS_SYSDESCR: &str = "$SYS/brokers/${node}/sysdescr";
+// Retained capabilities of the broker, published once at startup
+pub const SYSTEM_TOPIC_BROKER_VERSION: &str = "$SYS/broker/version";
 
 // Event
 pub const SYSTEM_TOPIC_BROKERS_CONNECTED: &str =
@@ -49,6 +53,7 @@ pub const SYSTEM_TOPIC_BROKERS_UNSUBSCRIBED: &str =
 pub mod broker;
 pub mod event;
 pub mod packet;
+pub mod scheduler;
 pub mod stats;
 pub mod sysmon;
 pub mod warn;
@@ -77,6 +82,20 @@ where
 
     pub async fn start_thread(&self, stop_send: broadcast::Sender<bool>) {
         self.try_init_system_topic().await;
+        if let Err(e) = publish_broker_version(
+            &self.client_pool,
+            &self.metadata_cache,
+            &self.message_storage_adapter,
+        )
+        .await
+        {
+            error!(
+                "Failed to publish the retained message of {}, error message :{}",
+                SYSTEM_TOPIC_BROKER_VERSION, e
+            );
+        }
+        let mut scheduler = SystemTopicScheduler::from_config(&broker_mqtt_conf().system_topic);
+        let tick_interval_sec = scheduler.tick_interval_sec();
         let mut stop_rx = stop_send.subscribe();
         loop {
             select! {
@@ -88,17 +107,18 @@ where
                         }
                     }
                 }
-                _ = self.report_info()=>{}
+                _ = self.report_info(&mut scheduler)=>{}
             }
-            sleep(Duration::from_secs(60)).await;
+            sleep(Duration::from_secs(tick_interval_sec)).await;
         }
     }
 
-    pub async fn report_info(&self) {
+    pub async fn report_info(&self, scheduler: &mut SystemTopicScheduler) {
         report_broker_info(
             &self.client_pool,
             &self.metadata_cache,
             &self.message_storage_adapter,
+            scheduler,
         )
         .await;
     }
@@ -133,6 +153,7 @@ where
             SYSTEM_TOPIC_BROKERS_UPTIME.to_string(),
             SYSTEM_TOPIC_BROKERS_DATETIME.to_string(),
             SYSTEM_TOPIC_BROKERS_SYSDESCR.to_string(),
+            SYSTEM_TOPIC_BROKER_VERSION.to_string(),
         ]
     }
 }
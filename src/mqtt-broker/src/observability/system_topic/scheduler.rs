@@ -0,0 +1,116 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use common_base::config::broker_mqtt::SystemTopic;
+
+// Metric names used to configure the publish interval of each system topic
+pub const SYSTEM_METRIC_BROKERS: &str = "brokers";
+pub const SYSTEM_METRIC_VERSION: &str = "version";
+pub const SYSTEM_METRIC_UPTIME: &str = "uptime";
+pub const SYSTEM_METRIC_DATETIME: &str = "datetime";
+pub const SYSTEM_METRIC_SYSDESCR: &str = "sysdescr";
+
+const MIN_INTERVAL_SEC: u64 = 1;
+
+/// Decides which $SYS metrics are due for publishing, so that each metric is
+/// recomputed at its own rate instead of all of them at the fastest rate.
+pub struct SystemTopicScheduler {
+    default_interval_sec: u64,
+    // (metric name, publish interval)
+    metric_interval_sec: HashMap<String, u64>,
+    // (metric name, last publish time)
+    last_publish_time: HashMap<String, u64>,
+}
+
+impl SystemTopicScheduler {
+    pub fn new(default_interval_sec: u64, metric_interval_sec: HashMap<String, u64>) -> Self {
+        SystemTopicScheduler {
+            default_interval_sec: default_interval_sec.max(MIN_INTERVAL_SEC),
+            metric_interval_sec,
+            last_publish_time: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(config: &SystemTopic) -> Self {
+        SystemTopicScheduler::new(
+            config.default_interval_sec,
+            config.metric_interval_sec.clone(),
+        )
+    }
+
+    pub fn interval_sec(&self, metric: &str) -> u64 {
+        if let Some(interval) = self.metric_interval_sec.get(metric) {
+            return (*interval).max(MIN_INTERVAL_SEC);
+        }
+        self.default_interval_sec
+    }
+
+    // The scheduler thread wakes up at the smallest configured interval.
+    pub fn tick_interval_sec(&self) -> u64 {
+        self.metric_interval_sec
+            .values()
+            .map(|interval| (*interval).max(MIN_INTERVAL_SEC))
+            .fold(self.default_interval_sec, u64::min)
+    }
+
+    // Returns true and records the publish time if the metric is due at now.
+    pub fn is_due(&mut self, metric: &str, now: u64) -> bool {
+        if let Some(last_time) = self.last_publish_time.get(metric) {
+            if now < last_time + self.interval_sec(metric) {
+                return false;
+            }
+        }
+        self.last_publish_time.insert(metric.to_string(), now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{SystemTopicScheduler, SYSTEM_METRIC_UPTIME, SYSTEM_METRIC_VERSION};
+
+    #[test]
+    fn interval_sec_test() {
+        let scheduler =
+            SystemTopicScheduler::new(60, HashMap::from([(SYSTEM_METRIC_UPTIME.to_string(), 0)]));
+        assert_eq!(scheduler.interval_sec(SYSTEM_METRIC_VERSION), 60);
+        assert_eq!(scheduler.interval_sec(SYSTEM_METRIC_UPTIME), 1);
+        assert_eq!(scheduler.tick_interval_sec(), 1);
+    }
+
+    #[test]
+    fn long_interval_metric_not_republished_test() {
+        let mut scheduler = SystemTopicScheduler::new(
+            1,
+            HashMap::from([(SYSTEM_METRIC_VERSION.to_string(), 3600)]),
+        );
+
+        let mut version_num = 0;
+        let mut uptime_num = 0;
+        for now in 0..7200 {
+            if scheduler.is_due(SYSTEM_METRIC_VERSION, now) {
+                version_num += 1;
+            }
+            if scheduler.is_due(SYSTEM_METRIC_UPTIME, now) {
+                uptime_num += 1;
+            }
+        }
+        assert_eq!(version_num, 2);
+        assert_eq!(uptime_num, 7200);
+    }
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -26,6 +26,7 @@ use regex::Regex;
 
 use crate::handler::cache::CacheManager;
 use crate::handler::constant::WILDCARD_RESOURCE;
+use crate::subscribe::sub_common::{self, path_regex_match};
 
 pub fn is_allow_acl(
     cache_manager: &Arc<CacheManager>,
@@ -58,7 +59,86 @@ pub fn is_allow_acl(
     true
 }
 
-fn is_super_user(cache_manager: &Arc<CacheManager>, username: &str) -> bool {
+// A subscribe filter is denied when some topic it matches is denied by a rule, including the
+// topics that are created after the SUBSCRIBE, e.g. "sensors/#" by a deny rule on
+// "sensors/secret".
+pub fn is_allow_subscribe_filter(
+    cache_manager: &Arc<CacheManager>,
+    connection: &MQTTConnection,
+    topic_filter: &str,
+) -> bool {
+    if is_super_user(cache_manager, &connection.login_user) {
+        return true;
+    }
+
+    if is_blacklist(cache_manager, connection) {
+        return false;
+    }
+
+    !is_acl_deny_by(
+        cache_manager,
+        connection,
+        MqttAclAction::Subscribe,
+        |rule_topic| {
+            rule_topic == WILDCARD_RESOURCE
+                || sub_common::topic_filter_overlap(rule_topic, topic_filter)
+        },
+    )
+}
+
+// The subscribe ACL of the topics configured for delivery-time checks is checked again for
+// every batch pushed to the client, so that a revoked permission stops the delivery promptly.
+pub fn is_allow_delivery(
+    cache_manager: &Arc<CacheManager>,
+    client_id: &str,
+    topic_name: &str,
+) -> bool {
+    let security = cache_manager.get_cluster_info().security;
+    if !security
+        .delivery_acl_check_topics
+        .iter()
+        .any(|topic_filter| path_regex_match(topic_name, topic_filter))
+    {
+        return true;
+    }
+
+    let Some(connection) = cache_manager
+        .get_connect_id(client_id)
+        .and_then(|connect_id| cache_manager.get_connection(connect_id))
+    else {
+        return true;
+    };
+
+    is_allow_acl(
+        cache_manager,
+        &connection,
+        topic_name,
+        MqttAclAction::Subscribe,
+        false,
+        QoS::AtMostOnce,
+    )
+}
+
+// Checked when a connection is accepted, before any packet is read. A peer on the deny list
+// is refused, and with a non-empty allow list only the peers on it are accepted.
+pub fn is_allow_accept(cache_manager: &Arc<CacheManager>, addr: &SocketAddr) -> bool {
+    let network = cache_manager.get_cluster_info().network;
+    let source_ip_addr = addr.ip().to_string();
+    if network
+        .accept_ip_deny_list
+        .iter()
+        .any(|ip_role| ip_match(&source_ip_addr, ip_role))
+    {
+        return false;
+    }
+    network.accept_ip_allow_list.is_empty()
+        || network
+            .accept_ip_allow_list
+            .iter()
+            .any(|ip_role| ip_match(&source_ip_addr, ip_role))
+}
+
+pub fn is_super_user(cache_manager: &Arc<CacheManager>, username: &str) -> bool {
     if username.is_empty() {
         return false;
     }
@@ -160,6 +240,19 @@ fn is_acl_deny(
     connection: &MQTTConnection,
     topic_name: &str,
     action: MqttAclAction,
+) -> bool {
+    is_acl_deny_by(cache_mamanger, connection, action, |rule_topic| {
+        topic_match(topic_name, rule_topic)
+    })
+}
+
+// Whether a deny rule of the action whose topic is accepted by topic_matcher applies to the
+// connection
+fn is_acl_deny_by(
+    cache_mamanger: &Arc<CacheManager>,
+    connection: &MQTTConnection,
+    action: MqttAclAction,
+    topic_matcher: impl Fn(&str) -> bool,
 ) -> bool {
     // check user acl
     if let Some(acl_list) = cache_mamanger
@@ -168,7 +261,7 @@ fn is_acl_deny(
         .get(&connection.login_user)
     {
         for raw in acl_list.clone() {
-            if topic_match(topic_name, &raw.topic)
+            if topic_matcher(&raw.topic)
                 && ip_match(&connection.source_ip_addr, &raw.ip)
                 && (raw.action == action || raw.action == MqttAclAction::All)
                 && raw.permission == MqttAclPermission::Deny
@@ -184,7 +277,7 @@ fn is_acl_deny(
         .get(&connection.client_id)
     {
         for raw in client_id_list.clone() {
-            if topic_match(topic_name, &raw.topic)
+            if topic_matcher(&raw.topic)
                 && ip_match(&connection.source_ip_addr, &raw.ip)
                 && (raw.action == action || raw.action == MqttAclAction::All)
                 && raw.permission == MqttAclPermission::Deny
@@ -196,11 +289,12 @@ fn is_acl_deny(
     false
 }
 
+// The topic of a rule is either the wildcard resource or a topic filter, e.g. "sensors/+/temp"
 fn topic_match(topic_name: &str, match_topic_name: &str) -> bool {
     if match_topic_name == WILDCARD_RESOURCE {
         return true;
     }
-    topic_name == match_topic_name
+    sub_common::topic_match(match_topic_name, topic_name)
 }
 
 fn ip_match(source_ip_addr: &str, ip_role: &str) -> bool {
@@ -231,8 +325,12 @@ mod test {
     use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
     use metadata_struct::mqtt::user::MqttUser;
 
-    use super::{ip_match, is_acl_deny, is_blacklist, is_super_user, topic_match};
+    use super::{
+        ip_match, is_acl_deny, is_allow_accept, is_allow_subscribe_filter, is_blacklist,
+        is_super_user, topic_match,
+    };
     use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
     use crate::handler::constant::WILDCARD_RESOURCE;
 
     #[tokio::test]
@@ -524,6 +622,55 @@ mod test {
         ));
     }
 
+    #[tokio::test]
+    pub async fn subscribe_filter_overlap_deny_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, "test".to_string()));
+        let config = ConnectionConfig {
+            connect_id: 1,
+            client_id: "client_id-1".to_string(),
+            receive_maximum: 3,
+            max_packet_size: 3,
+            topic_alias_max: 3,
+            request_problem_info: 1,
+            keep_alive: 2,
+            source_ip_addr: "127.0.0.1".to_string(),
+        };
+        let mut connection = MQTTConnection::new(config);
+        connection.login_success("loboxu".to_string());
+
+        cache_manager.add_acl(MqttAcl {
+            resource_type: MqttAclResourceType::User,
+            resource_name: "loboxu".to_string(),
+            topic: "sensors/secret".to_string(),
+            ip: WILDCARD_RESOURCE.to_string(),
+            action: MqttAclAction::Subscribe,
+            permission: MqttAclPermission::Deny,
+        });
+
+        // no topic exists yet, the wildcard filters still cover sensors/secret
+        assert!(!is_allow_subscribe_filter(
+            &cache_manager,
+            &connection,
+            "sensors/#"
+        ));
+        assert!(!is_allow_subscribe_filter(
+            &cache_manager,
+            &connection,
+            "+/secret"
+        ));
+        assert!(is_allow_subscribe_filter(
+            &cache_manager,
+            &connection,
+            "sensors/public/#"
+        ));
+        assert!(is_allow_subscribe_filter(
+            &cache_manager,
+            &connection,
+            "sensors/+/temp"
+        ));
+    }
+
     #[tokio::test]
     pub async fn check_client_id_wildcard_acl_test() {
         let client_pool = Arc::new(ClientPool::new(1));
@@ -663,6 +810,13 @@ mod test {
         assert!(topic_match(topic_name, &match_topic_name));
         assert!(topic_match(topic_name, topic_name));
         assert!(!topic_match(topic_name, "v1"));
+
+        assert!(topic_match("sensors/room1/temp", "sensors/+/temp"));
+        assert!(topic_match("sensors/room1/temp", "sensors/#"));
+        assert!(!topic_match("sensors/room1/humidity", "sensors/+/temp"));
+        assert!(!topic_match("$SYS/brokers", "#"));
+        // a subscribed filter is covered by a rule that matches all of its topics
+        assert!(topic_match("sensors/+/temp", "sensors/#"));
     }
 
     #[tokio::test]
@@ -675,4 +829,29 @@ mod test {
         assert!(!ip_match(source_ip, "192.1.1.1"));
         assert!(ip_match(source_ip, "127.0.0.1/24"));
     }
+
+    #[test]
+    pub fn allow_accept_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, "test".to_string()));
+        let mut cluster = build_default_cluster_config();
+        cache_manager.set_cluster_info(cluster.clone());
+        let addr = "10.0.1.5:50000".parse().unwrap();
+        let other_addr = "192.168.1.5:50000".parse().unwrap();
+        assert!(is_allow_accept(&cache_manager, &addr));
+
+        cluster.network.accept_ip_allow_list = vec!["10.0.0.0/16".to_string()];
+        cache_manager.set_cluster_info(cluster.clone());
+        assert!(is_allow_accept(&cache_manager, &addr));
+        assert!(!is_allow_accept(&cache_manager, &other_addr));
+
+        // the deny list wins over the allow list
+        cluster.network.accept_ip_deny_list = vec!["10.0.1.0/24".to_string()];
+        cache_manager.set_cluster_info(cluster);
+        assert!(!is_allow_accept(&cache_manager, &addr));
+        assert!(is_allow_accept(
+            &cache_manager,
+            &"10.0.2.5:50000".parse().unwrap()
+        ));
+    }
 }
@@ -0,0 +1,172 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use bytes::Bytes;
+use common_base::config::broker_mqtt::TenantEncryptionKey;
+
+use crate::handler::cache::CacheManager;
+use crate::handler::error::MqttBrokerError;
+
+pub const ENCRYPTION_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+pub trait MessageEncryptor: Send + Sync {
+    fn encrypt(&self, payload: &[u8], key: &[u8]) -> Result<Vec<u8>, MqttBrokerError>;
+
+    fn decrypt(&self, payload: &[u8], key: &[u8]) -> Result<Vec<u8>, MqttBrokerError>;
+}
+
+// The encrypted payload is the random nonce followed by the ciphertext and the authentication tag
+pub struct Aes256GcmEncryptor;
+
+impl MessageEncryptor for Aes256GcmEncryptor {
+    fn encrypt(&self, payload: &[u8], key: &[u8]) -> Result<Vec<u8>, MqttBrokerError> {
+        let cipher = build_cipher(key)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|e| MqttBrokerError::MessageEncryptFailed(e.to_string()))?;
+
+        let mut data = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    fn decrypt(&self, payload: &[u8], key: &[u8]) -> Result<Vec<u8>, MqttBrokerError> {
+        if payload.len() < NONCE_LEN {
+            return Err(MqttBrokerError::MessageDecryptFailed(
+                "the payload is shorter than the nonce".to_string(),
+            ));
+        }
+        let cipher = build_cipher(key)?;
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| MqttBrokerError::MessageDecryptFailed(e.to_string()))
+    }
+}
+
+fn build_cipher(key: &[u8]) -> Result<Aes256Gcm, MqttBrokerError> {
+    if key.len() != ENCRYPTION_KEY_LEN {
+        return Err(MqttBrokerError::MessageEncryptFailed(format!(
+            "the key is {} bytes, expected {}",
+            key.len(),
+            ENCRYPTION_KEY_LEN
+        )));
+    }
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)))
+}
+
+// The tenant of a topic is its first level, the system topics do not belong to a tenant
+pub fn topic_tenant(topic_name: &str) -> Option<&str> {
+    if topic_name.starts_with('$') {
+        return None;
+    }
+    topic_name.split('/').find(|level| !level.is_empty())
+}
+
+pub fn decode_encryption_key(tenant_key: &TenantEncryptionKey) -> Result<Vec<u8>, MqttBrokerError> {
+    let hex = tenant_key.key.as_bytes();
+    if hex.len() != ENCRYPTION_KEY_LEN * 2 {
+        return Err(MqttBrokerError::InvalidEncryptionKey(
+            tenant_key.tenant.clone(),
+        ));
+    }
+    hex.chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| MqttBrokerError::InvalidEncryptionKey(tenant_key.tenant.clone()))
+        })
+        .collect()
+}
+
+// Encrypts the payload before it is written to the storage, if the tenant of the topic has a key
+pub fn encrypt_message_payload(
+    cache_manager: &CacheManager,
+    topic_name: &str,
+    payload: &Bytes,
+) -> Result<Bytes, MqttBrokerError> {
+    let Some(key) = cache_manager.get_topic_encryption_key(topic_name) else {
+        return Ok(payload.clone());
+    };
+    Ok(Bytes::from(Aes256GcmEncryptor.encrypt(payload, &key)?))
+}
+
+// Decrypts the payload read from the storage before it is pushed to the subscribers
+pub fn decrypt_message_payload(
+    cache_manager: &CacheManager,
+    topic_name: &str,
+    payload: &Bytes,
+) -> Result<Bytes, MqttBrokerError> {
+    let Some(key) = cache_manager.get_topic_encryption_key(topic_name) else {
+        return Ok(payload.clone());
+    };
+    Ok(Bytes::from(Aes256GcmEncryptor.decrypt(payload, &key)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use common_base::config::broker_mqtt::TenantEncryptionKey;
+
+    use super::{decode_encryption_key, topic_tenant, Aes256GcmEncryptor, MessageEncryptor};
+
+    #[test]
+    fn aes256gcm_encrypt_decrypt_test() {
+        let key = [7u8; 32];
+        let payload = b"tenant data";
+
+        let encrypted = Aes256GcmEncryptor.encrypt(payload, &key).unwrap();
+        assert_ne!(encrypted.as_slice(), payload.as_slice());
+        assert_eq!(
+            Aes256GcmEncryptor.decrypt(&encrypted, &key).unwrap(),
+            payload.to_vec()
+        );
+
+        // a random nonce per message
+        let encrypted_again = Aes256GcmEncryptor.encrypt(payload, &key).unwrap();
+        assert_ne!(encrypted, encrypted_again);
+
+        assert!(Aes256GcmEncryptor.decrypt(&encrypted, &[8u8; 32]).is_err());
+        assert!(Aes256GcmEncryptor.encrypt(payload, &[7u8; 16]).is_err());
+    }
+
+    #[test]
+    fn topic_tenant_test() {
+        assert_eq!(topic_tenant("tenant1/sensor/1"), Some("tenant1"));
+        assert_eq!(topic_tenant("/tenant1/sensor/1"), Some("tenant1"));
+        assert_eq!(topic_tenant("$SYS/brokers"), None);
+        assert_eq!(topic_tenant("/"), None);
+    }
+
+    #[test]
+    fn decode_encryption_key_test() {
+        let mut tenant_key = TenantEncryptionKey {
+            tenant: "tenant1".to_string(),
+            key: "00".repeat(31) + "ff",
+        };
+        let key = decode_encryption_key(&tenant_key).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(key[31], 0xff);
+
+        tenant_key.key = "zz".repeat(32);
+        assert!(decode_encryption_key(&tenant_key).is_err());
+        tenant_key.key = "00".repeat(16);
+        assert!(decode_encryption_key(&tenant_key).is_err());
+    }
+}
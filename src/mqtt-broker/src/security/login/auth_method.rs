@@ -0,0 +1,216 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::async_trait;
+use log::warn;
+
+use super::plaintext::Plaintext;
+use super::Authentication;
+use crate::handler::cache::CacheManager;
+
+pub const AUTH_METHOD_PLAIN: &str = "PLAIN";
+pub const AUTH_METHOD_SCRAM_SHA_256: &str = "SCRAM-SHA-256";
+pub const AUTH_METHOD_X509: &str = "X509";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthResult {
+    Success,
+    Failure,
+}
+
+/// Authenticates the Authentication Data of a CONNECT carrying the
+/// Authentication Method the authenticator is registered under.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(&self, data: &[u8]) -> AuthResult;
+}
+
+/// The extended authentication methods accepted in the CONNECT, by name.
+#[derive(Default, Clone)]
+pub struct AuthenticationMethodRegistry {
+    methods: HashMap<String, Arc<dyn Authenticator>>,
+}
+
+impl AuthenticationMethodRegistry {
+    pub fn new() -> Self {
+        AuthenticationMethodRegistry::default()
+    }
+
+    // Registers the built-in methods listed in the config, all of them when the list is empty.
+    pub fn build(cache_manager: Arc<CacheManager>, method_names: &[String]) -> Self {
+        let mut registry = AuthenticationMethodRegistry::new();
+        let enabled =
+            |name: &str| method_names.is_empty() || method_names.iter().any(|n| n == name);
+
+        if enabled(AUTH_METHOD_PLAIN) {
+            registry.register(
+                AUTH_METHOD_PLAIN,
+                Arc::new(PlainAuthenticator::new(cache_manager)),
+            );
+        }
+        if enabled(AUTH_METHOD_SCRAM_SHA_256) {
+            registry.register(
+                AUTH_METHOD_SCRAM_SHA_256,
+                Arc::new(ScramSha256Authenticator),
+            );
+        }
+        if enabled(AUTH_METHOD_X509) {
+            registry.register(AUTH_METHOD_X509, Arc::new(X509Authenticator));
+        }
+
+        for name in method_names {
+            if !registry.contains(name) {
+                warn!(
+                    "Authentication method [{}] is not a built-in method and is ignored",
+                    name
+                );
+            }
+        }
+        registry
+    }
+
+    pub fn register(&mut self, method: &str, authenticator: Arc<dyn Authenticator>) {
+        self.methods.insert(method.to_owned(), authenticator);
+    }
+
+    pub fn get(&self, method: &str) -> Option<Arc<dyn Authenticator>> {
+        self.methods.get(method).cloned()
+    }
+
+    pub fn contains(&self, method: &str) -> bool {
+        self.methods.contains_key(method)
+    }
+}
+
+// SASL PLAIN (RFC 4616), the Authentication Data is authzid NUL authcid NUL passwd
+pub struct PlainAuthenticator {
+    cache_manager: Arc<CacheManager>,
+}
+
+impl PlainAuthenticator {
+    pub fn new(cache_manager: Arc<CacheManager>) -> Self {
+        PlainAuthenticator { cache_manager }
+    }
+}
+
+#[async_trait]
+impl Authenticator for PlainAuthenticator {
+    async fn authenticate(&self, data: &[u8]) -> AuthResult {
+        let fields: Vec<&[u8]> = data.split(|b| *b == 0).collect();
+        if fields.len() != 3 {
+            return AuthResult::Failure;
+        }
+        let (Ok(username), Ok(password)) = (
+            String::from_utf8(fields[1].to_vec()),
+            String::from_utf8(fields[2].to_vec()),
+        ) else {
+            return AuthResult::Failure;
+        };
+        if username.is_empty() {
+            return AuthResult::Failure;
+        }
+
+        let plaintext = Plaintext::new(username, password, self.cache_manager.clone());
+        match plaintext.apply().await {
+            Ok(true) => AuthResult::Success,
+            _ => AuthResult::Failure,
+        }
+    }
+}
+
+// SCRAM needs a challenge/response exchange over AUTH packets, which the broker
+// does not process yet, so a single CONNECT can never complete it.
+pub struct ScramSha256Authenticator;
+
+#[async_trait]
+impl Authenticator for ScramSha256Authenticator {
+    async fn authenticate(&self, _: &[u8]) -> AuthResult {
+        warn!("SCRAM-SHA-256 requires the AUTH packet exchange, which is not supported yet");
+        AuthResult::Failure
+    }
+}
+
+// The client certificate is verified by the TLS handshake, which does not request
+// client certificates yet, so there is no verified identity to accept.
+pub struct X509Authenticator;
+
+#[async_trait]
+impl Authenticator for X509Authenticator {
+    async fn authenticate(&self, _: &[u8]) -> AuthResult {
+        warn!("X509 requires the TLS listener to verify client certificates, which is not supported yet");
+        AuthResult::Failure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use common_base::tools::unique_id;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::mqtt::user::MqttUser;
+
+    use super::{
+        AuthResult, AuthenticationMethodRegistry, AUTH_METHOD_PLAIN, AUTH_METHOD_SCRAM_SHA_256,
+        AUTH_METHOD_X509,
+    };
+    use crate::handler::cache::CacheManager;
+
+    #[tokio::test]
+    async fn plain_authenticator_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        cache_manager.add_user(MqttUser {
+            username: "lobo".to_string(),
+            password: "pwd123".to_string(),
+            is_superuser: false,
+        });
+
+        let registry = AuthenticationMethodRegistry::build(cache_manager, &[]);
+        assert!(registry.contains(AUTH_METHOD_SCRAM_SHA_256));
+        assert!(registry.contains(AUTH_METHOD_X509));
+
+        let plain = registry.get(AUTH_METHOD_PLAIN).unwrap();
+        assert_eq!(
+            plain.authenticate(b"\0lobo\0pwd123").await,
+            AuthResult::Success
+        );
+        assert_eq!(
+            plain.authenticate(b"\0lobo\0pwd").await,
+            AuthResult::Failure
+        );
+        assert_eq!(
+            plain.authenticate(b"lobo:pwd123").await,
+            AuthResult::Failure
+        );
+    }
+
+    #[test]
+    fn build_from_config_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+
+        let registry = AuthenticationMethodRegistry::build(
+            cache_manager,
+            &[AUTH_METHOD_PLAIN.to_string(), "UNKNOWN".to_string()],
+        );
+        assert!(registry.contains(AUTH_METHOD_PLAIN));
+        assert!(!registry.contains(AUTH_METHOD_SCRAM_SHA_256));
+        assert!(!registry.contains(AUTH_METHOD_X509));
+        assert!(!registry.contains("UNKNOWN"));
+    }
+}
@@ -15,6 +15,7 @@
 use crate::handler::error::MqttBrokerError;
 use axum::async_trait;
 
+pub mod auth_method;
 pub mod http;
 pub mod jwt;
 pub mod plaintext;
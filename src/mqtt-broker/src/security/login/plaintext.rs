@@ -39,10 +39,19 @@ impl Plaintext {
 #[async_trait]
 impl Authentication for Plaintext {
     async fn apply(&self) -> Result<bool, MqttBrokerError> {
-        if let Some(user) = self.cache_manager.user_info.get(&self.username) {
-            return Ok(user.password == self.password);
-        }
-        return Err(MqttBrokerError::UserDoesNotExist);
+        let Some(user) = self
+            .cache_manager
+            .user_info
+            .get(&self.username)
+            .map(|user| user.clone())
+        else {
+            return Err(MqttBrokerError::UserDoesNotExist);
+        };
+        // argon2 is slow on purpose, it is kept off the runtime threads serving the connections
+        let password = self.password.clone();
+        tokio::task::spawn_blocking(move || user.verify(&password))
+            .await
+            .map_err(|e| MqttBrokerError::CommonError(e.to_string()))
     }
 }
 
@@ -72,11 +81,7 @@ mod test {
         ));
         let username = "lobo".to_string();
         let password = "pwd123".to_string();
-        let user = MqttUser {
-            username: username.clone(),
-            password: password.clone(),
-            is_superuser: true,
-        };
+        let user = MqttUser::new(username.clone(), &password, true).unwrap();
         cache_manager.add_user(user);
 
         let login = Login {
@@ -95,4 +100,28 @@ mod test {
         let res = pt.apply().await.unwrap();
         assert!(!res);
     }
+
+    #[tokio::test]
+    pub async fn plaintext_stored_password_test() {
+        let client_pool: Arc<ClientPool> = Arc::new(ClientPool::new(100));
+        let cache_manager: Arc<CacheManager> =
+            Arc::new(CacheManager::new(client_pool.clone(), "test".to_string()));
+        let username = "lobo".to_string();
+        let user = MqttUser {
+            username: username.clone(),
+            password: "pwd123".to_string(),
+            is_superuser: true,
+        };
+        cache_manager.add_user(user);
+
+        let pt = Plaintext::new(
+            username.clone(),
+            "pwd123".to_string(),
+            cache_manager.clone(),
+        );
+        assert!(pt.apply().await.unwrap());
+
+        let pt = Plaintext::new(username, "pwd1111".to_string(), cache_manager.clone());
+        assert!(!pt.apply().await.unwrap());
+    }
 }
@@ -11,3 +11,75 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+
+use axum::async_trait;
+use protocol::mqtt::common::Login;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use x509_parser::parse_x509_certificate;
+
+use super::Authentication;
+use crate::handler::error::MqttBrokerError;
+
+// The common name of the subject of a DER encoded certificate
+pub fn certificate_common_name(cert: &CertificateDer) -> Option<String> {
+    let (_, cert) = parse_x509_certificate(cert.as_ref()).ok()?;
+    let common_name = cert.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(|name| name.to_string())
+}
+
+/// Login of a client authenticated by its TLS certificate, the username of the CONNECT must be
+/// the common name of the certificate.
+pub struct X509 {
+    common_name: String,
+    login: Option<Login>,
+}
+
+impl X509 {
+    pub fn new(common_name: String, login: Option<Login>) -> Self {
+        X509 { common_name, login }
+    }
+}
+
+#[async_trait]
+impl Authentication for X509 {
+    async fn apply(&self) -> Result<bool, MqttBrokerError> {
+        if let Some(login) = &self.login {
+            return Ok(login.username == self.common_name);
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use protocol::mqtt::common::Login;
+    use rcgen::{CertificateParams, DnType, KeyPair};
+
+    use super::{certificate_common_name, X509};
+    use crate::security::login::Authentication;
+
+    #[tokio::test]
+    pub async fn x509_test() {
+        let key_pair = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "sensor-1");
+        let cert = params.self_signed(&key_pair).unwrap();
+        let common_name = certificate_common_name(cert.der()).unwrap();
+        assert_eq!(common_name, "sensor-1");
+
+        let login = |username: &str| {
+            Some(Login {
+                username: username.to_string(),
+                password: "".to_string(),
+            })
+        };
+        let x509 = X509::new(common_name.clone(), login("sensor-1"));
+        assert!(x509.apply().await.unwrap());
+        let x509 = X509::new(common_name.clone(), login("sensor-2"));
+        assert!(!x509.apply().await.unwrap());
+        let x509 = X509::new(common_name, None);
+        assert!(!x509.apply().await.unwrap());
+    }
+}
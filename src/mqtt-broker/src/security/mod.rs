@@ -17,13 +17,17 @@ use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use acl::auth::is_allow_acl;
+use acl::auth::{is_allow_acl, is_allow_subscribe_filter};
 use axum::async_trait;
+use bytes::Bytes;
 use common_base::config::broker_mqtt::broker_mqtt_conf;
 use common_base::config::common::Auth;
 use dashmap::DashMap;
 use grpc_clients::pool::ClientPool;
+use log::warn;
+use login::auth_method::{AuthResult, AuthenticationMethodRegistry, Authenticator};
 use login::plaintext::Plaintext;
+use login::x509::X509;
 use login::Authentication;
 use metadata_struct::acl::mqtt_acl::{MqttAcl, MqttAclAction, MqttAclResourceType};
 use metadata_struct::acl::mqtt_blacklist::MqttAclBlackList;
@@ -37,9 +41,10 @@ use storage_adapter::StorageType;
 use crate::handler::cache::CacheManager;
 use crate::handler::error::MqttBrokerError;
 use crate::security::acl::auth::is_blacklist;
-use crate::subscribe::sub_common::get_sub_topic_id_list;
+use crate::subscribe::sub_common::sub_path_filter;
 
 pub mod acl;
+pub mod encryption;
 pub mod login;
 pub mod storage;
 
@@ -55,6 +60,8 @@ pub trait AuthStorageAdapter {
 
     async fn save_user(&self, user_info: MqttUser) -> Result<(), MqttBrokerError>;
 
+    async fn update_user(&self, user_info: MqttUser) -> Result<(), MqttBrokerError>;
+
     async fn delete_user(&self, username: String) -> Result<(), MqttBrokerError>;
 
     async fn save_acl(&self, acl: MqttAcl) -> Result<(), MqttBrokerError>;
@@ -70,6 +77,7 @@ pub struct AuthDriver {
     cache_manager: Arc<CacheManager>,
     client_pool: Arc<ClientPool>,
     driver: Arc<dyn AuthStorageAdapter + Send + 'static + Sync>,
+    authentication_methods: AuthenticationMethodRegistry,
 }
 
 impl AuthDriver {
@@ -81,13 +89,26 @@ impl AuthDriver {
                 panic!("{}", e.to_string());
             }
         };
+        let authentication_methods = AuthenticationMethodRegistry::build(
+            cache_manager.clone(),
+            &conf.auth.authentication_methods,
+        );
         AuthDriver {
             cache_manager,
             driver,
             client_pool,
+            authentication_methods,
         }
     }
 
+    pub fn register_authentication_method(
+        &mut self,
+        method: &str,
+        authenticator: Arc<dyn Authenticator>,
+    ) {
+        self.authentication_methods.register(method, authenticator);
+    }
+
     pub fn update_driver(&mut self, auth: Auth) -> Result<(), MqttBrokerError> {
         let driver = build_driver(self.client_pool.clone(), auth)?;
         self.driver = driver;
@@ -142,7 +163,7 @@ impl AuthDriver {
     pub async fn check_login_auth(
         &self,
         login: &Option<Login>,
-        _: &Option<ConnectProperties>,
+        connect_properties: &Option<ConnectProperties>,
         _: &SocketAddr,
     ) -> Result<bool, MqttBrokerError> {
         let cluster = self.cache_manager.get_cluster_info();
@@ -151,13 +172,35 @@ impl AuthDriver {
             return Ok(true);
         }
 
+        // Extended authentication, dispatched to the authenticator of the method
+        if let Some(properties) = connect_properties {
+            if let Some(method) = &properties.authentication_method {
+                return self
+                    .extended_auth_check_login(method, &properties.authentication_data)
+                    .await;
+            }
+        }
+
         if let Some(info) = login {
-            return self
-                .plaintext_check_login(&info.username, &info.password)
-                .await;
+            if !info.username.is_empty() {
+                return self
+                    .plaintext_check_login(&info.username, &info.password)
+                    .await;
+            }
         }
 
-        Ok(false)
+        // Anonymous login, rejected unless explicitly allowed
+        Ok(cluster.security.allow_anonymous)
+    }
+
+    // A client that presented a certificate to the TLS listener requiring one logs in as the
+    // common name of the certificate, the password is not checked
+    pub async fn check_client_cert_login(
+        &self,
+        common_name: String,
+        login: &Option<Login>,
+    ) -> Result<bool, MqttBrokerError> {
+        X509::new(common_name, login.clone()).apply().await
     }
 
     pub async fn save_acl(&self, acl: MqttAcl) -> Result<(), MqttBrokerError> {
@@ -228,27 +271,46 @@ impl AuthDriver {
         )
     }
 
+    // Whether each filter of the SUBSCRIBE is allowed, in the order of the filters. A filter
+    // overlapping a deny rule is refused, so that the topics created after the SUBSCRIBE are
+    // covered too.
     pub async fn allow_subscribe(
         &self,
         connection: &MQTTConnection,
         subscribe: &Subscribe,
-    ) -> bool {
-        for filter in subscribe.filters.clone() {
-            let topic_list = get_sub_topic_id_list(&self.cache_manager, &filter.path).await;
-            for topic in topic_list {
-                if !is_allow_acl(
+    ) -> Vec<bool> {
+        subscribe
+            .filters
+            .iter()
+            .map(|filter| {
+                is_allow_subscribe_filter(
                     &self.cache_manager,
                     connection,
-                    &topic,
-                    MqttAclAction::Subscribe,
-                    false,
-                    filter.qos,
-                ) {
-                    return false;
-                }
-            }
-        }
-        true
+                    &sub_path_filter(&filter.path),
+                )
+            })
+            .collect()
+    }
+
+    // Runs the authenticator of the method again, on an AUTH with reason ReAuthenticate
+    pub async fn re_authenticate(
+        &self,
+        method: &str,
+        data: &Option<Bytes>,
+    ) -> Result<bool, MqttBrokerError> {
+        self.extended_auth_check_login(method, data).await
+    }
+
+    async fn extended_auth_check_login(
+        &self,
+        method: &str,
+        data: &Option<Bytes>,
+    ) -> Result<bool, MqttBrokerError> {
+        let Some(authenticator) = self.authentication_methods.get(method) else {
+            return Err(MqttBrokerError::BadAuthenticationMethod(method.to_owned()));
+        };
+        let data = data.clone().unwrap_or_default();
+        Ok(authenticator.authenticate(&data).await == AuthResult::Success)
     }
 
     async fn plaintext_check_login(
@@ -264,13 +326,15 @@ impl AuthDriver {
         match plaintext.apply().await {
             Ok(flag) => {
                 if flag {
+                    self.try_upgrade_plaintext_password(username, password)
+                        .await;
                     return Ok(true);
                 }
             }
             Err(e) => {
                 // If the user does not exist, try to get the user information from the storage layer
                 if e.to_string() == MqttBrokerError::UserDoesNotExist.to_string() {
-                    return self.try_get_check_user_by_driver(username).await;
+                    return self.try_get_check_user_by_driver(username, password).await;
                 }
                 return Err(e);
             }
@@ -278,23 +342,80 @@ impl AuthDriver {
         Ok(false)
     }
 
-    async fn try_get_check_user_by_driver(&self, username: &str) -> Result<bool, MqttBrokerError> {
+    async fn try_get_check_user_by_driver(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<bool, MqttBrokerError> {
         if let Some(user) = self.driver.get_user(username.to_owned()).await? {
             self.cache_manager.add_user(user.clone());
 
             let plaintext = Plaintext::new(
                 user.username.clone(),
-                user.password.clone(),
+                password.to_owned(),
                 self.cache_manager.clone(),
             );
 
             if plaintext.apply().await? {
+                self.try_upgrade_plaintext_password(username, password)
+                    .await;
                 return Ok(true);
             }
         }
 
         Ok(false)
     }
+
+    // Replaces a plaintext stored password by its hash once the user logged in with it
+    async fn try_upgrade_plaintext_password(&self, username: &str, password: &str) {
+        let cluster = self.cache_manager.get_cluster_info();
+        if !cluster.security.upgrade_plaintext_password {
+            return;
+        }
+
+        let Some(user) = self
+            .cache_manager
+            .user_info
+            .get(username)
+            .map(|user| user.clone())
+        else {
+            return;
+        };
+        if !user.password_hash().is_plaintext() {
+            return;
+        }
+
+        let password = password.to_owned();
+        let upgraded = match tokio::task::spawn_blocking(move || {
+            MqttUser::new(user.username.clone(), &password, user.is_superuser)
+        })
+        .await
+        {
+            Ok(Ok(upgraded)) => upgraded,
+            Ok(Err(e)) => {
+                warn!(
+                    "Failed to upgrade the password of user {}, error message: {}",
+                    username, e
+                );
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to upgrade the password of user {}, error message: {}",
+                    username, e
+                );
+                return;
+            }
+        };
+        if let Err(e) = self.driver.update_user(upgraded.clone()).await {
+            warn!(
+                "Failed to upgrade the password of user {}, error message: {}",
+                username, e
+            );
+            return;
+        }
+        self.cache_manager.add_user(upgraded);
+    }
 }
 
 pub fn build_driver(
@@ -315,3 +436,117 @@ pub fn build_driver(
 
     Err(MqttBrokerError::UnavailableStorageType)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use grpc_clients::pool::ClientPool;
+    use protocol::mqtt::common::Login;
+
+    use axum::async_trait;
+    use bytes::Bytes;
+    use protocol::mqtt::common::ConnectProperties;
+
+    use super::login::auth_method::{AuthResult, Authenticator};
+    use super::AuthDriver;
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::handler::error::MqttBrokerError;
+
+    fn build_auth_driver(allow_anonymous: bool) -> AuthDriver {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        let mut cluster = build_default_cluster_config();
+        cluster.security.allow_anonymous = allow_anonymous;
+        cache_manager.set_cluster_info(cluster);
+        AuthDriver::new(cache_manager, client_pool)
+    }
+
+    #[tokio::test]
+    async fn anonymous_login_allowed_test() {
+        let auth_driver = build_auth_driver(true);
+        let addr: SocketAddr = "127.0.0.1:1883".parse().unwrap();
+
+        assert!(auth_driver
+            .check_login_auth(&None, &None, &addr)
+            .await
+            .unwrap());
+
+        let login = Some(Login {
+            username: "".to_string(),
+            password: "".to_string(),
+        });
+        assert!(auth_driver
+            .check_login_auth(&login, &None, &addr)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn anonymous_login_rejected_test() {
+        let auth_driver = build_auth_driver(false);
+        let addr: SocketAddr = "127.0.0.1:1883".parse().unwrap();
+
+        // the user store is empty, the connection must still be rejected
+        assert!(!auth_driver
+            .check_login_auth(&None, &None, &addr)
+            .await
+            .unwrap());
+
+        let login = Some(Login {
+            username: "".to_string(),
+            password: "".to_string(),
+        });
+        assert!(!auth_driver
+            .check_login_auth(&login, &None, &addr)
+            .await
+            .unwrap());
+    }
+
+    struct NoopAuthenticator;
+
+    #[async_trait]
+    impl Authenticator for NoopAuthenticator {
+        async fn authenticate(&self, _: &[u8]) -> AuthResult {
+            AuthResult::Success
+        }
+    }
+
+    fn build_auth_properties(method: &str) -> Option<ConnectProperties> {
+        Some(ConnectProperties {
+            authentication_method: Some(method.to_string()),
+            authentication_data: Some(Bytes::from_static(b"token")),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn custom_authentication_method_test() {
+        let mut auth_driver = build_auth_driver(false);
+        auth_driver.register_authentication_method("NOOP", Arc::new(NoopAuthenticator));
+        let addr: SocketAddr = "127.0.0.1:1883".parse().unwrap();
+
+        assert!(auth_driver
+            .check_login_auth(&None, &build_auth_properties("NOOP"), &addr)
+            .await
+            .unwrap());
+
+        let res = auth_driver
+            .check_login_auth(&None, &build_auth_properties("UNKNOWN"), &addr)
+            .await;
+        assert!(matches!(
+            res,
+            Err(MqttBrokerError::BadAuthenticationMethod(method)) if method == "UNKNOWN"
+        ));
+    }
+}
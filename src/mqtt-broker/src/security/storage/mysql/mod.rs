@@ -20,7 +20,7 @@ use metadata_struct::acl::mqtt_acl::{
 use metadata_struct::acl::mqtt_blacklist::MqttAclBlackList;
 use metadata_struct::mqtt::user::MqttUser;
 use mysql::prelude::Queryable;
-use mysql::Pool;
+use mysql::{params, Pool};
 use third_driver::mysql::build_mysql_conn_pool;
 
 use crate::handler::error::MqttBrokerError;
@@ -147,6 +147,23 @@ impl AuthStorageAdapter for MySQLAuthStorageAdapter {
         return Ok(());
     }
 
+    async fn update_user(&self, user_info: MqttUser) -> Result<(), MqttBrokerError> {
+        let mut conn = self.pool.get_conn()?;
+        let sql = format!(
+            "update {} set `password` = :password, `is_superuser` = :is_superuser where `username` = :username;",
+            self.table_user(),
+        );
+        conn.exec_drop(
+            sql,
+            params! {
+                "password" => user_info.password,
+                "is_superuser" => user_info.is_superuser as i32,
+                "username" => user_info.username,
+            },
+        )?;
+        return Ok(());
+    }
+
     async fn delete_user(&self, username: String) -> Result<(), MqttBrokerError> {
         let mut conn = self.pool.get_conn()?;
         let sql = format!(
@@ -64,6 +64,11 @@ impl AuthStorageAdapter for PlacementAuthStorageAdapter {
         return user_storage.save_user(user_info).await;
     }
 
+    async fn update_user(&self, user_info: MqttUser) -> Result<(), MqttBrokerError> {
+        let user_storage = UserStorage::new(self.client_pool.clone());
+        return user_storage.save_user(user_info).await;
+    }
+
     async fn delete_user(&self, username: String) -> Result<(), MqttBrokerError> {
         let user_storage = UserStorage::new(self.client_pool.clone());
         return user_storage.delete_user(username).await;
@@ -14,10 +14,12 @@
 
 use std::fmt;
 use std::net::SocketAddr;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
+use common_base::tools::now_mills;
 use log::error;
-use protocol::mqtt::common::MqttProtocol;
+use protocol::mqtt::common::{MqttPacket, MqttProtocol};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 static CONNECTION_ID_BUILD: AtomicU64 = AtomicU64::new(1);
@@ -47,6 +49,49 @@ impl fmt::Display for NetworkConnectionType {
     }
 }
 
+/// Traffic of a connection since it was accepted, reported in its disconnect event.
+#[derive(Default)]
+pub struct ConnectionStats {
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionStatsSnapshot {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl ConnectionStats {
+    pub fn record_sent(&self, packet: &MqttPacket, packet_size: u64) {
+        if let MqttPacket::Publish(_, _) = packet {
+            self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes_sent.fetch_add(packet_size, Ordering::Relaxed);
+    }
+
+    pub fn record_received(&self, packet: &MqttPacket, packet_size: u64) {
+        if let MqttPacket::Publish(_, _) = packet {
+            self.messages_received.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes_received
+            .fetch_add(packet_size, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ConnectionStatsSnapshot {
+        ConnectionStatsSnapshot {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct NetworkConnection {
     pub connection_type: NetworkConnectionType,
@@ -55,6 +100,15 @@ pub struct NetworkConnection {
     pub addr: SocketAddr,
     #[serde(skip_serializing, skip_deserializing)]
     pub connection_stop_sx: Option<mpsc::Sender<bool>>,
+    // Set when the first CONNECT packet of the connection is received
+    #[serde(skip_serializing, skip_deserializing)]
+    pub has_connected: Arc<AtomicBool>,
+    // Shared by all the clones of the connection
+    #[serde(skip_serializing, skip_deserializing)]
+    pub stats: Arc<ConnectionStats>,
+    // Common name of the certificate the client presented in the TLS handshake
+    pub client_cert_common_name: Option<String>,
+    pub create_time: u128,
 }
 
 impl NetworkConnection {
@@ -70,13 +124,26 @@ impl NetworkConnection {
             protocol: None,
             addr,
             connection_stop_sx,
+            has_connected: Arc::new(AtomicBool::new(false)),
+            stats: Arc::new(ConnectionStats::default()),
+            client_cert_common_name: None,
+            create_time: now_mills(),
         }
     }
 
+    // Marks the connection as connected, returns whether a CONNECT was already received on it
+    pub fn mark_connected(&self) -> bool {
+        self.has_connected.swap(true, Ordering::SeqCst)
+    }
+
     pub fn connection_id(&self) -> u64 {
         self.connection_id
     }
 
+    pub fn set_client_cert_common_name(&mut self, common_name: Option<String>) {
+        self.client_cert_common_name = common_name;
+    }
+
     pub fn set_protocol(&mut self, protocol: MqttProtocol) {
         self.protocol = Some(protocol);
     }
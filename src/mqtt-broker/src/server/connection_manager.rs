@@ -12,16 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use axum::extract::ws::{Message, WebSocket};
+use common_base::config::default_mqtt::default_network_max_total_connections;
 use dashmap::DashMap;
 use futures::stream::SplitSink;
 use futures::SinkExt;
 use log::{debug, info};
-use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
+use protocol::mqtt::codec::{calc_mqtt_packet_size, MqttCodec, MqttPacketWrapper};
 use protocol::mqtt::common::MqttProtocol;
+use tokio::io::AsyncWrite;
+use tokio::sync::broadcast;
 use tokio::time::sleep;
 use tokio_util::codec::FramedWrite;
 
@@ -44,7 +48,12 @@ pub struct ConnectionManager {
     >,
     websocket_write_list: DashMap<u64, SplitSink<WebSocket, Message>>,
     quic_write_list: DashMap<u64, QuicFramedWriteStream>,
+    // (connection_id, signal sent when the connection is closed)
+    connection_close_sx: DashMap<u64, broadcast::Sender<bool>>,
     cache_manager: Arc<CacheManager>,
+    // TCP and TLS connections accepted and not closed yet
+    total_connections: Arc<AtomicUsize>,
+    max_total_connections: usize,
 }
 
 impl ConnectionManager {
@@ -54,6 +63,7 @@ impl ConnectionManager {
         let tcp_tls_write_list = DashMap::with_capacity(64);
         let websocket_write_list = DashMap::with_capacity(64);
         let quic_write_list = DashMap::with_capacity(64);
+        let connection_close_sx = DashMap::with_capacity(64);
         ConnectionManager {
             connections,
             tcp_write_list,
@@ -61,15 +71,57 @@ impl ConnectionManager {
             cache_manager,
             websocket_write_list,
             quic_write_list,
+            connection_close_sx,
+            total_connections: Arc::new(AtomicUsize::new(0)),
+            max_total_connections: default_network_max_total_connections(),
         }
     }
 
+    pub fn with_max_total_connections(mut self, max_total_connections: usize) -> Self {
+        self.max_total_connections = max_total_connections;
+        self
+    }
+
+    // Counts an accepted TCP connection, returns false when the broker already holds the maximum.
+    pub fn try_acquire_total_connection(&self) -> bool {
+        self.total_connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+                (total < self.max_total_connections).then_some(total + 1)
+            })
+            .is_ok()
+    }
+
+    pub fn release_total_connection(&self) {
+        let _ = self
+            .total_connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+                total.checked_sub(1)
+            });
+    }
+
+    pub fn total_connections(&self) -> usize {
+        self.total_connections.load(Ordering::SeqCst)
+    }
+
     pub fn add_connection(&self, connection: NetworkConnection) -> u64 {
         let connection_id = connection.connection_id();
         self.connections.insert(connection_id, connection);
+        let (close_sx, _) = broadcast::channel(1);
+        self.connection_close_sx.insert(connection_id, close_sx);
         connection_id
     }
 
+    // Returns once the connection is closed, at once if it is not open
+    pub async fn wait_connection_close(&self, connection_id: u64) {
+        // Subscribed while the entry is held, so a close_connect that removes it after this
+        // point is always received
+        let mut close_rx = match self.connection_close_sx.get(&connection_id) {
+            Some(close_sx) => close_sx.subscribe(),
+            None => return,
+        };
+        let _ = close_rx.recv().await;
+    }
+
     pub fn list_connect(&self) -> DashMap<u64, NetworkConnection> {
         self.connections.clone()
     }
@@ -115,8 +167,12 @@ impl ConnectionManager {
         if let Some((_, connection)) = self.connections.remove(&connection_id) {
             connection.stop_connection().await;
         }
+        if let Some((_, close_sx)) = self.connection_close_sx.remove(&connection_id) {
+            let _ = close_sx.send(true);
+        }
 
         if let Some((id, mut stream)) = self.tcp_write_list.remove(&connection_id) {
+            self.release_total_connection();
             if stream.close().await.is_ok() {
                 debug!(
                     "server closes the tcp connection actively, connection id [{}]",
@@ -126,6 +182,7 @@ impl ConnectionManager {
         }
 
         if let Some((id, mut stream)) = self.tcp_tls_write_list.remove(&connection_id) {
+            self.release_total_connection();
             if stream.close().await.is_ok() {
                 debug!(
                     "server closes the tcp connection actively, connection id [{}]",
@@ -159,14 +216,7 @@ impl ConnectionManager {
                 dashmap::try_result::TryResult::Present(mut da) => {
                     match da.send(resp.clone()).await {
                         Ok(_) => {
-                            let network_type =
-                                if let Some(connection) = self.get_connect(connection_id) {
-                                    connection.connection_type.to_string()
-                                } else {
-                                    "".to_string()
-                                };
-
-                            record_sent_metrics(&packet_wrapper, network_type);
+                            self.record_sent(connection_id, &packet_wrapper);
                             info!("WebSockets response success,connection_id:{connection_id}");
                             break;
                         }
@@ -227,15 +277,7 @@ impl ConnectionManager {
                 dashmap::try_result::TryResult::Present(mut da) => {
                     match da.send(resp.clone()).await {
                         Ok(_) => {
-                            // write tls stream
-                            let network_type =
-                                if let Some(connection) = self.get_connect(connection_id) {
-                                    connection.connection_type.to_string()
-                                } else {
-                                    "".to_string()
-                                };
-
-                            record_sent_metrics(&resp, network_type);
+                            self.record_sent(connection_id, &resp);
                             break;
                         }
                         Err(e) => {
@@ -277,6 +319,89 @@ impl ConnectionManager {
         Ok(())
     }
 
+    /// Writes several packets to the same tcp/tls connection with a single flush,
+    /// so that the encoded frames are coalesced into as few write syscalls as possible.
+    /// Each packet is still encoded as an independent MQTT frame.
+    pub async fn write_tcp_frames(
+        &self,
+        connection_id: u64,
+        resps: Vec<MqttPacketWrapper>,
+    ) -> Result<(), MqttBrokerError> {
+        if resps.is_empty() {
+            return Ok(());
+        }
+
+        if resps.len() == 1 {
+            let resp = resps.into_iter().next().unwrap();
+            return self.write_tcp_frame(connection_id, resp).await;
+        }
+
+        debug!(
+            "Tcp response batch packet num:{},connection_id:{connection_id}",
+            resps.len()
+        );
+
+        let is_tls = if let Some(connection) = self.get_connect(connection_id) {
+            connection.connection_type == NetworkConnectionType::Tls
+        } else {
+            false
+        };
+
+        let mut times = 0;
+        // The packets already in the write buffer, a retry feeds only the rest of them
+        let mut fed = 0;
+        let cluster = self.cache_manager.get_cluster_info();
+        loop {
+            let result = if is_tls {
+                match self.tcp_tls_write_list.try_get_mut(&connection_id) {
+                    dashmap::try_result::TryResult::Present(mut da) => {
+                        Some(feed_and_flush_from(da.value_mut(), &resps, &mut fed).await)
+                    }
+                    _ => None,
+                }
+            } else {
+                match self.tcp_write_list.try_get_mut(&connection_id) {
+                    dashmap::try_result::TryResult::Present(mut da) => {
+                        Some(feed_and_flush_from(da.value_mut(), &resps, &mut fed).await)
+                    }
+                    _ => None,
+                }
+            };
+
+            match result {
+                Some(Ok(())) => {
+                    for resp in resps.iter() {
+                        self.record_sent(connection_id, resp);
+                    }
+                    break;
+                }
+                Some(Err(e)) => {
+                    if times > cluster.network.response_max_try_mut_times {
+                        return Err(MqttBrokerError::CommonError(format!(
+                            "Failed to write batch data to the mqtt tcp client, error message: {e:?}"
+                        )));
+                    }
+                }
+                None => {
+                    if times > cluster.network.response_max_try_mut_times {
+                        return Err(MqttBrokerError::CommonError(
+                            format!(
+                                "[write_frames]Connection management could not obtain an available tcp connection. Connection ID: {}",
+                                connection_id
+                            )
+                        ));
+                    }
+                }
+            }
+            times += 1;
+            sleep(Duration::from_millis(
+                cluster.network.response_try_mut_sleep_time_ms,
+            ))
+            .await
+        }
+        Ok(())
+    }
+
     async fn write_tcp_tls_frame(
         &self,
         connection_id: u64,
@@ -289,14 +414,7 @@ impl ConnectionManager {
                 dashmap::try_result::TryResult::Present(mut da) => {
                     match da.send(resp.clone()).await {
                         Ok(_) => {
-                            let network_type =
-                                if let Some(connection) = self.get_connect(connection_id) {
-                                    connection.connection_type.to_string()
-                                } else {
-                                    "".to_string()
-                                };
-
-                            record_sent_metrics(&resp, network_type);
+                            self.record_sent(connection_id, &resp);
                             break;
                         }
                         Err(e) => {
@@ -346,6 +464,18 @@ impl ConnectionManager {
         false
     }
 
+    fn record_sent(&self, connection_id: u64, resp: &MqttPacketWrapper) {
+        let network_type = if let Some(connection) = self.connections.get(&connection_id) {
+            connection
+                .stats
+                .record_sent(&resp.packet, calc_mqtt_packet_size(resp.clone()) as u64);
+            connection.connection_type.to_string()
+        } else {
+            "".to_string()
+        };
+        record_sent_metrics(resp, network_type);
+    }
+
     pub fn get_connect(&self, connect_id: u64) -> Option<NetworkConnection> {
         if let Some(connect) = self.connections.get(&connect_id) {
             return Some(connect.clone());
@@ -378,3 +508,33 @@ impl ConnectionManager {
         false
     }
 }
+
+// Encodes all packets into the write buffer of the FramedWrite first and flushes once at the end.
+pub(crate) async fn feed_and_flush<T>(
+    write: &mut FramedWrite<T, MqttCodec>,
+    resps: &[MqttPacketWrapper],
+) -> Result<(), protocol::mqtt::common::Error>
+where
+    T: AsyncWrite + Unpin,
+{
+    let mut fed = 0;
+    feed_and_flush_from(write, resps, &mut fed).await
+}
+
+// Like feed_and_flush, starting at the packet fed. fed is advanced past every packet encoded
+// into the write buffer, so that after an error the call can be retried without writing a
+// frame twice.
+pub(crate) async fn feed_and_flush_from<T>(
+    write: &mut FramedWrite<T, MqttCodec>,
+    resps: &[MqttPacketWrapper],
+    fed: &mut usize,
+) -> Result<(), protocol::mqtt::common::Error>
+where
+    T: AsyncWrite + Unpin,
+{
+    for resp in resps[*fed..].iter() {
+        write.feed(resp.clone()).await?;
+        *fed += 1;
+    }
+    write.flush().await
+}
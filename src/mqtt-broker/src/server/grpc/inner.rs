@@ -16,12 +16,14 @@ use std::sync::Arc;
 
 use common_base::config::broker_mqtt::broker_mqtt_conf;
 use grpc_clients::pool::ClientPool;
-use log::debug;
+use log::{debug, warn};
 use metadata_struct::mqtt::lastwill::LastWillData;
 use protocol::broker_mqtt::broker_mqtt_inner::mqtt_broker_inner_service_server::MqttBrokerInnerService;
 use protocol::broker_mqtt::broker_mqtt_inner::{
-    DeleteSessionReply, DeleteSessionRequest, SendLastWillMessageReply, SendLastWillMessageRequest,
-    UpdateMqttCacheReply, UpdateMqttCacheRequest,
+    DeleteSessionReply, DeleteSessionRequest, ExportSessionReply, ExportSessionRequest,
+    ForwardMessageReply, ForwardMessageRequest, ImportSessionReply, ImportSessionRequest,
+    SendLastWillMessageReply, SendLastWillMessageRequest, UpdateMqttCacheReply,
+    UpdateMqttCacheRequest,
 };
 use schema_register::schema::SchemaRegisterManager;
 use storage_adapter::storage::StorageAdapter;
@@ -30,11 +32,15 @@ use tonic::{Request, Response, Status};
 use crate::bridge::manager::ConnectorManager;
 use crate::handler::cache::CacheManager;
 use crate::handler::cache_update::update_cache_metadata;
-use crate::handler::lastwill::send_last_will_message;
+use crate::handler::lastwill::{delete_last_will_message, send_last_will_message};
+use crate::handler::session_migrate::{export_session, import_session, SessionState};
+use crate::server::connection_manager::ConnectionManager;
+use crate::subscribe::cluster_route::save_forwarded_message;
 use crate::subscribe::subscribe_manager::SubscribeManager;
 
 pub struct GrpcInnerServices<S> {
     cache_manager: Arc<CacheManager>,
+    connection_manager: Arc<ConnectionManager>,
     connector_manager: Arc<ConnectorManager>,
     subscribe_manager: Arc<SubscribeManager>,
     schema_manager: Arc<SchemaRegisterManager>,
@@ -45,6 +51,7 @@ pub struct GrpcInnerServices<S> {
 impl<S> GrpcInnerServices<S> {
     pub fn new(
         cache_manager: Arc<CacheManager>,
+        connection_manager: Arc<ConnectionManager>,
         subscribe_manager: Arc<SubscribeManager>,
         connector_manager: Arc<ConnectorManager>,
         schema_manager: Arc<SchemaRegisterManager>,
@@ -53,6 +60,7 @@ impl<S> GrpcInnerServices<S> {
     ) -> Self {
         GrpcInnerServices {
             cache_manager,
+            connection_manager,
             subscribe_manager,
             connector_manager,
             client_pool,
@@ -78,6 +86,7 @@ where
         }
         update_cache_metadata(
             &self.cache_manager,
+            &self.connection_manager,
             &self.connector_manager,
             &self.subscribe_manager,
             &self.schema_manager,
@@ -136,6 +145,10 @@ where
         .await
         {
             Ok(()) => {
+                // A will is published once, the placement center must not find it again
+                if let Err(e) = delete_last_will_message(req.client_id, &self.client_pool).await {
+                    warn!("delete last will message failed, {}", e.to_string());
+                }
                 return Ok(Response::new(SendLastWillMessageReply::default()));
             }
             Err(e) => {
@@ -143,4 +156,79 @@ where
             }
         }
     }
+
+    async fn forward_message(
+        &self,
+        request: Request<ForwardMessageRequest>,
+    ) -> Result<Response<ForwardMessageReply>, Status> {
+        let req = request.into_inner();
+        let conf = broker_mqtt_conf();
+        if conf.cluster_name != req.cluster_name {
+            return Err(Status::cancelled("Cluster name does not match".to_string()));
+        }
+
+        match save_forwarded_message(
+            &self.cache_manager,
+            &self.client_pool,
+            &self.message_storage_adapter,
+            req,
+        )
+        .await
+        {
+            Ok(_) => Ok(Response::new(ForwardMessageReply::default())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn export_session(
+        &self,
+        request: Request<ExportSessionRequest>,
+    ) -> Result<Response<ExportSessionReply>, Status> {
+        let req = request.into_inner();
+        let conf = broker_mqtt_conf();
+        if conf.cluster_name != req.cluster_name {
+            return Err(Status::cancelled("Cluster name does not match".to_string()));
+        }
+
+        match export_session(
+            &self.cache_manager,
+            &self.subscribe_manager,
+            &self.message_storage_adapter,
+            &req.client_id,
+        )
+        .await
+        .and_then(|state| state.encode())
+        {
+            Ok(session_state) => Ok(Response::new(ExportSessionReply { session_state })),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn import_session(
+        &self,
+        request: Request<ImportSessionRequest>,
+    ) -> Result<Response<ImportSessionReply>, Status> {
+        let req = request.into_inner();
+        let conf = broker_mqtt_conf();
+        if conf.cluster_name != req.cluster_name {
+            return Err(Status::cancelled("Cluster name does not match".to_string()));
+        }
+
+        let state = match SessionState::decode(&req.session_state) {
+            Ok(state) => state,
+            Err(e) => return Err(Status::invalid_argument(e.to_string())),
+        };
+        match import_session(
+            &self.cache_manager,
+            &self.subscribe_manager,
+            &self.message_storage_adapter,
+            &self.client_pool,
+            state,
+        )
+        .await
+        {
+            Ok(()) => Ok(Response::new(ImportSessionReply::default())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
 }
@@ -72,6 +72,7 @@ where
         info!("Broker Grpc Server start success. port:{}", self.port);
         let inner_handler = GrpcInnerServices::new(
             self.metadata_cache.clone(),
+            self.connection_manager.clone(),
             self.subscribe_manager.clone(),
             self.connector_manager.clone(),
             self.schema_manager.clone(),
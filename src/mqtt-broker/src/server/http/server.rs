@@ -0,0 +1,407 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use common_base::config::broker_mqtt::broker_mqtt_conf;
+use grpc_clients::pool::ClientPool;
+use log::info;
+use serde::Deserialize;
+use storage_adapter::storage::StorageAdapter;
+use tokio_util::io::ReaderStream;
+
+use crate::handler::cache::CacheManager;
+use crate::handler::error::MqttBrokerError;
+use crate::handler::retain::resync_retained;
+use crate::server::connection_manager::ConnectionManager;
+use crate::storage::message::{ExportFormat, MessageStorage};
+use crate::subscribe::share_leader_push::share_group_lag;
+use crate::subscribe::subscribe_manager::SubscribeManager;
+
+// The topic name is a single path segment, so the '/' in it must be percent-encoded
+pub const ROUTE_TOPIC_EXPORT: &str = "/topics/:name/export";
+pub const ROUTE_CLIENT_RETAINED_RESYNC: &str = "/clients/:client_id/retained/resync";
+pub const ROUTE_SHARE_GROUP_LAG: &str = "/share-groups/lag";
+pub const ROUTE_SUBSCRIPTION_INDEX_REBUILD: &str = "/subscriptions/index/rebuild";
+
+#[derive(Clone)]
+pub struct HttpServerState<S> {
+    cache_manager: Arc<CacheManager>,
+    message_storage_adapter: Arc<S>,
+    client_pool: Arc<ClientPool>,
+    connection_manager: Arc<ConnectionManager>,
+    subscribe_manager: Arc<SubscribeManager>,
+}
+
+impl<S> HttpServerState<S>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    pub fn new(
+        cache_manager: Arc<CacheManager>,
+        message_storage_adapter: Arc<S>,
+        client_pool: Arc<ClientPool>,
+        connection_manager: Arc<ConnectionManager>,
+        subscribe_manager: Arc<SubscribeManager>,
+    ) -> Self {
+        Self {
+            cache_manager,
+            message_storage_adapter,
+            client_pool,
+            connection_manager,
+            subscribe_manager,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportParams {
+    format: Option<String>,
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+pub async fn http_server<S>(state: HttpServerState<S>)
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let config = broker_mqtt_conf();
+    let ip: SocketAddr = format!("0.0.0.0:{}", config.http_port).parse().unwrap();
+    let app = routes_v1(state);
+    info!(
+        "Broker HTTP Server start success. port:{}",
+        config.http_port
+    );
+    match axum_server::bind(ip).serve(app.into_make_service()).await {
+        Ok(()) => {}
+        Err(e) => panic!("{}", e.to_string()),
+    }
+}
+
+fn routes_v1<S>(state: HttpServerState<S>) -> Router
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    Router::new()
+        .route(ROUTE_TOPIC_EXPORT, get(export_topic_handler))
+        .route(ROUTE_CLIENT_RETAINED_RESYNC, post(resync_retained_handler))
+        .route(ROUTE_SHARE_GROUP_LAG, get(share_group_lag_handler))
+        .route(
+            ROUTE_SUBSCRIPTION_INDEX_REBUILD,
+            post(rebuild_subscription_index_handler),
+        )
+        .with_state(state)
+}
+
+// GET /topics/<name>/export?format=json&from=0&to=1000 streams the messages of the
+// topic in [from, to), all of them when from and to are absent.
+async fn export_topic_handler<S>(
+    State(state): State<HttpServerState<S>>,
+    Path(topic_name): Path<String>,
+    Query(params): Query<ExportParams>,
+) -> Response
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let format = match params
+        .format
+        .as_deref()
+        .unwrap_or("json")
+        .parse::<ExportFormat>()
+    {
+        Ok(format) => format,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let Some(topic) = state.cache_manager.get_topic_by_name(&topic_name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Topic [{}] does not exist", topic_name),
+        )
+            .into_response();
+    };
+
+    let message_storage = MessageStorage::new(state.message_storage_adapter.clone());
+    let reader = match message_storage
+        .export_topic_messages(
+            &topic.topic_id,
+            params.from.unwrap_or(0),
+            params.to.unwrap_or(u64::MAX),
+            format,
+        )
+        .await
+    {
+        Ok(reader) => reader,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let content_type = match format {
+        ExportFormat::Json => "application/json",
+        ExportFormat::Csv => "text/csv",
+    };
+    (
+        [(header::CONTENT_TYPE, content_type)],
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+        .into_response()
+}
+
+// POST /clients/<client_id>/retained/resync sends the retained messages matching the
+// subscriptions of the client again, and returns the number of subscriptions resynced.
+async fn resync_retained_handler<S>(
+    State(state): State<HttpServerState<S>>,
+    Path(client_id): Path<String>,
+) -> Response
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    match resync_retained(
+        &client_id,
+        &state.client_pool,
+        &state.cache_manager,
+        &state.connection_manager,
+        &state.subscribe_manager,
+    )
+    .await
+    {
+        Ok(num) => (StatusCode::OK, num.to_string()).into_response(),
+        Err(MqttBrokerError::SessionDoesNotExist) => (
+            StatusCode::NOT_FOUND,
+            format!("Client [{}] does not exist", client_id),
+        )
+            .into_response(),
+        Err(e @ MqttBrokerError::ClientNotConnected(_)) => {
+            (StatusCode::CONFLICT, e.to_string()).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// GET /share-groups/lag returns {"<group_name>": <lag>} for the share groups led by this
+// broker, the number of messages published to the topics of the group and not consumed yet.
+async fn share_group_lag_handler<S>(State(state): State<HttpServerState<S>>) -> Response
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let message_storage = MessageStorage::new(state.message_storage_adapter.clone());
+    match share_group_lag(&state.subscribe_manager, &message_storage).await {
+        Ok(lags) => (StatusCode::OK, Json(lags)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// POST /subscriptions/index/rebuild rebuilds the subscription index from the subscriptions of
+// the broker, and returns the number of subscriptions indexed.
+async fn rebuild_subscription_index_handler<S>(State(state): State<HttpServerState<S>>) -> Response
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let num = state.subscribe_manager.rebuild_subscription_index();
+    info!(
+        "The subscription index was rebuilt with {} subscriptions",
+        num
+    );
+    (StatusCode::OK, num.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use axum::body::to_bytes;
+    use axum::extract::{Path, Query, State};
+    use axum::http::StatusCode;
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use dashmap::DashMap;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::adapter::record::Record;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use metadata_struct::mqtt::topic::MqttTopic;
+    use storage_adapter::memory::MemoryStorageAdapter;
+
+    use super::{export_topic_handler, share_group_lag_handler, ExportParams, HttpServerState};
+    use crate::handler::cache::CacheManager;
+    use crate::server::connection_manager::ConnectionManager;
+    use crate::storage::message::{ExportedMessage, MessageStorage};
+    use crate::subscribe::share_leader_push::share_leader_group_id;
+    use crate::subscribe::subscribe_manager::{ShareLeaderSubscribeData, SubscribeManager};
+
+    #[tokio::test]
+    async fn export_topic_handler_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let message_storage = MessageStorage::new(storage_adapter.clone());
+
+        let topic_name = "/test/http/export".to_string();
+        let topic = MqttTopic::new(unique_id(), "mqtt-broker".to_string(), topic_name.clone());
+        cache_manager.add_topic(&topic_name, &topic);
+        let records = (0..100)
+            .map(|i| {
+                let message = MqttMessage {
+                    topic: Bytes::from(topic_name.clone()),
+                    payload: Bytes::from(format!("message-{}", i)),
+                    ..Default::default()
+                };
+                Record::build_byte(message.encode())
+            })
+            .collect();
+        message_storage
+            .append_topic_message(&topic.topic_id, records)
+            .await
+            .unwrap();
+
+        let state = HttpServerState::new(
+            cache_manager.clone(),
+            storage_adapter,
+            client_pool,
+            Arc::new(ConnectionManager::new(cache_manager)),
+            Arc::new(SubscribeManager::new()),
+        );
+        let response = export_topic_handler(
+            State(state.clone()),
+            Path(topic_name.clone()),
+            Query(ExportParams {
+                format: Some("json".to_string()),
+                from: Some(0),
+                to: Some(1000),
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let messages: Vec<ExportedMessage> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(messages.len(), 100);
+        for (i, message) in messages.iter().enumerate() {
+            assert_eq!(message.payload, format!("message-{}", i));
+        }
+
+        let response = export_topic_handler(
+            State(state.clone()),
+            Path("/test/http/unknown".to_string()),
+            Query(ExportParams::default()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = export_topic_handler(
+            State(state),
+            Path(topic_name),
+            Query(ExportParams {
+                format: Some("xml".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn share_group_lag_handler_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let message_storage = MessageStorage::new(storage_adapter.clone());
+        let subscribe_manager = Arc::new(SubscribeManager::new());
+
+        let group_name = "g1".to_string();
+        let sub_name = "/test/http/lag".to_string();
+        let topic_id = unique_id();
+        subscribe_manager.share_leader_push.insert(
+            format!("{}_{}_{}", group_name, sub_name, topic_id),
+            ShareLeaderSubscribeData {
+                group_name: group_name.clone(),
+                topic_id: topic_id.clone(),
+                topic_name: sub_name.clone(),
+                sub_name: sub_name.clone(),
+                sub_list: DashMap::new(),
+            },
+        );
+        let publish = |num: usize| {
+            (0..num)
+                .map(|i| {
+                    let message = MqttMessage {
+                        payload: Bytes::from(format!("message-{}", i)),
+                        ..Default::default()
+                    };
+                    Record::build_byte(message.encode())
+                })
+                .collect::<Vec<Record>>()
+        };
+
+        let state = HttpServerState::new(
+            cache_manager.clone(),
+            storage_adapter,
+            client_pool,
+            Arc::new(ConnectionManager::new(cache_manager)),
+            subscribe_manager,
+        );
+        let lag = |state: HttpServerState<MemoryStorageAdapter>| async move {
+            let response = share_group_lag_handler(State(state)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<HashMap<String, u64>>(&body).unwrap()
+        };
+
+        // nothing was published yet
+        assert_eq!(lag(state.clone()).await.get(&group_name), Some(&0));
+
+        message_storage
+            .append_topic_message(&topic_id, publish(10))
+            .await
+            .unwrap();
+        assert_eq!(lag(state.clone()).await.get(&group_name), Some(&10));
+
+        // the group pushed the messages up to offset 4
+        let group_id = share_leader_group_id(&group_name, &sub_name, &topic_id);
+        message_storage
+            .commit_group_offset(&group_id, &topic_id, 4)
+            .await
+            .unwrap();
+        assert_eq!(lag(state.clone()).await.get(&group_name), Some(&5));
+
+        // publishing outpaces the consumption of the group
+        message_storage
+            .append_topic_message(&topic_id, publish(20))
+            .await
+            .unwrap();
+        message_storage
+            .commit_group_offset(&group_id, &topic_id, 9)
+            .await
+            .unwrap();
+        assert_eq!(lag(state).await.get(&group_name), Some(&20));
+    }
+}
@@ -15,6 +15,7 @@
 pub mod connection;
 pub mod connection_manager;
 pub mod grpc;
+pub mod http;
 pub mod packet;
 pub mod quic;
 pub mod tcp;
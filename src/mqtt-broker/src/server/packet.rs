@@ -40,13 +40,18 @@ impl RequestPackage {
 pub struct ResponsePackage {
     pub connection_id: u64,
     pub packet: MqttPacket,
+    // Close the network connection once the packet has been sent
+    pub close_after_send: bool,
 }
 
 impl ResponsePackage {
     pub fn new(connection_id: u64, packet: MqttPacket) -> Self {
+        // The server closes the network connection after sending a DISCONNECT
+        let close_after_send = matches!(packet, MqttPacket::Disconnect(_, _));
         Self {
             connection_id,
             packet,
+            close_after_send,
         }
     }
 }
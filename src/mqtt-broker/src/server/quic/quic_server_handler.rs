@@ -17,6 +17,7 @@ use crate::observability::metrics::packets::{
     record_received_error_metrics, record_received_metrics,
 };
 use crate::observability::slow::request::try_record_total_request_ms;
+use crate::security::acl::auth::is_allow_accept;
 use crate::server::connection::{NetworkConnection, NetworkConnectionType};
 use crate::server::connection_manager::ConnectionManager;
 use crate::server::packet::RequestPackage;
@@ -62,6 +63,11 @@ pub(crate) async fn acceptor_process(
                     val = endpoint.accept()=> {
                         match val {
                             Some(incoming) => {
+                                if !is_allow_accept(&cache_manager, &incoming.remote_address()) {
+                                    info!("quic connection {:?} is refused by the accept ip lists", incoming.remote_address());
+                                    incoming.refuse();
+                                    continue;
+                                }
                                 match incoming.await {
                                 Ok(connection) => {
                                         info!("accept quic connection:{:?}",connection.remote_address());
@@ -18,7 +18,6 @@ use std::sync::Arc;
 use grpc_clients::pool::ClientPool;
 use log::{debug, error};
 use protocol::mqtt::codec::MqttPacketWrapper;
-use protocol::mqtt::common::MqttPacket;
 use tokio::select;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc::{self, Receiver, Sender};
@@ -145,7 +144,7 @@ pub(crate) fn response_child_process(
                                     }
                             }
 
-                            if let MqttPacket::Disconnect(_, _) = response_package.packet {
+                            if response_package.close_after_send {
                                 if let Some(connection) = raw_cache_manager.get_connection(response_package.connection_id){
                                     match disconnect_connection(
                                         &connection.client_id,
@@ -158,6 +157,8 @@ pub(crate) fn response_child_process(
                                         Err(e) => error!("{}",e)
                                     };
                                 }
+                                raw_cache_manager.remove_connection(response_package.connection_id);
+                                raw_connect_manager.close_connect(response_package.connection_id).await;
                             }
                         }
                     }
@@ -66,7 +66,8 @@ pub async fn start_quic_server<S>(
         connection_manager.clone(),
         schema_register_manager.clone(),
         auth_driver.clone(),
-    );
+    )
+    .with_protocol_versions(conf.network.quic_protocol_versions.clone());
 
     let mut server = QuicServer::new(SocketAddr::new(
         IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
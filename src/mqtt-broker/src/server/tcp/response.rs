@@ -15,6 +15,8 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use common_base::config::broker_mqtt::broker_mqtt_conf;
+
 use crate::handler::cache::CacheManager;
 use crate::handler::connection::disconnect_connection;
 use crate::observability::metrics::server::{metrics_request_queue, metrics_response_queue};
@@ -28,6 +30,7 @@ use protocol::mqtt::common::MqttPacket;
 use tokio::select;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time::{timeout_at, Duration, Instant};
 
 pub(crate) async fn response_process(
     response_process_num: usize,
@@ -113,6 +116,7 @@ pub(crate) fn response_child_process(
         let raw_connect_manager = connection_manager.clone();
         let raw_cache_manager = cache_manager.clone();
         let raw_client_pool = client_pool.clone();
+        let conf = broker_mqtt_conf();
         tokio::spawn(async move {
             debug!("TCP Server response process thread {index} start successfully.");
 
@@ -131,37 +135,23 @@ pub(crate) fn response_child_process(
                             let label = format!("handler-{}",index);
                             metrics_response_queue(&label, response_process_rx.len());
 
-                            if let Some(protocol) =
-                            raw_connect_manager.get_connect_protocol(response_package.connection_id)
-                            {
-                                let packet_wrapper = MqttPacketWrapper {
-                                    protocol_version: protocol.into(),
-                                    packet: response_package.packet.clone(),
-                                };
-                                match raw_connect_manager
-                                    .write_tcp_frame(response_package.connection_id, packet_wrapper)
-                                    .await{
-                                        Ok(()) => {},
-                                        Err(e) => {
-                                            error!("{}",e);
-                                            raw_connect_manager.close_connect(response_package.connection_id).await;
-                                        }
-                                    }
-                            }
+                            let packages = batch_response_package(
+                                response_package,
+                                &mut response_process_rx,
+                                conf.tcp_thread.response_batch_max_num,
+                                conf.tcp_thread.response_batch_window_ms,
+                            ).await;
 
-                            if let MqttPacket::Disconnect(_, _) = response_package.packet {
-                                if let Some(connection) = raw_cache_manager.get_connection(response_package.connection_id){
-                                    match disconnect_connection(
-                                        &connection.client_id,
-                                        connection.connect_id,
-                                        &raw_cache_manager,
-                                        &raw_client_pool,
-                                        &raw_connect_manager,
-                                    ).await{
-                                        Ok(()) => {},
-                                        Err(e) => error!("{}",e)
-                                    };
-                                }
+                            let close_connection_ids = close_after_send_connection_ids(&packages);
+                            for (connection_id, packets) in group_response_by_connection(packages) {
+                                write_response_packets(
+                                    connection_id,
+                                    packets,
+                                    close_connection_ids.contains(&connection_id),
+                                    &raw_connect_manager,
+                                    &raw_cache_manager,
+                                    &raw_client_pool,
+                                ).await;
                             }
                         }
                     }
@@ -170,3 +160,341 @@ pub(crate) fn response_child_process(
         });
     }
 }
+
+// When the first packet is a PUBLISH, keep collecting packets from the queue until
+// the batch is full or the time window expires, so that they can be written together.
+pub(crate) async fn batch_response_package(
+    first: ResponsePackage,
+    response_process_rx: &mut Receiver<ResponsePackage>,
+    batch_max_num: usize,
+    batch_window_ms: u64,
+) -> Vec<ResponsePackage> {
+    let is_publish = matches!(first.packet, MqttPacket::Publish(_, _));
+    let mut packages = vec![first];
+    if !is_publish || batch_max_num <= 1 {
+        return packages;
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(batch_window_ms);
+    while packages.len() < batch_max_num {
+        if let Ok(package) = response_process_rx.try_recv() {
+            packages.push(package);
+            continue;
+        }
+
+        match timeout_at(deadline, response_process_rx.recv()).await {
+            Ok(Some(package)) => packages.push(package),
+            _ => break,
+        }
+    }
+    packages
+}
+
+// Group packets by connection, keeping the order of the packets within each connection.
+pub(crate) fn group_response_by_connection(
+    packages: Vec<ResponsePackage>,
+) -> Vec<(u64, Vec<MqttPacket>)> {
+    let mut results: Vec<(u64, Vec<MqttPacket>)> = Vec::new();
+    for package in packages {
+        if let Some((_, packets)) = results
+            .iter_mut()
+            .find(|(connection_id, _)| *connection_id == package.connection_id)
+        {
+            packets.push(package.packet);
+        } else {
+            results.push((package.connection_id, vec![package.packet]));
+        }
+    }
+    results
+}
+
+pub(crate) fn close_after_send_connection_ids(packages: &[ResponsePackage]) -> Vec<u64> {
+    packages
+        .iter()
+        .filter(|package| package.close_after_send)
+        .map(|package| package.connection_id)
+        .collect()
+}
+
+async fn write_response_packets(
+    connection_id: u64,
+    packets: Vec<MqttPacket>,
+    close_after_send: bool,
+    connect_manager: &Arc<ConnectionManager>,
+    cache_manager: &Arc<CacheManager>,
+    client_pool: &Arc<ClientPool>,
+) {
+    if let Some(protocol) = connect_manager.get_connect_protocol(connection_id) {
+        let protocol_version: u8 = protocol.into();
+        let packet_wrappers = packets
+            .iter()
+            .map(|packet| MqttPacketWrapper {
+                protocol_version,
+                packet: packet.clone(),
+            })
+            .collect();
+
+        match connect_manager
+            .write_tcp_frames(connection_id, packet_wrappers)
+            .await
+        {
+            Ok(()) => {}
+            Err(e) => {
+                error!("{}", e);
+                connect_manager.close_connect(connection_id).await;
+            }
+        }
+    }
+
+    if close_after_send {
+        if let Some(connection) = cache_manager.get_connection(connection_id) {
+            match disconnect_connection(
+                &connection.client_id,
+                connection.connect_id,
+                cache_manager,
+                client_pool,
+                connect_manager,
+            )
+            .await
+            {
+                Ok(()) => {}
+                Err(e) => error!("{}", e),
+            };
+        }
+
+        // disconnect_connection returns before closing the connection if the session
+        // can no longer be updated, so make sure the connection is closed anyway.
+        cache_manager.remove_connection(connection_id);
+        connect_manager.close_connect(connection_id).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Instant;
+
+    use bytes::{Bytes, BytesMut};
+    use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
+    use protocol::mqtt::common::{Disconnect, MqttPacket, PingResp, Publish, QoS};
+    use tokio::io::{AsyncReadExt, AsyncWrite};
+    use tokio::sync::mpsc;
+    use tokio_util::codec::FramedWrite;
+
+    use super::{
+        batch_response_package, close_after_send_connection_ids, group_response_by_connection,
+    };
+    use crate::server::connection_manager::{feed_and_flush, feed_and_flush_from};
+    use crate::server::packet::ResponsePackage;
+
+    fn build_publish(pkid: u16) -> MqttPacket {
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid,
+            retain: false,
+            topic: Bytes::from("/test/batch".to_string()),
+            payload: Bytes::from(format!("message-{}", pkid)),
+        };
+        MqttPacket::Publish(publish, None)
+    }
+
+    #[tokio::test]
+    async fn batch_response_package_test() {
+        let (sx, mut rx) = mpsc::channel::<ResponsePackage>(100);
+        for i in 2..=5 {
+            sx.send(ResponsePackage::new(1, build_publish(i)))
+                .await
+                .unwrap();
+        }
+
+        let packages =
+            batch_response_package(ResponsePackage::new(1, build_publish(1)), &mut rx, 3, 1).await;
+        assert_eq!(packages.len(), 3);
+
+        let packages =
+            batch_response_package(ResponsePackage::new(1, build_publish(1)), &mut rx, 16, 1).await;
+        assert_eq!(packages.len(), 3);
+
+        // non publish packets are not batched
+        sx.send(ResponsePackage::new(1, build_publish(6)))
+            .await
+            .unwrap();
+        let packages = batch_response_package(
+            ResponsePackage::new(1, MqttPacket::PingResp(PingResp)),
+            &mut rx,
+            16,
+            1,
+        )
+        .await;
+        assert_eq!(packages.len(), 1);
+    }
+
+    #[test]
+    fn group_response_by_connection_test() {
+        let packages = vec![
+            ResponsePackage::new(1, build_publish(1)),
+            ResponsePackage::new(2, build_publish(1)),
+            ResponsePackage::new(1, build_publish(2)),
+            ResponsePackage::new(1, build_publish(3)),
+        ];
+        let groups = group_response_by_connection(packages);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].0, 1);
+        assert_eq!(
+            groups[0].1,
+            vec![build_publish(1), build_publish(2), build_publish(3)]
+        );
+        assert_eq!(groups[1].0, 2);
+        assert_eq!(groups[1].1, vec![build_publish(1)]);
+    }
+
+    #[test]
+    fn close_after_send_connection_ids_test() {
+        let packages = vec![
+            ResponsePackage::new(1, build_publish(1)),
+            ResponsePackage::new(
+                2,
+                MqttPacket::Disconnect(Disconnect { reason_code: None }, None),
+            ),
+            ResponsePackage::new(3, MqttPacket::PingResp(PingResp)),
+        ];
+        assert_eq!(close_after_send_connection_ids(&packages), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn batch_write_decode_distinct_packets_test() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut write = FramedWrite::new(server, MqttCodec::new(Some(4)));
+
+        let packets: Vec<MqttPacket> = (1..=10).map(build_publish).collect();
+        let wrappers: Vec<MqttPacketWrapper> = packets
+            .iter()
+            .map(|packet| MqttPacketWrapper {
+                protocol_version: 4,
+                packet: packet.clone(),
+            })
+            .collect();
+        feed_and_flush(&mut write, &wrappers).await.unwrap();
+        drop(write);
+
+        let mut client = client;
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).await.unwrap();
+
+        let mut buf = BytesMut::from(data.as_slice());
+        let mut codec = MqttCodec::new(Some(4));
+        let mut results = Vec::new();
+        while !buf.is_empty() {
+            results.push(codec.decode_data(&mut buf).unwrap().unwrap());
+        }
+        assert_eq!(results, packets);
+    }
+
+    // Fails the first write to the stream
+    struct FailOnceWrite<T> {
+        inner: T,
+        failed: bool,
+    }
+
+    impl<T: AsyncWrite + Unpin> AsyncWrite for FailOnceWrite<T> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if !self.failed {
+                self.failed = true;
+                return Poll::Ready(Err(io::Error::other("write failed")));
+            }
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_write_retry_test() {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let mut write = FramedWrite::new(
+            FailOnceWrite {
+                inner: server,
+                failed: false,
+            },
+            MqttCodec::new(Some(4)),
+        );
+
+        let packets: Vec<MqttPacket> = (1..=10).map(build_publish).collect();
+        let wrappers: Vec<MqttPacketWrapper> = packets
+            .iter()
+            .map(|packet| MqttPacketWrapper {
+                protocol_version: 4,
+                packet: packet.clone(),
+            })
+            .collect();
+        let mut fed = 0;
+        assert!(feed_and_flush_from(&mut write, &wrappers, &mut fed)
+            .await
+            .is_err());
+        assert_eq!(fed, wrappers.len());
+        // the retry only flushes the frames already in the write buffer
+        feed_and_flush_from(&mut write, &wrappers, &mut fed)
+            .await
+            .unwrap();
+        drop(write);
+
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).await.unwrap();
+        let mut buf = BytesMut::from(data.as_slice());
+        let mut codec = MqttCodec::new(Some(4));
+        let mut results = Vec::new();
+        while !buf.is_empty() {
+            results.push(codec.decode_data(&mut buf).unwrap().unwrap());
+        }
+        assert_eq!(results, packets);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn batch_write_benchmark_test() {
+        let num = 100000;
+        let wrappers: Vec<MqttPacketWrapper> = (1..=num)
+            .map(|i| MqttPacketWrapper {
+                protocol_version: 4,
+                packet: build_publish((i % 65535 + 1) as u16),
+            })
+            .collect();
+
+        let mut results = Vec::new();
+        for batch in [1, 16, 64] {
+            let (mut client, server) = tokio::io::duplex(1024 * 1024);
+            let reader = tokio::spawn(async move {
+                let mut data = Vec::new();
+                client.read_to_end(&mut data).await.unwrap();
+                data.len()
+            });
+
+            let mut write = FramedWrite::new(server, MqttCodec::new(Some(4)));
+            let start = Instant::now();
+            for chunk in wrappers.chunks(batch) {
+                feed_and_flush(&mut write, chunk).await.unwrap();
+            }
+            let elapsed = start.elapsed();
+            drop(write);
+            let total = reader.await.unwrap();
+            results.push((total, elapsed));
+        }
+
+        // every batch size writes the same frames, the larger batches with fewer flushes
+        assert!(results.iter().all(|(total, _)| *total == results[0].0));
+        assert!(results[2].1 < results[0].1);
+    }
+}
@@ -68,7 +68,9 @@ pub async fn start_tcp_server<S>(
     };
 
     let mut server = TcpServer::<S>::new(
-        command.clone(),
+        command
+            .clone()
+            .with_protocol_versions(conf.network.tcp_protocol_versions.clone()),
         proc_config,
         stop_sx.clone(),
         connection_manager.clone(),
@@ -78,7 +80,7 @@ pub async fn start_tcp_server<S>(
     server.start(conf.network.tcp_port).await;
 
     let mut server = TcpServer::<S>::new(
-        command,
+        command.with_protocol_versions(conf.network.tcps_protocol_versions.clone()),
         proc_config,
         stop_sx.clone(),
         connection_manager,
@@ -200,6 +202,7 @@ where
             self.network_connection_type.clone(),
             self.connection_manager.clone(),
             request_queue_sx,
+            self.cache_manager.clone(),
         )
         .await;
 
@@ -18,6 +18,7 @@ use std::time::Duration;
 use futures_util::StreamExt;
 use log::{debug, error, info};
 use protocol::mqtt::codec::MqttCodec;
+use protocol::mqtt::common::MqttPacket;
 use tokio::net::TcpListener;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc::{self, Receiver, Sender};
@@ -31,6 +32,7 @@ use crate::observability::metrics::packets::{
     record_received_error_metrics, record_received_metrics,
 };
 use crate::observability::slow::request::try_record_total_request_ms;
+use crate::security::acl::auth::is_allow_accept;
 use crate::server::connection::{NetworkConnection, NetworkConnectionType};
 use crate::server::connection_manager::ConnectionManager;
 use crate::server::packet::RequestPackage;
@@ -80,6 +82,10 @@ pub(crate) async fn acceptor_process(
                         match val{
                             Ok((stream, addr)) => {
                                 info!("accept tcp connection:{:?}",addr);
+                                if !is_allow_accept(&cache_manager, &addr) {
+                                    info!("tcp connection {:?} is refused by the accept ip lists", addr);
+                                    continue;
+                                }
 
                                 let (r_stream, w_stream) = io::split(stream);
                                 let codec = MqttCodec::new(None);
@@ -99,7 +105,7 @@ pub(crate) async fn acceptor_process(
                                 connection_manager.add_connection(connection.clone());
                                 connection_manager.add_tcp_write(connection.connection_id, write_frame_stream);
 
-                                read_frame_process(read_frame_stream,connection,raw_request_queue_sx.clone(),connection_stop_rx,network_type.clone(),cache_manager.clone());
+                                read_frame_process(read_frame_stream,connection,connection_manager.clone(),raw_request_queue_sx.clone(),connection_stop_rx,network_type.clone(),cache_manager.clone());
                             }
                             Err(e) => {
                                 error!("TCP accept failed to create connection with error message :{:?}",e);
@@ -115,6 +121,7 @@ pub(crate) async fn acceptor_process(
 fn read_frame_process(
     mut read_frame_stream: FramedRead<io::ReadHalf<tokio::net::TcpStream>, MqttCodec>,
     connection: NetworkConnection,
+    connection_manager: Arc<ConnectionManager>,
     request_queue_sx: Sender<RequestPackage>,
     mut connection_stop_rx: Receiver<bool>,
     network_type: NetworkConnectionType,
@@ -137,6 +144,13 @@ fn read_frame_process(
                             Ok(pack) => {
                                 record_received_metrics(&connection, &pack, &network_type);
 
+                                // A client can only send CONNECT once on a connection [MQTT-3.1.0-2]
+                                if matches!(pack, MqttPacket::Connect(..)) && connection.mark_connected() {
+                                    info!("TCP connection 【{}】 sent a second CONNECT packet and is closed", connection.connection_id);
+                                    connection_manager.close_connect(connection.connection_id).await;
+                                    break;
+                                }
+
                                 info!("revc tcp packet:{:?}", pack);
                                 let package =
                                     RequestPackage::new(connection.connection_id, connection.addr, pack);
@@ -161,3 +175,234 @@ fn read_frame_process(
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use futures_util::{SinkExt, StreamExt};
+    use grpc_clients::pool::ClientPool;
+    use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
+    use protocol::mqtt::common::{Connect, ConnectReturnCode, MqttPacket};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::{broadcast, mpsc};
+    use tokio::time::timeout;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    use super::acceptor_process;
+    use crate::handler::cache::CacheManager;
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::server::connection::NetworkConnectionType;
+    use crate::server::connection_manager::ConnectionManager;
+
+    fn build_connect_packet() -> MqttPacketWrapper {
+        MqttPacketWrapper {
+            protocol_version: 5,
+            packet: MqttPacket::Connect(
+                5,
+                Connect {
+                    keep_alive: 30,
+                    client_id: unique_id(),
+                    clean_session: true,
+                },
+                None,
+                None,
+                None,
+                None,
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate_connect_closes_connection_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+
+        let listener = Arc::new(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let addr = listener.local_addr().unwrap();
+        let (stop_sx, _) = broadcast::channel(1);
+        let (request_queue_sx, mut request_queue_rx) = mpsc::channel(10);
+        acceptor_process(
+            1,
+            connection_manager.clone(),
+            stop_sx.clone(),
+            listener,
+            request_queue_sx,
+            cache_manager,
+            NetworkConnectionType::Tcp,
+        )
+        .await;
+
+        let client_stream = TcpStream::connect(addr).await.unwrap();
+        let (mut read_stream, write_stream) = tokio::io::split(client_stream);
+        let mut write_frame_stream = FramedWrite::new(write_stream, MqttCodec::new(Some(5)));
+
+        // the first CONNECT is processed
+        write_frame_stream
+            .send(build_connect_packet())
+            .await
+            .unwrap();
+        let package = timeout(Duration::from_secs(10), request_queue_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(package.packet, MqttPacket::Connect(..)));
+        assert!(connection_manager
+            .get_connect(package.connection_id)
+            .is_some());
+
+        // the second CONNECT closes the connection without any response
+        write_frame_stream
+            .send(build_connect_packet())
+            .await
+            .unwrap();
+        let mut buf = [0u8; 16];
+        let len = timeout(Duration::from_secs(10), read_stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap_or(0);
+        assert_eq!(len, 0);
+        assert!(connection_manager
+            .get_connect(package.connection_id)
+            .is_none());
+        assert!(request_queue_rx.try_recv().is_err());
+
+        stop_sx.send(true).unwrap();
+    }
+
+    #[tokio::test]
+    async fn max_total_connections_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        cache_manager.set_cluster_info(build_default_cluster_config());
+        let connection_manager =
+            Arc::new(ConnectionManager::new(cache_manager.clone()).with_max_total_connections(3));
+
+        let listener = Arc::new(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let addr = listener.local_addr().unwrap();
+        let (stop_sx, _) = broadcast::channel(1);
+        let (request_queue_sx, mut request_queue_rx) = mpsc::channel(10);
+        acceptor_process(
+            1,
+            connection_manager.clone(),
+            stop_sx.clone(),
+            listener,
+            request_queue_sx,
+            cache_manager,
+            NetworkConnectionType::Tcp,
+        )
+        .await;
+
+        // the first 3 connections are accepted
+        let mut clients = Vec::new();
+        let mut connection_ids = Vec::new();
+        for _ in 0..3 {
+            let client_stream = TcpStream::connect(addr).await.unwrap();
+            let (read_stream, write_stream) = tokio::io::split(client_stream);
+            let mut write_frame_stream = FramedWrite::new(write_stream, MqttCodec::new(Some(5)));
+            write_frame_stream
+                .send(build_connect_packet())
+                .await
+                .unwrap();
+            let package = timeout(Duration::from_secs(10), request_queue_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(matches!(package.packet, MqttPacket::Connect(..)));
+            connection_ids.push(package.connection_id);
+            clients.push((read_stream, write_frame_stream));
+        }
+        assert_eq!(connection_manager.total_connections(), 3);
+
+        // the 4th is answered with ServerUnavailable and closed
+        let client_stream = TcpStream::connect(addr).await.unwrap();
+        let (read_stream, _write_stream) = tokio::io::split(client_stream);
+        let mut read_frame_stream = FramedRead::new(read_stream, MqttCodec::new(Some(5)));
+        let packet = timeout(Duration::from_secs(10), read_frame_stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let MqttPacket::ConnAck(connack, _) = packet else {
+            panic!("expected a CONNACK, got {:?}", packet);
+        };
+        assert_eq!(connack.code, ConnectReturnCode::ServerUnavailable);
+        assert!(timeout(Duration::from_secs(10), read_frame_stream.next())
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(connection_manager.total_connections(), 3);
+        assert!(request_queue_rx.try_recv().is_err());
+
+        // closing a connection frees its place
+        connection_manager.close_connect(connection_ids[0]).await;
+        assert_eq!(connection_manager.total_connections(), 2);
+
+        stop_sx.send(true).unwrap();
+    }
+
+    #[tokio::test]
+    async fn accept_ip_deny_list_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, unique_id()));
+        let mut cluster = build_default_cluster_config();
+        cluster.network.accept_ip_deny_list = vec!["127.0.0.0/8".to_string()];
+        cache_manager.set_cluster_info(cluster);
+        let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+
+        let listener = Arc::new(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let addr = listener.local_addr().unwrap();
+        let (stop_sx, _) = broadcast::channel(1);
+        let (request_queue_sx, mut request_queue_rx) = mpsc::channel(10);
+        acceptor_process(
+            1,
+            connection_manager.clone(),
+            stop_sx.clone(),
+            listener,
+            request_queue_sx,
+            cache_manager,
+            NetworkConnectionType::Tcp,
+        )
+        .await;
+
+        // the connection is closed without reading the CONNECT
+        let client_stream = TcpStream::connect(addr).await.unwrap();
+        let (mut read_stream, write_stream) = tokio::io::split(client_stream);
+        let mut write_frame_stream = FramedWrite::new(write_stream, MqttCodec::new(Some(5)));
+        let _ = write_frame_stream.send(build_connect_packet()).await;
+        let mut buf = [0u8; 16];
+        let len = timeout(Duration::from_secs(10), read_stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap_or(0);
+        assert_eq!(len, 0);
+        assert_eq!(connection_manager.total_connections(), 0);
+        assert!(request_queue_rx.try_recv().is_err());
+
+        stop_sx.send(true).unwrap();
+    }
+}
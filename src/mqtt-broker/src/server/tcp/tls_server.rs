@@ -18,10 +18,11 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
-use common_base::config::broker_mqtt::broker_mqtt_conf;
+use common_base::config::broker_mqtt::{broker_mqtt_conf, Network};
 use futures_util::StreamExt;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use protocol::mqtt::codec::MqttCodec;
+use protocol::mqtt::common::MqttPacket;
 use rustls_pemfile::{certs, private_key};
 use tokio::net::TcpListener;
 use tokio::select;
@@ -30,14 +31,19 @@ use tokio::sync::{broadcast, mpsc};
 use tokio::time::sleep;
 
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
 use tokio_rustls::TlsAcceptor;
 use tokio_util::codec::{FramedRead, FramedWrite};
 
+use crate::handler::cache::CacheManager;
+use crate::handler::error::MqttBrokerError;
 use crate::handler::validator::tcp_tls_establish_connection_check;
 use crate::observability::metrics::packets::{
     record_received_error_metrics, record_received_metrics,
 };
+use crate::security::acl::auth::is_allow_accept;
+use crate::security::login::x509::certificate_common_name;
 use crate::server::connection::{NetworkConnection, NetworkConnectionType};
 use crate::server::connection_manager::ConnectionManager;
 use crate::server::packet::RequestPackage;
@@ -55,6 +61,58 @@ pub(crate) fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
         ))
 }
 
+// The clients are asked for a certificate signed by tls_ca when it is set, and refused without
+// one when tls_require_client_cert is on.
+pub(crate) fn build_tls_server_config(network: &Network) -> Result<ServerConfig, MqttBrokerError> {
+    let certs = load_certs(Path::new(&network.tls_cert))
+        .map_err(|e| MqttBrokerError::CommonError(format!("load certs: {}", e)))?;
+    let key = load_key(Path::new(&network.tls_key))
+        .map_err(|e| MqttBrokerError::CommonError(format!("load key: {}", e)))?;
+
+    let builder = ServerConfig::builder();
+    let builder = if network.tls_ca.is_empty() {
+        if network.tls_require_client_cert {
+            return Err(MqttBrokerError::CommonError(
+                "tls_require_client_cert needs the CA of the client certificates in tls_ca"
+                    .to_string(),
+            ));
+        }
+        builder.with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        let ca_certs = load_certs(Path::new(&network.tls_ca))
+            .map_err(|e| MqttBrokerError::CommonError(format!("load ca certs: {}", e)))?;
+        for cert in ca_certs {
+            roots
+                .add(cert)
+                .map_err(|e| MqttBrokerError::CommonError(format!("add ca cert: {}", e)))?;
+        }
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+        let verifier = if network.tls_require_client_cert {
+            verifier.build()
+        } else {
+            verifier.allow_unauthenticated().build()
+        }
+        .map_err(|e| MqttBrokerError::CommonError(format!("client cert verifier: {}", e)))?;
+        builder.with_client_cert_verifier(verifier)
+    };
+
+    builder
+        .with_single_cert(certs, key)
+        .map_err(|e| MqttBrokerError::CommonError(format!("ssl build cert: {}", e)))
+}
+
+// The common name of the certificate of the client, when the listener requires one
+pub(crate) fn client_cert_common_name(
+    network: &Network,
+    peer_certificates: Option<&[CertificateDer<'static>]>,
+) -> Option<String> {
+    if !network.tls_require_client_cert {
+        return None;
+    }
+    peer_certificates?.first().and_then(certificate_common_name)
+}
+
 pub(crate) async fn acceptor_tls_process(
     accept_thread_num: usize,
     listener_arc: Arc<TcpListener>,
@@ -62,29 +120,13 @@ pub(crate) async fn acceptor_tls_process(
     network_connection_type: NetworkConnectionType,
     connection_manager: Arc<ConnectionManager>,
     request_queue_sx: Sender<RequestPackage>,
+    cache_manager: Arc<CacheManager>,
 ) {
     let conf = broker_mqtt_conf();
-    let certs = match load_certs(Path::new(&conf.network.tls_cert)) {
-        Ok(data) => data,
-        Err(e) => {
-            panic!("load certs: {}", e);
-        }
-    };
-
-    let key = match load_key(Path::new(&conf.network.tls_key)) {
-        Ok(data) => data,
-        Err(e) => {
-            panic!("load key: {}", e);
-        }
-    };
-
-    let config = match ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-    {
+    let config = match build_tls_server_config(&conf.network) {
         Ok(data) => data,
         Err(e) => {
-            panic!("ssl build cert:{}", e);
+            panic!("{}", e);
         }
     };
     let tls_acceptor = TlsAcceptor::from(Arc::new(config));
@@ -96,6 +138,7 @@ pub(crate) async fn acceptor_tls_process(
         let raw_request_queue_sx = request_queue_sx.clone();
         let raw_tls_acceptor = tls_acceptor.clone();
         let network_type = network_connection_type.clone();
+        let cache_manager = cache_manager.clone();
         tokio::spawn(async move {
             debug!("TCP Server acceptor thread {} start successfully.", index);
             loop {
@@ -112,13 +155,21 @@ pub(crate) async fn acceptor_tls_process(
                         match val{
                             Ok((stream, addr)) => {
                                 info!("accept tcp tls connection:{:?}",addr);
+                                if !is_allow_accept(&cache_manager, &addr) {
+                                    info!("tcp tls connection {:?} is refused by the accept ip lists", addr);
+                                    continue;
+                                }
                                 let stream = match raw_tls_acceptor.accept(stream).await{
                                     Ok(da) => da,
                                     Err(e) => {
-                                        error!("Tls Accepter failed to read Stream with error message :{e:?}");
+                                        warn!("TLS handshake with {:?} failed, {}", addr, e);
                                         continue;
                                     }
                                 };
+                                let common_name = client_cert_common_name(
+                                    &broker_mqtt_conf().network,
+                                    stream.get_ref().1.peer_certificates(),
+                                );
                                 let (r_stream, w_stream) = tokio::io::split(stream);
                                 let codec = MqttCodec::new(None);
                                 let read_frame_stream = FramedRead::new(r_stream, codec.clone());
@@ -129,15 +180,16 @@ pub(crate) async fn acceptor_tls_process(
                                 }
 
                                 let (connection_stop_sx, connection_stop_rx) = mpsc::channel::<bool>(1);
-                                let connection = NetworkConnection::new(
+                                let mut connection = NetworkConnection::new(
                                     crate::server::connection::NetworkConnectionType::Tls,
                                     addr,
                                     Some(connection_stop_sx.clone())
                                 );
+                                connection.set_client_cert_common_name(common_name);
                                 connection_manager.add_connection(connection.clone());
                                 connection_manager.add_tcp_tls_write(connection.connection_id, write_frame_stream);
 
-                                read_tls_frame_process(read_frame_stream,connection,raw_request_queue_sx.clone(),connection_stop_rx, network_type.clone());
+                                read_tls_frame_process(read_frame_stream,connection,connection_manager.clone(),raw_request_queue_sx.clone(),connection_stop_rx, network_type.clone());
                             }
                             Err(e) => {
                                 error!("TCP accept failed to create connection with error message :{:?}",e);
@@ -156,6 +208,7 @@ pub(crate) fn read_tls_frame_process(
         MqttCodec,
     >,
     connection: NetworkConnection,
+    connection_manager: Arc<ConnectionManager>,
     request_queue_sx: Sender<RequestPackage>,
     mut connection_stop_rx: Receiver<bool>,
     network_type: NetworkConnectionType,
@@ -176,6 +229,14 @@ pub(crate) fn read_tls_frame_process(
                         match pkg {
                             Ok(pack) => {
                                 record_received_metrics(&connection, &pack, &network_type);
+
+                                // A client can only send CONNECT once on a connection [MQTT-3.1.0-2]
+                                if matches!(pack, MqttPacket::Connect(..)) && connection.mark_connected() {
+                                    info!("TCP tls connection 【{}】 sent a second CONNECT packet and is closed", connection.connection_id);
+                                    connection_manager.close_connect(connection.connection_id).await;
+                                    break;
+                                }
+
                                 info!("revc tcp tls packet:{:?}", pack);
                                 let package =
                                     RequestPackage::new(connection.connection_id, connection.addr, pack);
@@ -198,3 +259,148 @@ pub(crate) fn read_tls_frame_process(
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use common_base::config::broker_mqtt::Network;
+    use common_base::tools::unique_id;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+        KeyPair,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::pki_types::{PrivateKeyDer, ServerName};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::{build_tls_server_config, client_cert_common_name};
+
+    struct TestCerts {
+        ca: Certificate,
+        ca_key: KeyPair,
+        network: Network,
+    }
+
+    // A CA and a server certificate for localhost signed by it, written where the listener
+    // configuration points
+    fn build_test_certs(require_client_cert: bool) -> TestCerts {
+        let dir = format!("/tmp/robustmq_tls_{}", unique_id());
+        fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "robustmq test ca");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let mut server_params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        let server = server_params.signed_by(&server_key, &ca, &ca_key).unwrap();
+
+        let network = Network {
+            tls_cert: format!("{}/server.pem", dir),
+            tls_key: format!("{}/server.key", dir),
+            tls_ca: format!("{}/ca.pem", dir),
+            tls_require_client_cert: require_client_cert,
+            ..Default::default()
+        };
+        fs::write(&network.tls_cert, server.pem()).unwrap();
+        fs::write(&network.tls_key, server_key.serialize_pem()).unwrap();
+        fs::write(&network.tls_ca, ca.pem()).unwrap();
+        TestCerts {
+            ca,
+            ca_key,
+            network,
+        }
+    }
+
+    fn build_client_config(certs: &TestCerts, common_name: Option<&str>) -> ClientConfig {
+        let mut roots = RootCertStore::empty();
+        roots.add(certs.ca.der().clone()).unwrap();
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let Some(common_name) = common_name else {
+            return builder.with_no_client_auth();
+        };
+
+        let client_key = KeyPair::generate().unwrap();
+        let mut client_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        client_params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client = client_params
+            .signed_by(&client_key, &certs.ca, &certs.ca_key)
+            .unwrap();
+        builder
+            .with_client_auth_cert(
+                vec![client.der().clone()],
+                PrivateKeyDer::Pkcs8(client_key.serialize_der().into()),
+            )
+            .unwrap()
+    }
+
+    // Runs a handshake and returns the common name the listener got, or None when it failed
+    async fn handshake(network: &Network, client_config: ClientConfig) -> Option<Option<String>> {
+        let acceptor = TlsAcceptor::from(Arc::new(build_tls_server_config(network).unwrap()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let network = network.clone();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.ok()?;
+            let common_name =
+                client_cert_common_name(&network, stream.get_ref().1.peer_certificates());
+            stream.write_all(b"ok").await.ok()?;
+            Some(common_name)
+        });
+
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        if let Ok(mut stream) = connector.connect(server_name, stream).await {
+            // TLS 1.3 reports a refused client certificate on the first read
+            let mut buf = [0u8; 2];
+            let _ = stream.read_exact(&mut buf).await;
+        }
+        server.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn mtls_client_cert_common_name_test() {
+        let certs = build_test_certs(true);
+
+        let client_config = build_client_config(&certs, Some("sensor-1"));
+        let common_name = handshake(&certs.network, client_config).await;
+        assert_eq!(common_name, Some(Some("sensor-1".to_string())));
+
+        // a client without a certificate is refused
+        let client_config = build_client_config(&certs, None);
+        assert_eq!(handshake(&certs.network, client_config).await, None);
+    }
+
+    #[tokio::test]
+    async fn tls_without_client_cert_test() {
+        let mut certs = build_test_certs(false);
+
+        // the certificate is optional, its common name is not used as the username
+        let client_config = build_client_config(&certs, Some("sensor-1"));
+        assert_eq!(handshake(&certs.network, client_config).await, Some(None));
+        let client_config = build_client_config(&certs, None);
+        assert_eq!(handshake(&certs.network, client_config).await, Some(None));
+
+        // a client certificate cannot be required without the CA to verify it
+        certs.network.tls_ca = "".to_string();
+        let client_config = build_client_config(&certs, None);
+        assert_eq!(handshake(&certs.network, client_config).await, Some(None));
+        certs.network.tls_require_client_cert = true;
+        assert!(build_tls_server_config(&certs.network).is_err());
+    }
+}
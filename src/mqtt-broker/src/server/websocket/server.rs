@@ -18,7 +18,8 @@ use std::sync::Arc;
 
 use axum::extract::ws::{Message, WebSocket};
 use axum::extract::{ConnectInfo, State, WebSocketUpgrade};
-use axum::response::Response;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
 use axum_extra::headers::UserAgent;
@@ -31,7 +32,7 @@ use futures_util::stream::StreamExt;
 use grpc_clients::pool::ClientPool;
 use log::{error, info};
 use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
-use protocol::mqtt::common::{MqttPacket, MqttProtocol};
+use protocol::mqtt::common::MqttProtocol;
 use schema_register::schema::SchemaRegisterManager;
 use storage_adapter::storage::StorageAdapter;
 use tokio::select;
@@ -39,6 +40,7 @@ use tokio::sync::broadcast::{self};
 
 use crate::handler::cache::CacheManager;
 use crate::handler::command::Command;
+use crate::security::acl::auth::is_allow_accept;
 use crate::security::AuthDriver;
 use crate::server::connection::NetworkConnection;
 use crate::server::connection_manager::ConnectionManager;
@@ -57,6 +59,7 @@ pub struct WebSocketServerState<S> {
     connection_manager: Arc<ConnectionManager>,
     schema_manager: Arc<SchemaRegisterManager>,
     auth_driver: Arc<AuthDriver>,
+    protocol_versions: Vec<u8>,
 }
 
 impl<S> WebSocketServerState<S>
@@ -85,8 +88,14 @@ where
             client_pool,
             auth_driver,
             stop_sx,
+            protocol_versions: Vec::new(),
         }
     }
+
+    pub fn with_protocol_versions(mut self, protocol_versions: Vec<u8>) -> Self {
+        self.protocol_versions = protocol_versions;
+        self
+    }
 }
 
 pub async fn websocket_server<S>(state: WebSocketServerState<S>)
@@ -97,7 +106,8 @@ where
     let ip: SocketAddr = format!("0.0.0.0:{}", config.network.websocket_port)
         .parse()
         .unwrap();
-    let app = routes_v1(state);
+    let app =
+        routes_v1(state.with_protocol_versions(config.network.websocket_protocol_versions.clone()));
     info!(
         "Broker WebSocket Server start success. port:{}",
         config.network.websocket_port
@@ -119,7 +129,9 @@ where
     let ip: SocketAddr = format!("0.0.0.0:{}", config.network.websockets_port)
         .parse()
         .unwrap();
-    let app = routes_v1(state);
+    let app = routes_v1(
+        state.with_protocol_versions(config.network.websockets_protocol_versions.clone()),
+    );
 
     let tls_config = match RustlsConfig::from_pem_file(
         PathBuf::from(config.network.tls_cert.clone()),
@@ -165,6 +177,11 @@ async fn ws_handler<S>(
 where
     S: StorageAdapter + Sync + Send + 'static + Clone,
 {
+    if !is_allow_accept(&state.cache_manager, &addr) {
+        info!("websocket connection {addr} is refused by the accept ip lists");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
         user_agent.to_string()
     } else {
@@ -180,7 +197,8 @@ where
         state.connection_manager.clone(),
         state.schema_manager.clone(),
         state.auth_driver.clone(),
-    );
+    )
+    .with_protocol_versions(state.protocol_versions.clone());
     let codec = MqttCodec::new(None);
     ws.protocols(["mqtt", "mqttv3.1"])
         .on_upgrade(move |socket| {
@@ -206,7 +224,7 @@ async fn handle_socket<S>(
     S: StorageAdapter + Sync + Send + 'static + Clone,
 {
     let (sender, mut receiver) = socket.split();
-    let mut tcp_connection = NetworkConnection::new(
+    let tcp_connection = NetworkConnection::new(
         crate::server::connection::NetworkConnectionType::WebSocket,
         addr,
         None,
@@ -216,8 +234,10 @@ async fn handle_socket<S>(
     connection_manager.add_connection(tcp_connection.clone());
     let mut protocol_version = MqttProtocol::Mqtt5;
     let mut stop_rx = stop_sx.subscribe();
+    // Bytes received that do not make a whole packet yet
+    let mut stream = BytesMut::new();
 
-    loop {
+    'connection: loop {
         select! {
             val = stop_rx.recv() =>{
                 if let Ok(flag) = val {
@@ -230,52 +250,47 @@ async fn handle_socket<S>(
                 if let Some(msg) = val{
                     match msg {
                         Ok(Message::Binary(data)) => {
-                            let mut buf = BytesMut::with_capacity(data.len());
-                            buf.put(data.as_slice());
-                            match codec.decode_data(&mut buf) {
-                                Ok(Some(packet)) => {
-                                    info!("recv websocket packet:{packet:?}");
-                                    if let Some(resp_pkg) = command
-                                        .apply(
-                                            connection_manager.clone(),
-                                            tcp_connection.clone(),
-                                            addr,
-                                            packet.clone(),
-                                        )
-                                        .await
-                                    {
-                                        if let MqttPacket::Connect(_,_,_,_,_,_) = packet {
-                                            if let Some(pv) = connection_manager.get_connect_protocol(tcp_connection.connection_id){
-                                                protocol_version = pv.clone();
-                                                tcp_connection.set_protocol(pv);
-                                            }
-                                        }
+                            // A packet may span several frames and a frame may carry several packets
+                            stream.put(data.as_slice());
+                            let responses = match command
+                                .apply_raw(
+                                    connection_manager.clone(),
+                                    tcp_connection.connection_id,
+                                    addr,
+                                    &mut codec,
+                                    &mut stream,
+                                )
+                                .await
+                            {
+                                Ok(responses) => responses,
+                                Err(e) => {
+                                    error!("Websocket failed to parse MQTT protocol packet with error message :{e:?}");
+                                    connection_manager.close_connect(tcp_connection.connection_id).await;
+                                    break;
+                                }
+                            };
+                            if let Some(pv) = connection_manager.get_connect_protocol(tcp_connection.connection_id) {
+                                protocol_version = pv;
+                            }
 
-                                        let mut response_buff = BytesMut::new();
-                                        let packet_wrapper = MqttPacketWrapper {
-                                            protocol_version: protocol_version.clone().into(),
-                                            packet: resp_pkg,
-                                        };
+                            for resp_pkg in responses {
+                                info!("websocket response packet:{resp_pkg:?}");
+                                let mut response_buff = BytesMut::new();
+                                let packet_wrapper = MqttPacketWrapper {
+                                    protocol_version: protocol_version.clone().into(),
+                                    packet: resp_pkg,
+                                };
 
-                                        match codec.encode_data(packet_wrapper.clone(), &mut response_buff){
-                                            Ok(()) => {},
-                                            Err(e) => {
-                                                error!("Websocket encode back packet failed with error message: {e:?}");
-                                            }
-                                        }
-                                        match connection_manager.write_websocket_frame(tcp_connection.connection_id, packet_wrapper, Message::Binary(response_buff.to_vec())).await{
-                                            Ok(()) => {},
-                                            Err(e) => {
-                                                error!("websocket returns failure to write the packet to the client with error message {e:?}");
-                                                connection_manager.close_connect(tcp_connection.connection_id).await;
-                                                break;
-                                            }
-                                        }
+                                match codec.encode_data(packet_wrapper.clone(), &mut response_buff){
+                                    Ok(()) => {},
+                                    Err(e) => {
+                                        error!("Websocket encode back packet failed with error message: {e:?}");
                                     }
                                 }
-                                Ok(None) => {}
-                                Err(e) => {
-                                    error!("Websocket failed to parse MQTT protocol packet with error message :{e:?}");
+                                if let Err(e) = connection_manager.write_websocket_frame(tcp_connection.connection_id, packet_wrapper, Message::Binary(response_buff.to_vec())).await {
+                                    error!("websocket returns failure to write the packet to the client with error message {e:?}");
+                                    connection_manager.close_connect(tcp_connection.connection_id).await;
+                                    break 'connection;
                                 }
                             }
                         }
@@ -311,6 +326,9 @@ async fn handle_socket<S>(
                             info!("websocket server parsing request packet error, error message :{e:?}");
                         },
                     }
+                } else {
+                    // The client went away without a close frame
+                    break;
                 }
             }
         }
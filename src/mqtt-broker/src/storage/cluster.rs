@@ -18,15 +18,16 @@ use common_base::config::broker_mqtt::{broker_mqtt_conf, BrokerMqttConfig};
 use common_base::error::common::CommonError;
 use common_base::tools::get_local_ip;
 use grpc_clients::placement::inner::call::{
-    delete_resource_config, get_resource_config, heartbeat, node_list, register_node,
-    set_resource_config, unregister_node,
+    broker_heartbeat, delete_resource_config, describe_cluster, get_resource_config, heartbeat,
+    node_list, register_node, set_resource_config, unregister_node,
 };
 use grpc_clients::pool::ClientPool;
 use metadata_struct::mqtt::node_extend::MqttNodeExtend;
 use metadata_struct::placement::node::BrokerNode;
 use protocol::placement_center::placement_center_inner::{
-    ClusterType, DeleteResourceConfigRequest, GetResourceConfigRequest, HeartbeatRequest,
-    NodeListRequest, RegisterNodeRequest, SetResourceConfigRequest, UnRegisterNodeRequest,
+    BrokerHeartbeatRequest, ClusterType, DeleteResourceConfigRequest, DescribeClusterRequest,
+    GetResourceConfigRequest, HeartbeatRequest, NodeListRequest, PeerInfo, RegisterNodeRequest,
+    SetResourceConfigRequest, UnRegisterNodeRequest,
 };
 
 pub struct ClusterStorage {
@@ -42,6 +43,7 @@ impl ClusterStorage {
         let conf = broker_mqtt_conf();
         let request = NodeListRequest {
             cluster_name: conf.cluster_name.clone(),
+            consistent: false,
         };
 
         let reply = node_list(&self.client_pool, &conf.placement_center, request).await?;
@@ -58,6 +60,18 @@ impl ClusterStorage {
         Ok(node_list)
     }
 
+    // Placement center peers, discovered from any of the configured addresses
+    pub async fn describe_cluster(&self) -> Result<Vec<PeerInfo>, CommonError> {
+        let conf = broker_mqtt_conf();
+        let reply = describe_cluster(
+            &self.client_pool,
+            &conf.placement_center,
+            DescribeClusterRequest {},
+        )
+        .await?;
+        Ok(reply.peers)
+    }
+
     pub async fn register_node(&self, config: &BrokerMqttConfig) -> Result<(), CommonError> {
         let local_ip = get_local_ip();
 
@@ -107,6 +121,26 @@ impl ClusterStorage {
         Ok(())
     }
 
+    // Reports the load of the node, returns the address of the least loaded broker of the cluster
+    pub async fn broker_heartbeat(
+        &self,
+        active_connections: usize,
+    ) -> Result<Option<String>, CommonError> {
+        let config = broker_mqtt_conf();
+        let req = BrokerHeartbeatRequest {
+            cluster_name: config.cluster_name.clone(),
+            node_id: config.broker_id,
+            addr: format!("{}:{}", get_local_ip(), config.network.tcp_port),
+            active_connections: active_connections as u64,
+        };
+
+        let reply = broker_heartbeat(&self.client_pool, &config.placement_center, req).await?;
+        if reply.least_loaded_addr.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(reply.least_loaded_addr))
+    }
+
     pub async fn set_dynamic_config(
         &self,
         cluster_name: &str,
@@ -13,19 +13,117 @@
 // limitations under the License.
 
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 
 use common_base::config::broker_mqtt::broker_mqtt_conf;
 use common_base::error::common::CommonError;
+use common_base::tools::circuit_breaker::CircuitBreaker;
+use futures::{future, pin_mut, Stream, TryStreamExt};
+use log::error;
 use metadata_struct::adapter::read_config::ReadConfig;
 use metadata_struct::adapter::record::Record;
+use metadata_struct::mqtt::message::MqttMessage;
+use serde::{Deserialize, Serialize};
 use storage_adapter::storage::StorageAdapter;
+use tokio::io::{AsyncWrite, AsyncWriteExt, DuplexStream};
+
+const STORAGE_FAILURE_THRESHOLD: u32 = 5;
+const STORAGE_SUCCESS_THRESHOLD: u32 = 2;
+const STORAGE_RESET_TIMEOUT_SECS: u64 = 10;
+
+const TAG_READ_BATCH: u64 = 100;
+const EXPORT_BUFFER_SIZE: usize = 64 * 1024;
+const EXPORT_CSV_HEADER: &str = "offset,client_id,topic,qos,retain,create_time,payload\n";
+
+// All the message storages of the broker share the same storage, and therefore the same breaker.
+static STORAGE_CIRCUIT_BREAKER: OnceLock<CircuitBreaker> = OnceLock::new();
+
+pub fn storage_circuit_breaker() -> &'static CircuitBreaker {
+    STORAGE_CIRCUIT_BREAKER.get_or_init(|| {
+        CircuitBreaker::new(
+            STORAGE_FAILURE_THRESHOLD,
+            STORAGE_SUCCESS_THRESHOLD,
+            STORAGE_RESET_TIMEOUT_SECS,
+        )
+    })
+}
 
 pub fn cluster_name() -> String {
     let conf = broker_mqtt_conf();
     conf.cluster_name.clone()
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    // A JSON array of ExportedMessage
+    Json,
+    // A header line followed by one line per message, quoted as in RFC 4180
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = CommonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(CommonError::CommonError(format!(
+                "unsupported export format [{}], expected json or csv",
+                s
+            ))),
+        }
+    }
+}
+
+/// A topic message as written by the export, the payload is exported as UTF-8 text.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    pub offset: u64,
+    pub client_id: String,
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+    pub create_time: u64,
+    pub payload: String,
+}
+
+impl ExportedMessage {
+    fn build(offset: u64, message: MqttMessage) -> Self {
+        ExportedMessage {
+            offset,
+            client_id: message.client_id,
+            topic: String::from_utf8_lossy(&message.topic).to_string(),
+            qos: message.qos as u8,
+            retain: message.retain,
+            create_time: message.create_time,
+            payload: String::from_utf8_lossy(&message.payload).to_string(),
+        }
+    }
+
+    fn to_csv_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}\n",
+            self.offset,
+            csv_field(&self.client_id),
+            csv_field(&self.topic),
+            self.qos,
+            self.retain,
+            self.create_time,
+            csv_field(&self.payload)
+        )
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
 #[derive(Clone)]
 pub struct MessageStorage<T> {
     storage_adapter: Arc<T>,
@@ -46,9 +144,11 @@ where
     ) -> Result<Vec<u64>, CommonError> {
         let shard_name = topic_id;
         let namespace = cluster_name();
-        let results = self
-            .storage_adapter
-            .batch_write(namespace, shard_name.to_owned(), record)
+        let results = storage_circuit_breaker()
+            .call(
+                self.storage_adapter
+                    .batch_write(namespace, shard_name.to_owned(), record),
+            )
             .await?;
         Ok(results)
     }
@@ -64,9 +164,13 @@ where
         let mut read_config = ReadConfig::new();
         read_config.max_record_num = record_num;
 
-        let records = self
-            .storage_adapter
-            .read_by_offset(namespace, shard_name.to_owned(), offset, read_config)
+        let records = storage_circuit_breaker()
+            .call(self.storage_adapter.read_by_offset(
+                namespace,
+                shard_name.to_owned(),
+                offset,
+                read_config,
+            ))
             .await?;
         for raw in records.iter() {
             if !raw.crc32_check() {
@@ -76,10 +180,99 @@ where
         Ok(records)
     }
 
+    // Reads the newest record_num messages of the topic, newest first.
+    pub async fn read_latest_topic_message(
+        &self,
+        topic_id: &str,
+        record_num: u64,
+    ) -> Result<Vec<Record>, CommonError> {
+        let shard_name = topic_id;
+        let namespace = cluster_name();
+        let mut read_config = ReadConfig::new();
+        read_config.max_record_num = record_num;
+
+        let records = storage_circuit_breaker()
+            .call(
+                self.storage_adapter
+                    .read_latest(namespace, shard_name.to_owned(), read_config),
+            )
+            .await?;
+        for raw in records.iter() {
+            if !raw.crc32_check() {
+                return Err(CommonError::CrcCheckByMessage);
+            }
+        }
+        Ok(records)
+    }
+
+    // Returns up to limit messages of the topic carrying the tag, oldest first. The lookup goes
+    // through the tag index of the storage, and the tags are checked again on the decoded
+    // message, since the index only holds the joined tag.
+    pub async fn read_topic_messages_by_tag(
+        &self,
+        topic_id: &str,
+        tag_key: &str,
+        tag_value: &str,
+        limit: usize,
+    ) -> Result<Vec<Record>, CommonError> {
+        let shard_name = topic_id;
+        let namespace = cluster_name();
+        let tag = MqttMessage::record_tag(tag_key, tag_value);
+        let mut read_config = ReadConfig::new();
+        read_config.max_record_num = TAG_READ_BATCH;
+
+        let mut results = Vec::new();
+        let mut offset = 0;
+        while results.len() < limit {
+            let records = storage_circuit_breaker()
+                .call(self.storage_adapter.read_by_tag(
+                    namespace.clone(),
+                    shard_name.to_owned(),
+                    offset,
+                    tag.clone(),
+                    read_config.clone(),
+                ))
+                .await?;
+            let Some(last_offset) = records.last().and_then(|record| record.offset) else {
+                break;
+            };
+            offset = last_offset + 1;
+
+            for record in records {
+                if !record.crc32_check() {
+                    return Err(CommonError::CrcCheckByMessage);
+                }
+                let message = MqttMessage::decode_record(record.clone())?;
+                if message.has_tag(tag_key, tag_value) && results.len() < limit {
+                    results.push(record);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    // Streams the messages of the topic from offset, reading a batch at a time.
+    pub fn iter_topic_messages(
+        &self,
+        topic_id: &str,
+        offset: u64,
+    ) -> impl Stream<Item = Result<Record, CommonError>> + Send + '_ {
+        self.storage_adapter
+            .iter_shard_records(cluster_name(), topic_id.to_owned(), offset)
+            .and_then(|record| async move {
+                if !record.crc32_check() {
+                    return Err(CommonError::CrcCheckByMessage);
+                }
+                Ok(record)
+            })
+    }
+
     pub async fn get_group_offset(&self, group_id: &str) -> Result<u64, CommonError> {
-        let offset_data = self
-            .storage_adapter
-            .get_offset_by_group(group_id.to_owned())
+        let offset_data = storage_circuit_breaker()
+            .call(
+                self.storage_adapter
+                    .get_offset_by_group(group_id.to_owned()),
+            )
             .await?;
 
         if let Some(offset) = offset_data.first() {
@@ -88,6 +281,103 @@ where
         Ok(0)
     }
 
+    // Number of messages of the topic the group has not consumed yet
+    pub async fn group_lag(&self, group_id: &str, topic_id: &str) -> Result<u64, CommonError> {
+        let latest = self.read_latest_topic_message(topic_id, 1).await?;
+        let Some(latest_offset) = latest.first().and_then(|record| record.offset) else {
+            return Ok(0);
+        };
+
+        let offset_data = storage_circuit_breaker()
+            .call(
+                self.storage_adapter
+                    .get_offset_by_group(group_id.to_owned()),
+            )
+            .await?;
+        // The committed offset is the last message pushed to the group
+        Ok(match offset_data.first() {
+            Some(offset) => latest_offset.saturating_sub(offset.offset),
+            None => latest_offset + 1,
+        })
+    }
+
+    // Exports the messages of the topic in [from_offset, to_offset). The messages are streamed
+    // from the storage while the returned reader is consumed, so the topic is never loaded whole.
+    pub async fn export_topic_messages(
+        &self,
+        topic_id: &str,
+        from_offset: u64,
+        to_offset: u64,
+        format: ExportFormat,
+    ) -> Result<DuplexStream, CommonError> {
+        // read the first message here, so that a failing storage is reported to the caller
+        self.read_topic_message(topic_id, from_offset, 1).await?;
+
+        let (mut writer, reader) = tokio::io::duplex(EXPORT_BUFFER_SIZE);
+        let message_storage = MessageStorage::new(self.storage_adapter.clone());
+        let topic_id = topic_id.to_owned();
+        tokio::spawn(async move {
+            if let Err(e) = message_storage
+                .write_export(&mut writer, &topic_id, from_offset, to_offset, format)
+                .await
+            {
+                error!(
+                    "Export of the messages of topic [{}] failed with error message: {}",
+                    topic_id, e
+                );
+            }
+        });
+        Ok(reader)
+    }
+
+    async fn write_export<W>(
+        &self,
+        writer: &mut W,
+        topic_id: &str,
+        from_offset: u64,
+        to_offset: u64,
+        format: ExportFormat,
+    ) -> Result<(), CommonError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        match format {
+            ExportFormat::Json => writer.write_all(b"[").await?,
+            ExportFormat::Csv => writer.write_all(EXPORT_CSV_HEADER.as_bytes()).await?,
+        }
+
+        let records = self
+            .iter_topic_messages(topic_id, from_offset)
+            .try_take_while(|record| {
+                future::ready(Ok(record.offset.is_some_and(|offset| offset < to_offset)))
+            });
+        pin_mut!(records);
+
+        let mut first = true;
+        while let Some(record) = records.try_next().await? {
+            let offset = record.offset.unwrap_or_default();
+            let message = ExportedMessage::build(offset, MqttMessage::decode_record(record)?);
+            match format {
+                ExportFormat::Json => {
+                    if !first {
+                        writer.write_all(b",").await?;
+                    }
+                    writer.write_all(&serde_json::to_vec(&message)?).await?;
+                }
+                ExportFormat::Csv => {
+                    writer.write_all(message.to_csv_line().as_bytes()).await?;
+                }
+            }
+            first = false;
+        }
+
+        if format == ExportFormat::Json {
+            writer.write_all(b"]").await?;
+        }
+        writer.shutdown().await?;
+        Ok(())
+    }
+
     pub async fn commit_group_offset(
         &self,
         group_id: &str,
@@ -100,8 +390,201 @@ where
         let mut offset_data = HashMap::new();
         offset_data.insert(shard_name.to_owned(), offset);
 
-        self.storage_adapter
-            .commit_offset(group_id.to_owned(), namespace, offset_data)
+        storage_circuit_breaker()
+            .call(
+                self.storage_adapter
+                    .commit_offset(group_id.to_owned(), namespace, offset_data),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use metadata_struct::adapter::record::Record;
+    use metadata_struct::mqtt::message::{MqttMessage, MESSAGE_TAG_USER_PROPERTY_PREFIX};
+    use protocol::mqtt::common::{Publish, PublishProperties};
+    use storage_adapter::memory::MemoryStorageAdapter;
+    use tokio::io::AsyncReadExt;
+
+    use super::{ExportFormat, ExportedMessage, MessageStorage};
+
+    async fn build_topic(message_storage: &MessageStorage<MemoryStorageAdapter>) -> String {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let topic_id = unique_id();
+        let records = (0..100)
+            .map(|i| {
+                let message = MqttMessage {
+                    client_id: "publisher".to_string(),
+                    topic: Bytes::from("/test/export"),
+                    // commas, quotes and line breaks must survive the csv export
+                    payload: Bytes::from(format!("message-{}, \"quoted\"\nline", i)),
+                    ..Default::default()
+                };
+                Record::build_byte(message.encode())
+            })
+            .collect();
+        message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+        topic_id
+    }
+
+    fn expected_payload(i: u64) -> String {
+        format!("message-{}, \"quoted\"\nline", i)
+    }
+
+    #[tokio::test]
+    async fn export_topic_messages_json_test() {
+        let message_storage = MessageStorage::new(Arc::new(MemoryStorageAdapter::new()));
+        let topic_id = build_topic(&message_storage).await;
+
+        let mut reader = message_storage
+            .export_topic_messages(&topic_id, 0, 1000, ExportFormat::Json)
+            .await
+            .unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).await.unwrap();
+
+        let messages: Vec<ExportedMessage> = serde_json::from_str(&content).unwrap();
+        assert_eq!(messages.len(), 100);
+        for (i, message) in messages.iter().enumerate() {
+            assert_eq!(message.offset, i as u64);
+            assert_eq!(message.client_id, "publisher");
+            assert_eq!(message.topic, "/test/export");
+            assert_eq!(message.payload, expected_payload(i as u64));
+        }
+
+        // the upper bound is exclusive
+        let mut reader = message_storage
+            .export_topic_messages(&topic_id, 10, 20, ExportFormat::Json)
+            .await
+            .unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).await.unwrap();
+        let messages: Vec<ExportedMessage> = serde_json::from_str(&content).unwrap();
+        let offsets: Vec<u64> = messages.iter().map(|message| message.offset).collect();
+        assert_eq!(offsets, (10..20).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn export_topic_messages_csv_test() {
+        let message_storage = MessageStorage::new(Arc::new(MemoryStorageAdapter::new()));
+        let topic_id = build_topic(&message_storage).await;
+
+        let mut reader = message_storage
+            .export_topic_messages(&topic_id, 0, 1000, ExportFormat::Csv)
+            .await
+            .unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).await.unwrap();
+
+        let (header, body) = content.split_once('\n').unwrap();
+        assert_eq!(
+            header,
+            "offset,client_id,topic,qos,retain,create_time,payload"
+        );
+        let lines: Vec<&str> = body.split_terminator("line\"\n").collect();
+        assert_eq!(lines.len(), 100);
+        for (i, line) in lines.iter().enumerate() {
+            let escaped_payload = format!("\"message-{}, \"\"quoted\"\"\n", i);
+            assert_eq!(
+                *line,
+                format!("{},publisher,/test/export,0,false,0,{}", i, escaped_payload)
+            );
+        }
+    }
+
+    #[test]
+    fn export_format_test() {
+        assert_eq!("json".parse::<ExportFormat>().unwrap(), ExportFormat::Json);
+        assert_eq!("CSV".parse::<ExportFormat>().unwrap(), ExportFormat::Csv);
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+
+    #[tokio::test]
+    async fn read_topic_messages_by_tag_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let message_storage = MessageStorage::new(Arc::new(MemoryStorageAdapter::new()));
+        let topic_id = unique_id();
+        let regions = ["eu", "us", "ap"];
+        let records = (0..50)
+            .map(|i| {
+                let publish = Publish {
+                    topic: Bytes::from("/test/tag"),
+                    payload: Bytes::from(format!("message-{}", i)),
+                    ..Default::default()
+                };
+                // every 5th message is untagged
+                let mut user_properties = vec![("k".to_string(), "v".to_string())];
+                if i % 5 != 0 {
+                    user_properties.push((
+                        format!("{}region", MESSAGE_TAG_USER_PROPERTY_PREFIX),
+                        regions[i % 3].to_string(),
+                    ));
+                }
+                let properties = Some(PublishProperties {
+                    user_properties,
+                    ..Default::default()
+                });
+                MqttMessage::build_record("publisher", &publish, &properties, 0).unwrap()
+            })
+            .collect();
+        message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+
+        let expected: Vec<String> = (0..50)
+            .filter(|i| i % 5 != 0 && i % 3 == 0)
+            .map(|i| format!("message-{}", i))
+            .collect();
+        let records = message_storage
+            .read_topic_messages_by_tag(&topic_id, "region", "eu", 100)
+            .await
+            .unwrap();
+        let payloads: Vec<String> = records
+            .into_iter()
+            .map(|record| {
+                let message = MqttMessage::decode_record(record).unwrap();
+                assert_eq!(message.tags().get("region"), Some(&"eu".to_string()));
+                String::from_utf8(message.payload.to_vec()).unwrap()
+            })
+            .collect();
+        assert_eq!(payloads, expected);
+
+        // the limit is applied to the matching messages
+        let records = message_storage
+            .read_topic_messages_by_tag(&topic_id, "region", "eu", 3)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 3);
+
+        assert!(message_storage
+            .read_topic_messages_by_tag(&topic_id, "region", "cn", 100)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(message_storage
+            .read_topic_messages_by_tag(&topic_id, "k", "v", 100)
             .await
+            .unwrap()
+            .is_empty());
     }
 }
@@ -17,6 +17,7 @@ pub mod blacklist;
 pub mod cluster;
 pub mod connector;
 pub mod message;
+pub mod retain_index;
 pub mod schema;
 pub mod session;
 pub mod topic;
@@ -0,0 +1,209 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use common_base::error::common::CommonError;
+use common_base::tools::now_second;
+use metadata_struct::adapter::record::Record;
+use metadata_struct::mqtt::message::MqttMessage;
+use serde::{Deserialize, Serialize};
+use storage_adapter::storage::{ShardInfo, StorageAdapter};
+
+use super::message::{cluster_name, MessageStorage};
+
+pub const RETAIN_INDEX_SHARD_NAME: &str = "$retain_index";
+const RETAIN_INDEX_LOAD_BATCH: u64 = 1000;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct RetainIndexEntry {
+    topic_name: String,
+    // Empty when the retained message of the topic was cleared
+    retain_message: Vec<u8>,
+    retain_message_expired_at: u64,
+}
+
+/// Index of the latest retained message of every topic, appended to a dedicated shard of the
+/// message storage, so that the retained state is restored by reading a single shard at startup.
+pub struct RetainMessageIndex<S> {
+    storage_adapter: Arc<S>,
+}
+
+impl<S> RetainMessageIndex<S>
+where
+    S: StorageAdapter + Send + Sync + 'static,
+{
+    pub fn new(storage_adapter: Arc<S>) -> Self {
+        RetainMessageIndex { storage_adapter }
+    }
+
+    pub async fn set(
+        &self,
+        topic_name: &str,
+        retain_message: &MqttMessage,
+        retain_message_expired_at: u64,
+    ) -> Result<(), CommonError> {
+        self.append(RetainIndexEntry {
+            topic_name: topic_name.to_owned(),
+            retain_message: retain_message.encode(),
+            retain_message_expired_at,
+        })
+        .await
+    }
+
+    pub async fn clear(&self, topic_name: &str) -> Result<(), CommonError> {
+        self.append(RetainIndexEntry {
+            topic_name: topic_name.to_owned(),
+            retain_message: Vec::new(),
+            retain_message_expired_at: 0,
+        })
+        .await
+    }
+
+    // Returns (topic_name, encoded retained message) of all the topics with a live retained message.
+    pub async fn load(&self) -> Result<HashMap<String, Vec<u8>>, CommonError> {
+        self.try_init_shard().await?;
+
+        let message_storage = MessageStorage::new(self.storage_adapter.clone());
+        let mut entries: HashMap<String, RetainIndexEntry> = HashMap::new();
+        let mut offset = 0;
+        loop {
+            let records = message_storage
+                .read_topic_message(RETAIN_INDEX_SHARD_NAME, offset, RETAIN_INDEX_LOAD_BATCH)
+                .await?;
+            let Some(last) = records.last() else {
+                break;
+            };
+            offset = last.offset.unwrap_or(offset) + 1;
+
+            // Later entries of a topic replace the earlier ones
+            for record in records {
+                let entry = serde_json::from_slice::<RetainIndexEntry>(&record.data)?;
+                entries.insert(entry.topic_name.clone(), entry);
+            }
+        }
+
+        let now = now_second();
+        Ok(entries
+            .into_values()
+            .filter(|entry| {
+                !entry.retain_message.is_empty()
+                    && (entry.retain_message_expired_at == 0
+                        || entry.retain_message_expired_at > now)
+            })
+            .map(|entry| (entry.topic_name, entry.retain_message))
+            .collect())
+    }
+
+    async fn append(&self, entry: RetainIndexEntry) -> Result<(), CommonError> {
+        let mut record = Record::build_byte(serde_json::to_vec(&entry)?);
+        record.key = entry.topic_name;
+        let message_storage = MessageStorage::new(self.storage_adapter.clone());
+        message_storage
+            .append_topic_message(RETAIN_INDEX_SHARD_NAME, vec![record])
+            .await?;
+        Ok(())
+    }
+
+    async fn try_init_shard(&self) -> Result<(), CommonError> {
+        let namespace = cluster_name();
+        let list = self
+            .storage_adapter
+            .list_shard(namespace.clone(), RETAIN_INDEX_SHARD_NAME.to_owned())
+            .await?;
+        if !list.is_empty() {
+            return Ok(());
+        }
+
+        let shard = ShardInfo {
+            namespace,
+            shard_name: RETAIN_INDEX_SHARD_NAME.to_owned(),
+            replica_num: 1,
+        };
+        match self.storage_adapter.create_shard(shard).await {
+            Ok(()) | Err(CommonError::ShardAlreadyExists(_, _)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::now_second;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use storage_adapter::memory::MemoryStorageAdapter;
+
+    use super::RetainMessageIndex;
+
+    fn build_message(topic_name: &str, payload: &str) -> MqttMessage {
+        MqttMessage {
+            client_id: "publisher".to_string(),
+            topic: Bytes::from(topic_name.to_owned()),
+            payload: Bytes::from(payload.to_owned()),
+            retain: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn retain_index_set_clear_restart_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let index = RetainMessageIndex::new(storage_adapter.clone());
+        assert!(index.load().await.unwrap().is_empty());
+
+        let expired_at = now_second() + 3600;
+        let t1_message = build_message("/test/retain/1", "1");
+        let t2_message = build_message("/test/retain/2", "2");
+        let t2_new_message = build_message("/test/retain/2", "2-new");
+        let t3_message = build_message("/test/retain/3", "3");
+        index
+            .set("/test/retain/1", &t1_message, expired_at)
+            .await
+            .unwrap();
+        index
+            .set("/test/retain/2", &t2_message, expired_at)
+            .await
+            .unwrap();
+        index.clear("/test/retain/1").await.unwrap();
+        index
+            .set("/test/retain/2", &t2_new_message, expired_at)
+            .await
+            .unwrap();
+        // already expired
+        index
+            .set("/test/retain/3", &t3_message, now_second() - 1)
+            .await
+            .unwrap();
+
+        // a new index over the same storage, as after a restart of the broker
+        let index = RetainMessageIndex::new(storage_adapter);
+        let retain_messages = index.load().await.unwrap();
+        assert_eq!(retain_messages.len(), 1);
+        assert_eq!(
+            retain_messages.get("/test/retain/2"),
+            Some(&t2_new_message.encode())
+        );
+    }
+}
@@ -18,13 +18,16 @@ use common_base::config::broker_mqtt::broker_mqtt_conf;
 use common_base::error::common::CommonError;
 use dashmap::DashMap;
 use grpc_clients::placement::mqtt::call::{
-    placement_create_session, placement_delete_session, placement_list_session,
-    placement_save_last_will_message, placement_update_session,
+    placement_create_session, placement_delete_last_will_message, placement_delete_session,
+    placement_get_last_will_message, placement_list_session, placement_save_last_will_message,
+    placement_update_session,
 };
 use grpc_clients::pool::ClientPool;
+use metadata_struct::mqtt::lastwill::LastWillData;
 use metadata_struct::mqtt::session::MqttSession;
 use protocol::placement_center::placement_center_mqtt::{
-    CreateSessionRequest, DeleteSessionRequest, ListSessionRequest, SaveLastWillMessageRequest,
+    CreateSessionRequest, DeleteLastWillMessageRequest, DeleteSessionRequest,
+    GetLastWillMessageRequest, ListSessionRequest, SaveLastWillMessageRequest,
     UpdateSessionRequest,
 };
 
@@ -154,4 +157,47 @@ impl SessionStorage {
             Err(e) => Err(e),
         }
     }
+
+    pub async fn get_last_will_message(
+        &self,
+        client_id: String,
+    ) -> Result<Option<LastWillData>, CommonError> {
+        let config = broker_mqtt_conf();
+        let request = GetLastWillMessageRequest {
+            cluster_name: config.cluster_name.clone(),
+            client_id,
+        };
+        match placement_get_last_will_message(&self.client_pool, &config.placement_center, request)
+            .await
+        {
+            Ok(reply) => {
+                if reply.last_will_message.is_empty() {
+                    return Ok(None);
+                }
+                match serde_json::from_slice::<LastWillData>(&reply.last_will_message) {
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) => Err(CommonError::CommonError(e.to_string())),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn delete_last_will_message(&self, client_id: String) -> Result<(), CommonError> {
+        let config = broker_mqtt_conf();
+        let request = DeleteLastWillMessageRequest {
+            cluster_name: config.cluster_name.clone(),
+            client_id,
+        };
+        match placement_delete_last_will_message(
+            &self.client_pool,
+            &config.placement_center,
+            request,
+        )
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
 }
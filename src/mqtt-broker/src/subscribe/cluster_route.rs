@@ -0,0 +1,573 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common_base::config::broker_mqtt::broker_mqtt_conf;
+use common_base::tools::{now_second, unique_id};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use grpc_clients::mqtt::inner::call::broker_mqtt_forward_message;
+use grpc_clients::pool::ClientPool;
+use log::{debug, error, warn};
+use metadata_struct::adapter::record::Record;
+use metadata_struct::mqtt::message::MqttMessage;
+use metadata_struct::mqtt::topic::MqttTopic;
+use protocol::broker_mqtt::broker_mqtt_inner::ForwardMessageRequest;
+use storage_adapter::storage::StorageAdapter;
+use tokio::select;
+use tokio::sync::{broadcast, AcquireError, OwnedSemaphorePermit, Semaphore};
+use tokio::time::sleep;
+
+use crate::handler::cache::CacheManager;
+use crate::handler::error::MqttBrokerError;
+use crate::handler::topic::try_init_topic;
+use crate::storage::cluster::ClusterStorage;
+use crate::storage::message::MessageStorage;
+use crate::subscribe::sub_common::{is_queue_sub, is_share_sub};
+use crate::subscribe::subscribe_manager::SubscribeManager;
+
+const BROKER_NODE_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+// Forwards sent and not answered yet, the publishers wait beyond it
+const MAX_IN_FLIGHT_FORWARD: usize = 64;
+// The broker of the publisher forwards to the brokers of the subscribers, which never forward again
+const MAX_FORWARD_HOPS: u32 = 1;
+// Forwards remembered to drop the ones retried after they were already saved
+const FORWARD_DEDUP_WINDOW_SEC: u64 = 300;
+const FORWARD_DEDUP_CAPACITY: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForwardState {
+    // The messages of the forward are being saved
+    Pending,
+    Saved,
+}
+
+/// State of the forwarding of the messages between the brokers of the cluster.
+#[derive(Clone)]
+pub struct MessageForward {
+    in_flight: Arc<Semaphore>,
+    // (forward_id, (state, time it was received))
+    received: Arc<DashMap<String, (ForwardState, u64)>>,
+}
+
+impl Default for MessageForward {
+    fn default() -> Self {
+        MessageForward {
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT_FORWARD)),
+            received: Arc::new(DashMap::with_capacity(8)),
+        }
+    }
+}
+
+impl MessageForward {
+    pub fn new() -> Self {
+        MessageForward::default()
+    }
+
+    // Waits until fewer than MAX_IN_FLIGHT_FORWARD forwards are in flight
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, AcquireError> {
+        self.in_flight.clone().acquire_owned().await
+    }
+
+    // Reserves the forward id for the caller that saves its messages. Returns None if the id
+    // was free, or the state of the forward that already holds it.
+    pub fn try_reserve(&self, forward_id: &str) -> Option<ForwardState> {
+        let now = now_second();
+        if self.received.len() >= FORWARD_DEDUP_CAPACITY {
+            self.received
+                .retain(|_, (_, received_at)| *received_at + FORWARD_DEDUP_WINDOW_SEC > now);
+        }
+        match self.received.entry(forward_id.to_owned()) {
+            Entry::Occupied(entry) => Some(entry.get().0),
+            Entry::Vacant(entry) => {
+                entry.insert((ForwardState::Pending, now));
+                None
+            }
+        }
+    }
+
+    pub fn complete(&self, forward_id: &str) {
+        self.received
+            .insert(forward_id.to_owned(), (ForwardState::Saved, now_second()));
+    }
+
+    // Frees the id of a forward whose messages could not be saved, so that its retry saves them
+    pub fn release(&self, forward_id: &str) {
+        self.received
+            .remove_if(forward_id, |_, (state, _)| *state == ForwardState::Pending);
+    }
+}
+
+// The brokers other than local_broker_id holding a subscription whose topic filter matches the
+// topic, looked up in the subscription index. The shared subscriptions are left out, the followers
+// of the other brokers already receive the messages from the leader of the group.
+pub fn remote_subscriber_brokers(
+    subscribe_manager: &SubscribeManager,
+    topic_name: &str,
+    local_broker_id: u64,
+) -> HashSet<u64> {
+    subscribe_manager.subscription_trie.match_topic_broker_ids(
+        topic_name,
+        |broker_id, subscriber| {
+            broker_id != local_broker_id
+                && !is_share_sub(&subscriber.sub_path)
+                && !is_queue_sub(&subscriber.sub_path)
+        },
+    )
+}
+
+/// Sends the messages stored at offsets of the topic to the brokers holding a subscriber of the
+/// topic. Every broker only reads the messages of its own storage, so the messages published on
+/// another broker reach the subscribers of the broker through it.
+///
+/// The messages are sent as they were stored, so their QoS and properties are kept. Every batch
+/// carries a forward id, so that a batch retried after the receiver saved it is not saved twice.
+pub async fn forward_message<S>(
+    cache_manager: &Arc<CacheManager>,
+    client_pool: &Arc<ClientPool>,
+    message_storage_adapter: &Arc<S>,
+    topic: &MqttTopic,
+    offsets: &[u64],
+    broker_ids: HashSet<u64>,
+) -> Result<(), MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let (Some(first), Some(last)) = (offsets.iter().min(), offsets.iter().max()) else {
+        return Ok(());
+    };
+    if broker_ids.is_empty() {
+        return Ok(());
+    }
+
+    let message_storage = MessageStorage::new(message_storage_adapter.clone());
+    let messages: Vec<Vec<u8>> = message_storage
+        .read_topic_message(&topic.topic_id, *first, last - first + 1)
+        .await?
+        .into_iter()
+        .map(|record| record.data)
+        .collect();
+
+    let conf = broker_mqtt_conf();
+    for broker_id in broker_ids {
+        let Some(node) = cache_manager.get_broker_node(broker_id) else {
+            warn!(
+                "Messages of topic [{}] are not forwarded to broker {}, the address of the broker is unknown",
+                topic.topic_name, broker_id
+            );
+            continue;
+        };
+        let request = ForwardMessageRequest {
+            cluster_name: conf.cluster_name.clone(),
+            source_broker_id: conf.broker_id,
+            topic_name: topic.topic_name.clone(),
+            messages: messages.clone(),
+            forward_id: unique_id(),
+            hop: 1,
+        };
+        if let Err(e) =
+            broker_mqtt_forward_message(client_pool, &[node.node_inner_addr], request).await
+        {
+            error!(
+                "Failed to forward the messages of topic [{}] to broker {}, {}",
+                topic.topic_name, broker_id, e
+            );
+        }
+    }
+    Ok(())
+}
+
+// Stores the messages forwarded by another broker, the push threads then deliver them to the
+// local subscribers. They are not forwarded again.
+pub async fn save_forwarded_message<S>(
+    cache_manager: &Arc<CacheManager>,
+    client_pool: &Arc<ClientPool>,
+    message_storage_adapter: &Arc<S>,
+    request: ForwardMessageRequest,
+) -> Result<Vec<u64>, MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    if request.hop > MAX_FORWARD_HOPS {
+        return Err(MqttBrokerError::ForwardMessageLoop(
+            request.topic_name,
+            request.source_broker_id,
+            request.hop,
+        ));
+    }
+    let message_forward = &cache_manager.message_forward;
+    match message_forward.try_reserve(&request.forward_id) {
+        None => {}
+        Some(ForwardState::Saved) => {
+            debug!(
+                "Messages of topic [{}] forwarded by broker {} were already saved, forward id {}",
+                request.topic_name, request.source_broker_id, request.forward_id
+            );
+            return Ok(Vec::new());
+        }
+        // The sender retries once the first attempt saved the messages or gave up
+        Some(ForwardState::Pending) => {
+            return Err(MqttBrokerError::ForwardMessageInProgress(
+                request.forward_id,
+            ));
+        }
+    }
+
+    let forward_id = request.forward_id.clone();
+    match save_reserved_forward(cache_manager, client_pool, message_storage_adapter, request).await
+    {
+        Ok(offsets) => {
+            message_forward.complete(&forward_id);
+            Ok(offsets)
+        }
+        Err(e) => {
+            message_forward.release(&forward_id);
+            Err(e)
+        }
+    }
+}
+
+async fn save_reserved_forward<S>(
+    cache_manager: &Arc<CacheManager>,
+    client_pool: &Arc<ClientPool>,
+    message_storage_adapter: &Arc<S>,
+    request: ForwardMessageRequest,
+) -> Result<Vec<u64>, MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let topic = try_init_topic(
+        &request.topic_name,
+        cache_manager,
+        message_storage_adapter,
+        client_pool,
+    )
+    .await?;
+
+    let mut records = Vec::with_capacity(request.messages.len());
+    for data in request.messages {
+        let mut record = Record::build_byte(data);
+        record.tags = MqttMessage::decode_record(record.clone())?.record_tags();
+        records.push(record);
+    }
+
+    let message_storage = MessageStorage::new(message_storage_adapter.clone());
+    let offsets = message_storage
+        .append_topic_message(&topic.topic_id, records)
+        .await?;
+    debug!(
+        "Saved {} messages of topic [{}] forwarded by broker {}",
+        offsets.len(),
+        request.topic_name,
+        request.source_broker_id
+    );
+    Ok(offsets)
+}
+
+// Keeps the addresses of the brokers of the cluster up to date for the forwarding.
+pub async fn start_broker_node_refresh_thread(
+    cache_manager: Arc<CacheManager>,
+    client_pool: Arc<ClientPool>,
+    stop_send: broadcast::Sender<bool>,
+) {
+    loop {
+        let mut stop_recv = stop_send.subscribe();
+        select! {
+            val = stop_recv.recv() =>{
+                if let Ok(flag) = val {
+                    if flag {
+                        debug!("{}","Broker node refresh thread exited successfully");
+                        break;
+                    }
+                }
+            }
+            _ = refresh_broker_nodes(&cache_manager, &client_pool) => {
+                sleep(BROKER_NODE_REFRESH_INTERVAL).await;
+            }
+        }
+    }
+}
+
+async fn refresh_broker_nodes(cache_manager: &Arc<CacheManager>, client_pool: &Arc<ClientPool>) {
+    let cluster_storage = ClusterStorage::new(client_pool.clone());
+    match cluster_storage.node_list().await {
+        Ok(nodes) => cache_manager.set_broker_nodes(nodes),
+        Err(e) => error!("Failed to refresh the broker nodes of the cluster, {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::{now_second, unique_id};
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::adapter::record::Record;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use metadata_struct::mqtt::subscribe_data::MqttSubscribe;
+    use metadata_struct::mqtt::topic::MqttTopic;
+    use metadata_struct::placement::node::BrokerNode;
+    use protocol::broker_mqtt::broker_mqtt_inner::ForwardMessageRequest;
+    use protocol::mqtt::common::{Filter, MqttPacket, MqttProtocol, QoS, RetainForwardRule};
+    use schema_register::schema::SchemaRegisterManager;
+    use storage_adapter::memory::MemoryStorageAdapter;
+    use tokio::net::TcpStream;
+    use tokio::time::{sleep, timeout};
+
+    use super::{
+        forward_message, remote_subscriber_brokers, save_forwarded_message, ForwardState,
+        MessageForward,
+    };
+    use crate::bridge::manager::ConnectorManager;
+    use crate::handler::cache::CacheManager;
+    use crate::server::grpc::server::GrpcServer;
+    use crate::storage::message::MessageStorage;
+    use crate::subscribe::push_fixture::{build_push_fixture, read_packet};
+    use crate::subscribe::subscribe_manager::SubscribeManager;
+
+    fn build_subscribe(client_id: &str, path: &str, broker_id: u64) -> MqttSubscribe {
+        MqttSubscribe {
+            client_id: client_id.to_owned(),
+            path: path.to_owned(),
+            cluster_name: "mqtt-broker".to_string(),
+            broker_id,
+            protocol: MqttProtocol::Mqtt5,
+            filter: Filter {
+                path: path.to_owned(),
+                qos: QoS::AtLeastOnce,
+                nolocal: false,
+                preserve_retain: false,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            },
+            pkid: 1,
+            subscribe_properties: None,
+        }
+    }
+
+    #[test]
+    fn remote_subscriber_brokers_test() {
+        let subscribe_manager = SubscribeManager::new();
+        subscribe_manager.add_subscribe(build_subscribe("c1", "/test/route/+", 1));
+        subscribe_manager.add_subscribe(build_subscribe("c2", "/test/route/+", 2));
+        subscribe_manager.add_subscribe(build_subscribe("c3", "/test/#", 3));
+        subscribe_manager.add_subscribe(build_subscribe("c4", "/test/other", 4));
+        subscribe_manager.add_subscribe(build_subscribe("c5", "$share/g1/test/route/a", 5));
+
+        let mut broker_ids: Vec<u64> =
+            remote_subscriber_brokers(&subscribe_manager, "/test/route/a", 1)
+                .into_iter()
+                .collect();
+        broker_ids.sort();
+        assert_eq!(broker_ids, vec![2, 3]);
+
+        assert!(remote_subscriber_brokers(&subscribe_manager, "/nothing", 1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn forward_message_two_node_test() {
+        let topic_name = "/test/route/two_node";
+        // known by both brokers, the topics are shared through the placement center
+        let topic = MqttTopic::new(
+            unique_id(),
+            "mqtt-broker".to_string(),
+            topic_name.to_string(),
+        );
+
+        // node 2, the broker of the subscriber
+        let node2 = build_push_fixture();
+        let client_pool = node2.client_pool.clone();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        node2.cache_manager.add_topic(topic_name, &topic);
+        let server = GrpcServer::new(
+            port as u32,
+            node2.cache_manager.clone(),
+            Arc::new(ConnectorManager::new()),
+            node2.subscribe_manager.clone(),
+            node2.connection_manager.clone(),
+            Arc::new(SchemaRegisterManager::new()),
+            client_pool.clone(),
+            node2.storage_adapter.clone(),
+        );
+        tokio::spawn(async move { server.start().await });
+        timeout(Duration::from_secs(10), async {
+            while TcpStream::connect(("127.0.0.1", port)).await.is_err() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // the subscriber connected to node 2
+        let (client_id, _, mut read_stream) = node2.connect_client().await;
+        node2.add_exclusive_push(&client_id, topic_name, &topic.topic_id, QoS::AtMostOnce);
+        node2.start_exclusive_push().await;
+
+        // node 1, the broker of the publisher
+        let node1_cache = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        node1_cache.add_topic(topic_name, &topic);
+        node1_cache.set_broker_nodes(vec![BrokerNode {
+            node_id: 2,
+            node_inner_addr: format!("127.0.0.1:{}", port),
+            ..Default::default()
+        }]);
+        let node1_storage = Arc::new(MemoryStorageAdapter::new());
+        let node1_subscribe_manager = SubscribeManager::new();
+        node1_subscribe_manager.add_subscribe(build_subscribe(&client_id, topic_name, 2));
+        let broker_ids = remote_subscriber_brokers(&node1_subscribe_manager, topic_name, 1);
+
+        let message = MqttMessage {
+            client_id: "publisher".to_string(),
+            topic: Bytes::from(topic_name),
+            payload: Bytes::from("from node 1"),
+            qos: QoS::ExactlyOnce,
+            expiry_interval: now_second() + 3600,
+            create_time: now_second(),
+            ..Default::default()
+        };
+        let offsets = MessageStorage::new(node1_storage.clone())
+            .append_topic_message(&topic.topic_id, vec![Record::build_byte(message.encode())])
+            .await
+            .unwrap();
+        forward_message(
+            &node1_cache,
+            &client_pool,
+            &node1_storage,
+            &topic,
+            &offsets,
+            broker_ids,
+        )
+        .await
+        .unwrap();
+
+        // saved in the storage of node 2
+        let records = node2
+            .message_storage
+            .read_topic_message(&topic.topic_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        let received = MqttMessage::decode_record(records[0].clone()).unwrap();
+        assert_eq!(received.client_id, "publisher");
+        assert_eq!(received.qos, QoS::ExactlyOnce);
+
+        // and delivered by the push thread of node 2 to its subscriber
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.topic, Bytes::from(topic_name));
+        assert_eq!(publish.payload, Bytes::from("from node 1"));
+
+        node2.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn save_forwarded_message_once_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let topic_name = "/test/route/once";
+        let topic = MqttTopic::new(
+            unique_id(),
+            "mqtt-broker".to_string(),
+            topic_name.to_string(),
+        );
+        let client_pool = Arc::new(ClientPool::new(1));
+        let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+        cache_manager.add_topic(topic_name, &topic);
+        let storage = Arc::new(MemoryStorageAdapter::new());
+
+        let message = MqttMessage {
+            client_id: "publisher".to_string(),
+            topic: Bytes::from(topic_name),
+            payload: Bytes::from("forwarded".to_string()),
+            ..Default::default()
+        };
+        let request = ForwardMessageRequest {
+            cluster_name: "mqtt-broker".to_string(),
+            source_broker_id: 2,
+            topic_name: topic_name.to_string(),
+            messages: vec![message.encode()],
+            forward_id: unique_id(),
+            hop: 1,
+        };
+
+        let offsets =
+            save_forwarded_message(&cache_manager, &client_pool, &storage, request.clone())
+                .await
+                .unwrap();
+        assert_eq!(offsets.len(), 1);
+        // the same forward retried, e.g. after its reply was lost
+        let offsets =
+            save_forwarded_message(&cache_manager, &client_pool, &storage, request.clone())
+                .await
+                .unwrap();
+        assert!(offsets.is_empty());
+
+        // forwarded again by the receiver, it would loop between the brokers
+        let looped = ForwardMessageRequest {
+            forward_id: unique_id(),
+            hop: 2,
+            ..request
+        };
+        assert!(
+            save_forwarded_message(&cache_manager, &client_pool, &storage, looped)
+                .await
+                .is_err()
+        );
+
+        let records = MessageStorage::new(storage)
+            .read_topic_message(&topic.topic_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn forward_id_reserve_test() {
+        let message_forward = MessageForward::new();
+        let forward_id = unique_id();
+        assert_eq!(message_forward.try_reserve(&forward_id), None);
+
+        // a retry of the forward while it is being saved, also through a clone of the state
+        let cloned = message_forward.clone();
+        assert_eq!(cloned.try_reserve(&forward_id), Some(ForwardState::Pending));
+
+        // the save failed, the retry may save the messages
+        message_forward.release(&forward_id);
+        assert_eq!(cloned.try_reserve(&forward_id), None);
+        cloned.complete(&forward_id);
+        assert_eq!(
+            message_forward.try_reserve(&forward_id),
+            Some(ForwardState::Saved)
+        );
+        message_forward.release(&forward_id);
+        assert_eq!(
+            message_forward.try_reserve(&forward_id),
+            Some(ForwardState::Saved)
+        );
+    }
+}
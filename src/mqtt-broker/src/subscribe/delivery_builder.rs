@@ -0,0 +1,234 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use metadata_struct::mqtt::message::MqttMessage;
+use protocol::mqtt::common::{Publish, PublishProperties, QoS};
+
+use super::sub_common::{build_user_properties, min_qos, transform_subscription_payload};
+use super::subscriber::Subscriber;
+use crate::handler::message::message_remaining_expiry;
+
+/// Builds the PUBLISH of a stored message for a subscriber, the same way for all the push
+/// workers:
+/// - the QoS is the one of the subscription, downgraded to the maximum QoS given;
+/// - the retain flag is kept only when the subscription preserves it;
+/// - the message is not delivered back to its publisher when the subscription is no local;
+/// - the message is delivered only when its publisher is in the publisher allowlist of the
+///   subscription, if it has one;
+/// - the properties of the publisher are forwarded, with the remaining expiry interval and the
+///   Subscription Identifiers of the subscription. The broker does not set a topic alias on
+///   the messages it delivers.
+pub struct DeliveryBuilder<'a> {
+    subscriber: &'a Subscriber,
+    topic_name: &'a str,
+    qos: QoS,
+    sub_ids: Vec<usize>,
+}
+
+impl<'a> DeliveryBuilder<'a> {
+    pub fn new(subscriber: &'a Subscriber) -> Self {
+        DeliveryBuilder {
+            subscriber,
+            topic_name: &subscriber.topic_name,
+            qos: subscriber.qos,
+            sub_ids: subscriber.subscription_identifier.into_iter().collect(),
+        }
+    }
+
+    // The topic the message was published to, when it is not the one of the subscriber
+    pub fn topic_name(mut self, topic_name: &'a str) -> Self {
+        self.topic_name = topic_name;
+        self
+    }
+
+    pub fn max_qos(mut self, max_qos: QoS) -> Self {
+        self.qos = min_qos(max_qos, self.qos);
+        self
+    }
+
+    pub fn sub_ids(mut self, sub_ids: &[usize]) -> Self {
+        self.sub_ids = sub_ids.to_vec();
+        self
+    }
+
+    /// Returns None when the message must not be delivered to the subscriber. The pkid of the
+    /// PUBLISH is left to 0 for the caller to set.
+    pub fn build(&self, msg: MqttMessage) -> Option<(Publish, PublishProperties)> {
+        if self.subscriber.nolocal && self.subscriber.client_id == msg.client_id {
+            return None;
+        }
+
+        if let Some(allowlist) = &self.subscriber.publisher_allowlist {
+            if !allowlist.contains(&msg.client_id) {
+                return None;
+            }
+        }
+
+        let message_expiry_interval = message_remaining_expiry(&msg);
+        let publish = Publish {
+            dup: false,
+            qos: self.qos,
+            pkid: 0,
+            retain: self.subscriber.preserve_retain && msg.retain,
+            topic: Bytes::from(self.topic_name.to_owned()),
+            payload: transform_subscription_payload(
+                &self.subscriber.client_id,
+                &self.subscriber.sub_path,
+                self.topic_name,
+                msg.payload,
+            ),
+        };
+
+        let properties = PublishProperties {
+            payload_format_indicator: msg.format_indicator,
+            message_expiry_interval: Some(message_expiry_interval),
+            topic_alias: None,
+            response_topic: msg.response_topic,
+            correlation_data: msg.correlation_data,
+            user_properties: build_user_properties(
+                self.topic_name,
+                msg.user_properties,
+                msg.create_time,
+            ),
+            subscription_identifiers: self.sub_ids.clone(),
+            content_type: msg.content_type,
+        };
+        Some((publish, properties))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::now_second;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use protocol::mqtt::common::QoS;
+
+    use super::DeliveryBuilder;
+    use crate::subscribe::subscriber::Subscriber;
+
+    fn build_message() -> MqttMessage {
+        MqttMessage {
+            client_id: "publisher".to_string(),
+            qos: QoS::ExactlyOnce,
+            retain: true,
+            topic: Bytes::from("/test/delivery"),
+            payload: Bytes::from("payload"),
+            format_indicator: Some(1),
+            expiry_interval: now_second() + 60,
+            response_topic: Some("/test/response".to_string()),
+            correlation_data: Some(Bytes::from("correlation")),
+            user_properties: vec![("k1".to_string(), "v1".to_string())],
+            content_type: Some("text/plain".to_string()),
+            create_time: now_second(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn delivery_builder_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let subscriber = Subscriber {
+            client_id: "c1".to_string(),
+            sub_path: "/test/+".to_string(),
+            topic_name: "/test/delivery".to_string(),
+            qos: QoS::ExactlyOnce,
+            subscription_identifier: Some(7),
+            ..Default::default()
+        };
+
+        let (publish, properties) = DeliveryBuilder::new(&subscriber)
+            .max_qos(QoS::AtLeastOnce)
+            .build(build_message())
+            .unwrap();
+        assert_eq!(publish.qos, QoS::AtLeastOnce);
+        assert_eq!(publish.pkid, 0);
+        assert!(!publish.dup);
+        assert!(!publish.retain);
+        assert_eq!(publish.topic, Bytes::from("/test/delivery"));
+        assert_eq!(publish.payload, Bytes::from("payload"));
+
+        assert_eq!(properties.payload_format_indicator, Some(1));
+        let expiry = properties.message_expiry_interval.unwrap();
+        assert!(expiry > 0 && expiry <= 60);
+        assert_eq!(properties.topic_alias, None);
+        assert_eq!(
+            properties.response_topic,
+            Some("/test/response".to_string())
+        );
+        assert_eq!(
+            properties.correlation_data,
+            Some(Bytes::from("correlation"))
+        );
+        assert!(properties
+            .user_properties
+            .contains(&("k1".to_string(), "v1".to_string())));
+        assert_eq!(properties.subscription_identifiers, vec![7]);
+        assert_eq!(properties.content_type, Some("text/plain".to_string()));
+
+        // the retain flag is preserved, the topic and the identifiers are overridden
+        let subscriber = Subscriber {
+            preserve_retain: true,
+            ..subscriber
+        };
+        let (publish, properties) = DeliveryBuilder::new(&subscriber)
+            .topic_name("/test/other")
+            .sub_ids(&[1, 2])
+            .build(build_message())
+            .unwrap();
+        assert_eq!(publish.qos, QoS::ExactlyOnce);
+        assert!(publish.retain);
+        assert_eq!(publish.topic, Bytes::from("/test/other"));
+        assert_eq!(properties.subscription_identifiers, vec![1, 2]);
+
+        // an expired message keeps no expiry interval left
+        let mut message = build_message();
+        message.expiry_interval = now_second() - 10;
+        let (_, properties) = DeliveryBuilder::new(&subscriber).build(message).unwrap();
+        assert_eq!(properties.message_expiry_interval, Some(0));
+
+        // publisher allowlist
+        let allowed = Subscriber {
+            publisher_allowlist: Some(vec!["sensor-1".to_string(), "publisher".to_string()]),
+            ..subscriber.clone()
+        };
+        assert!(DeliveryBuilder::new(&allowed)
+            .build(build_message())
+            .is_some());
+        let not_allowed = Subscriber {
+            publisher_allowlist: Some(vec!["sensor-1".to_string()]),
+            ..subscriber.clone()
+        };
+        assert!(DeliveryBuilder::new(&not_allowed)
+            .build(build_message())
+            .is_none());
+
+        // no local
+        let subscriber = Subscriber {
+            client_id: "publisher".to_string(),
+            nolocal: true,
+            ..subscriber
+        };
+        assert!(DeliveryBuilder::new(&subscriber)
+            .build(build_message())
+            .is_none());
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use protocol::mqtt::common::SubscribeProperties;
+use tokio::time::sleep_until;
+
+/// User property of a SUBSCRIBE with the maximum number of messages per second the client
+/// wants to be delivered, e.g. a constrained device that cannot keep up with its topics.
+pub const MAX_DELIVERY_RATE_USER_PROPERTY: &str = "x-max-delivery-rate";
+
+// The rate requested by the user properties of the SUBSCRIBE, the last one wins
+pub fn requested_delivery_rate(properties: &Option<SubscribeProperties>) -> Option<u32> {
+    properties
+        .as_ref()?
+        .user_properties
+        .iter()
+        .rev()
+        .find(|(key, _)| key == MAX_DELIVERY_RATE_USER_PROPERTY)
+        .and_then(|(_, value)| value.trim().parse::<u32>().ok())
+        .filter(|rate| *rate > 0)
+}
+
+// The rate the subscription is delivered at, bounded by the broker maximum, 0 is unpaced
+pub fn bound_delivery_rate(requested: Option<u32>, broker_max: u32) -> u32 {
+    match requested {
+        Some(rate) if broker_max > 0 => rate.min(broker_max),
+        Some(rate) => rate,
+        None => 0,
+    }
+}
+
+/// Paces the delivery of a subscription to at most `rate` messages per second.
+pub struct DeliveryRate {
+    rate: u64,
+    window_start: Instant,
+    window_sent: u64,
+}
+
+impl DeliveryRate {
+    pub fn new(rate: u32) -> Self {
+        DeliveryRate {
+            rate: rate as u64,
+            window_start: Instant::now(),
+            window_sent: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rate > 0
+    }
+
+    pub fn set_rate(&mut self, rate: u32) {
+        if self.rate != rate as u64 {
+            self.rate = rate as u64;
+            self.window_start = Instant::now();
+            self.window_sent = 0;
+        }
+    }
+
+    /// Waits until a message may be delivered and returns how many of the record_num
+    /// messages may be delivered now.
+    pub async fn allowance(&mut self, record_num: u64) -> u64 {
+        if !self.is_enabled() {
+            return record_num;
+        }
+        loop {
+            if self.window_start.elapsed() >= Duration::from_secs(1) {
+                self.window_start = Instant::now();
+                self.window_sent = 0;
+            }
+            if self.window_sent < self.rate {
+                return record_num.min(self.rate - self.window_sent);
+            }
+            sleep_until((self.window_start + Duration::from_secs(1)).into()).await;
+        }
+    }
+
+    pub fn consume(&mut self, num: u64) {
+        self.window_sent = self.window_sent.saturating_add(num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::mqtt::common::SubscribeProperties;
+    use tokio::time::Instant;
+
+    use super::{
+        bound_delivery_rate, requested_delivery_rate, DeliveryRate, MAX_DELIVERY_RATE_USER_PROPERTY,
+    };
+
+    #[test]
+    fn requested_delivery_rate_test() {
+        assert_eq!(requested_delivery_rate(&None), None);
+
+        let mut properties = SubscribeProperties::default();
+        assert_eq!(requested_delivery_rate(&Some(properties.clone())), None);
+
+        for value in ["fast", "0", "-1"] {
+            properties.user_properties = vec![(
+                MAX_DELIVERY_RATE_USER_PROPERTY.to_string(),
+                value.to_string(),
+            )];
+            assert_eq!(requested_delivery_rate(&Some(properties.clone())), None);
+        }
+
+        properties.user_properties = vec![
+            (
+                MAX_DELIVERY_RATE_USER_PROPERTY.to_string(),
+                "10".to_string(),
+            ),
+            ("k1".to_string(), "v1".to_string()),
+            (
+                MAX_DELIVERY_RATE_USER_PROPERTY.to_string(),
+                " 20 ".to_string(),
+            ),
+        ];
+        assert_eq!(requested_delivery_rate(&Some(properties)), Some(20));
+
+        assert_eq!(bound_delivery_rate(None, 100), 0);
+        assert_eq!(bound_delivery_rate(Some(20), 0), 20);
+        assert_eq!(bound_delivery_rate(Some(20), 100), 20);
+        assert_eq!(bound_delivery_rate(Some(200), 100), 100);
+    }
+
+    #[tokio::test]
+    async fn allowance_test() {
+        let mut delivery_rate = DeliveryRate::new(0);
+        assert!(!delivery_rate.is_enabled());
+        assert_eq!(delivery_rate.allowance(100).await, 100);
+
+        delivery_rate.set_rate(10);
+        assert_eq!(delivery_rate.allowance(4).await, 4);
+        delivery_rate.consume(4);
+        assert_eq!(delivery_rate.allowance(100).await, 6);
+        delivery_rate.consume(6);
+
+        // waits for the next second, which allows as many messages again
+        let start = Instant::now();
+        assert_eq!(delivery_rate.allowance(100).await, 10);
+        assert!(start.elapsed().as_millis() >= 900);
+    }
+}
@@ -13,38 +13,70 @@
 // limitations under the License.
 
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use bytes::Bytes;
+use common_base::tools::circuit_breaker::{CircuitBreaker, CircuitState};
 use common_base::tools::now_second;
-use log::{debug, error, info};
+use dashmap::DashMap;
+use futures::future::join_all;
+use futures::{stream, FutureExt, StreamExt};
+use log::{debug, error, info, warn};
 use metadata_struct::adapter::record::Record;
 use metadata_struct::mqtt::message::MqttMessage;
-use protocol::mqtt::common::{MqttPacket, MqttProtocol, Publish, PublishProperties, QoS};
+use protocol::mqtt::common::{MqttPacket, MqttProtocol, Publish, QoS};
 use storage_adapter::storage::StorageAdapter;
 use tokio::select;
 use tokio::sync::broadcast::{self};
 use tokio::time::sleep;
 
+use super::delivery_builder::DeliveryBuilder;
+use super::delivery_rate::{bound_delivery_rate, DeliveryRate};
+use super::reader_inflight::ReaderInflight;
+use super::slow_start::SlowStart;
 use super::sub_common::{
-    loop_commit_offset, min_qos, publish_message_qos0, publish_message_to_client,
-    qos2_send_publish, qos2_send_pubrel, wait_packet_ack,
+    delivery_dup, disconnect_unauthorized_subscriber, loop_commit_offset, min_qos,
+    order_record_offsets, publish_message_qos0, publish_message_to_client, qos2_send_publish,
+    qos2_send_pubrel, redelivery_properties, wait_packet_ack_timeout,
 };
 use super::subscribe_manager::SubscribeManager;
 use super::subscriber::Subscriber;
 use crate::handler::cache::{CacheManager, QosAckPackageData, QosAckPackageType, QosAckPacketInfo};
 use crate::handler::error::MqttBrokerError;
 use crate::handler::message::is_message_expire;
+use crate::observability::metrics::publish::{record_message_dropped, MessageDropReason};
+use crate::observability::metrics::server::{
+    metrics_push_circuit_open, metrics_push_thread_num, PUSH_THREAD_TYPE_EXCLUSIVE,
+};
+use crate::security::acl::auth::is_allow_delivery;
+use crate::security::encryption::decrypt_message_payload;
 use crate::server::connection_manager::ConnectionManager;
 use crate::server::packet::ResponsePackage;
 use crate::storage::message::MessageStorage;
 use crate::subscribe::subscriber::SubPublishParam;
 
+// Time to wait for the PUBACK of a QoS1 delivery before sending the publish again
+pub const QOS1_ACK_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Time to wait for the PUBREC/PUBCOMP of a QoS2 delivery before sending the packet again
+pub const QOS2_ACK_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Consecutive read failures of a topic after which its delivery is paused
+const PUSH_FAILURE_THRESHOLD: u32 = 5;
+const PUSH_RESET_TIMEOUT_SECS: u64 = 30;
+
+// Applied to every message before it is pushed to the client
+pub type MessageTransformer = Arc<dyn Fn(MqttMessage) -> MqttMessage + Send + Sync>;
+
 pub struct ExclusivePush<S> {
     cache_manager: Arc<CacheManager>,
     subscribe_manager: Arc<SubscribeManager>,
     connection_manager: Arc<ConnectionManager>,
     message_storage: Arc<S>,
+    message_transformer: Option<MessageTransformer>,
+    // (topic_id, breaker of the storage reads of the topic)
+    circuit_breakers: Arc<DashMap<String, Arc<CircuitBreaker>>>,
+    failure_threshold: u32,
+    reset_timeout_secs: u64,
 }
 
 impl<S> ExclusivePush<S>
@@ -62,13 +94,46 @@ where
             cache_manager,
             subscribe_manager,
             connection_manager,
+            message_transformer: None,
+            circuit_breakers: Arc::new(DashMap::with_capacity(8)),
+            failure_threshold: PUSH_FAILURE_THRESHOLD,
+            reset_timeout_secs: PUSH_RESET_TIMEOUT_SECS,
         }
     }
 
+    pub fn with_message_transformer(mut self, message_transformer: MessageTransformer) -> Self {
+        self.message_transformer = Some(message_transformer);
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, reset_timeout_secs: u64) -> Self {
+        self.failure_threshold = failure_threshold;
+        self.reset_timeout_secs = reset_timeout_secs;
+        self
+    }
+
+    // The push threads of all the subscribers of a topic share the breaker of the topic.
+    fn circuit_breaker(&self, topic_id: &str) -> Arc<CircuitBreaker> {
+        self.circuit_breakers
+            .entry(topic_id.to_owned())
+            .or_insert_with(|| {
+                Arc::new(CircuitBreaker::new(
+                    self.failure_threshold,
+                    1,
+                    self.reset_timeout_secs,
+                ))
+            })
+            .clone()
+    }
+
     pub async fn start(&self) {
         loop {
             self.start_push_thread().await;
             self.try_thread_gc().await;
+            metrics_push_thread_num(
+                PUSH_THREAD_TYPE_EXCLUSIVE,
+                self.subscribe_manager.exclusive_push_thread.len(),
+            );
             sleep(Duration::from_secs(1)).await;
         }
     }
@@ -77,23 +142,32 @@ where
         // Periodically verify that a push task is running, but the subscribe task has stopped
         // If so, stop the process and clean up the data
         for (exclusive_key, sx) in self.subscribe_manager.exclusive_push_thread.clone() {
-            if !self
+            if self
                 .subscribe_manager
                 .exclusive_push
                 .contains_key(&exclusive_key)
-                && sx.send(true).is_ok()
             {
+                continue;
+            }
+
+            // A stopped thread removes its own entries, so that no other push thread of the
+            // subscription is started while it is still pushing. They are only removed here when
+            // the thread is already gone.
+            if sx.send(true).is_err() {
                 self.subscribe_manager
                     .exclusive_push_thread
                     .remove(&exclusive_key);
+                self.subscribe_manager
+                    .exclusive_push_drain_thread
+                    .remove(&exclusive_key);
             }
         }
     }
 
     // Handles exclusive subscription push tasks
     // Exclusively subscribed messages are pushed directly to the consuming client
-    async fn start_push_thread(&self) {
-        for (exclusive_key, subscriber) in self.subscribe_manager.exclusive_push.clone() {
+    pub async fn start_push_thread(&self) {
+        for (exclusive_key, mut subscriber) in self.subscribe_manager.exclusive_push.clone() {
             if self
                 .subscribe_manager
                 .exclusive_push_thread
@@ -103,24 +177,35 @@ where
             }
 
             let (sub_thread_stop_sx, mut sub_thread_stop_rx) = broadcast::channel(1);
+            let (sub_thread_drain_sx, mut sub_thread_drain_rx) = broadcast::channel(1);
 
             let message_storage = MessageStorage::new(self.message_storage.clone());
             let cache_manager = self.cache_manager.clone();
             let connection_manager = self.connection_manager.clone();
             let subscribe_manager = self.subscribe_manager.clone();
+            let message_transformer = self.message_transformer.clone();
+            let circuit_breaker = self.circuit_breaker(&subscriber.topic_id);
 
             // Subscribe to the data push thread
             self.subscribe_manager
                 .exclusive_push_thread
                 .insert(exclusive_key.clone(), sub_thread_stop_sx.clone());
+            self.subscribe_manager
+                .exclusive_push_drain_thread
+                .insert(exclusive_key.clone(), sub_thread_drain_sx);
 
-            tokio::spawn(async move {
+            let supervisor_subscribe_manager = self.subscribe_manager.clone();
+            let supervisor_exclusive_key = exclusive_key.clone();
+            let supervisor_topic_id = subscriber.topic_id.clone();
+            let mut supervisor_stop_rx = sub_thread_stop_sx.subscribe();
+
+            let push_thread = tokio::spawn(async move {
                 info!("Exclusive push thread for client_id [{}], sub_path: [{}], topic_id [{}] was started successfully",
                         subscriber.client_id, subscriber.sub_path, subscriber.topic_id);
 
                 let group_id = build_group_name(&subscriber);
-                let qos = build_pub_qos(&cache_manager, &subscriber);
-                let sub_ids = build_sub_ids(&subscriber);
+                let mut qos = build_pub_qos(&cache_manager, &subscriber);
+                let mut sub_ids = build_sub_ids(&subscriber);
 
                 let mut offset = match message_storage.get_group_offset(&group_id).await {
                     Ok(offset) => offset,
@@ -129,11 +214,108 @@ where
                         subscribe_manager
                             .exclusive_push_thread
                             .remove(&exclusive_key);
+                        subscribe_manager
+                            .exclusive_push_drain_thread
+                            .remove(&exclusive_key);
                         return;
                     }
                 };
+                subscribe_manager.flow_control_signal.report_offset(
+                    &subscriber.topic_id,
+                    &exclusive_key,
+                    offset,
+                );
+
+                let protocol = cache_manager.get_cluster_info().protocol;
+                let mut slow_start = SlowStart::new(
+                    protocol.slow_start_duration_secs,
+                    protocol.slow_start_initial_rate,
+                );
+                let mut reader_inflight = ReaderInflight::new(protocol.max_reader_inflight_records);
+                let mut delivery_rate = DeliveryRate::new(bound_delivery_rate(
+                    subscriber.max_delivery_rate,
+                    protocol.max_client_delivery_rate,
+                ));
 
                 loop {
+                    // The drain signal is only checked between batches, so that the batch being
+                    // pushed is not interrupted and its offset is committed before exiting.
+                    if let Ok(true) = sub_thread_drain_rx.try_recv() {
+                        info!(
+                            "Exclusive Push thread for client_id [{}], sub_path: [{}], topic_id [{}] was drained and stopped successfully",
+                            subscriber.client_id,
+                            subscriber.sub_path,
+                            subscriber.topic_id
+                        );
+
+                        subscribe_manager.exclusive_push.remove(&exclusive_key);
+                        subscribe_manager
+                            .exclusive_push_thread
+                            .remove(&exclusive_key);
+                        subscribe_manager
+                            .exclusive_push_drain_thread
+                            .remove(&exclusive_key);
+                        subscribe_manager
+                            .flow_control_signal
+                            .remove_consumer(&subscriber.topic_id, &exclusive_key);
+                        break;
+                    }
+
+                    // The QoS, the Subscription Identifier, the delivery rate or the publisher
+                    // allowlist of the subscription was changed by a re-subscribe
+                    let current = subscribe_manager
+                        .exclusive_push
+                        .get(&exclusive_key)
+                        .map(|sub| {
+                            (
+                                sub.qos,
+                                sub.subscription_identifier,
+                                sub.max_delivery_rate,
+                                sub.publisher_allowlist.clone(),
+                            )
+                        });
+                    if let Some((
+                        current_qos,
+                        current_identifier,
+                        current_rate,
+                        current_allowlist,
+                    )) = current
+                    {
+                        if current_identifier != subscriber.subscription_identifier {
+                            info!(
+                                "Exclusive push thread for client_id [{}], sub_path: [{}], topic_id [{}] changed Subscription Identifier from {:?} to {:?}",
+                                subscriber.client_id,
+                                subscriber.sub_path,
+                                subscriber.topic_id,
+                                subscriber.subscription_identifier,
+                                current_identifier
+                            );
+                            subscriber.subscription_identifier = current_identifier;
+                            sub_ids = build_sub_ids(&subscriber);
+                        }
+                        if current_qos != subscriber.qos {
+                            info!(
+                                "Exclusive push thread for client_id [{}], sub_path: [{}], topic_id [{}] changed QoS from {:?} to {:?}",
+                                subscriber.client_id,
+                                subscriber.sub_path,
+                                subscriber.topic_id,
+                                subscriber.qos,
+                                current_qos
+                            );
+                            subscriber.qos = current_qos;
+                            qos = build_pub_qos(&cache_manager, &subscriber);
+                        }
+                        subscriber.max_delivery_rate = current_rate;
+                        subscriber.publisher_allowlist = current_allowlist;
+                    }
+                    delivery_rate.set_rate(bound_delivery_rate(
+                        subscriber.max_delivery_rate,
+                        cache_manager
+                            .get_cluster_info()
+                            .protocol
+                            .max_client_delivery_rate,
+                    ));
+
                     select! {
                         val = sub_thread_stop_rx.recv() =>{
                             if let Ok(flag) = val {
@@ -146,6 +328,8 @@ where
                                     );
 
                                     subscribe_manager.exclusive_push_thread.remove(&exclusive_key);
+                                    subscribe_manager.exclusive_push_drain_thread.remove(&exclusive_key);
+                                    subscribe_manager.flow_control_signal.remove_consumer(&subscriber.topic_id, &exclusive_key);
                                     break;
                                 }
                             }
@@ -159,12 +343,22 @@ where
                                 &qos,
                                 &sub_ids,
                                 offset,
-                                &sub_thread_stop_sx
+                                &sub_thread_stop_sx,
+                                &message_transformer,
+                                &circuit_breaker,
+                                &mut slow_start,
+                                &mut delivery_rate,
+                                &mut reader_inflight
                             ) => {
                                 match val{
                                     Ok(offset_op) => {
                                         if let Some(off) = offset_op{
                                             offset = off + 1;
+                                            subscribe_manager.flow_control_signal.report_offset(
+                                                &subscriber.topic_id,
+                                                &exclusive_key,
+                                                offset,
+                                            );
                                         }else{
                                             sleep(Duration::from_millis(100)).await;
                                         }
@@ -183,6 +377,30 @@ where
                     }
                 }
             });
+
+            // A thread that exits normally has already removed itself. A thread that panicked
+            // is removed here unless it was being stopped, so that start_push_thread restarts it.
+            tokio::spawn(push_thread.map(move |r| {
+                if let Err(e) = r {
+                    error!("push thread panicked: {:?}", e);
+                    supervisor_subscribe_manager
+                        .flow_control_signal
+                        .remove_consumer(&supervisor_topic_id, &supervisor_exclusive_key);
+                    if let Ok(true) = supervisor_stop_rx.try_recv() {
+                        return;
+                    }
+                    warn!(
+                        "Exclusive push thread [{}] exited unexpectedly and will be restarted",
+                        supervisor_exclusive_key
+                    );
+                    supervisor_subscribe_manager
+                        .exclusive_push_thread
+                        .remove(&supervisor_exclusive_key);
+                    supervisor_subscribe_manager
+                        .exclusive_push_drain_thread
+                        .remove(&supervisor_exclusive_key);
+                }
+            }));
         }
     }
 }
@@ -198,78 +416,181 @@ async fn pub_message<S>(
     sub_ids: &[usize],
     offset: u64,
     sub_thread_stop_sx: &broadcast::Sender<bool>,
+    message_transformer: &Option<MessageTransformer>,
+    circuit_breaker: &CircuitBreaker,
+    slow_start: &mut SlowStart,
+    delivery_rate: &mut DeliveryRate,
+    reader_inflight: &mut ReaderInflight,
 ) -> Result<Option<u64>, MqttBrokerError>
 where
     S: StorageAdapter + Sync + Send + 'static + Clone,
 {
-    let record_num = 5;
+    let concurrency = cache_manager
+        .get_cluster_info()
+        .protocol
+        .delivery_concurrency
+        .max(1) as usize;
+    let record_num = concurrency.max(5) as u64;
     let client_id = subscriber.client_id.clone();
 
-    let results = message_storage
+    // The reads of the topic keep failing, e.g. a corrupt segment, wait for the breaker to reset
+    if !circuit_breaker.allow_request() {
+        sleep(Duration::from_secs(1)).await;
+        return Ok(None);
+    }
+
+    // A new subscription is not sent more of its backlog than the slow start allows
+    let record_num = slow_start.allowance(record_num).await;
+
+    // Nor more than the rate the client asked for in its SUBSCRIBE
+    let record_num = delivery_rate.allowance(record_num).await;
+
+    // Reads no more records than the bound of the records held in memory allows
+    let record_num = reader_inflight.available(record_num);
+    if record_num == 0 {
+        return Ok(None);
+    }
+
+    let mut results = match message_storage
         .read_topic_message(&subscriber.topic_id, offset, record_num)
-        .await?;
+        .await
+    {
+        Ok(results) => {
+            circuit_breaker.record_success();
+            slow_start.consume(results.len() as u64);
+            delivery_rate.consume(results.len() as u64);
+            results
+        }
+        Err(e) => {
+            let state = circuit_breaker.state();
+            circuit_breaker.record_failure();
+            if state != CircuitState::Open && circuit_breaker.state() == CircuitState::Open {
+                warn!(
+                    "Reading the messages of topic_id [{}] failed repeatedly, the delivery of the topic is paused, error: {}",
+                    subscriber.topic_id, e
+                );
+                metrics_push_circuit_open(&subscriber.topic_id);
+            }
+            return Err(e.into());
+        }
+    };
 
     if results.is_empty() {
         return Ok(None);
     }
+    order_record_offsets(
+        &subscriber.topic_id,
+        &mut results,
+        |record| record.offset.unwrap_or_default(),
+        cache_manager
+            .get_cluster_info()
+            .protocol
+            .strict_record_offset_order,
+    )?;
+    // The records are released when the batch is pushed, or when the push fails or is stopped
+    let _held_records = reader_inflight.hold(results.len() as u64);
 
-    for record in results.iter() {
-        let record_offset = record.offset.unwrap();
+    // The messages are skipped while the client is not authorized to receive them
+    if !is_allow_delivery(cache_manager, &client_id, &subscriber.topic_name) {
+        warn!(
+            "client_id [{}] is not authorized to receive messages of topic [{}], skipped",
+            client_id, subscriber.topic_name
+        );
+        record_message_dropped(MessageDropReason::AuthDenied, *qos, results.len() as u64);
+        if cache_manager
+            .get_cluster_info()
+            .security
+            .delivery_acl_deny_disconnect
+        {
+            disconnect_unauthorized_subscriber(cache_manager, connection_manager, &client_id).await;
+        }
+        let last_offset = results.last().unwrap().offset.unwrap();
+        loop_commit_offset(message_storage, &subscriber.topic_id, group_id, last_offset).await;
+        return Ok(Some(last_offset));
+    }
 
-        // build publish params
-        let sub_pub_param = if let Some(params) = build_pub_message(
+    // (record offset, publish params)
+    let mut messages = Vec::with_capacity(results.len());
+    for record in results.iter() {
+        if let Some(params) = build_pub_message(
             record.to_owned(),
             group_id,
             qos,
             subscriber,
             cache_manager,
             sub_ids,
+            message_transformer,
         )
         .await?
         {
-            params
-        } else {
-            continue;
-        };
+            messages.push((record.offset.unwrap(), params));
+        }
+    }
 
-        let pkid = sub_pub_param.pkid;
-        match qos {
-            QoS::AtMostOnce => {
-                publish_message_qos0(
-                    cache_manager,
-                    connection_manager,
-                    &sub_pub_param,
-                    sub_thread_stop_sx,
-                )
+    match qos {
+        // QoS 0 messages have no ordering guarantee, so they are all written concurrently
+        QoS::AtMostOnce => {
+            stream::iter(messages.iter())
+                .for_each_concurrent(concurrency, |(_, sub_pub_param)| {
+                    publish_message_qos0(
+                        cache_manager,
+                        connection_manager,
+                        sub_pub_param,
+                        sub_thread_stop_sx,
+                    )
+                })
                 .await;
-            }
+        }
 
-            QoS::AtLeastOnce => {
-                let (wait_puback_sx, _) = broadcast::channel(1);
-                cache_manager.add_ack_packet(
-                    &client_id,
-                    pkid,
-                    QosAckPacketInfo {
-                        sx: wait_puback_sx.clone(),
-                        create_time: now_second(),
-                    },
-                );
+        // The in-flight QoS 1 messages are bounded by the Receive Maximum of the client
+        QoS::AtLeastOnce => {
+            let window = cache_manager
+                .get_connect_id(&client_id)
+                .and_then(|connect_id| cache_manager.get_connection(connect_id))
+                .map(|conn| concurrency.min(conn.receive_maximum.max(1) as usize))
+                .unwrap_or(concurrency);
 
-                exclusive_publish_message_qos1(
+            let mut last_offset = None;
+            for chunk in messages.chunks(window) {
+                let sub_pub_params: Vec<SubPublishParam> = chunk
+                    .iter()
+                    .map(|(_, sub_pub_param)| sub_pub_param.clone())
+                    .collect();
+                let settled = exclusive_publish_messages_qos1(
                     cache_manager,
                     connection_manager,
-                    &sub_pub_param,
+                    &sub_pub_params,
                     sub_thread_stop_sx,
-                    &wait_puback_sx,
+                    QOS1_ACK_TIMEOUT,
                 )
-                .await?;
+                .await;
 
-                cache_manager.remove_pkid_info(&client_id, pkid);
-                cache_manager.remove_ack_packet(&client_id, pkid);
+                // Only the offset of the messages acked without a gap is committed
+                if settled > 0 {
+                    let record_offset = chunk[settled - 1].0;
+                    loop_commit_offset(
+                        message_storage,
+                        &subscriber.topic_id,
+                        group_id,
+                        record_offset,
+                    )
+                    .await;
+                    last_offset = Some(record_offset);
+                }
+
+                // The messages not acked are delivered again when the push restarts
+                if settled < chunk.len() {
+                    return Ok(last_offset);
+                }
             }
+        }
 
-            QoS::ExactlyOnce => {
-                let (wait_ack_sx, _) = broadcast::channel(1);
+        // QoS 2 messages are delivered one at a time
+        QoS::ExactlyOnce => {
+            let mut last_offset = None;
+            for (record_offset, sub_pub_param) in messages.iter() {
+                let pkid = sub_pub_param.pkid;
+                let (wait_ack_sx, _wait_ack_rx) = broadcast::channel(1);
                 cache_manager.add_ack_packet(
                     &client_id,
                     pkid,
@@ -279,31 +600,47 @@ where
                     },
                 );
 
-                exclusive_publish_message_qos2(
+                let completed = exclusive_publish_message_qos2(
                     cache_manager,
                     connection_manager,
-                    &sub_pub_param,
+                    sub_pub_param,
                     sub_thread_stop_sx,
                     &wait_ack_sx,
+                    QOS2_ACK_TIMEOUT,
                 )
                 .await?;
 
                 cache_manager.remove_pkid_info(&client_id, pkid);
                 cache_manager.remove_ack_packet(&client_id, pkid);
+
+                // The message is delivered again when the push restarts
+                if !completed {
+                    return Ok(last_offset);
+                }
+
+                loop_commit_offset(
+                    message_storage,
+                    &subscriber.topic_id,
+                    group_id,
+                    *record_offset,
+                )
+                .await;
+                last_offset = Some(*record_offset);
             }
         }
-
-        // commit offset
-        loop_commit_offset(
-            message_storage,
-            &subscriber.topic_id,
-            group_id,
-            record_offset,
-        )
-        .await;
     }
 
-    Ok(Some(results.last().unwrap().offset.unwrap()))
+    // Every record read is delivered or filtered out, the records filtered out after the last
+    // message delivered are committed as well, so that they are not read again after a restart
+    let last_offset = results.last().unwrap().offset.unwrap();
+    let committed_offset = match qos {
+        QoS::AtMostOnce => None,
+        _ => messages.last().map(|(record_offset, _)| *record_offset),
+    };
+    if committed_offset != Some(last_offset) {
+        loop_commit_offset(message_storage, &subscriber.topic_id, group_id, last_offset).await;
+    }
+    Ok(Some(last_offset))
 }
 
 async fn build_pub_message(
@@ -313,42 +650,37 @@ async fn build_pub_message(
     subscriber: &Subscriber,
     cache_manager: &Arc<CacheManager>,
     sub_ids: &[usize],
+    message_transformer: &Option<MessageTransformer>,
 ) -> Result<Option<SubPublishParam>, MqttBrokerError> {
-    let msg = MqttMessage::decode_record(record.clone())?;
+    let mut msg = MqttMessage::decode_record(record.clone())?;
+    msg.payload = match decrypt_message_payload(cache_manager, &subscriber.topic_name, &msg.payload)
+    {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!(
+                "message of topic {} is not pushed to the client and is discarded, {}",
+                subscriber.topic_name, e
+            );
+            record_message_dropped(MessageDropReason::Quarantined, *qos, 1);
+            return Ok(None);
+        }
+    };
+    if let Some(transformer) = message_transformer {
+        msg = transformer(msg);
+    }
 
     if is_message_expire(&msg) {
         debug!("message expires, is not pushed to the client, and is discarded");
+        record_message_dropped(MessageDropReason::Expired, *qos, 1);
         return Ok(None);
     }
 
-    if subscriber.nolocal && (subscriber.client_id == msg.client_id) {
+    let Some((mut publish, properties)) = DeliveryBuilder::new(subscriber)
+        .max_qos(*qos)
+        .sub_ids(sub_ids)
+        .build(msg)
+    else {
         return Ok(None);
-    }
-
-    let retain = if subscriber.preserve_retain {
-        msg.retain
-    } else {
-        false
-    };
-
-    let mut publish = Publish {
-        dup: false,
-        qos: qos.to_owned(),
-        pkid: 0,
-        retain,
-        topic: Bytes::from(subscriber.topic_name.clone()),
-        payload: msg.payload,
-    };
-
-    let properties = PublishProperties {
-        payload_format_indicator: msg.format_indicator,
-        message_expiry_interval: Some(msg.expiry_interval as u32),
-        topic_alias: None,
-        response_topic: msg.response_topic,
-        correlation_data: msg.correlation_data,
-        user_properties: msg.user_properties,
-        subscription_identifiers: sub_ids.into(),
-        content_type: msg.content_type,
     };
 
     let pkid = if *qos != QoS::AtMostOnce {
@@ -379,12 +711,116 @@ pub async fn exclusive_publish_message_qos1(
     stop_sx: &broadcast::Sender<bool>,
     wait_puback_sx: &broadcast::Sender<QosAckPackageData>,
 ) -> Result<(), MqttBrokerError> {
-    let mut retry_times = 0;
-    let mut publish = sub_pub_param.publish.clone();
+    let mut wait_puback_rx = wait_puback_sx.subscribe();
+    if qos1_send_publish(
+        metadata_cache,
+        connection_manager,
+        sub_pub_param,
+        &sub_pub_param.publish,
+        stop_sx,
+    )
+    .await
+        != Qos1Send::Sent
+    {
+        return Ok(());
+    }
+    qos1_wait_puback(
+        metadata_cache,
+        connection_manager,
+        sub_pub_param,
+        stop_sx,
+        &mut wait_puback_rx,
+        QOS1_ACK_TIMEOUT,
+    )
+    .await;
+    Ok(())
+}
+
+// Sends the PUBLISH of every message in order, then waits for their PUBACKs together,
+// so that the messages are in flight at the same time but still delivered in order.
+// Returns how many of the first messages are settled, acked or dropped for good, without a gap,
+// the offsets of the others must not be committed.
+pub async fn exclusive_publish_messages_qos1(
+    metadata_cache: &Arc<CacheManager>,
+    connection_manager: &Arc<ConnectionManager>,
+    sub_pub_params: &[SubPublishParam],
+    stop_sx: &broadcast::Sender<bool>,
+    ack_timeout: Duration,
+) -> usize {
+    let mut settled = vec![false; sub_pub_params.len()];
+    let mut in_flight = Vec::with_capacity(sub_pub_params.len());
+    for (i, sub_pub_param) in sub_pub_params.iter().enumerate() {
+        let (wait_puback_sx, wait_puback_rx) = broadcast::channel(1);
+        metadata_cache.add_ack_packet(
+            &sub_pub_param.subscribe.client_id,
+            sub_pub_param.pkid,
+            QosAckPacketInfo {
+                sx: wait_puback_sx,
+                create_time: now_second(),
+            },
+        );
+
+        match qos1_send_publish(
+            metadata_cache,
+            connection_manager,
+            sub_pub_param,
+            &sub_pub_param.publish,
+            stop_sx,
+        )
+        .await
+        {
+            Qos1Send::Sent => in_flight.push((i, sub_pub_param, wait_puback_rx)),
+            Qos1Send::Dropped => settled[i] = true,
+            // The messages after it are not sent either
+            Qos1Send::Stopped => break,
+        }
+    }
+
+    let acked = join_all(in_flight.iter_mut().map(
+        |(i, sub_pub_param, wait_puback_rx)| async move {
+            let acked = qos1_wait_puback(
+                metadata_cache,
+                connection_manager,
+                sub_pub_param,
+                stop_sx,
+                wait_puback_rx,
+                ack_timeout,
+            )
+            .await;
+            (*i, acked)
+        },
+    ))
+    .await;
+    for (i, acked) in acked {
+        settled[i] = acked;
+    }
+
+    for sub_pub_param in sub_pub_params {
+        metadata_cache.remove_pkid_info(&sub_pub_param.subscribe.client_id, sub_pub_param.pkid);
+        metadata_cache.remove_ack_packet(&sub_pub_param.subscribe.client_id, sub_pub_param.pkid);
+    }
+    settled.iter().take_while(|settled| **settled).count()
+}
+
+#[derive(Debug, PartialEq)]
+enum Qos1Send {
+    Sent,
+    // The message is too large for the client and is never delivered
+    Dropped,
+    Stopped,
+}
+
+async fn qos1_send_publish(
+    metadata_cache: &Arc<CacheManager>,
+    connection_manager: &Arc<ConnectionManager>,
+    sub_pub_param: &SubPublishParam,
+    publish: &Publish,
+    stop_sx: &broadcast::Sender<bool>,
+) -> Qos1Send {
     loop {
         if let Ok(flag) = stop_sx.subscribe().try_recv() {
             if flag {
-                return Ok(());
+                return Qos1Send::Stopped;
             }
         }
 
@@ -398,13 +834,11 @@ pub async fn exclusive_publish_message_qos1(
 
         if let Some(conn) = metadata_cache.get_connection(connect_id) {
             if publish.payload.len() > (conn.max_packet_size as usize) {
-                return Ok(());
+                record_message_dropped(MessageDropReason::Oversized, publish.qos, 1);
+                return Qos1Send::Dropped;
             }
         }
 
-        retry_times += 1;
-        publish.dup = retry_times >= 2;
-
         let mut contain_properties = false;
         if let Some(protocol) = connection_manager.get_connect_protocol(connect_id) {
             if MqttProtocol::is_mqtt5(&protocol) {
@@ -416,25 +850,20 @@ pub async fn exclusive_publish_message_qos1(
             ResponsePackage {
                 connection_id: connect_id,
                 packet: MqttPacket::Publish(publish.clone(), sub_pub_param.properties.clone()),
+                close_after_send: false,
             }
         } else {
             ResponsePackage {
                 connection_id: connect_id,
                 packet: MqttPacket::Publish(publish.clone(), None),
+                close_after_send: false,
             }
         };
 
         match publish_message_to_client(resp, sub_pub_param, connection_manager, metadata_cache)
             .await
         {
-            Ok(_) => {
-                if let Some(data) = wait_packet_ack(wait_puback_sx).await {
-                    if data.ack_type == QosAckPackageType::PubAck && data.pkid == sub_pub_param.pkid
-                    {
-                        return Ok(());
-                    }
-                }
-            }
+            Ok(_) => return Qos1Send::Sent,
             Err(e) => {
                 error!(
                     "Failed to write QOS1 Publish message to response queue, failure message: {}",
@@ -446,35 +875,105 @@ pub async fn exclusive_publish_message_qos1(
     }
 }
 
+// Waits for the PUBACK of the message, the publish is sent again with DUP when it times out.
+// Returns false if the push is stopped before the message is acked.
+async fn qos1_wait_puback(
+    metadata_cache: &Arc<CacheManager>,
+    connection_manager: &Arc<ConnectionManager>,
+    sub_pub_param: &SubPublishParam,
+    stop_sx: &broadcast::Sender<bool>,
+    wait_puback_rx: &mut broadcast::Receiver<QosAckPackageData>,
+    ack_timeout: Duration,
+) -> bool {
+    let mut stop_rx = stop_sx.subscribe();
+    let first_sent = Instant::now();
+    let mut redelivery = sub_pub_param.clone();
+    redelivery.publish.dup = delivery_dup(redelivery.publish.qos, true);
+    loop {
+        select! {
+            val = stop_rx.recv() => {
+                if let Ok(true) = val {
+                    return false;
+                }
+            }
+            val = wait_packet_ack_timeout(wait_puback_rx, ack_timeout) => {
+                match val {
+                    Some(data) => {
+                        if data.ack_type == QosAckPackageType::PubAck
+                            && data.pkid == sub_pub_param.pkid
+                        {
+                            return true;
+                        }
+                    }
+                    None => {
+                        redelivery.properties =
+                            redelivery_properties(&sub_pub_param.properties, first_sent);
+                        match qos1_send_publish(
+                            metadata_cache,
+                            connection_manager,
+                            &redelivery,
+                            &redelivery.publish,
+                            stop_sx,
+                        )
+                        .await
+                        {
+                            Qos1Send::Sent => {}
+                            Qos1Send::Dropped => return true,
+                            Qos1Send::Stopped => return false,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 // send publish message
-// wait pubrec message
+// wait pubrec message, the publish is sent again with DUP when it times out
 // send pubrel message
-// wait pubcomp message
+// wait pubcomp message, the pubrel is sent again when it times out
+// Returns false if the push is stopped before the PUBCOMP is received,
+// in which case the offset of the message must not be committed.
 pub async fn exclusive_publish_message_qos2(
     metadata_cache: &Arc<CacheManager>,
     connection_manager: &Arc<ConnectionManager>,
     sub_pub_param: &SubPublishParam,
     stop_sx: &broadcast::Sender<bool>,
     wait_ack_sx: &broadcast::Sender<QosAckPackageData>,
-) -> Result<(), MqttBrokerError> {
+    ack_timeout: Duration,
+) -> Result<bool, MqttBrokerError> {
+    let mut stop_rx = stop_sx.subscribe();
+    let mut ack_rx = wait_ack_sx.subscribe();
+
     // 1. send Publish to Client
     qos2_send_publish(connection_manager, metadata_cache, sub_pub_param, stop_sx).await?;
+    let first_sent = Instant::now();
 
     // 2. wait PubRec ack
+    let mut redelivery = sub_pub_param.clone();
+    redelivery.publish.dup = delivery_dup(redelivery.publish.qos, true);
     loop {
-        if let Ok(flag) = stop_sx.subscribe().try_recv() {
-            if flag {
-                return Ok(());
+        select! {
+            val = stop_rx.recv() => {
+                if let Ok(flag) = val {
+                    if flag {
+                        return Ok(false);
+                    }
+                }
             }
-        }
-        if let Some(data) = wait_packet_ack(wait_ack_sx).await {
-            if data.ack_type == QosAckPackageType::PubRec && data.pkid == sub_pub_param.pkid {
-                break;
+            val = wait_packet_ack_timeout(&mut ack_rx, ack_timeout) => {
+                if let Some(data) = val {
+                    if data.ack_type == QosAckPackageType::PubRec && data.pkid == sub_pub_param.pkid {
+                        break;
+                    }
+                } else {
+                    redelivery.properties =
+                        redelivery_properties(&sub_pub_param.properties, first_sent);
+                    qos2_send_publish(connection_manager, metadata_cache, &redelivery, stop_sx)
+                        .await?;
+                }
             }
-        } else {
-            qos2_send_publish(connection_manager, metadata_cache, sub_pub_param, stop_sx).await?;
         }
-        sleep(Duration::from_millis(1)).await;
     }
 
     // 3. send PubRel to Client
@@ -482,30 +981,42 @@ pub async fn exclusive_publish_message_qos2(
 
     // 4. wait pub comp
     loop {
-        if let Ok(flag) = stop_sx.subscribe().try_recv() {
-            if flag {
-                break;
+        select! {
+            val = stop_rx.recv() => {
+                if let Ok(flag) = val {
+                    if flag {
+                        return Ok(false);
+                    }
+                }
             }
-        }
-        if let Some(data) = wait_packet_ack(wait_ack_sx).await {
-            if data.ack_type == QosAckPackageType::PubComp && data.pkid == sub_pub_param.pkid {
-                break;
+            val = wait_packet_ack_timeout(&mut ack_rx, ack_timeout) => {
+                if let Some(data) = val {
+                    if data.ack_type == QosAckPackageType::PubComp && data.pkid == sub_pub_param.pkid {
+                        break;
+                    }
+                } else {
+                    qos2_send_pubrel(metadata_cache, sub_pub_param, connection_manager, stop_sx)
+                        .await;
+                }
             }
-        } else {
-            qos2_send_pubrel(metadata_cache, sub_pub_param, connection_manager, stop_sx).await;
         }
-        sleep(Duration::from_millis(1)).await;
     }
-    Ok(())
+    Ok(true)
 }
 
 fn build_group_name(subscriber: &Subscriber) -> String {
-    format!(
-        "system_sub_{}_{}_{}",
-        subscriber.client_id, subscriber.sub_path, subscriber.topic_id
+    exclusive_group_name(
+        &subscriber.client_id,
+        &subscriber.sub_path,
+        &subscriber.topic_id,
     )
 }
 
+// The consumer group whose offset records the progress of the push of a subscription
+pub fn exclusive_group_name(client_id: &str, sub_path: &str, topic_id: &str) -> String {
+    format!("system_sub_{}_{}_{}", client_id, sub_path, topic_id)
+}
+
 fn build_pub_qos(cache_manager: &Arc<CacheManager>, subscriber: &Subscriber) -> QoS {
     let cluster_qos = cache_manager.get_cluster_info().protocol.max_qos;
     min_qos(cluster_qos, subscriber.qos)
@@ -520,4 +1031,1551 @@ fn build_sub_ids(subscriber: &Subscriber) -> Vec<usize> {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use common_base::tools::circuit_breaker::{CircuitBreaker, CircuitState};
+    use common_base::tools::{now_second, unique_id};
+    use delay_message::DelayMessageManager;
+    use futures::StreamExt;
+    use metadata_struct::acl::mqtt_acl::{
+        MqttAcl, MqttAclAction, MqttAclPermission, MqttAclResourceType,
+    };
+    use metadata_struct::adapter::record::Record;
+    use metadata_struct::mqtt::connection::{ConnectionConfig, MQTTConnection};
+    use metadata_struct::mqtt::message::MqttMessage;
+    use metadata_struct::mqtt::topic::MqttTopic;
+    use protocol::mqtt::common::{
+        Filter, MqttPacket, MqttProtocol, Publish, PublishProperties, QoS, RetainForwardRule,
+        SubscribeProperties,
+    };
+    use storage_adapter::memory::MemoryStorageAdapter;
+    use storage_adapter::storage::StorageAdapter;
+    use tokio::sync::broadcast;
+    use tokio::time::{sleep, timeout};
+
+    use super::{
+        build_group_name, build_pub_qos, build_sub_ids, exclusive_publish_message_qos2,
+        exclusive_publish_messages_qos1, pub_message,
+    };
+    use crate::handler::cache::{QosAckPackageData, QosAckPackageType};
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::handler::offline_message::save_message;
+    use crate::handler::subscribe::parse_subscribe;
+    use crate::observability::metrics::publish::{get_message_dropped_num, MessageDropReason};
+    use crate::observability::metrics::server::get_push_circuit_open_num;
+    use crate::storage::message::cluster_name;
+    use crate::subscribe::delivery_rate::{bound_delivery_rate, DeliveryRate};
+    use crate::subscribe::publisher_allowlist::PUBLISHER_ALLOWLIST_USER_PROPERTY;
+    use crate::subscribe::push_fixture::{
+        build_push_fixture, build_record, read_packet, PushFixture,
+    };
+    use crate::subscribe::reader_inflight::ReaderInflight;
+    use crate::subscribe::slow_start::SlowStart;
+    use crate::subscribe::subscriber::{SubPublishParam, Subscriber};
+
+    // Reads and pushes the next batch of the subscription from the offset, and returns the
+    // offset of the last message of the batch.
+    async fn pub_next_batch(
+        fixture: &PushFixture<MemoryStorageAdapter>,
+        subscriber: &Subscriber,
+        offset: u64,
+        slow_start: &mut SlowStart,
+        delivery_rate: &mut DeliveryRate,
+    ) -> Option<u64> {
+        let (stop_sx, _) = broadcast::channel(1);
+        pub_message(
+            &fixture.connection_manager,
+            &fixture.message_storage,
+            &fixture.cache_manager,
+            subscriber,
+            &build_group_name(subscriber),
+            &build_pub_qos(&fixture.cache_manager, subscriber),
+            &build_sub_ids(subscriber),
+            offset,
+            &stop_sx,
+            &None,
+            &CircuitBreaker::new(3, 1, 60),
+            slow_start,
+            delivery_rate,
+            &mut ReaderInflight::new(0),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn exclusive_push_qos2_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let sub_path = "/test/qos2".to_string();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, vec![build_record(&sub_path, 0)])
+            .await
+            .unwrap();
+
+        let subscriber =
+            fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::ExactlyOnce);
+        let group_name = build_group_name(&subscriber);
+        fixture.start_exclusive_push().await;
+
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert!(!publish.dup);
+        assert_eq!(publish.qos, QoS::ExactlyOnce);
+        fixture
+            .cache_manager
+            .get_ack_packet(client_id.clone(), publish.pkid)
+            .unwrap()
+            .sx
+            .send(QosAckPackageData {
+                ack_type: QosAckPackageType::PubRec,
+                pkid: publish.pkid,
+            })
+            .unwrap();
+
+        let MqttPacket::PubRel(pubrel, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBREL");
+        };
+        assert_eq!(pubrel.pkid, publish.pkid);
+        // the offset is only committed once the PUBCOMP is received
+        assert!(fixture
+            .storage_adapter
+            .get_offset_by_group(group_name.clone())
+            .await
+            .unwrap()
+            .is_empty());
+
+        fixture
+            .cache_manager
+            .get_ack_packet(client_id.clone(), publish.pkid)
+            .unwrap()
+            .sx
+            .send(QosAckPackageData {
+                ack_type: QosAckPackageType::PubComp,
+                pkid: publish.pkid,
+            })
+            .unwrap();
+
+        timeout(Duration::from_secs(10), async {
+            while fixture
+                .storage_adapter
+                .get_offset_by_group(group_name.clone())
+                .await
+                .unwrap()
+                .is_empty()
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let offset = fixture
+            .message_storage
+            .get_group_offset(&group_name)
+            .await
+            .unwrap();
+        assert_eq!(offset, 0);
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn qos2_pubrec_timeout_redelivery_test() {
+        let fixture = build_push_fixture();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let subscriber = Subscriber {
+            client_id: client_id.clone(),
+            sub_path: "/test/qos2/redelivery".to_string(),
+            topic_name: "/test/qos2/redelivery".to_string(),
+            qos: QoS::ExactlyOnce,
+            ..Default::default()
+        };
+        let publish = Publish {
+            dup: false,
+            qos: QoS::ExactlyOnce,
+            pkid: 1,
+            retain: false,
+            topic: Bytes::from(subscriber.topic_name.clone()),
+            payload: Bytes::from("message"),
+        };
+        let sub_pub_param = SubPublishParam::new(subscriber, publish, None, 0, unique_id(), 1);
+
+        let (stop_sx, _) = broadcast::channel(1);
+        let (wait_ack_sx, _) = broadcast::channel(1);
+        let push_cache_manager = fixture.cache_manager.clone();
+        let push_connection_manager = fixture.connection_manager.clone();
+        let push_wait_ack_sx = wait_ack_sx.clone();
+        let handle = tokio::spawn(async move {
+            exclusive_publish_message_qos2(
+                &push_cache_manager,
+                &push_connection_manager,
+                &sub_pub_param,
+                &stop_sx,
+                &push_wait_ack_sx,
+                Duration::from_secs(1),
+            )
+            .await
+        });
+
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert!(!publish.dup);
+
+        // no PUBREC within the timeout, the message is delivered again with DUP
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a redelivered PUBLISH");
+        };
+        assert!(publish.dup);
+        assert_eq!(publish.pkid, 1);
+
+        wait_ack_sx
+            .send(QosAckPackageData {
+                ack_type: QosAckPackageType::PubRec,
+                pkid: 1,
+            })
+            .unwrap();
+        let MqttPacket::PubRel(pubrel, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBREL");
+        };
+        assert_eq!(pubrel.pkid, 1);
+
+        wait_ack_sx
+            .send(QosAckPackageData {
+                ack_type: QosAckPackageType::PubComp,
+                pkid: 1,
+            })
+            .unwrap();
+        let completed = timeout(Duration::from_secs(10), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(completed);
+    }
+
+    #[tokio::test]
+    async fn qos1_puback_timeout_redelivery_test() {
+        let fixture = build_push_fixture();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let subscriber = Subscriber {
+            client_id: client_id.clone(),
+            sub_path: "/test/qos1/redelivery".to_string(),
+            topic_name: "/test/qos1/redelivery".to_string(),
+            qos: QoS::AtLeastOnce,
+            ..Default::default()
+        };
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid: 1,
+            retain: false,
+            topic: Bytes::from(subscriber.topic_name.clone()),
+            payload: Bytes::from("message"),
+        };
+        let sub_pub_param = SubPublishParam::new(subscriber, publish, None, 0, unique_id(), 1);
+
+        let (stop_sx, _) = broadcast::channel(1);
+        let push_cache_manager = fixture.cache_manager.clone();
+        let push_connection_manager = fixture.connection_manager.clone();
+        let handle = tokio::spawn(async move {
+            exclusive_publish_messages_qos1(
+                &push_cache_manager,
+                &push_connection_manager,
+                &[sub_pub_param],
+                &stop_sx,
+                Duration::from_secs(1),
+            )
+            .await
+        });
+
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert!(!publish.dup);
+
+        // no PUBACK within the timeout, the message is delivered again with DUP
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a redelivered PUBLISH");
+        };
+        assert!(publish.dup);
+        assert_eq!(publish.pkid, 1);
+
+        fixture
+            .cache_manager
+            .get_ack_packet(client_id.clone(), 1)
+            .unwrap()
+            .sx
+            .send(QosAckPackageData {
+                ack_type: QosAckPackageType::PubAck,
+                pkid: 1,
+            })
+            .unwrap();
+        timeout(Duration::from_secs(10), handle)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn qos1_stopped_before_puback_test() {
+        let fixture = build_push_fixture();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let subscriber = Subscriber {
+            client_id: client_id.clone(),
+            sub_path: "/test/qos1/stopped".to_string(),
+            topic_name: "/test/qos1/stopped".to_string(),
+            qos: QoS::AtLeastOnce,
+            ..Default::default()
+        };
+        let sub_pub_params: Vec<SubPublishParam> = (1..=3)
+            .map(|pkid| {
+                let publish = Publish {
+                    dup: false,
+                    qos: QoS::AtLeastOnce,
+                    pkid,
+                    retain: false,
+                    topic: Bytes::from(subscriber.topic_name.clone()),
+                    payload: Bytes::from(format!("message-{}", pkid)),
+                };
+                SubPublishParam::new(subscriber.clone(), publish, None, 0, unique_id(), pkid)
+            })
+            .collect();
+
+        let (stop_sx, _) = broadcast::channel(1);
+        let push_cache_manager = fixture.cache_manager.clone();
+        let push_connection_manager = fixture.connection_manager.clone();
+        let push_stop_sx = stop_sx.clone();
+        let handle = tokio::spawn(async move {
+            exclusive_publish_messages_qos1(
+                &push_cache_manager,
+                &push_connection_manager,
+                &sub_pub_params,
+                &push_stop_sx,
+                Duration::from_secs(30),
+            )
+            .await
+        });
+
+        for _ in 1..=3 {
+            let MqttPacket::Publish(_, _) = read_packet(&mut read_stream).await else {
+                panic!("expected a PUBLISH");
+            };
+        }
+
+        // the first and the third messages are acked, the second is not
+        for pkid in [1, 3] {
+            fixture
+                .cache_manager
+                .get_ack_packet(client_id.clone(), pkid)
+                .unwrap()
+                .sx
+                .send(QosAckPackageData {
+                    ack_type: QosAckPackageType::PubAck,
+                    pkid,
+                })
+                .unwrap();
+        }
+        sleep(Duration::from_millis(100)).await;
+        stop_sx.send(true).unwrap();
+
+        // only the first message may be committed
+        let settled = timeout(Duration::from_secs(10), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(settled, 1);
+    }
+
+    #[tokio::test]
+    async fn drain_stop_exclusive_push_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let sub_path = "/test/drain".to_string();
+        let client_id = fixture.add_session(1);
+
+        let records = (0..3).map(|i| build_record(&sub_path, i)).collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+
+        let subscriber =
+            fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtLeastOnce);
+        fixture.start_exclusive_push().await;
+
+        // wait until the first message of the batch is waiting for its PUBACK
+        timeout(Duration::from_secs(10), async {
+            while fixture
+                .cache_manager
+                .get_ack_packet(client_id.clone(), 1)
+                .is_none()
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert!(fixture
+            .subscribe_manager
+            .drain_stop_exclusive_push(&client_id, &sub_path, &topic_id));
+
+        // the thread keeps delivering the in-flight batch and exits once it is committed
+        timeout(Duration::from_secs(10), async {
+            while !fixture.subscribe_manager.exclusive_push_thread.is_empty() {
+                if let Some(ack) = fixture.cache_manager.get_ack_packet(client_id.clone(), 1) {
+                    let _ = ack.sx.send(QosAckPackageData {
+                        ack_type: QosAckPackageType::PubAck,
+                        pkid: 1,
+                    });
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let offset = fixture
+            .message_storage
+            .get_group_offset(&build_group_name(&subscriber))
+            .await
+            .unwrap();
+        assert_eq!(offset, 2);
+        assert!(fixture.subscribe_manager.exclusive_push.is_empty());
+        assert!(fixture
+            .subscribe_manager
+            .exclusive_push_drain_thread
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn multiple_subscriptions_per_client_test() {
+        let fixture = build_push_fixture();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        // the push threads are keyed by (client_id, sub_path, topic_id)
+        let mut sub_paths = HashSet::new();
+        for i in 0..5 {
+            let topic_id = unique_id();
+            let sub_path = format!("/test/multiple/{}", i);
+            fixture
+                .message_storage
+                .append_topic_message(&topic_id, vec![build_record(&sub_path, i)])
+                .await
+                .unwrap();
+            fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtMostOnce);
+            sub_paths.insert(Bytes::from(sub_path));
+        }
+
+        let exclusive_push = fixture.start_exclusive_push().await;
+        assert_eq!(fixture.subscribe_manager.exclusive_push_thread.len(), 5);
+
+        let mut topics = HashSet::new();
+        for _ in 0..5 {
+            let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+                panic!("expected a PUBLISH");
+            };
+            topics.insert(publish.topic);
+        }
+        assert_eq!(topics, sub_paths);
+
+        // all the subscriptions of the client are removed with it, and their threads stopped
+        fixture.subscribe_manager.remove_client_id(&client_id);
+        assert!(fixture.subscribe_manager.exclusive_push.is_empty());
+        timeout(Duration::from_secs(10), async {
+            while !fixture.subscribe_manager.exclusive_push_thread.is_empty() {
+                exclusive_push.try_thread_gc().await;
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn restart_panicked_push_thread_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let sub_path = "/test/panic".to_string();
+        let client_id = fixture.add_session(1);
+
+        let records = (0..3).map(|i| build_record(&sub_path, i)).collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+        fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtLeastOnce);
+
+        // the first message panics the push thread
+        let panicked = Arc::new(AtomicBool::new(false));
+        let transformer_panicked = panicked.clone();
+        let exclusive_push =
+            fixture
+                .exclusive_push()
+                .with_message_transformer(Arc::new(move |msg: MqttMessage| {
+                    if !transformer_panicked.swap(true, Ordering::SeqCst) {
+                        panic!("inject panic in push thread");
+                    }
+                    msg
+                }));
+        exclusive_push.start_push_thread().await;
+
+        // the supervisor removes the panicked thread
+        timeout(Duration::from_secs(10), async {
+            while !panicked.load(Ordering::SeqCst)
+                || !fixture.subscribe_manager.exclusive_push_thread.is_empty()
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(fixture.subscribe_manager.exclusive_push.len(), 1);
+
+        // the restarted thread delivers the messages again
+        exclusive_push.start_push_thread().await;
+        assert_eq!(fixture.subscribe_manager.exclusive_push_thread.len(), 1);
+        timeout(Duration::from_secs(10), async {
+            while fixture
+                .cache_manager
+                .get_ack_packet(client_id.clone(), 1)
+                .is_none()
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn push_encrypted_tenant_message_test() {
+        let fixture = build_push_fixture();
+        fixture
+            .cache_manager
+            .add_tenant_encryption_key("tenant1", vec![9u8; 32]);
+        let delay_message_manager = Arc::new(DelayMessageManager::new(
+            unique_id(),
+            1,
+            fixture.storage_adapter.clone(),
+        ));
+
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+        let topic_name = "tenant1/sensor/1".to_string();
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.clone());
+        fixture
+            .subscribe_manager
+            .add_topic_subscribe(&topic_name, &client_id, &topic_name);
+
+        let payload = Bytes::from("tenant1 private data");
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            pkid: 0,
+            retain: false,
+            topic: Bytes::from(topic_name.clone()),
+            payload: payload.clone(),
+        };
+        save_message(
+            &fixture.storage_adapter,
+            &delay_message_manager,
+            &fixture.cache_manager,
+            &publish,
+            &None,
+            &fixture.subscribe_manager,
+            "publisher",
+            &topic,
+        )
+        .await
+        .unwrap();
+
+        // the payload is stored encrypted
+        let records = fixture
+            .message_storage
+            .read_topic_message(&topic.topic_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        let stored = MqttMessage::decode_record(records[0].clone()).unwrap();
+        assert_ne!(stored.payload, payload);
+        assert!(!stored
+            .payload
+            .windows(payload.len())
+            .any(|window| window == payload.as_ref()));
+
+        fixture.add_exclusive_push(&client_id, &topic_name, &topic.topic_id, QoS::AtMostOnce);
+        fixture.start_exclusive_push().await;
+
+        // the subscriber receives the plaintext
+        let MqttPacket::Publish(received, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(received.payload, payload);
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn push_payload_format_indicator_test() {
+        let fixture = build_push_fixture();
+        let delay_message_manager = Arc::new(DelayMessageManager::new(
+            unique_id(),
+            1,
+            fixture.storage_adapter.clone(),
+        ));
+
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+        let topic_name = "/test/format/indicator".to_string();
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.clone());
+        fixture
+            .subscribe_manager
+            .add_topic_subscribe(&topic_name, &client_id, &topic_name);
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            pkid: 0,
+            retain: false,
+            topic: Bytes::from(topic_name.clone()),
+            payload: Bytes::from(r#"{"temperature":21}"#),
+        };
+        let publish_properties = Some(PublishProperties {
+            payload_format_indicator: Some(1),
+            content_type: Some("application/json".to_string()),
+            ..Default::default()
+        });
+        save_message(
+            &fixture.storage_adapter,
+            &delay_message_manager,
+            &fixture.cache_manager,
+            &publish,
+            &publish_properties,
+            &fixture.subscribe_manager,
+            "publisher",
+            &topic,
+        )
+        .await
+        .unwrap();
+
+        fixture.add_exclusive_push(&client_id, &topic_name, &topic.topic_id, QoS::AtMostOnce);
+        fixture.start_exclusive_push().await;
+
+        let MqttPacket::Publish(received, properties) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(received.payload, publish.payload);
+        let properties = properties.unwrap();
+        assert_eq!(properties.payload_format_indicator, Some(1));
+        assert_eq!(
+            properties.content_type,
+            Some("application/json".to_string())
+        );
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn qos1_delivery_concurrency_order_test() {
+        let fixture = build_push_fixture();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.delivery_concurrency = 4;
+        fixture.cache_manager.set_cluster_info(cluster);
+
+        let topic_id = unique_id();
+        let sub_path = "/test/qos1/concurrency".to_string();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let records = (0..20).map(|i| build_record(&sub_path, i)).collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+
+        let subscriber =
+            fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtLeastOnce);
+        let group_name = build_group_name(&subscriber);
+        fixture.start_exclusive_push().await;
+
+        let mut payloads = Vec::new();
+        for _ in 0..5 {
+            // the 4 messages of a window are all sent before any of them is acknowledged
+            let mut pkids = Vec::new();
+            for _ in 0..4 {
+                let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+                    panic!("expected a PUBLISH");
+                };
+                assert!(!publish.dup);
+                payloads.push(publish.payload);
+                pkids.push(publish.pkid);
+            }
+            for pkid in pkids {
+                fixture
+                    .cache_manager
+                    .get_ack_packet(client_id.clone(), pkid)
+                    .unwrap()
+                    .sx
+                    .send(QosAckPackageData {
+                        ack_type: QosAckPackageType::PubAck,
+                        pkid,
+                    })
+                    .unwrap();
+            }
+        }
+
+        let expected: Vec<Bytes> = (0..20)
+            .map(|i| Bytes::from(format!("message-{}", i)))
+            .collect();
+        assert_eq!(payloads, expected);
+
+        timeout(Duration::from_secs(10), async {
+            while fixture
+                .message_storage
+                .get_group_offset(&group_name)
+                .await
+                .unwrap()
+                != 19
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn reader_inflight_bound_test() {
+        let fixture = build_push_fixture();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.delivery_concurrency = 10;
+        cluster.protocol.max_reader_inflight_records = 3;
+        fixture.cache_manager.set_cluster_info(cluster);
+
+        let topic_id = unique_id();
+        let sub_path = "/test/reader/inflight".to_string();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let records = (0..9).map(|i| build_record(&sub_path, i)).collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+
+        fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtLeastOnce);
+        fixture.start_exclusive_push().await;
+
+        let mut payloads = Vec::new();
+        for _ in 0..3 {
+            // the window of 10 is bounded by the 3 records the reader may hold
+            let mut pkids = Vec::new();
+            for _ in 0..3 {
+                let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+                    panic!("expected a PUBLISH");
+                };
+                payloads.push(publish.payload);
+                pkids.push(publish.pkid);
+            }
+            assert!(
+                timeout(Duration::from_millis(300), read_stream.next())
+                    .await
+                    .is_err(),
+                "the reader read more records than it may hold"
+            );
+
+            for pkid in pkids {
+                fixture
+                    .cache_manager
+                    .get_ack_packet(client_id.clone(), pkid)
+                    .unwrap()
+                    .sx
+                    .send(QosAckPackageData {
+                        ack_type: QosAckPackageType::PubAck,
+                        pkid,
+                    })
+                    .unwrap();
+            }
+        }
+
+        let expected: Vec<Bytes> = (0..9)
+            .map(|i| Bytes::from(format!("message-{}", i)))
+            .collect();
+        assert_eq!(payloads, expected);
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn slow_start_delivery_rate_test() {
+        let fixture = build_push_fixture();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.delivery_concurrency = 20;
+        fixture.cache_manager.set_cluster_info(cluster);
+
+        let topic_id = unique_id();
+        let sub_path = "/test/slow/start".to_string();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let records = (0..35).map(|i| build_record(&sub_path, i)).collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+        let subscriber = Subscriber {
+            client_id,
+            sub_path: sub_path.clone(),
+            topic_name: sub_path,
+            topic_id,
+            qos: QoS::AtMostOnce,
+            ..Default::default()
+        };
+
+        // batches of 20 are read, capped to 5 in the first second of the slow start, 10 in
+        // the next one, then read whole once the slow start is over
+        let mut slow_start = SlowStart::new(2, 5);
+        let mut delivery_rate = DeliveryRate::new(0);
+        let mut offset = 0;
+        let mut payloads = Vec::new();
+        for (batch_size, last_offset) in [(5, 4), (10, 14), (20, 34)] {
+            let pushed = pub_next_batch(
+                &fixture,
+                &subscriber,
+                offset,
+                &mut slow_start,
+                &mut delivery_rate,
+            )
+            .await;
+            assert_eq!(pushed, Some(last_offset));
+            for _ in 0..batch_size {
+                let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+                    panic!("expected a PUBLISH");
+                };
+                payloads.push(publish.payload);
+            }
+            offset = last_offset + 1;
+        }
+
+        // the QoS 0 messages of a batch are written concurrently
+        payloads.sort();
+        let mut expected: Vec<Bytes> = (0..35)
+            .map(|i| Bytes::from(format!("message-{}", i)))
+            .collect();
+        expected.sort();
+        assert_eq!(payloads, expected);
+    }
+
+    #[tokio::test]
+    async fn client_delivery_rate_test() {
+        let fixture = build_push_fixture();
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.max_client_delivery_rate = 100;
+        fixture.cache_manager.set_cluster_info(cluster);
+
+        let topic_id = unique_id();
+        let sub_path = "/test/delivery/rate".to_string();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let records = (0..12).map(|i| build_record(&sub_path, i)).collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+
+        // a constrained device that asked for 4 messages per second
+        let subscriber = Subscriber {
+            client_id,
+            sub_path: sub_path.clone(),
+            topic_name: sub_path,
+            topic_id,
+            qos: QoS::AtMostOnce,
+            max_delivery_rate: Some(4),
+            ..Default::default()
+        };
+        let mut slow_start = SlowStart::new(0, 0);
+        let mut delivery_rate = DeliveryRate::new(bound_delivery_rate(
+            subscriber.max_delivery_rate,
+            fixture
+                .cache_manager
+                .get_cluster_info()
+                .protocol
+                .max_client_delivery_rate,
+        ));
+
+        // batches of 5 are read, capped to the 4 messages of each second
+        let mut offset = 0;
+        let mut payloads = Vec::new();
+        for last_offset in [3, 7, 11] {
+            let pushed = pub_next_batch(
+                &fixture,
+                &subscriber,
+                offset,
+                &mut slow_start,
+                &mut delivery_rate,
+            )
+            .await;
+            assert_eq!(pushed, Some(last_offset));
+            for _ in 0..4 {
+                let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+                    panic!("expected a PUBLISH");
+                };
+                payloads.push(publish.payload);
+            }
+            offset = last_offset + 1;
+        }
+
+        payloads.sort();
+        let mut expected: Vec<Bytes> = (0..12)
+            .map(|i| Bytes::from(format!("message-{}", i)))
+            .collect();
+        expected.sort();
+        assert_eq!(payloads, expected);
+    }
+
+    #[tokio::test]
+    async fn publisher_allowlist_test() {
+        let fixture = build_push_fixture();
+        let topic_name = "/test/publisher/allowlist".to_string();
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.clone());
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        // the subscription only receives the messages of sensor-1 and sensor-3
+        let subscribe_properties = Some(SubscribeProperties {
+            subscription_identifier: None,
+            user_properties: vec![(
+                PUBLISHER_ALLOWLIST_USER_PROPERTY.to_string(),
+                "sensor-1,sensor-3".to_string(),
+            )],
+        });
+        let filter = Filter {
+            path: topic_name.clone(),
+            qos: QoS::AtMostOnce,
+            nolocal: false,
+            preserve_retain: false,
+            retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+        };
+        parse_subscribe(
+            &fixture.client_pool,
+            &fixture.cache_manager,
+            &fixture.subscribe_manager,
+            &client_id,
+            &topic,
+            &MqttProtocol::Mqtt5,
+            1,
+            &filter,
+            &subscribe_properties,
+        )
+        .await;
+        assert_eq!(fixture.subscribe_manager.exclusive_push.len(), 1);
+
+        let records = ["sensor-1", "sensor-2", "sensor-3", "sensor-2"]
+            .iter()
+            .enumerate()
+            .map(|(i, publisher)| {
+                let message = MqttMessage {
+                    client_id: publisher.to_string(),
+                    topic: Bytes::from(topic_name.clone()),
+                    payload: Bytes::from(format!("message-{}", i)),
+                    expiry_interval: now_second() + 3600,
+                    create_time: now_second(),
+                    ..Default::default()
+                };
+                Record::build_byte(message.encode())
+            })
+            .collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic.topic_id, records)
+            .await
+            .unwrap();
+
+        fixture.start_exclusive_push().await;
+
+        for payload in ["message-0", "message-2"] {
+            let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+                panic!("expected a PUBLISH");
+            };
+            assert_eq!(publish.payload, Bytes::from(payload));
+        }
+        assert!(timeout(Duration::from_millis(500), read_stream.next())
+            .await
+            .is_err());
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn shared_subscription_identifier_test() {
+        let fixture = build_push_fixture();
+        let topic_name = "/test/sub_id/shared".to_string();
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.clone());
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let build_subscribe_properties = |identifier| {
+            Some(SubscribeProperties {
+                subscription_identifier: Some(identifier),
+                user_properties: Vec::new(),
+            })
+        };
+        // two filters of the client matching the topic, with the same identifier
+        let build_filter = |path: &str| Filter {
+            path: path.to_owned(),
+            qos: QoS::AtMostOnce,
+            nolocal: false,
+            preserve_retain: false,
+            retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+        };
+        for (pkid, path) in [(1, "/test/sub_id/+"), (2, "/test/sub_id/#")] {
+            parse_subscribe(
+                &fixture.client_pool,
+                &fixture.cache_manager,
+                &fixture.subscribe_manager,
+                &client_id,
+                &topic,
+                &MqttProtocol::Mqtt5,
+                pkid,
+                &build_filter(path),
+                &build_subscribe_properties(5),
+            )
+            .await;
+        }
+        assert_eq!(fixture.subscribe_manager.exclusive_push.len(), 2);
+
+        fixture.start_exclusive_push().await;
+
+        fixture
+            .message_storage
+            .append_topic_message(&topic.topic_id, vec![build_record(&topic_name, 0)])
+            .await
+            .unwrap();
+        // a PUBLISH per matching subscription, each with the identifier of its subscription
+        for _ in 0..2 {
+            let MqttPacket::Publish(_, properties) = read_packet(&mut read_stream).await else {
+                panic!("expected a PUBLISH");
+            };
+            assert_eq!(properties.unwrap().subscription_identifiers, vec![5]);
+        }
+
+        // a re-subscribe replaces the identifier of the filter
+        parse_subscribe(
+            &fixture.client_pool,
+            &fixture.cache_manager,
+            &fixture.subscribe_manager,
+            &client_id,
+            &topic,
+            &MqttProtocol::Mqtt5,
+            3,
+            &build_filter("/test/sub_id/#"),
+            &build_subscribe_properties(6),
+        )
+        .await;
+        assert_eq!(fixture.subscribe_manager.exclusive_push.len(), 2);
+
+        // let the idle push threads go through their loop once to pick up the new identifier
+        sleep(Duration::from_millis(300)).await;
+        fixture
+            .message_storage
+            .append_topic_message(&topic.topic_id, vec![build_record(&topic_name, 1)])
+            .await
+            .unwrap();
+        let mut identifiers = Vec::new();
+        for _ in 0..2 {
+            let MqttPacket::Publish(_, properties) = read_packet(&mut read_stream).await else {
+                panic!("expected a PUBLISH");
+            };
+            identifiers.extend(properties.unwrap().subscription_identifiers);
+        }
+        identifiers.sort();
+        assert_eq!(identifiers, vec![5, 6]);
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn expired_message_dropped_metrics_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let sub_path = "/test/dropped/expired".to_string();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let expired = MqttMessage {
+            client_id: "publisher".to_string(),
+            topic: Bytes::from(sub_path.clone()),
+            payload: Bytes::from("expired"),
+            expiry_interval: now_second() - 10,
+            create_time: now_second() - 20,
+            ..Default::default()
+        };
+        let records = vec![
+            Record::build_byte(expired.encode()),
+            build_record(&sub_path, 1),
+        ];
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+        fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtMostOnce);
+
+        let before = get_message_dropped_num(MessageDropReason::Expired);
+        fixture.start_exclusive_push().await;
+
+        // only the message that has not expired is pushed
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.payload, Bytes::from("message-1"));
+        assert!(get_message_dropped_num(MessageDropReason::Expired) > before);
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn filtered_records_commit_offset_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let sub_path = "/test/filtered/offset".to_string();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let expired = || {
+            Record::build_byte(
+                MqttMessage {
+                    client_id: "publisher".to_string(),
+                    topic: Bytes::from(sub_path.clone()),
+                    payload: Bytes::from("expired"),
+                    expiry_interval: now_second() - 10,
+                    create_time: now_second() - 20,
+                    ..Default::default()
+                }
+                .encode(),
+            )
+        };
+        let records = vec![build_record(&sub_path, 0), expired(), expired()];
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+        let subscriber = Subscriber {
+            client_id,
+            sub_path: sub_path.clone(),
+            topic_name: sub_path.clone(),
+            topic_id: topic_id.clone(),
+            qos: QoS::AtMostOnce,
+            ..Default::default()
+        };
+        let group_name = build_group_name(&subscriber);
+        let mut slow_start = SlowStart::new(0, 0);
+        let mut delivery_rate = DeliveryRate::new(0);
+
+        // the expired records after the last message pushed are committed with the batch
+        let pushed = pub_next_batch(
+            &fixture,
+            &subscriber,
+            0,
+            &mut slow_start,
+            &mut delivery_rate,
+        )
+        .await;
+        assert_eq!(pushed, Some(2));
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.payload, Bytes::from("message-0"));
+        let offset = fixture
+            .message_storage
+            .get_group_offset(&group_name)
+            .await
+            .unwrap();
+        assert_eq!(offset, 2);
+
+        // so is a batch of expired records only
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, vec![expired(), expired()])
+            .await
+            .unwrap();
+        let pushed = pub_next_batch(
+            &fixture,
+            &subscriber,
+            3,
+            &mut slow_start,
+            &mut delivery_rate,
+        )
+        .await;
+        assert_eq!(pushed, Some(4));
+        let offset = fixture
+            .message_storage
+            .get_group_offset(&group_name)
+            .await
+            .unwrap();
+        assert_eq!(offset, 4);
+    }
+
+    #[tokio::test]
+    async fn push_remaining_expiry_interval_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let sub_path = "/test/expiry/remaining".to_string();
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        // published 20 seconds ago with a 60 seconds message expiry interval
+        let message = MqttMessage {
+            client_id: "publisher".to_string(),
+            topic: Bytes::from(sub_path.clone()),
+            payload: Bytes::from("live"),
+            expiry_interval: now_second() + 40,
+            create_time: now_second() - 20,
+            ..Default::default()
+        };
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, vec![Record::build_byte(message.encode())])
+            .await
+            .unwrap();
+        fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtMostOnce);
+        fixture.start_exclusive_push().await;
+
+        // the subscriber is told the seconds left, not the 60 seconds of the publisher
+        let MqttPacket::Publish(publish, properties) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.payload, Bytes::from("live"));
+        let expiry = properties.unwrap().message_expiry_interval.unwrap();
+        assert!(expiry > 30 && expiry <= 40);
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn resubscribe_upgrade_qos_test() {
+        let fixture = build_push_fixture();
+        let topic_name = "/test/qos/upgrade".to_string();
+        let topic = MqttTopic::new(unique_id(), unique_id(), topic_name.clone());
+        let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+        let mut filter = Filter {
+            path: topic_name.clone(),
+            qos: QoS::AtMostOnce,
+            nolocal: false,
+            preserve_retain: false,
+            retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+        };
+        parse_subscribe(
+            &fixture.client_pool,
+            &fixture.cache_manager,
+            &fixture.subscribe_manager,
+            &client_id,
+            &topic,
+            &MqttProtocol::Mqtt5,
+            1,
+            &filter,
+            &None,
+        )
+        .await;
+        fixture.start_exclusive_push().await;
+
+        fixture
+            .message_storage
+            .append_topic_message(&topic.topic_id, vec![build_record(&topic_name, 0)])
+            .await
+            .unwrap();
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.qos, QoS::AtMostOnce);
+
+        filter.qos = QoS::AtLeastOnce;
+        parse_subscribe(
+            &fixture.client_pool,
+            &fixture.cache_manager,
+            &fixture.subscribe_manager,
+            &client_id,
+            &topic,
+            &MqttProtocol::Mqtt5,
+            2,
+            &filter,
+            &None,
+        )
+        .await;
+        assert_eq!(fixture.subscribe_manager.exclusive_push.len(), 1);
+        assert_eq!(fixture.subscribe_manager.exclusive_push_thread.len(), 1);
+
+        // let the idle push thread go through its loop once to pick up the new QoS
+        sleep(Duration::from_millis(300)).await;
+        fixture
+            .message_storage
+            .append_topic_message(&topic.topic_id, vec![build_record(&topic_name, 1)])
+            .await
+            .unwrap();
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.qos, QoS::AtLeastOnce);
+        assert_eq!(publish.payload, Bytes::from("message-1"));
+
+        // the QoS1 delivery waits for the PUBACK before committing the offset
+        fixture
+            .cache_manager
+            .get_ack_packet(client_id.clone(), publish.pkid)
+            .unwrap()
+            .sx
+            .send(QosAckPackageData {
+                ack_type: QosAckPackageType::PubAck,
+                pkid: publish.pkid,
+            })
+            .unwrap();
+        let group_name = build_group_name(
+            fixture
+                .subscribe_manager
+                .exclusive_push
+                .iter()
+                .next()
+                .unwrap()
+                .value(),
+        );
+        timeout(Duration::from_secs(10), async {
+            while fixture
+                .message_storage
+                .get_group_offset(&group_name)
+                .await
+                .unwrap()
+                != 1
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn delivery_acl_revoked_test() {
+        let fixture = build_push_fixture();
+        let mut cluster = build_default_cluster_config();
+        cluster.security.delivery_acl_check_topics = vec!["/test/acl/#".to_string()];
+        fixture.cache_manager.set_cluster_info(cluster);
+
+        let topic_id = unique_id();
+        let sub_path = "/test/acl/1".to_string();
+        let (client_id, connection_id, mut read_stream) = fixture.connect_client().await;
+        let mut connection = MQTTConnection::new(ConnectionConfig {
+            connect_id: connection_id,
+            client_id: client_id.clone(),
+            receive_maximum: 10,
+            max_packet_size: 1024 * 1024,
+            topic_alias_max: 0,
+            request_problem_info: 0,
+            keep_alive: 60,
+            source_ip_addr: "127.0.0.1".to_string(),
+        });
+        connection.login_success("lobo".to_string());
+        fixture
+            .cache_manager
+            .add_connection(connection_id, connection);
+
+        let subscriber =
+            fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtMostOnce);
+        let group_name = build_group_name(&subscriber);
+        fixture.start_exclusive_push().await;
+
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, vec![build_record(&sub_path, 0)])
+            .await
+            .unwrap();
+        let MqttPacket::Publish(publish, _) = read_packet(&mut read_stream).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.payload, Bytes::from("message-0"));
+
+        // revoke the subscribe permission of the client
+        fixture.cache_manager.add_acl(MqttAcl {
+            resource_type: MqttAclResourceType::ClientId,
+            resource_name: client_id.clone(),
+            topic: sub_path.clone(),
+            ip: "*".to_string(),
+            action: MqttAclAction::Subscribe,
+            permission: MqttAclPermission::Deny,
+        });
+
+        fixture
+            .message_storage
+            .append_topic_message(
+                &topic_id,
+                vec![build_record(&sub_path, 1), build_record(&sub_path, 2)],
+            )
+            .await
+            .unwrap();
+
+        // the denied messages are skipped and their offset committed
+        timeout(Duration::from_secs(10), async {
+            while fixture
+                .message_storage
+                .get_group_offset(&group_name)
+                .await
+                .unwrap()
+                != 2
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(timeout(Duration::from_millis(500), read_stream.next())
+            .await
+            .is_err());
+        // the client stays connected unless the disconnect on denial is enabled
+        assert_eq!(
+            fixture.cache_manager.get_connect_id(&client_id),
+            Some(connection_id)
+        );
+
+        fixture.stop_push_threads();
+    }
+
+    // Delivery time of QoS1 messages to a client that acknowledges every message
+    // after a fixed delay, by delivery concurrency.
+    // cargo test -p mqtt-broker qos1_delivery_concurrency_throughput -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn qos1_delivery_concurrency_throughput() {
+        let message_num = 200;
+        let ack_delay = Duration::from_millis(5);
+        let mut elapsed = Vec::new();
+        for concurrency in [1, 4, 16] {
+            let fixture = build_push_fixture();
+            let (client_id, _, mut read_stream) = fixture.connect_client().await;
+
+            let ack_cache_manager = fixture.cache_manager.clone();
+            let ack_client_id = client_id.clone();
+            tokio::spawn(async move {
+                while let Some(Ok(MqttPacket::Publish(publish, _))) = read_stream.next().await {
+                    let cache_manager = ack_cache_manager.clone();
+                    let client_id = ack_client_id.clone();
+                    tokio::spawn(async move {
+                        sleep(ack_delay).await;
+                        if let Some(data) = cache_manager.get_ack_packet(client_id, publish.pkid) {
+                            let _ = data.sx.send(QosAckPackageData {
+                                ack_type: QosAckPackageType::PubAck,
+                                pkid: publish.pkid,
+                            });
+                        }
+                    });
+                }
+            });
+
+            let subscriber = Subscriber {
+                client_id: client_id.clone(),
+                sub_path: "/test/qos1/throughput".to_string(),
+                topic_name: "/test/qos1/throughput".to_string(),
+                qos: QoS::AtLeastOnce,
+                ..Default::default()
+            };
+            let mut sub_pub_params = Vec::new();
+            for i in 0..message_num {
+                let pkid = fixture.cache_manager.get_pkid(&client_id).await;
+                let publish = Publish {
+                    dup: false,
+                    qos: QoS::AtLeastOnce,
+                    pkid,
+                    retain: false,
+                    topic: Bytes::from(subscriber.topic_name.clone()),
+                    payload: Bytes::from(format!("message-{}", i)),
+                };
+                sub_pub_params.push(SubPublishParam::new(
+                    subscriber.clone(),
+                    publish,
+                    None,
+                    0,
+                    unique_id(),
+                    pkid,
+                ));
+            }
+
+            let (stop_sx, _) = broadcast::channel(1);
+            let start = Instant::now();
+            for chunk in sub_pub_params.chunks(concurrency) {
+                exclusive_publish_messages_qos1(
+                    &fixture.cache_manager,
+                    &fixture.connection_manager,
+                    chunk,
+                    &stop_sx,
+                    Duration::from_secs(10),
+                )
+                .await;
+            }
+            elapsed.push(start.elapsed());
+        }
+
+        // the messages delivered one at a time wait for the acknowledgement of each, at
+        // least the ack delay per message
+        assert!(elapsed[0] >= ack_delay * message_num);
+        // the delivery concurrency overlaps those waits
+        assert!(elapsed[1] * 2 < elapsed[0]);
+        assert!(elapsed[2] * 2 < elapsed[1]);
+    }
+
+    #[tokio::test]
+    async fn storage_read_failure_circuit_open_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let sub_path = "/test/circuit".to_string();
+        let client_id = fixture.add_session(1);
+
+        // a record whose crc does not match its data, as read from a corrupt segment
+        let mut record = build_record(&sub_path, 0);
+        record.crc_num = record.crc_num.wrapping_add(1);
+        fixture
+            .storage_adapter
+            .batch_write(cluster_name(), topic_id.clone(), vec![record])
+            .await
+            .unwrap();
+        fixture.add_exclusive_push(&client_id, &sub_path, &topic_id, QoS::AtLeastOnce);
+
+        let exclusive_push = fixture.exclusive_push().with_circuit_breaker(3, 60);
+        exclusive_push.start_push_thread().await;
+
+        timeout(Duration::from_secs(10), async {
+            while exclusive_push.circuit_breaker(&topic_id).state() != CircuitState::Open {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(get_push_circuit_open_num(&topic_id), 1);
+
+        // the thread is paused, it neither reads the topic again nor exits
+        sleep(Duration::from_millis(1500)).await;
+        assert_eq!(
+            exclusive_push.circuit_breaker(&topic_id).state(),
+            CircuitState::Open
+        );
+        assert_eq!(get_push_circuit_open_num(&topic_id), 1);
+        assert_eq!(fixture.subscribe_manager.exclusive_push_thread.len(), 1);
+
+        fixture.stop_push_threads();
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Loom models of the lifecycle of the exclusive push threads, run with
+//! `RUSTFLAGS="--cfg loom" cargo test -p mqtt-broker --release exclusive_push_loom`.
+//!
+//! The push threads share their state through DashMaps and tokio channels, which loom cannot
+//! instrument, so the models replay the steps of `ExclusivePush` on loom primitives, in the
+//! order of the real code:
+//! - `exclusive_push` holds the subscriptions and `exclusive_push_thread` the stop signal of
+//!   the running push threads, both keyed by the exclusive key;
+//! - `start_push_thread` and `try_thread_gc` run in turn on the same task, the subscribe and
+//!   unsubscribe requests on the request handler tasks;
+//! - a push thread reads the committed offset of its group, then delivers the messages and
+//!   commits the offset of the last message delivered. The stop signal is raced against the
+//!   push by a `select!`, so a push can be cancelled between the delivery and the commit.
+//!
+//! The invariants checked:
+//! 1. At most one push thread of a subscription runs at a time. `try_thread_gc` only signals a
+//!    thread to stop and leaves the removal of its `exclusive_push_thread` entry to the thread
+//!    itself, so `start_push_thread` cannot start a second thread while the stopped one is
+//!    still pushing. The entry is removed by the gc only once the thread is gone, i.e. the stop
+//!    signal has no receiver left.
+//! 2. A stop signal racing with a push may cause a message to be delivered again, but never to
+//!    be lost: the committed offset is always one of a message already delivered.
+//! 3. The last commit of a stopped thread happens before its entry is removed, which happens
+//!    before the next thread of the subscription is started, so the next thread reads exactly
+//!    the offset the previous one committed last.
+
+use std::collections::{HashMap, HashSet};
+
+use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use loom::sync::{Arc, Mutex};
+use loom::thread::{self, JoinHandle};
+
+const EXCLUSIVE_KEY: &str = "client_id_sub_name_topic_id";
+// Messages of the topic, kept small so that loom can explore every interleaving
+const MESSAGE_NUM: u64 = 2;
+const PREEMPTION_BOUND: usize = 3;
+
+struct PushThread {
+    stop: AtomicBool,
+    // The push thread and its stop signal receiver are dropped
+    exited: AtomicBool,
+}
+
+struct Model {
+    exclusive_push: Mutex<HashSet<&'static str>>,
+    exclusive_push_thread: Mutex<HashMap<&'static str, Arc<PushThread>>>,
+    running_threads: AtomicUsize,
+    // Offsets in the order they were delivered to the client
+    delivered: Mutex<Vec<u64>>,
+    committed: Mutex<Option<u64>>,
+    // Offset every push thread started from
+    start_offsets: Mutex<Vec<u64>>,
+    // Offset committed when every push thread exited
+    exit_offsets: Mutex<Vec<Option<u64>>>,
+}
+
+impl Model {
+    fn new() -> Arc<Self> {
+        Arc::new(Model {
+            exclusive_push: Mutex::new(HashSet::from([EXCLUSIVE_KEY])),
+            exclusive_push_thread: Mutex::new(HashMap::new()),
+            running_threads: AtomicUsize::new(0),
+            delivered: Mutex::new(Vec::new()),
+            committed: Mutex::new(None),
+            start_offsets: Mutex::new(Vec::new()),
+            exit_offsets: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn subscribe(&self) {
+        self.exclusive_push.lock().unwrap().insert(EXCLUSIVE_KEY);
+    }
+
+    fn unsubscribe(&self) {
+        self.exclusive_push.lock().unwrap().remove(EXCLUSIVE_KEY);
+    }
+
+    fn is_subscribed(&self) -> bool {
+        self.exclusive_push.lock().unwrap().contains(EXCLUSIVE_KEY)
+    }
+
+    fn push_thread(&self) -> Option<Arc<PushThread>> {
+        self.exclusive_push_thread
+            .lock()
+            .unwrap()
+            .get(EXCLUSIVE_KEY)
+            .cloned()
+    }
+
+    // loom's Arc cannot be the receiver of a method, the functions that spawn a push thread
+    // take the model as an argument
+    fn start_push_thread(model: &Arc<Self>) -> Option<JoinHandle<()>> {
+        if !model.is_subscribed() || model.push_thread().is_some() {
+            return None;
+        }
+
+        let push_thread = Arc::new(PushThread {
+            stop: AtomicBool::new(false),
+            exited: AtomicBool::new(false),
+        });
+        model
+            .exclusive_push_thread
+            .lock()
+            .unwrap()
+            .insert(EXCLUSIVE_KEY, push_thread.clone());
+
+        let model = model.clone();
+        Some(thread::spawn(move || model.push(&push_thread)))
+    }
+
+    fn try_thread_gc(&self) {
+        let Some(push_thread) = self.push_thread() else {
+            return;
+        };
+        if self.is_subscribed() {
+            return;
+        }
+        if push_thread.exited.load(Ordering::SeqCst) {
+            self.exclusive_push_thread
+                .lock()
+                .unwrap()
+                .remove(EXCLUSIVE_KEY);
+        } else {
+            push_thread.stop.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn push(&self, push_thread: &PushThread) {
+        assert_eq!(
+            self.running_threads.fetch_add(1, Ordering::SeqCst),
+            0,
+            "two push threads of the same subscription are running"
+        );
+
+        // get_group_offset, the message at the committed offset is delivered again
+        let mut offset = self.committed.lock().unwrap().unwrap_or(0);
+        self.start_offsets.lock().unwrap().push(offset);
+
+        loop {
+            if push_thread.stop.load(Ordering::SeqCst) {
+                break;
+            }
+            if offset >= MESSAGE_NUM {
+                // Waits for new messages
+                thread::yield_now();
+                continue;
+            }
+
+            self.delivered.lock().unwrap().push(offset);
+            // The select! drops pub_message when the stop signal wins, before the commit
+            if push_thread.stop.load(Ordering::SeqCst) {
+                break;
+            }
+            *self.committed.lock().unwrap() = Some(offset);
+            offset += 1;
+        }
+
+        let committed = *self.committed.lock().unwrap();
+        self.exit_offsets.lock().unwrap().push(committed);
+        self.running_threads.fetch_sub(1, Ordering::SeqCst);
+        self.exclusive_push_thread
+            .lock()
+            .unwrap()
+            .remove(EXCLUSIVE_KEY);
+        push_thread.exited.store(true, Ordering::SeqCst);
+    }
+
+    // One round of ExclusivePush::start
+    fn tick(model: &Arc<Self>, handles: &mut Vec<JoinHandle<()>>) {
+        handles.extend(Model::start_push_thread(model));
+        model.try_thread_gc();
+    }
+
+    // Unsubscribes and runs the rounds until no push thread is left
+    fn shutdown(model: &Arc<Self>, mut handles: Vec<JoinHandle<()>>) {
+        model.unsubscribe();
+        while model.push_thread().is_some() {
+            Model::tick(model, &mut handles);
+            thread::yield_now();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(model.running_threads.load(Ordering::SeqCst), 0);
+    }
+
+    fn assert_no_message_lost(&self) {
+        let delivered = self.delivered.lock().unwrap();
+        if let Some(committed) = *self.committed.lock().unwrap() {
+            assert!(delivered.contains(&committed));
+        }
+        for start_offset in self.start_offsets.lock().unwrap().iter() {
+            assert!(*start_offset == 0 || delivered.contains(start_offset));
+        }
+    }
+}
+
+fn loom_model<F>(f: F)
+where
+    F: Fn() + Sync + Send + 'static,
+{
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(PREEMPTION_BOUND);
+    builder.check(f);
+}
+
+#[test]
+fn concurrent_subscribe_unsubscribe_test() {
+    loom_model(|| {
+        let model = Model::new();
+        let mut handles = Vec::new();
+        Model::tick(&model, &mut handles);
+
+        let client = {
+            let model = model.clone();
+            thread::spawn(move || {
+                model.unsubscribe();
+                model.subscribe();
+            })
+        };
+        Model::tick(&model, &mut handles);
+        client.join().unwrap();
+
+        // The subscription gets a running push thread once the stopped one is gone
+        loop {
+            Model::tick(&model, &mut handles);
+            if model
+                .push_thread()
+                .is_some_and(|push_thread| !push_thread.stop.load(Ordering::SeqCst))
+            {
+                break;
+            }
+            thread::yield_now();
+        }
+
+        Model::shutdown(&model, handles);
+        model.assert_no_message_lost();
+    });
+}
+
+#[test]
+fn concurrent_push_and_stop_test() {
+    loom_model(|| {
+        let model = Model::new();
+        let mut handles = Vec::new();
+        Model::tick(&model, &mut handles);
+
+        // Stopped while it pushes
+        model.unsubscribe();
+        model.try_thread_gc();
+        Model::shutdown(&model, handles);
+
+        model.assert_no_message_lost();
+        let delivered = model.delivered.lock().unwrap();
+        assert!(delivered.len() <= MESSAGE_NUM as usize);
+    });
+}
+
+#[test]
+fn concurrent_offset_commit_and_read_test() {
+    loom_model(|| {
+        let model = Model::new();
+        let mut handles = Vec::new();
+        Model::tick(&model, &mut handles);
+
+        // A re-subscription while the first thread still commits its offsets
+        model.unsubscribe();
+        model.try_thread_gc();
+        model.subscribe();
+        while model.start_offsets.lock().unwrap().len() < 2 {
+            Model::tick(&model, &mut handles);
+            thread::yield_now();
+        }
+
+        Model::shutdown(&model, handles);
+        model.assert_no_message_lost();
+
+        // The second thread read the last offset committed by the first one
+        let start_offsets = model.start_offsets.lock().unwrap();
+        let exit_offsets = model.exit_offsets.lock().unwrap();
+        assert_eq!(start_offsets[1], exit_offsets[0].unwrap_or(0));
+    });
+}
@@ -0,0 +1,446 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use log::{error, info};
+use metadata_struct::adapter::record::Record;
+use metadata_struct::mqtt::message::MqttMessage;
+use storage_adapter::storage::StorageAdapter;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{sleep, timeout};
+
+use crate::handler::error::MqttBrokerError;
+use crate::storage::message::MessageStorage;
+
+const FAN_OUT_READ_BATCH: u64 = 100;
+// Decoded records kept per topic for the consumers that are behind the reader, the oldest are
+// evicted first
+const FAN_OUT_BUFFER_CAPACITY: usize = 10_000;
+const FAN_OUT_WAIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A message of a topic decoded once and shared by all the consumers of the topic.
+#[derive(Clone, Debug)]
+pub struct SharedRecord {
+    pub offset: u64,
+    pub timestamp: u64,
+    pub message: MqttMessage,
+}
+
+pub fn decode_records(records: Vec<Record>) -> Result<Vec<Arc<SharedRecord>>, MqttBrokerError> {
+    let mut results = Vec::with_capacity(records.len());
+    for record in records {
+        let offset = record.offset.unwrap_or_default();
+        let timestamp = record.timestamp;
+        let message = MqttMessage::decode_record(record)?;
+        results.push(Arc::new(SharedRecord {
+            offset,
+            timestamp,
+            message,
+        }));
+    }
+    Ok(results)
+}
+
+struct FanOutBuffer {
+    // Offset of the first record of the buffer
+    start_offset: u64,
+    // Offset the reader reads next
+    next_offset: u64,
+    records: VecDeque<Arc<SharedRecord>>,
+}
+
+impl FanOutBuffer {
+    // Drops the records all the consumers have passed, and the oldest records beyond the
+    // capacity. A consumer behind the buffer reads the storage instead, so that a stuck
+    // consumer group does not hold up the others.
+    fn trim(&mut self, min_consumer_offset: u64, capacity: usize) {
+        while self.records.len() > capacity
+            || self
+                .records
+                .front()
+                .is_some_and(|record| record.offset < min_consumer_offset)
+        {
+            self.records.pop_front();
+        }
+        self.start_offset = self
+            .records
+            .front()
+            .map(|record| record.offset)
+            .unwrap_or(self.next_offset);
+    }
+}
+
+struct FanOutTopic {
+    buffer: RwLock<FanOutBuffer>,
+    // The reader sends the next offset after every batch added to the buffer
+    wakeup: broadcast::Sender<u64>,
+    // (consumer_key, offset the consumer reads next)
+    consumer_offsets: DashMap<String, u64>,
+}
+
+impl FanOutTopic {
+    fn min_consumer_offset(&self) -> Option<u64> {
+        self.consumer_offsets.iter().map(|raw| *raw.value()).min()
+    }
+
+    fn max_consumer_offset(&self) -> Option<u64> {
+        self.consumer_offsets.iter().map(|raw| *raw.value()).max()
+    }
+}
+
+/// Reads every topic once for all the consumer groups subscribed to it. A reader task per topic
+/// appends the decoded batches to an in-memory buffer and wakes up the push threads through a
+/// broadcast channel. Each consumer group still tracks and commits its own offset, and falls
+/// back to reading the storage when its offset is no longer in the buffer.
+pub struct FanOutReader<S> {
+    message_storage: Arc<S>,
+    topics: Arc<DashMap<String, Arc<FanOutTopic>>>,
+    buffer_capacity: usize,
+}
+
+impl<S> FanOutReader<S>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    pub fn new(message_storage: Arc<S>) -> Self {
+        FanOutReader {
+            message_storage,
+            topics: Arc::new(DashMap::with_capacity(8)),
+            buffer_capacity: FAN_OUT_BUFFER_CAPACITY,
+        }
+    }
+
+    pub fn subscribe(
+        &self,
+        topic_id: &str,
+        consumer_key: &str,
+        offset: u64,
+    ) -> FanOutSubscription<S> {
+        let mut is_new = false;
+        let topic = {
+            let entry = self.topics.entry(topic_id.to_owned()).or_insert_with(|| {
+                is_new = true;
+                let (wakeup, _) = broadcast::channel(64);
+                Arc::new(FanOutTopic {
+                    buffer: RwLock::new(FanOutBuffer {
+                        start_offset: offset,
+                        next_offset: offset,
+                        records: VecDeque::new(),
+                    }),
+                    wakeup,
+                    consumer_offsets: DashMap::with_capacity(8),
+                })
+            });
+            // Registered while the entry is locked, so that the reader does not exit in between
+            entry
+                .consumer_offsets
+                .insert(consumer_key.to_owned(), offset);
+            entry.clone()
+        };
+
+        if is_new {
+            self.start_reader(topic_id.to_owned(), topic.clone());
+        }
+
+        FanOutSubscription {
+            topic_id: topic_id.to_owned(),
+            consumer_key: consumer_key.to_owned(),
+            wakeup_rx: topic.wakeup.subscribe(),
+            topic,
+            message_storage: MessageStorage::new(self.message_storage.clone()),
+        }
+    }
+
+    fn start_reader(&self, topic_id: String, topic: Arc<FanOutTopic>) {
+        let topics = self.topics.clone();
+        let message_storage = MessageStorage::new(self.message_storage.clone());
+        let buffer_capacity = self.buffer_capacity;
+        tokio::spawn(async move {
+            info!(
+                "Fan out reader of topic_id [{}] was started successfully",
+                topic_id
+            );
+            loop {
+                if topics
+                    .remove_if(&topic_id, |_, topic| topic.consumer_offsets.is_empty())
+                    .is_some()
+                {
+                    info!(
+                        "Fan out reader of topic_id [{}] was stopped, the topic has no consumer",
+                        topic_id
+                    );
+                    break;
+                }
+
+                if !read_next_batch(&message_storage, &topic_id, &topic, buffer_capacity).await {
+                    sleep(FAN_OUT_WAIT_TIMEOUT).await;
+                }
+            }
+        });
+    }
+}
+
+// Appends the next batch of the topic to the buffer, returns false when there was nothing to read.
+async fn read_next_batch<S>(
+    message_storage: &MessageStorage<S>,
+    topic_id: &str,
+    topic: &FanOutTopic,
+    buffer_capacity: usize,
+) -> bool
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let min_consumer_offset = topic.min_consumer_offset().unwrap_or_default();
+    let max_consumer_offset = topic.max_consumer_offset().unwrap_or_default();
+    let next_offset = {
+        let mut buffer = topic.buffer.write().unwrap();
+        buffer.trim(min_consumer_offset, buffer_capacity);
+
+        // Waits for the fastest consumer instead of reading further ahead than the buffer holds
+        if buffer.next_offset >= max_consumer_offset + buffer_capacity as u64 {
+            return false;
+        }
+        buffer.next_offset
+    };
+
+    let records = match message_storage
+        .read_topic_message(topic_id, next_offset, FAN_OUT_READ_BATCH)
+        .await
+        .map_err(MqttBrokerError::from)
+        .and_then(decode_records)
+    {
+        Ok(records) => records,
+        Err(e) => {
+            error!(
+                "Fan out reader failed to read the messages of topic_id [{}], {}",
+                topic_id, e
+            );
+            return false;
+        }
+    };
+    let Some(last) = records.last() else {
+        return false;
+    };
+
+    let next_offset = last.offset + 1;
+    {
+        let mut buffer = topic.buffer.write().unwrap();
+        buffer.records.extend(records);
+        buffer.next_offset = next_offset;
+        buffer.trim(min_consumer_offset, buffer_capacity);
+    }
+    let _ = topic.wakeup.send(next_offset);
+    true
+}
+
+/// The place of a consumer group in the shared read of a topic.
+pub struct FanOutSubscription<S> {
+    topic_id: String,
+    consumer_key: String,
+    topic: Arc<FanOutTopic>,
+    wakeup_rx: broadcast::Receiver<u64>,
+    message_storage: MessageStorage<S>,
+}
+
+impl<S> FanOutSubscription<S>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    // Returns up to record_num records from offset, or nothing if none is available yet.
+    pub async fn read(
+        &mut self,
+        offset: u64,
+        record_num: u64,
+    ) -> Result<Vec<Arc<SharedRecord>>, MqttBrokerError> {
+        self.topic
+            .consumer_offsets
+            .insert(self.consumer_key.clone(), offset);
+
+        loop {
+            let start_offset = {
+                let buffer = self.topic.buffer.read().unwrap();
+                if offset >= buffer.start_offset && offset < buffer.next_offset {
+                    let start = buffer
+                        .records
+                        .partition_point(|record| record.offset < offset);
+                    return Ok(buffer
+                        .records
+                        .iter()
+                        .skip(start)
+                        .take(record_num as usize)
+                        .cloned()
+                        .collect());
+                }
+                buffer.start_offset
+            };
+
+            // Older than the buffer, e.g. a group that joined later than the others
+            if offset < start_offset {
+                let max_num = record_num.min(start_offset - offset);
+                let records = self
+                    .message_storage
+                    .read_topic_message(&self.topic_id, offset, max_num)
+                    .await?;
+                return decode_records(records);
+            }
+
+            match timeout(FAN_OUT_WAIT_TIMEOUT, self.wakeup_rx.recv()).await {
+                Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) | Err(_) => return Ok(Vec::new()),
+            }
+        }
+    }
+}
+
+impl<S> Drop for FanOutSubscription<S> {
+    fn drop(&mut self) {
+        self.topic.consumer_offsets.remove(&self.consumer_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+    use common_base::tools::unique_id;
+    use metadata_struct::adapter::record::Record;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use storage_adapter::memory::MemoryStorageAdapter;
+    use tokio::time::{sleep, timeout};
+
+    use super::FanOutReader;
+    use crate::storage::message::MessageStorage;
+
+    #[tokio::test]
+    async fn fan_out_read_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let message_storage = MessageStorage::new(storage_adapter.clone());
+        let topic_id = unique_id();
+        let records = (0..10)
+            .map(|i| {
+                let message = MqttMessage {
+                    payload: Bytes::from(format!("message-{}", i)),
+                    ..Default::default()
+                };
+                Record::build_byte(message.encode())
+            })
+            .collect();
+        message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+
+        let reader = FanOutReader::new(storage_adapter);
+        let mut g1 = reader.subscribe(&topic_id, "g1", 0);
+        let mut g2 = reader.subscribe(&topic_id, "g2", 0);
+
+        let records = g1.read(0, 4).await.unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.offset).collect::<Vec<u64>>(),
+            vec![0, 1, 2, 3]
+        );
+        // the groups share the decoded records
+        let g2_records = g2.read(0, 4).await.unwrap();
+        assert!(Arc::ptr_eq(&records[0], &g2_records[0]));
+
+        let records = g2.read(8, 4).await.unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.offset).collect::<Vec<u64>>(),
+            vec![8, 9]
+        );
+        assert_eq!(records[1].message.payload, Bytes::from("message-9"));
+
+        // nothing was published after the last message
+        assert!(g2.read(10, 4).await.unwrap().is_empty());
+
+        // the reader stops once the topic has no consumer
+        drop(g1);
+        drop(g2);
+        timeout(Duration::from_secs(10), async {
+            while reader.topics.contains_key(&topic_id) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn fan_out_stuck_group_test() {
+        let path = format!(
+            "{}/../../config/mqtt-server.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        init_broker_mqtt_conf_by_path(&path);
+
+        let storage_adapter = Arc::new(MemoryStorageAdapter::new());
+        let message_storage = MessageStorage::new(storage_adapter.clone());
+        let topic_id = unique_id();
+        let records = (0..30)
+            .map(|i| {
+                let message = MqttMessage {
+                    payload: Bytes::from(format!("message-{}", i)),
+                    ..Default::default()
+                };
+                Record::build_byte(message.encode())
+            })
+            .collect();
+        message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+
+        let mut reader = FanOutReader::new(storage_adapter);
+        reader.buffer_capacity = 5;
+        // g1 never advances past the first message
+        let mut g1 = reader.subscribe(&topic_id, "g1", 0);
+        let mut g2 = reader.subscribe(&topic_id, "g2", 0);
+
+        let mut offsets = Vec::new();
+        timeout(Duration::from_secs(10), async {
+            while offsets.len() < 30 {
+                let next = offsets.len() as u64;
+                for record in g2.read(next, 4).await.unwrap() {
+                    offsets.push(record.offset);
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(offsets, (0..30).collect::<Vec<u64>>());
+
+        // the records of the stuck group were evicted, it reads them from the storage
+        let topic = reader.topics.get(&topic_id).unwrap().clone();
+        assert_eq!(topic.buffer.read().unwrap().start_offset, 25);
+        let records = g1.read(0, 4).await.unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.offset).collect::<Vec<u64>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(records[0].message.payload, Bytes::from("message-0"));
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use dashmap::DashMap;
+
+pub const BROKER_LAG_USER_PROPERTY: &str = "x-broker-lag";
+
+/// Progress of the exclusive push threads, reported to the publish path so that
+/// the producer of a topic can be told how far behind its slowest subscriber is.
+#[derive(Default)]
+pub struct FlowControlSignal {
+    // (topic_id, (consumer_key, next offset to push))
+    consumer_offsets: DashMap<String, DashMap<String, u64>>,
+}
+
+impl FlowControlSignal {
+    pub fn new() -> Self {
+        FlowControlSignal::default()
+    }
+
+    pub fn report_offset(&self, topic_id: &str, consumer_key: &str, offset: u64) {
+        self.consumer_offsets
+            .entry(topic_id.to_owned())
+            .or_default()
+            .insert(consumer_key.to_owned(), offset);
+    }
+
+    pub fn remove_consumer(&self, topic_id: &str, consumer_key: &str) {
+        if let Some(consumers) = self.consumer_offsets.get(topic_id) {
+            consumers.remove(consumer_key);
+        }
+        self.consumer_offsets
+            .remove_if(topic_id, |_, consumers| consumers.is_empty());
+    }
+
+    // Number of messages up to publish_offset that the slowest consumer has not pushed yet
+    pub fn lag(&self, topic_id: &str, publish_offset: u64) -> Option<u64> {
+        let consumers = self.consumer_offsets.get(topic_id)?;
+        let min_offset = consumers.iter().map(|raw| *raw.value()).min()?;
+        Some((publish_offset + 1).saturating_sub(min_offset))
+    }
+
+    // The lag to report to the producer, if it exceeds the threshold
+    pub fn exceeded_lag(&self, topic_id: &str, publish_offset: u64, threshold: u64) -> Option<u64> {
+        if threshold == 0 {
+            return None;
+        }
+        self.lag(topic_id, publish_offset)
+            .filter(|lag| *lag > threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlowControlSignal;
+
+    #[test]
+    fn lag_test() {
+        let signal = FlowControlSignal::new();
+        assert_eq!(signal.lag("t1", 10), None);
+
+        signal.report_offset("t1", "c1", 8);
+        signal.report_offset("t1", "c2", 3);
+        assert_eq!(signal.lag("t1", 10), Some(8));
+        assert_eq!(signal.exceeded_lag("t1", 10, 8), None);
+        assert_eq!(signal.exceeded_lag("t1", 10, 5), Some(8));
+        assert_eq!(signal.exceeded_lag("t1", 10, 0), None);
+
+        signal.remove_consumer("t1", "c2");
+        assert_eq!(signal.lag("t1", 10), Some(3));
+
+        signal.remove_consumer("t1", "c1");
+        assert_eq!(signal.lag("t1", 10), None);
+    }
+}
@@ -12,9 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod cluster_route;
+pub mod delivery_builder;
+pub mod delivery_rate;
 pub mod exclusive_push;
+#[cfg(all(test, loom))]
+mod exclusive_push_loom;
+pub mod fan_out_read;
+pub mod flow_control_signal;
+pub mod publisher_allowlist;
+#[cfg(test)]
+pub(crate) mod push_fixture;
+pub mod reader_inflight;
 pub mod share_follower_resub;
 pub mod share_leader_push;
+pub mod slow_start;
 pub mod sub_common;
 pub mod subscribe_manager;
 pub mod subscriber;
+pub mod subscription_trie;
@@ -0,0 +1,86 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use protocol::mqtt::common::SubscribeProperties;
+
+/// User property of a SUBSCRIBE with the comma separated client ids of the publishers whose
+/// messages the subscription receives, the messages of the other publishers are filtered out.
+pub const PUBLISHER_ALLOWLIST_USER_PROPERTY: &str = "x-publisher-allowlist";
+
+// The publishers allowed by the user properties of the SUBSCRIBE, the properties add up
+pub fn requested_publisher_allowlist(
+    properties: &Option<SubscribeProperties>,
+) -> Option<Vec<String>> {
+    let allowlist: Vec<String> = properties
+        .as_ref()?
+        .user_properties
+        .iter()
+        .filter(|(key, _)| key == PUBLISHER_ALLOWLIST_USER_PROPERTY)
+        .flat_map(|(_, value)| value.split(','))
+        .map(|client_id| client_id.trim().to_string())
+        .filter(|client_id| !client_id.is_empty())
+        .collect();
+
+    if allowlist.is_empty() {
+        return None;
+    }
+    Some(allowlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::mqtt::common::SubscribeProperties;
+
+    use super::{requested_publisher_allowlist, PUBLISHER_ALLOWLIST_USER_PROPERTY};
+
+    #[test]
+    fn requested_publisher_allowlist_test() {
+        assert_eq!(requested_publisher_allowlist(&None), None);
+
+        let mut properties = SubscribeProperties::default();
+        assert_eq!(
+            requested_publisher_allowlist(&Some(properties.clone())),
+            None
+        );
+
+        properties.user_properties = vec![(
+            PUBLISHER_ALLOWLIST_USER_PROPERTY.to_string(),
+            " , ".to_string(),
+        )];
+        assert_eq!(
+            requested_publisher_allowlist(&Some(properties.clone())),
+            None
+        );
+
+        properties.user_properties = vec![
+            (
+                PUBLISHER_ALLOWLIST_USER_PROPERTY.to_string(),
+                "sensor-1, sensor-2".to_string(),
+            ),
+            ("k1".to_string(), "v1".to_string()),
+            (
+                PUBLISHER_ALLOWLIST_USER_PROPERTY.to_string(),
+                "sensor-3".to_string(),
+            ),
+        ];
+        assert_eq!(
+            requested_publisher_allowlist(&Some(properties)),
+            Some(vec![
+                "sensor-1".to_string(),
+                "sensor-2".to_string(),
+                "sensor-3".to_string()
+            ])
+        );
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The broker caches and the client connections shared by the tests of the push threads.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use common_base::config::broker_mqtt::init_broker_mqtt_conf_by_path;
+use common_base::tools::{now_second, unique_id};
+use futures::StreamExt;
+use grpc_clients::pool::ClientPool;
+use metadata_struct::adapter::record::Record;
+use metadata_struct::mqtt::message::MqttMessage;
+use metadata_struct::mqtt::session::MqttSession;
+use protocol::mqtt::codec::MqttCodec;
+use protocol::mqtt::common::{MqttPacket, QoS};
+use storage_adapter::memory::MemoryStorageAdapter;
+use storage_adapter::storage::StorageAdapter;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use super::exclusive_push::ExclusivePush;
+use super::share_leader_push::ShareLeaderPush;
+use super::subscribe_manager::SubscribeManager;
+use super::subscriber::Subscriber;
+use crate::handler::cache::CacheManager;
+use crate::handler::cluster_config::build_default_cluster_config;
+use crate::server::connection::{NetworkConnection, NetworkConnectionType};
+use crate::server::connection_manager::ConnectionManager;
+use crate::storage::message::MessageStorage;
+
+pub struct PushFixture<S> {
+    pub client_pool: Arc<ClientPool>,
+    pub cache_manager: Arc<CacheManager>,
+    pub subscribe_manager: Arc<SubscribeManager>,
+    pub connection_manager: Arc<ConnectionManager>,
+    pub storage_adapter: Arc<S>,
+    pub message_storage: MessageStorage<S>,
+}
+
+// A broker with the default cluster config and the memory storage
+pub fn build_push_fixture() -> PushFixture<MemoryStorageAdapter> {
+    build_push_fixture_with_storage(Arc::new(MemoryStorageAdapter::new()))
+}
+
+pub fn build_push_fixture_with_storage<S>(storage_adapter: Arc<S>) -> PushFixture<S>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let path = format!(
+        "{}/../../config/mqtt-server.toml",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    init_broker_mqtt_conf_by_path(&path);
+
+    let client_pool = Arc::new(ClientPool::new(1));
+    let cache_manager = Arc::new(CacheManager::new(client_pool.clone(), unique_id()));
+    cache_manager.set_cluster_info(build_default_cluster_config());
+    let connection_manager = Arc::new(ConnectionManager::new(cache_manager.clone()));
+    PushFixture {
+        client_pool,
+        cache_manager,
+        subscribe_manager: Arc::new(SubscribeManager::new()),
+        connection_manager,
+        message_storage: MessageStorage::new(storage_adapter.clone()),
+        storage_adapter,
+    }
+}
+
+impl<S> PushFixture<S>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    // Registers a MQTT 5 tcp connection in the connection manager and returns the
+    // connection id and the client side of it.
+    pub async fn connect(&self) -> (u64, FramedRead<TcpStream, MqttCodec>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client_stream = TcpStream::connect(addr).await.unwrap();
+        let (server_stream, peer_addr) = listener.accept().await.unwrap();
+        let (_, w_stream) = tokio::io::split(server_stream);
+
+        let connection_id = self
+            .connection_manager
+            .add_connection(NetworkConnection::new(
+                NetworkConnectionType::Tcp,
+                peer_addr,
+                None,
+            ));
+        self.connection_manager
+            .set_connect_protocol(connection_id, 5);
+        self.connection_manager.add_tcp_write(
+            connection_id,
+            FramedWrite::new(w_stream, MqttCodec::new(None)),
+        );
+        (
+            connection_id,
+            FramedRead::new(client_stream, MqttCodec::new(Some(5))),
+        )
+    }
+
+    // A client with a session on the connection, which may not be registered
+    pub fn add_session(&self, connection_id: u64) -> String {
+        let client_id = unique_id();
+        let mut session = MqttSession::new(client_id.clone(), 60, false, None);
+        session.connection_id = Some(connection_id);
+        self.cache_manager.add_session(client_id.clone(), session);
+        client_id
+    }
+
+    // A client with a session on a new MQTT 5 tcp connection
+    pub async fn connect_client(&self) -> (String, u64, FramedRead<TcpStream, MqttCodec>) {
+        let (connection_id, read_stream) = self.connect().await;
+        let client_id = self.add_session(connection_id);
+        (client_id, connection_id, read_stream)
+    }
+
+    // The exclusive subscription of the client to the topic, the sub_path is the topic name
+    pub fn add_exclusive_push(
+        &self,
+        client_id: &str,
+        sub_path: &str,
+        topic_id: &str,
+        qos: QoS,
+    ) -> Subscriber {
+        let subscriber = Subscriber {
+            client_id: client_id.to_owned(),
+            sub_path: sub_path.to_owned(),
+            topic_name: sub_path.to_owned(),
+            topic_id: topic_id.to_owned(),
+            qos,
+            ..Default::default()
+        };
+        self.subscribe_manager.add_exclusive_push(
+            client_id,
+            sub_path,
+            topic_id,
+            subscriber.clone(),
+        );
+        subscriber
+    }
+
+    pub fn exclusive_push(&self) -> ExclusivePush<S> {
+        ExclusivePush::new(
+            self.storage_adapter.clone(),
+            self.cache_manager.clone(),
+            self.subscribe_manager.clone(),
+            self.connection_manager.clone(),
+        )
+    }
+
+    // Starts the push threads of the exclusive subscriptions added so far
+    pub async fn start_exclusive_push(&self) -> ExclusivePush<S> {
+        let exclusive_push = self.exclusive_push();
+        exclusive_push.start_push_thread().await;
+        exclusive_push
+    }
+
+    // Starts the push threads of the shared subscriptions added so far
+    pub async fn start_share_leader_push(&self) -> ShareLeaderPush<S> {
+        let share_leader_push = ShareLeaderPush::new(
+            self.subscribe_manager.clone(),
+            self.storage_adapter.clone(),
+            self.connection_manager.clone(),
+            self.cache_manager.clone(),
+        );
+        share_leader_push.start_push_thread().await;
+        share_leader_push
+    }
+
+    pub fn stop_push_threads(&self) {
+        for (_, sx) in self.subscribe_manager.exclusive_push_thread.clone() {
+            sx.send(true).unwrap();
+        }
+        for (_, sx) in self.subscribe_manager.share_leader_push_thread.clone() {
+            sx.send(true).unwrap();
+        }
+    }
+}
+
+pub fn build_record(topic_name: &str, i: u64) -> Record {
+    let message = MqttMessage {
+        client_id: "publisher".to_string(),
+        topic: Bytes::from(topic_name.to_owned()),
+        payload: Bytes::from(format!("message-{}", i)),
+        expiry_interval: now_second() + 3600,
+        create_time: now_second(),
+        ..Default::default()
+    };
+    Record::build_byte(message.encode())
+}
+
+pub async fn read_packet(read_stream: &mut FramedRead<TcpStream, MqttCodec>) -> MqttPacket {
+    timeout(Duration::from_secs(10), read_stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+}
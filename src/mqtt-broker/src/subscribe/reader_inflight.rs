@@ -0,0 +1,96 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Bounds the records a push thread holds in memory, read from the topic but not yet
+/// delivered to the client. The reader stops reading once `max` records are held and resumes
+/// as the held records are delivered. A `max` of 0 does not bound the records.
+pub struct ReaderInflight {
+    max: u64,
+    held: u64,
+}
+
+impl ReaderInflight {
+    pub fn new(max: u32) -> Self {
+        ReaderInflight {
+            max: max as u64,
+            held: 0,
+        }
+    }
+
+    /// How many of the record_num records may be read now.
+    pub fn available(&self, record_num: u64) -> u64 {
+        if self.max == 0 {
+            return record_num;
+        }
+        record_num.min(self.max.saturating_sub(self.held))
+    }
+
+    pub fn held(&self) -> u64 {
+        self.held
+    }
+
+    /// Holds the num records just read until the returned guard is dropped, once they are
+    /// delivered or dropped.
+    pub fn hold(&mut self, num: u64) -> HeldRecords<'_> {
+        self.held = self.held.saturating_add(num);
+        HeldRecords {
+            inflight: self,
+            num,
+        }
+    }
+}
+
+pub struct HeldRecords<'a> {
+    inflight: &'a mut ReaderInflight,
+    num: u64,
+}
+
+impl HeldRecords<'_> {
+    pub fn held(&self) -> u64 {
+        self.inflight.held
+    }
+}
+
+impl Drop for HeldRecords<'_> {
+    fn drop(&mut self) {
+        self.inflight.held = self.inflight.held.saturating_sub(self.num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReaderInflight;
+
+    #[test]
+    fn available_test() {
+        let inflight = ReaderInflight::new(0);
+        assert_eq!(inflight.available(100), 100);
+
+        let mut inflight = ReaderInflight::new(8);
+        assert_eq!(inflight.available(5), 5);
+        inflight.held = 5;
+        assert_eq!(inflight.available(5), 3);
+        inflight.held = 8;
+        assert_eq!(inflight.available(5), 0);
+
+        inflight.held = 0;
+        {
+            let held = inflight.hold(6);
+            assert_eq!(held.held(), 6);
+        }
+        // the records are released once delivered
+        assert_eq!(inflight.held(), 0);
+        assert_eq!(inflight.available(5), 5);
+    }
+}
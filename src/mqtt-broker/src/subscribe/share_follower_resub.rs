@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use common_base::config::broker_mqtt::broker_mqtt_conf;
 use common_base::tools::{now_second, unique_id};
@@ -36,13 +36,14 @@ use tokio::{io, select};
 use tokio_util::codec::{FramedRead, FramedWrite};
 
 use super::sub_common::{
-    get_share_sub_leader, publish_message_qos0, publish_message_to_client, qos2_send_publish,
-    qos2_send_pubrel, wait_packet_ack,
+    delivery_dup, get_share_sub_leader, publish_message_qos0, publish_message_to_client,
+    qos2_send_publish, qos2_send_pubrel, redelivery_properties, send_packet_ack, wait_packet_ack,
 };
 use super::subscribe_manager::SubscribeManager;
 use super::subscriber::SubPublishParam;
 use crate::handler::cache::{CacheManager, QosAckPackageData, QosAckPackageType, QosAckPacketInfo};
 use crate::handler::error::MqttBrokerError;
+use crate::observability::metrics::publish::{record_message_dropped, MessageDropReason};
 use crate::server::connection_manager::ConnectionManager;
 use crate::server::packet::ResponsePackage;
 use crate::subscribe::subscribe_manager::ShareSubShareSub;
@@ -368,7 +369,7 @@ async fn process_packet(
                     }
 
                     protocol::mqtt::common::QoS::AtLeastOnce => {
-                        let (wait_puback_sx, _) = broadcast::channel(1);
+                        let (wait_puback_sx, _wait_puback_rx) = broadcast::channel(1);
                         cache_manager.add_ack_packet(
                             &mqtt_client_id,
                             publish_to_client_pkid,
@@ -401,7 +402,7 @@ async fn process_packet(
                     }
 
                     protocol::mqtt::common::QoS::ExactlyOnce => {
-                        let (wait_client_ack_sx, _) = broadcast::channel(1);
+                        let (wait_client_ack_sx, _wait_client_ack_rx) = broadcast::channel(1);
 
                         cache_manager.add_ack_packet(
                             &mqtt_client_id,
@@ -412,7 +413,7 @@ async fn process_packet(
                             },
                         );
 
-                        let (wait_leader_ack_sx, _) = broadcast::channel(1);
+                        let (wait_leader_ack_sx, _wait_leader_ack_rx) = broadcast::channel(1);
                         cache_manager.add_ack_packet(
                             &follower_sub_leader_client_id,
                             publish.pkid,
@@ -455,19 +456,14 @@ async fn process_packet(
         }
 
         MqttPacket::PubRel(pubrel, _) => {
-            if let Some(data) =
-                cache_manager.get_ack_packet(follower_sub_leader_client_id.to_owned(), pubrel.pkid)
-            {
-                match data.sx.send(QosAckPackageData {
+            send_packet_ack(
+                cache_manager,
+                follower_sub_leader_client_id,
+                QosAckPackageData {
                     ack_type: QosAckPackageType::PubRel,
                     pkid: pubrel.pkid,
-                }) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("{}", e);
-                    }
-                }
-            }
+                },
+            );
             false
         }
 
@@ -533,6 +529,8 @@ async fn resub_publish_message_qos1(
     let mut retry_times = 0;
     let current_message_pkid = sub_pub_param.pkid;
     let mut publish = sub_pub_param.publish.clone();
+    let mut properties = sub_pub_param.properties.clone();
+    let first_sent = Instant::now();
     loop {
         if let Ok(flag) = stop_sx.subscribe().try_recv() {
             if flag {
@@ -550,17 +548,22 @@ async fn resub_publish_message_qos1(
 
         if let Some(conn) = metadata_cache.get_connection(connect_id) {
             if publish.payload.len() > (conn.max_packet_size as usize) {
+                record_message_dropped(MessageDropReason::Oversized, publish.qos, 1);
                 return Ok(());
             }
         }
 
         retry_times += 1;
         publish.pkid = sub_pub_param.pkid;
-        publish.dup = retry_times >= 2;
+        publish.dup = delivery_dup(publish.qos, retry_times >= 2);
+        if retry_times >= 2 {
+            properties = redelivery_properties(&sub_pub_param.properties, first_sent);
+        }
 
         let resp = ResponsePackage {
             connection_id: connect_id,
-            packet: MqttPacket::Publish(publish.clone(), sub_pub_param.properties.clone()),
+            packet: MqttPacket::Publish(publish.clone(), properties.clone()),
+            close_after_send: false,
         };
 
         // 2. publish to mqtt client
@@ -12,38 +12,48 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use bytes::Bytes;
 use common_base::tools::now_second;
-use log::{error, info};
-use metadata_struct::mqtt::message::MqttMessage;
-use protocol::mqtt::common::{MqttPacket, MqttProtocol, Publish, PublishProperties, QoS};
+use log::{error, info, warn};
+use protocol::mqtt::common::{MqttPacket, MqttProtocol, QoS};
 use storage_adapter::storage::StorageAdapter;
 use tokio::select;
 use tokio::sync::broadcast::{self, Sender};
 use tokio::time::sleep;
 
+use super::delivery_builder::DeliveryBuilder;
+use super::fan_out_read::{decode_records, FanOutReader, FanOutSubscription};
 use super::sub_common::{
-    loop_commit_offset, min_qos, publish_message_qos0, publish_message_to_client,
-    qos2_send_publish, qos2_send_pubrel, wait_packet_ack,
+    disconnect_unauthorized_subscriber, loop_commit_offset, order_record_offsets,
+    publish_message_qos0, publish_message_to_client, qos2_send_publish, qos2_send_pubrel,
+    wait_packet_ack,
 };
 use super::subscribe_manager::{ShareLeaderSubscribeData, SubscribeManager};
 use crate::handler::cache::{CacheManager, QosAckPackageData, QosAckPackageType, QosAckPacketInfo};
 use crate::handler::error::MqttBrokerError;
 use crate::handler::message::is_message_expire;
+use crate::observability::metrics::publish::{record_message_dropped, MessageDropReason};
+use crate::observability::metrics::server::{
+    metrics_push_thread_num, PUSH_THREAD_TYPE_SHARE_LEADER,
+};
+use crate::security::acl::auth::is_allow_delivery;
+use crate::security::encryption::decrypt_message_payload;
 use crate::server::connection_manager::ConnectionManager;
 use crate::server::packet::ResponsePackage;
 use crate::storage::message::MessageStorage;
 use crate::subscribe::subscriber::SubPublishParam;
 use crate::subscribe::subscriber::Subscriber;
+
 #[derive(Clone)]
 pub struct ShareLeaderPush<S> {
     pub subscribe_manager: Arc<SubscribeManager>,
     message_storage: Arc<S>,
     connection_manager: Arc<ConnectionManager>,
     cache_manager: Arc<CacheManager>,
+    fan_out_reader: Arc<FanOutReader<S>>,
 }
 
 impl<S> ShareLeaderPush<S>
@@ -58,6 +68,7 @@ where
     ) -> Self {
         ShareLeaderPush {
             subscribe_manager,
+            fan_out_reader: Arc::new(FanOutReader::new(message_storage.clone())),
             message_storage,
             connection_manager,
             cache_manager,
@@ -68,6 +79,10 @@ where
         loop {
             self.start_push_thread().await;
             self.try_thread_gc();
+            metrics_push_thread_num(
+                PUSH_THREAD_TYPE_SHARE_LEADER,
+                self.subscribe_manager.share_leader_push_thread.len(),
+            );
             sleep(Duration::from_secs(1)).await;
         }
     }
@@ -136,11 +151,11 @@ where
     ) {
         let (sub_thread_stop_sx, mut sub_thread_stop_rx) = broadcast::channel(1);
 
-        let group_id = format!(
-            "system_sub_{}_{}_{}",
-            sub_data.group_name, sub_data.sub_name, sub_data.topic_id
-        );
-        let cursor_point = 0;
+        let group_id =
+            share_leader_group_id(&sub_data.group_name, &sub_data.sub_name, &sub_data.topic_id);
+        // The member the last message went to, kept across the reads so that every member of
+        // the group takes its turn
+        let mut cursor_point = 0;
 
         let message_storage = MessageStorage::new(self.message_storage.clone());
 
@@ -161,6 +176,16 @@ where
         let connection_manager = self.connection_manager.clone();
         let cache_manager = self.cache_manager.clone();
 
+        // The groups of the topic share a single read of its messages
+        let mut fan_out = if cache_manager.get_cluster_info().protocol.fan_out_read_once {
+            Some(
+                self.fan_out_reader
+                    .subscribe(&sub_data.topic_id, &group_id, offset),
+            )
+        } else {
+            None
+        };
+
         tokio::spawn(async move {
             info!(
                 "Share leader push data thread for GroupName {}/{},Topic [{}] was started successfully",
@@ -187,10 +212,11 @@ where
                         &connection_manager,
                         &cache_manager,
                         &message_storage,
+                        &mut fan_out,
                         &sub_data,
                         &sub_list,
                         &group_id,
-                        cursor_point,
+                        &mut cursor_point,
                         offset,
                         &sub_thread_stop_sx
                     ) =>{
@@ -229,14 +255,51 @@ where
 }
 
 #[allow(clippy::too_many_arguments)]
+// The consumer group the offset of a shared subscription of a topic is committed under
+pub fn share_leader_group_id(group_name: &str, sub_name: &str, topic_id: &str) -> String {
+    format!("system_sub_{}_{}_{}", group_name, sub_name, topic_id)
+}
+
+// (group_name, lag) of the share groups led by this broker, the lag of a group is the sum of
+// the lags of the topics it is subscribed to.
+pub async fn share_group_lag<S>(
+    subscribe_manager: &Arc<SubscribeManager>,
+    message_storage: &MessageStorage<S>,
+) -> Result<HashMap<String, u64>, MqttBrokerError>
+where
+    S: StorageAdapter + Sync + Send + 'static + Clone,
+{
+    let subscriptions: Vec<(String, String, String)> = subscribe_manager
+        .share_leader_push
+        .iter()
+        .map(|raw| {
+            let sub_data = raw.value();
+            (
+                sub_data.group_name.clone(),
+                sub_data.sub_name.clone(),
+                sub_data.topic_id.clone(),
+            )
+        })
+        .collect();
+
+    let mut results = HashMap::new();
+    for (group_name, sub_name, topic_id) in subscriptions {
+        let group_id = share_leader_group_id(&group_name, &sub_name, &topic_id);
+        let lag = message_storage.group_lag(&group_id, &topic_id).await?;
+        *results.entry(group_name).or_insert(0) += lag;
+    }
+    Ok(results)
+}
+
 async fn read_message_process<S>(
     connection_manager: &Arc<ConnectionManager>,
     cache_manager: &Arc<CacheManager>,
     message_storage: &MessageStorage<S>,
+    fan_out: &mut Option<FanOutSubscription<S>>,
     sub_data: &ShareLeaderSubscribeData,
     sub_list: &[Subscriber],
     group_id: &str,
-    mut cursor_point: usize,
+    cursor_point: &mut usize,
     offset: u64,
     stop_sx: &Sender<bool>,
 ) -> Result<Option<u64>, MqttBrokerError>
@@ -245,18 +308,45 @@ where
 {
     let record_num = calc_record_num(sub_list.len());
 
-    let results = message_storage
-        .read_topic_message(&sub_data.topic_id, offset, record_num as u64)
-        .await?;
+    let mut results = if let Some(fan_out) = fan_out {
+        fan_out.read(offset, record_num as u64).await?
+    } else {
+        let records = message_storage
+            .read_topic_message(&sub_data.topic_id, offset, record_num as u64)
+            .await?;
+        decode_records(records)?
+    };
 
     if results.is_empty() {
         return Ok(None);
     }
+    order_record_offsets(
+        &sub_data.topic_id,
+        &mut results,
+        |record| record.offset,
+        cache_manager
+            .get_cluster_info()
+            .protocol
+            .strict_record_offset_order,
+    )?;
 
     for record in results.iter() {
-        let msg = MqttMessage::decode_record(record.clone())?;
+        let mut msg = record.message.clone();
+        msg.payload =
+            match decrypt_message_payload(cache_manager, &sub_data.topic_name, &msg.payload) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    error!(
+                        "message of topic {} is not pushed to the client and is discarded, {}",
+                        sub_data.topic_name, e
+                    );
+                    record_message_dropped(MessageDropReason::Quarantined, msg.qos, 1);
+                    continue;
+                }
+            };
 
         if is_message_expire(&msg) {
+            record_message_dropped(MessageDropReason::Expired, msg.qos, 1);
             continue;
         }
 
@@ -264,18 +354,49 @@ where
         loop {
             if loop_times > try_loop_times(sub_list.len()) {
                 error!("Share subscription push message fails, dropping the message, possibly because no subscriber is available");
+                record_message_dropped(MessageDropReason::NoSubscriber, msg.qos, 1);
                 break;
             }
 
-            cursor_point = choose_available_sub(cursor_point, sub_list);
-            let subscribe = if let Some(sub) = sub_list.get(cursor_point) {
+            *cursor_point = choose_available_sub(*cursor_point, sub_list);
+            let subscribe = if let Some(sub) = sub_list.get(*cursor_point) {
                 sub.clone()
             } else {
                 continue;
             };
 
-            if let Some((mut publish, properties)) =
-                build_publish(cache_manager, &subscribe, &sub_data.topic_name, &msg)
+            // A member without a connection is out of the rotation until it reconnects
+            if cache_manager.get_connect_id(&subscribe.client_id).is_none() {
+                loop_times += 1;
+                continue;
+            }
+
+            // The message goes to another member of the group if this one is no longer authorized
+            if !is_allow_delivery(cache_manager, &subscribe.client_id, &sub_data.topic_name) {
+                warn!(
+                    "client_id [{}] is not authorized to receive messages of topic [{}], skipped",
+                    subscribe.client_id, sub_data.topic_name
+                );
+                if cache_manager
+                    .get_cluster_info()
+                    .security
+                    .delivery_acl_deny_disconnect
+                {
+                    disconnect_unauthorized_subscriber(
+                        cache_manager,
+                        connection_manager,
+                        &subscribe.client_id,
+                    )
+                    .await;
+                }
+                loop_times += 1;
+                continue;
+            }
+
+            if let Some((mut publish, properties)) = DeliveryBuilder::new(&subscribe)
+                .max_qos(cache_manager.get_cluster_info().protocol.max_qos)
+                .topic_name(&sub_data.topic_name)
+                .build(msg.clone())
             {
                 let pkid = if publish.qos != QoS::AtMostOnce {
                     cache_manager.get_pkid(&subscribe.client_id).await
@@ -299,7 +420,7 @@ where
                     cache_manager,
                     message_storage,
                     sub_pub_param,
-                    record.offset.unwrap(),
+                    record.offset,
                     stop_sx,
                 )
                 .await
@@ -311,15 +432,9 @@ where
         }
 
         // commit offset
-        loop_commit_offset(
-            message_storage,
-            &sub_data.topic_id,
-            group_id,
-            record.offset.unwrap(),
-        )
-        .await;
+        loop_commit_offset(message_storage, &sub_data.topic_id, group_id, record.offset).await;
     }
-    Ok(results.last().unwrap().offset)
+    Ok(results.last().map(|record| record.offset))
 }
 
 fn try_loop_times(sub_len: usize) -> usize {
@@ -353,7 +468,7 @@ where
         }
 
         QoS::AtLeastOnce => {
-            let (wait_puback_sx, _) = broadcast::channel(1);
+            let (wait_puback_sx, _wait_puback_rx) = broadcast::channel(1);
             cache_manager.add_ack_packet(
                 &sub_pub_param.subscribe.client_id,
                 sub_pub_param.pkid,
@@ -391,7 +506,7 @@ where
         }
 
         QoS::ExactlyOnce => {
-            let (wait_ack_sx, _) = broadcast::channel(1);
+            let (wait_ack_sx, _wait_ack_rx) = broadcast::channel(1);
             cache_manager.add_ack_packet(
                 &sub_pub_param.subscribe.client_id,
                 sub_pub_param.pkid,
@@ -422,52 +537,6 @@ where
     }
 }
 
-fn build_publish(
-    metadata_cache: &Arc<CacheManager>,
-    subscribe: &Subscriber,
-    topic_name: &str,
-    msg: &MqttMessage,
-) -> Option<(Publish, PublishProperties)> {
-    let cluster_qos = metadata_cache.get_cluster_info().protocol.max_qos;
-    let qos = min_qos(cluster_qos, subscribe.qos);
-
-    let retain = if subscribe.preserve_retain {
-        msg.retain
-    } else {
-        false
-    };
-
-    if subscribe.nolocal && (subscribe.client_id == msg.client_id) {
-        return None;
-    }
-
-    let publish = Publish {
-        dup: false,
-        qos,
-        pkid: 0,
-        retain,
-        topic: Bytes::from(topic_name.to_owned()),
-        payload: msg.payload.clone(),
-    };
-
-    let mut sub_ids = Vec::new();
-    if let Some(id) = subscribe.subscription_identifier {
-        sub_ids.push(id);
-    }
-
-    let properties = PublishProperties {
-        payload_format_indicator: msg.format_indicator,
-        message_expiry_interval: Some(msg.expiry_interval as u32),
-        topic_alias: None,
-        response_topic: msg.response_topic.clone(),
-        correlation_data: msg.correlation_data.clone(),
-        user_properties: msg.user_properties.clone(),
-        subscription_identifiers: sub_ids,
-        content_type: msg.content_type.clone(),
-    };
-    Some((publish, properties))
-}
-
 // To avoid messages that are not successfully pushed to the client. When the client Session expires,
 // the push thread will exit automatically and will not attempt to push again.
 async fn share_leader_publish_message_qos1(
@@ -508,11 +577,13 @@ async fn share_leader_publish_message_qos1(
                 sub_pub_param.publish.clone(),
                 sub_pub_param.properties.clone(),
             ),
+            close_after_send: false,
         }
     } else {
         ResponsePackage {
             connection_id: connect_id,
             packet: MqttPacket::Publish(sub_pub_param.publish.clone(), None),
+            close_after_send: false,
         }
     };
 
@@ -525,15 +596,27 @@ async fn share_leader_publish_message_qos1(
     .await
     {
         Ok(_) => {
-            if let Some(data) = wait_packet_ack(wait_puback_sx).await {
-                if data.ack_type == QosAckPackageType::PubAck && data.pkid == sub_pub_param.pkid {
-                    return Ok(());
+            select! {
+                val = wait_packet_ack(wait_puback_sx) => {
+                    if let Some(data) = val {
+                        if data.ack_type == QosAckPackageType::PubAck
+                            && data.pkid == sub_pub_param.pkid
+                        {
+                            return Ok(());
+                        }
+                    }
+                    Err(MqttBrokerError::CommonError(
+                        "QOS1 publishes a message and waits for the PubAck packet to fail to be received"
+                            .to_string(),
+                    ))
+                }
+                _ = wait_member_disconnect(connection_manager, Some(connect_id)) => {
+                    Err(MqttBrokerError::CommonError(format!(
+                        "Client [{}] disconnected before the PubAck packet was received",
+                        sub_pub_param.subscribe.client_id
+                    )))
                 }
             }
-            Err(MqttBrokerError::CommonError(
-                "QOS1 publishes a message and waits for the PubAck packet to fail to be received"
-                    .to_string(),
-            ))
         }
         Err(e) => Err(MqttBrokerError::CommonError(format!(
             "Failed to write QOS1 Publish message to response queue, failure message: {}",
@@ -561,6 +644,7 @@ where
 {
     // 1. send Publish to Client
     qos2_send_publish(connection_manager, cache_manager, sub_pub_param, stop_sx).await?;
+    let connect_id = cache_manager.get_connect_id(&sub_pub_param.subscribe.client_id);
 
     // 2. wait pub rec
     loop {
@@ -569,7 +653,16 @@ where
                 return Ok(());
             }
         }
-        if let Some(data) = wait_packet_ack(wait_ack_sx).await {
+        let val = select! {
+            val = wait_packet_ack(wait_ack_sx) => val,
+            _ = wait_member_disconnect(connection_manager, connect_id) => {
+                return Err(MqttBrokerError::CommonError(format!(
+                    "Client [{}] disconnected before the PubRec packet was received",
+                    sub_pub_param.subscribe.client_id
+                )));
+            }
+        };
+        if let Some(data) = val {
             if data.ack_type == QosAckPackageType::PubRec && data.pkid == sub_pub_param.pkid {
                 // When sending a QOS2 message, as long as the pubrec is received, the offset can be submitted,
                 // the pubrel is sent asynchronously, and the pubcomp is waited for. Push the next message at the same time.
@@ -616,6 +709,17 @@ where
     Ok(())
 }
 
+// Returns once the connection the message was sent on is closed, the message then goes to
+// another member of the group rather than waiting for this one to reconnect
+async fn wait_member_disconnect(
+    connection_manager: &Arc<ConnectionManager>,
+    connect_id: Option<u64>,
+) {
+    if let Some(connect_id) = connect_id {
+        connection_manager.wait_connection_close(connect_id).await;
+    }
+}
+
 fn build_share_leader_sub_list(
     subscribe_manager: &Arc<SubscribeManager>,
     key: &str,
@@ -626,11 +730,10 @@ fn build_share_leader_sub_list(
         return Vec::new();
     };
 
-    let mut result = Vec::new();
-    for (_, sub) in sub_list {
-        result.push(sub);
-    }
-    result
+    // The members keep their turn when the list is refreshed
+    let mut result: Vec<(String, Subscriber)> = sub_list.into_iter().collect();
+    result.sort_by(|a, b| a.0.cmp(&b.0));
+    result.into_iter().map(|(_, sub)| sub).collect()
 }
 
 fn calc_record_num(sub_len: usize) -> usize {
@@ -646,4 +749,536 @@ fn calc_record_num(sub_len: usize) -> usize {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::async_trait;
+    use bytes::Bytes;
+    use common_base::error::common::CommonError;
+    use common_base::tools::{now_second, unique_id};
+    use futures::StreamExt;
+    use metadata_struct::adapter::read_config::ReadConfig;
+    use metadata_struct::adapter::record::Record;
+    use metadata_struct::mqtt::message::MqttMessage;
+    use protocol::mqtt::codec::MqttCodec;
+    use protocol::mqtt::common::{MqttPacket, MqttProtocol, Publish, QoS};
+    use storage_adapter::memory::MemoryStorageAdapter;
+    use storage_adapter::storage::{ShardInfo, ShardOffset, StorageAdapter};
+    use tokio::net::TcpStream;
+    use tokio::time::{sleep, timeout};
+    use tokio_util::codec::FramedRead;
+
+    use crate::handler::cache::{CacheManager, QosAckPackageData, QosAckPackageType};
+    use crate::handler::cluster_config::build_default_cluster_config;
+    use crate::storage::message::MessageStorage;
+    use crate::subscribe::push_fixture::{
+        build_push_fixture, build_push_fixture_with_storage, build_record, read_packet, PushFixture,
+    };
+    use crate::subscribe::sub_common::{decode_share_info, share_group_key};
+    use crate::subscribe::subscriber::Subscriber;
+
+    // Counts the records read from the memory storage
+    #[derive(Clone, Default)]
+    struct CountingStorageAdapter {
+        inner: Arc<MemoryStorageAdapter>,
+        read_records: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl StorageAdapter for CountingStorageAdapter {
+        async fn create_shard(&self, shard: ShardInfo) -> Result<(), CommonError> {
+            self.inner.create_shard(shard).await
+        }
+
+        async fn list_shard(
+            &self,
+            namespace: String,
+            shard_name: String,
+        ) -> Result<Vec<ShardInfo>, CommonError> {
+            self.inner.list_shard(namespace, shard_name).await
+        }
+
+        async fn delete_shard(
+            &self,
+            namespace: String,
+            shard_name: String,
+        ) -> Result<(), CommonError> {
+            self.inner.delete_shard(namespace, shard_name).await
+        }
+
+        async fn write(
+            &self,
+            namespace: String,
+            shard_name: String,
+            data: Record,
+        ) -> Result<u64, CommonError> {
+            self.inner.write(namespace, shard_name, data).await
+        }
+
+        async fn batch_write(
+            &self,
+            namespace: String,
+            shard_name: String,
+            data: Vec<Record>,
+        ) -> Result<Vec<u64>, CommonError> {
+            self.inner.batch_write(namespace, shard_name, data).await
+        }
+
+        async fn read_by_offset(
+            &self,
+            namespace: String,
+            shard_name: String,
+            offset: u64,
+            read_config: ReadConfig,
+        ) -> Result<Vec<Record>, CommonError> {
+            let records = self
+                .inner
+                .read_by_offset(namespace, shard_name, offset, read_config)
+                .await?;
+            self.read_records
+                .fetch_add(records.len() as u64, Ordering::SeqCst);
+            Ok(records)
+        }
+
+        async fn read_latest(
+            &self,
+            namespace: String,
+            shard_name: String,
+            read_config: ReadConfig,
+        ) -> Result<Vec<Record>, CommonError> {
+            self.inner
+                .read_latest(namespace, shard_name, read_config)
+                .await
+        }
+
+        async fn read_by_tag(
+            &self,
+            namespace: String,
+            shard_name: String,
+            offset: u64,
+            tag: String,
+            read_config: ReadConfig,
+        ) -> Result<Vec<Record>, CommonError> {
+            self.inner
+                .read_by_tag(namespace, shard_name, offset, tag, read_config)
+                .await
+        }
+
+        async fn read_by_key(
+            &self,
+            namespace: String,
+            shard_name: String,
+            offset: u64,
+            key: String,
+            read_config: ReadConfig,
+        ) -> Result<Vec<Record>, CommonError> {
+            self.inner
+                .read_by_key(namespace, shard_name, offset, key, read_config)
+                .await
+        }
+
+        async fn get_offset_by_timestamp(
+            &self,
+            namespace: String,
+            shard_name: String,
+            timestamp: u64,
+        ) -> Result<Option<ShardOffset>, CommonError> {
+            self.inner
+                .get_offset_by_timestamp(namespace, shard_name, timestamp)
+                .await
+        }
+
+        async fn get_offset_by_group(
+            &self,
+            group_name: String,
+        ) -> Result<Vec<ShardOffset>, CommonError> {
+            self.inner.get_offset_by_group(group_name).await
+        }
+
+        async fn commit_offset(
+            &self,
+            group_name: String,
+            namespace: String,
+            offset: HashMap<String, u64>,
+        ) -> Result<(), CommonError> {
+            self.inner
+                .commit_offset(group_name, namespace, offset)
+                .await
+        }
+
+        async fn close(&self) -> Result<(), CommonError> {
+            self.inner.close().await
+        }
+    }
+
+    #[tokio::test]
+    async fn fan_out_read_once_test() {
+        let fixture = build_push_fixture_with_storage(Arc::new(CountingStorageAdapter::default()));
+        let mut cluster = build_default_cluster_config();
+        cluster.protocol.fan_out_read_once = true;
+        fixture.cache_manager.set_cluster_info(cluster);
+
+        let topic_id = unique_id();
+        let topic_name = "/test/fan_out".to_string();
+        let sub_name = topic_name.clone();
+
+        // 100 shared subscription groups with a member each. The members have no network
+        // connection, so the messages are dropped once they are pushed.
+        let group_names: Vec<String> = (0..100).map(|i| format!("group-{}", i)).collect();
+        for (i, group_name) in group_names.iter().enumerate() {
+            let client_id = fixture.add_session(i as u64 + 1);
+            fixture.subscribe_manager.add_share_subscribe_leader(
+                &sub_name,
+                Subscriber {
+                    protocol: MqttProtocol::Mqtt5,
+                    client_id,
+                    sub_path: format!("$share/{}{}", group_name, topic_name),
+                    topic_name: topic_name.clone(),
+                    group_name: Some(group_name.clone()),
+                    topic_id: topic_id.clone(),
+                    qos: QoS::AtMostOnce,
+                    ..Default::default()
+                },
+            );
+        }
+        assert_eq!(fixture.subscribe_manager.share_leader_push.len(), 100);
+
+        let records = (0..1000).map(|i| build_record(&topic_name, i)).collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+        fixture.start_share_leader_push().await;
+
+        // every group consumes the 1000 messages and commits its own offset
+        timeout(Duration::from_secs(60), async {
+            loop {
+                let mut done = 0;
+                for group_name in group_names.iter() {
+                    let group_id = format!("system_sub_{}_{}_{}", group_name, sub_name, topic_id);
+                    if fixture
+                        .message_storage
+                        .get_group_offset(&group_id)
+                        .await
+                        .unwrap()
+                        == 999
+                    {
+                        done += 1;
+                    }
+                }
+                if done == group_names.len() {
+                    break;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // the topic was read once for all the groups, instead of once per group
+        assert_eq!(
+            fixture.storage_adapter.read_records.load(Ordering::SeqCst),
+            1000
+        );
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn queue_subscribe_round_robin_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let topic_name = "/sensor-data".to_string();
+        let sub_path = "$queue/sensor-data".to_string();
+        let (group_name, sub_name) = share_group_key(&sub_path);
+
+        let mut read_streams = Vec::new();
+        for _ in 0..3 {
+            let (client_id, _, read_stream) = fixture.connect_client().await;
+            read_streams.push(read_stream);
+            fixture.subscribe_manager.add_share_subscribe_leader(
+                &sub_name,
+                Subscriber {
+                    protocol: MqttProtocol::Mqtt5,
+                    client_id,
+                    sub_path: sub_path.clone(),
+                    topic_name: topic_name.clone(),
+                    group_name: Some(group_name.clone()),
+                    topic_id: topic_id.clone(),
+                    qos: QoS::AtMostOnce,
+                    ..Default::default()
+                },
+            );
+        }
+        // the three queue subscribers are members of the same shared subscription
+        assert_eq!(fixture.subscribe_manager.share_leader_push.len(), 1);
+
+        let records = (0..30).map(|i| build_record(&topic_name, i)).collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+        fixture.start_share_leader_push().await;
+
+        let mut payloads = Vec::new();
+        for read_stream in read_streams.iter_mut() {
+            for _ in 0..10 {
+                let MqttPacket::Publish(publish, _) = read_packet(read_stream).await else {
+                    panic!("expected a PUBLISH");
+                };
+                payloads.push(publish.payload);
+            }
+        }
+
+        // every message was received by exactly one of the subscribers
+        payloads.sort();
+        payloads.dedup();
+        assert_eq!(payloads.len(), 30);
+        for read_stream in read_streams.iter_mut() {
+            assert!(timeout(Duration::from_millis(200), read_stream.next())
+                .await
+                .is_err());
+        }
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn share_subscribe_round_robin_across_reads_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let mut members = add_share_members(&fixture, &topic_id, QoS::AtMostOnce, 2).await;
+        fixture.start_share_leader_push().await;
+
+        // every message is read on its own, the members still take turns
+        let mut received = [0; 2];
+        for i in 0..4 {
+            append_messages(
+                &fixture.message_storage,
+                &topic_id,
+                [format!("message-{}", i)].into_iter(),
+            )
+            .await;
+            let (index, _) = read_any_member(&mut members).await;
+            received[index] += 1;
+        }
+        assert_eq!(received, [2, 2]);
+
+        fixture.stop_push_threads();
+    }
+
+    // Connects the members of the $share/group1/sensor-data subscription
+    async fn add_share_members<S>(
+        fixture: &PushFixture<S>,
+        topic_id: &str,
+        qos: QoS,
+        num: usize,
+    ) -> Vec<(String, FramedRead<TcpStream, MqttCodec>)>
+    where
+        S: StorageAdapter + Sync + Send + 'static + Clone,
+    {
+        let topic_name = "/sensor-data".to_string();
+        let sub_path = "$share/group1/sensor-data".to_string();
+        let (group_name, sub_name) = decode_share_info(&sub_path);
+
+        let mut members = Vec::new();
+        for _ in 0..num {
+            let (client_id, _, read_stream) = fixture.connect_client().await;
+            fixture.subscribe_manager.add_share_subscribe_leader(
+                &sub_name,
+                Subscriber {
+                    protocol: MqttProtocol::Mqtt5,
+                    client_id: client_id.clone(),
+                    sub_path: sub_path.clone(),
+                    topic_name: topic_name.clone(),
+                    group_name: Some(group_name.clone()),
+                    topic_id: topic_id.to_owned(),
+                    qos,
+                    ..Default::default()
+                },
+            );
+            members.push((client_id, read_stream));
+        }
+        members
+    }
+
+    async fn append_messages<S>(
+        message_storage: &MessageStorage<S>,
+        topic_id: &str,
+        payloads: impl Iterator<Item = String>,
+    ) where
+        S: StorageAdapter + Sync + Send + 'static + Clone,
+    {
+        let records = payloads
+            .map(|payload| {
+                let message = MqttMessage {
+                    client_id: "publisher".to_string(),
+                    topic: Bytes::from("/sensor-data"),
+                    payload: Bytes::from(payload),
+                    expiry_interval: now_second() + 3600,
+                    ..Default::default()
+                };
+                Record::build_byte(message.encode())
+            })
+            .collect();
+        message_storage
+            .append_topic_message(topic_id, records)
+            .await
+            .unwrap();
+    }
+
+    // The PUBACK of the member, sent once the leader waits for it
+    async fn send_puback(cache_manager: &Arc<CacheManager>, client_id: &str, pkid: u16) {
+        loop {
+            if let Some(info) = cache_manager.get_ack_packet(client_id.to_owned(), pkid) {
+                let ack = QosAckPackageData {
+                    ack_type: QosAckPackageType::PubAck,
+                    pkid,
+                };
+                if info.sx.send(ack).is_ok() {
+                    return;
+                }
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    // The member the next message is delivered to, with its PUBLISH. The members whose
+    // connection was closed are skipped.
+    async fn read_any_member(
+        members: &mut [(String, FramedRead<TcpStream, MqttCodec>)],
+    ) -> (usize, Publish) {
+        for _ in 0..100 {
+            for (index, (_, read_stream)) in members.iter_mut().enumerate() {
+                if let Ok(Some(packet)) =
+                    timeout(Duration::from_millis(100), read_stream.next()).await
+                {
+                    let MqttPacket::Publish(publish, _) = packet.unwrap() else {
+                        panic!("expected a PUBLISH");
+                    };
+                    return (index, publish);
+                }
+            }
+        }
+        panic!("no member received the message");
+    }
+
+    #[tokio::test]
+    async fn share_subscribe_member_disconnect_redelivery_test() {
+        let fixture = build_push_fixture();
+        let cache_manager = &fixture.cache_manager;
+        let message_storage = &fixture.message_storage;
+        let topic_id = unique_id();
+        let mut members = add_share_members(&fixture, &topic_id, QoS::AtLeastOnce, 2).await;
+        fixture.start_share_leader_push().await;
+
+        // the messages are split evenly between the two members
+        append_messages(message_storage, &topic_id, (0..10).map(|i| i.to_string())).await;
+        let mut received = [0; 2];
+        for _ in 0..10 {
+            let (index, publish) = read_any_member(&mut members).await;
+            send_puback(cache_manager, &members[index].0, publish.pkid).await;
+            received[index] += 1;
+        }
+        assert_eq!(received, [5, 5]);
+
+        // the member drops before the PUBACK, the other one receives the message
+        append_messages(
+            message_storage,
+            &topic_id,
+            ["in-flight".to_string()].into_iter(),
+        )
+        .await;
+        let (dropped, publish) = read_any_member(&mut members).await;
+        assert_eq!(publish.payload, Bytes::from("in-flight"));
+        let connect_id = cache_manager.get_connect_id(&members[dropped].0).unwrap();
+        cache_manager.update_session_connect_id(&members[dropped].0, None);
+        fixture.connection_manager.close_connect(connect_id).await;
+
+        let (index, publish) = read_any_member(&mut members).await;
+        assert_ne!(index, dropped);
+        assert_eq!(publish.payload, Bytes::from("in-flight"));
+        send_puback(cache_manager, &members[index].0, publish.pkid).await;
+
+        // and it is the only member in the rotation
+        append_messages(message_storage, &topic_id, (0..4).map(|i| i.to_string())).await;
+        for _ in 0..4 {
+            let (next, publish) = read_any_member(&mut members).await;
+            assert_eq!(next, index);
+            send_puback(cache_manager, &members[index].0, publish.pkid).await;
+        }
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn share_subscribe_message_expiry_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let mut members = add_share_members(&fixture, &topic_id, QoS::AtMostOnce, 1).await;
+
+        // an expired message, then one published 20 seconds ago that lives for 60 seconds
+        let records = [("expired", now_second() - 10), ("live", now_second() + 40)]
+            .into_iter()
+            .map(|(payload, expiry_interval)| {
+                let message = MqttMessage {
+                    client_id: "publisher".to_string(),
+                    topic: Bytes::from("/sensor-data"),
+                    payload: Bytes::from(payload),
+                    expiry_interval,
+                    create_time: now_second() - 20,
+                    ..Default::default()
+                };
+                Record::build_byte(message.encode())
+            })
+            .collect();
+        fixture
+            .message_storage
+            .append_topic_message(&topic_id, records)
+            .await
+            .unwrap();
+        fixture.start_share_leader_push().await;
+
+        let MqttPacket::Publish(publish, properties) = read_packet(&mut members[0].1).await else {
+            panic!("expected a PUBLISH");
+        };
+        assert_eq!(publish.payload, Bytes::from("live"));
+        let expiry = properties.unwrap().message_expiry_interval.unwrap();
+        assert!(expiry > 30 && expiry <= 40);
+
+        fixture.stop_push_threads();
+    }
+
+    #[tokio::test]
+    async fn share_subscribe_skip_disconnected_member_test() {
+        let fixture = build_push_fixture();
+        let topic_id = unique_id();
+        let mut members = add_share_members(&fixture, &topic_id, QoS::AtMostOnce, 2).await;
+        // the first member keeps its session but is not connected
+        fixture
+            .cache_manager
+            .update_session_connect_id(&members[0].0, None);
+
+        append_messages(
+            &fixture.message_storage,
+            &topic_id,
+            (0..4).map(|i| i.to_string()),
+        )
+        .await;
+        fixture.start_share_leader_push().await;
+
+        // every message goes to the connected member, none is held for the other one
+        for i in 0..4 {
+            let (index, publish) = read_any_member(&mut members).await;
+            assert_eq!(index, 1);
+            assert_eq!(publish.payload, Bytes::from(i.to_string()));
+        }
+
+        fixture.stop_push_threads();
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep_until;
+
+/// Caps the delivery rate of a subscription during the first seconds after it starts,
+/// so that a client that just connected is not flooded with the backlog of its topic.
+/// The cap is `initial_rate` messages in the first second and doubles every second,
+/// until the subscription is `duration` old and is delivered at full speed.
+pub struct SlowStart {
+    started: Instant,
+    duration: Duration,
+    initial_rate: u64,
+    // Second of the slow start the messages were counted in
+    window: u64,
+    window_sent: u64,
+}
+
+impl SlowStart {
+    pub fn new(duration_secs: u64, initial_rate: u32) -> Self {
+        SlowStart {
+            started: Instant::now(),
+            duration: Duration::from_secs(duration_secs),
+            initial_rate: initial_rate as u64,
+            window: 0,
+            window_sent: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.duration.is_zero() && self.initial_rate > 0
+    }
+
+    // Messages that may be delivered in the given second of the slow start
+    fn window_cap(&self, window: u64) -> u64 {
+        self.initial_rate
+            .saturating_mul(1u64.checked_shl(window as u32).unwrap_or(u64::MAX))
+    }
+
+    /// Waits until a message may be delivered and returns how many of the record_num
+    /// messages may be delivered now.
+    pub async fn allowance(&mut self, record_num: u64) -> u64 {
+        if !self.is_enabled() {
+            return record_num;
+        }
+        loop {
+            let elapsed = self.started.elapsed();
+            if elapsed >= self.duration {
+                return record_num;
+            }
+
+            let window = elapsed.as_secs();
+            if window != self.window {
+                self.window = window;
+                self.window_sent = 0;
+            }
+            let cap = self.window_cap(window);
+            if self.window_sent < cap {
+                return record_num.min(cap - self.window_sent);
+            }
+
+            let next_window = self.started + Duration::from_secs(window + 1);
+            sleep_until(next_window.into()).await;
+        }
+    }
+
+    pub fn consume(&mut self, num: u64) {
+        self.window_sent = self.window_sent.saturating_add(num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SlowStart;
+
+    #[tokio::test]
+    async fn allowance_test() {
+        let mut slow_start = SlowStart::new(0, 10);
+        assert!(!slow_start.is_enabled());
+        assert_eq!(slow_start.allowance(100).await, 100);
+
+        let mut slow_start = SlowStart::new(3, 10);
+        assert_eq!(slow_start.window_cap(0), 10);
+        assert_eq!(slow_start.window_cap(2), 40);
+        assert_eq!(slow_start.window_cap(100), u64::MAX);
+
+        assert_eq!(slow_start.allowance(4).await, 4);
+        slow_start.consume(4);
+        assert_eq!(slow_start.allowance(100).await, 6);
+        slow_start.consume(6);
+        assert_eq!(slow_start.window, 0);
+
+        // waits for the next second, which allows twice as many messages
+        assert_eq!(slow_start.allowance(100).await, 20);
+        assert_eq!(slow_start.window, 1);
+    }
+}
@@ -13,18 +13,22 @@
 // limitations under the License.
 
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use axum::extract::ws::Message;
-use bytes::BytesMut;
-use common_base::config::broker_mqtt::broker_mqtt_conf;
+use bytes::{Bytes, BytesMut};
+use common_base::config::broker_mqtt::{
+    broker_mqtt_conf, InjectUserProperty, SubscriptionTransform,
+};
 use common_base::error::common::CommonError;
 use common_base::tools::now_mills;
 use grpc_clients::placement::mqtt::call::placement_get_share_sub_leader;
 use grpc_clients::pool::ClientPool;
-use log::error;
+use log::{error, info, warn};
 use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
-use protocol::mqtt::common::{MqttPacket, MqttProtocol, PubRel, QoS};
+use protocol::mqtt::common::{
+    DisconnectReasonCode, MqttPacket, MqttProtocol, PubRel, PublishProperties, QoS,
+};
 use protocol::placement_center::placement_center_mqtt::{
     GetShareSubLeaderReply, GetShareSubLeaderRequest,
 };
@@ -37,6 +41,9 @@ use tokio::time::{sleep, timeout};
 use super::subscriber::SubPublishParam;
 use crate::handler::cache::{CacheManager, QosAckPackageData};
 use crate::handler::error::MqttBrokerError;
+use crate::handler::response::response_packet_mqtt_distinct_by_reason;
+use crate::observability::metrics::publish::{record_message_dropped, MessageDropReason};
+use crate::observability::metrics::server::metrics_qos_ack_receiver_gone;
 use crate::observability::slow::sub::{record_slow_sub_data, SlowSubData};
 use crate::server::connection_manager::ConnectionManager;
 use crate::server::packet::ResponsePackage;
@@ -46,6 +53,9 @@ const SHARE_SUB_PREFIX: &str = "$share";
 
 const QUEUE_SUB_PREFIX: &str = "$queue";
 
+// The shared subscription group name of the $queue/<topic> subscribers, see share_group_key
+pub const QUEUE_SHARE_GROUP: &str = "__queue__";
+
 pub fn path_contain_sub(_: &str) -> bool {
     true
 }
@@ -57,6 +67,16 @@ pub fn sub_path_validator(sub_path: String) -> bool {
         return false;
     }
 
+    // $share/{group}/{filter}, the group name must not be empty
+    if is_share_sub(&sub_path) {
+        let mut parts = sub_path.splitn(3, "/");
+        parts.next();
+        match (parts.next(), parts.next()) {
+            (Some(group_name), Some(_)) if !group_name.is_empty() => {}
+            _ => return false,
+        }
+    }
+
     for path in sub_path.split("/") {
         if path.contains("+") && path != "+" {
             return false;
@@ -70,45 +90,170 @@ pub fn sub_path_validator(sub_path: String) -> bool {
 }
 
 pub fn path_regex_match(topic_name: &str, sub_path: &str) -> bool {
-    let path = if is_share_sub(sub_path) {
+    topic_match(&sub_path_filter(sub_path), &sub_path_filter(topic_name))
+}
+
+// The topic filter of a subscription, without the prefix of a shared or queue subscription
+pub fn sub_path_filter(sub_path: &str) -> String {
+    if is_share_sub(sub_path) {
         let (_, group_path) = decode_share_info(sub_path);
         group_path
     } else if is_queue_sub(sub_path) {
         decode_queue_info(sub_path)
     } else {
         sub_path.to_owned()
-    };
+    }
+}
 
-    let topic = if is_share_sub(topic_name) {
-        let (_, group_path) = decode_share_info(topic_name);
-        group_path
-    } else if is_queue_sub(topic_name) {
-        decode_queue_info(topic_name)
-    } else {
-        topic_name.to_owned()
-    };
+/// Whether the topic filter matches the topic name by the rules of MQTT: "+" matches exactly
+/// one level and "#", the last level of a filter, matches any number of levels including the
+/// parent one. A wildcard in the first level does not match a topic starting with "$".
+pub fn topic_match(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
 
-    // Path perfect matching
-    if topic == path {
-        return true;
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            // "sport/#" also matches "sport"
+            (Some("#"), _) => return filter_levels.next().is_none(),
+            (Some("+"), Some(_)) => {}
+            (Some(filter_level), Some(topic_level)) => {
+                if filter_level != topic_level {
+                    return false;
+                }
+            }
+            (Some(_), None) => return false,
+            (None, None) => return true,
+            (None, Some(_)) => return false,
+        }
     }
+}
 
-    if path.contains("+") {
-        let sub_regex = path.replace("+", "[^+*/]+");
-        let re = Regex::new(&sub_regex.to_string()).unwrap();
-        return re.is_match(&topic);
+/// Whether some topic is matched by both topic filters, e.g. "sensors/+/temp" and "sensors/room1/#".
+pub fn topic_filter_overlap(filter: &str, other_filter: &str) -> bool {
+    let starts_with_wildcard = |filter: &str| filter.starts_with('+') || filter.starts_with('#');
+    if (filter.starts_with('$') && starts_with_wildcard(other_filter))
+        || (other_filter.starts_with('$') && starts_with_wildcard(filter))
+    {
+        return false;
     }
 
-    if path.contains("#") {
-        if path.split("/").last().unwrap() != "#" {
-            return false;
+    let mut filter_levels = filter.split('/');
+    let mut other_levels = other_filter.split('/');
+    loop {
+        match (filter_levels.next(), other_levels.next()) {
+            (Some("#"), _) | (_, Some("#")) => return true,
+            (None, None) => return true,
+            (Some(_), None) | (None, Some(_)) => return false,
+            (Some("+"), Some(_)) | (Some(_), Some("+")) => {}
+            (Some(level), Some(other_level)) => {
+                if level != other_level {
+                    return false;
+                }
+            }
         }
-        let sub_regex = path.replace("#", "[^+#]+");
-        let re = Regex::new(&sub_regex.to_string()).unwrap();
-        return re.is_match(&topic);
     }
+}
 
-    false
+// Merges the user properties configured by inject_user_properties into the ones of the publisher.
+pub fn build_user_properties(
+    topic_name: &str,
+    user_properties: Vec<(String, String)>,
+    ingest_ts: u64,
+) -> Vec<(String, String)> {
+    let conf = broker_mqtt_conf();
+    merge_inject_user_properties(
+        &conf.inject_user_properties,
+        conf.broker_id,
+        topic_name,
+        user_properties,
+        ingest_ts,
+    )
+}
+
+fn merge_inject_user_properties(
+    inject_properties: &[InjectUserProperty],
+    broker_id: u64,
+    topic_name: &str,
+    mut user_properties: Vec<(String, String)>,
+    ingest_ts: u64,
+) -> Vec<(String, String)> {
+    for inject in inject_properties {
+        if !path_regex_match(topic_name, &inject.topic_filter) {
+            continue;
+        }
+        for (key, value) in inject.user_properties.iter() {
+            // the properties set by the publisher take precedence
+            if user_properties.iter().any(|(k, _)| k == key) {
+                continue;
+            }
+            let value = value
+                .replace("${broker_id}", &broker_id.to_string())
+                .replace("${ingest_ts}", &ingest_ts.to_string());
+            user_properties.push((key.to_owned(), value));
+        }
+    }
+    user_properties
+}
+
+// Applies the subscription_transforms configured for the subscription of client_id on sub_path.
+pub fn transform_subscription_payload(
+    client_id: &str,
+    sub_path: &str,
+    topic_name: &str,
+    payload: Bytes,
+) -> Bytes {
+    let conf = broker_mqtt_conf();
+    apply_subscription_transforms(
+        &conf.subscription_transforms,
+        client_id,
+        sub_path,
+        topic_name,
+        payload,
+    )
+}
+
+fn apply_subscription_transforms(
+    transforms: &[SubscriptionTransform],
+    client_id: &str,
+    sub_path: &str,
+    topic_name: &str,
+    mut payload: Bytes,
+) -> Bytes {
+    let Some(transform) = transforms
+        .iter()
+        .find(|transform| transform.client_id == client_id && transform.sub_path == sub_path)
+    else {
+        return payload;
+    };
+
+    // a payload that is not a JSON object gets no fields
+    if !transform.inject_fields.is_empty() {
+        if let Ok(serde_json::Value::Object(mut object)) = serde_json::from_slice(&payload) {
+            for (key, value) in transform.inject_fields.iter() {
+                object.insert(key.to_owned(), serde_json::Value::String(value.to_owned()));
+            }
+            if let Ok(data) = serde_json::to_vec(&object) {
+                payload = Bytes::from(data);
+            }
+        }
+    }
+
+    // a binary payload is not wrapped
+    if !transform.envelope.is_empty() {
+        if let Ok(text) = std::str::from_utf8(&payload) {
+            payload = Bytes::from(
+                transform
+                    .envelope
+                    .replace("${topic}", topic_name)
+                    .replace("${payload}", text),
+            );
+        }
+    }
+    payload
 }
 
 pub fn min_qos(qos: QoS, sub_qos: QoS) -> QoS {
@@ -118,17 +263,37 @@ pub fn min_qos(qos: QoS, sub_qos: QoS) -> QoS {
     sub_qos
 }
 
+// The DUP flag of a PUBLISH sent to a subscriber, only a QoS 1 or 2 message sent again has it
+// [MQTT-3.3.1-1] [MQTT-3.3.1-2]
+pub fn delivery_dup(qos: QoS, is_resend: bool) -> bool {
+    is_resend && qos != QoS::AtMostOnce
+}
+
+// The properties of a PUBLISH sent again, the message expiry interval is reduced by the time
+// the message waited since it was first sent [MQTT-3.3.2-6]. The delivery already started, so
+// the interval is at least one second rather than expiring the message in flight.
+pub fn redelivery_properties(
+    properties: &Option<PublishProperties>,
+    first_sent: Instant,
+) -> Option<PublishProperties> {
+    let mut properties = properties.clone()?;
+    if let Some(interval) = properties.message_expiry_interval {
+        let elapsed = first_sent.elapsed().as_secs().min(u32::MAX as u64) as u32;
+        properties.message_expiry_interval = Some(interval.saturating_sub(elapsed).max(1));
+    }
+    Some(properties)
+}
+
 pub async fn get_sub_topic_id_list(
     metadata_cache: &Arc<CacheManager>,
     sub_path: &str,
 ) -> Vec<String> {
-    let mut result = Vec::new();
-    for (topic_id, topic_name) in metadata_cache.topic_id_name.clone() {
-        if path_regex_match(&topic_name, sub_path) {
-            result.push(topic_id);
-        }
-    }
-    result
+    metadata_cache
+        .topic_id_name
+        .iter()
+        .filter(|raw| path_regex_match(raw.value(), sub_path))
+        .map(|raw| raw.key().clone())
+        .collect()
 }
 
 pub fn is_share_sub(sub_name: &str) -> bool {
@@ -140,10 +305,10 @@ pub fn is_queue_sub(sub_name: &str) -> bool {
 }
 
 pub fn decode_share_info(sub_name: &str) -> (String, String) {
-    let mut str_slice: Vec<&str> = sub_name.split("/").collect();
-    str_slice.remove(0);
-    let group_name = str_slice.remove(0).to_string();
-    let sub_name = format!("/{}", str_slice.join("/"));
+    let mut parts = sub_name.splitn(3, "/");
+    parts.next();
+    let group_name = parts.next().unwrap_or_default().to_string();
+    let sub_name = format!("/{}", parts.next().unwrap_or_default());
     (group_name, sub_name)
 }
 
@@ -153,6 +318,31 @@ pub fn decode_queue_info(sub_name: &str) -> String {
     format!("/{}", str_slice.join("/"))
 }
 
+// $queue/<topic> is an alias of $share/__queue__/<topic>: every message of the topic is delivered
+// to exactly one of the queue subscribers, which take turns in round robin.
+pub fn queue_sub_to_share_sub(sub_name: &str) -> String {
+    format!(
+        "{}/{}{}",
+        SHARE_SUB_PREFIX,
+        QUEUE_SHARE_GROUP,
+        decode_queue_info(sub_name)
+    )
+}
+
+// The (group key, filter) of a $share or $queue subscription. The key of a group at the
+// placement center and in the push threads joins the group name and the filter, so a group
+// is per filter: $share/g1/a and $share/g1/b are two groups, and $queue/<topic> joins the
+// group of $share/__queue__/<topic>, of the subscribers of the same topic only.
+pub fn share_group_key(sub_path: &str) -> (String, String) {
+    let share_path = if is_queue_sub(sub_path) {
+        queue_sub_to_share_sub(sub_path)
+    } else {
+        sub_path.to_owned()
+    };
+    let (group_name, sub_name) = decode_share_info(&share_path);
+    (format!("{}_{}", group_name, sub_name), sub_name)
+}
+
 pub async fn get_share_sub_leader(
     client_pool: &Arc<ClientPool>,
     group_name: &String,
@@ -179,35 +369,82 @@ pub async fn wait_packet_ack(sx: &Sender<QosAckPackageData>) -> Option<QosAckPac
     (res.await).unwrap_or_default()
 }
 
+// Hands the ack of a QoS 1 or 2 packet to the delivery waiting for it. When the broadcast
+// channel of the ack has no receiver left, the delivery ended without an ack, so its ack packet
+// and pkid are removed and false is returned.
+pub fn send_packet_ack(
+    metadata_cache: &Arc<CacheManager>,
+    client_id: &str,
+    data: QosAckPackageData,
+) -> bool {
+    let Some(info) = metadata_cache.get_ack_packet(client_id.to_owned(), data.pkid) else {
+        return false;
+    };
+    if info.sx.receiver_count() == 0 {
+        warn!(
+            "No delivery waits for the {:?} of client_id [{}], pkid [{}], the packet is removed",
+            data.ack_type, client_id, data.pkid
+        );
+        metadata_cache.remove_ack_packet(client_id, data.pkid);
+        metadata_cache.remove_pkid_info(client_id, data.pkid);
+        metrics_qos_ack_receiver_gone(&format!("{:?}", data.ack_type).to_lowercase());
+        return false;
+    }
+    info.sx.send(data).is_ok()
+}
+
+// The receiver must be subscribed before the packet is sent, so that an ack
+// arriving right after the send is not missed.
+pub async fn wait_packet_ack_timeout(
+    rx: &mut broadcast::Receiver<QosAckPackageData>,
+    ack_timeout: Duration,
+) -> Option<QosAckPackageData> {
+    match timeout(ack_timeout, rx.recv()).await {
+        Ok(Ok(data)) => Some(data),
+        _ => None,
+    }
+}
+
+// Writes the packet to the connection, returns whether the connection was found.
+pub async fn write_packet_to_client(
+    resp: ResponsePackage,
+    connection_manager: &Arc<ConnectionManager>,
+) -> Result<bool, MqttBrokerError> {
+    let Some(protocol) = connection_manager.get_connect_protocol(resp.connection_id) else {
+        return Ok(false);
+    };
+    let response: MqttPacketWrapper = MqttPacketWrapper {
+        protocol_version: protocol.clone().into(),
+        packet: resp.packet,
+    };
+
+    if connection_manager.is_websocket(resp.connection_id) {
+        let mut codec = MqttCodec::new(Some(protocol.into()));
+        let mut buff = BytesMut::new();
+        match codec.encode_data(response.clone(), &mut buff) {
+            Ok(()) => {}
+            Err(e) => {
+                error!("Websocket encode back packet failed with error message: {e:?}");
+            }
+        }
+        connection_manager
+            .write_websocket_frame(resp.connection_id, response, Message::Binary(buff.to_vec()))
+            .await?;
+    } else {
+        connection_manager
+            .write_tcp_frame(resp.connection_id, response)
+            .await?
+    }
+    Ok(true)
+}
+
 pub async fn publish_message_to_client(
     resp: ResponsePackage,
     sub_pub_param: &SubPublishParam,
     connection_manager: &Arc<ConnectionManager>,
     metadata_cache: &Arc<CacheManager>,
 ) -> Result<(), MqttBrokerError> {
-    if let Some(protocol) = connection_manager.get_connect_protocol(resp.connection_id) {
-        let response: MqttPacketWrapper = MqttPacketWrapper {
-            protocol_version: protocol.clone().into(),
-            packet: resp.packet,
-        };
-
-        if connection_manager.is_websocket(resp.connection_id) {
-            let mut codec = MqttCodec::new(Some(protocol.into()));
-            let mut buff = BytesMut::new();
-            match codec.encode_data(response.clone(), &mut buff) {
-                Ok(()) => {}
-                Err(e) => {
-                    error!("Websocket encode back packet failed with error message: {e:?}");
-                }
-            }
-            connection_manager
-                .write_websocket_frame(resp.connection_id, response, Message::Binary(buff.to_vec()))
-                .await?;
-        } else {
-            connection_manager
-                .write_tcp_frame(resp.connection_id, response)
-                .await?
-        }
+    if write_packet_to_client(resp, connection_manager).await? {
         // record slow sub data
         if metadata_cache.get_slow_sub_config().enable && sub_pub_param.create_time > 0 {
             let slow_data = SlowSubData::build(
@@ -223,6 +460,44 @@ pub async fn publish_message_to_client(
     Ok(())
 }
 
+// Sends a DISCONNECT with Not authorized to a subscriber whose delivery was denied by the ACL,
+// then closes its connection.
+pub async fn disconnect_unauthorized_subscriber(
+    metadata_cache: &Arc<CacheManager>,
+    connection_manager: &Arc<ConnectionManager>,
+    client_id: &str,
+) {
+    let Some(connect_id) = metadata_cache.get_connect_id(client_id) else {
+        return;
+    };
+    let Some(protocol) = connection_manager.get_connect_protocol(connect_id) else {
+        return;
+    };
+
+    let resp = ResponsePackage {
+        connection_id: connect_id,
+        packet: response_packet_mqtt_distinct_by_reason(
+            &protocol,
+            Some(DisconnectReasonCode::NotAuthorized),
+        ),
+        close_after_send: true,
+    };
+    if let Err(e) = write_packet_to_client(resp, connection_manager).await {
+        warn!(
+            "Failed to send DISCONNECT to client_id [{}], connect_id [{}], {}",
+            client_id, connect_id, e
+        );
+    }
+
+    metadata_cache.remove_connection(connect_id);
+    metadata_cache.update_session_connect_id(client_id, None);
+    connection_manager.close_connect(connect_id).await;
+    info!(
+        "client disconnected, client_id={}, connect_id={}, reason=delivery_not_authorized",
+        client_id, connect_id
+    );
+}
+
 pub async fn qos2_send_publish(
     connection_manager: &Arc<ConnectionManager>,
     metadata_cache: &Arc<CacheManager>,
@@ -249,7 +524,8 @@ pub async fn qos2_send_publish(
         }
 
         retry_times += 1;
-        publish.dup = retry_times >= 2;
+        // a redelivery after the PUBREC timed out is sent with DUP as well
+        publish.dup = delivery_dup(publish.qos, sub_pub_param.publish.dup || retry_times >= 2);
 
         let mut contain_properties = false;
         if let Some(protocol) = connection_manager.get_connect_protocol(connect_id) {
@@ -262,11 +538,13 @@ pub async fn qos2_send_publish(
             ResponsePackage {
                 connection_id: connect_id,
                 packet: MqttPacket::Publish(publish.clone(), sub_pub_param.properties.clone()),
+                close_after_send: false,
             }
         } else {
             ResponsePackage {
                 connection_id: connect_id,
                 packet: MqttPacket::Publish(publish.clone(), None),
+                close_after_send: false,
             }
         };
 
@@ -327,6 +605,7 @@ pub async fn qos2_send_pubrel(
         let pubrel_resp = ResponsePackage {
             connection_id: connect_id,
             packet: MqttPacket::PubRel(pubrel, None),
+            close_after_send: false,
         };
 
         select! {
@@ -361,6 +640,44 @@ pub async fn qos2_send_pubrel(
     }
 }
 
+// The offset of the last record of a batch is committed once it is pushed, so the records must
+// be in increasing offset order. A storage may return them out of order, e.g. when it merges
+// several segments. They are then sorted by offset, or the batch fails when strict.
+pub fn order_record_offsets<T>(
+    topic_id: &str,
+    records: &mut Vec<T>,
+    offset_of: impl Fn(&T) -> u64,
+    strict: bool,
+) -> Result<(), MqttBrokerError> {
+    let Some(index) = records
+        .windows(2)
+        .position(|pair| offset_of(&pair[0]) >= offset_of(&pair[1]))
+    else {
+        return Ok(());
+    };
+
+    let (prev, next) = (offset_of(&records[index]), offset_of(&records[index + 1]));
+    if strict {
+        error!(
+            "Records of topic {} were read out of order, offset {} was followed by offset {}",
+            topic_id, prev, next
+        );
+        return Err(MqttBrokerError::RecordOffsetOutOfOrder(
+            topic_id.to_owned(),
+            prev,
+            next,
+        ));
+    }
+
+    warn!(
+        "Records of topic {} were read out of order, offset {} was followed by offset {}, the batch is sorted by offset",
+        topic_id, prev, next
+    );
+    records.sort_by_key(|record| offset_of(record));
+    records.dedup_by_key(|record| offset_of(record));
+    Ok(())
+}
+
 pub async fn loop_commit_offset<S>(
     message_storage: &MessageStorage<S>,
     topic_id: &str,
@@ -379,6 +696,8 @@ pub async fn loop_commit_offset<S>(
             }
             Err(e) => {
                 error!("{}", e);
+                // e.g. the storage circuit breaker is open, wait before retrying
+                sleep(Duration::from_millis(100)).await;
             }
         }
     }
@@ -411,6 +730,7 @@ pub async fn publish_message_qos0(
 
     if let Some(conn) = metadata_cache.get_connection(connect_id) {
         if sub_pub_param.publish.payload.len() > (conn.max_packet_size as usize) {
+            record_message_dropped(MessageDropReason::Oversized, QoS::AtMostOnce, 1);
             return;
         }
     }
@@ -422,18 +742,19 @@ pub async fn publish_message_qos0(
         }
     }
 
+    let mut publish = sub_pub_param.publish.clone();
+    publish.dup = delivery_dup(QoS::AtMostOnce, publish.dup);
     let resp = if contain_properties {
         ResponsePackage {
             connection_id: connect_id,
-            packet: MqttPacket::Publish(
-                sub_pub_param.publish.clone(),
-                sub_pub_param.properties.clone(),
-            ),
+            packet: MqttPacket::Publish(publish, sub_pub_param.properties.clone()),
+            close_after_send: false,
         }
     } else {
         ResponsePackage {
             connection_id: connect_id,
-            packet: MqttPacket::Publish(sub_pub_param.publish.clone(), None),
+            packet: MqttPacket::Publish(publish, None),
+            close_after_send: false,
         }
     };
 
@@ -458,19 +779,52 @@ pub async fn publish_message_qos0(
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
-    use common_base::tools::unique_id;
+    use bytes::Bytes;
+    use common_base::config::broker_mqtt::{InjectUserProperty, SubscriptionTransform};
+    use common_base::tools::{now_second, unique_id};
     use grpc_clients::pool::ClientPool;
     use metadata_struct::mqtt::topic::MqttTopic;
-    use protocol::mqtt::common::QoS;
+    use protocol::mqtt::common::{PublishProperties, QoS};
+    use tokio::sync::broadcast;
 
-    use crate::handler::cache::CacheManager;
+    use crate::handler::cache::{
+        CacheManager, QosAckPackageData, QosAckPackageType, QosAckPacketInfo,
+    };
+    use crate::handler::error::MqttBrokerError;
+    use crate::observability::metrics::server::get_qos_ack_receiver_gone_num;
     use crate::subscribe::sub_common::{
-        decode_share_info, get_sub_topic_id_list, is_share_sub, min_qos, path_regex_match,
-        sub_path_validator,
+        apply_subscription_transforms, decode_share_info, delivery_dup, get_sub_topic_id_list,
+        is_share_sub, merge_inject_user_properties, min_qos, order_record_offsets,
+        path_regex_match, queue_sub_to_share_sub, redelivery_properties, send_packet_ack,
+        share_group_key, sub_path_validator, topic_filter_overlap, topic_match,
     };
 
+    #[test]
+    fn order_record_offsets_test() {
+        let mut records: Vec<u64> = vec![1, 2, 3];
+        order_record_offsets("t1", &mut records, |offset| *offset, true).unwrap();
+        assert_eq!(records, vec![1, 2, 3]);
+
+        // lenient, the batch is sorted and the duplicated offsets are removed
+        let mut records: Vec<u64> = vec![3, 1, 2, 2, 5];
+        order_record_offsets("t1", &mut records, |offset| *offset, false).unwrap();
+        assert_eq!(records, vec![1, 2, 3, 5]);
+
+        let mut records: Vec<u64> = vec![3, 1, 2];
+        match order_record_offsets("t1", &mut records, |offset| *offset, true) {
+            Err(MqttBrokerError::RecordOffsetOutOfOrder(topic_id, prev, next)) => {
+                assert_eq!(topic_id, "t1");
+                assert_eq!((prev, next), (3, 1));
+            }
+            _ => panic!("expected RecordOffsetOutOfOrder"),
+        }
+        assert_eq!(records, vec![3, 1, 2]);
+    }
+
     #[tokio::test]
     async fn is_share_sub_test() {
         let sub1 = "$share/consumer1/sport/tennis/+".to_string();
@@ -490,6 +844,43 @@ mod tests {
         assert!(!is_share_sub(&sub6));
     }
 
+    #[test]
+    fn queue_sub_to_share_sub_test() {
+        assert_eq!(
+            queue_sub_to_share_sub("$queue/sensor-data"),
+            "$share/__queue__/sensor-data".to_string()
+        );
+        assert_eq!(
+            queue_sub_to_share_sub("$queue/sport/tennis/+"),
+            "$share/__queue__/sport/tennis/+".to_string()
+        );
+
+        let (group_name, sub_name) =
+            decode_share_info(&queue_sub_to_share_sub("$queue/sensor-data"));
+        assert_eq!(group_name, "__queue__".to_string());
+        assert_eq!(sub_name, "/sensor-data".to_string());
+    }
+
+    #[test]
+    fn share_group_key_test() {
+        let queue = share_group_key("$queue/sensor-data");
+        assert_eq!(
+            queue,
+            (
+                "__queue___/sensor-data".to_string(),
+                "/sensor-data".to_string()
+            )
+        );
+        assert_eq!(share_group_key("$share/__queue__/sensor-data"), queue);
+
+        // a group per filter
+        assert_ne!(share_group_key("$queue/sensor-data/1").0, queue.0);
+        assert_eq!(
+            share_group_key("$share/g1/sport/#"),
+            ("g1_/sport/#".to_string(), "/sport/#".to_string())
+        );
+    }
+
     #[tokio::test]
     #[ignore]
     async fn decode_share_info_test() {
@@ -513,6 +904,10 @@ mod tests {
         let (group_name, topic_name) = decode_share_info(&sub4);
         assert_eq!(group_name, "consumer1".to_string());
         assert_eq!(topic_name, "/finance/#".to_string());
+
+        let (group_name, topic_name) = decode_share_info("$share");
+        assert_eq!(group_name, "".to_string());
+        assert_eq!(topic_name, "/".to_string());
     }
     #[test]
     fn path_regex_match_test() {
@@ -569,6 +964,55 @@ mod tests {
         assert!(path_regex_match(&topic_name, &sub_regex));
     }
 
+    #[test]
+    fn topic_filter_overlap_test() {
+        assert!(topic_filter_overlap("sensors/#", "sensors/room1/temp"));
+        assert!(topic_filter_overlap("sensors/+/temp", "sensors/room1/#"));
+        assert!(topic_filter_overlap("#", "sensors/secret"));
+        assert!(topic_filter_overlap("+/+", "sensors/secret"));
+        assert!(topic_filter_overlap("sensors/#", "sensors"));
+        assert!(!topic_filter_overlap("sensors/+", "sensors/room1/temp"));
+        assert!(!topic_filter_overlap(
+            "sensors/+/temp",
+            "sensors/room1/humidity"
+        ));
+        assert!(!topic_filter_overlap("#", "$SYS/brokers"));
+        assert!(topic_filter_overlap("$SYS/#", "$SYS/brokers"));
+    }
+
+    #[test]
+    fn topic_match_test() {
+        // single level
+        assert!(topic_match("sensors/+/temp", "sensors/a/temp"));
+        assert!(!topic_match("sensors/+/temp", "sensors/a/b/temp"));
+        assert!(!topic_match("sensors/+/temp", "sensors/temp"));
+        assert!(topic_match("sensors/+", "sensors/"));
+        assert!(!topic_match("sensors/+", "sensors"));
+        assert!(topic_match("+/+", "/sensors"));
+        assert!(topic_match("+", "sensors"));
+        assert!(!topic_match("+", "/sensors"));
+
+        // multi level
+        assert!(topic_match("sensors/#", "sensors/a/temp"));
+        assert!(topic_match("sensors/#", "sensors"));
+        assert!(topic_match("#", "sensors/a/temp"));
+        assert!(!topic_match("sensors/#", "other/a"));
+        assert!(!topic_match("sensors/#/temp", "sensors/a/temp"));
+
+        // mixed
+        assert!(topic_match("+/a/#", "sensors/a/b/temp"));
+        assert!(topic_match("+/a/#", "sensors/a"));
+        assert!(!topic_match("+/a/#", "sensors/b/temp"));
+        assert!(!topic_match("sensors/a/temp", "x/sensors/a/temp"));
+        assert!(!topic_match("sensors/a", "sensors/a/temp"));
+
+        // topics starting with $ are only matched by a filter starting with the same level
+        assert!(!topic_match("#", "$SYS/brokers"));
+        assert!(!topic_match("+/brokers", "$SYS/brokers"));
+        assert!(topic_match("$SYS/#", "$SYS/brokers"));
+        assert!(topic_match("$SYS/+", "$SYS/brokers"));
+    }
+
     #[test]
     fn max_qos_test() {
         let mut sub_max_qos = QoS::AtMostOnce;
@@ -580,6 +1024,89 @@ mod tests {
         assert_eq!(min_qos(msg_qos, sub_max_qos), msg_qos);
     }
 
+    #[test]
+    fn delivery_dup_test() {
+        // the first delivery never has DUP
+        for qos in [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce] {
+            assert!(!delivery_dup(qos, false));
+        }
+        assert!(!delivery_dup(QoS::AtMostOnce, true));
+        assert!(delivery_dup(QoS::AtLeastOnce, true));
+        assert!(delivery_dup(QoS::ExactlyOnce, true));
+    }
+
+    #[tokio::test]
+    async fn send_packet_ack_test() {
+        let client_pool: Arc<ClientPool> = Arc::new(ClientPool::new(100));
+        let cache_manager = Arc::new(CacheManager::new(client_pool, "test".to_string()));
+        let client_id = unique_id();
+        let build_ack = |pkid| QosAckPackageData {
+            ack_type: QosAckPackageType::PubAck,
+            pkid,
+        };
+        assert!(!send_packet_ack(&cache_manager, &client_id, build_ack(1)));
+
+        // the delivery waits for the ack
+        let pkid = cache_manager.get_pkid(&client_id).await;
+        let (wait_ack_sx, mut wait_ack_rx) = broadcast::channel(1);
+        cache_manager.add_ack_packet(
+            &client_id,
+            pkid,
+            QosAckPacketInfo {
+                sx: wait_ack_sx,
+                create_time: now_second(),
+            },
+        );
+        assert_eq!(cache_manager.ack_packet_receiver_count(&client_id, pkid), 1);
+        assert!(send_packet_ack(&cache_manager, &client_id, build_ack(pkid)));
+        assert_eq!(wait_ack_rx.recv().await.unwrap().pkid, pkid);
+
+        // the delivery ended without removing its ack packet
+        drop(wait_ack_rx);
+        assert_eq!(cache_manager.ack_packet_receiver_count(&client_id, pkid), 0);
+        let before = get_qos_ack_receiver_gone_num("puback");
+        assert!(!send_packet_ack(
+            &cache_manager,
+            &client_id,
+            build_ack(pkid)
+        ));
+        assert!(cache_manager
+            .get_ack_packet(client_id.clone(), pkid)
+            .is_none());
+        assert_eq!(
+            cache_manager
+                .publish_pkid_info
+                .get(&client_id)
+                .unwrap()
+                .in_use_num(),
+            0
+        );
+        assert_eq!(get_qos_ack_receiver_gone_num("puback"), before + 1);
+    }
+
+    #[test]
+    fn redelivery_properties_test() {
+        assert_eq!(redelivery_properties(&None, Instant::now()), None);
+
+        let properties = Some(PublishProperties::default());
+        assert_eq!(
+            redelivery_properties(&properties, Instant::now()),
+            properties
+        );
+
+        let properties = Some(PublishProperties {
+            message_expiry_interval: Some(30),
+            ..Default::default()
+        });
+        let first_sent = Instant::now() - Duration::from_secs(10);
+        let redelivery = redelivery_properties(&properties, first_sent).unwrap();
+        assert_eq!(redelivery.message_expiry_interval, Some(20));
+
+        let first_sent = Instant::now() - Duration::from_secs(60);
+        let redelivery = redelivery_properties(&properties, first_sent).unwrap();
+        assert_eq!(redelivery.message_expiry_interval, Some(1));
+    }
+
     #[tokio::test]
     async fn get_sub_topic_list_test() {
         let client_pool: Arc<ClientPool> = Arc::new(ClientPool::new(100));
@@ -622,5 +1149,125 @@ mod tests {
 
         let path = "$share/loboxu/*test".to_string();
         assert!(!sub_path_validator(path));
+
+        let path = "$share".to_string();
+        assert!(!sub_path_validator(path));
+
+        let path = "$share//test".to_string();
+        assert!(!sub_path_validator(path));
+
+        let path = "$share/loboxu".to_string();
+        assert!(!sub_path_validator(path));
+    }
+
+    #[test]
+    fn apply_subscription_transforms_test() {
+        let transforms = vec![
+            SubscriptionTransform {
+                client_id: "c1".to_string(),
+                sub_path: "sensor/+".to_string(),
+                inject_fields: HashMap::from([("source".to_string(), "robustmq".to_string())]),
+                envelope: r#"{"topic":"${topic}","data":${payload}}"#.to_string(),
+            },
+            SubscriptionTransform {
+                client_id: "c2".to_string(),
+                sub_path: "sensor/+".to_string(),
+                inject_fields: HashMap::new(),
+                envelope: "<${payload}>".to_string(),
+            },
+        ];
+        let payload = Bytes::from(r#"{"temp":21}"#);
+
+        let res = apply_subscription_transforms(
+            &transforms,
+            "c1",
+            "sensor/+",
+            "sensor/1",
+            payload.clone(),
+        );
+        let value: serde_json::Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"topic": "sensor/1", "data": {"temp": 21, "source": "robustmq"}})
+        );
+
+        let res = apply_subscription_transforms(
+            &transforms,
+            "c2",
+            "sensor/+",
+            "sensor/1",
+            Bytes::from("raw"),
+        );
+        assert_eq!(res, Bytes::from("<raw>"));
+
+        // the other subscriptions of the topic get the original payload
+        let res = apply_subscription_transforms(
+            &transforms,
+            "c3",
+            "sensor/+",
+            "sensor/1",
+            payload.clone(),
+        );
+        assert_eq!(res, payload);
+        let res = apply_subscription_transforms(
+            &transforms,
+            "c1",
+            "sensor/#",
+            "sensor/1",
+            payload.clone(),
+        );
+        assert_eq!(res, payload);
+    }
+
+    #[test]
+    fn merge_inject_user_properties_test() {
+        let inject_properties = vec![
+            InjectUserProperty {
+                topic_filter: "sensor/#".to_string(),
+                user_properties: HashMap::from([
+                    ("broker-node".to_string(), "${broker_id}".to_string()),
+                    ("ingest-ts".to_string(), "${ingest_ts}".to_string()),
+                ]),
+            },
+            InjectUserProperty {
+                topic_filter: "other/+".to_string(),
+                user_properties: HashMap::from([("k1".to_string(), "v1".to_string())]),
+            },
+        ];
+
+        let publisher_properties = vec![
+            ("app".to_string(), "a1".to_string()),
+            ("broker-node".to_string(), "publisher".to_string()),
+        ];
+        let mut res = merge_inject_user_properties(
+            &inject_properties,
+            3,
+            "sensor/1/temperature",
+            publisher_properties,
+            1000,
+        );
+        res.sort();
+        assert_eq!(
+            res,
+            vec![
+                ("app".to_string(), "a1".to_string()),
+                ("broker-node".to_string(), "publisher".to_string()),
+                ("ingest-ts".to_string(), "1000".to_string()),
+            ]
+        );
+
+        let mut res =
+            merge_inject_user_properties(&inject_properties, 3, "sensor/2", Vec::new(), 1000);
+        res.sort();
+        assert_eq!(
+            res,
+            vec![
+                ("broker-node".to_string(), "3".to_string()),
+                ("ingest-ts".to_string(), "1000".to_string()),
+            ]
+        );
+
+        let res = merge_inject_user_properties(&inject_properties, 3, "test/1", Vec::new(), 1000);
+        assert!(res.is_empty());
     }
 }
@@ -12,11 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::subscribe::delivery_rate::requested_delivery_rate;
+use crate::subscribe::flow_control_signal::FlowControlSignal;
+use crate::subscribe::publisher_allowlist::requested_publisher_allowlist;
+use crate::subscribe::sub_common::{
+    decode_queue_info, decode_share_info, is_queue_sub, is_share_sub,
+};
 use crate::subscribe::subscriber::Subscriber;
+use crate::subscribe::subscription_trie::SubscriptionTrie;
+use common_base::utils::topic_util::decode_exclusive_sub_path_to_topic_name;
 use dashmap::DashMap;
 use metadata_struct::mqtt::subscribe_data::MqttSubscribe;
 use protocol::mqtt::common::{Filter, MqttProtocol};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use tokio::sync::broadcast::Sender;
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -40,6 +49,13 @@ pub struct ShareLeaderSubscribeData {
     pub sub_list: DashMap<String, Subscriber>,
 }
 
+// Sent to the subscribe manager when a topic is deleted
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TopicDeleteEvent {
+    pub topic_id: String,
+    pub topic_name: String,
+}
+
 #[derive(Clone)]
 pub struct TopicSubscribeInfo {
     pub client_id: String,
@@ -57,6 +73,9 @@ pub struct SubscribeManager {
     // (client_id_sub_name_topic_id, Sender<bool>)
     pub exclusive_push_thread: DashMap<String, Sender<bool>>,
 
+    // (client_id_sub_name_topic_id, Sender<bool>), stop after the messages already read are pushed
+    pub exclusive_push_drain_thread: DashMap<String, Sender<bool>>,
+
     // (group_name_sub_name_topic_id, ShareLeaderSubscribeData)
     pub share_leader_push: DashMap<String, ShareLeaderSubscribeData>,
 
@@ -77,6 +96,15 @@ pub struct SubscribeManager {
 
     //(topic_id, Vec<TopicSubscribeInfo>)
     pub topic_subscribe_list: DashMap<String, Vec<TopicSubscribeInfo>>,
+
+    // Topic filter index of all subscriptions
+    pub subscription_trie: SubscriptionTrie,
+
+    // Push progress of the exclusive subscriptions, used to signal the consumer lag to the producer
+    pub flow_control_signal: FlowControlSignal,
+
+    // (group_name, (client_id, number of subscriptions of the client in the group))
+    pub share_group_members: DashMap<String, DashMap<String, u32>>,
 }
 
 impl SubscribeManager {
@@ -88,16 +116,34 @@ impl SubscribeManager {
             share_follower_resub: DashMap::with_capacity(8),
             share_follower_identifier_id: DashMap::with_capacity(8),
             exclusive_push_thread: DashMap::with_capacity(8),
+            exclusive_push_drain_thread: DashMap::with_capacity(8),
             share_leader_push_thread: DashMap::with_capacity(8),
             share_follower_resub_thread: DashMap::with_capacity(8),
             exclusive_subscribe: DashMap::with_capacity(8),
             topic_subscribe_list: DashMap::with_capacity(8),
+            subscription_trie: SubscriptionTrie::new(),
+            flow_control_signal: FlowControlSignal::new(),
+            share_group_members: DashMap::with_capacity(8),
         }
     }
 
     // subscribe info
     pub fn add_subscribe(&self, subscribe: MqttSubscribe) {
         let key = self.subscribe_key(&subscribe.client_id, &subscribe.path);
+        if is_share_sub(&subscribe.path) && !self.subscribe_list.contains_key(&key) {
+            let (group_name, _) = decode_share_info(&subscribe.path);
+            *self
+                .share_group_members
+                .entry(group_name)
+                .or_default()
+                .entry(subscribe.client_id.clone())
+                .or_insert(0) += 1;
+        }
+        self.subscription_trie.insert(
+            &subscribe.path,
+            subscribe.broker_id,
+            build_trie_subscriber(&subscribe),
+        );
         self.subscribe_list.insert(key, subscribe);
     }
 
@@ -111,7 +157,60 @@ impl SubscribeManager {
 
     pub fn remove_subscribe(&self, client_id: &str, path: &str) {
         let key = self.subscribe_key(client_id, path);
-        self.subscribe_list.remove(&key);
+        if self.subscribe_list.remove(&key).is_some() && is_share_sub(path) {
+            let (group_name, _) = decode_share_info(path);
+            self.remove_share_group_member(&group_name, client_id);
+        }
+        self.subscription_trie.remove(path, client_id);
+    }
+
+    // Rebuilds the subscription index from subscribe_list, the subscriptions the broker holds,
+    // in case the index drifted from them. Returns the number of subscriptions indexed.
+    pub fn rebuild_subscription_index(&self) -> usize {
+        self.subscription_trie
+            .rebuild(self.subscribe_list.iter().map(|raw| {
+                let subscribe = raw.value();
+                (
+                    subscribe.path.clone(),
+                    subscribe.broker_id,
+                    build_trie_subscriber(subscribe),
+                )
+            }))
+    }
+
+    // shared subscription groups
+    pub fn share_group_num(&self) -> usize {
+        self.share_group_members.len()
+    }
+
+    pub fn share_group_member_num(&self, group_name: &str) -> usize {
+        if let Some(members) = self.share_group_members.get(group_name) {
+            return members.len();
+        }
+        0
+    }
+
+    pub fn is_share_group_member(&self, group_name: &str, client_id: &str) -> bool {
+        if let Some(members) = self.share_group_members.get(group_name) {
+            return members.contains_key(client_id);
+        }
+        false
+    }
+
+    fn remove_share_group_member(&self, group_name: &str, client_id: &str) {
+        if let Some(members) = self.share_group_members.get(group_name) {
+            if let Some(mut count) = members.get_mut(client_id) {
+                *count = count.saturating_sub(1);
+            }
+            members.remove_if(client_id, |_, count| *count == 0);
+        }
+        self.share_group_members
+            .remove_if(group_name, |_, members| members.is_empty());
+    }
+
+    // All subscribers whose topic filter matches the topic name
+    pub fn match_topic_subscribers(&self, topic_name: &str) -> Vec<Subscriber> {
+        self.subscription_trie.match_topic(topic_name)
     }
 
     // push by exclusive subscribe
@@ -120,6 +219,37 @@ impl SubscribeManager {
         self.exclusive_push.insert(key, sub);
     }
 
+    // The running push thread picks up the new QoS, Subscription Identifier, delivery rate and
+    // publisher allowlist of the subscriber before pushing its next batch. A filter of a client
+    // only keeps the identifier of its latest SUBSCRIBE.
+    pub fn update_exclusive_push(
+        &self,
+        client_id: &str,
+        path: &str,
+        topic_id: &str,
+        update: &Subscriber,
+    ) -> bool {
+        let key = self.exclusive_key(client_id, path, topic_id);
+        if let Some(mut sub) = self.exclusive_push.get_mut(&key) {
+            sub.qos = update.qos;
+            sub.subscription_identifier = update.subscription_identifier;
+            sub.max_delivery_rate = update.max_delivery_rate;
+            sub.publisher_allowlist = update.publisher_allowlist.clone();
+            return true;
+        }
+        false
+    }
+
+    // Unlike the stop signal, the push thread first finishes pushing the batch it has already
+    // read and commits its offset, then removes the exclusive push and exits.
+    pub fn drain_stop_exclusive_push(&self, client_id: &str, path: &str, topic_id: &str) -> bool {
+        let key = self.exclusive_key(client_id, path, topic_id);
+        if let Some(sx) = self.exclusive_push_drain_thread.get(&key) {
+            return sx.send(true).is_ok();
+        }
+        false
+    }
+
     fn remove_exclusive_push_by_client_id(&self, client_id: &str) {
         for (key, subscriber) in self.exclusive_push.clone() {
             if subscriber.client_id == *client_id {
@@ -256,6 +386,53 @@ impl SubscribeManager {
         self.remove_share_subscribe_leader_by_client_id(client_id);
         self.remove_share_subscribe_follower_by_client_id(client_id);
         self.remove_exclusive_subscribe_by_client_id(client_id);
+        self.subscription_trie.remove_by_client_id(client_id);
+    }
+
+    // Stops the pushes of the deleted topic, and removes the subscriptions whose filter is the
+    // topic itself. The subscriptions with a wildcard filter are kept for the other topics they
+    // match. Returns the client ids of the subscribers of the topic.
+    pub fn remove_deleted_topic_subscribe(&self, event: &TopicDeleteEvent) -> Vec<String> {
+        let mut client_ids = HashSet::new();
+
+        for (key, subscriber) in self.exclusive_push.clone() {
+            if subscriber.topic_id != event.topic_id {
+                continue;
+            }
+            self.exclusive_push.remove(&key);
+            self.exclusive_push_drain_thread.remove(&key);
+            if let Some((_, sx)) = self.exclusive_push_thread.remove(&key) {
+                let _ = sx.send(true);
+            }
+            client_ids.insert(subscriber.client_id);
+        }
+
+        for (key, share_sub) in self.share_leader_push.clone() {
+            if share_sub.topic_id != event.topic_id {
+                continue;
+            }
+            self.share_leader_push.remove(&key);
+            if let Some((_, sx)) = self.share_leader_push_thread.remove(&key) {
+                let _ = sx.send(true);
+            }
+            for (_, subscriber) in share_sub.sub_list {
+                client_ids.insert(subscriber.client_id);
+            }
+        }
+
+        if let Some((_, list)) = self.topic_subscribe_list.remove(&event.topic_name) {
+            client_ids.extend(list.into_iter().map(|info| info.client_id));
+        }
+
+        for (_, subscribe) in self.subscribe_list.clone() {
+            if subscribe_topic_filter(&subscribe.path) == event.topic_name {
+                self.remove_subscribe(&subscribe.client_id, &subscribe.path);
+                client_ids.insert(subscribe.client_id);
+            }
+        }
+        self.remove_exclusive_subscribe_by_topic(&event.topic_name);
+
+        client_ids.into_iter().collect()
     }
 
     // key
@@ -279,3 +456,118 @@ impl SubscribeManager {
         format!("{}_{}_{}", client_id, group_name, topic_id)
     }
 }
+
+// The topic filter of a subscription path, without the shared, queue or exclusive prefix
+fn subscribe_topic_filter(path: &str) -> String {
+    if is_share_sub(path) {
+        let (_, sub_name) = decode_share_info(path);
+        return sub_name;
+    }
+    if is_queue_sub(path) {
+        return decode_queue_info(path);
+    }
+    decode_exclusive_sub_path_to_topic_name(path).to_owned()
+}
+
+fn build_trie_subscriber(subscribe: &MqttSubscribe) -> Subscriber {
+    let subscription_identifier = if let Some(properties) = &subscribe.subscribe_properties {
+        properties.subscription_identifier
+    } else {
+        None
+    };
+
+    let group_name = if is_share_sub(&subscribe.path) {
+        let (group_name, _) = decode_share_info(&subscribe.path);
+        Some(group_name)
+    } else {
+        None
+    };
+
+    Subscriber {
+        protocol: subscribe.protocol.clone(),
+        client_id: subscribe.client_id.clone(),
+        sub_path: subscribe.path.clone(),
+        topic_name: "".to_string(),
+        group_name,
+        topic_id: "".to_string(),
+        qos: subscribe.filter.qos,
+        nolocal: subscribe.filter.nolocal,
+        preserve_retain: subscribe.filter.preserve_retain,
+        retain_forward_rule: subscribe.filter.retain_forward_rule.clone(),
+        subscription_identifier,
+        max_delivery_rate: requested_delivery_rate(&subscribe.subscribe_properties),
+        publisher_allowlist: requested_publisher_allowlist(&subscribe.subscribe_properties),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use metadata_struct::mqtt::subscribe_data::MqttSubscribe;
+    use protocol::mqtt::common::{Filter, MqttProtocol, QoS, RetainForwardRule};
+
+    use super::{build_trie_subscriber, SubscribeManager};
+
+    fn build_subscribe(client_id: &str, path: &str) -> MqttSubscribe {
+        MqttSubscribe {
+            client_id: client_id.to_owned(),
+            path: path.to_owned(),
+            cluster_name: "mqtt-broker".to_string(),
+            broker_id: 1,
+            protocol: MqttProtocol::Mqtt5,
+            filter: Filter {
+                path: path.to_owned(),
+                qos: QoS::AtLeastOnce,
+                nolocal: false,
+                preserve_retain: false,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            },
+            pkid: 1,
+            subscribe_properties: None,
+        }
+    }
+
+    fn match_client_ids(subscribe_manager: &SubscribeManager, topic: &str) -> Vec<String> {
+        let mut client_ids: Vec<String> = subscribe_manager
+            .match_topic_subscribers(topic)
+            .into_iter()
+            .map(|sub| sub.client_id)
+            .collect();
+        client_ids.sort();
+        client_ids
+    }
+
+    #[test]
+    fn rebuild_subscription_index_test() {
+        let subscribe_manager = SubscribeManager::new();
+        subscribe_manager.add_subscribe(build_subscribe("c1", "/test/rebuild/+"));
+        subscribe_manager.add_subscribe(build_subscribe("c2", "/test/#"));
+        subscribe_manager.add_subscribe(build_subscribe("c3", "$share/g1/test/rebuild/a"));
+        assert_eq!(
+            match_client_ids(&subscribe_manager, "/test/rebuild/a"),
+            vec!["c1", "c2", "c3"]
+        );
+
+        // the index lost a subscription and gained one that does not exist
+        subscribe_manager.subscription_trie.remove("/test/#", "c2");
+        subscribe_manager.subscription_trie.insert(
+            "/test/rebuild/a",
+            1,
+            build_trie_subscriber(&build_subscribe("c4", "/test/rebuild/a")),
+        );
+        assert_eq!(
+            match_client_ids(&subscribe_manager, "/test/rebuild/a"),
+            vec!["c1", "c3", "c4"]
+        );
+
+        assert_eq!(subscribe_manager.rebuild_subscription_index(), 3);
+        assert_eq!(subscribe_manager.subscription_trie.len(), 3);
+        assert_eq!(
+            match_client_ids(&subscribe_manager, "/test/rebuild/a"),
+            vec!["c1", "c2", "c3"]
+        );
+        assert_eq!(
+            match_client_ids(&subscribe_manager, "/test/other"),
+            vec!["c2"]
+        );
+    }
+}
@@ -30,6 +30,12 @@ pub struct Subscriber {
     pub preserve_retain: bool,
     pub retain_forward_rule: RetainForwardRule,
     pub subscription_identifier: Option<usize>,
+    // Messages per second requested by the client in the SUBSCRIBE
+    #[serde(default)]
+    pub max_delivery_rate: Option<u32>,
+    // Client ids of the only publishers whose messages are delivered, requested in the SUBSCRIBE
+    #[serde(default)]
+    pub publisher_allowlist: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -0,0 +1,409 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use common_base::utils::topic_util::{decode_exclusive_sub_path_to_topic_name, is_exclusive_sub};
+
+use super::sub_common::{decode_queue_info, decode_share_info, is_queue_sub, is_share_sub};
+use super::subscriber::Subscriber;
+
+const SINGLE_LEVEL_WILDCARD: &str = "+";
+const MULTI_LEVEL_WILDCARD: &str = "#";
+const SYSTEM_TOPIC_PREFIX: &str = "$";
+
+// A subscription of the index and the broker holding it
+struct TrieEntry {
+    broker_id: u64,
+    subscriber: Subscriber,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    // (topic level, TrieNode)
+    children: HashMap<String, TrieNode>,
+    // ((client_id, topic filter), TrieEntry), a client may subscribe to a topic several times,
+    // e.g. directly and in a shared subscription group
+    subscribers: HashMap<(String, String), TrieEntry>,
+}
+
+impl TrieNode {
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.subscribers.is_empty()
+    }
+}
+
+/// Index of the subscription filters by topic level, so that all the subscribers
+/// of a topic can be found at publish time in O(depth) instead of scanning every subscription.
+#[derive(Clone, Default)]
+pub struct SubscriptionTrie {
+    root: Arc<RwLock<TrieNode>>,
+}
+
+impl SubscriptionTrie {
+    pub fn new() -> Self {
+        SubscriptionTrie::default()
+    }
+
+    pub fn insert(&self, filter: &str, broker_id: u64, subscriber: Subscriber) {
+        let mut root = self.root.write().unwrap();
+        insert_node(&mut root, filter, broker_id, subscriber);
+    }
+
+    pub fn remove(&self, filter: &str, client_id: &str) {
+        let path = filter_path(filter);
+        let levels: Vec<&str> = path.split('/').collect();
+        let mut root = self.root.write().unwrap();
+        remove_node(&mut root, &levels, client_id, filter);
+    }
+
+    pub fn remove_by_client_id(&self, client_id: &str) {
+        let mut root = self.root.write().unwrap();
+        remove_client_id_node(&mut root, client_id);
+    }
+
+    /// Replaces the whole index by the given subscriptions. They are read while the index is
+    /// locked, so a subscription added or removed in the meantime is applied to the new index.
+    pub fn rebuild(&self, subscriptions: impl Iterator<Item = (String, u64, Subscriber)>) -> usize {
+        let mut root = self.root.write().unwrap();
+        let mut new_root = TrieNode::default();
+        for (filter, broker_id, subscriber) in subscriptions {
+            insert_node(&mut new_root, &filter, broker_id, subscriber);
+        }
+        *root = new_root;
+        count_node(&root)
+    }
+
+    pub fn match_topic(&self, topic: &str) -> Vec<Subscriber> {
+        let root = self.root.read().unwrap();
+        let mut entries = Vec::new();
+        match_topic_entries(&root, topic, &mut entries);
+        entries
+            .into_iter()
+            .map(|entry| {
+                let mut subscriber = entry.subscriber.clone();
+                subscriber.topic_name = topic.to_owned();
+                subscriber
+            })
+            .collect()
+    }
+
+    // The brokers holding a subscription that matches the topic and is accepted by the filter
+    pub fn match_topic_broker_ids(
+        &self,
+        topic: &str,
+        filter: impl Fn(u64, &Subscriber) -> bool,
+    ) -> HashSet<u64> {
+        let root = self.root.read().unwrap();
+        let mut entries = Vec::new();
+        match_topic_entries(&root, topic, &mut entries);
+        entries
+            .into_iter()
+            .filter(|entry| filter(entry.broker_id, &entry.subscriber))
+            .map(|entry| entry.broker_id)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        let root = self.root.read().unwrap();
+        count_node(&root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Shared, queue and exclusive subscriptions are indexed by the topic filter they actually
+// subscribe to.
+fn filter_path(filter: &str) -> String {
+    if is_share_sub(filter) {
+        let (_, path) = decode_share_info(filter);
+        return path;
+    }
+    if is_queue_sub(filter) {
+        return decode_queue_info(filter);
+    }
+    if is_exclusive_sub(filter) {
+        return decode_exclusive_sub_path_to_topic_name(filter).to_owned();
+    }
+    filter.to_owned()
+}
+
+fn insert_node(root: &mut TrieNode, filter: &str, broker_id: u64, subscriber: Subscriber) {
+    let path = filter_path(filter);
+    let mut node = root;
+    for level in path.split('/') {
+        node = node.children.entry(level.to_owned()).or_default();
+    }
+    node.subscribers.insert(
+        (subscriber.client_id.clone(), filter.to_owned()),
+        TrieEntry {
+            broker_id,
+            subscriber,
+        },
+    );
+}
+
+fn remove_node(node: &mut TrieNode, levels: &[&str], client_id: &str, filter: &str) -> bool {
+    if let Some((level, rest)) = levels.split_first() {
+        if let Some(child) = node.children.get_mut(*level) {
+            if remove_node(child, rest, client_id, filter) {
+                node.children.remove(*level);
+            }
+        }
+    } else {
+        node.subscribers
+            .remove(&(client_id.to_owned(), filter.to_owned()));
+    }
+    node.is_empty()
+}
+
+fn remove_client_id_node(node: &mut TrieNode, client_id: &str) -> bool {
+    node.subscribers.retain(|(id, _), _| id != client_id);
+    node.children
+        .retain(|_, child| !remove_client_id_node(child, client_id));
+    node.is_empty()
+}
+
+fn match_topic_entries<'a>(root: &'a TrieNode, topic: &str, results: &mut Vec<&'a TrieEntry>) {
+    let levels: Vec<&str> = topic.split('/').collect();
+    let is_system_topic = topic.starts_with(SYSTEM_TOPIC_PREFIX);
+    match_node(root, &levels, is_system_topic, results);
+}
+
+fn match_node<'a>(
+    node: &'a TrieNode,
+    levels: &[&str],
+    is_system_topic: bool,
+    results: &mut Vec<&'a TrieEntry>,
+) {
+    // Topics starting with $ are not matched by a wildcard in the first level.
+    let allow_wildcard = !is_system_topic;
+
+    // "#" also matches the parent level, e.g. "sport/#" matches "sport".
+    if allow_wildcard {
+        if let Some(child) = node.children.get(MULTI_LEVEL_WILDCARD) {
+            results.extend(child.subscribers.values());
+        }
+    }
+
+    let Some((level, rest)) = levels.split_first() else {
+        results.extend(node.subscribers.values());
+        return;
+    };
+
+    if let Some(child) = node.children.get(*level) {
+        match_node(child, rest, false, results);
+    }
+
+    if allow_wildcard {
+        if let Some(child) = node.children.get(SINGLE_LEVEL_WILDCARD) {
+            match_node(child, rest, false, results);
+        }
+    }
+}
+
+fn count_node(node: &TrieNode) -> usize {
+    node.subscribers.len() + node.children.values().map(count_node).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::SubscriptionTrie;
+    use crate::subscribe::subscriber::Subscriber;
+
+    fn build_subscriber(client_id: &str, sub_path: &str) -> Subscriber {
+        Subscriber {
+            client_id: client_id.to_owned(),
+            sub_path: sub_path.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn match_client_ids(trie: &SubscriptionTrie, topic: &str) -> Vec<String> {
+        let mut client_ids: Vec<String> = trie
+            .match_topic(topic)
+            .into_iter()
+            .map(|sub| sub.client_id)
+            .collect();
+        client_ids.sort();
+        client_ids
+    }
+
+    #[test]
+    fn insert_match_test() {
+        let trie = SubscriptionTrie::new();
+        trie.insert("/sensor/1/temperature", 1, build_subscriber("c1", ""));
+        trie.insert("/sensor/+/temperature", 1, build_subscriber("c2", ""));
+        trie.insert("/sensor/#", 1, build_subscriber("c3", ""));
+        trie.insert("#", 1, build_subscriber("c4", ""));
+        trie.insert(
+            "$share/g1/sensor/+/temperature",
+            1,
+            build_subscriber("c5", ""),
+        );
+        assert_eq!(trie.len(), 5);
+
+        assert_eq!(
+            match_client_ids(&trie, "/sensor/1/temperature"),
+            vec!["c1", "c2", "c3", "c4", "c5"]
+        );
+        assert_eq!(
+            match_client_ids(&trie, "/sensor/2/temperature"),
+            vec!["c2", "c3", "c4", "c5"]
+        );
+        assert_eq!(match_client_ids(&trie, "/sensor"), vec!["c3", "c4"]);
+        assert_eq!(
+            match_client_ids(&trie, "/sensor/1/2/temperature"),
+            vec!["c3", "c4"]
+        );
+        assert_eq!(match_client_ids(&trie, "/other"), vec!["c4"]);
+
+        let subs = trie.match_topic("/sensor/2/temperature");
+        assert!(subs
+            .iter()
+            .all(|sub| sub.topic_name == "/sensor/2/temperature"));
+    }
+
+    #[test]
+    fn system_topic_match_test() {
+        let trie = SubscriptionTrie::new();
+        trie.insert("#", 1, build_subscriber("c1", ""));
+        trie.insert("+/broker/uptime", 1, build_subscriber("c2", ""));
+        trie.insert("$SYS/#", 1, build_subscriber("c3", ""));
+        assert_eq!(match_client_ids(&trie, "$SYS/broker/uptime"), vec!["c3"]);
+    }
+
+    #[test]
+    fn remove_test() {
+        let trie = SubscriptionTrie::new();
+        trie.insert("/sensor/+/temperature", 1, build_subscriber("c1", ""));
+        trie.insert("/sensor/+/temperature", 1, build_subscriber("c2", ""));
+        trie.insert("/sensor/#", 1, build_subscriber("c1", ""));
+
+        trie.remove("/sensor/+/temperature", "c1");
+        assert_eq!(
+            match_client_ids(&trie, "/sensor/1/temperature"),
+            vec!["c1", "c2"]
+        );
+
+        trie.remove_by_client_id("c1");
+        assert_eq!(match_client_ids(&trie, "/sensor/1/temperature"), vec!["c2"]);
+
+        trie.remove("/sensor/+/temperature", "c2");
+        assert!(trie.is_empty());
+    }
+
+    #[test]
+    fn same_client_several_filters_test() {
+        let trie = SubscriptionTrie::new();
+        trie.insert("/sensor/temperature", 1, build_subscriber("c1", ""));
+        trie.insert(
+            "$share/g1/sensor/temperature",
+            1,
+            build_subscriber("c1", ""),
+        );
+        trie.insert(
+            "$share/g2/sensor/temperature",
+            1,
+            build_subscriber("c1", ""),
+        );
+        assert_eq!(trie.len(), 3);
+        assert_eq!(
+            match_client_ids(&trie, "/sensor/temperature"),
+            vec!["c1", "c1", "c1"]
+        );
+
+        // only the subscription of the filter is removed
+        trie.remove("$share/g1/sensor/temperature", "c1");
+        assert_eq!(trie.len(), 2);
+        assert_eq!(
+            match_client_ids(&trie, "/sensor/temperature"),
+            vec!["c1", "c1"]
+        );
+
+        trie.remove_by_client_id("c1");
+        assert!(trie.is_empty());
+    }
+
+    #[test]
+    fn match_topic_broker_ids_test() {
+        let trie = SubscriptionTrie::new();
+        trie.insert("/route/+", 1, build_subscriber("c1", "/route/+"));
+        trie.insert("/route/a", 2, build_subscriber("c2", "/route/a"));
+        trie.insert(
+            "$exclusive/route/a",
+            3,
+            build_subscriber("c3", "$exclusive/route/a"),
+        );
+        trie.insert(
+            "$share/g1/route/a",
+            4,
+            build_subscriber("c4", "$share/g1/route/a"),
+        );
+        trie.insert("/other", 5, build_subscriber("c5", "/other"));
+
+        let mut broker_ids: Vec<u64> = trie
+            .match_topic_broker_ids("/route/a", |_, _| true)
+            .into_iter()
+            .collect();
+        broker_ids.sort();
+        assert_eq!(broker_ids, vec![1, 2, 3, 4]);
+
+        let mut broker_ids: Vec<u64> = trie
+            .match_topic_broker_ids("/route/a", |broker_id, subscriber| {
+                broker_id != 1 && !subscriber.sub_path.starts_with("$share")
+            })
+            .into_iter()
+            .collect();
+        broker_ids.sort();
+        assert_eq!(broker_ids, vec![2, 3]);
+    }
+
+    #[test]
+    #[ignore]
+    fn match_topic_benchmark_test() {
+        let topic_num = 10000;
+        let sub_num = 100000;
+        let trie = SubscriptionTrie::new();
+        for i in 0..sub_num {
+            let topic_index = i % topic_num;
+            let filter = match i % 10 {
+                0 => format!("/bench/{}/+", topic_index / 100),
+                1 => format!("/bench/{}/#", topic_index / 100),
+                _ => format!("/bench/{}/{}", topic_index / 100, topic_index),
+            };
+            trie.insert(
+                &filter,
+                1,
+                build_subscriber(&format!("client-{}", i), &filter),
+            );
+        }
+        assert_eq!(trie.len(), sub_num);
+
+        let start = Instant::now();
+        let mut matched = 0;
+        for topic_index in 0..topic_num {
+            let topic = format!("/bench/{}/{}", topic_index / 100, topic_index);
+            matched += trie.match_topic(&topic).len();
+        }
+        let avg = start.elapsed() / topic_num as u32;
+        assert!(matched > 0);
+        assert!(avg.as_micros() < 50);
+    }
+}
@@ -15,16 +15,22 @@
 use std::sync::Arc;
 
 use common_base::tools::now_second;
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use metadata_struct::placement::cluster::ClusterInfo;
 use metadata_struct::placement::node::BrokerNode;
 use serde::{Deserialize, Serialize};
 
-use super::heartbeat::NodeHeartbeatData;
+use super::heartbeat::{NodeHeartbeatData, NodeLoadData};
+use crate::storage::journal::segment::SegmentStorage;
 use crate::storage::placement::cluster::ClusterStorage;
 use crate::storage::placement::node::NodeStorage;
 use crate::storage::rocksdb::RocksDBEngine;
 
+// Requests carrying an idempotency key are deduplicated within this window
+const IDEMPOTENCY_KEY_TTL_SEC: u64 = 600;
+const IDEMPOTENCY_KEY_CAPACITY: usize = 10000;
+
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct PlacementCacheManager {
     // (cluster_name, ClusterInfo)
@@ -35,6 +41,30 @@ pub struct PlacementCacheManager {
 
     // (cluster_name_node_id, NodeHeartbeatData)
     node_heartbeat: DashMap<String, NodeHeartbeatData>,
+
+    // (cluster_name_node_id, NodeLoadData)
+    broker_load: DashMap<String, NodeLoadData>,
+
+    // (cluster_name_node_id, segment replica num)
+    node_segment_count: DashMap<String, usize>,
+
+    // (idempotency_key, (state, processed time))
+    recent_idempotency_keys: DashMap<String, (IdempotencyState, u64)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IdempotencyState {
+    InProgress,
+    // The encoded reply of the processed request
+    Completed(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IdempotencyReserve {
+    // The caller processes the request and then completes or releases the key
+    Reserved,
+    InProgress,
+    Completed(Vec<u8>),
 }
 
 impl PlacementCacheManager {
@@ -42,7 +72,10 @@ impl PlacementCacheManager {
         let mut cache = PlacementCacheManager {
             cluster_list: DashMap::with_capacity(2),
             node_heartbeat: DashMap::with_capacity(2),
+            broker_load: DashMap::with_capacity(2),
             node_list: DashMap::with_capacity(2),
+            node_segment_count: DashMap::with_capacity(2),
+            recent_idempotency_keys: DashMap::with_capacity(2),
         };
         cache.load_cache(rocksdb_engine_handler);
         cache
@@ -88,6 +121,8 @@ impl PlacementCacheManager {
         cluster_name: &str,
         node_id: u64,
     ) -> Option<(u64, BrokerNode)> {
+        self.broker_load
+            .remove(&self.node_key(cluster_name, node_id));
         if let Some(data) = self.node_list.get_mut(cluster_name) {
             return data.remove(&node_id);
         }
@@ -155,6 +190,139 @@ impl PlacementCacheManager {
         None
     }
 
+    // Load
+    pub fn report_broker_load(
+        &self,
+        cluster_name: &str,
+        node_id: u64,
+        addr: &str,
+        active_connections: u64,
+    ) {
+        let key = self.node_key(cluster_name, node_id);
+        let data = NodeLoadData {
+            addr: addr.to_string(),
+            active_connections,
+            time: now_second(),
+        };
+        self.broker_load.insert(key, data);
+    }
+
+    pub fn get_broker_load(&self, cluster_name: &str, node_id: u64) -> Option<NodeLoadData> {
+        let key = self.node_key(cluster_name, node_id);
+        if let Some(load) = self.broker_load.get(&key) {
+            return Some(load.clone());
+        }
+        None
+    }
+
+    // The broker of the cluster with the fewest active connections other than exclude_node_id,
+    // the one the clients of a busy or drained broker are redirected to.
+    pub fn get_least_loaded_broker(
+        &self,
+        cluster_name: &str,
+        exclude_node_id: u64,
+    ) -> Option<NodeLoadData> {
+        self.get_broker_node_id_by_cluster(cluster_name)
+            .into_iter()
+            .filter(|node_id| *node_id != exclude_node_id)
+            .filter_map(|node_id| {
+                self.get_broker_load(cluster_name, node_id)
+                    .map(|load| (node_id, load))
+            })
+            .min_by_key(|(node_id, load)| (load.active_connections, *node_id))
+            .map(|(_, load)| load)
+    }
+
+    // Segment
+    pub fn add_node_segment(&self, cluster_name: &str, node_id: u64) {
+        let key = self.node_key(cluster_name, node_id);
+        *self.node_segment_count.entry(key).or_insert(0) += 1;
+    }
+
+    pub fn remove_node_segment(&self, cluster_name: &str, node_id: u64) {
+        let key = self.node_key(cluster_name, node_id);
+        if let Some(mut count) = self.node_segment_count.get_mut(&key) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    pub fn get_node_segment_num(&self, cluster_name: &str, node_id: u64) -> usize {
+        let key = self.node_key(cluster_name, node_id);
+        if let Some(count) = self.node_segment_count.get(&key) {
+            return *count;
+        }
+        0
+    }
+
+    // Returns the num nodes of the cluster that host the fewest segment replicas.
+    pub fn get_least_loaded_node_ids(&self, cluster_name: &str, num: usize) -> Vec<u64> {
+        let mut node_ids = self.get_broker_node_id_by_cluster(cluster_name);
+        node_ids
+            .sort_by_key(|node_id| (self.get_node_segment_num(cluster_name, *node_id), *node_id));
+        node_ids.truncate(num);
+        node_ids
+    }
+
+    // Idempotency
+    pub fn reserve_idempotency_key(&self, key: &str) -> IdempotencyReserve {
+        self.reserve_idempotency_key_by_time(key, now_second())
+    }
+
+    pub fn complete_idempotency_key(&self, key: &str, reply: Vec<u8>) {
+        self.recent_idempotency_keys.insert(
+            key.to_string(),
+            (IdempotencyState::Completed(reply), now_second()),
+        );
+    }
+
+    pub fn release_idempotency_key(&self, key: &str) {
+        self.recent_idempotency_keys
+            .remove_if(key, |_, (state, _)| *state == IdempotencyState::InProgress);
+    }
+
+    fn reserve_idempotency_key_by_time(&self, key: &str, now: u64) -> IdempotencyReserve {
+        if !self.recent_idempotency_keys.contains_key(key)
+            && self.recent_idempotency_keys.len() >= IDEMPOTENCY_KEY_CAPACITY
+        {
+            self.recent_idempotency_keys
+                .retain(|_, (_, time)| now < *time + IDEMPOTENCY_KEY_TTL_SEC);
+
+            // still full, evict the least recently processed key
+            if self.recent_idempotency_keys.len() >= IDEMPOTENCY_KEY_CAPACITY {
+                let oldest = self
+                    .recent_idempotency_keys
+                    .iter()
+                    .min_by_key(|row| row.value().1)
+                    .map(|row| row.key().clone());
+                if let Some(oldest) = oldest {
+                    self.recent_idempotency_keys.remove(&oldest);
+                }
+            }
+        }
+
+        // The key is checked and reserved under the lock of its shard, so of the concurrent
+        // requests with the same key only one is processed
+        match self.recent_idempotency_keys.entry(key.to_string()) {
+            Entry::Occupied(mut entry) => {
+                let (state, time) = entry.get();
+                if now < *time + IDEMPOTENCY_KEY_TTL_SEC {
+                    return match state {
+                        IdempotencyState::InProgress => IdempotencyReserve::InProgress,
+                        IdempotencyState::Completed(reply) => {
+                            IdempotencyReserve::Completed(reply.clone())
+                        }
+                    };
+                }
+                entry.insert((IdempotencyState::InProgress, now));
+                IdempotencyReserve::Reserved
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((IdempotencyState::InProgress, now));
+                IdempotencyReserve::Reserved
+            }
+        }
+    }
+
     pub fn load_cache(&mut self, rocksdb_engine_handler: Arc<RocksDBEngine>) {
         let cluster = ClusterStorage::new(rocksdb_engine_handler.clone());
         if let Ok(result) = cluster.list(None) {
@@ -169,9 +337,130 @@ impl PlacementCacheManager {
                 self.add_broker_node(bn);
             }
         }
+
+        // The segment replicas of the nodes are rebuilt from the persisted segments
+        let segment = SegmentStorage::new(rocksdb_engine_handler.clone());
+        if let Ok(result) = segment.all_segment() {
+            for segment in result {
+                for replica in segment.replicas.iter() {
+                    self.add_node_segment(&segment.cluster_name, replica.node_id);
+                }
+            }
+        }
     }
 
     fn node_key(&self, cluster_name: &str, node_id: u64) -> String {
         format!("{}_{}", cluster_name, node_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use common_base::tools::now_second;
+    use metadata_struct::placement::node::BrokerNode;
+
+    use super::{
+        IdempotencyReserve, PlacementCacheManager, IDEMPOTENCY_KEY_CAPACITY,
+        IDEMPOTENCY_KEY_TTL_SEC,
+    };
+
+    #[test]
+    fn broker_load_test() {
+        let cache = PlacementCacheManager::default();
+        for node_id in 1..=3 {
+            cache.add_broker_node(BrokerNode {
+                cluster_name: "c1".to_string(),
+                node_id,
+                ..Default::default()
+            });
+        }
+        cache.report_broker_load("c1", 1, "127.0.0.1:1883", 50);
+        cache.report_broker_load("c1", 2, "127.0.0.2:1883", 20);
+        cache.report_broker_load("c1", 3, "127.0.0.3:1883", 30);
+        assert_eq!(
+            cache.get_broker_load("c1", 1).unwrap().active_connections,
+            50
+        );
+
+        let least_loaded = cache.get_least_loaded_broker("c1", 1).unwrap();
+        assert_eq!(least_loaded.addr, "127.0.0.2:1883");
+        // a broker is never redirected to itself
+        let least_loaded = cache.get_least_loaded_broker("c1", 2).unwrap();
+        assert_eq!(least_loaded.addr, "127.0.0.3:1883");
+
+        // a removed broker no longer takes the redirected clients
+        cache.remove_broker_node("c1", 2);
+        assert!(cache.get_broker_load("c1", 2).is_none());
+        let least_loaded = cache.get_least_loaded_broker("c1", 1).unwrap();
+        assert_eq!(least_loaded.addr, "127.0.0.3:1883");
+        assert!(cache.get_least_loaded_broker("c2", 1).is_none());
+    }
+
+    #[test]
+    fn idempotency_key_test() {
+        let cache = PlacementCacheManager::default();
+        let now = 1000;
+        assert_eq!(
+            cache.reserve_idempotency_key_by_time("k1", now),
+            IdempotencyReserve::Reserved
+        );
+        // a concurrent retry does not process the request a second time
+        assert_eq!(
+            cache.reserve_idempotency_key_by_time("k1", now + 1),
+            IdempotencyReserve::InProgress
+        );
+
+        cache.complete_idempotency_key("k1", vec![1, 2]);
+        let now = now_second();
+        assert_eq!(
+            cache.reserve_idempotency_key_by_time("k1", now + 1),
+            IdempotencyReserve::Completed(vec![1, 2])
+        );
+
+        // expired
+        assert_eq!(
+            cache.reserve_idempotency_key_by_time("k1", now + IDEMPOTENCY_KEY_TTL_SEC),
+            IdempotencyReserve::Reserved
+        );
+    }
+
+    #[test]
+    fn idempotency_key_release_test() {
+        let cache = PlacementCacheManager::default();
+        assert_eq!(
+            cache.reserve_idempotency_key("k1"),
+            IdempotencyReserve::Reserved
+        );
+        // a failed request can be retried with the same key
+        cache.release_idempotency_key("k1");
+        assert_eq!(
+            cache.reserve_idempotency_key("k1"),
+            IdempotencyReserve::Reserved
+        );
+
+        // a completed key is kept
+        cache.complete_idempotency_key("k1", Vec::new());
+        cache.release_idempotency_key("k1");
+        assert_eq!(
+            cache.reserve_idempotency_key("k1"),
+            IdempotencyReserve::Completed(Vec::new())
+        );
+    }
+
+    #[test]
+    fn idempotency_key_capacity_test() {
+        let cache = PlacementCacheManager::default();
+        for i in 0..IDEMPOTENCY_KEY_CAPACITY {
+            cache.reserve_idempotency_key_by_time(&format!("k{}", i), i as u64);
+        }
+        cache.reserve_idempotency_key_by_time("new", IDEMPOTENCY_KEY_CAPACITY as u64);
+
+        assert_eq!(
+            cache.recent_idempotency_keys.len(),
+            IDEMPOTENCY_KEY_CAPACITY
+        );
+        assert!(!cache.recent_idempotency_keys.contains_key("k0"));
+        assert!(cache.recent_idempotency_keys.contains_key("k1"));
+        assert!(cache.recent_idempotency_keys.contains_key("new"));
+    }
+}
@@ -12,15 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
+use common_base::config::placement_center::placement_center_conf;
 use common_base::tools::now_mills;
 use grpc_clients::pool::ClientPool;
 use metadata_struct::placement::cluster::ClusterInfo;
 use metadata_struct::placement::node::BrokerNode;
 use prost::Message as _;
 use protocol::placement_center::placement_center_inner::{
-    ClusterType, RegisterNodeRequest, UnRegisterNodeRequest,
+    ClusterType, PeerInfo, RegisterNodeRequest, UnRegisterNodeRequest,
 };
 
 use super::cache::PlacementCacheManager;
@@ -137,3 +139,66 @@ async fn sync_save_cluster(
     }
     Err(PlacementCenterError::ExecutionResultIsEmpty)
 }
+
+/// Lists the placement center peers known to this node, itself included: the members of the
+/// Raft cluster, completed with the nodes of the config that have not joined it yet.
+pub fn describe_cluster_by_req(raft_machine_apply: &Arc<RaftMachineApply>) -> Vec<PeerInfo> {
+    let conf = placement_center_conf();
+    let mut addrs: Vec<(u64, String)> = raft_machine_apply
+        .openraft_node
+        .metrics()
+        .borrow()
+        .membership_config
+        .membership()
+        .nodes()
+        .map(|(node_id, node)| (*node_id, node.rpc_addr.clone()))
+        .collect();
+
+    for (node_id, addr) in conf.node.nodes.iter() {
+        let (Ok(node_id), Some(addr)) = (node_id.parse::<u64>(), addr.as_str()) else {
+            continue;
+        };
+        addrs.push((node_id, addr.to_string()));
+    }
+
+    addrs.push((
+        conf.node.node_id,
+        format!("{}:{}", conf.network.local_ip, conf.network.grpc_port),
+    ));
+    build_peers(addrs)
+}
+
+// The first address of a node wins, the Raft service is served on the grpc port
+fn build_peers(addrs: impl IntoIterator<Item = (u64, String)>) -> Vec<PeerInfo> {
+    let mut peers = BTreeMap::new();
+    for (node_id, addr) in addrs {
+        peers.entry(node_id).or_insert_with(|| PeerInfo {
+            node_id,
+            raft_addr: addr.clone(),
+            grpc_addr: addr,
+        });
+    }
+    peers.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_peers;
+
+    #[test]
+    fn build_peers_test() {
+        let peers = build_peers(vec![
+            (2, "127.0.0.1:2228".to_string()),
+            (1, "127.0.0.1:1228".to_string()),
+            (3, "127.0.0.1:3228".to_string()),
+            (1, "127.0.0.2:1228".to_string()),
+        ]);
+        assert_eq!(peers.len(), 3);
+        assert_eq!(
+            peers.iter().map(|peer| peer.node_id).collect::<Vec<u64>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(peers[0].raft_addr, "127.0.0.1:1228");
+        assert_eq!(peers[0].grpc_addr, "127.0.0.1:1228");
+    }
+}
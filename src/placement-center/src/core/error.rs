@@ -57,6 +57,9 @@ pub enum PlacementCenterError {
     #[error("{0}")]
     OpenRaftError(#[from] RaftError<TypeConfig, ClientWriteError<TypeConfig>>),
 
+    #[error("The raft cluster has no leader to serve the consistent read")]
+    RaftLeaderNotFound,
+
     #[error("Description The interface {0} submitted logs to the commit log")]
     RaftLogCommitTimeout(String),
 
@@ -95,6 +98,12 @@ pub enum PlacementCenterError {
     )]
     NotEnoughNodes(u32, u32),
 
+    #[error("The number of replicas of shard {0} cannot be decreased from {1} to {2}, the data has to be migrated first")]
+    ShardReplicaNumDecrease(String, u32, u32),
+
+    #[error("The max segment size of shard {0} must be greater than 0")]
+    InvalidShardSegmentSize(String),
+
     #[error("Execution result is empty, please check whether the server logic is normal")]
     ExecutionResultIsEmpty,
 
@@ -107,6 +116,9 @@ pub enum PlacementCenterError {
     #[error("Invalid Segment, Segment {0} is less than Start Segment {1}")]
     InvalidSegmentLessThan(u32, u32),
 
+    #[error("The request with the idempotency key {0} is still being processed")]
+    RequestInProgress(String),
+
     #[error("Request parameters [{0}] cannot be null")]
     RequestParamsNotEmpty(String),
 
@@ -34,6 +34,15 @@ pub struct NodeHeartbeatData {
     pub time: u64,
 }
 
+// The load a broker reports with its heartbeat
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct NodeLoadData {
+    // The address the clients connect to
+    pub addr: String,
+    pub active_connections: u64,
+    pub time: u64,
+}
+
 pub struct BrokerHeartbeat {
     timeout_ms: u64,
     cluster_cache: Arc<PlacementCacheManager>,
@@ -72,6 +72,7 @@ impl JournalCacheManager {
         let key = self.shard_key(cluster_name, namespace, shard_name);
         self.shard_list.remove(&key);
         self.segment_list.remove(&key);
+        self.segment_meta_list.remove(&key);
     }
 
     pub fn get_segment_list_by_shard(
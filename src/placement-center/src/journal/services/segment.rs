@@ -21,16 +21,16 @@ use metadata_struct::journal::segment::{
 };
 use metadata_struct::journal::segment_meta::JournalSegmentMetadata;
 use metadata_struct::journal::shard::JournalShard;
+use prost::Message;
 use protocol::placement_center::placement_center_journal::{
     CreateNextSegmentReply, CreateNextSegmentRequest, DeleteSegmentReply, DeleteSegmentRequest,
     UpdateSegmentMetaRequest, UpdateSegmentStatusRequest,
 };
-use rand::seq::SliceRandom;
 use rand::{thread_rng, Rng};
 use rocksdb_engine::RocksDBEngine;
 
 use super::shard::update_last_segment_by_shard;
-use crate::core::cache::PlacementCacheManager;
+use crate::core::cache::{IdempotencyReserve, PlacementCacheManager};
 use crate::core::error::PlacementCenterError;
 use crate::journal::cache::{load_journal_cache, JournalCacheManager};
 use crate::journal::controller::call_node::{
@@ -48,6 +48,49 @@ pub async fn create_segment_by_req(
     client_pool: &Arc<ClientPool>,
     rocksdb_engine_handler: &Arc<RocksDBEngine>,
     req: &CreateNextSegmentRequest,
+) -> Result<CreateNextSegmentReply, PlacementCenterError> {
+    // A retried request that has already been applied returns the original reply
+    if let Some(key) = &req.idempotency_key {
+        match cluster_cache.reserve_idempotency_key(key) {
+            IdempotencyReserve::Reserved => {}
+            IdempotencyReserve::InProgress => {
+                return Err(PlacementCenterError::RequestInProgress(key.to_string()));
+            }
+            IdempotencyReserve::Completed(reply) => {
+                return Ok(CreateNextSegmentReply::decode(reply.as_slice())?);
+            }
+        }
+    }
+
+    let result = create_next_segment(
+        engine_cache,
+        cluster_cache,
+        raft_machine_apply,
+        call_manager,
+        client_pool,
+        rocksdb_engine_handler,
+        req,
+    )
+    .await;
+
+    if let Some(key) = &req.idempotency_key {
+        match &result {
+            Ok(reply) => cluster_cache.complete_idempotency_key(key, reply.encode_to_vec()),
+            // the key is released so that the request can be retried
+            Err(_) => cluster_cache.release_idempotency_key(key),
+        }
+    }
+    result
+}
+
+async fn create_next_segment(
+    engine_cache: &Arc<JournalCacheManager>,
+    cluster_cache: &Arc<PlacementCacheManager>,
+    raft_machine_apply: &Arc<RaftMachineApply>,
+    call_manager: &Arc<JournalInnerCallManager>,
+    client_pool: &Arc<ClientPool>,
+    rocksdb_engine_handler: &Arc<RocksDBEngine>,
+    req: &CreateNextSegmentRequest,
 ) -> Result<CreateNextSegmentReply, PlacementCenterError> {
     let mut shard = if let Some(shard) =
         engine_cache.get_shard(&req.cluster_name, &req.namespace, &req.shard_name)
@@ -337,12 +380,10 @@ pub async fn build_segment(
         ));
     }
 
-    //todo Get the node copies at random
-    let mut rng = thread_rng();
-    let node_ids: Vec<u64> = node_list
-        .choose_multiple(&mut rng, shard_info.config.replica_num as usize)
-        .cloned()
-        .collect();
+    let node_ids = cluster_cache.get_least_loaded_node_ids(
+        &shard_info.cluster_name,
+        shard_info.config.replica_num as usize,
+    );
     let mut replicas = Vec::new();
     for i in 0..node_ids.len() {
         let node_id = *node_ids.get(i).unwrap();
@@ -377,6 +418,24 @@ pub async fn build_segment(
     })
 }
 
+pub fn add_segment_replica_num(
+    cluster_cache: &Arc<PlacementCacheManager>,
+    segment: &JournalSegment,
+) {
+    for replica in segment.replicas.iter() {
+        cluster_cache.add_node_segment(&segment.cluster_name, replica.node_id);
+    }
+}
+
+pub fn remove_segment_replica_num(
+    cluster_cache: &Arc<PlacementCacheManager>,
+    segment: &JournalSegment,
+) {
+    for replica in segment.replicas.iter() {
+        cluster_cache.remove_node_segment(&segment.cluster_name, replica.node_id);
+    }
+}
+
 fn calc_leader_node(replicas: &[Replica]) -> u64 {
     replicas.first().unwrap().node_id
 }
@@ -479,12 +538,14 @@ mod tests {
     use common_base::config::placement_center::placement_center_test_conf;
     use common_base::tools::now_mills;
     use metadata_struct::journal::node_extend::JournalNodeExtend;
+    use metadata_struct::journal::shard::{JournalShard, JournalShardConfig};
     use metadata_struct::placement::node::BrokerNode;
     use protocol::placement_center::placement_center_inner::ClusterType;
     use rocksdb_engine::RocksDBEngine;
 
-    use super::calc_node_fold;
+    use super::{add_segment_replica_num, build_segment, calc_node_fold};
     use crate::core::cache::PlacementCacheManager;
+    use crate::journal::cache::JournalCacheManager;
     use crate::storage::rocksdb::{column_family_list, storage_data_fold};
 
     #[tokio::test]
@@ -517,6 +578,65 @@ mod tests {
         assert!(!res.is_empty())
     }
 
+    #[tokio::test]
+    async fn build_segment_least_loaded_test() {
+        let config = placement_center_test_conf();
+        let rocksdb_engine_handler = Arc::new(RocksDBEngine::new(
+            &storage_data_fold(&config.rocksdb.data_path),
+            config.rocksdb.max_open_files.unwrap(),
+            column_family_list(),
+        ));
+        let cluster_cache = Arc::new(PlacementCacheManager::new(rocksdb_engine_handler));
+        let engine_cache = Arc::new(JournalCacheManager::new());
+        let cluster_name = "build_segment_least_loaded_test".to_string();
+
+        let extend_info = JournalNodeExtend {
+            data_fold: vec!["/tmp/t1".to_string()],
+            tcp_addr: "127.0.0.1:3110".to_string(),
+            tcps_addr: "127.0.0.1:3110".to_string(),
+        };
+        let node_ids = vec![1, 2, 3, 4];
+        for node_id in node_ids.iter() {
+            cluster_cache.add_broker_node(BrokerNode {
+                cluster_name: cluster_name.clone(),
+                cluster_type: ClusterType::JournalServer.as_str_name().to_string(),
+                create_time: now_mills(),
+                extend: serde_json::to_string(&extend_info).unwrap(),
+                node_id: *node_id,
+                node_inner_addr: "".to_string(),
+                node_ip: "".to_string(),
+            });
+        }
+
+        let shard = JournalShard {
+            cluster_name: cluster_name.clone(),
+            namespace: "n1".to_string(),
+            shard_name: "s1".to_string(),
+            config: JournalShardConfig {
+                replica_num: 1,
+                max_segment_size: 1024,
+            },
+            ..Default::default()
+        };
+
+        for segment_no in 0..20 {
+            let segment = build_segment(&shard, &engine_cache, &cluster_cache, segment_no)
+                .await
+                .unwrap();
+            add_segment_replica_num(&cluster_cache, &segment);
+        }
+
+        for node_id in node_ids {
+            let num = cluster_cache.get_node_segment_num(&cluster_name, node_id);
+            assert!(
+                (3..=7).contains(&num),
+                "node {} hosts {} segments",
+                node_id,
+                num
+            );
+        }
+    }
+
     // #[tokio::test]
     // async fn create_segment_test() {
     //     let config = placement_center_test_conf();
@@ -18,9 +18,12 @@ use common_base::tools::{now_mills, unique_id};
 use grpc_clients::pool::ClientPool;
 use metadata_struct::journal::segment::SegmentStatus;
 use metadata_struct::journal::segment_meta::JournalSegmentMetadata;
-use metadata_struct::journal::shard::{JournalShard, JournalShardConfig, JournalShardStatus};
+use metadata_struct::journal::shard::{
+    shard_name_iden, JournalShard, JournalShardConfig, JournalShardStatus,
+};
 use protocol::placement_center::placement_center_journal::{
-    CreateShardReply, CreateShardRequest, DeleteShardReply, DeleteShardRequest,
+    CreateShardReply, CreateShardRequest, DeleteShardReply, DeleteShardRequest, UpdateShardReply,
+    UpdateShardRequest,
 };
 
 use super::segment::{
@@ -168,6 +171,65 @@ pub async fn delete_shard_by_req(
     Ok(DeleteShardReply::default())
 }
 
+pub async fn update_shard_by_req(
+    engine_cache: &Arc<JournalCacheManager>,
+    cluster_cache: &Arc<PlacementCacheManager>,
+    raft_machine_apply: &Arc<RaftMachineApply>,
+    call_manager: &Arc<JournalInnerCallManager>,
+    client_pool: &Arc<ClientPool>,
+    req: &UpdateShardRequest,
+) -> Result<UpdateShardReply, PlacementCenterError> {
+    let mut shard = if let Some(shard) =
+        engine_cache.get_shard(&req.cluster_name, &req.namespace, &req.shard_name)
+    {
+        shard
+    } else {
+        return Err(PlacementCenterError::ShardDoesNotExist(shard_name_iden(
+            &req.namespace,
+            &req.shard_name,
+        )));
+    };
+
+    let new_config = serde_json::from_slice::<JournalShardConfig>(&req.new_config)?;
+    let node_num = cluster_cache.get_broker_num(&req.cluster_name) as u32;
+    validate_shard_config_update(&shard, &new_config, node_num)?;
+
+    shard.config = new_config;
+    sync_save_shard_info(raft_machine_apply, &shard).await?;
+    engine_cache.set_shard(&shard);
+
+    // The journal nodes place the segments created from now on by the new config
+    update_cache_by_set_shard(&req.cluster_name, call_manager, client_pool, shard).await?;
+
+    Ok(UpdateShardReply::default())
+}
+
+// Increasing the number of replicas only takes new nodes, decreasing it would drop the data of
+// the replicas removed, so it is rejected until the data can be migrated.
+fn validate_shard_config_update(
+    shard: &JournalShard,
+    new_config: &JournalShardConfig,
+    node_num: u32,
+) -> Result<(), PlacementCenterError> {
+    if new_config.replica_num < shard.config.replica_num {
+        return Err(PlacementCenterError::ShardReplicaNumDecrease(
+            shard.name(),
+            shard.config.replica_num,
+            new_config.replica_num,
+        ));
+    }
+    if node_num < new_config.replica_num {
+        return Err(PlacementCenterError::NotEnoughNodes(
+            new_config.replica_num,
+            node_num,
+        ));
+    }
+    if new_config.max_segment_size == 0 {
+        return Err(PlacementCenterError::InvalidShardSegmentSize(shard.name()));
+    }
+    Ok(())
+}
+
 pub async fn update_start_segment_by_shard(
     raft_machine_apply: &Arc<RaftMachineApply>,
     engine_cache: &Arc<JournalCacheManager>,
@@ -234,4 +296,41 @@ pub async fn update_shard_status(
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use metadata_struct::journal::shard::{JournalShard, JournalShardConfig};
+
+    use super::validate_shard_config_update;
+    use crate::core::error::PlacementCenterError;
+
+    #[test]
+    fn validate_shard_config_update_test() {
+        let shard = JournalShard {
+            namespace: "n1".to_string(),
+            shard_name: "s1".to_string(),
+            config: JournalShardConfig {
+                replica_num: 2,
+                max_segment_size: 1024,
+            },
+            ..Default::default()
+        };
+        let config = |replica_num, max_segment_size| JournalShardConfig {
+            replica_num,
+            max_segment_size,
+        };
+
+        assert!(validate_shard_config_update(&shard, &config(3, 2048), 3).is_ok());
+        assert!(validate_shard_config_update(&shard, &config(2, 512), 3).is_ok());
+        assert!(matches!(
+            validate_shard_config_update(&shard, &config(1, 1024), 3),
+            Err(PlacementCenterError::ShardReplicaNumDecrease(_, 2, 1))
+        ));
+        assert!(matches!(
+            validate_shard_config_update(&shard, &config(3, 1024), 2),
+            Err(PlacementCenterError::NotEnoughNodes(3, 2))
+        ));
+        assert!(matches!(
+            validate_shard_config_update(&shard, &config(2, 0), 3),
+            Err(PlacementCenterError::InvalidShardSegmentSize(_))
+        ));
+    }
+}
@@ -112,7 +112,11 @@ impl PlacementCenter {
 
         let openraft_node = create_raft_node(self.client_pool.clone(), data_route).await;
 
-        let placement_center_storage = Arc::new(RaftMachineApply::new(openraft_node.clone()));
+        let placement_center_storage = Arc::new(RaftMachineApply::new(
+            openraft_node.clone(),
+            self.cluster_cache.clone(),
+            self.client_pool.clone(),
+        ));
 
         self.start_heartbeat(placement_center_storage.clone(), stop_send.clone());
 
@@ -12,23 +12,41 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
 use std::time::Duration;
 
+use bincode::deserialize;
+use grpc_clients::placement::openraft::call::placement_openraft_read_index;
+use grpc_clients::pool::ClientPool;
+use openraft::error::{CheckIsLeaderError, RaftError};
 use openraft::raft::ClientWriteResponse;
-use openraft::Raft;
+use openraft::{LogId, Raft};
+use protocol::placement_center::placement_center_openraft::ReadIndexRequest;
 use tokio::time::timeout;
 
+use crate::core::cache::PlacementCacheManager;
 use crate::core::error::PlacementCenterError;
+use crate::raft::raft_node::NodeId;
 use crate::raft::typeconfig::TypeConfig;
 use crate::route::data::StorageData;
 
 pub struct RaftMachineApply {
     pub openraft_node: Raft<TypeConfig>,
+    cluster_cache: Arc<PlacementCacheManager>,
+    client_pool: Arc<ClientPool>,
 }
 
 impl RaftMachineApply {
-    pub fn new(openraft_node: Raft<TypeConfig>) -> Self {
-        RaftMachineApply { openraft_node }
+    pub fn new(
+        openraft_node: Raft<TypeConfig>,
+        cluster_cache: Arc<PlacementCacheManager>,
+        client_pool: Arc<ClientPool>,
+    ) -> Self {
+        RaftMachineApply {
+            openraft_node,
+            cluster_cache,
+            client_pool,
+        }
     }
 
     pub async fn client_write(
@@ -52,4 +70,44 @@ impl RaftMachineApply {
         .await?;
         Ok(resp?)
     }
+
+    // Linearizable read of the cache: the read index is confirmed by the leader and
+    // f is called once the local state machine has applied it, so that a follower
+    // does not return data older than a write acknowledged before the read.
+    pub async fn client_read<F, R>(&self, f: F) -> Result<R, PlacementCenterError>
+    where
+        F: FnOnce(&PlacementCacheManager) -> R,
+    {
+        let read_log_id = timeout(Duration::from_secs(10), self.read_index()).await??;
+        if let Some(log_id) = read_log_id {
+            if let Err(e) = self
+                .openraft_node
+                .wait(Some(Duration::from_secs(10)))
+                .applied_index_at_least(Some(log_id.index), "client_read")
+                .await
+            {
+                return Err(PlacementCenterError::CommonError(e.to_string()));
+            }
+        }
+        Ok(f(&self.cluster_cache))
+    }
+
+    async fn read_index(&self) -> Result<Option<LogId<NodeId>>, PlacementCenterError> {
+        match self.openraft_node.ensure_linearizable().await {
+            Ok(read_log_id) => Ok(read_log_id),
+            Err(RaftError::APIError(CheckIsLeaderError::ForwardToLeader(forward))) => {
+                let Some(leader_node) = forward.leader_node else {
+                    return Err(PlacementCenterError::RaftLeaderNotFound);
+                };
+                let reply = placement_openraft_read_index(
+                    &self.client_pool,
+                    &[leader_node.rpc_addr],
+                    ReadIndexRequest {},
+                )
+                .await?;
+                Ok(deserialize(&reply.value)?)
+            }
+            Err(e) => Err(PlacementCenterError::CommonError(e.to_string())),
+        }
+    }
 }
@@ -75,6 +75,7 @@ pub enum StorageDataType {
     MqttDeleteSession,
     MqttUpdateSession,
     MqttSaveLastWillMessage,
+    MqttDeleteLastWillMessage,
     MqttSetAcl,
     MqttDeleteAcl,
     MqttSetBlacklist,
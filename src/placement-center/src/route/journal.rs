@@ -18,8 +18,10 @@ use metadata_struct::journal::segment::JournalSegment;
 use metadata_struct::journal::segment_meta::JournalSegmentMetadata;
 use metadata_struct::journal::shard::JournalShard;
 
+use crate::core::cache::PlacementCacheManager;
 use crate::core::error::PlacementCenterError;
 use crate::journal::cache::JournalCacheManager;
+use crate::journal::services::segment::{add_segment_replica_num, remove_segment_replica_num};
 use crate::storage::journal::segment::SegmentStorage;
 use crate::storage::journal::segment_meta::SegmentMetadataStorage;
 use crate::storage::journal::shard::ShardStorage;
@@ -28,16 +30,19 @@ use crate::storage::rocksdb::RocksDBEngine;
 #[derive(Clone)]
 pub struct DataRouteJournal {
     rocksdb_engine_handler: Arc<RocksDBEngine>,
+    cluster_cache: Arc<PlacementCacheManager>,
     engine_cache: Arc<JournalCacheManager>,
 }
 
 impl DataRouteJournal {
     pub fn new(
         rocksdb_engine_handler: Arc<RocksDBEngine>,
+        cluster_cache: Arc<PlacementCacheManager>,
         engine_cache: Arc<JournalCacheManager>,
     ) -> Self {
         DataRouteJournal {
             rocksdb_engine_handler,
+            cluster_cache,
             engine_cache,
         }
     }
@@ -63,6 +68,54 @@ impl DataRouteJournal {
             &shard_info.shard_name,
         )?;
 
+        // The segments of the shard are deleted along with it
+        let segment_storage = SegmentStorage::new(self.rocksdb_engine_handler.clone());
+        for segment in segment_storage.list_by_shard(
+            &shard_info.cluster_name,
+            &shard_info.namespace,
+            &shard_info.shard_name,
+        )? {
+            segment_storage.delete(
+                &segment.cluster_name,
+                &segment.namespace,
+                &segment.shard_name,
+                segment.segment_seq,
+            )?;
+            remove_segment_replica_num(&self.cluster_cache, &segment);
+        }
+        for segment in self.engine_cache.get_segment_list_by_shard(
+            &shard_info.cluster_name,
+            &shard_info.namespace,
+            &shard_info.shard_name,
+        ) {
+            self.engine_cache.remove_segment(
+                &segment.cluster_name,
+                &segment.namespace,
+                &segment.shard_name,
+                segment.segment_seq,
+            );
+        }
+
+        let meta_storage = SegmentMetadataStorage::new(self.rocksdb_engine_handler.clone());
+        for meta in meta_storage.list_by_shard(
+            &shard_info.cluster_name,
+            &shard_info.namespace,
+            &shard_info.shard_name,
+        )? {
+            meta_storage.delete(
+                &meta.cluster_name,
+                &meta.namespace,
+                &meta.shard_name,
+                meta.segment_seq,
+            )?;
+            self.engine_cache.remove_segment_meta(
+                &meta.cluster_name,
+                &meta.namespace,
+                &meta.shard_name,
+                meta.segment_seq,
+            );
+        }
+
         self.engine_cache.remove_shard(
             &shard_info.cluster_name,
             &shard_info.namespace,
@@ -75,8 +128,19 @@ impl DataRouteJournal {
     pub async fn set_segment(&self, value: Vec<u8>) -> Result<Vec<u8>, PlacementCenterError> {
         let segment = serde_json::from_slice::<JournalSegment>(&value)?;
 
+        // The segment counts of the nodes change on every placement center node, the replicas of
+        // an updated segment replace the ones it was stored with
         let storage = SegmentStorage::new(self.rocksdb_engine_handler.clone());
+        if let Some(stored) = storage.get(
+            &segment.cluster_name,
+            &segment.namespace,
+            &segment.shard_name,
+            segment.segment_seq,
+        )? {
+            remove_segment_replica_num(&self.cluster_cache, &stored);
+        }
         storage.save(segment.clone())?;
+        add_segment_replica_num(&self.cluster_cache, &segment);
 
         self.engine_cache.set_segment(&segment);
 
@@ -87,12 +151,20 @@ impl DataRouteJournal {
         let segment = serde_json::from_slice::<JournalSegment>(&value)?;
 
         let storage = SegmentStorage::new(self.rocksdb_engine_handler.clone());
-        storage.delete(
+        if let Some(stored) = storage.get(
             &segment.cluster_name,
             &segment.namespace,
             &segment.shard_name,
             segment.segment_seq,
-        )?;
+        )? {
+            storage.delete(
+                &segment.cluster_name,
+                &segment.namespace,
+                &segment.shard_name,
+                segment.segment_seq,
+            )?;
+            remove_segment_replica_num(&self.cluster_cache, &stored);
+        }
 
         self.engine_cache.remove_segment(
             &segment.cluster_name,
@@ -134,3 +206,237 @@ impl DataRouteJournal {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::sync::Arc;
+
+    use common_base::config::placement_center::placement_center_test_conf;
+    use common_base::tools::unique_id;
+    use metadata_struct::journal::segment::{JournalSegment, Replica, SegmentStatus};
+    use metadata_struct::journal::segment_meta::JournalSegmentMetadata;
+    use metadata_struct::journal::shard::JournalShard;
+
+    use super::DataRouteJournal;
+    use crate::core::cache::PlacementCacheManager;
+    use crate::journal::cache::JournalCacheManager;
+    use crate::storage::journal::segment::SegmentStorage;
+    use crate::storage::journal::segment_meta::SegmentMetadataStorage;
+    use crate::storage::rocksdb::{column_family_list, RocksDBEngine};
+
+    #[tokio::test]
+    async fn delete_shard_cascade_segment_test() {
+        let config = placement_center_test_conf();
+        let data_path = format!("{}/{}", config.rocksdb.data_path, unique_id());
+        let rocksdb_engine = Arc::new(RocksDBEngine::new(
+            &data_path,
+            config.rocksdb.max_open_files.unwrap(),
+            column_family_list(),
+        ));
+        let cluster_cache = Arc::new(PlacementCacheManager::new(rocksdb_engine.clone()));
+        let engine_cache = Arc::new(JournalCacheManager::new());
+        let route = DataRouteJournal::new(
+            rocksdb_engine.clone(),
+            cluster_cache.clone(),
+            engine_cache.clone(),
+        );
+
+        let cluster_name = unique_id();
+        let namespace = "n1".to_string();
+        let shard = JournalShard {
+            cluster_name: cluster_name.clone(),
+            namespace: namespace.clone(),
+            shard_name: "s1".to_string(),
+            ..Default::default()
+        };
+        let other_shard = JournalShard {
+            shard_name: "s2".to_string(),
+            ..shard.clone()
+        };
+        for shard in [&shard, &other_shard] {
+            route
+                .set_shard(serde_json::to_vec(shard).unwrap())
+                .await
+                .unwrap();
+        }
+
+        for segment_seq in 0..3 {
+            let segment = JournalSegment {
+                cluster_name: cluster_name.clone(),
+                namespace: namespace.clone(),
+                shard_name: shard.shard_name.clone(),
+                segment_seq,
+                replicas: vec![Replica {
+                    replica_seq: 0,
+                    node_id: 1,
+                    fold: "/data".to_string(),
+                }],
+                ..Default::default()
+            };
+            route
+                .set_segment(serde_json::to_vec(&segment).unwrap())
+                .await
+                .unwrap();
+            let meta = JournalSegmentMetadata {
+                cluster_name: cluster_name.clone(),
+                namespace: namespace.clone(),
+                shard_name: shard.shard_name.clone(),
+                segment_seq,
+                ..Default::default()
+            };
+            route
+                .set_segment_meta(serde_json::to_vec(&meta).unwrap())
+                .await
+                .unwrap();
+        }
+        let other_segment = JournalSegment {
+            cluster_name: cluster_name.clone(),
+            namespace: namespace.clone(),
+            shard_name: other_shard.shard_name.clone(),
+            segment_seq: 0,
+            replicas: vec![Replica {
+                replica_seq: 0,
+                node_id: 1,
+                fold: "/data".to_string(),
+            }],
+            ..Default::default()
+        };
+        route
+            .set_segment(serde_json::to_vec(&other_segment).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(cluster_cache.get_node_segment_num(&cluster_name, 1), 4);
+
+        // the replicas of the persisted segments are counted when the cache is loaded
+        let cluster_cache = Arc::new(PlacementCacheManager::new(rocksdb_engine.clone()));
+        let route = DataRouteJournal::new(
+            rocksdb_engine.clone(),
+            cluster_cache.clone(),
+            engine_cache.clone(),
+        );
+        assert_eq!(cluster_cache.get_node_segment_num(&cluster_name, 1), 4);
+
+        let segment_storage = SegmentStorage::new(rocksdb_engine.clone());
+        let meta_storage = SegmentMetadataStorage::new(rocksdb_engine.clone());
+        assert_eq!(
+            segment_storage
+                .list_by_shard(&cluster_name, &namespace, &shard.shard_name)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        route
+            .delete_shard(serde_json::to_vec(&shard).unwrap())
+            .await
+            .unwrap();
+
+        assert!(segment_storage
+            .list_by_shard(&cluster_name, &namespace, &shard.shard_name)
+            .unwrap()
+            .is_empty());
+        assert!(meta_storage
+            .list_by_shard(&cluster_name, &namespace, &shard.shard_name)
+            .unwrap()
+            .is_empty());
+        assert!(engine_cache
+            .get_segment_list_by_shard(&cluster_name, &namespace, &shard.shard_name)
+            .is_empty());
+        assert!(engine_cache
+            .get_segment_meta_list_by_shard(&cluster_name, &namespace, &shard.shard_name)
+            .is_empty());
+        assert_eq!(cluster_cache.get_node_segment_num(&cluster_name, 1), 1);
+
+        // the segments of the other shard are kept
+        assert_eq!(
+            segment_storage
+                .list_by_shard(&cluster_name, &namespace, &other_shard.shard_name)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        remove_dir_all(data_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn segment_replica_num_apply_test() {
+        let config = placement_center_test_conf();
+        let data_path = format!("{}/{}", config.rocksdb.data_path, unique_id());
+        let rocksdb_engine = Arc::new(RocksDBEngine::new(
+            &data_path,
+            config.rocksdb.max_open_files.unwrap(),
+            column_family_list(),
+        ));
+        let cluster_cache = Arc::new(PlacementCacheManager::new(rocksdb_engine.clone()));
+        let engine_cache = Arc::new(JournalCacheManager::new());
+        let route = DataRouteJournal::new(rocksdb_engine, cluster_cache.clone(), engine_cache);
+
+        let cluster_name = unique_id();
+        let replicas = |node_ids: &[u64]| -> Vec<Replica> {
+            node_ids
+                .iter()
+                .enumerate()
+                .map(|(i, node_id)| Replica {
+                    replica_seq: i as u64,
+                    node_id: *node_id,
+                    fold: "/data".to_string(),
+                })
+                .collect()
+        };
+        let segment_num = |node_id| cluster_cache.get_node_segment_num(&cluster_name, node_id);
+
+        let other_segment = JournalSegment {
+            cluster_name: cluster_name.clone(),
+            namespace: "n1".to_string(),
+            shard_name: "s1".to_string(),
+            segment_seq: 0,
+            replicas: replicas(&[2]),
+            ..Default::default()
+        };
+        let segment = JournalSegment {
+            segment_seq: 1,
+            replicas: replicas(&[1, 2]),
+            ..other_segment.clone()
+        };
+        for segment in [&other_segment, &segment] {
+            route
+                .set_segment(serde_json::to_vec(segment).unwrap())
+                .await
+                .unwrap();
+        }
+        assert_eq!((segment_num(1), segment_num(2), segment_num(3)), (1, 2, 0));
+
+        // an update of the segment keeps the counts, a new replica set replaces the old one
+        let sealed = JournalSegment {
+            status: SegmentStatus::SealUp,
+            ..segment.clone()
+        };
+        route
+            .set_segment(serde_json::to_vec(&sealed).unwrap())
+            .await
+            .unwrap();
+        assert_eq!((segment_num(1), segment_num(2), segment_num(3)), (1, 2, 0));
+        let moved = JournalSegment {
+            replicas: replicas(&[2, 3]),
+            ..sealed
+        };
+        route
+            .set_segment(serde_json::to_vec(&moved).unwrap())
+            .await
+            .unwrap();
+        assert_eq!((segment_num(1), segment_num(2), segment_num(3)), (0, 2, 1));
+
+        // a segment that is already deleted is not counted down again
+        for _ in 0..2 {
+            route
+                .delete_segment(serde_json::to_vec(&moved).unwrap())
+                .await
+                .unwrap();
+            assert_eq!((segment_num(1), segment_num(2), segment_num(3)), (0, 1, 0));
+        }
+
+        remove_dir_all(data_path).unwrap();
+    }
+}
@@ -56,8 +56,11 @@ impl DataRoute {
         let route_mqtt = DataRouteMqtt::new(rocksdb_engine_handler.clone(), mqtt_cache.clone());
         let route_cluster =
             DataRouteCluster::new(rocksdb_engine_handler.clone(), cluster_cache.clone());
-        let route_journal =
-            DataRouteJournal::new(rocksdb_engine_handler.clone(), engine_cache.clone());
+        let route_journal = DataRouteJournal::new(
+            rocksdb_engine_handler.clone(),
+            cluster_cache.clone(),
+            engine_cache.clone(),
+        );
         DataRoute {
             route_kv,
             route_mqtt,
@@ -219,6 +222,11 @@ impl DataRoute {
                 self.route_mqtt.save_last_will_message(storage_data.value)?;
                 Ok(None)
             }
+            StorageDataType::MqttDeleteLastWillMessage => {
+                self.route_mqtt
+                    .delete_last_will_message(storage_data.value)?;
+                Ok(None)
+            }
 
             StorageDataType::MqttCreateTopicRewriteRule => {
                 self.route_mqtt
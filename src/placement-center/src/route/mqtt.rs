@@ -27,9 +27,10 @@ use prost::Message as _;
 use protocol::placement_center::placement_center_mqtt::{
     CreateAclRequest, CreateBlacklistRequest, CreateConnectorRequest, CreateSessionRequest,
     CreateTopicRequest, CreateTopicRewriteRuleRequest, CreateUserRequest, DeleteAclRequest,
-    DeleteBlacklistRequest, DeleteConnectorRequest, DeleteSessionRequest, DeleteSubscribeRequest,
-    DeleteTopicRequest, DeleteTopicRewriteRuleRequest, DeleteUserRequest,
-    SaveLastWillMessageRequest, SetSubscribeRequest, UpdateSessionRequest,
+    DeleteBlacklistRequest, DeleteConnectorRequest, DeleteLastWillMessageRequest,
+    DeleteSessionRequest, DeleteSubscribeRequest, DeleteTopicRequest,
+    DeleteTopicRewriteRuleRequest, DeleteUserRequest, SaveLastWillMessageRequest,
+    SetSubscribeRequest, UpdateSessionRequest,
 };
 
 use crate::core::error::PlacementCenterError;
@@ -108,6 +109,13 @@ impl DataRouteMqtt {
         Ok(())
     }
 
+    pub fn delete_last_will_message(&self, value: Vec<u8>) -> Result<(), PlacementCenterError> {
+        let req = DeleteLastWillMessageRequest::decode(value.as_ref())?;
+        let storage = MqttLastWillStorage::new(self.rocksdb_engine_handler.clone());
+        storage.delete(&req.cluster_name, &req.client_id)?;
+        Ok(())
+    }
+
     // Session
     pub fn create_session(&self, value: Vec<u8>) -> Result<(), PlacementCenterError> {
         let req = CreateSessionRequest::decode(value.as_ref())?;
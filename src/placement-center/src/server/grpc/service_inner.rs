@@ -22,11 +22,12 @@ use prost::Message;
 use prost_validate::Validator;
 use protocol::placement_center::placement_center_inner::placement_center_service_server::PlacementCenterService;
 use protocol::placement_center::placement_center_inner::{
-    BindSchemaReply, BindSchemaRequest, ClusterStatusReply, ClusterStatusRequest,
-    CreateSchemaReply, CreateSchemaRequest, DeleteIdempotentDataReply, DeleteIdempotentDataRequest,
-    DeleteResourceConfigReply, DeleteResourceConfigRequest, DeleteSchemaReply, DeleteSchemaRequest,
-    ExistsIdempotentDataReply, ExistsIdempotentDataRequest, GetOffsetDataReply,
-    GetOffsetDataReplyOffset, GetOffsetDataRequest, GetResourceConfigReply,
+    BindSchemaReply, BindSchemaRequest, BrokerHeartbeatReply, BrokerHeartbeatRequest,
+    ClusterStatusReply, ClusterStatusRequest, CreateSchemaReply, CreateSchemaRequest,
+    DeleteIdempotentDataReply, DeleteIdempotentDataRequest, DeleteResourceConfigReply,
+    DeleteResourceConfigRequest, DeleteSchemaReply, DeleteSchemaRequest, DescribeClusterReply,
+    DescribeClusterRequest, ExistsIdempotentDataReply, ExistsIdempotentDataRequest,
+    GetOffsetDataReply, GetOffsetDataReplyOffset, GetOffsetDataRequest, GetResourceConfigReply,
     GetResourceConfigRequest, HeartbeatReply, HeartbeatRequest, ListBindSchemaReply,
     ListBindSchemaRequest, ListSchemaReply, ListSchemaRequest, NodeListReply, NodeListRequest,
     RegisterNodeReply, RegisterNodeRequest, ReportMonitorReply, ReportMonitorRequest,
@@ -38,7 +39,9 @@ use tonic::{Request, Response, Status};
 
 use super::validate::ValidateExt;
 use crate::core::cache::PlacementCacheManager;
-use crate::core::cluster::{register_node_by_req, un_register_node_by_req};
+use crate::core::cluster::{
+    describe_cluster_by_req, register_node_by_req, un_register_node_by_req,
+};
 use crate::core::error::PlacementCenterError;
 use crate::core::schema::{
     bind_schema_req, create_schema_req, delete_schema_req, list_bind_schema_req, list_schema_req,
@@ -107,6 +110,14 @@ impl PlacementCenterService for GrpcPlacementService {
         return Ok(Response::new(reply));
     }
 
+    async fn describe_cluster(
+        &self,
+        _: Request<DescribeClusterRequest>,
+    ) -> Result<Response<DescribeClusterReply>, Status> {
+        let peers = describe_cluster_by_req(&self.raft_machine_apply);
+        Ok(Response::new(DescribeClusterReply { peers }))
+    }
+
     async fn node_list(
         &self,
         request: Request<NodeListRequest>,
@@ -115,12 +126,18 @@ impl PlacementCenterService for GrpcPlacementService {
 
         let _ = req.validate_ext()?;
 
-        let mut nodes = Vec::new();
+        let broker_nodes = if req.consistent {
+            self.raft_machine_apply
+                .client_read(|cache| cache.get_broker_node_by_cluster(&req.cluster_name))
+                .await
+                .map_err(|e| Status::cancelled(e.to_string()))?
+        } else {
+            self.cluster_cache
+                .get_broker_node_by_cluster(&req.cluster_name)
+        };
 
-        for raw in self
-            .cluster_cache
-            .get_broker_node_by_cluster(&req.cluster_name)
-        {
+        let mut nodes = Vec::new();
+        for raw in broker_nodes {
             nodes.push(raw.encode())
         }
 
@@ -202,6 +219,42 @@ impl PlacementCenterService for GrpcPlacementService {
         return Ok(Response::new(HeartbeatReply::default()));
     }
 
+    async fn broker_heartbeat(
+        &self,
+        request: Request<BrokerHeartbeatRequest>,
+    ) -> Result<Response<BrokerHeartbeatReply>, Status> {
+        let req = request.into_inner();
+        if self
+            .cluster_cache
+            .get_broker_node(&req.cluster_name, req.node_id)
+            .is_none()
+        {
+            return Err(Status::internal(
+                PlacementCenterError::NodeDoesNotExist(req.node_id).to_string(),
+            ));
+        }
+
+        debug!(
+            "receive broker heartbeat from node:{:?},active connections:{}",
+            req.node_id, req.active_connections
+        );
+        self.cluster_cache
+            .report_broker_heart(&req.cluster_name, req.node_id);
+        self.cluster_cache.report_broker_load(
+            &req.cluster_name,
+            req.node_id,
+            &req.addr,
+            req.active_connections,
+        );
+
+        let least_loaded_addr = self
+            .cluster_cache
+            .get_least_loaded_broker(&req.cluster_name, req.node_id)
+            .map(|load| load.addr)
+            .unwrap_or_default();
+        return Ok(Response::new(BrokerHeartbeatReply { least_loaded_addr }));
+    }
+
     async fn report_monitor(
         &self,
         _: Request<ReportMonitorRequest>,
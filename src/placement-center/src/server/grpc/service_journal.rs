@@ -21,7 +21,7 @@ use protocol::placement_center::placement_center_journal::{
     DeleteSegmentReply, DeleteSegmentRequest, DeleteShardReply, DeleteShardRequest,
     ListSegmentMetaReply, ListSegmentMetaRequest, ListSegmentReply, ListSegmentRequest,
     ListShardReply, ListShardRequest, UpdateSegmentMetaReply, UpdateSegmentMetaRequest,
-    UpdateSegmentStatusReply, UpdateSegmentStatusRequest,
+    UpdateSegmentStatusReply, UpdateSegmentStatusRequest, UpdateShardReply, UpdateShardRequest,
 };
 use rocksdb_engine::RocksDBEngine;
 use tonic::{Request, Response, Status};
@@ -34,7 +34,9 @@ use crate::journal::services::segment::{
     create_segment_by_req, delete_segment_by_req, update_segment_meta_req,
     update_segment_status_req,
 };
-use crate::journal::services::shard::{create_shard_by_req, delete_shard_by_req};
+use crate::journal::services::shard::{
+    create_shard_by_req, delete_shard_by_req, update_shard_by_req,
+};
 use crate::route::apply::RaftMachineApply;
 use crate::storage::journal::segment::SegmentStorage;
 use crate::storage::journal::segment_meta::SegmentMetadataStorage;
@@ -178,6 +180,37 @@ impl EngineService for GrpcEngineService {
         }
     }
 
+    async fn update_shard(
+        &self,
+        request: Request<UpdateShardRequest>,
+    ) -> Result<Response<UpdateShardReply>, Status> {
+        let req = request.into_inner();
+
+        if self.cluster_cache.get_cluster(&req.cluster_name).is_none() {
+            return Err(Status::cancelled(
+                PlacementCenterError::ClusterDoesNotExist(req.cluster_name).to_string(),
+            ));
+        }
+
+        match update_shard_by_req(
+            &self.engine_cache,
+            &self.cluster_cache,
+            &self.raft_machine_apply,
+            &self.call_manager,
+            &self.client_pool,
+            &req,
+        )
+        .await
+        {
+            Ok(data) => {
+                return Ok(Response::new(data));
+            }
+            Err(e) => {
+                return Err(Status::cancelled(e.to_string()));
+            }
+        }
+    }
+
     async fn list_segment(
         &self,
         request: Request<ListSegmentRequest>,
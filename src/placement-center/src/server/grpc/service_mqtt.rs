@@ -22,16 +22,18 @@ use protocol::placement_center::placement_center_mqtt::{
     CreateSessionReply, CreateSessionRequest, CreateTopicReply, CreateTopicRequest,
     CreateTopicRewriteRuleReply, CreateTopicRewriteRuleRequest, CreateUserReply, CreateUserRequest,
     DeleteAclReply, DeleteAclRequest, DeleteBlacklistReply, DeleteBlacklistRequest,
-    DeleteConnectorReply, DeleteConnectorRequest, DeleteSessionReply, DeleteSessionRequest,
-    DeleteSubscribeReply, DeleteSubscribeRequest, DeleteTopicReply, DeleteTopicRequest,
-    DeleteTopicRewriteRuleReply, DeleteTopicRewriteRuleRequest, DeleteUserReply, DeleteUserRequest,
-    GetShareSubLeaderReply, GetShareSubLeaderRequest, ListAclReply, ListAclRequest,
-    ListBlacklistReply, ListBlacklistRequest, ListConnectorReply, ListConnectorRequest,
-    ListSessionReply, ListSessionRequest, ListSubscribeReply, ListSubscribeRequest, ListTopicReply,
-    ListTopicRequest, ListTopicRewriteRuleReply, ListTopicRewriteRuleRequest, ListUserReply,
-    ListUserRequest, SaveLastWillMessageReply, SaveLastWillMessageRequest, SetSubscribeReply,
-    SetSubscribeRequest, SetTopicRetainMessageReply, SetTopicRetainMessageRequest,
-    UpdateConnectorReply, UpdateConnectorRequest, UpdateSessionReply, UpdateSessionRequest,
+    DeleteConnectorReply, DeleteConnectorRequest, DeleteLastWillMessageReply,
+    DeleteLastWillMessageRequest, DeleteSessionReply, DeleteSessionRequest, DeleteSubscribeReply,
+    DeleteSubscribeRequest, DeleteTopicReply, DeleteTopicRequest, DeleteTopicRewriteRuleReply,
+    DeleteTopicRewriteRuleRequest, DeleteUserReply, DeleteUserRequest, GetLastWillMessageReply,
+    GetLastWillMessageRequest, GetShareSubLeaderReply, GetShareSubLeaderRequest, ListAclReply,
+    ListAclRequest, ListBlacklistReply, ListBlacklistRequest, ListConnectorReply,
+    ListConnectorRequest, ListSessionReply, ListSessionRequest, ListSubscribeReply,
+    ListSubscribeRequest, ListTopicReply, ListTopicRequest, ListTopicRewriteRuleReply,
+    ListTopicRewriteRuleRequest, ListUserReply, ListUserRequest, SaveLastWillMessageReply,
+    SaveLastWillMessageRequest, SetSubscribeReply, SetSubscribeRequest, SetTopicRetainMessageReply,
+    SetTopicRetainMessageRequest, UpdateConnectorReply, UpdateConnectorRequest, UpdateSessionReply,
+    UpdateSessionRequest,
 };
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
@@ -58,6 +60,7 @@ use crate::route::apply::RaftMachineApply;
 use crate::route::data::{StorageData, StorageDataType};
 use crate::server::grpc::validate::ValidateExt;
 use crate::storage::mqtt::blacklist::MqttBlackListStorage;
+use crate::storage::mqtt::lastwill::MqttLastWillStorage;
 use crate::storage::mqtt::subscribe::MqttSubscribeStorage;
 use crate::storage::mqtt::topic::MqttTopicStorage;
 use crate::storage::rocksdb::RocksDBEngine;
@@ -333,6 +336,37 @@ impl MqttService for GrpcMqttService {
         }
     }
 
+    async fn get_last_will_message(
+        &self,
+        request: Request<GetLastWillMessageRequest>,
+    ) -> Result<Response<GetLastWillMessageReply>, Status> {
+        let req = request.into_inner();
+        let storage = MqttLastWillStorage::new(self.rocksdb_engine_handler.clone());
+        match storage.get(&req.cluster_name, &req.client_id) {
+            Ok(Some(data)) => Ok(Response::new(GetLastWillMessageReply {
+                last_will_message: data.encode(),
+            })),
+            Ok(None) => Ok(Response::new(GetLastWillMessageReply::default())),
+            Err(e) => Err(Status::cancelled(e.to_string())),
+        }
+    }
+
+    async fn delete_last_will_message(
+        &self,
+        request: Request<DeleteLastWillMessageRequest>,
+    ) -> Result<Response<DeleteLastWillMessageReply>, Status> {
+        let req = request.into_inner();
+        let data = StorageData::new(
+            StorageDataType::MqttDeleteLastWillMessage,
+            DeleteLastWillMessageRequest::encode_to_vec(&req),
+        );
+
+        match self.raft_machine_apply.client_write(data).await {
+            Ok(_) => Ok(Response::new(DeleteLastWillMessageReply::default())),
+            Err(e) => Err(Status::cancelled(e.to_string())),
+        }
+    }
+
     // ACL
     async fn list_acl(
         &self,
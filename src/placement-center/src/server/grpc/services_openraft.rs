@@ -17,7 +17,8 @@ use openraft::Raft;
 use protocol::placement_center::placement_center_openraft::open_raft_service_server::OpenRaftService;
 use protocol::placement_center::placement_center_openraft::{
     AddLearnerReply, AddLearnerRequest, AppendReply, AppendRequest, ChangeMembershipReply,
-    ChangeMembershipRequest, SnapshotReply, SnapshotRequest, VoteReply, VoteRequest,
+    ChangeMembershipRequest, ReadIndexReply, ReadIndexRequest, SnapshotReply, SnapshotRequest,
+    VoteReply, VoteRequest,
 };
 use tonic::{Request, Response, Status};
 
@@ -136,4 +137,22 @@ impl OpenRaftService for GrpcOpenRaftServices {
         let reply = ChangeMembershipReply { value };
         return Ok(Response::new(reply));
     }
+
+    async fn read_index(
+        &self,
+        _request: Request<ReadIndexRequest>,
+    ) -> Result<Response<ReadIndexReply>, Status> {
+        // Confirms with a quorum that this node is still the leader and returns
+        // the log id that must be applied before a linearizable read is served.
+        let res = match self.raft_node.ensure_linearizable().await {
+            Ok(data) => data,
+            Err(e) => {
+                return Err(Status::cancelled(e.to_string()));
+            }
+        };
+
+        let value = serialize(&res).map_err(|e| Status::cancelled(e.to_string()))?;
+        let reply = ReadIndexReply { value };
+        return Ok(Response::new(reply));
+    }
 }
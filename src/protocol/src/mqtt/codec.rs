@@ -82,6 +82,9 @@ impl MqttCodec {
                         return Ok(Some(packet));
                     }
                 }
+                Err(Error::InconsistentWillFlags(connect_flags)) => {
+                    return Err(Error::InconsistentWillFlags(connect_flags));
+                }
                 Err(_) => {
                     return Err(Error::InvalidProtocol);
                 }
@@ -428,6 +428,14 @@ pub fn qos(num: u8) -> Option<QoS> {
     }
 }
 
+/// If the Will Flag is 0, the Will QoS and the Will Retain of the CONNECT must be 0 too
+pub fn check_will_flags(connect_flags: u8) -> Result<(), Error> {
+    if connect_flags & 0b100 == 0 && connect_flags & 0b0011_1000 != 0 {
+        return Err(Error::InconsistentWillFlags(connect_flags));
+    }
+    Ok(())
+}
+
 impl From<QoS> for u8 {
     fn from(value: QoS) -> Self {
         match value {
@@ -1113,6 +1121,10 @@ pub enum Error {
     InvalidProtocolLevel(u8),
     #[error("Invalid packet format")]
     IncorrectPacketFormat,
+    #[error(
+        "Will QoS and Will Retain must be 0 when the Will Flag is 0, connect flags = {0:#010b}"
+    )]
+    InconsistentWillFlags(u8),
     #[error("Invalid packet type = {0}")]
     InvalidPacketType(u8),
     #[error("Invalid retain forward rule = {0}")]
@@ -127,13 +127,10 @@ pub mod will {
     }
 
     pub fn read(connect_flags: u8, bytes: &mut Bytes) -> Result<Option<LastWill>, Error> {
+        // when Will Flag(bit 2) is 0, Will QoS(bit 3 & 4) and Will Retain(bit 5) must be 0
+        check_will_flags(connect_flags)?;
         let last_will = match connect_flags & 0b100 {
             // & 0b100 to check Will Flag(bit 2) is 0 or 1
-            0 if (connect_flags & 0b0011_1000) != 0 => {
-                // when Will Flag is 0, then "if" part checks
-                // Will QoS(bit 3 & 4) and Will Retain(bit 5)
-                return Err(Error::IncorrectPacketFormat); // if Will QoS and Retain are 1 but Will Flag 0, return incorrect
-            }
             0 => None,
             _ => {
                 let will_topic = read_mqtt_bytes(bytes)?;
@@ -146,7 +143,7 @@ pub mod will {
                     topic: will_topic,
                     message: will_message,
                     qos: will_qos,
-                    retain: (connect_flags & 0b0010_00000) != 0,
+                    retain: (connect_flags & 0b0010_0000) != 0,
                 })
             }
         };
@@ -336,4 +333,55 @@ mod tests {
         println!("{}", lastwill);
         println!("connect display ends.............................");
     }
+
+    #[test]
+    fn test_connect_will_flags() {
+        use super::*;
+
+        let connect = Connect {
+            keep_alive: 30u16,
+            client_id: String::from("test_client_id"),
+            clean_session: true,
+        };
+
+        // will flag 0 with will qos 1
+        let mut buffer = BytesMut::new();
+        write(&connect, &None, &None, &mut buffer).unwrap();
+        let fixedheader: FixedHeader = parse_fixed_header(buffer.iter()).unwrap();
+        // connect flags follow the protocol name and the protocol level
+        let flags_index = fixedheader.fixed_header_len + 2 + 4 + 1;
+        buffer[flags_index] |= 0b0000_1000;
+        let result = read(fixedheader, buffer.copy_to_bytes(buffer.len()));
+        assert!(matches!(result, Err(Error::InconsistentWillFlags(_))));
+
+        // will flag 0 with will retain 1
+        let mut buffer = BytesMut::new();
+        write(&connect, &None, &None, &mut buffer).unwrap();
+        let fixedheader: FixedHeader = parse_fixed_header(buffer.iter()).unwrap();
+        buffer[flags_index] |= 0b0010_0000;
+        let result = read(fixedheader, buffer.copy_to_bytes(buffer.len()));
+        assert!(matches!(result, Err(Error::InconsistentWillFlags(_))));
+
+        // will flag 1 with every will qos and retain
+        for (qos, retain) in [
+            (QoS::AtMostOnce, false),
+            (QoS::AtLeastOnce, true),
+            (QoS::ExactlyOnce, false),
+            (QoS::ExactlyOnce, true),
+        ] {
+            let lastwill = LastWill {
+                topic: Bytes::from("will_topic"),
+                message: Bytes::from("will_message"),
+                qos,
+                retain,
+            };
+            let mut buffer = BytesMut::new();
+            write(&connect, &None, &Some(lastwill), &mut buffer).unwrap();
+            let fixedheader: FixedHeader = parse_fixed_header(buffer.iter()).unwrap();
+            let (_, _, _, will) = read(fixedheader, buffer.copy_to_bytes(buffer.len())).unwrap();
+            let will = will.unwrap();
+            assert_eq!(will.qos, qos);
+            assert_eq!(will.retain, retain);
+        }
+    }
 }
@@ -329,10 +329,8 @@ pub mod will {
         connect_flags: u8,
         bytes: &mut Bytes,
     ) -> Result<(Option<LastWill>, Option<LastWillProperties>), Error> {
+        check_will_flags(connect_flags)?;
         let o = match connect_flags & 0b100 {
-            0 if (connect_flags & 0b0011_1000) != 0 => {
-                return Err(Error::IncorrectPacketFormat);
-            }
             0 => (None, None),
             _ => {
                 // Properties in variable header
@@ -742,4 +740,73 @@ mod tests {
         println!("{}", lastwillproperties_read);
         println!("connect in v5 display ends.............................");
     }
+
+    #[test]
+    fn test_connect_v5_invalid_will_qos() {
+        use super::*;
+
+        let mut buffer = BytesMut::new();
+        let connect = Connect {
+            keep_alive: 30u16,
+            client_id: String::from("test_client_id"),
+            clean_session: true,
+        };
+        let lastwill = LastWill {
+            topic: Bytes::from("will_topic"),
+            message: Bytes::from("will_message"),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+        };
+        write(&connect, &None, &Some(lastwill), &None, &None, &mut buffer).unwrap();
+
+        let fixedheader: FixedHeader = parse_fixed_header(buffer.iter()).unwrap();
+        // connect flags follow the protocol name and the protocol level, set the will qos to 3
+        let flags_index = fixedheader.fixed_header_len + 2 + 4 + 1;
+        buffer[flags_index] |= 0b0001_1000;
+
+        let result = read(fixedheader, buffer.copy_to_bytes(buffer.len()));
+        assert!(matches!(result, Err(Error::InvalidQoS(3))));
+    }
+
+    #[test]
+    fn test_connect_v5_will_flags() {
+        use super::*;
+
+        let connect = Connect {
+            keep_alive: 30u16,
+            client_id: String::from("test_client_id"),
+            clean_session: true,
+        };
+
+        // will flag 0 with will qos 1
+        let mut buffer = BytesMut::new();
+        write(&connect, &None, &None, &None, &None, &mut buffer).unwrap();
+        let fixedheader: FixedHeader = parse_fixed_header(buffer.iter()).unwrap();
+        let flags_index = fixedheader.fixed_header_len + 2 + 4 + 1;
+        buffer[flags_index] |= 0b0000_1000;
+        let result = read(fixedheader, buffer.copy_to_bytes(buffer.len()));
+        assert!(matches!(result, Err(Error::InconsistentWillFlags(_))));
+
+        // will flag 0, will qos 0 and will retain 0
+        let mut buffer = BytesMut::new();
+        write(&connect, &None, &None, &None, &None, &mut buffer).unwrap();
+        let fixedheader: FixedHeader = parse_fixed_header(buffer.iter()).unwrap();
+        let outcome = read(fixedheader, buffer.copy_to_bytes(buffer.len())).unwrap();
+        assert!(outcome.last_will.is_none());
+
+        // will flag 1 with will qos 2 and will retain 1
+        let lastwill = LastWill {
+            topic: Bytes::from("will_topic"),
+            message: Bytes::from("will_message"),
+            qos: QoS::ExactlyOnce,
+            retain: true,
+        };
+        let mut buffer = BytesMut::new();
+        write(&connect, &None, &Some(lastwill), &None, &None, &mut buffer).unwrap();
+        let fixedheader: FixedHeader = parse_fixed_header(buffer.iter()).unwrap();
+        let outcome = read(fixedheader, buffer.copy_to_bytes(buffer.len())).unwrap();
+        let will = outcome.last_will.unwrap();
+        assert_eq!(will.qos, QoS::ExactlyOnce);
+        assert!(will.retain);
+    }
 }
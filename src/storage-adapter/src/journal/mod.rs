@@ -158,6 +158,18 @@ impl StorageAdapter for JournalStorageAdapter {
         }
     }
 
+    async fn read_latest(
+        &self,
+        _namespace: String,
+        _shard_name: String,
+        _read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        Err(CommonError::NotSupportFeature(
+            "JournalStorageAdapter".to_string(),
+            "read_latest".to_string(),
+        ))
+    }
+
     async fn read_by_tag(
         &self,
         namespace: String,
@@ -247,3 +259,34 @@ impl StorageAdapter for JournalStorageAdapter {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use common_base::error::common::CommonError;
+    use grpc_clients::pool::ClientPool;
+    use metadata_struct::adapter::read_config::ReadConfig;
+
+    use super::JournalStorageAdapter;
+    use crate::storage::StorageAdapter;
+
+    #[tokio::test]
+    #[ignore]
+    async fn read_latest_not_support_test() {
+        // needs a journal server and a placement center running with the default config
+        let storage_adapter = JournalStorageAdapter::new(
+            Arc::new(ClientPool::new(1)),
+            "test".to_string(),
+            vec!["127.0.0.1:3110".to_string()],
+            vec!["127.0.0.1:1228".to_string()],
+        )
+        .await
+        .unwrap();
+
+        let result = storage_adapter
+            .read_latest("n1".to_string(), "s1".to_string(), ReadConfig::default())
+            .await;
+        assert!(matches!(result, Err(CommonError::NotSupportFeature(_, _))));
+    }
+}
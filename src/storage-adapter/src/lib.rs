@@ -17,6 +17,7 @@ use std::str::FromStr;
 pub mod journal;
 pub mod memory;
 pub mod minio;
+pub mod mmap;
 pub mod mysql;
 pub mod placement;
 pub mod rocksdb;
@@ -31,6 +32,7 @@ pub enum StorageType {
     Placement,
     RocksDB,
     MinIO,
+    Mmap,
 }
 
 impl FromStr for StorageType {
@@ -44,6 +46,7 @@ impl FromStr for StorageType {
             "placement" => Ok(StorageType::Placement),
             "rocksdb" => Ok(StorageType::RocksDB),
             "minio" => Ok(StorageType::MinIO),
+            "mmap" => Ok(StorageType::Mmap),
             _ => Err(()),
         }
     }
@@ -109,5 +112,6 @@ mod tests {
             StorageType::RocksDB
         );
         assert_eq!(StorageType::from_str("minio").unwrap(), StorageType::MinIO);
+        assert_eq!(StorageType::from_str("mmap").unwrap(), StorageType::Mmap);
     }
 }
@@ -165,6 +165,26 @@ impl StorageAdapter for MemoryStorageAdapter {
         Ok(Vec::new())
     }
 
+    async fn read_latest(
+        &self,
+        namespace: String,
+        shard_name: String,
+        read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        let shard_key = self.shard_key(&namespace, &shard_name);
+
+        if let Some(data_list) = self.shard_data.get(&shard_key) {
+            return Ok(data_list
+                .iter()
+                .rev()
+                .take(read_config.max_record_num as usize)
+                .cloned()
+                .collect());
+        }
+
+        Ok(Vec::new())
+    }
+
     async fn read_by_tag(
         &self,
         namespace: String,
@@ -179,18 +199,14 @@ impl StorageAdapter for MemoryStorageAdapter {
             if record_list.len() < offset as usize {
                 return Ok(Vec::new());
             }
-            let mut result = Vec::new();
-
-            for i in offset..(offset + read_config.max_record_num) {
-                if let Some(value) = record_list.get(i as usize) {
-                    if value.tags.contains(&tag) {
-                        result.push(value.clone());
-                    }
-                } else {
-                    break;
-                }
-            }
-            return Ok(result);
+            // Up to max_record_num records carrying the tag, as the backends with a tag index
+            return Ok(record_list
+                .iter()
+                .skip(offset as usize)
+                .filter(|value| value.tags.contains(&tag))
+                .take(read_config.max_record_num as usize)
+                .cloned()
+                .collect());
         }
 
         Ok(Vec::new())
@@ -301,6 +317,7 @@ mod tests {
     use std::collections::HashMap;
 
     use common_base::tools::unique_id;
+    use futures::{pin_mut, TryStreamExt};
     use metadata_struct::adapter::read_config::ReadConfig;
     use metadata_struct::adapter::record::Record;
 
@@ -464,4 +481,102 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn read_latest_test() {
+        let storage_adapter = MemoryStorageAdapter::new();
+        let namespace = unique_id();
+        let shard_name = "test-latest".to_string();
+
+        let data = (0..5)
+            .map(|i| Record::build_byte(format!("test{}", i).as_bytes().to_vec()))
+            .collect();
+        storage_adapter
+            .batch_write(namespace.clone(), shard_name.clone(), data)
+            .await
+            .unwrap();
+
+        let mut read_config = ReadConfig::new();
+        read_config.max_record_num = 3;
+        let res = storage_adapter
+            .read_latest(namespace.clone(), shard_name.clone(), read_config.clone())
+            .await
+            .unwrap();
+        let offsets: Vec<u64> = res.iter().map(|r| r.offset.unwrap()).collect();
+        assert_eq!(offsets, vec![4, 3, 2]);
+
+        let res = storage_adapter
+            .read_latest(namespace, "not-exist".to_string(), read_config)
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+    }
+
+    // The resident memory of the process in KB
+    fn vm_rss_kb() -> u64 {
+        std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| {
+                status
+                    .lines()
+                    .find(|line| line.starts_with("VmRSS:"))
+                    .and_then(|line| line.split_whitespace().nth(1))
+                    .and_then(|kb| kb.parse().ok())
+            })
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn iter_shard_records_test() {
+        let storage_adapter = MemoryStorageAdapter::new();
+        let namespace = unique_id();
+        let shard_name = "test-iter".to_string();
+
+        for _ in 0..100 {
+            let data = (0..1000)
+                .map(|_| Record::build_byte(vec![b'a'; 1024]))
+                .collect();
+            storage_adapter
+                .batch_write(namespace.clone(), shard_name.clone(), data)
+                .await
+                .unwrap();
+        }
+
+        let start_rss = vm_rss_kb();
+        let mut peak_rss = start_rss;
+        let records = storage_adapter.iter_shard_records(namespace.clone(), shard_name.clone(), 0);
+        pin_mut!(records);
+        let mut expected_offset = 0;
+        while let Some(record) = records.try_next().await.unwrap() {
+            assert_eq!(record.offset, Some(expected_offset));
+            assert_eq!(record.data.len(), 1024);
+            expected_offset += 1;
+            if expected_offset % 1000 == 0 {
+                peak_rss = peak_rss.max(vm_rss_kb());
+            }
+        }
+        assert_eq!(expected_offset, 100_000);
+        // the records are streamed, not copied out of the shard all at once
+        assert!(peak_rss.saturating_sub(start_rss) < 100 * 1024);
+
+        // from the middle of the shard, and from its end
+        let records: Vec<Record> = storage_adapter
+            .iter_shard_records(namespace.clone(), shard_name.clone(), 99_998)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| r.offset.unwrap())
+                .collect::<Vec<u64>>(),
+            vec![99_998, 99_999]
+        );
+        let records: Vec<Record> = storage_adapter
+            .iter_shard_records(namespace, shard_name, 100_000)
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(records.is_empty());
+    }
 }
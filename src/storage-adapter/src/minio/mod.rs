@@ -426,6 +426,18 @@ impl StorageAdapter for MinIoStorageAdapter {
         Ok(res)
     }
 
+    async fn read_latest(
+        &self,
+        _namespace: String,
+        _shard_name: String,
+        _read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        Err(CommonError::NotSupportFeature(
+            "MinIoStorageAdapter".to_string(),
+            "read_latest".to_string(),
+        ))
+    }
+
     async fn read_by_tag(
         &self,
         namespace: String,
@@ -584,7 +596,7 @@ impl StorageAdapter for MinIoStorageAdapter {
 mod tests {
     use std::{collections::HashMap, sync::Arc, vec};
 
-    use common_base::{tools::unique_id, utils::crc::calc_crc32};
+    use common_base::{error::common::CommonError, tools::unique_id, utils::crc::calc_crc32};
     use futures::future;
     use metadata_struct::adapter::{
         read_config::ReadConfig,
@@ -596,6 +608,16 @@ mod tests {
         storage::{ShardInfo, StorageAdapter},
     };
 
+    #[tokio::test]
+    async fn read_latest_not_support_test() {
+        let storage_adapter = MinIoStorageAdapter::new("/tmp/minio", "test").unwrap();
+
+        let result = storage_adapter
+            .read_latest(unique_id(), "test-11".to_string(), ReadConfig::default())
+            .await;
+        assert!(matches!(result, Err(CommonError::NotSupportFeature(_, _))));
+    }
+
     #[tokio::test]
     #[ignore]
     async fn stream_read_write() {
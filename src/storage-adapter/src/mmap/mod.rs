@@ -0,0 +1,1111 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use axum::async_trait;
+use common_base::error::common::CommonError;
+use dashmap::DashMap;
+use memmap2::Mmap;
+use metadata_struct::adapter::read_config::ReadConfig;
+use metadata_struct::adapter::record::{Header, Record};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{ShardInfo, ShardOffset, StorageAdapter};
+
+const SHARD_DIR: &str = "shard";
+const GROUP_DIR: &str = "group";
+const SHARD_LOG_FILE: &str = "records.log";
+const SHARD_INFO_FILE: &str = "shard.json";
+// Every record is prefixed by the length of its frame
+const FRAME_LEN_SIZE: usize = 4;
+// The timestamp follows the offset at the start of the frame
+const FRAME_TIMESTAMP_POS: usize = 8;
+
+/// A storage adapter for single broker deployments, which keeps every shard in an append-only
+/// log file mapped into the memory of the process. The records are decoded in place from the
+/// mapped pages, without a read into an intermediate buffer or a lookup of the record per
+/// offset as with the RocksDB adapter.
+///
+/// The offset, key and tag indexes are kept in memory and rebuilt from the logs when the
+/// adapter is opened. A record partially written when the process stopped is truncated.
+#[derive(Clone)]
+pub struct MmapStorageAdapter {
+    data_path: PathBuf,
+    shards: Arc<DashMap<String, Arc<RwLock<ShardLog>>>>,
+    // group, (namespace_shard_name, offset)
+    groups: Arc<DashMap<String, HashMap<String, GroupOffset>>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct GroupOffset {
+    namespace: String,
+    shard_name: String,
+    offset: u64,
+}
+
+struct ShardLog {
+    info: ShardInfo,
+    dir: PathBuf,
+    file: File,
+    // Mapped up to the length of the file at the last remap
+    mmap: Option<Mmap>,
+    // Bytes appended to the file
+    len: u64,
+    // Position of the frame of every record, by offset
+    positions: Vec<u64>,
+    // The offset of the last record of every key
+    keys: HashMap<String, u64>,
+    tags: HashMap<String, Vec<u64>>,
+}
+
+impl ShardLog {
+    fn create(dir: PathBuf, info: ShardInfo) -> Result<Self, CommonError> {
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(SHARD_INFO_FILE), serde_json::to_vec(&info)?)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(dir.join(SHARD_LOG_FILE))?;
+        Ok(ShardLog {
+            info,
+            dir,
+            file,
+            mmap: None,
+            len: 0,
+            positions: Vec::new(),
+            keys: HashMap::new(),
+            tags: HashMap::new(),
+        })
+    }
+
+    fn open(dir: PathBuf) -> Result<Self, CommonError> {
+        let info = serde_json::from_slice::<ShardInfo>(&fs::read(dir.join(SHARD_INFO_FILE))?)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(dir.join(SHARD_LOG_FILE))?;
+        let mut log = ShardLog {
+            info,
+            dir,
+            len: file.metadata()?.len(),
+            file,
+            mmap: None,
+            positions: Vec::new(),
+            keys: HashMap::new(),
+            tags: HashMap::new(),
+        };
+        log.remap()?;
+        log.recover()?;
+        Ok(log)
+    }
+
+    // Rebuilds the indexes from the log. The log is truncated at the first frame that is torn
+    // or does not decode, the frames after it are not readable either.
+    fn recover(&mut self) -> Result<(), CommonError> {
+        let mut pos = 0;
+        let mut entries = Vec::new();
+        if let Some(mmap) = &self.mmap {
+            while let Some(frame) = frame_at(mmap, pos) {
+                let Some((offset, key, tags)) = recover_frame(frame) else {
+                    break;
+                };
+                if offset != entries.len() as u64 {
+                    break;
+                }
+                entries.push((offset, pos, key, tags));
+                pos += (FRAME_LEN_SIZE + frame.len()) as u64;
+            }
+        }
+
+        if pos < self.len {
+            self.mmap = None;
+            self.file.set_len(pos)?;
+            self.len = pos;
+            self.remap()?;
+        }
+        for (offset, pos, key, tags) in entries {
+            self.positions.push(pos);
+            self.index(offset, key, tags);
+        }
+        Ok(())
+    }
+
+    fn next_offset(&self) -> u64 {
+        self.positions.len() as u64
+    }
+
+    fn index(&mut self, offset: u64, key: String, tags: Vec<String>) {
+        if !key.is_empty() {
+            self.keys.insert(key, offset);
+        }
+        for tag in tags {
+            self.tags.entry(tag).or_default().push(offset);
+        }
+    }
+
+    fn append(&mut self, records: Vec<Record>) -> Result<Vec<u64>, CommonError> {
+        let start_offset = self.next_offset();
+        let mut buf = Vec::new();
+        let mut positions = Vec::with_capacity(records.len());
+        for (i, record) in records.iter().enumerate() {
+            positions.push(self.len + buf.len() as u64);
+            encode_record(record, start_offset + i as u64, &mut buf);
+        }
+
+        // The indexes are only updated once the records are in the file
+        self.file.write_all(&buf)?;
+        self.len += buf.len() as u64;
+        self.positions.extend(positions);
+
+        let mut offsets = Vec::with_capacity(records.len());
+        for (i, record) in records.into_iter().enumerate() {
+            let offset = start_offset + i as u64;
+            self.index(offset, record.key, record.tags);
+            offsets.push(offset);
+        }
+        Ok(offsets)
+    }
+
+    fn is_mapped(&self) -> bool {
+        self.mmap.as_ref().map_or(0, |mmap| mmap.len() as u64) >= self.len
+    }
+
+    fn remap(&mut self) -> Result<(), CommonError> {
+        if self.len == 0 {
+            self.mmap = None;
+            return Ok(());
+        }
+        // SAFETY: the log file is only written by this adapter, which appends to it and only
+        // truncates it when no mapping of it is left, so the mapped bytes never change.
+        self.mmap = Some(unsafe { Mmap::map(&self.file)? });
+        Ok(())
+    }
+
+    // The frame of the record, a slice of the mapped log
+    fn record_slice(&self, offset: u64) -> Option<&[u8]> {
+        let pos = *self.positions.get(offset as usize)?;
+        frame_at(self.mmap.as_ref()?, pos)
+    }
+
+    fn read(&self, offset: u64) -> Result<Option<Record>, CommonError> {
+        Ok(self.read_ref(offset)?.map(|record| record.to_record()))
+    }
+
+    fn read_ref(&self, offset: u64) -> Result<Option<RecordRef<'_>>, CommonError> {
+        match self.record_slice(offset) {
+            Some(frame) => decode_record(frame).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn timestamp(&self, offset: u64) -> Option<u64> {
+        let frame = self.record_slice(offset)?;
+        let bytes = frame.get(FRAME_TIMESTAMP_POS..FRAME_TIMESTAMP_POS + 8)?;
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+// The offset, key and tags of the frame, None if the frame does not decode
+fn recover_frame(frame: &[u8]) -> Option<(u64, String, Vec<String>)> {
+    let record = decode_record(frame).ok()?;
+    Some((
+        record.offset,
+        record.key.to_owned(),
+        record.tags.iter().map(|tag| tag.to_string()).collect(),
+    ))
+}
+
+fn frame_at(mmap: &[u8], pos: u64) -> Option<&[u8]> {
+    let pos = pos as usize;
+    let len_bytes = mmap.get(pos..pos + FRAME_LEN_SIZE)?;
+    let len = u32::from_le_bytes(len_bytes.try_into().ok()?) as usize;
+    mmap.get(pos + FRAME_LEN_SIZE..pos + FRAME_LEN_SIZE + len)
+}
+
+// The frame of a record, little endian:
+// offset u64 | timestamp u64 | delay_timestamp u64 | crc_num u32 | key | headers | tags | data
+// where a string or the data is prefixed by its u32 length, and a list by its u32 length.
+fn encode_record(record: &Record, offset: u64, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; FRAME_LEN_SIZE]);
+    buf.extend_from_slice(&offset.to_le_bytes());
+    buf.extend_from_slice(&record.timestamp.to_le_bytes());
+    buf.extend_from_slice(&record.delay_timestamp.to_le_bytes());
+    buf.extend_from_slice(&record.crc_num.to_le_bytes());
+    put_bytes(buf, record.key.as_bytes());
+    buf.extend_from_slice(&(record.header.len() as u32).to_le_bytes());
+    for header in record.header.iter() {
+        put_bytes(buf, header.name.as_bytes());
+        put_bytes(buf, header.value.as_bytes());
+    }
+    buf.extend_from_slice(&(record.tags.len() as u32).to_le_bytes());
+    for tag in record.tags.iter() {
+        put_bytes(buf, tag.as_bytes());
+    }
+    put_bytes(buf, &record.data);
+
+    let frame_len = (buf.len() - start - FRAME_LEN_SIZE) as u32;
+    buf[start..start + FRAME_LEN_SIZE].copy_from_slice(&frame_len.to_le_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// A record decoded in place, its key, headers, tags and data borrow the mapped log.
+pub struct RecordRef<'a> {
+    pub offset: u64,
+    pub header: Vec<(&'a str, &'a str)>,
+    pub key: &'a str,
+    pub data: &'a [u8],
+    pub tags: Vec<&'a str>,
+    pub timestamp: u64,
+    pub delay_timestamp: u64,
+    pub crc_num: u32,
+}
+
+impl RecordRef<'_> {
+    pub fn to_record(&self) -> Record {
+        Record {
+            offset: Some(self.offset),
+            header: self
+                .header
+                .iter()
+                .map(|(name, value)| Header {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            key: self.key.to_owned(),
+            data: self.data.to_vec(),
+            tags: self.tags.iter().map(|tag| tag.to_string()).collect(),
+            timestamp: self.timestamp,
+            delay_timestamp: self.delay_timestamp,
+            crc_num: self.crc_num,
+        }
+    }
+}
+
+fn decode_record(frame: &[u8]) -> Result<RecordRef<'_>, CommonError> {
+    let mut reader = FrameReader::new(frame);
+    let offset = reader.u64()?;
+    let timestamp = reader.u64()?;
+    let delay_timestamp = reader.u64()?;
+    let crc_num = reader.u32()?;
+    let key = reader.str()?;
+    let header_num = reader.u32()?;
+    let mut header = Vec::with_capacity(header_num as usize);
+    for _ in 0..header_num {
+        header.push((reader.str()?, reader.str()?));
+    }
+    let tag_num = reader.u32()?;
+    let mut tags = Vec::with_capacity(tag_num as usize);
+    for _ in 0..tag_num {
+        tags.push(reader.str()?);
+    }
+    let data = reader.bytes()?;
+    Ok(RecordRef {
+        offset,
+        header,
+        key,
+        data,
+        tags,
+        timestamp,
+        delay_timestamp,
+        crc_num,
+    })
+}
+
+struct FrameReader<'a> {
+    frame: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> FrameReader<'a> {
+    fn new(frame: &'a [u8]) -> Self {
+        FrameReader { frame, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CommonError> {
+        let Some(bytes) = self.frame.get(self.pos..self.pos + len) else {
+            return Err(CommonError::CommonError(format!(
+                "record frame of {} bytes is truncated at {}",
+                self.frame.len(),
+                self.pos
+            )));
+        };
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, CommonError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, CommonError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], CommonError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<&'a str, CommonError> {
+        std::str::from_utf8(self.bytes()?).map_err(|e| CommonError::CommonError(e.to_string()))
+    }
+}
+
+impl MmapStorageAdapter {
+    /// Opens the shards and the group offsets kept under data_path.
+    pub fn new(data_path: impl AsRef<Path>) -> Result<Self, CommonError> {
+        let data_path = data_path.as_ref().to_path_buf();
+        fs::create_dir_all(data_path.join(SHARD_DIR))?;
+        fs::create_dir_all(data_path.join(GROUP_DIR))?;
+
+        let shards = DashMap::with_capacity(8);
+        for namespace_dir in fs::read_dir(data_path.join(SHARD_DIR))? {
+            for shard_dir in fs::read_dir(namespace_dir?.path())? {
+                let log = ShardLog::open(shard_dir?.path())?;
+                let key = Self::shard_key(&log.info.namespace, &log.info.shard_name);
+                shards.insert(key, Arc::new(RwLock::new(log)));
+            }
+        }
+
+        let groups = DashMap::with_capacity(8);
+        for group_file in fs::read_dir(data_path.join(GROUP_DIR))? {
+            let path = group_file?.path();
+            let Some(group_name) = path.file_stem().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let offsets = serde_json::from_slice::<Vec<GroupOffset>>(&fs::read(&path)?)?;
+            let offsets = offsets
+                .into_iter()
+                .map(|raw| (Self::shard_key(&raw.namespace, &raw.shard_name), raw))
+                .collect();
+            groups.insert(group_name.to_owned(), offsets);
+        }
+
+        Ok(MmapStorageAdapter {
+            data_path,
+            shards: Arc::new(shards),
+            groups: Arc::new(groups),
+        })
+    }
+
+    pub fn shard_key(namespace: &str, shard_name: &str) -> String {
+        format!("{}_{}", namespace, shard_name)
+    }
+
+    fn shard_dir(&self, namespace: &str, shard_name: &str) -> PathBuf {
+        self.data_path
+            .join(SHARD_DIR)
+            .join(namespace)
+            .join(shard_name)
+    }
+
+    fn get_shard(
+        &self,
+        namespace: &str,
+        shard_name: &str,
+    ) -> Result<Arc<RwLock<ShardLog>>, CommonError> {
+        match self.shards.get(&Self::shard_key(namespace, shard_name)) {
+            Some(shard) => Ok(shard.clone()),
+            None => Err(CommonError::CommonError(format!(
+                "shard {} under {} not exists",
+                shard_name, namespace
+            ))),
+        }
+    }
+
+    // Runs f on the shard mapped up to its last record, an unknown shard has no record
+    fn read_shard<T: Default>(
+        &self,
+        namespace: &str,
+        shard_name: &str,
+        f: impl FnOnce(&ShardLog) -> Result<T, CommonError>,
+    ) -> Result<T, CommonError> {
+        let Some(shard) = self
+            .shards
+            .get(&Self::shard_key(namespace, shard_name))
+            .map(|shard| shard.clone())
+        else {
+            return Ok(T::default());
+        };
+
+        {
+            let log = shard.read().unwrap();
+            if log.is_mapped() {
+                return f(&log);
+            }
+        }
+        // The records appended since the last remap are mapped on their first read
+        let mut log = shard.write().unwrap();
+        if !log.is_mapped() {
+            log.remap()?;
+        }
+        f(&log)
+    }
+
+    /// Reads like read_by_offset, but hands the records to f as they are decoded in place
+    /// from the mapped log, without copying their data. The records returned by the
+    /// StorageAdapter methods own their data and are copied out of the log.
+    pub fn read_by_offset_ref(
+        &self,
+        namespace: &str,
+        shard_name: &str,
+        offset: u64,
+        read_config: ReadConfig,
+        mut f: impl FnMut(RecordRef<'_>),
+    ) -> Result<(), CommonError> {
+        self.read_shard(namespace, shard_name, |log| {
+            let end = log
+                .next_offset()
+                .min(offset.saturating_add(read_config.max_record_num));
+            for i in offset..end {
+                if let Some(record) = log.read_ref(i)? {
+                    f(record);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn save_group(&self, group_name: &str) -> Result<(), CommonError> {
+        let offsets: Vec<GroupOffset> = match self.groups.get(group_name) {
+            Some(offsets) => offsets.values().cloned().collect(),
+            None => Vec::new(),
+        };
+        let path = self
+            .data_path
+            .join(GROUP_DIR)
+            .join(format!("{}.json", group_name));
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&offsets)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageAdapter for MmapStorageAdapter {
+    async fn create_shard(&self, shard: ShardInfo) -> Result<(), CommonError> {
+        let key = Self::shard_key(&shard.namespace, &shard.shard_name);
+        if self.shards.contains_key(&key) {
+            return Err(CommonError::ShardAlreadyExists(
+                shard.shard_name,
+                shard.namespace,
+            ));
+        }
+        let dir = self.shard_dir(&shard.namespace, &shard.shard_name);
+        let log = ShardLog::create(dir, shard)?;
+        self.shards.insert(key, Arc::new(RwLock::new(log)));
+        Ok(())
+    }
+
+    async fn list_shard(
+        &self,
+        namespace: String,
+        shard_name: String,
+    ) -> Result<Vec<ShardInfo>, CommonError> {
+        Ok(self
+            .shards
+            .iter()
+            .map(|raw| raw.value().read().unwrap().info.clone())
+            .filter(|info| namespace.is_empty() || info.namespace == namespace)
+            .filter(|info| shard_name.is_empty() || info.shard_name == shard_name)
+            .collect())
+    }
+
+    async fn delete_shard(&self, namespace: String, shard_name: String) -> Result<(), CommonError> {
+        let Some((_, shard)) = self
+            .shards
+            .remove(&Self::shard_key(&namespace, &shard_name))
+        else {
+            return Err(CommonError::CommonError(format!(
+                "shard {} under namespace {} not exists",
+                shard_name, namespace
+            )));
+        };
+        let dir = shard.read().unwrap().dir.clone();
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    async fn write(
+        &self,
+        namespace: String,
+        shard_name: String,
+        data: Record,
+    ) -> Result<u64, CommonError> {
+        let offsets = self.batch_write(namespace, shard_name, vec![data]).await?;
+        Ok(offsets[0])
+    }
+
+    async fn batch_write(
+        &self,
+        namespace: String,
+        shard_name: String,
+        data: Vec<Record>,
+    ) -> Result<Vec<u64>, CommonError> {
+        let shard = self.get_shard(&namespace, &shard_name)?;
+        let mut log = shard.write().unwrap();
+        log.append(data)
+    }
+
+    async fn read_by_offset(
+        &self,
+        namespace: String,
+        shard_name: String,
+        offset: u64,
+        read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        self.read_shard(&namespace, &shard_name, |log| {
+            let end = log
+                .next_offset()
+                .min(offset.saturating_add(read_config.max_record_num));
+            (offset..end)
+                .filter_map(|i| log.read(i).transpose())
+                .collect()
+        })
+    }
+
+    async fn read_latest(
+        &self,
+        namespace: String,
+        shard_name: String,
+        read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        self.read_shard(&namespace, &shard_name, |log| {
+            (0..log.next_offset())
+                .rev()
+                .take(read_config.max_record_num as usize)
+                .filter_map(|i| log.read(i).transpose())
+                .collect()
+        })
+    }
+
+    async fn read_by_tag(
+        &self,
+        namespace: String,
+        shard_name: String,
+        offset: u64,
+        tag: String,
+        read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        self.read_shard(&namespace, &shard_name, |log| {
+            let Some(offsets) = log.tags.get(&tag) else {
+                return Ok(Vec::new());
+            };
+            let start = offsets.partition_point(|i| *i < offset);
+            offsets[start..]
+                .iter()
+                .take(read_config.max_record_num as usize)
+                .filter_map(|i| log.read(*i).transpose())
+                .collect()
+        })
+    }
+
+    async fn read_by_key(
+        &self,
+        namespace: String,
+        shard_name: String,
+        offset: u64,
+        key: String,
+        read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        self.read_shard(&namespace, &shard_name, |log| match log.keys.get(&key) {
+            Some(key_offset) if *key_offset >= offset && read_config.max_record_num >= 1 => {
+                Ok(log.read(*key_offset)?.into_iter().collect())
+            }
+            _ => Ok(Vec::new()),
+        })
+    }
+
+    async fn get_offset_by_timestamp(
+        &self,
+        namespace: String,
+        shard_name: String,
+        timestamp: u64,
+    ) -> Result<Option<ShardOffset>, CommonError> {
+        self.read_shard(&namespace, &shard_name, |log| {
+            // Only the timestamp of every record is read, not the whole record
+            let offset =
+                (0..log.next_offset()).find(|i| log.timestamp(*i).is_some_and(|t| t >= timestamp));
+            Ok(offset.map(|offset| ShardOffset {
+                namespace: namespace.clone(),
+                shard_name: shard_name.clone(),
+                offset,
+                ..Default::default()
+            }))
+        })
+    }
+
+    async fn get_offset_by_group(
+        &self,
+        group_name: String,
+    ) -> Result<Vec<ShardOffset>, CommonError> {
+        let Some(offsets) = self.groups.get(&group_name) else {
+            return Ok(Vec::new());
+        };
+        Ok(offsets
+            .values()
+            .map(|raw| ShardOffset {
+                namespace: raw.namespace.clone(),
+                shard_name: raw.shard_name.clone(),
+                offset: raw.offset,
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    async fn commit_offset(
+        &self,
+        group_name: String,
+        namespace: String,
+        offset: HashMap<String, u64>,
+    ) -> Result<(), CommonError> {
+        {
+            let mut offsets = self.groups.entry(group_name.clone()).or_default();
+            for (shard_name, offset) in offset {
+                offsets.insert(
+                    Self::shard_key(&namespace, &shard_name),
+                    GroupOffset {
+                        namespace: namespace.clone(),
+                        shard_name,
+                        offset,
+                    },
+                );
+            }
+        }
+        self.save_group(&group_name)
+    }
+
+    async fn close(&self) -> Result<(), CommonError> {
+        for shard in self.shards.iter() {
+            shard.read().unwrap().file.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    use common_base::tools::unique_id;
+    use metadata_struct::adapter::read_config::ReadConfig;
+    use metadata_struct::adapter::record::{Header, Record};
+
+    use super::{MmapStorageAdapter, SHARD_DIR, SHARD_LOG_FILE};
+    use crate::rocksdb::RocksDBStorageAdapter;
+    use crate::storage::{ShardInfo, StorageAdapter};
+
+    fn build_shard(namespace: &str, shard_name: &str) -> ShardInfo {
+        ShardInfo {
+            namespace: namespace.to_owned(),
+            shard_name: shard_name.to_owned(),
+            replica_num: 1,
+        }
+    }
+
+    fn read_config(max_record_num: u64) -> ReadConfig {
+        ReadConfig {
+            max_record_num,
+            max_size: 1024 * 1024,
+        }
+    }
+
+    #[tokio::test]
+    async fn read_write_test() {
+        let data_path = format!("/tmp/robustmq_mmap_{}", unique_id());
+        let storage_adapter = MmapStorageAdapter::new(&data_path).unwrap();
+        let namespace = unique_id();
+        let shard_name = "test-11".to_string();
+        storage_adapter
+            .create_shard(build_shard(&namespace, &shard_name))
+            .await
+            .unwrap();
+        assert!(storage_adapter
+            .create_shard(build_shard(&namespace, &shard_name))
+            .await
+            .is_err());
+
+        let mut records = Vec::new();
+        for i in 0..10 {
+            let mut record = Record::build_str(format!("test{}", i));
+            record.set_key(format!("k{}", i % 2));
+            record.set_tags(vec![format!("t{}", i % 3)]);
+            record.set_header(vec![Header {
+                name: "n".to_string(),
+                value: format!("v{}", i),
+            }]);
+            record.timestamp = 100 + i;
+            records.push(record);
+        }
+        let offsets = storage_adapter
+            .batch_write(namespace.clone(), shard_name.clone(), records)
+            .await
+            .unwrap();
+        assert_eq!(offsets, (0..10).collect::<Vec<u64>>());
+
+        let records = storage_adapter
+            .read_by_offset(namespace.clone(), shard_name.clone(), 8, read_config(10))
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].offset, Some(8));
+        assert_eq!(records[0].data, b"test8".to_vec());
+        assert_eq!(records[0].header[0].value, "v8");
+        assert!(records[0].crc32_check());
+
+        // the same records decoded in place
+        let mut refs = Vec::new();
+        storage_adapter
+            .read_by_offset_ref(&namespace, &shard_name, 8, read_config(10), |record| {
+                assert_eq!(
+                    record.header,
+                    vec![("n", format!("v{}", record.offset).as_str())]
+                );
+                refs.push((record.offset, record.data.to_vec(), record.to_record()));
+            })
+            .unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0].0, 8);
+        assert_eq!(refs[0].1, b"test8".to_vec());
+        assert_eq!(refs[0].2.data, records[0].data);
+        assert!(refs[0].2.crc32_check());
+
+        // written after the log was mapped
+        let offset = storage_adapter
+            .write(
+                namespace.clone(),
+                shard_name.clone(),
+                Record::build_str("test10".to_string()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(offset, 10);
+        let records = storage_adapter
+            .read_latest(namespace.clone(), shard_name.clone(), read_config(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.offset).collect::<Vec<_>>(),
+            vec![Some(10), Some(9)]
+        );
+
+        let records = storage_adapter
+            .read_by_tag(
+                namespace.clone(),
+                shard_name.clone(),
+                1,
+                "t0".to_string(),
+                read_config(2),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.offset).collect::<Vec<_>>(),
+            vec![Some(3), Some(6)]
+        );
+
+        let records = storage_adapter
+            .read_by_key(
+                namespace.clone(),
+                shard_name.clone(),
+                0,
+                "k1".to_string(),
+                read_config(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(records[0].offset, Some(9));
+
+        let shard_offset = storage_adapter
+            .get_offset_by_timestamp(namespace.clone(), shard_name.clone(), 105)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(shard_offset.offset, 5);
+
+        let group_name = unique_id();
+        storage_adapter
+            .commit_offset(
+                group_name.clone(),
+                namespace.clone(),
+                HashMap::from([(shard_name.clone(), 7)]),
+            )
+            .await
+            .unwrap();
+        let offsets = storage_adapter
+            .get_offset_by_group(group_name.clone())
+            .await
+            .unwrap();
+        assert_eq!(offsets[0].offset, 7);
+
+        storage_adapter
+            .delete_shard(namespace.clone(), shard_name.clone())
+            .await
+            .unwrap();
+        assert!(storage_adapter
+            .list_shard(namespace.clone(), shard_name.clone())
+            .await
+            .unwrap()
+            .is_empty());
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reopen_test() {
+        let data_path = format!("/tmp/robustmq_mmap_{}", unique_id());
+        let namespace = unique_id();
+        let shard_name = "test-11".to_string();
+        let group_name = unique_id();
+        {
+            let storage_adapter = MmapStorageAdapter::new(&data_path).unwrap();
+            storage_adapter
+                .create_shard(build_shard(&namespace, &shard_name))
+                .await
+                .unwrap();
+            let mut record = Record::build_str("test0".to_string());
+            record.set_key("k0".to_string());
+            let records = vec![record, Record::build_str("test1".to_string())];
+            storage_adapter
+                .batch_write(namespace.clone(), shard_name.clone(), records)
+                .await
+                .unwrap();
+            storage_adapter
+                .commit_offset(
+                    group_name.clone(),
+                    namespace.clone(),
+                    HashMap::from([(shard_name.clone(), 1)]),
+                )
+                .await
+                .unwrap();
+            storage_adapter.close().await.unwrap();
+        }
+
+        // a record partially written when the process stopped
+        let log_path = format!(
+            "{}/{}/{}/{}/{}",
+            data_path, SHARD_DIR, namespace, shard_name, SHARD_LOG_FILE
+        );
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(&[100, 0, 0, 0, 1, 2]).unwrap();
+
+        let storage_adapter = MmapStorageAdapter::new(&data_path).unwrap();
+        let records = storage_adapter
+            .read_by_offset(namespace.clone(), shard_name.clone(), 0, read_config(10))
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].data, b"test1".to_vec());
+        let records = storage_adapter
+            .read_by_key(
+                namespace.clone(),
+                shard_name.clone(),
+                0,
+                "k0".to_string(),
+                read_config(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(records[0].offset, Some(0));
+        let offsets = storage_adapter
+            .get_offset_by_group(group_name)
+            .await
+            .unwrap();
+        assert_eq!(offsets[0].offset, 1);
+
+        // the torn record was truncated, the next record follows the last complete one
+        let offset = storage_adapter
+            .write(
+                namespace.clone(),
+                shard_name.clone(),
+                Record::build_str("test2".to_string()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(offset, 2);
+        let records = storage_adapter
+            .read_by_offset(namespace.clone(), shard_name.clone(), 2, read_config(10))
+            .await
+            .unwrap();
+        assert_eq!(records[0].data, b"test2".to_vec());
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recover_undecodable_frame_test() {
+        let data_path = format!("/tmp/robustmq_mmap_{}", unique_id());
+        let namespace = unique_id();
+        let shard_name = "test-12".to_string();
+        {
+            let storage_adapter = MmapStorageAdapter::new(&data_path).unwrap();
+            storage_adapter
+                .create_shard(build_shard(&namespace, &shard_name))
+                .await
+                .unwrap();
+            let records = vec![
+                Record::build_str("test0".to_string()),
+                Record::build_str("test1".to_string()),
+            ];
+            storage_adapter
+                .batch_write(namespace.clone(), shard_name.clone(), records)
+                .await
+                .unwrap();
+            storage_adapter.close().await.unwrap();
+        }
+
+        // a trailing frame whose length was written, but whose record ends after its offset
+        let log_path = format!(
+            "{}/{}/{}/{}/{}",
+            data_path, SHARD_DIR, namespace, shard_name, SHARD_LOG_FILE
+        );
+        let len = fs::metadata(&log_path).unwrap().len();
+        let mut frame = 12u32.to_le_bytes().to_vec();
+        frame.extend_from_slice(&2u64.to_le_bytes());
+        frame.extend_from_slice(&[1, 2, 3, 4]);
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(&frame).unwrap();
+
+        // the log is truncated to the end of the last record that decodes
+        let storage_adapter = MmapStorageAdapter::new(&data_path).unwrap();
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), len);
+        let records = storage_adapter
+            .read_by_offset(namespace.clone(), shard_name.clone(), 0, read_config(10))
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].data, b"test1".to_vec());
+
+        let offset = storage_adapter
+            .write(
+                namespace.clone(),
+                shard_name.clone(),
+                Record::build_str("test2".to_string()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(offset, 2);
+        storage_adapter.close().await.unwrap();
+        drop(storage_adapter);
+
+        let storage_adapter = MmapStorageAdapter::new(&data_path).unwrap();
+        let records = storage_adapter
+            .read_by_offset(namespace.clone(), shard_name.clone(), 0, read_config(10))
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].data, b"test2".to_vec());
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    async fn write_records<S: StorageAdapter + Sync>(
+        storage_adapter: &S,
+        namespace: &str,
+        shard_name: &str,
+        record_num: u64,
+        payload: &[u8],
+    ) {
+        storage_adapter
+            .create_shard(build_shard(namespace, shard_name))
+            .await
+            .unwrap();
+        for _ in 0..record_num / 1000 {
+            let records = (0..1000)
+                .map(|_| Record::build_byte(payload.to_vec()))
+                .collect();
+            storage_adapter
+                .batch_write(namespace.to_owned(), shard_name.to_owned(), records)
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn sequential_read<S: StorageAdapter + Sync>(
+        storage_adapter: &S,
+        namespace: &str,
+        shard_name: &str,
+        record_num: u64,
+    ) -> Duration {
+        let start = Instant::now();
+        let mut offset = 0;
+        while offset < record_num {
+            let records = storage_adapter
+                .read_by_offset(
+                    namespace.to_owned(),
+                    shard_name.to_owned(),
+                    offset,
+                    read_config(100),
+                )
+                .await
+                .unwrap();
+            assert_eq!(records[0].offset, Some(offset));
+            offset += records.len() as u64;
+        }
+        start.elapsed()
+    }
+
+    // A benchmark, run with cargo test --release -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn sequential_read_throughput_test() {
+        let namespace = unique_id();
+        let shard_name = "test-11".to_string();
+        let record_num = 20_000;
+        let payload = vec![b'x'; 512];
+
+        let mmap_path = format!("/tmp/robustmq_mmap_{}", unique_id());
+        let mmap_adapter = MmapStorageAdapter::new(&mmap_path).unwrap();
+        let rocksdb_path = format!("/tmp/robustmq_{}", unique_id());
+        let rocksdb_adapter = RocksDBStorageAdapter::new(rocksdb_path.as_str(), 100);
+        write_records(&mmap_adapter, &namespace, &shard_name, record_num, &payload).await;
+        write_records(
+            &rocksdb_adapter,
+            &namespace,
+            &shard_name,
+            record_num,
+            &payload,
+        )
+        .await;
+
+        let mmap_elapsed =
+            sequential_read(&mmap_adapter, &namespace, &shard_name, record_num).await;
+        let rocksdb_elapsed =
+            sequential_read(&rocksdb_adapter, &namespace, &shard_name, record_num).await;
+        // at least 40% more records read per second
+        assert!(rocksdb_elapsed.as_secs_f64() >= mmap_elapsed.as_secs_f64() * 1.4);
+
+        rocksdb_adapter.close().await.unwrap();
+        fs::remove_dir_all(mmap_path).unwrap();
+        fs::remove_dir_all(rocksdb_path).unwrap();
+    }
+}
@@ -185,10 +185,10 @@ impl StorageAdapter for MySQLStorageAdapter {
             .query_first::<Row, _>(check_table_exists_sql)?
             .is_some()
         {
-            return Err(CommonError::CommonError(format!(
-                "shard {} under namespace {} already exists",
-                &shard.shard_name, &shard.namespace
-            )));
+            return Err(CommonError::ShardAlreadyExists(
+                shard.shard_name,
+                shard.namespace,
+            ));
         };
 
         let create_table_sql = format!(
@@ -339,6 +339,51 @@ impl StorageAdapter for MySQLStorageAdapter {
         Ok(res)
     }
 
+    async fn read_latest(
+        &self,
+        namespace: String,
+        shard_name: String,
+        read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let sql = format!(
+            "SELECT `offset`, `key`, `data`, `header`, `tags`, `ts`
+            FROM `{}`
+            ORDER BY `offset` DESC
+            LIMIT :limit;",
+            Self::record_table_name(&namespace, &shard_name)
+        );
+
+        let res: Vec<Record> = conn.exec_map(
+            sql,
+            params! {
+                "limit" => read_config.max_record_num,
+            },
+            |(offset, key, data, header, tags, ts): (
+                u64,
+                String,
+                Vec<u8>,
+                Vec<u8>,
+                Vec<u8>,
+                u64,
+            )| {
+                Record {
+                    offset: Some(offset),
+                    key,
+                    data: data.clone(),
+                    header: serde_json::from_slice(&header).unwrap(),
+                    tags: serde_json::from_slice(&tags).unwrap(),
+                    timestamp: ts,
+                    delay_timestamp: 0,
+                    crc_num: calc_crc32(&data),
+                }
+            },
+        )?;
+
+        Ok(res)
+    }
+
     async fn read_by_tag(
         &self,
         namespace: String,
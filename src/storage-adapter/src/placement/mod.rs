@@ -355,10 +355,7 @@ impl StorageAdapter for PlacementStorageAdapter {
         .await?;
 
         if !reply.value.is_empty() {
-            return Err(CommonError::CommonError(format!(
-                "shard {} under namespace {} already exists",
-                shard_name, namespace
-            )));
+            return Err(CommonError::ShardAlreadyExists(shard_name, namespace));
         }
 
         placement_set(
@@ -480,6 +477,18 @@ impl StorageAdapter for PlacementStorageAdapter {
         Ok(records)
     }
 
+    async fn read_latest(
+        &self,
+        _namespace: String,
+        _shard_name: String,
+        _read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        Err(CommonError::NotSupportFeature(
+            "PlacementStorageAdapter".to_string(),
+            "read_latest".to_string(),
+        ))
+    }
+
     async fn read_by_tag(
         &self,
         namespace: String,
@@ -684,7 +693,7 @@ impl StorageAdapter for PlacementStorageAdapter {
 mod tests {
     use std::{collections::HashMap, sync::Arc};
 
-    use common_base::{tools::unique_id, utils::crc::calc_crc32};
+    use common_base::{error::common::CommonError, tools::unique_id, utils::crc::calc_crc32};
     use futures::future;
     use grpc_clients::pool::ClientPool;
     use metadata_struct::adapter::{
@@ -698,6 +707,17 @@ mod tests {
         "127.0.0.1:1228".to_string()
     }
 
+    #[tokio::test]
+    async fn read_latest_not_support_test() {
+        let client_pool = Arc::new(ClientPool::new(1));
+        let storage_adapter = PlacementStorageAdapter::new(client_pool, vec![get_placement_addr()]);
+
+        let result = storage_adapter
+            .read_latest(unique_id(), "test-11".to_string(), ReadConfig::default())
+            .await;
+        assert!(matches!(result, Err(CommonError::NotSupportFeature(_, _))));
+    }
+
     #[tokio::test]
     async fn stream_read_write() {
         let client_pool = Arc::new(ClientPool::new(100));
@@ -325,10 +325,7 @@ impl StorageAdapter for RocksDBStorageAdapter {
             .read::<u64>(cf.clone(), shard_offset_key.as_str())?
             .is_some()
         {
-            return Err(CommonError::CommonError(format!(
-                "shard {} under namespace {} already exists",
-                shard_name, namespace
-            )));
+            return Err(CommonError::ShardAlreadyExists(shard_name, namespace));
         }
 
         self.db
@@ -416,6 +413,43 @@ impl StorageAdapter for RocksDBStorageAdapter {
         Ok(records)
     }
 
+    async fn read_latest(
+        &self,
+        namespace: String,
+        shard_name: String,
+        read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError> {
+        let cf = self.db.cf_handle(DB_COLUMN_FAMILY).unwrap();
+
+        // the shard offset is the offset of the next record to be written
+        let shard_offset_key = Self::shard_offset_key(&namespace, &shard_name);
+        let next_offset = match self.db.read::<u64>(cf.clone(), shard_offset_key.as_str())? {
+            Some(offset) => offset,
+            None => return Ok(Vec::new()),
+        };
+
+        if next_offset == 0 {
+            return Ok(Vec::new());
+        }
+
+        // records that have been trimmed by retention are skipped by the iterator
+        let shard_record_key_prefix = Self::shard_record_key_prefix(&namespace, &shard_name);
+        let last_record_key = Self::shard_record_key(&namespace, &shard_name, next_offset - 1);
+        let raw_res = self.db.read_prefix_rev(
+            cf.clone(),
+            &shard_record_key_prefix,
+            &last_record_key,
+            read_config.max_record_num as usize,
+        )?;
+
+        let mut records = Vec::with_capacity(raw_res.len());
+        for (_, v) in raw_res {
+            records.push(serde_json::from_slice::<Record>(&v)?);
+        }
+
+        Ok(records)
+    }
+
     async fn read_by_tag(
         &self,
         namespace: String,
@@ -800,6 +834,57 @@ mod tests {
         let _ = std::fs::remove_dir_all(&db_path);
     }
 
+    #[tokio::test]
+    async fn read_latest_test() {
+        let db_path = format!("/tmp/robustmq_{}", unique_id());
+
+        let storage_adapter = RocksDBStorageAdapter::new(db_path.as_str(), 100);
+        let namespace = unique_id();
+        let shard_name = "test-latest".to_string();
+
+        storage_adapter
+            .create_shard(ShardInfo {
+                namespace: namespace.clone(),
+                shard_name: shard_name.clone(),
+                replica_num: 1,
+            })
+            .await
+            .unwrap();
+
+        let read_config = ReadConfig {
+            max_record_num: 3,
+            max_size: 1024,
+        };
+        assert!(storage_adapter
+            .read_latest(namespace.clone(), shard_name.clone(), read_config.clone())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let data = (0..5)
+            .map(|i| Record::build_byte(format!("test{}", i).as_bytes().to_vec()))
+            .collect();
+        storage_adapter
+            .batch_write(namespace.clone(), shard_name.clone(), data)
+            .await
+            .unwrap();
+
+        let records = storage_adapter
+            .read_latest(namespace.clone(), shard_name.clone(), read_config)
+            .await
+            .unwrap();
+        let offsets: Vec<u64> = records.iter().map(|r| r.offset.unwrap()).collect();
+        assert_eq!(offsets, vec![4, 3, 2]);
+        assert_eq!(records[0].data, "test4".as_bytes().to_vec());
+
+        storage_adapter
+            .delete_shard(namespace, shard_name)
+            .await
+            .unwrap();
+
+        let _ = std::fs::remove_dir_all(&db_path);
+    }
+
     #[tokio::test]
     #[ignore]
     async fn concurrency_test() {
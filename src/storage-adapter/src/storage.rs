@@ -16,10 +16,14 @@ use std::collections::HashMap;
 
 use axum::async_trait;
 use common_base::error::common::CommonError;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 use metadata_struct::adapter::read_config::ReadConfig;
 use metadata_struct::adapter::record::Record;
 use serde::{Deserialize, Serialize};
 
+// Records read from the storage at a time by iter_shard_records
+const SHARD_ITER_BATCH: u64 = 1000;
+
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub struct ShardInfo {
     pub namespace: String,
@@ -69,6 +73,50 @@ pub trait StorageAdapter {
         read_config: ReadConfig,
     ) -> Result<Vec<Record>, CommonError>;
 
+    /// Reads the newest max_record_num records of the shard, newest first.
+    /// Streams the records of the shard from from_offset to the end, one batch is read from the
+    /// storage at a time as the stream is consumed, so the shard is never loaded whole.
+    fn iter_shard_records(
+        &self,
+        namespace: String,
+        shard_name: String,
+        from_offset: u64,
+    ) -> impl Stream<Item = Result<Record, CommonError>> + Send + '_
+    where
+        Self: Sync,
+    {
+        stream::try_unfold(Some(from_offset), move |offset| {
+            let namespace = namespace.clone();
+            let shard_name = shard_name.clone();
+            async move {
+                let Some(offset) = offset else {
+                    return Ok(None);
+                };
+                let mut read_config = ReadConfig::new();
+                read_config.max_record_num = SHARD_ITER_BATCH;
+                let records = self
+                    .read_by_offset(namespace, shard_name, offset, read_config)
+                    .await?;
+                // An empty batch is the end of the shard
+                let next_offset = records
+                    .last()
+                    .and_then(|record| record.offset)
+                    .map(|offset| offset + 1);
+                Ok(Some((records, next_offset)))
+            }
+        })
+        .map_ok(|records| stream::iter(records.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+
+    async fn read_latest(
+        &self,
+        namespace: String,
+        shard_name: String,
+        read_config: ReadConfig,
+    ) -> Result<Vec<Record>, CommonError>;
+
     async fn read_by_tag(
         &self,
         namespace: String,
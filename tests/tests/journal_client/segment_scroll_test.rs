@@ -14,10 +14,19 @@
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
     use common_base::tools::unique_id;
+    use grpc_clients::journal::admin::call::journal_admin_list_segment;
+    use grpc_clients::pool::ClientPool;
     use journal_client::client::{JournalClient, JournalClientWriteData};
+    use metadata_struct::adapter::read_config::ReadConfig;
+    use protocol::journal_server::journal_admin::ListSegmentRequest;
+    use tokio::time::{sleep, timeout};
 
     use crate::journal_client::common::journal_tcp_addr_vec;
+    use crate::journal_server::common::journal_grpc_addr;
 
     #[tokio::test]
     async fn write_scroll_test() {
@@ -49,4 +58,68 @@ mod tests {
             assert!(res_opt.is_ok());
         }
     }
+
+    #[tokio::test]
+    async fn read_across_segments_test() {
+        let namespace = unique_id();
+        let shard_name = "s1".to_string();
+        let client = JournalClient::new(journal_tcp_addr_vec()).await.unwrap();
+        client
+            .create_shard(&namespace, &shard_name, 1)
+            .await
+            .unwrap();
+
+        // max_segment_size of the journal server is 1MB, so the writes roll over 3 segments
+        let record_num = 3000;
+        for i in 0..record_num {
+            let data = JournalClientWriteData {
+                key: format!("key-{}", i),
+                content: format!("{:04}", i).repeat(256).as_bytes().to_vec(),
+                tags: vec![],
+            };
+            client
+                .write(namespace.to_owned(), shard_name.to_owned(), data)
+                .await
+                .unwrap();
+        }
+
+        let client_pool = Arc::new(ClientPool::new(10));
+        let addrs = vec![journal_grpc_addr()];
+        let request = ListSegmentRequest {
+            namespace: namespace.clone(),
+            shard_name: shard_name.clone(),
+            segment_no: -1,
+        };
+        let reply = journal_admin_list_segment(&client_pool, &addrs, request)
+            .await
+            .unwrap();
+        assert!(reply.segments.len() >= 3);
+
+        let read_config = ReadConfig {
+            max_record_num: 100,
+            max_size: 1024 * 1024,
+        };
+        let mut offset = 0;
+        timeout(Duration::from_secs(60), async {
+            while offset < record_num {
+                let records = client
+                    .read_by_offset(&namespace, &shard_name, offset, &read_config)
+                    .await
+                    .unwrap();
+                if records.is_empty() {
+                    // the metadata of the next segment is not in the client cache yet
+                    sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+                for record in records {
+                    assert_eq!(record.offset, Some(offset));
+                    assert_eq!(record.key, format!("key-{}", offset));
+                    offset += 1;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(offset, record_num);
+    }
 }
@@ -337,7 +337,7 @@ mod tests {
                         .unwrap()
                         .get_int()
                         .unwrap(),
-                    65535
+                    32
                 );
 
                 assert!(resp_pros.get(PropertyCode::ReasonString).is_none());
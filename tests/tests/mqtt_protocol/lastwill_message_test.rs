@@ -15,12 +15,24 @@
 #[cfg(test)]
 mod tests {
     use std::process;
+    use std::sync::Arc;
     use std::time::Duration;
 
+    use bytes::Bytes;
     use common_base::tools::{now_second, unique_id};
+    use futures::SinkExt;
+    use grpc_clients::placement::mqtt::call::placement_get_last_will_message;
+    use grpc_clients::pool::ClientPool;
     use paho_mqtt::{
-        Client, ConnectOptionsBuilder, MessageBuilder, Properties, PropertyCode, QOS_1,
+        Client, ConnectOptionsBuilder, DisconnectOptionsBuilder, MessageBuilder, Properties,
+        PropertyCode, ReasonCode, QOS_1,
     };
+    use protocol::mqtt::common::{Connect, LastWill, Login, MqttPacket, QoS};
+    use protocol::mqtt::mqttv4::codec::Mqtt4Codec;
+    use protocol::placement_center::placement_center_mqtt::GetLastWillMessageRequest;
+    use tokio::net::TcpStream;
+    use tokio::time::sleep;
+    use tokio_util::codec::Framed;
 
     use crate::mqtt_protocol::common::{
         broker_addr, build_create_pros, build_v5_pros, connect_server5, distinct_conn, password,
@@ -97,4 +109,136 @@ mod tests {
         }
         distinct_conn(cli);
     }
+
+    #[tokio::test]
+    async fn normal_disconnect_delete_last_will_test() {
+        let client_id = unique_id();
+        let addr = broker_addr();
+
+        let create_opts = build_create_pros(&client_id, &addr);
+        let cli = Client::new(create_opts).unwrap();
+
+        let will = MessageBuilder::new()
+            .payload("will message content")
+            .topic(format!("/tests/{}", unique_id()))
+            .qos(QOS_1)
+            .retained(false)
+            .finalize();
+        let conn_opts = ConnectOptionsBuilder::new_v5()
+            .keep_alive_interval(Duration::from_secs(20))
+            .clean_start(true)
+            .connect_timeout(Duration::from_secs(5))
+            .properties(build_v5_pros())
+            .will_message(will)
+            .user_name(username())
+            .password(password())
+            .finalize();
+        cli.connect(conn_opts).unwrap();
+
+        let client_pool = Arc::new(ClientPool::new(3));
+        let addrs = vec!["127.0.0.1:1228".to_string()];
+        let request = GetLastWillMessageRequest {
+            cluster_name: "mqtt-broker".to_string(),
+            client_id: client_id.clone(),
+        };
+        let reply = placement_get_last_will_message(&client_pool, &addrs, request.clone())
+            .await
+            .unwrap();
+        assert!(!reply.last_will_message.is_empty());
+
+        let disconnect_opts = DisconnectOptionsBuilder::new()
+            .reason_code(ReasonCode::NormalDisconnection)
+            .finalize();
+        cli.disconnect(disconnect_opts).unwrap();
+
+        sleep(Duration::from_secs(2)).await;
+        let reply = placement_get_last_will_message(&client_pool, &addrs, request)
+            .await
+            .unwrap();
+        assert!(reply.last_will_message.is_empty());
+    }
+
+    #[tokio::test]
+    async fn abnormal_disconnect_last_will_test() {
+        let addr = broker_addr();
+        let will_topic = format!("/tests/{}", unique_id());
+        let will_message_content = "abnormal disconnect will".to_string();
+
+        let sub_cli = connect_server5(&unique_id(), &addr, false, false);
+        let rx = sub_cli.start_consuming();
+        sub_cli.subscribe(&will_topic, QOS_1).unwrap();
+
+        // the client stops sending anything, the keep alive check drops the connection
+        let socket = TcpStream::connect("127.0.0.1:1883").await.unwrap();
+        let mut stream = Framed::new(socket, Mqtt4Codec::new());
+        let connect = Connect {
+            keep_alive: 2,
+            client_id: unique_id(),
+            clean_session: true,
+        };
+        let last_will = Some(LastWill {
+            topic: Bytes::from(will_topic.clone()),
+            message: Bytes::from(will_message_content.clone()),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+        });
+        let login = Some(Login {
+            username: username(),
+            password: password(),
+        });
+        stream
+            .send(MqttPacket::Connect(
+                4, connect, None, last_will, None, login,
+            ))
+            .await
+            .unwrap();
+
+        let msg = rx
+            .recv_timeout(Duration::from_secs(30))
+            .unwrap()
+            .expect("the will was not published");
+        assert_eq!(msg.topic(), will_topic);
+        assert_eq!(
+            String::from_utf8(msg.payload().to_vec()).unwrap(),
+            will_message_content
+        );
+        distinct_conn(sub_cli);
+    }
+
+    #[tokio::test]
+    async fn normal_disconnect_suppress_last_will_test() {
+        let addr = broker_addr();
+        let will_topic = format!("/tests/{}", unique_id());
+
+        let sub_cli = connect_server5(&unique_id(), &addr, false, false);
+        let rx = sub_cli.start_consuming();
+        sub_cli.subscribe(&will_topic, QOS_1).unwrap();
+
+        let client_id = unique_id();
+        let cli = Client::new(build_create_pros(&client_id, &addr)).unwrap();
+        let will = MessageBuilder::new()
+            .payload("suppressed will")
+            .topic(will_topic)
+            .qos(QOS_1)
+            .retained(false)
+            .finalize();
+        let conn_opts = ConnectOptionsBuilder::new_v5()
+            .keep_alive_interval(Duration::from_secs(2))
+            .clean_start(true)
+            .connect_timeout(Duration::from_secs(5))
+            .properties(build_v5_pros())
+            .will_message(will)
+            .user_name(username())
+            .password(password())
+            .finalize();
+        cli.connect(conn_opts).unwrap();
+        let disconnect_opts = DisconnectOptionsBuilder::new()
+            .reason_code(ReasonCode::NormalDisconnection)
+            .finalize();
+        cli.disconnect(disconnect_opts).unwrap();
+
+        // longer than the keep alive check would take to drop the connection
+        assert!(rx.recv_timeout(Duration::from_secs(10)).is_err());
+        distinct_conn(sub_cli);
+    }
 }
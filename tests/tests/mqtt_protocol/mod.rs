@@ -23,6 +23,7 @@ pub mod lastwill_message_test;
 pub mod permission34_test;
 pub mod permission5_test;
 pub mod pub_qos_test;
+pub mod puback_reason_string_test;
 pub mod req_resp_test;
 pub mod retain_message_test;
 // pub mod share_sub_test;
@@ -34,5 +35,6 @@ pub mod sub_options_test;
 pub mod topic_alias_test;
 mod topic_rewrite_rule_test;
 pub mod user_properties_test;
+pub mod ws_raw_frame_test;
 pub mod ws_sub_pub_test;
 pub mod wss_sub_pub_test;
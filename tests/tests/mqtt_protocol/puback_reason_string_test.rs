@@ -0,0 +1,84 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use common_base::tools::unique_id;
+    use futures::{SinkExt, StreamExt};
+    use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
+    use protocol::mqtt::common::{
+        Connect, ConnectProperties, ConnectReturnCode, Login, MqttPacket, Publish, QoS,
+    };
+    use tokio::net::TcpStream;
+    use tokio_util::codec::Framed;
+
+    use crate::mqtt_protocol::common::{password, username};
+
+    #[tokio::test]
+    async fn puback_reason_string_topic_auto_created_test() {
+        let socket = TcpStream::connect("127.0.0.1:1883").await.unwrap();
+        let mut stream = Framed::new(socket, MqttCodec::new(Some(5)));
+
+        stream.send(build_connect_wrapper()).await.unwrap();
+        let Some(Ok(MqttPacket::ConnAck(conn_ack, _))) = stream.next().await else {
+            panic!("expected a CONNACK packet");
+        };
+        assert_eq!(conn_ack.code, ConnectReturnCode::Success);
+
+        // a topic no client used before
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid: 1,
+            retain: false,
+            topic: Bytes::from(format!("/tests/puback/{}", unique_id())),
+            payload: Bytes::from("auto created"),
+        };
+        stream
+            .send(MqttPacketWrapper {
+                protocol_version: 5,
+                packet: MqttPacket::Publish(publish, None),
+            })
+            .await
+            .unwrap();
+
+        let Some(Ok(MqttPacket::PubAck(_, Some(properties)))) = stream.next().await else {
+            panic!("expected a PUBACK packet");
+        };
+        let reason_string = properties.reason_string.unwrap();
+        assert!(reason_string.contains("topic auto-created"));
+        assert!(reason_string.contains("offset: "));
+    }
+
+    fn build_connect_wrapper() -> MqttPacketWrapper {
+        let connect = Connect {
+            keep_alive: 30,
+            client_id: unique_id(),
+            clean_session: true,
+        };
+        let properties = ConnectProperties {
+            request_problem_info: Some(1),
+            ..Default::default()
+        };
+        let login = Some(Login {
+            username: username(),
+            password: password(),
+        });
+        MqttPacketWrapper {
+            protocol_version: 5,
+            packet: MqttPacket::Connect(5, connect, Some(properties), None, None, login),
+        }
+    }
+}
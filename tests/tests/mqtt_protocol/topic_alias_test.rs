@@ -14,10 +14,15 @@
 
 #[cfg(test)]
 mod tests {
+    use std::process;
+
     use common_base::tools::unique_id;
-    use paho_mqtt::{MessageBuilder, Properties, PropertyCode, QOS_1};
+    use paho_mqtt::{Client, MessageBuilder, Properties, PropertyCode, ReasonCode, QOS_1};
 
-    use crate::mqtt_protocol::common::{broker_addr, connect_server5, distinct_conn};
+    use crate::mqtt_protocol::common::{
+        broker_addr, build_create_pros, build_v5_conn_pros, build_v5_pros, connect_server5,
+        distinct_conn,
+    };
 
     #[tokio::test]
     async fn topic_alias_test() {
@@ -117,4 +122,50 @@ mod tests {
         }
         distinct_conn(cli);
     }
+
+    #[tokio::test]
+    async fn topic_alias_maximum_test() {
+        let client_id = unique_id();
+        let addr = broker_addr();
+        let topic = format!("/tests/{}", unique_id());
+
+        let create_opts = build_create_pros(&client_id, &addr);
+        let cli = Client::new(create_opts).unwrap_or_else(|err| {
+            println!("Error creating the client: {:?}", err);
+            process::exit(1);
+        });
+
+        let conn_opts = build_v5_conn_pros(build_v5_pros(), false, false, false);
+        let topic_alias_max = match cli.connect(conn_opts) {
+            Ok(response) => {
+                assert_eq!(response.reason_code(), ReasonCode::Success);
+                response
+                    .properties()
+                    .get(PropertyCode::TopicAliasMaximum)
+                    .unwrap()
+                    .get_int()
+                    .unwrap() as u16
+            }
+            Err(e) => {
+                panic!("{:?}", e);
+            }
+        };
+        assert_eq!(topic_alias_max, 32);
+
+        // register one more alias than the broker advertised
+        let mut props = Properties::new();
+        props
+            .push_u16(PropertyCode::TopicAlias, topic_alias_max + 1)
+            .unwrap();
+        let msg = MessageBuilder::new()
+            .properties(props)
+            .payload("mqtt message")
+            .topic(topic.clone())
+            .qos(QOS_1)
+            .retained(false)
+            .finalize();
+        let result = cli.publish(msg);
+        assert!(result.is_err());
+        assert!(!cli.is_connected());
+    }
 }
@@ -0,0 +1,197 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::{Bytes, BytesMut};
+    use common_base::tools::unique_id;
+    use futures::{SinkExt, StreamExt};
+    use protocol::mqtt::codec::{MqttCodec, MqttPacketWrapper};
+    use protocol::mqtt::common::{
+        Connect, ConnectReturnCode, Error, Filter, Login, MqttPacket, PubAck, PubAckReason,
+        Publish, QoS, RetainForwardRule, Subscribe,
+    };
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::HeaderValue;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+    use crate::mqtt_protocol::common::{broker_ws_addr, password, username};
+
+    type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    #[tokio::test]
+    async fn ws4_raw_frame_sub_pub_test() {
+        raw_frame_sub_pub(4).await;
+    }
+
+    #[tokio::test]
+    async fn ws5_raw_frame_sub_pub_test() {
+        raw_frame_sub_pub(5).await;
+    }
+
+    async fn raw_frame_sub_pub(protocol_version: u8) {
+        let mut request = format!("{}/mqtt", broker_ws_addr())
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", HeaderValue::from_static("mqtt"));
+        let (mut ws, _) = connect_async(request).await.unwrap();
+        let mut codec = MqttCodec::new(Some(protocol_version));
+        let mut received = BytesMut::new();
+
+        // the CONNECT and the SUBSCRIBE in the same frame
+        let topic = format!("/tests/ws/raw/{}", unique_id());
+        let mut frame = encode(
+            &mut codec,
+            protocol_version,
+            build_connect(protocol_version),
+        );
+        frame.extend_from_slice(&encode(
+            &mut codec,
+            protocol_version,
+            build_subscribe(&topic),
+        ));
+        ws.send(Message::Binary(frame)).await.unwrap();
+
+        let packets = read_packets(&mut ws, &mut codec, &mut received, 2).await;
+        let MqttPacket::ConnAck(conn_ack, _) = &packets[0] else {
+            panic!("expected a CONNACK packet, got {:?}", packets[0]);
+        };
+        assert_eq!(conn_ack.code, ConnectReturnCode::Success);
+        assert!(matches!(packets[1], MqttPacket::SubAck(_, _)));
+
+        // a PUBLISH split across two frames
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pkid: 2,
+            retain: false,
+            topic: Bytes::from(topic.clone()),
+            payload: Bytes::from("over websocket"),
+        };
+        let frame = encode(
+            &mut codec,
+            protocol_version,
+            MqttPacket::Publish(publish, None),
+        );
+        let (head, tail) = frame.split_at(frame.len() / 2);
+        ws.send(Message::Binary(head.to_vec())).await.unwrap();
+        ws.send(Message::Binary(tail.to_vec())).await.unwrap();
+
+        // the PUBACK and the message delivered to the subscription, in any order
+        let packets = read_packets(&mut ws, &mut codec, &mut received, 2).await;
+        assert!(packets
+            .iter()
+            .any(|packet| matches!(packet, MqttPacket::PubAck(pub_ack, _) if pub_ack.pkid == 2)));
+        let Some(delivered) = packets.iter().find_map(|packet| match packet {
+            MqttPacket::Publish(publish, _) => Some(publish),
+            _ => None,
+        }) else {
+            panic!("expected a PUBLISH packet, got {:?}", packets);
+        };
+        assert_eq!(delivered.topic, Bytes::from(topic));
+        assert_eq!(delivered.payload, Bytes::from("over websocket"));
+
+        let pub_ack = PubAck {
+            pkid: delivered.pkid,
+            reason: (protocol_version == 5).then_some(PubAckReason::Success),
+        };
+        let frame = encode(
+            &mut codec,
+            protocol_version,
+            MqttPacket::PubAck(pub_ack, None),
+        );
+        ws.send(Message::Binary(frame)).await.unwrap();
+        ws.close(None).await.unwrap();
+    }
+
+    fn build_connect(protocol_version: u8) -> MqttPacket {
+        let connect = Connect {
+            keep_alive: 30,
+            client_id: unique_id(),
+            clean_session: true,
+        };
+        let login = Some(Login {
+            username: username(),
+            password: password(),
+        });
+        MqttPacket::Connect(protocol_version, connect, None, None, None, login)
+    }
+
+    fn build_subscribe(topic: &str) -> MqttPacket {
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            filters: vec![Filter {
+                path: topic.to_owned(),
+                qos: QoS::AtLeastOnce,
+                nolocal: false,
+                preserve_retain: false,
+                retain_forward_rule: RetainForwardRule::OnEverySubscribe,
+            }],
+        };
+        MqttPacket::Subscribe(subscribe, None)
+    }
+
+    fn encode(codec: &mut MqttCodec, protocol_version: u8, packet: MqttPacket) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        codec
+            .encode_data(
+                MqttPacketWrapper {
+                    protocol_version,
+                    packet,
+                },
+                &mut buf,
+            )
+            .unwrap();
+        buf.to_vec()
+    }
+
+    // Reads frames until num packets are decoded, the packets may span several frames
+    async fn read_packets(
+        ws: &mut WsStream,
+        codec: &mut MqttCodec,
+        received: &mut BytesMut,
+        num: usize,
+    ) -> Vec<MqttPacket> {
+        let mut packets = Vec::new();
+        while packets.len() < num {
+            while !received.is_empty() {
+                match codec.decode_data(received) {
+                    Ok(Some(packet)) => packets.push(packet),
+                    Ok(None) | Err(Error::InsufficientBytes(_)) => break,
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+            if packets.len() >= num {
+                break;
+            }
+
+            let frame = timeout(Duration::from_secs(10), ws.next())
+                .await
+                .expect("no packet received from the broker")
+                .unwrap()
+                .unwrap();
+            if let Message::Binary(data) = frame {
+                received.extend_from_slice(&data);
+            }
+        }
+        packets
+    }
+}
@@ -20,7 +20,7 @@ mod tests {
     use metadata_struct::placement::node::BrokerNode;
     use protocol::placement_center::placement_center_inner::placement_center_service_client::PlacementCenterServiceClient;
     use protocol::placement_center::placement_center_inner::{
-        NodeListRequest, RegisterNodeRequest,
+        BrokerHeartbeatRequest, NodeListRequest, RegisterNodeRequest,
     };
     use tokio::time::sleep;
 
@@ -70,4 +70,63 @@ mod tests {
         println!("{}", total_ms);
         assert!((4..=10).contains(&total_ms));
     }
+
+    #[tokio::test]
+    async fn broker_heartbeat_load_test() {
+        let mut client = PlacementCenterServiceClient::connect(pc_addr())
+            .await
+            .unwrap();
+        let cluster_name = cluster_name();
+        for node_id in 1..=3 {
+            let request = RegisterNodeRequest {
+                cluster_type: cluster_type(),
+                cluster_name: cluster_name.clone(),
+                node_id,
+                node_ip: node_ip(),
+                node_inner_addr: node_ip(),
+                extend_info: extend_info(),
+            };
+            client
+                .register_node(tonic::Request::new(request))
+                .await
+                .unwrap();
+        }
+
+        let report = |node_id: u64, active_connections: u64| {
+            let request = BrokerHeartbeatRequest {
+                cluster_name: cluster_name.clone(),
+                node_id,
+                addr: format!("127.0.0.{}:1883", node_id),
+                active_connections,
+            };
+            let mut client = client.clone();
+            async move {
+                client
+                    .broker_heartbeat(tonic::Request::new(request))
+                    .await
+                    .unwrap()
+                    .into_inner()
+                    .least_loaded_addr
+            }
+        };
+
+        report(1, 50).await;
+        report(2, 10).await;
+        // node 1 with 50 connections is busier than node 2
+        assert_eq!(report(3, 30).await, "127.0.0.2:1883");
+        assert_eq!(report(2, 60).await, "127.0.0.3:1883");
+        assert_eq!(report(3, 30).await, "127.0.0.1:1883");
+
+        // a node that is not registered cannot report its load
+        let request = BrokerHeartbeatRequest {
+            cluster_name: cluster_name.clone(),
+            node_id: 4,
+            addr: "127.0.0.4:1883".to_string(),
+            active_connections: 0,
+        };
+        assert!(client
+            .broker_heartbeat(tonic::Request::new(request))
+            .await
+            .is_err());
+    }
 }
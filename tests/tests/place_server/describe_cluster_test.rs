@@ -0,0 +1,57 @@
+// Copyright 2023 RobustMQ Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(test)]
+mod tests {
+    use protocol::placement_center::placement_center_inner::placement_center_service_client::PlacementCenterServiceClient;
+    use protocol::placement_center::placement_center_inner::{DescribeClusterRequest, PeerInfo};
+
+    use crate::place_server::common::pc_addr;
+
+    async fn describe_cluster(addr: String) -> Vec<PeerInfo> {
+        let mut client = PlacementCenterServiceClient::connect(addr).await.unwrap();
+        client
+            .describe_cluster(tonic::Request::new(DescribeClusterRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .peers
+    }
+
+    #[tokio::test]
+    async fn describe_cluster_test() {
+        let peers = describe_cluster(pc_addr()).await;
+        let peer = peers.iter().find(|peer| peer.node_id == 1).unwrap();
+        assert_eq!(peer.grpc_addr, "127.0.0.1:1228");
+        assert_eq!(peer.raft_addr, "127.0.0.1:1228");
+    }
+
+    // Needs the 3 nodes of example/mqtt-cluster/placement-center
+    #[ignore]
+    #[tokio::test]
+    async fn describe_cluster_three_node_test() {
+        // A node that only knows one seed address discovers all the peers
+        let peers = describe_cluster(pc_addr()).await;
+        let addrs: Vec<String> = peers.iter().map(|peer| peer.grpc_addr.clone()).collect();
+        assert_eq!(
+            addrs,
+            vec!["127.0.0.1:1228", "127.0.0.1:2228", "127.0.0.1:3228"]
+        );
+
+        // Every peer includes itself and returns the same peers
+        for addr in addrs {
+            assert_eq!(describe_cluster(format!("http://{}", addr)).await, peers);
+        }
+    }
+}
@@ -15,7 +15,8 @@
 #[cfg(test)]
 mod tests {
     use common_base::error::common::CommonError;
-    use metadata_struct::journal::shard::JournalShardConfig;
+    use metadata_struct::journal::node_extend::JournalNodeExtend;
+    use metadata_struct::journal::shard::{JournalShard, JournalShardConfig};
     use protocol::placement_center::placement_center_inner::placement_center_service_client::PlacementCenterServiceClient;
     use protocol::placement_center::placement_center_inner::{
         HeartbeatRequest, RegisterNodeRequest, SetIdempotentDataRequest, UnRegisterNodeRequest,
@@ -23,6 +24,7 @@ mod tests {
     use protocol::placement_center::placement_center_journal::engine_service_client::EngineServiceClient;
     use protocol::placement_center::placement_center_journal::{
         CreateNextSegmentRequest, CreateShardRequest, DeleteSegmentRequest, DeleteShardRequest,
+        ListShardRequest, UpdateShardRequest,
     };
     use tonic::Status;
 
@@ -314,6 +316,93 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_update_shard() {
+        let cluster_name = cluster_name();
+        let namespace = namespace();
+
+        // two journal nodes, so that the shard can take a second replica
+        let mut inner_client = PlacementCenterServiceClient::connect(pc_addr())
+            .await
+            .unwrap();
+        let extend_info = JournalNodeExtend {
+            data_fold: vec!["/tmp/robustmq/journal".to_string()],
+            tcp_addr: "127.0.0.1:3110".to_string(),
+            tcps_addr: "127.0.0.1:3111".to_string(),
+        };
+        for node_id in 1..=2 {
+            let request = RegisterNodeRequest {
+                cluster_name: cluster_name.clone(),
+                cluster_type: cluster_type(),
+                node_ip: format!("127.0.0.{}", node_id),
+                node_id,
+                node_inner_addr: format!("127.0.0.{}:2228", node_id),
+                extend_info: serde_json::to_string(&extend_info).unwrap(),
+            };
+            inner_client
+                .register_node(tonic::Request::new(request))
+                .await
+                .unwrap();
+        }
+
+        let mut client = EngineServiceClient::connect(pc_addr()).await.unwrap();
+        let config = JournalShardConfig {
+            max_segment_size: 1024 * 1024 * 10,
+            replica_num: 1,
+        };
+        let request = CreateShardRequest {
+            cluster_name: cluster_name.clone(),
+            namespace: namespace.clone(),
+            shard_name: shard_name(),
+            shard_config: serde_json::to_vec(&config).unwrap(),
+        };
+        client
+            .create_shard(tonic::Request::new(request))
+            .await
+            .unwrap();
+
+        let new_config = JournalShardConfig {
+            max_segment_size: 1024 * 1024 * 10,
+            replica_num: 2,
+        };
+        let request = UpdateShardRequest {
+            cluster_name: cluster_name.clone(),
+            namespace: namespace.clone(),
+            shard_name: shard_name(),
+            new_config: serde_json::to_vec(&new_config).unwrap(),
+        };
+        client
+            .update_shard(tonic::Request::new(request))
+            .await
+            .unwrap();
+
+        let request = ListShardRequest {
+            cluster_name: cluster_name.clone(),
+            namespace: namespace.clone(),
+            shard_name: shard_name(),
+        };
+        let reply = client
+            .list_shard(tonic::Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        let shards = serde_json::from_slice::<Vec<JournalShard>>(&reply.shards).unwrap();
+        assert_eq!(shards.len(), 1);
+        assert_eq!(shards[0].config.replica_num, 2);
+
+        // the replicas cannot be decreased without migrating the data
+        let request = UpdateShardRequest {
+            cluster_name,
+            namespace,
+            shard_name: shard_name(),
+            new_config: serde_json::to_vec(&config).unwrap(),
+        };
+        assert!(client
+            .update_shard(tonic::Request::new(request))
+            .await
+            .is_err());
+    }
+
     #[tokio::test]
     async fn test_delete_shard() {
         let mut client = EngineServiceClient::connect(pc_addr()).await.unwrap();
@@ -339,6 +428,7 @@ mod tests {
             cluster_name: cluster_name(),
             namespace: namespace(),
             shard_name: shard_name(),
+            idempotency_key: None,
         };
         match client
             .create_next_segment(tonic::Request::new(request))
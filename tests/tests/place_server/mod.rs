@@ -15,6 +15,7 @@
 pub mod broker_heartbeat_test;
 pub mod cluster_node_test;
 pub mod common;
+pub mod describe_cluster_test;
 pub mod grpc_clients_test;
 pub mod kv_storage_test;
 pub mod share_sub;